@@ -0,0 +1,190 @@
+use burn::backend::Autodiff;
+use burn::backend::autodiff::checkpoint::strategy::{BalancedCheckpointing, CheckpointStrategy};
+use burn::record::{FullPrecisionSettings, HalfPrecisionSettings, NamedMpkFileRecorder};
+use burn::tensor::backend::{AutodiffBackend, Backend};
+use burn_ndarray::NdArray;
+use clap::ValueEnum;
+use crate::config::{HopeConfig, Precision};
+use crate::training::precision::HalfPrecisionStep;
+#[cfg(any(feature = "wgpu-backend", feature = "tch-backend"))]
+use crate::training::precision::HalfCompute;
+
+/// Backend of the CPU-only commands (eval, generate, forgetting, online)
+pub type CpuBackend = NdArray<f32>;
+/// `CpuBackend` with autodiff, for commands that take optimizer steps
+pub type CpuAutodiffBackend = Autodiff<CpuBackend>;
+
+/// Recorder for model weights, optimizer and carry state. Every backend
+/// reads and writes the same format, so checkpoints move between them.
+pub type CheckpointRecorder = NamedMpkFileRecorder<FullPrecisionSettings>;
+
+pub fn checkpoint_recorder() -> CheckpointRecorder {
+    CheckpointRecorder::new()
+}
+
+/// Recorder for model weights saved with `training.checkpoint_precision: half`
+pub type HalfCheckpointRecorder = NamedMpkFileRecorder<HalfPrecisionSettings>;
+
+pub fn half_checkpoint_recorder() -> HalfCheckpointRecorder {
+    HalfCheckpointRecorder::new()
+}
+
+/// Compute backends available in this build. Another engine (e.g. Candle,
+/// for targets without a Burn backend) is added behind its own feature as a
+/// variant here, a `DeviceIndex` impl and its arms in `run_training`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BackendKind {
+    /// CPU (ndarray)
+    Ndarray,
+    /// GPU via WebGPU (requires the `wgpu-backend` feature)
+    #[cfg(feature = "wgpu-backend")]
+    Wgpu,
+    /// CUDA via LibTorch (requires the `tch-backend` feature)
+    #[cfg(feature = "tch-backend")]
+    Tch,
+}
+
+/// Devices addressable by index: the GPU ordinal on GPU backends, ignored
+/// on the CPU
+pub trait DeviceIndex: Backend {
+    fn device(index: usize) -> Self::Device;
+
+    /// Device memory currently allocated to tensors, on backends whose
+    /// allocator reports it (on the CPU tensors are part of the process RSS)
+    fn allocated_bytes(_device: &Self::Device) -> Option<u64> {
+        None
+    }
+
+    /// Half-precision compute copy of a `config` model on `device`, for
+    /// training an f32 master model on `M` with `training.precision`; `None`
+    /// when this backend has no `precision` floats (ndarray computes in f32)
+    fn half_precision<M: AutodiffBackend<Device = Self::Device>>(
+        _precision: Precision,
+        _config: &HopeConfig,
+        _device: &Self::Device,
+    ) -> Option<Box<dyn HalfPrecisionStep<M>>> {
+        None
+    }
+}
+
+impl DeviceIndex for NdArray<f32> {
+    fn device(_index: usize) -> Self::Device {
+        Default::default()
+    }
+}
+
+#[cfg(feature = "wgpu-backend")]
+impl DeviceIndex for burn_wgpu::Wgpu {
+    fn device(index: usize) -> Self::Device {
+        burn_wgpu::WgpuDevice::DiscreteGpu(index)
+    }
+
+    fn allocated_bytes(device: &Self::Device) -> Option<u64> {
+        use cubecl::Runtime;
+        Some(burn_wgpu::WgpuRuntime::client(device).memory_usage().bytes_in_use)
+    }
+
+    fn half_precision<M: AutodiffBackend<Device = Self::Device>>(
+        precision: Precision,
+        config: &HopeConfig,
+        device: &Self::Device,
+    ) -> Option<Box<dyn HalfPrecisionStep<M>>> {
+        match precision {
+            Precision::F16 => Some(Box::new(HalfCompute::<Autodiff<burn_wgpu::Wgpu<burn::tensor::f16>>>::new(
+                config,
+                device.clone(),
+            ))),
+            Precision::F32 | Precision::Bf16 => None,
+        }
+    }
+}
+
+#[cfg(feature = "tch-backend")]
+impl DeviceIndex for burn_tch::LibTorch<f32> {
+    fn device(index: usize) -> Self::Device {
+        burn_tch::LibTorchDevice::Cuda(index)
+    }
+
+    fn half_precision<M: AutodiffBackend<Device = Self::Device>>(
+        precision: Precision,
+        config: &HopeConfig,
+        device: &Self::Device,
+    ) -> Option<Box<dyn HalfPrecisionStep<M>>> {
+        use burn::tensor::{bf16, f16};
+        match precision {
+            Precision::F16 => Some(Box::new(HalfCompute::<Autodiff<burn_tch::LibTorch<f16>>>::new(config, *device))),
+            Precision::Bf16 => Some(Box::new(HalfCompute::<Autodiff<burn_tch::LibTorch<bf16>>>::new(config, *device))),
+            Precision::F32 => None,
+        }
+    }
+}
+
+impl<B: DeviceIndex, C: CheckpointStrategy> DeviceIndex for Autodiff<B, C> {
+    fn device(index: usize) -> Self::Device {
+        B::device(index)
+    }
+
+    fn allocated_bytes(device: &Self::Device) -> Option<u64> {
+        B::allocated_bytes(device)
+    }
+
+    fn half_precision<M: AutodiffBackend<Device = Self::Device>>(
+        precision: Precision,
+        config: &HopeConfig,
+        device: &Self::Device,
+    ) -> Option<Box<dyn HalfPrecisionStep<M>>> {
+        B::half_precision::<M>(precision, config, device)
+    }
+}
+
+/// Work generic over the training backend, run by `run_training` on the
+/// backend picked at runtime
+pub trait TrainingTask {
+    type Output;
+
+    fn run<B: AutodiffBackend + DeviceIndex>(self) -> Self::Output;
+}
+
+/// Run `task` on `kind`, with activation recomputation in the backward
+/// pass when `checkpointing` is set (`training.activation_checkpointing`)
+pub fn run_training<T: TrainingTask>(kind: BackendKind, checkpointing: bool, task: T) -> T::Output {
+    match (kind, checkpointing) {
+        (BackendKind::Ndarray, false) => task.run::<Autodiff<NdArray<f32>>>(),
+        (BackendKind::Ndarray, true) => task.run::<Autodiff<NdArray<f32>, BalancedCheckpointing>>(),
+        #[cfg(feature = "wgpu-backend")]
+        (BackendKind::Wgpu, false) => task.run::<Autodiff<burn_wgpu::Wgpu>>(),
+        #[cfg(feature = "wgpu-backend")]
+        (BackendKind::Wgpu, true) => task.run::<Autodiff<burn_wgpu::Wgpu, BalancedCheckpointing>>(),
+        #[cfg(feature = "tch-backend")]
+        (BackendKind::Tch, false) => task.run::<Autodiff<burn_tch::LibTorch<f32>>>(),
+        #[cfg(feature = "tch-backend")]
+        (BackendKind::Tch, true) => task.run::<Autodiff<burn_tch::LibTorch<f32>, BalancedCheckpointing>>(),
+    }
+}
+
+/// `count` consecutive devices starting at `first`
+pub fn devices<B: DeviceIndex>(first: usize, count: usize) -> Vec<B::Device> {
+    (first..first + count).map(B::device).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct BackendName;
+
+    impl TrainingTask for BackendName {
+        type Output = &'static str;
+
+        fn run<B: AutodiffBackend + DeviceIndex>(self) -> &'static str {
+            std::any::type_name::<B>()
+        }
+    }
+
+    #[test]
+    fn test_run_training_picks_checkpointed_backend() {
+        assert!(!run_training(BackendKind::Ndarray, false, BackendName).contains("BalancedCheckpointing"));
+        assert!(run_training(BackendKind::Ndarray, true, BackendName).contains("BalancedCheckpointing"));
+        assert_eq!(devices::<CpuAutodiffBackend>(3, 2).len(), 2);
+    }
+}
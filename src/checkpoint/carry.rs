@@ -0,0 +1,116 @@
+use anyhow::{Context, Result};
+use burn::record::{Record, Recorder};
+use burn::tensor::backend::Backend;
+use std::path::Path;
+
+use crate::backend::checkpoint_recorder;
+use crate::config::HopeConfig;
+use crate::model::hope::HopeCarry;
+
+/// Bumped whenever the layout of `HopeCarry` changes
+pub const CARRY_FORMAT_VERSION: usize = 2;
+
+/// On-disk form of a carry
+#[derive(Record)]
+struct CarryFile<B: Backend> {
+    format_version: usize,
+    hidden_size: usize,
+    seq_len: usize,
+    carry: HopeCarry<B>,
+}
+
+/// Save a full carry (level states, memory banks, self-modify state, step
+/// count) so an interactive session can continue after a restart
+pub fn save_carry<B: Backend>(carry: &HopeCarry<B>, config: &HopeConfig, path: &Path) -> Result<()> {
+    let file = CarryFile {
+        format_version: CARRY_FORMAT_VERSION,
+        hidden_size: config.hidden_size,
+        seq_len: config.seq_len,
+        carry: carry.clone(),
+    };
+
+    checkpoint_recorder()
+        .record(file, path.to_path_buf())
+        .with_context(|| format!("Failed to save carry state to: {:?}", path))
+}
+
+/// Load a carry written by `save_carry` for a model with `config`
+pub fn load_carry<B: Backend>(
+    path: &Path,
+    config: &HopeConfig,
+    device: &B::Device,
+) -> Result<HopeCarry<B>> {
+    let file: CarryFile<B> = checkpoint_recorder()
+        .load(path.to_path_buf(), device)
+        .with_context(|| format!("Failed to load carry state from: {:?}", path))?;
+
+    if file.format_version != CARRY_FORMAT_VERSION {
+        anyhow::bail!(
+            "Carry state {:?} has format version {}, expected {}",
+            path,
+            file.format_version,
+            CARRY_FORMAT_VERSION
+        );
+    }
+
+    if file.hidden_size != config.hidden_size
+        || file.seq_len != config.seq_len
+        || file.carry.level_states.len() != config.num_levels
+    {
+        anyhow::bail!(
+            "Carry state {:?} (hidden_size {}, seq_len {}, {} levels) does not match the model \
+             (hidden_size {}, seq_len {}, {} levels)",
+            path,
+            file.hidden_size,
+            file.seq_len,
+            file.carry.level_states.len(),
+            config.hidden_size,
+            config.seq_len,
+            config.num_levels
+        );
+    }
+
+    Ok(file.carry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ContinuumMemConfig;
+    use crate::model::{HopeInput, HopeModel};
+    use burn::tensor::{Int, Tensor};
+    use burn_ndarray::NdArray;
+    use tempfile::TempDir;
+
+    type B = NdArray<f32>;
+
+    #[test]
+    fn test_carry_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("session.carry");
+        let device = Default::default();
+        let config = HopeConfig {
+            continuum_mem: ContinuumMemConfig {
+                enabled: true,
+                ..Default::default()
+            },
+            ..HopeConfig::tiny()
+        };
+        let model = HopeModel::<B>::new(config.clone(), &device);
+        let tokens = Tensor::<B, 1, Int>::arange(0..4, &device).reshape([1, 4]);
+        let (carry, _) = model.forward(HopeInput { tokens }, model.initial_carry(1, &device));
+
+        save_carry(&carry, &config, &path).unwrap();
+        let loaded = load_carry::<B>(&path, &config, &device).unwrap();
+
+        assert_eq!(loaded.step_count, carry.step_count);
+        assert_eq!(
+            loaded.continuum_memory.unwrap().episodic.into_data(),
+            carry.continuum_memory.unwrap().episodic.into_data()
+        );
+        assert_eq!(loaded.level_states[0].clone().into_data(), carry.level_states[0].clone().into_data());
+
+        let other = HopeConfig { seq_len: 8, ..config };
+        assert!(load_carry::<B>(&path, &other, &device).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+use anyhow::{Context, Result};
+use burn::module::{Module, ModuleVisitor, Param};
+use burn::tensor::{Tensor, backend::Backend};
+use safetensors::{Dtype, serialize_to_file, tensor::TensorView};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::HopeConfig;
+use crate::model::HopeModel;
+
+/// Version of the tensor naming scheme, recorded in the file's metadata
+pub const SAFETENSORS_FORMAT_VERSION: usize = 1;
+
+/// Write `model`'s parameters to `path` (`.safetensors`, f32 little-endian)
+/// and its configuration to `<path stem>.config.json` next to it; returns
+/// the config path. Tensors are named by their module path, e.g.
+/// `level_encoders.0.layers.1.mha.query.weight`, with Burn's layouts
+/// (linear weights are `[d_input, d_output]`, the transpose of PyTorch's).
+pub fn export_safetensors<B: Backend>(
+    model: &HopeModel<B>,
+    config: &HopeConfig,
+    step: usize,
+    path: &Path,
+) -> Result<PathBuf> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create export directory: {:?}", dir))?;
+    }
+
+    let mut parameters = NamedParameters { path: Vec::new(), tensors: Vec::new() };
+    model.visit(&mut parameters);
+    let views = parameters
+        .tensors
+        .iter()
+        .map(|(name, shape, bytes)| {
+            TensorView::new(Dtype::F32, shape.clone(), bytes)
+                .map(|view| (name.clone(), view))
+                .with_context(|| format!("Invalid tensor {}", name))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let metadata = HashMap::from([
+        ("format".to_string(), "hope".to_string()),
+        ("format_version".to_string(), SAFETENSORS_FORMAT_VERSION.to_string()),
+        ("step".to_string(), step.to_string()),
+    ]);
+    serialize_to_file(views, Some(metadata), path)
+        .with_context(|| format!("Failed to write safetensors file: {:?}", path))?;
+
+    let config_path = path.with_extension("config.json");
+    let json = serde_json::to_string_pretty(config).with_context(|| "Failed to serialize model config")?;
+    fs::write(&config_path, json).with_context(|| format!("Failed to write model config: {:?}", config_path))?;
+    Ok(config_path)
+}
+
+/// Write the ONNX model of `model`'s forward pass over `seq_len` tokens
+/// (`HopeModel::onnx_graph`) to `path`
+pub fn export_onnx<B: Backend>(model: &HopeModel<B>, seq_len: usize, path: &Path) -> Result<()> {
+    let graph = model.onnx_graph(seq_len)?;
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create export directory: {:?}", dir))?;
+    }
+    fs::write(path, graph.to_bytes()).with_context(|| format!("Failed to write ONNX model: {:?}", path))
+}
+
+/// Module path, shape and f32 little-endian bytes of every float parameter
+struct NamedParameters {
+    path: Vec<String>,
+    tensors: Vec<(String, Vec<usize>, Vec<u8>)>,
+}
+
+impl<B: Backend> ModuleVisitor<B> for NamedParameters {
+    fn enter_module(&mut self, name: &str, _container_type: &str) {
+        self.path.push(name.to_string());
+    }
+
+    fn exit_module(&mut self, _name: &str, _container_type: &str) {
+        self.path.pop();
+    }
+
+    fn visit_float<const D: usize>(&mut self, param: &Param<Tensor<B, D>>) {
+        let tensor = param.val();
+        let shape = tensor.dims().to_vec();
+        let values = tensor.into_data().convert::<f32>().to_vec::<f32>().expect("f32 tensor data");
+        let bytes = values.iter().flat_map(|value| value.to_le_bytes()).collect();
+        self.tensors.push((self.path.join("."), shape, bytes));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use burn_ndarray::NdArray;
+    use safetensors::SafeTensors;
+
+    #[test]
+    fn test_export_safetensors_names_every_parameter() {
+        let device = Default::default();
+        let config = HopeConfig { num_levels: 2, level_timescales: vec![1, 2], ..HopeConfig::tiny() };
+        let model = HopeModel::<NdArray<f32>>::new(config.clone(), &device);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("model.safetensors");
+
+        let config_path = export_safetensors(&model, &config, 7, &path).unwrap();
+        assert_eq!(config_path, dir.path().join("model.config.json"));
+        let saved: HopeConfig = serde_json::from_str(&fs::read_to_string(config_path).unwrap()).unwrap();
+        assert_eq!(saved.level_timescales, config.level_timescales);
+
+        let bytes = fs::read(&path).unwrap();
+        let (_, metadata) = SafeTensors::read_metadata(&bytes).unwrap();
+        assert_eq!(metadata.metadata().as_ref().unwrap()["step"], "7");
+        let tensors = SafeTensors::deserialize(&bytes).unwrap();
+        let total: usize = tensors.tensors().iter().map(|(_, view)| view.shape().iter().product::<usize>()).sum();
+        assert_eq!(total, model.num_params());
+
+        let head = tensors.tensor("head.weight").unwrap();
+        assert_eq!(head.shape(), [16, 8]);
+        let expected = model.into_record().head.weight.val().into_data().to_vec::<f32>().unwrap();
+        let values: Vec<f32> = head.data().chunks(4).map(|b| f32::from_le_bytes(b.try_into().unwrap())).collect();
+        assert_eq!(values, expected);
+        assert!(tensors.tensor("level_encoders.1.layers.0.mha.query.weight").is_ok());
+    }
+}
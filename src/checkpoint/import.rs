@@ -0,0 +1,363 @@
+use anyhow::{Context, Result};
+use burn::module::{Module, ModuleMapper, Param};
+use burn::tensor::{Tensor, TensorData, backend::Backend, bf16, f16};
+use safetensors::{Dtype, SafeTensors};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::model::HopeModel;
+
+/// Parameters of a pretrained checkpoint copied onto a `HopeModel`
+#[derive(Debug, Clone, Default)]
+pub struct ImportReport {
+    /// HOPE parameter path and the source tensor it was loaded from
+    pub loaded: Vec<(String, String)>,
+    /// HOPE parameter path (`block.<i>.` for blocks past the last encoder
+    /// layer) and why its source tensor was not used
+    pub skipped: Vec<(String, String)>,
+}
+
+/// Copy the token embeddings (and position embeddings and output head) of a
+/// HuggingFace safetensors checkpoint in the GPT-2 or Llama layout onto
+/// `model`; with `blocks`, also its transformer blocks, the i-th block onto
+/// the i-th encoder layer counting through the levels in order. A parameter
+/// is replaced only when the source has its shape (position embeddings may
+/// have more rows than `seq_len`); everything else keeps its initialization.
+/// Llama blocks need `ffn_activation: swiglu`, GPT-2 blocks `gelu`.
+pub fn import_pretrained<B: Backend>(
+    model: HopeModel<B>,
+    path: &Path,
+    blocks: bool,
+) -> Result<(HopeModel<B>, ImportReport)> {
+    let bytes = fs::read(path).with_context(|| format!("Failed to read pretrained weights: {:?}", path))?;
+    let file = SafeTensors::deserialize(&bytes)
+        .with_context(|| format!("Failed to parse safetensors file: {:?}", path))?;
+    let names = file.names();
+
+    let sources = if let Some(embed) = names.iter().find(|name| name.ends_with("wte.weight")) {
+        let prefix = embed.trim_end_matches("wte.weight").to_string();
+        gpt2_sources(&file, &prefix, blocks)?
+    } else if let Some(embed) = names.iter().find(|name| name.ends_with("embed_tokens.weight")) {
+        let prefix = embed.trim_end_matches("embed_tokens.weight").to_string();
+        llama_sources(&file, &prefix, blocks)?
+    } else {
+        anyhow::bail!("{:?} has neither GPT-2 (`wte.weight`) nor Llama (`embed_tokens.weight`) tensors", path);
+    };
+
+    let block_paths = encoder_layer_paths(&model);
+    let mut report = ImportReport::default();
+    let mut targets = HashMap::new();
+    for (target, source) in sources {
+        match resolve_target(&target, &block_paths) {
+            Some(path) => {
+                targets.insert(path, source);
+            }
+            None => report.skipped.push((target, format!("{}: the model has fewer encoder layers", source.name))),
+        }
+    }
+
+    let mut mapper = CopySources { sources: targets, path: Vec::new(), report };
+    let model = model.map(&mut mapper);
+    let mut report = mapper.report;
+    for (target, source) in mapper.sources {
+        report.skipped.push((target, format!("{}: no such HOPE parameter", source.name)));
+    }
+    Ok((model, report))
+}
+
+/// A tensor of the pretrained file, as f32
+#[derive(Debug, Clone)]
+struct SourceTensor {
+    name: String,
+    shape: Vec<usize>,
+    values: Vec<f32>,
+}
+
+impl SourceTensor {
+    fn read(file: &SafeTensors, name: &str) -> Result<Self> {
+        let view = file.tensor(name).with_context(|| format!("Missing tensor {}", name))?;
+        let data = view.data();
+        let values = match view.dtype() {
+            Dtype::F32 => data.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect(),
+            Dtype::F16 => data.chunks_exact(2).map(|b| f16::from_le_bytes([b[0], b[1]]).to_f32()).collect(),
+            Dtype::BF16 => data.chunks_exact(2).map(|b| bf16::from_le_bytes([b[0], b[1]]).to_f32()).collect(),
+            dtype => anyhow::bail!("Tensor {} has unsupported dtype {:?}", name, dtype),
+        };
+        Ok(Self { name: name.to_string(), shape: view.shape().to_vec(), values })
+    }
+
+    /// Read `name` if the file has it
+    fn read_optional(file: &SafeTensors, name: &str) -> Result<Option<Self>> {
+        match file.tensor(name) {
+            Ok(_) => Self::read(file, name).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Transpose of a matrix (PyTorch `[out, in]` to Burn `[in, out]`)
+    fn transpose(&self) -> Self {
+        let [rows, cols] = [self.shape[0], self.shape[1]];
+        let values = (0..cols)
+            .flat_map(|col| (0..rows).map(move |row| self.values[row * cols + col]))
+            .collect();
+        Self { name: self.name.clone(), shape: vec![cols, rows], values }
+    }
+
+    /// The last dimension cut into `parts` equal pieces
+    fn split_last(&self, parts: usize) -> Vec<Self> {
+        let width = *self.shape.last().expect("tensor has a dimension");
+        let part = width / parts;
+        (0..parts)
+            .map(|i| {
+                let values = self
+                    .values
+                    .chunks(width)
+                    .flat_map(|row| row[i * part..(i + 1) * part].iter().copied())
+                    .collect();
+                let mut shape = self.shape.clone();
+                *shape.last_mut().expect("tensor has a dimension") = part;
+                Self { name: format!("{}[{}]", self.name, i), shape, values }
+            })
+            .collect()
+    }
+
+    /// Two matrices side by side (`[rows, a + b]`)
+    fn concat_cols(&self, other: &Self) -> Self {
+        let (a, b) = (self.shape[1], other.shape[1]);
+        let values = self
+            .values
+            .chunks(a)
+            .zip(other.values.chunks(b))
+            .flat_map(|(left, right)| left.iter().chain(right).copied())
+            .collect();
+        Self { name: format!("{}+{}", self.name, other.name), shape: vec![self.shape[0], a + b], values }
+    }
+}
+
+/// Targets are HOPE parameter paths, with `block.<i>.` standing for the
+/// i-th encoder layer
+type Sources = Vec<(String, SourceTensor)>;
+
+fn gpt2_sources(file: &SafeTensors, prefix: &str, blocks: bool) -> Result<Sources> {
+    let read = |name: &str| SourceTensor::read(file, &format!("{}{}", prefix, name));
+    let embed = read("wte.weight")?;
+    let mut sources = vec![("head.weight".to_string(), embed.transpose()), ("token_embed.weight".to_string(), embed)];
+    if let Some(positions) = SourceTensor::read_optional(file, &format!("{}wpe.weight", prefix))? {
+        sources.push(("pos_embed.weight".to_string(), positions));
+    }
+    if !blocks {
+        return Ok(sources);
+    }
+
+    for block in 0.. {
+        let layer = |name: &str| format!("{}h.{}.{}", prefix, block, name);
+        let Some(attn) = SourceTensor::read_optional(file, &layer("attn.c_attn.weight"))? else {
+            break;
+        };
+        let target = |name: &str| format!("block.{}.{}", block, name);
+        // Conv1D weights are already [in, out]; query, key and value side by side
+        let attn_bias = read(&format!("h.{}.attn.c_attn.bias", block))?;
+        for ((part, weight), bias) in ["query", "key", "value"].iter().zip(attn.split_last(3)).zip(attn_bias.split_last(3)) {
+            sources.push((target(&format!("mha.{}.weight", part)), weight));
+            sources.push((target(&format!("mha.{}.bias", part)), bias));
+        }
+        for (name, source) in [
+            ("mha.output.weight", "attn.c_proj.weight"),
+            ("mha.output.bias", "attn.c_proj.bias"),
+            ("pwff.linear_inner.weight", "mlp.c_fc.weight"),
+            ("pwff.linear_inner.bias", "mlp.c_fc.bias"),
+            ("pwff.linear_outer.weight", "mlp.c_proj.weight"),
+            ("pwff.linear_outer.bias", "mlp.c_proj.bias"),
+            // Pre-norm: norm_2 before attention, norm_1 before the feed-forward
+            ("norm_2.gamma", "ln_1.weight"),
+            ("norm_2.beta", "ln_1.bias"),
+            ("norm_1.gamma", "ln_2.weight"),
+            ("norm_1.beta", "ln_2.bias"),
+        ] {
+            sources.push((target(name), SourceTensor::read(file, &layer(source))?));
+        }
+    }
+    Ok(sources)
+}
+
+fn llama_sources(file: &SafeTensors, prefix: &str, blocks: bool) -> Result<Sources> {
+    let embed = SourceTensor::read(file, &format!("{}embed_tokens.weight", prefix))?;
+    // Without an `lm_head`, the head is tied to the embeddings
+    let head = SourceTensor::read_optional(file, "lm_head.weight")?.unwrap_or_else(|| embed.clone());
+    let mut sources = vec![("token_embed.weight".to_string(), embed), ("head.weight".to_string(), head.transpose())];
+    if !blocks {
+        return Ok(sources);
+    }
+
+    for block in 0.. {
+        let layer = |name: &str| format!("{}layers.{}.{}", prefix, block, name);
+        let Some(query) = SourceTensor::read_optional(file, &layer("self_attn.q_proj.weight"))? else {
+            break;
+        };
+        let target = |name: &str| format!("block.{}.{}", block, name);
+        let read = |name: &str| SourceTensor::read(file, &layer(name));
+        sources.push((target("mha.query.weight"), query.transpose()));
+        sources.push((target("mha.key.weight"), read("self_attn.k_proj.weight")?.transpose()));
+        sources.push((target("mha.value.weight"), read("self_attn.v_proj.weight")?.transpose()));
+        sources.push((target("mha.output.weight"), read("self_attn.o_proj.weight")?.transpose()));
+        // SwiGLU: the gate and the value projections side by side
+        let gate = read("mlp.gate_proj.weight")?.transpose();
+        let up = read("mlp.up_proj.weight")?.transpose();
+        sources.push((target("pwff.linear_inner.weight"), gate.concat_cols(&up)));
+        sources.push((target("pwff.linear_outer.weight"), read("mlp.down_proj.weight")?.transpose()));
+        // RMSNorm scales onto the layer norms' gamma
+        sources.push((target("norm_2.gamma"), read("input_layernorm.weight")?));
+        sources.push((target("norm_1.gamma"), read("post_attention_layernorm.weight")?));
+    }
+    Ok(sources)
+}
+
+/// Module paths of the model's encoder layers, levels in order
+/// (`level_encoders.<level>.layers.<layer>`)
+fn encoder_layer_paths<B: Backend>(model: &HopeModel<B>) -> Vec<String> {
+    let config = model.config();
+    (0..config.num_levels)
+        .flat_map(|level| {
+            let layers = config.level_shape(level).num_layers;
+            (0..layers).map(move |layer| format!("level_encoders.{}.layers.{}", level, layer))
+        })
+        .collect()
+}
+
+/// HOPE path of a source target; `None` for blocks past the last encoder layer
+fn resolve_target(target: &str, block_paths: &[String]) -> Option<String> {
+    let Some(rest) = target.strip_prefix("block.") else {
+        return Some(target.to_string());
+    };
+    let (block, name) = rest.split_once('.')?;
+    let layer = block_paths.get(block.parse::<usize>().ok()?)?;
+    Some(format!("{}.{}", layer, name))
+}
+
+struct CopySources {
+    sources: HashMap<String, SourceTensor>,
+    path: Vec<String>,
+    report: ImportReport,
+}
+
+impl<B: Backend> ModuleMapper<B> for CopySources {
+    fn enter_module(&mut self, name: &str, _container_type: &str) {
+        self.path.push(name.to_string());
+    }
+
+    fn exit_module(&mut self, _name: &str, _container_type: &str) {
+        self.path.pop();
+    }
+
+    fn map_float<const D: usize>(&mut self, param: Param<Tensor<B, D>>) -> Param<Tensor<B, D>> {
+        let path = self.path.join(".");
+        let Some(mut source) = self.sources.remove(&path) else {
+            return param;
+        };
+        let (id, tensor, mapper) = param.consume();
+        let shape = tensor.dims().to_vec();
+
+        // Position embeddings: the first `seq_len` positions
+        if path == "pos_embed.weight" && source.shape.len() == 2 && source.shape[0] > shape[0] && source.shape[1] == shape[1] {
+            source.values.truncate(shape[0] * shape[1]);
+            source.shape[0] = shape[0];
+        }
+        if source.shape != shape {
+            self.report.skipped.push((path, format!("{} has shape {:?}, expected {:?}", source.name, source.shape, shape)));
+            return Param::from_mapped_value(id, tensor, mapper);
+        }
+
+        let device = tensor.device();
+        let loaded = Tensor::from_data(TensorData::new(source.values, shape), &device);
+        self.report.loaded.push((path, source.name));
+        Param::from_mapped_value(id, loaded, mapper)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{FfnActivation, HopeConfig};
+    use burn_ndarray::NdArray;
+    use safetensors::{serialize_to_file, tensor::TensorView};
+
+    type B = NdArray<f32>;
+
+    /// Llama-layout file of `tensors` (name, PyTorch shape), each filled with
+    /// its index so the copies can be traced
+    fn write_llama(path: &Path, tensors: &[(&str, Vec<usize>)]) {
+        let data: Vec<(String, Vec<usize>, Vec<u8>)> = tensors
+            .iter()
+            .map(|(name, shape)| {
+                let len: usize = shape.iter().product();
+                let bytes = (0..len).flat_map(|i| (i as f32).to_le_bytes()).collect();
+                (name.to_string(), shape.clone(), bytes)
+            })
+            .collect();
+        let views: Vec<_> = data
+            .iter()
+            .map(|(name, shape, bytes)| (name.clone(), TensorView::new(Dtype::F32, shape.clone(), bytes).unwrap()))
+            .collect();
+        serialize_to_file(views, None, path).unwrap();
+    }
+
+    #[test]
+    fn test_import_llama_embeddings_and_blocks() {
+        let device = Default::default();
+        let config = HopeConfig {
+            hidden_size: 8,
+            vocab_size: 6,
+            seq_len: 4,
+            num_heads: 2,
+            num_layers: 1,
+            ff_multiplier: 2.0,
+            ffn_activation: FfnActivation::Swiglu,
+            num_levels: 2,
+            level_timescales: vec![1, 1],
+            ..Default::default()
+        };
+        let model = HopeModel::<B>::new(config, &device);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("model.safetensors");
+        let mut tensors = vec![("model.embed_tokens.weight", vec![6, 8])];
+        // Two blocks for two encoder layers; the first with a mismatched key
+        let names = [
+            ("self_attn.q_proj.weight", vec![8, 8]),
+            ("self_attn.k_proj.weight", vec![8, 8]),
+            ("self_attn.v_proj.weight", vec![8, 8]),
+            ("self_attn.o_proj.weight", vec![8, 8]),
+            ("mlp.gate_proj.weight", vec![16, 8]),
+            ("mlp.up_proj.weight", vec![16, 8]),
+            ("mlp.down_proj.weight", vec![8, 16]),
+            ("input_layernorm.weight", vec![8]),
+            ("post_attention_layernorm.weight", vec![8]),
+        ];
+        let layer_names: Vec<(String, Vec<usize>)> = (0..2)
+            .flat_map(|block| {
+                names.iter().map(move |(name, shape)| {
+                    let shape = if block == 0 && name.starts_with("self_attn.k_proj") { vec![4, 8] } else { shape.clone() };
+                    (format!("model.layers.{}.{}", block, name), shape)
+                })
+            })
+            .collect();
+        tensors.extend(layer_names.iter().map(|(name, shape)| (name.as_str(), shape.clone())));
+        write_llama(&path, &tensors);
+
+        let (model, report) = import_pretrained(model, &path, true).unwrap();
+        let loaded: Vec<&str> = report.loaded.iter().map(|(target, _)| target.as_str()).collect();
+        assert!(loaded.contains(&"token_embed.weight"));
+        assert!(loaded.contains(&"head.weight"));
+        assert!(loaded.contains(&"level_encoders.1.layers.0.mha.key.weight"));
+        assert!(loaded.contains(&"level_encoders.0.layers.0.pwff.linear_inner.weight"));
+        assert_eq!(report.skipped.len(), 1);
+        assert_eq!(report.skipped[0].0, "level_encoders.0.layers.0.mha.key.weight");
+
+        let record = model.into_record();
+        let embed = record.token_embed.weight.val().into_data().to_vec::<f32>().unwrap();
+        assert_eq!(embed, (0..48).map(|i| i as f32).collect::<Vec<_>>());
+        // The head is the transposed (tied) embedding: head[h][v] = embed[v][h]
+        let head = record.head.weight.val().into_data().to_vec::<f32>().unwrap();
+        assert_eq!(head[1], 8.0);
+    }
+}
@@ -0,0 +1,104 @@
+use anyhow::{Context, Result};
+use burn::module::Module;
+use burn::record::Recorder;
+use burn::tensor::backend::Backend;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::record::current_timestamp;
+use crate::backend::checkpoint_recorder;
+use crate::model::HopeModel;
+use crate::model::lora::{LoraAdapters, LoraConfig};
+
+/// Metadata of an adapter checkpoint (`lora_step_<n>.json`); the adapter
+/// weights are in `adapters_file`, a fraction of a full checkpoint's size
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LoraCheckpointData {
+    pub step: usize,
+    /// Checkpoint whose weights the adapters were trained on
+    pub base_checkpoint: PathBuf,
+    pub rank: usize,
+    pub alpha: f32,
+    pub targets: Vec<String>,
+    /// Module paths of the adapted weights
+    pub paths: Vec<String>,
+    pub adapters_file: String,
+    pub timestamp: u64,
+}
+
+impl LoraCheckpointData {
+    pub fn config(&self) -> LoraConfig {
+        LoraConfig {
+            rank: self.rank,
+            alpha: self.alpha,
+            targets: self.targets.clone(),
+        }
+    }
+}
+
+/// Save `adapters` after `step` as `lora_step_<step>.json` in `checkpoint_dir`
+pub fn save_lora_adapters<B: Backend>(
+    adapters: &LoraAdapters<B>,
+    config: &LoraConfig,
+    base_checkpoint: &Path,
+    step: usize,
+    checkpoint_dir: &Path,
+) -> Result<PathBuf> {
+    fs::create_dir_all(checkpoint_dir)
+        .with_context(|| format!("Failed to create checkpoint directory: {:?}", checkpoint_dir))?;
+
+    let name = format!("lora_step_{}", step);
+    let adapters_file = format!("{}_adapters", name);
+    checkpoint_recorder()
+        .record(adapters.clone().into_record(), checkpoint_dir.join(&adapters_file))
+        .with_context(|| "Failed to save LoRA adapters")?;
+
+    let data = LoraCheckpointData {
+        step,
+        base_checkpoint: base_checkpoint.to_path_buf(),
+        rank: config.rank,
+        alpha: config.alpha,
+        targets: config.targets.clone(),
+        paths: adapters.paths().to_vec(),
+        adapters_file,
+        timestamp: current_timestamp(),
+    };
+    let metadata_path = checkpoint_dir.join(name).with_extension("json");
+    let metadata_json = serde_json::to_string_pretty(&data)
+        .with_context(|| "Failed to serialize adapter metadata")?;
+    fs::write(&metadata_path, metadata_json)
+        .with_context(|| format!("Failed to write adapter metadata: {:?}", metadata_path))?;
+
+    Ok(metadata_path)
+}
+
+pub fn read_lora_metadata(path: &Path) -> Result<LoraCheckpointData> {
+    let json = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read adapter metadata: {:?}", path))?;
+    serde_json::from_str(&json).with_context(|| format!("Failed to parse adapter metadata: {:?}", path))
+}
+
+/// Load the adapters saved at `path` for `model` (the base they were trained on)
+pub fn load_lora_adapters<B: Backend>(
+    path: &Path,
+    model: &HopeModel<B>,
+    device: &B::Device,
+) -> Result<(LoraAdapters<B>, LoraCheckpointData)> {
+    let data = read_lora_metadata(path)?;
+    let adapters = LoraAdapters::new(model, &data.config(), device);
+    if adapters.paths() != data.paths.as_slice() {
+        anyhow::bail!(
+            "Adapters {:?} were trained on a model with different weights ({} adapted, this model has {})",
+            path,
+            data.paths.len(),
+            adapters.paths().len()
+        );
+    }
+
+    let adapters_path = path.parent().unwrap_or(Path::new(".")).join(&data.adapters_file);
+    let record = checkpoint_recorder()
+        .load(adapters_path.clone(), device)
+        .with_context(|| format!("Failed to load LoRA adapters from: {:?}", adapters_path))?;
+    Ok((adapters.load_record(record), data))
+}
@@ -0,0 +1,175 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use crate::config::{CorpusMismatchPolicy, TrainConfig};
+use super::record::current_timestamp;
+
+/// Record of a training run: what it started from and which data it saw
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RunManifest {
+    pub started_at: u64,
+    pub start_step: usize,
+    pub resumed_from: Option<PathBuf>,
+    pub data_path: Option<PathBuf>,
+    pub corpus_version: Option<String>,
+    /// Seed of the backend RNG (the configured `seed` or a random one)
+    #[serde(default)]
+    pub rng_seed: Option<u64>,
+    pub config: TrainConfig,
+}
+
+impl RunManifest {
+    pub fn new(config: &TrainConfig, start_step: usize, corpus_version: Option<String>, rng_seed: u64) -> Self {
+        Self {
+            started_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            start_step,
+            resumed_from: config.training.resume_from.clone(),
+            data_path: config.data.data_path.clone(),
+            corpus_version,
+            rng_seed: Some(rng_seed),
+            config: config.clone(),
+        }
+    }
+}
+
+/// Write the run manifest into the checkpoint directory
+pub fn write_run_manifest(manifest: &RunManifest, checkpoint_dir: &Path) -> Result<PathBuf> {
+    fs::create_dir_all(checkpoint_dir)
+        .with_context(|| format!("Failed to create checkpoint directory: {:?}", checkpoint_dir))?;
+
+    let manifest_path = checkpoint_dir.join(format!("run_{}.manifest.json", manifest.started_at));
+    let manifest_json = serde_json::to_string_pretty(manifest)
+        .with_context(|| "Failed to serialize run manifest")?;
+
+    fs::write(&manifest_path, manifest_json)
+        .with_context(|| format!("Failed to write run manifest: {:?}", manifest_path))?;
+
+    info!("Run manifest saved to: {:?}", manifest_path);
+
+    Ok(manifest_path)
+}
+
+/// File name of the `PipelineManifest` in the checkpoint directory
+pub const PIPELINE_MANIFEST_FILE: &str = "pipeline.manifest.json";
+
+/// Record shared by the stages of `hope-train pipeline`: what the corpus was
+/// preprocessed from, which checkpoint training produced, how it evaluated
+/// and where it was exported. The next run reads it to skip preprocessing
+/// an unchanged corpus.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PipelineManifest {
+    pub updated_at: u64,
+    pub input: Option<PathBuf>,
+    /// `source_fingerprint` of `input` when the corpus was preprocessed
+    pub source_fingerprint: Option<String>,
+    pub data_path: Option<PathBuf>,
+    pub corpus_version: Option<String>,
+    /// Checkpoint that was evaluated and exported
+    pub checkpoint: Option<PathBuf>,
+    pub step: Option<usize>,
+    pub eval_data: Option<PathBuf>,
+    pub eval_loss: Option<f32>,
+    pub eval_perplexity: Option<f32>,
+    pub export_dir: Option<PathBuf>,
+}
+
+/// The pipeline manifest in `dir`, if there is a readable one
+pub fn read_pipeline_manifest(dir: &Path) -> Option<PipelineManifest> {
+    let path = dir.join(PIPELINE_MANIFEST_FILE);
+    let json = fs::read_to_string(&path).ok()?;
+    match serde_json::from_str(&json) {
+        Ok(manifest) => Some(manifest),
+        Err(e) => {
+            warn!("Ignoring unreadable pipeline manifest {:?}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// Write `manifest` into `dir`, stamped with the current time
+pub fn write_pipeline_manifest(manifest: &mut PipelineManifest, dir: &Path) -> Result<PathBuf> {
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create directory: {:?}", dir))?;
+
+    manifest.updated_at = current_timestamp();
+    let path = dir.join(PIPELINE_MANIFEST_FILE);
+    let json = serde_json::to_string_pretty(manifest)
+        .with_context(|| "Failed to serialize pipeline manifest")?;
+    fs::write(&path, json)
+        .with_context(|| format!("Failed to write pipeline manifest: {:?}", path))?;
+
+    Ok(path)
+}
+
+/// Compare the corpus version stored in a checkpoint against the current one.
+/// A mismatch is an error or a warning depending on `policy`; a missing
+/// version on either side only warns, since it cannot be verified.
+pub fn verify_corpus_version(
+    checkpoint_version: Option<&str>,
+    current_version: Option<&str>,
+    policy: CorpusMismatchPolicy,
+) -> Result<()> {
+    match (checkpoint_version, current_version) {
+        (Some(saved), Some(current)) if saved != current => {
+            let message = format!(
+                "Corpus version changed since checkpoint (checkpoint: {}, current: {})",
+                saved, current
+            );
+            match policy {
+                CorpusMismatchPolicy::Error => anyhow::bail!(
+                    "{}; set training.corpus_mismatch to \"warn\" to resume anyway",
+                    message
+                ),
+                CorpusMismatchPolicy::Warn => warn!("{}", message),
+            }
+        }
+        (Some(saved), None) => {
+            warn!("Checkpoint was trained on corpus {}, but the current corpus has no version", saved);
+        }
+        (None, Some(current)) => {
+            warn!("Checkpoint has no corpus version; cannot verify against corpus {}", current);
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pipeline_manifest_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(read_pipeline_manifest(dir.path()).is_none());
+
+        let mut manifest = PipelineManifest {
+            source_fingerprint: Some("abc".to_string()),
+            step: Some(12),
+            ..Default::default()
+        };
+        write_pipeline_manifest(&mut manifest, dir.path()).unwrap();
+        let read = read_pipeline_manifest(dir.path()).unwrap();
+        assert_eq!(read.source_fingerprint.as_deref(), Some("abc"));
+        assert_eq!(read.step, Some(12));
+        assert!(read.updated_at > 0);
+
+        fs::write(dir.path().join(PIPELINE_MANIFEST_FILE), "{").unwrap();
+        assert!(read_pipeline_manifest(dir.path()).is_none());
+    }
+
+    #[test]
+    fn test_verify_corpus_version() {
+        assert!(verify_corpus_version(Some("abc"), Some("abc"), CorpusMismatchPolicy::Error).is_ok());
+        assert!(verify_corpus_version(Some("abc"), Some("def"), CorpusMismatchPolicy::Error).is_err());
+        assert!(verify_corpus_version(Some("abc"), Some("def"), CorpusMismatchPolicy::Warn).is_ok());
+        assert!(verify_corpus_version(None, Some("def"), CorpusMismatchPolicy::Error).is_ok());
+    }
+}
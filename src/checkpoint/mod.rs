@@ -1,4 +1,20 @@
+mod carry;
+mod export;
+mod import;
+mod lora;
+mod manifest;
 mod record;
 
-pub use record::{CheckpointData, save_checkpoint, load_checkpoint, list_checkpoints};
-
+pub use carry::{CARRY_FORMAT_VERSION, load_carry, save_carry};
+pub use export::{SAFETENSORS_FORMAT_VERSION, export_onnx, export_safetensors};
+pub use import::{ImportReport, import_pretrained};
+pub use lora::{LoraCheckpointData, load_lora_adapters, read_lora_metadata, save_lora_adapters};
+pub use manifest::{
+    PIPELINE_MANIFEST_FILE, PipelineManifest, RunManifest, read_pipeline_manifest, verify_corpus_version,
+    write_pipeline_manifest, write_run_manifest,
+};
+pub use record::{
+    BEST_CHECKPOINT_NAME, CheckpointData, list_checkpoints, load_checkpoint,
+    load_deep_optimizer_state, load_memory_state, load_optimizer_state, read_best_val_loss, read_checkpoint_metadata, save_best_checkpoint, save_checkpoint,
+    save_model_checkpoint, save_snapshot_checkpoint, save_swa_checkpoint, step_rng_seed, SWA_CHECKPOINT_NAME,
+};
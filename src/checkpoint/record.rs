@@ -1,14 +1,21 @@
 use anyhow::{Context, Result};
-use burn::record::{FullPrecisionSettings, NamedMpkFileRecorder, Recorder};
-use burn::tensor::backend::Backend;
+use burn::module::Module;
+use burn::record::Recorder;
+use burn::tensor::backend::{AutodiffBackend, Backend};
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::{Path, PathBuf};
 use tracing::{info, warn};
 use walkdir::WalkDir;
 
-use crate::config::TrainConfig;
+use crate::backend::{checkpoint_recorder, half_checkpoint_recorder};
+use crate::config::{CheckpointPrecision, TrainConfig};
+use crate::data::LoaderState;
 use crate::model::HopeModel;
+use crate::model::continuum_mem::ContinuumMemoryState;
+use crate::model::optimizer::DeepOptimizerState;
+use crate::training::HopeTrainer;
+use crate::training::optimizer::TrainingOptimizer;
 
 /// Checkpoint data structure containing all training state
 #[derive(Debug, Serialize, Deserialize)]
@@ -16,57 +23,289 @@ pub struct CheckpointData {
     pub step: usize,
     pub config: TrainConfig,
     pub model_file: String,
+    /// Precision `model_file` was written at (full in checkpoints from
+    /// older versions)
+    #[serde(default)]
+    pub precision: CheckpointPrecision,
     pub timestamp: u64,
+    /// Version of the corpus the model was trained on (if known)
+    #[serde(default)]
+    pub corpus_version: Option<String>,
+    /// Optimizer state file (absent in checkpoints from older versions)
+    #[serde(default)]
+    pub optimizer_file: Option<String>,
+    /// Deep optimizer state file (when `model.deep_optimizer` is enabled)
+    #[serde(default)]
+    pub deep_optimizer_file: Option<String>,
+    /// Continuum memory banks accumulated by stateful training (see
+    /// `HopeTrainer::memory_state`), for later sessions to resume
+    #[serde(default)]
+    pub memory_file: Option<String>,
+    /// Validation loss at this step (set on the best checkpoint)
+    #[serde(default)]
+    pub val_loss: Option<f32>,
+    /// Training data position after this step (absent in checkpoints from
+    /// older versions and for loaders that cannot resume)
+    #[serde(default)]
+    pub loader_state: Option<LoaderState>,
+    /// Run seed the backend RNG is reseeded from at every checkpoint (see
+    /// `step_rng_seed`), so a resumed run continues the same random stream
+    #[serde(default)]
+    pub rng_seed: Option<u64>,
 }
 
+/// File stem of the checkpoint holding the lowest validation loss so far
+pub const BEST_CHECKPOINT_NAME: &str = "best";
+
+/// File stem of the stochastic weight average (`training.swa`)
+pub const SWA_CHECKPOINT_NAME: &str = "swa";
+
 /// Save a complete checkpoint including model weights, optimizer state, and training progress
-pub fn save_checkpoint<B: Backend>(
+pub fn save_checkpoint<B: AutodiffBackend>(
+    trainer: &HopeTrainer<B>,
+    step: usize,
+    corpus_version: Option<&str>,
+    loader_state: Option<LoaderState>,
+    rng_seed: Option<u64>,
+    checkpoint_dir: &Path,
+) -> Result<PathBuf> {
+    let timestamp = current_timestamp();
+    let checkpoint_name = format!("checkpoint_step_{}_ts_{}", step, timestamp);
+    let progress = CheckpointProgress { step, timestamp, corpus_version, val_loss: None, loader_state, rng_seed };
+    let metadata_path = write_checkpoint(trainer, progress, &checkpoint_name, checkpoint_dir)?;
+    
+    info!("Checkpoint saved successfully at step {}: {:?}", step, metadata_path);
+    
+    Ok(metadata_path)
+}
+
+/// Write or refresh `best.json` (and its weights) in `checkpoint_dir`.
+/// Callers decide when `val_loss` is an improvement.
+pub fn save_best_checkpoint<B: AutodiffBackend>(
+    trainer: &HopeTrainer<B>,
+    step: usize,
+    val_loss: f32,
+    corpus_version: Option<&str>,
+    loader_state: Option<LoaderState>,
+    rng_seed: Option<u64>,
+    checkpoint_dir: &Path,
+) -> Result<PathBuf> {
+    let progress = CheckpointProgress {
+        step,
+        timestamp: current_timestamp(),
+        corpus_version,
+        val_loss: Some(val_loss),
+        loader_state,
+        rng_seed,
+    };
+    let metadata_path = write_checkpoint(trainer, progress, BEST_CHECKPOINT_NAME, checkpoint_dir)?;
+    
+    info!("Best checkpoint updated at step {} (val loss {:.6}): {:?}", step, val_loss, metadata_path);
+    
+    Ok(metadata_path)
+}
+
+/// Save `snapshot_cycle_<cycle + 1>` at the end of an LR schedule cycle
+/// (kept alongside the regular checkpoints for snapshot ensembles)
+pub fn save_snapshot_checkpoint<B: AutodiffBackend>(
+    trainer: &HopeTrainer<B>,
+    step: usize,
+    cycle: usize,
+    corpus_version: Option<&str>,
+    loader_state: Option<LoaderState>,
+    rng_seed: Option<u64>,
+    checkpoint_dir: &Path,
+) -> Result<PathBuf> {
+    let progress = CheckpointProgress {
+        step,
+        timestamp: current_timestamp(),
+        corpus_version,
+        val_loss: None,
+        loader_state,
+        rng_seed,
+    };
+    let name = format!("snapshot_cycle_{}", cycle + 1);
+    let metadata_path = write_checkpoint(trainer, progress, &name, checkpoint_dir)?;
+    
+    info!("Snapshot checkpoint for cycle {} saved at step {}: {:?}", cycle + 1, step, metadata_path);
+    
+    Ok(metadata_path)
+}
+
+/// Write `swa.json` with the averaged weights of a run ending at `step`.
+/// The average has no optimizer state, so it is for evaluation and
+/// inference (or fine-tuning), not for resuming the run.
+pub fn save_swa_checkpoint<B: Backend>(
     model: &HopeModel<B>,
+    config: &TrainConfig,
     step: usize,
+    corpus_version: Option<&str>,
+    checkpoint_dir: &Path,
+) -> Result<PathBuf> {
+    save_model_checkpoint(model, config, step, corpus_version, SWA_CHECKPOINT_NAME, checkpoint_dir)
+}
+
+/// Write `<name>.json` with `model`'s weights only (no optimizer state),
+/// e.g. averaged or merged weights for evaluation, inference or fine-tuning
+pub fn save_model_checkpoint<B: Backend>(
+    model: &HopeModel<B>,
     config: &TrainConfig,
+    step: usize,
+    corpus_version: Option<&str>,
+    name: &str,
     checkpoint_dir: &Path,
 ) -> Result<PathBuf> {
-    // Create checkpoint directory if it doesn't exist
     fs::create_dir_all(checkpoint_dir)
         .with_context(|| format!("Failed to create checkpoint directory: {:?}", checkpoint_dir))?;
+    
+    let model_file = format!("{}_model", name);
+    let precision = config.training.checkpoint_precision;
+    record_model(model, precision, checkpoint_dir.join(&model_file))
+        .with_context(|| format!("Failed to save model weights: {}", name))?;
+    
+    let checkpoint_data = CheckpointData {
+        step,
+        config: config.clone(),
+        model_file,
+        precision,
+        timestamp: current_timestamp(),
+        corpus_version: corpus_version.map(str::to_string),
+        optimizer_file: None,
+        deep_optimizer_file: None,
+        memory_file: None,
+        val_loss: None,
+        loader_state: None,
+        rng_seed: None,
+    };
+    write_metadata(&checkpoint_data, name, checkpoint_dir)
+}
 
-    // Generate checkpoint filename
-    let timestamp = std::time::SystemTime::now()
+/// Validation loss recorded in `best.json`, if a best checkpoint exists
+pub fn read_best_val_loss(checkpoint_dir: &Path) -> Option<f32> {
+    let path = checkpoint_dir.join(BEST_CHECKPOINT_NAME).with_extension("json");
+    if !path.exists() {
+        return None;
+    }
+    read_checkpoint_metadata(&path).ok().and_then(|data| data.val_loss)
+}
+
+/// Seed for the backend RNG from `step` on. Burn cannot read back its RNG
+/// state, so training reseeds with this whenever it writes a checkpoint and
+/// a resumed run reseeds with it at the checkpoint's step.
+pub fn step_rng_seed(run_seed: u64, step: usize) -> u64 {
+    // splitmix64 finalizer, so nearby steps get unrelated seeds
+    let mut z = run_seed.wrapping_add((step as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+pub(super) fn current_timestamp() -> u64 {
+    std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
         .unwrap()
-        .as_secs();
-    
-    let checkpoint_name = format!("checkpoint_step_{}_ts_{}", step, timestamp);
-    let checkpoint_path = checkpoint_dir.join(&checkpoint_name);
+        .as_secs()
+}
+
+/// Training progress written into a checkpoint's metadata
+struct CheckpointProgress<'a> {
+    step: usize,
+    timestamp: u64,
+    corpus_version: Option<&'a str>,
+    val_loss: Option<f32>,
+    loader_state: Option<LoaderState>,
+    rng_seed: Option<u64>,
+}
+
+/// Save weights and optimizer state as `<name>_model`, `<name>_optimizer`
+/// (and `<name>_deep_optimizer`), then the `<name>.json` metadata
+fn write_checkpoint<B: AutodiffBackend>(
+    trainer: &HopeTrainer<B>,
+    progress: CheckpointProgress<'_>,
+    checkpoint_name: &str,
+    checkpoint_dir: &Path,
+) -> Result<PathBuf> {
+    // Create checkpoint directory if it doesn't exist
+    fs::create_dir_all(checkpoint_dir)
+        .with_context(|| format!("Failed to create checkpoint directory: {:?}", checkpoint_dir))?;
     
     // Save model weights using Burn's recorder
     let model_file = format!("{}_model", checkpoint_name);
     let model_path = checkpoint_dir.join(&model_file);
     
-    let recorder = NamedMpkFileRecorder::<FullPrecisionSettings>::new();
-    recorder
-        .record(model.clone().into_record(), model_path.clone())
+    let precision = trainer.config().training.checkpoint_precision;
+    record_model(trainer.model(), precision, model_path.clone())
         .with_context(|| "Failed to save model weights")?;
     
     info!("Model weights saved to: {:?}", model_path);
     
-    // Save checkpoint metadata
+    // Save optimizer state so resuming doesn't reset the moments
+    let optimizer_file = format!("{}_optimizer", checkpoint_name);
+    trainer.optimizer().save(&checkpoint_dir.join(&optimizer_file))?;
+    
+    // Deep optimizer fast/slow parameters
+    let recorder = checkpoint_recorder();
+    let deep_optimizer_file = match trainer.deep_optimizer_state() {
+        Some(state) => {
+            let file = format!("{}_deep_optimizer", checkpoint_name);
+            recorder
+                .record(state.clone(), checkpoint_dir.join(&file))
+                .with_context(|| "Failed to save deep optimizer state")?;
+            Some(file)
+        }
+        None => None,
+    };
+    
+    let memory_file = match trainer.memory_state() {
+        Some(state) => {
+            let file = format!("{}_memory", checkpoint_name);
+            recorder
+                .record(state, checkpoint_dir.join(&file))
+                .with_context(|| "Failed to save continuum memory state")?;
+            Some(file)
+        }
+        None => None,
+    };
+    
     let checkpoint_data = CheckpointData {
-        step,
-        config: config.clone(),
+        step: progress.step,
+        config: trainer.config().clone(),
         model_file,
-        timestamp,
+        precision,
+        timestamp: progress.timestamp,
+        corpus_version: progress.corpus_version.map(str::to_string),
+        optimizer_file: Some(optimizer_file),
+        deep_optimizer_file,
+        memory_file,
+        val_loss: progress.val_loss,
+        loader_state: progress.loader_state,
+        rng_seed: progress.rng_seed,
     };
-    
-    let metadata_path = checkpoint_path.with_extension("json");
-    let metadata_json = serde_json::to_string_pretty(&checkpoint_data)
+    write_metadata(&checkpoint_data, checkpoint_name, checkpoint_dir)
+}
+
+/// Write `model`'s weights to `path` at `precision`
+fn record_model<B: Backend>(model: &HopeModel<B>, precision: CheckpointPrecision, path: PathBuf) -> Result<()> {
+    let record = model.clone().into_record();
+    match precision {
+        CheckpointPrecision::Full => checkpoint_recorder().record(record, path)?,
+        CheckpointPrecision::Half => half_checkpoint_recorder().record(record, path)?,
+    }
+    Ok(())
+}
+
+fn write_metadata(
+    checkpoint_data: &CheckpointData,
+    checkpoint_name: &str,
+    checkpoint_dir: &Path,
+) -> Result<PathBuf> {
+    let metadata_path = checkpoint_dir.join(checkpoint_name).with_extension("json");
+    let metadata_json = serde_json::to_string_pretty(checkpoint_data)
         .with_context(|| "Failed to serialize checkpoint metadata")?;
     
     fs::write(&metadata_path, metadata_json)
         .with_context(|| format!("Failed to write checkpoint metadata: {:?}", metadata_path))?;
     
-    info!("Checkpoint saved successfully at step {}: {:?}", step, metadata_path);
-    
     Ok(metadata_path)
 }
 
@@ -76,13 +315,10 @@ pub fn load_checkpoint<B: Backend>(
     device: &B::Device,
 ) -> Result<(HopeModel<B>, usize, TrainConfig)> {
     // Load checkpoint metadata
-    let metadata_json = fs::read_to_string(checkpoint_path)
-        .with_context(|| format!("Failed to read checkpoint file: {:?}", checkpoint_path))?;
-    
-    let checkpoint_data: CheckpointData = serde_json::from_str(&metadata_json)
-        .with_context(|| "Failed to parse checkpoint metadata")?;
+    let checkpoint_data = read_checkpoint_metadata(checkpoint_path)?;
     
     info!("Loading checkpoint from step {}", checkpoint_data.step);
+    checkpoint_data.config.model.self_modify.warn_legacy_meta_lr();
     
     // Load model weights
     let checkpoint_dir = checkpoint_path.parent()
@@ -93,11 +329,12 @@ pub fn load_checkpoint<B: Backend>(
     // Create a new model with the config from checkpoint
     let model = HopeModel::<B>::new(checkpoint_data.config.model.clone(), device);
     
-    // Load the saved weights
-    let recorder = NamedMpkFileRecorder::<FullPrecisionSettings>::new();
-    let record = recorder
-        .load(model_path.clone(), device)
-        .with_context(|| format!("Failed to load model weights from: {:?}", model_path))?;
+    // Load the saved weights, in the precision they were written at
+    let record = match checkpoint_data.precision {
+        CheckpointPrecision::Full => checkpoint_recorder().load(model_path.clone(), device),
+        CheckpointPrecision::Half => half_checkpoint_recorder().load(model_path.clone(), device),
+    }
+    .with_context(|| format!("Failed to load model weights from: {:?}", model_path))?;
     
     let model = model.load_record(record);
     
@@ -106,7 +343,105 @@ pub fn load_checkpoint<B: Backend>(
     Ok((model, checkpoint_data.step, checkpoint_data.config))
 }
 
-/// List all available checkpoints in a directory
+/// Load the optimizer state saved with a checkpoint into `optimizer`.
+/// Returns `None` (keeping a fresh optimizer) if the checkpoint has no
+/// optimizer state or was written by a different optimizer type.
+pub fn load_optimizer_state<B: AutodiffBackend>(
+    checkpoint_path: &Path,
+    optimizer: TrainingOptimizer<B>,
+    device: &B::Device,
+) -> Result<Option<TrainingOptimizer<B>>> {
+    let checkpoint_data = read_checkpoint_metadata(checkpoint_path)?;
+    
+    let Some(optimizer_file) = checkpoint_data.optimizer_file else {
+        warn!("Checkpoint has no optimizer state; optimizer starts fresh");
+        return Ok(None);
+    };
+    
+    if checkpoint_data.config.training.optimizer != optimizer.kind() {
+        warn!("Checkpoint optimizer {:?} differs from configured {:?}; optimizer starts fresh",
+            checkpoint_data.config.training.optimizer, optimizer.kind());
+        return Ok(None);
+    }
+    
+    let checkpoint_dir = checkpoint_path.parent()
+        .ok_or_else(|| anyhow::anyhow!("Invalid checkpoint path"))?;
+    let optimizer = optimizer.load(&checkpoint_dir.join(optimizer_file), device)?;
+    
+    info!("Optimizer state restored");
+    
+    Ok(Some(optimizer))
+}
+
+/// Load the deep optimizer state saved with a checkpoint, if it has one
+pub fn load_deep_optimizer_state<B: Backend>(
+    checkpoint_path: &Path,
+    device: &B::Device,
+) -> Result<Option<DeepOptimizerState<B>>> {
+    let checkpoint_data = read_checkpoint_metadata(checkpoint_path)?;
+    
+    let Some(deep_optimizer_file) = checkpoint_data.deep_optimizer_file else {
+        return Ok(None);
+    };
+    
+    let checkpoint_dir = checkpoint_path.parent()
+        .ok_or_else(|| anyhow::anyhow!("Invalid checkpoint path"))?;
+    let path = checkpoint_dir.join(deep_optimizer_file);
+    
+    let recorder = checkpoint_recorder();
+    let state = recorder
+        .load(path.clone(), device)
+        .with_context(|| format!("Failed to load deep optimizer state from: {:?}", path))?;
+    
+    Ok(Some(state))
+}
+
+/// Load the continuum memory saved with a checkpoint, if it has one:
+/// banks of one row, for `HopeModel::carry_from_memory`
+pub fn load_memory_state<B: Backend>(
+    checkpoint_path: &Path,
+    device: &B::Device,
+) -> Result<Option<ContinuumMemoryState<B>>> {
+    let checkpoint_data = read_checkpoint_metadata(checkpoint_path)?;
+    
+    let Some(memory_file) = checkpoint_data.memory_file else {
+        return Ok(None);
+    };
+    
+    let checkpoint_dir = checkpoint_path.parent()
+        .ok_or_else(|| anyhow::anyhow!("Invalid checkpoint path"))?;
+    let path = checkpoint_dir.join(memory_file);
+    
+    let state: ContinuumMemoryState<B> = checkpoint_recorder()
+        .load(path.clone(), device)
+        .with_context(|| format!("Failed to load continuum memory state from: {:?}", path))?;
+    
+    let model = &checkpoint_data.config.model;
+    let width = model.continuum_mem.slot_dim.unwrap_or(model.hidden_size);
+    if state.short.dims()[2] != model.hidden_size || state.episodic.dims()[2] != width {
+        anyhow::bail!(
+            "Continuum memory {:?} has widths {} and {}, expected {} and {}",
+            path,
+            state.short.dims()[2],
+            state.episodic.dims()[2],
+            model.hidden_size,
+            width
+        );
+    }
+    
+    Ok(Some(state))
+}
+
+/// Read checkpoint metadata without loading the model weights
+pub fn read_checkpoint_metadata(checkpoint_path: &Path) -> Result<CheckpointData> {
+    let metadata_json = fs::read_to_string(checkpoint_path)
+        .with_context(|| format!("Failed to read checkpoint file: {:?}", checkpoint_path))?;
+    
+    serde_json::from_str(&metadata_json)
+        .with_context(|| "Failed to parse checkpoint metadata")
+}
+
+/// List all step checkpoints in a directory (`best.json` is not included)
 pub fn list_checkpoints(checkpoint_dir: &Path) -> Result<Vec<(PathBuf, usize, u64)>> {
     if !checkpoint_dir.exists() {
         warn!("Checkpoint directory does not exist: {:?}", checkpoint_dir);
@@ -121,6 +456,9 @@ pub fn list_checkpoints(checkpoint_dir: &Path) -> Result<Vec<(PathBuf, usize, u6
         .filter_map(|e| e.ok())
     {
         let path = entry.path();
+        if matches!(path.file_stem().and_then(|s| s.to_str()), Some(BEST_CHECKPOINT_NAME | SWA_CHECKPOINT_NAME)) {
+            continue;
+        }
         if path.extension().and_then(|s| s.to_str()) == Some("json") {
             if let Ok(metadata_json) = fs::read_to_string(path) {
                 if let Ok(checkpoint_data) = serde_json::from_str::<CheckpointData>(&metadata_json) {
@@ -151,5 +489,94 @@ mod tests {
         let checkpoints = list_checkpoints(temp_dir.path()).unwrap();
         assert_eq!(checkpoints.len(), 0);
     }
+    
+    #[test]
+    fn test_best_checkpoint_is_refreshed_and_not_listed() {
+        use crate::config::HopeConfig;
+        use burn::backend::Autodiff;
+        use burn_ndarray::NdArray;
+        
+        type B = Autodiff<NdArray<f32>>;
+        
+        let temp_dir = TempDir::new().unwrap();
+        let device = Default::default();
+        let config = TrainConfig::for_test(HopeConfig::tiny(), "{}");
+        let model = HopeModel::<B>::new(config.model.clone(), &device);
+        let trainer = HopeTrainer::new(model, config, &device);
+        
+        assert_eq!(read_best_val_loss(temp_dir.path()), None);
+        save_best_checkpoint(&trainer, 10, 3.0, None, None, None, temp_dir.path()).unwrap();
+        let loader_state = LoaderState { position: 40, epoch: 1 };
+        let path = save_best_checkpoint(&trainer, 20, 2.5, None, Some(loader_state), Some(7), temp_dir.path()).unwrap();
+        
+        assert_eq!(read_best_val_loss(temp_dir.path()), Some(2.5));
+        assert_eq!(read_checkpoint_metadata(&path).unwrap().step, 20);
+        assert_eq!(read_checkpoint_metadata(&path).unwrap().loader_state, Some(loader_state));
+        assert_eq!(read_checkpoint_metadata(&path).unwrap().rng_seed, Some(7));
+        assert!(list_checkpoints(temp_dir.path()).unwrap().is_empty());
+        assert!(load_checkpoint::<B>(&path, &device).is_ok());
+        // Deep optimizer is enabled by default and saved alongside
+        assert!(load_deep_optimizer_state::<NdArray<f32>>(&path, &device).unwrap().is_some());
+    }
+    
+    #[test]
+    fn test_memory_state_is_saved_with_checkpoint() {
+        use crate::config::HopeConfig;
+        use crate::training::{BatchData, generate_random_batch};
+        use burn::backend::Autodiff;
+        use burn_ndarray::NdArray;
+        
+        type B = Autodiff<NdArray<f32>>;
+        
+        let temp_dir = TempDir::new().unwrap();
+        let device = Default::default();
+        let config = TrainConfig::for_test(HopeConfig::tiny(), r#"{"stateful": {"enabled": true}}"#);
+        let model = HopeModel::<B>::new(config.model.clone(), &device);
+        let mut trainer = HopeTrainer::new(model, config, &device);
+        
+        // No memory is saved before stateful training has accumulated one
+        let path = save_checkpoint(&trainer, 0, None, None, None, temp_dir.path()).unwrap();
+        assert!(load_memory_state::<NdArray<f32>>(&path, &device).unwrap().is_none());
+        
+        trainer.train_step(BatchData { offsets: vec![0, 100], ..generate_random_batch(2, 4, 8, &device) });
+        let path = save_checkpoint(&trainer, 1, None, None, None, temp_dir.path()).unwrap();
+        let memory = load_memory_state::<NdArray<f32>>(&path, &device).unwrap().unwrap();
+        assert_eq!(
+            memory.episodic.clone().into_data(),
+            trainer.memory_state().unwrap().episodic.into_data()
+        );
+        
+        let (model, _, _) = load_checkpoint::<NdArray<f32>>(&path, &device).unwrap();
+        let carry = model.carry_from_memory(3, &memory);
+        assert_eq!(carry.continuum_memory.unwrap().long.dims(), [3, 4, 16]);
+    }
+    
+    #[test]
+    fn test_half_precision_checkpoint_round_trip() {
+        use crate::config::HopeConfig;
+        use burn_ndarray::NdArray;
+        
+        type B = NdArray<f32>;
+        
+        let temp_dir = TempDir::new().unwrap();
+        let device = Default::default();
+        let mut config = TrainConfig::for_test(HopeConfig { hidden_size: 32, ..HopeConfig::tiny() }, "{}");
+        let model = HopeModel::<B>::new(config.model.clone(), &device);
+        let full = save_model_checkpoint(&model, &config, 3, None, "full", temp_dir.path()).unwrap();
+        config.training.checkpoint_precision = CheckpointPrecision::Half;
+        let half = save_model_checkpoint(&model, &config, 3, None, "half", temp_dir.path()).unwrap();
+        
+        let size = |name: &str| fs::metadata(temp_dir.path().join(name).with_extension("mpk")).unwrap().len();
+        assert!(size("half_model") * 10 < size("full_model") * 6);
+        assert_eq!(read_checkpoint_metadata(&half).unwrap().precision, CheckpointPrecision::Half);
+        
+        // Loading picks the precision up from the metadata
+        let weights = |path: &Path| {
+            let (model, _, _) = load_checkpoint::<B>(path, &device).unwrap();
+            model.into_record().head.weight.val().into_data().to_vec::<f32>().unwrap()
+        };
+        let (full, half) = (weights(&full), weights(&half));
+        assert!(full.iter().zip(&half).all(|(a, b)| (a - b).abs() <= a.abs() * 1e-3 + 1e-6));
+        assert_ne!(full, half);
+    }
 }
-
@@ -1,6 +1,10 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt;
 use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::utils::CleaningStage;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default)]
@@ -11,6 +15,58 @@ pub struct ContinuumMemConfig {
     pub mid_span: usize,
     pub long_span: usize,
     pub episodic_span: usize,
+    /// Scale applied to retrieval scores (default: 1/sqrt(hidden_size))
+    pub attention_scale: Option<f32>,
+    /// Softmax temperature for retrieval; > 1 flattens, < 1 sharpens
+    pub temperature: f32,
+    /// Scores are clamped to [-score_clamp, score_clamp] before the softmax
+    pub score_clamp: f32,
+    /// Lower bound for the temperature and the sqrt(hidden_size) divisor
+    pub epsilon: f32,
+    /// Positions kept per bank (ultra_short, short, mid, long, episodic) by a
+    /// learned salience score before retrieval; empty keeps every position,
+    /// 0 keeps every position of that bank
+    pub top_k: Vec<usize>,
+    /// Each query attends only to its this many highest-scoring memory
+    /// slots, across all banks, searched among the slots of the two blocks
+    /// of this many consecutive slots whose mean key scores highest
+    /// (default: every slot)
+    pub retrieval_top_k: Option<usize>,
+    /// Slots of the long and episodic banks: each holds this many
+    /// compressions of the sequence (average pools of consecutive
+    /// positions) instead of one entry per position (default: per position)
+    pub memory_slots: Option<usize>,
+    /// Width of the compressed slots: averages of groups of consecutive
+    /// features, read back through a learned up-projection (default: the
+    /// hidden size; needs `memory_slots`)
+    pub slot_dim: Option<usize>,
+    /// Scale the retrieved memory by a learned per-token gate (a sigmoid of
+    /// the query and the retrieved vector) instead of adding all of it
+    pub gated: bool,
+    /// Retrieve before every level, from its input, rather than once from
+    /// the embeddings
+    pub retrieve_every_level: bool,
+    /// How strongly each position is written into the mid, long and
+    /// episodic banks
+    pub write_policy: MemoryWritePolicy,
+    /// With the novelty policy, how much faster the most novel positions
+    /// are written than familiar ones (up to `1 + surprise_gain` times the
+    /// bank's rate)
+    pub surprise_gain: f32,
+}
+
+/// Write rate of the slower memory banks (`ContinuumMemConfig::write_policy`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MemoryWritePolicy {
+    /// Every position at the bank's EMA rate (`1 / span`)
+    #[default]
+    Uniform,
+    /// The EMA rate raised per position by the novelty of the new hidden
+    /// state against what the bank holds there (their distance relative to
+    /// their norms, in [0, 1]), so rare content is not washed out by
+    /// frequent content
+    Novelty,
 }
 
 impl Default for ContinuumMemConfig {
@@ -22,6 +78,18 @@ impl Default for ContinuumMemConfig {
             mid_span: 32,
             long_span: 128,
             episodic_span: 512,
+            attention_scale: None,
+            temperature: 1.0,
+            score_clamp: 30.0,
+            epsilon: 1e-6,
+            top_k: Vec::new(),
+            retrieval_top_k: None,
+            memory_slots: None,
+            slot_dim: None,
+            gated: false,
+            retrieve_every_level: false,
+            write_policy: MemoryWritePolicy::default(),
+            surprise_gain: 4.0,
         }
     }
 }
@@ -34,6 +102,19 @@ impl ContinuumMemConfig {
             assert!(self.mid_span >= self.short_span, "mid_span must be >= short_span");
             assert!(self.long_span >= self.mid_span, "long_span must be >= mid_span");
             assert!(self.episodic_span >= self.long_span, "episodic_span must be >= long_span");
+            assert!(self.attention_scale.is_none_or(|s| s > 0.0), "attention_scale must be > 0");
+            assert!(self.temperature > 0.0, "temperature must be > 0");
+            assert!(self.score_clamp > 0.0, "score_clamp must be > 0");
+            assert!(self.epsilon > 0.0, "epsilon must be > 0");
+            assert!(
+                self.top_k.is_empty() || self.top_k.len() == 5,
+                "top_k must list one k per memory bank (5 values)"
+            );
+            assert!(self.retrieval_top_k != Some(0), "retrieval_top_k must be > 0");
+            assert!(self.memory_slots != Some(0), "memory_slots must be > 0");
+            assert!(self.slot_dim != Some(0), "slot_dim must be > 0");
+            assert!(self.slot_dim.is_none() || self.memory_slots.is_some(), "slot_dim needs memory_slots");
+            assert!(self.surprise_gain >= 0.0, "surprise_gain must be >= 0");
         }
     }
 }
@@ -48,7 +129,9 @@ impl fmt::Display for ContinuumMemConfig {
 #[serde(default)]
 pub struct SelfModifyConfig {
     pub enabled: bool,
+    /// Rate at which the meta state moves towards each new update rule
     pub meta_lr: f32,
+    /// Steps (timescale iterations) between meta state updates
     pub update_frequency: usize,
     pub weight_mod_dim: usize,
 }
@@ -57,17 +140,33 @@ impl Default for SelfModifyConfig {
     fn default() -> Self {
         Self {
             enabled: true,
-            meta_lr: 1e-5,
+            meta_lr: 0.1,
             update_frequency: 8,
             weight_mod_dim: 128,
         }
     }
 }
 
+/// `meta_lr` values below this most likely come from a config written when
+/// it was an (unused) learning rate, before it became the blend rate
+const LEGACY_META_LR: f32 = 1e-3;
+
 impl SelfModifyConfig {
+    /// Warn about a `meta_lr` that looks like a learning rate from an older
+    /// config: as a blend rate it leaves the meta state almost frozen
+    pub fn warn_legacy_meta_lr(&self) {
+        if self.enabled && self.meta_lr < LEGACY_META_LR {
+            tracing::warn!(
+                "self_modify.meta_lr = {:e} is the rate at which the meta state moves towards each new update rule \
+                 (default 0.1), not a learning rate; at this value the meta state barely changes",
+                self.meta_lr
+            );
+        }
+    }
+
     pub fn validate(&self) {
         if self.enabled {
-            assert!(self.meta_lr > 0.0, "meta_lr must be > 0");
+            assert!(self.meta_lr > 0.0 && self.meta_lr <= 1.0, "meta_lr must be in (0, 1]");
             assert!(self.update_frequency > 0, "update_frequency must be > 0");
             assert!(self.weight_mod_dim > 0, "weight_mod_dim must be > 0");
         }
@@ -129,11 +228,39 @@ pub struct HopeConfig {
     pub num_heads: usize,
     pub num_layers: usize,
     pub ff_multiplier: f32,
+    /// Activation of the encoder feed-forward blocks
+    pub ffn_activation: FfnActivation,
     pub dropout: f64,
+    /// Mask attention to future positions (next-token prediction)
+    pub causal: bool,
+    /// How the level encoders see token order (default: learned absolute
+    /// position embeddings)
+    pub position_encoding: PositionEncoding,
+    /// Each position attends only to the positions less than this many
+    /// tokens away in the level encoders (default: the whole sequence).
+    /// Attention then costs O(seq_len * window); what lies further back is
+    /// left to the continuum memory.
+    pub attention_window: Option<usize>,
     
     // 嵌套层级
     pub num_levels: usize,
     pub level_timescales: Vec<usize>,
+    /// Per-level overrides of `num_layers`, `num_heads` and `hidden_size`,
+    /// one value per level like `level_timescales` (empty: every level uses
+    /// the global value). A level whose width differs from `hidden_size`
+    /// projects its input in and its output back out.
+    pub level_num_layers: Vec<usize>,
+    pub level_num_heads: Vec<usize>,
+    pub level_hidden_sizes: Vec<usize>,
+    /// Per-level feed-forward widths, overriding `hidden_size *
+    /// ff_multiplier` (e.g. after pruning neurons with `prune --remove`)
+    pub level_feedforward_dims: Vec<usize>,
+    /// Add the level-0 input (embeddings with retrieved memory) to the input
+    /// of every later level, besides the previous level's output
+    pub level_skip: bool,
+    /// How the outputs of all levels are combined before the head (default:
+    /// the last level's output alone)
+    pub level_fusion: LevelFusionMode,
     
     // 连续内存
     pub continuum_mem: ContinuumMemConfig,
@@ -154,9 +281,19 @@ impl Default for HopeConfig {
             num_heads: 8,
             num_layers: 4,
             ff_multiplier: 4.0,
+            ffn_activation: FfnActivation::default(),
             dropout: 0.1,
+            causal: true,
+            position_encoding: PositionEncoding::default(),
+            attention_window: None,
             num_levels: 3,
             level_timescales: vec![1, 4, 16],
+            level_num_layers: Vec::new(),
+            level_num_heads: Vec::new(),
+            level_hidden_sizes: Vec::new(),
+            level_feedforward_dims: Vec::new(),
+            level_skip: false,
+            level_fusion: LevelFusionMode::default(),
             continuum_mem: ContinuumMemConfig::default(),
             self_modify: SelfModifyConfig::default(),
             deep_optimizer: DeepOptimizerConfig::default(),
@@ -167,18 +304,40 @@ impl Default for HopeConfig {
 impl HopeConfig {
     pub fn validate(&self) {
         assert!(self.hidden_size > 0, "hidden_size must be > 0");
-        assert!(self.hidden_size % self.num_heads == 0, "hidden_size must be divisible by num_heads");
+        assert!(self.hidden_size.is_multiple_of(self.num_heads), "hidden_size must be divisible by num_heads");
         assert!(self.vocab_size > 0, "vocab_size must be > 0");
         assert!(self.seq_len > 0, "seq_len must be > 0");
         assert!(self.num_heads > 0, "num_heads must be > 0");
         assert!(self.num_layers > 0, "num_layers must be > 0");
         assert!(self.num_levels > 0, "num_levels must be > 0");
+        assert!(self.attention_window != Some(0), "attention_window must be > 0");
         assert!(!self.level_timescales.is_empty(), "level_timescales must not be empty");
         assert_eq!(
             self.level_timescales.len(),
             self.num_levels,
             "level_timescales length must match num_levels"
         );
+        for (name, values) in [
+            ("level_num_layers", &self.level_num_layers),
+            ("level_num_heads", &self.level_num_heads),
+            ("level_hidden_sizes", &self.level_hidden_sizes),
+            ("level_feedforward_dims", &self.level_feedforward_dims),
+        ] {
+            assert!(
+                values.is_empty() || values.len() == self.num_levels,
+                "{} must be empty or list one value per level",
+                name
+            );
+            assert!(values.iter().all(|&value| value > 0), "{} values must be > 0", name);
+        }
+        for level in 0..self.num_levels {
+            let shape = self.level_shape(level);
+            assert!(
+                shape.hidden_size.is_multiple_of(shape.num_heads),
+                "level {} hidden size must be divisible by its number of heads",
+                level
+            );
+        }
         self.continuum_mem.validate();
         self.self_modify.validate();
         self.deep_optimizer.validate();
@@ -187,6 +346,87 @@ impl HopeConfig {
     pub fn feedforward_dim(&self) -> usize {
         (self.hidden_size as f32 * self.ff_multiplier).round() as usize
     }
+
+    /// Encoder shape of `level`, after the per-level overrides
+    pub fn level_shape(&self, level: usize) -> LevelShape {
+        let hidden_size = self.level_hidden_sizes.get(level).copied().unwrap_or(self.hidden_size);
+        LevelShape {
+            num_layers: self.level_num_layers.get(level).copied().unwrap_or(self.num_layers),
+            num_heads: self.level_num_heads.get(level).copied().unwrap_or(self.num_heads),
+            hidden_size,
+            feedforward_dim: self
+                .level_feedforward_dims
+                .get(level)
+                .copied()
+                .unwrap_or_else(|| (hidden_size as f32 * self.ff_multiplier).round() as usize),
+        }
+    }
+
+    /// Most tokens the model reads at once: `seq_len` with learned position
+    /// embeddings, unbounded with ALiBi
+    pub fn context_len(&self) -> Option<usize> {
+        match self.position_encoding {
+            PositionEncoding::Learned => Some(self.seq_len),
+            PositionEncoding::Alibi => None,
+        }
+    }
+}
+
+/// Encoder shape of one level (`HopeConfig::level_shape`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LevelShape {
+    pub num_layers: usize,
+    pub num_heads: usize,
+    pub hidden_size: usize,
+    pub feedforward_dim: usize,
+}
+
+/// Activation of the encoder feed-forward blocks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FfnActivation {
+    #[default]
+    Gelu,
+    Relu,
+    Silu,
+    /// Gated: `silu(x W) * (x V)`, with a second input projection (half
+    /// again the feed-forward parameters for the same `ff_multiplier`)
+    Swiglu,
+}
+
+impl fmt::Display for FfnActivation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Positional scheme of the model
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PositionEncoding {
+    /// Learned absolute position embeddings added to the token embeddings,
+    /// for at most `seq_len` positions
+    #[default]
+    Learned,
+    /// ALiBi (attention with linear biases): no position embeddings; each
+    /// head lowers attention scores in proportion to the query-key distance,
+    /// so inputs may run past `seq_len`
+    Alibi,
+}
+
+/// Combination of the level outputs fed to the head
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LevelFusionMode {
+    /// The last level's output only
+    #[default]
+    Last,
+    Sum,
+    /// Sum weighted by a learned softmax over the levels (starting at the
+    /// mean)
+    Weighted,
+    /// Levels concatenated and projected back to `hidden_size`
+    Concat,
 }
 
 impl fmt::Display for HopeConfig {
@@ -195,18 +435,29 @@ impl fmt::Display for HopeConfig {
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TrainingConfig {
     #[serde(default = "default_batch_size")]
     pub batch_size: usize,
     #[serde(default = "default_num_steps")]
     pub num_steps: usize,
+    /// Train until this many tokens (`batch_size * seq_len` per step, counted
+    /// from step 0) have been consumed; replaces `num_steps` when set
+    #[serde(default)]
+    pub max_tokens: Option<u64>,
+    /// Wall-clock budget of a run (both may be set and add up): training
+    /// stops before a step would exceed it and saves a resumable checkpoint
+    #[serde(default)]
+    pub max_hours: Option<f64>,
+    #[serde(default)]
+    pub max_minutes: Option<f64>,
     #[serde(default = "default_learning_rate")]
     pub learning_rate: f32,
     #[serde(default = "default_log_every")]
     pub log_every: usize,
-    #[serde(default = "default_use_random_data")]
-    #[allow(dead_code)]
+    /// Train on random tokens instead of `data` (smoke test of the model
+    /// and training loop)
+    #[serde(default)]
     pub use_random_data: bool,
     #[serde(default = "default_checkpoint_dir")]
     pub checkpoint_dir: PathBuf,
@@ -214,23 +465,500 @@ pub struct TrainingConfig {
     pub save_every: usize,
     #[serde(default)]
     pub resume_from: Option<PathBuf>,
+    /// Start a new run (step 0, fresh optimizer) from this checkpoint's
+    /// weights, e.g. to fine-tune on a new corpus
+    #[serde(default)]
+    pub init_from: Option<PathBuf>,
+    /// Warm-start a new run from a HuggingFace safetensors checkpoint in
+    /// the GPT-2 or Llama layout: its token and position embeddings and
+    /// output head are copied where the shapes match
+    #[serde(default)]
+    pub init_from_pretrained: Option<PathBuf>,
+    /// With `init_from_pretrained`, also copy its transformer blocks onto
+    /// the encoder layers (the i-th block onto the i-th layer, counting
+    /// through the levels in order)
+    #[serde(default)]
+    pub pretrained_blocks: bool,
+    /// Float type of the forward/backward pass; the master weights and
+    /// optimizer state stay in f32
+    #[serde(default)]
+    pub precision: Precision,
+    /// Initial loss scale of half-precision training, halved whenever the
+    /// gradients overflow and doubled after 2000 clean steps
+    #[serde(default = "default_loss_scale")]
+    pub loss_scale: f32,
+    /// Precision of the model weights in saved checkpoints; `half` stores
+    /// them as f16, about half the size. Optimizer state stays in f32.
+    #[serde(default)]
+    pub checkpoint_precision: CheckpointPrecision,
+    #[serde(default)]
+    pub corpus_mismatch: CorpusMismatchPolicy,
+    #[serde(default)]
+    pub optimizer: OptimizerKind,
+    #[serde(default)]
+    pub optimizer_params: OptimizerParams,
+    #[serde(default)]
+    pub weight_decay: Option<f32>,
+    /// Label smoothing for the cross-entropy loss, in [0, 1]
+    #[serde(default)]
+    pub label_smoothing: f32,
+    /// Recompute memory-bound activations in the backward pass instead of
+    /// keeping them for every level/timescale iteration
+    #[serde(default)]
+    pub activation_checkpointing: bool,
+    /// Write TensorBoard event files with training/validation metrics here
+    #[serde(default)]
+    pub log_dir: Option<PathBuf>,
+    /// Append metrics at every log interval to `checkpoint_dir/metrics.{csv,jsonl}`
+    #[serde(default)]
+    pub metrics_format: Option<MetricsFormat>,
+    /// Seed for the backend RNG (parameter initialization, dropout); runs
+    /// with the same seed and config produce identical loss curves
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Number of devices (model replicas) every batch is split across, with
+    /// gradients averaged each step; 1 = train on a single device
+    #[serde(default = "default_data_parallel")]
+    pub data_parallel: usize,
+    #[serde(default)]
+    pub document_loss_window: usize,
+    #[serde(default)]
+    pub quarantine: QuarantineConfig,
+    #[serde(default)]
+    pub nan_guard: NanGuardConfig,
+    #[serde(default)]
+    pub swa: SwaConfig,
+    #[serde(default)]
+    pub loss_weights: LossWeightsConfig,
+    #[serde(default)]
+    pub robust_loss: RobustLossConfig,
+    #[serde(default)]
+    pub val_data: Option<PathBuf>,
+    #[serde(default = "default_val_every")]
+    pub val_every: usize,
+    #[serde(default)]
+    pub early_stopping: EarlyStoppingConfig,
+    #[serde(default)]
+    pub stateful: StatefulConfig,
+    #[serde(default)]
+    pub lr_schedule: LrScheduleConfig,
+    /// LR multipliers by module path prefix (e.g. `token_embed`,
+    /// `self_modify`, `continuum_memory`); the longest matching prefix
+    /// applies, other parameters use 1.0 and 0 freezes a module
+    #[serde(default)]
+    pub lr_multipliers: BTreeMap<String, f32>,
+    /// Module path prefixes whose gradients are dropped before the
+    /// optimizer step, so the optimizer (and weight decay) never touches them
+    #[serde(default)]
+    pub freeze: Vec<String>,
+    /// Time every stage of the model's forward pass (embedding, memory
+    /// retrieval, each level, self-modification, memory update, head) and
+    /// write the aggregated report to `checkpoint_dir/profile.json` when
+    /// training ends. Synchronizes the device after every stage, so it slows
+    /// training down
+    #[serde(default)]
+    pub profile: bool,
+}
+
+impl TrainingConfig {
+    pub fn validate(&self) {
+        for &multiplier in self.lr_multipliers.values() {
+            assert!(multiplier.is_finite() && multiplier >= 0.0, "lr_multipliers must be finite and >= 0");
+        }
+    }
+}
+
+/// Truncated BPTT: keep the (detached) carry across consecutive batches of
+/// the same token streams instead of starting every batch from zero
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StatefulConfig {
+    pub enabled: bool,
+    /// Start from a fresh carry every N batches (0 = only when the data restarts)
+    pub reset_every: usize,
+}
+
+impl Default for StatefulConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            reset_every: 64,
+        }
+    }
+}
+
+/// Learning-rate schedule applied to `learning_rate`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LrScheduleConfig {
+    pub kind: LrScheduleKind,
+    /// Length of the first cycle in steps (`cosine_restarts`)
+    pub cycle_steps: usize,
+    /// Every cycle is this many times longer than the one before
+    pub cycle_mult: usize,
+    /// Learning rate at the end of a cycle, as a fraction of `learning_rate`
+    pub min_lr_ratio: f32,
+    /// Save `snapshot_cycle_<n>` at the end of every cycle (for snapshot ensembles)
+    pub snapshot_every_cycle: bool,
+}
+
+impl Default for LrScheduleConfig {
+    fn default() -> Self {
+        Self {
+            kind: LrScheduleKind::Constant,
+            cycle_steps: 1000,
+            cycle_mult: 1,
+            min_lr_ratio: 0.0,
+            snapshot_every_cycle: false,
+        }
+    }
+}
+
+impl LrScheduleConfig {
+    pub fn validate(&self) {
+        if self.kind == LrScheduleKind::CosineRestarts {
+            assert!(self.cycle_steps > 0, "cycle_steps must be > 0");
+            assert!(self.cycle_mult > 0, "cycle_mult must be > 0");
+            assert!((0.0..=1.0).contains(&self.min_lr_ratio), "min_lr_ratio must be within [0,1]");
+        } else {
+            assert!(!self.snapshot_every_cycle, "snapshot_every_cycle requires the cosine_restarts schedule");
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LrScheduleKind {
+    /// `learning_rate` at every step
+    Constant,
+    /// Cosine annealing from `learning_rate` down to the minimum over each
+    /// cycle, then a warm restart (SGDR)
+    CosineRestarts,
+}
+
+/// Early stopping on validation loss (requires `val_data`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EarlyStoppingConfig {
+    pub enabled: bool,
+    /// Validations without improvement before stopping
+    pub patience: usize,
+    /// Minimum decrease in validation loss that counts as an improvement
+    pub min_delta: f32,
+}
+
+impl Default for EarlyStoppingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            patience: 5,
+            min_delta: 0.0,
+        }
+    }
+}
+
+impl EarlyStoppingConfig {
+    pub fn validate(&self) {
+        if self.enabled {
+            assert!(self.patience > 0, "patience must be > 0");
+            assert!(self.min_delta >= 0.0, "min_delta must be >= 0");
+        }
+    }
+}
+
+/// Bad-batch quarantine: batches whose loss repeatedly spikes above the
+/// running average are dumped for inspection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QuarantineConfig {
+    pub enabled: bool,
+    /// Loss must exceed `spike_factor` x running average to count as a spike
+    pub spike_factor: f32,
+    /// Consecutive spiking batches before they are quarantined
+    pub patience: usize,
+    /// EMA decay of the running average loss
+    pub ema_decay: f32,
+    /// Output folder (default: `<checkpoint_dir>/quarantine`)
+    pub dir: Option<PathBuf>,
+    /// Skip later batches whose sequences overlap quarantined offsets
+    pub skip_offsets: bool,
+}
+
+impl Default for QuarantineConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            spike_factor: 3.0,
+            patience: 2,
+            ema_decay: 0.98,
+            dir: None,
+            skip_offsets: false,
+        }
+    }
+}
+
+impl QuarantineConfig {
+    pub fn validate(&self) {
+        if self.enabled {
+            assert!(self.spike_factor > 1.0, "spike_factor must be > 1");
+            assert!(self.patience > 0, "patience must be > 0");
+            assert!((0.0..1.0).contains(&self.ema_decay), "ema_decay must be within [0,1)");
+        }
+    }
+}
+
+/// Non-finite loss guard: the optimizer step of a NaN/Inf loss is skipped
+/// instead of corrupting the weights, and a run that keeps producing them is
+/// rolled back to its last checkpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NanGuardConfig {
+    pub enabled: bool,
+    /// Learning rate is multiplied by this after every non-finite loss (1 = unchanged)
+    pub lr_backoff: f32,
+    /// Consecutive non-finite losses before rolling back to the last checkpoint
+    pub max_consecutive: usize,
+}
+
+impl Default for NanGuardConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            lr_backoff: 1.0,
+            max_consecutive: 5,
+        }
+    }
+}
+
+impl NanGuardConfig {
+    pub fn validate(&self) {
+        if self.enabled {
+            assert!(self.lr_backoff > 0.0 && self.lr_backoff <= 1.0, "lr_backoff must be within (0,1]");
+            assert!(self.max_consecutive > 0, "max_consecutive must be > 0");
+        }
+    }
+}
+
+/// Stochastic weight averaging: a running mean of the weights collected at a
+/// fixed interval over the final phase of training, saved as `swa.json`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SwaConfig {
+    pub enabled: bool,
+    /// Start collecting after this fraction of the run's steps
+    pub start_fraction: f32,
+    /// Start collecting at this step instead (overrides `start_fraction`)
+    pub start_step: Option<usize>,
+    /// Steps between collected weights
+    pub every: usize,
+}
+
+impl Default for SwaConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            start_fraction: 0.75,
+            start_step: None,
+            every: 10,
+        }
+    }
+}
+
+impl SwaConfig {
+    pub fn validate(&self) {
+        if self.enabled {
+            assert!((0.0..1.0).contains(&self.start_fraction), "swa.start_fraction must be within [0,1)");
+            assert!(self.every > 0, "swa.every must be > 0");
+        }
+    }
+
+    /// First step whose weights are collected, for a run ending at `final_step`
+    pub fn first_step(&self, final_step: usize) -> usize {
+        self.start_step
+            .unwrap_or_else(|| (final_step as f64 * f64::from(self.start_fraction)).ceil() as usize)
+            .max(1)
+    }
 }
 
+/// Per-token weights of the training loss, e.g. to downweight structure
+/// markers and padding or upweight rare characters
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LossWeightsConfig {
+    /// Weight of every target position predicting one of these strings:
+    /// single characters are matched as tokens, longer strings (e.g.
+    /// `<CHAPTER>`) as token sequences within each row
+    pub tokens: BTreeMap<String, f32>,
+    /// Weight of padding targets
+    pub pad: f32,
+}
+
+impl Default for LossWeightsConfig {
+    fn default() -> Self {
+        Self {
+            tokens: BTreeMap::new(),
+            pad: 1.0,
+        }
+    }
+}
+
+impl LossWeightsConfig {
+    pub fn validate(&self) {
+        assert!(self.pad.is_finite() && self.pad >= 0.0, "loss_weights.pad must be finite and >= 0");
+        for (text, &weight) in &self.tokens {
+            assert!(!text.is_empty(), "loss_weights.tokens keys must not be empty");
+            assert!(weight.is_finite() && weight >= 0.0, "loss_weights.tokens must be finite and >= 0");
+        }
+    }
+
+    /// Whether any target is weighted differently from 1.0
+    pub fn is_enabled(&self) -> bool {
+        self.pad != 1.0 || !self.tokens.is_empty()
+    }
+}
+
+/// Limits how much single tokens with a huge loss (OCR garbage, stray
+/// binary data) can dominate the gradient
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RobustLossConfig {
+    pub kind: RobustLossKind,
+    /// Per-token loss cap (`clipped`)
+    pub max_loss: f32,
+    /// Exponent of `generalized`, in (0, 1]: towards 0 it approaches
+    /// cross-entropy, at 1 every token's loss is at most 1
+    pub q: f32,
+}
+
+impl Default for RobustLossConfig {
+    fn default() -> Self {
+        Self {
+            kind: RobustLossKind::None,
+            max_loss: 10.0,
+            q: 0.7,
+        }
+    }
+}
+
+impl RobustLossConfig {
+    pub fn validate(&self) {
+        match self.kind {
+            RobustLossKind::None => {}
+            RobustLossKind::Clipped => {
+                assert!(self.max_loss.is_finite() && self.max_loss > 0.0, "robust_loss.max_loss must be > 0");
+            }
+            RobustLossKind::Generalized => {
+                assert!(self.q > 0.0 && self.q <= 1.0, "robust_loss.q must be within (0,1]");
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RobustLossKind {
+    /// Plain cross-entropy
+    None,
+    /// Cap every token's loss at `max_loss`; tokens above it get no gradient
+    Clipped,
+    /// Generalized cross-entropy `(1 - p^q) / q`, `p` the probability of the
+    /// target (Zhang & Sabuncu 2018): the gradient of every token is scaled
+    /// by `p^q`, so targets the model finds implausible pull less
+    Generalized,
+}
+
+/// File format of the exported training metrics
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricsFormat {
+    Csv,
+    Jsonl,
+}
+
+/// Float type of the training forward/backward pass. Half precision runs
+/// on a copy of the f32 master weights on a backend with that float type.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Precision {
+    #[default]
+    F32,
+    F16,
+    Bf16,
+}
+
+/// Float precision of a checkpoint's model weights file. It is recorded in
+/// the checkpoint metadata, so loading picks the matching format; loaded
+/// weights are always f32.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckpointPrecision {
+    #[default]
+    Full,
+    Half,
+}
+
+/// Optimizer used for the training step
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OptimizerKind {
+    Sgd,
+    #[default]
+    Adam,
+    AdamW,
+    Lion,
+    RAdam,
+}
+
+/// Hyperparameters of the selected optimizer besides the learning rate and
+/// `weight_decay`; unset values use that optimizer's defaults
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OptimizerParams {
+    /// First-moment (momentum) decay: adam/adamw/radam 0.9, lion 0.9
+    pub beta_1: Option<f32>,
+    /// Second-moment decay: adam/adamw/radam 0.999; lion's momentum decay, 0.99
+    pub beta_2: Option<f32>,
+    /// Denominator epsilon of adam/adamw/radam (1e-5 for adam/adamw, 1e-8 for radam)
+    pub epsilon: Option<f32>,
+    /// Layer-wise LR decay for fine-tuning: the head trains at the full
+    /// rate, each level below at `layer_decay` times the one above it and
+    /// the embeddings below the first level (unset = no decay)
+    pub layer_decay: Option<f32>,
+}
+
+impl OptimizerParams {
+    pub fn validate(&self) {
+        for beta in [self.beta_1, self.beta_2].into_iter().flatten() {
+            assert!((0.0..1.0).contains(&beta), "optimizer betas must be within [0,1)");
+        }
+        if let Some(epsilon) = self.epsilon {
+            assert!(epsilon > 0.0, "optimizer epsilon must be > 0");
+        }
+        if let Some(decay) = self.layer_decay {
+            assert!(decay > 0.0 && decay <= 1.0, "layer_decay must be within (0,1]");
+        }
+    }
+}
+
+/// What to do when resuming against a corpus version other than the checkpoint's
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CorpusMismatchPolicy {
+    #[default]
+    Error,
+    Warn,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum DataType {
+    #[default]
     Random,
     Text,
     Books,
 }
 
-impl Default for DataType {
-    fn default() -> Self {
-        DataType::Random
-    }
-}
-
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DataConfig {
     #[serde(default)]
     pub data_type: DataType,
@@ -238,6 +966,147 @@ pub struct DataConfig {
     pub data_path: Option<PathBuf>,
     #[serde(default)]
     pub tokenizer_path: Option<PathBuf>,
+    /// Keep watching `data_path` (a directory of text files) and append new
+    /// files to the training stream as they appear
+    #[serde(default)]
+    pub follow: bool,
+    /// How often to look for new files in follow mode, in seconds
+    #[serde(default = "default_follow_poll_secs")]
+    pub follow_poll_secs: u64,
+    /// End follow-mode training once no new data arrived for this many
+    /// seconds (default: keep waiting)
+    #[serde(default)]
+    pub follow_timeout_secs: Option<u64>,
+    /// Batches prepared ahead on a background thread (0 = build them on the
+    /// training thread)
+    #[serde(default = "default_prefetch")]
+    pub prefetch: usize,
+    /// Pack batches to this many tokens (rows × longest row) instead of a
+    /// fixed `batch_size × seq_len`; documents are cut into sequences of at
+    /// most `seq_len` tokens and shorter rows are padded and masked
+    #[serde(default)]
+    pub max_batch_tokens: Option<usize>,
+    /// Visit the documents in a new random order (from the run seed) in
+    /// every pass over the data after the first
+    #[serde(default = "default_shuffle_documents")]
+    pub shuffle_documents: bool,
+    /// Train on several corpora at once: every batch row is drawn from one
+    /// of these sources (`data_type`/`data_path` above are then only used
+    /// for `training.val_data`)
+    #[serde(default)]
+    pub mixture: Vec<MixtureSource>,
+    /// Sources are sampled with probability proportional to
+    /// `weight^(1/mixture_temperature)`; above 1 flattens the mixture
+    /// towards uniform, below 1 sharpens it
+    #[serde(default = "default_mixture_temperature")]
+    pub mixture_temperature: f32,
+    /// How `preprocess` turns raw documents into the corpus at `data_path`
+    #[serde(default)]
+    pub preprocess: PreprocessConfig,
+}
+
+/// Settings of `hope-train preprocess`: documents under `input` are parsed,
+/// cleaned and tokenized into `data.data_path`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PreprocessConfig {
+    /// Directory of PDF/EPUB/HTML/DOCX/TXT files and archives
+    pub input: Option<PathBuf>,
+    /// Keep `<CHAPTER>`/`<PARAGRAPH>` structure markers
+    pub preserve_structure: bool,
+    /// OCR scanned PDFs
+    pub enable_ocr: bool,
+    /// Build the vocabulary from the corpus; otherwise reuse
+    /// `data.tokenizer_path` or the output's `vocab.json` when present
+    pub build_vocab: bool,
+    /// Redact emails, phone numbers, ID numbers and addresses
+    pub scrub_pii: bool,
+    /// Remove lines repeating on at least this fraction of pages/documents
+    pub boilerplate_threshold: Option<f32>,
+    /// Ordered text-cleaning stages
+    pub cleaning_stages: Vec<CleaningStage>,
+    /// Reprocess every document instead of reusing unchanged ones from the cache
+    pub full_rebuild: bool,
+    /// File with article or RSS/Atom feed URLs (one per line), downloaded
+    /// into `<input>/web` first
+    pub fetch: Option<PathBuf>,
+    pub fetch_concurrency: usize,
+    /// Retries (with exponential backoff) for failed downloads
+    pub fetch_retries: usize,
+    /// Maximum requests per second across all downloads
+    pub fetch_rate: Option<f64>,
+}
+
+impl Default for PreprocessConfig {
+    fn default() -> Self {
+        Self {
+            input: None,
+            preserve_structure: true,
+            enable_ocr: false,
+            build_vocab: true,
+            scrub_pii: false,
+            boilerplate_threshold: None,
+            cleaning_stages: CleaningStage::ALL.to_vec(),
+            full_rebuild: false,
+            fetch: None,
+            fetch_concurrency: 4,
+            fetch_retries: 3,
+            fetch_rate: None,
+        }
+    }
+}
+
+impl PreprocessConfig {
+    pub fn validate(&self) {
+        if let Some(threshold) = self.boilerplate_threshold {
+            assert!(threshold > 0.0 && threshold <= 1.0, "preprocess.boilerplate_threshold must be within (0,1]");
+        }
+        assert!(self.fetch_concurrency > 0, "preprocess.fetch_concurrency must be > 0");
+        if let Some(rate) = self.fetch_rate {
+            assert!(rate > 0.0, "preprocess.fetch_rate must be > 0");
+        }
+    }
+}
+
+/// Settings of `hope-train pipeline`: preprocess `data.preprocess.input`
+/// into `data.data_path` (when the corpus is stale), train, evaluate the
+/// result and export it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PipelineConfig {
+    /// Held-out data for the final evaluation, and for the periodic one when
+    /// `training.val_data` is unset (default: `training.val_data`, or the
+    /// training corpus)
+    pub eval_data: Option<PathBuf>,
+    /// Where the model, tokenizer, eval results and manifest are exported
+    /// (default: `<checkpoint_dir>/export`)
+    pub export_dir: Option<PathBuf>,
+    /// Export `best.json` (lowest validation loss) instead of the final
+    /// checkpoint when training wrote one
+    pub export_best: bool,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            eval_data: None,
+            export_dir: None,
+            export_best: true,
+        }
+    }
+}
+
+/// One corpus of `data.mixture`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MixtureSource {
+    pub data_type: DataType,
+    pub data_path: PathBuf,
+    /// Tokenizer of this source (default: `data.tokenizer_path`)
+    #[serde(default)]
+    pub tokenizer_path: Option<PathBuf>,
+    /// Relative sampling weight
+    #[serde(default = "default_mixture_weight")]
+    pub weight: f32,
 }
 
 impl Default for DataConfig {
@@ -246,17 +1115,28 @@ impl Default for DataConfig {
             data_type: DataType::Random,
             data_path: None,
             tokenizer_path: None,
+            follow: false,
+            follow_poll_secs: default_follow_poll_secs(),
+            follow_timeout_secs: None,
+            prefetch: default_prefetch(),
+            max_batch_tokens: None,
+            shuffle_documents: default_shuffle_documents(),
+            mixture: Vec::new(),
+            mixture_temperature: default_mixture_temperature(),
+            preprocess: PreprocessConfig::default(),
         }
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TrainConfig {
     pub model: HopeConfig,
     #[serde(rename = "training")]
     pub training: TrainingConfig,
     #[serde(default)]
     pub data: DataConfig,
+    #[serde(default)]
+    pub pipeline: PipelineConfig,
 }
 
 impl TrainConfig {
@@ -275,6 +1155,72 @@ impl TrainConfig {
     pub fn log_every(&self) -> usize {
         self.training.log_every
     }
+    
+    /// Tokens consumed by one training step (at most, with `data.max_batch_tokens`)
+    pub fn tokens_per_step(&self) -> u64 {
+        match self.data.max_batch_tokens {
+            Some(budget) => budget as u64,
+            None => (self.training.batch_size * self.model.seq_len) as u64,
+        }
+    }
+    
+    /// Number of steps to run after `start_step`: `num_steps`, or the steps
+    /// left until the `max_tokens` budget is reached
+    pub fn steps_to_run(&self, start_step: usize) -> usize {
+        match self.training.max_tokens {
+            Some(budget) => {
+                let consumed = start_step as u64 * self.tokens_per_step();
+                budget.saturating_sub(consumed).div_ceil(self.tokens_per_step()) as usize
+            }
+            None => self.training.num_steps,
+        }
+    }
+    
+    /// Wall-clock budget from `max_hours` + `max_minutes`, if either is set
+    pub fn time_budget(&self) -> Option<Duration> {
+        let hours = self.training.max_hours;
+        let minutes = self.training.max_minutes;
+        if hours.is_none() && minutes.is_none() {
+            return None;
+        }
+        let (hours, minutes) = (hours.unwrap_or(0.0), minutes.unwrap_or(0.0));
+        assert!(hours >= 0.0 && minutes >= 0.0 && hours + minutes > 0.0,
+            "training.max_hours/max_minutes must be >= 0 and add up to more than 0");
+        Some(Duration::from_secs_f64(hours * 3600.0 + minutes * 60.0))
+    }
+}
+
+/// Small configurations for unit tests
+#[cfg(test)]
+impl HopeConfig {
+    /// One level of one narrow layer over an 8-token vocabulary; override
+    /// fields with `HopeConfig { num_levels: 2, ..HopeConfig::tiny() }`
+    pub(crate) fn tiny() -> Self {
+        HopeConfig {
+            hidden_size: 16,
+            vocab_size: 8,
+            seq_len: 4,
+            num_heads: 2,
+            num_layers: 1,
+            num_levels: 1,
+            level_timescales: vec![1],
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+impl TrainConfig {
+    /// `model` with the training section parsed from `training` (JSON,
+    /// `"{}"` for the defaults) and default data and pipeline sections
+    pub(crate) fn for_test(model: HopeConfig, training: &str) -> Self {
+        TrainConfig {
+            model,
+            training: serde_json::from_str(training).unwrap(),
+            data: Default::default(),
+            pipeline: Default::default(),
+        }
+    }
 }
 
 fn default_batch_size() -> usize {
@@ -293,8 +1239,8 @@ fn default_log_every() -> usize {
     10
 }
 
-fn default_use_random_data() -> bool {
-    true
+fn default_data_parallel() -> usize {
+    1
 }
 
 fn default_checkpoint_dir() -> PathBuf {
@@ -305,3 +1251,31 @@ fn default_save_every() -> usize {
     100
 }
 
+fn default_loss_scale() -> f32 {
+    32768.0
+}
+
+fn default_val_every() -> usize {
+    100
+}
+
+fn default_follow_poll_secs() -> u64 {
+    5
+}
+
+fn default_prefetch() -> usize {
+    2
+}
+
+fn default_shuffle_documents() -> bool {
+    true
+}
+
+fn default_mixture_temperature() -> f32 {
+    1.0
+}
+
+fn default_mixture_weight() -> f32 {
+    1.0
+}
+
@@ -1,26 +1,38 @@
 use anyhow::{Context, Result};
 use burn::tensor::{Int, Tensor, backend::Backend};
+use serde::Deserialize;
+use std::fs;
 use std::path::{Path, PathBuf};
 use tracing::{info, warn};
 use walkdir::WalkDir;
 
-use super::loader::DataLoader;
+use super::corpus::document_id;
+use super::loader::{DataLoader, DocumentSpans, batch_offsets, layout_num_batches, pack_batches, packed_batch, reorder_documents};
 use super::tokenizer::Tokenizer;
 use crate::training::BatchData;
-use crate::utils::{extract_text_from_pdf, extract_text_from_epub, add_structure_markers, clean_text};
+use crate::utils::{document_to_text, for_each_archive_entry, is_archive, is_supported_document, parse_document, parse_document_bytes, CleaningPipeline, Document};
 
-/// Book data loader that supports PDF and EPUB files
+/// Book data loader that supports every format handled by `parse_document`
+/// (PDF, EPUB, HTML, DOCX, plain text)
 pub struct BookDataLoader<B: Backend> {
     tokens: Vec<i64>,
     batch_size: usize,
     seq_len: usize,
     current_pos: usize,
+    stream_layout: bool,
+    /// Batches packed by `with_token_budget` (`current_pos` indexes them)
+    token_batches: Option<Vec<Vec<(usize, usize)>>>,
+    max_batch_tokens: Option<usize>,
     device: B::Device,
     book_files: Vec<PathBuf>,
+    documents: DocumentSpans,
+    /// Loaded document of each document in `tokens` (see `start_epoch`)
+    document_order: Vec<usize>,
 }
 
 impl<B: Backend> BookDataLoader<B> {
-    /// Create a new book data loader from a directory (online mode)
+    /// Create a new book data loader from a directory or archive (online
+    /// mode); books inside `.zip`/`.tar.zst` archives are read in place
     pub fn from_directory<T: Tokenizer>(
         dir_path: &Path,
         tokenizer: &T,
@@ -31,10 +43,12 @@ impl<B: Backend> BookDataLoader<B> {
     ) -> Result<Self> {
         info!("Loading books from directory: {:?}", dir_path);
         
+        let mut sources = Vec::new();
         let mut book_files = Vec::new();
-        let mut all_text = String::new();
+        let mut tokens = Vec::new();
+        let mut documents = DocumentSpans::default();
         
-        // Find all PDF and EPUB files
+        // Find all supported document files and archives of them
         for entry in WalkDir::new(dir_path)
             .into_iter()
             .filter_map(|e| e.ok())
@@ -42,40 +56,54 @@ impl<B: Backend> BookDataLoader<B> {
         {
             let path = entry.path();
             
-            if let Some(ext) = path.extension() {
-                let ext_str = ext.to_string_lossy().to_lowercase();
-                
-                if ext_str == "pdf" || ext_str == "epub" {
-                    book_files.push(path.to_path_buf());
-                }
+            if is_supported_document(path) || is_archive(path) {
+                sources.push(path.to_path_buf());
             }
         }
         
-        info!("Found {} book files", book_files.len());
+        info!("Found {} book files and archives", sources.len());
         
-        // Process each book
-        for (idx, book_path) in book_files.iter().enumerate() {
-            info!("Processing book {}/{}: {:?}", idx + 1, book_files.len(), book_path);
-            
-            match Self::extract_book_text(book_path, preserve_structure) {
-                Ok(text) => {
-                    all_text.push_str(&text);
-                    all_text.push_str("\n\n");
+        let mut add_book = |book_path: PathBuf, document: Result<Document>| {
+            match document {
+                Ok(document) => {
+                    let (text, _) = document_to_text(&document, preserve_structure, &CleaningPipeline::default());
+                    // Tokenize per book so sequences can be traced back to their source
+                    documents.push(tokens.len(), document_id(&text), book_path.to_string_lossy());
+                    tokens.extend(tokenizer.encode(&text));
+                    tokens.extend(tokenizer.encode("\n\n"));
                 }
                 Err(e) => {
                     warn!("Failed to process book {:?}: {}", book_path, e);
                 }
             }
+            book_files.push(book_path);
+        };
+        
+        // Process each book
+        for (idx, source) in sources.iter().enumerate() {
+            info!("Processing {}/{}: {:?}", idx + 1, sources.len(), source);
+            
+            if is_archive(source) {
+                let result = for_each_archive_entry(source, |name, bytes| {
+                    let book_path = source.join(name);
+                    if is_supported_document(&book_path) {
+                        let document = parse_document_bytes(&book_path, &bytes);
+                        add_book(book_path, document);
+                    }
+                    Ok(())
+                });
+                if let Err(e) = result {
+                    warn!("Failed to read archive {:?}: {}", source, e);
+                }
+            } else {
+                add_book(source.clone(), parse_document(source));
+            }
         }
         
-        if all_text.is_empty() {
+        if tokens.is_empty() {
             anyhow::bail!("No text extracted from books in {:?}", dir_path);
         }
         
-        info!("Total text length: {} characters", all_text.len());
-        
-        // Tokenize
-        let tokens = tokenizer.encode(&all_text);
         info!("Tokenized to {} tokens", tokens.len());
         
         Ok(Self {
@@ -83,8 +111,13 @@ impl<B: Backend> BookDataLoader<B> {
             batch_size,
             seq_len,
             current_pos: 0,
+            stream_layout: false,
+            token_batches: None,
+            max_batch_tokens: None,
             device,
             book_files,
+            documents,
+            document_order: Vec::new(),
         })
     }
     
@@ -102,56 +135,87 @@ impl<B: Backend> BookDataLoader<B> {
             batch_size,
             seq_len,
             current_pos: 0,
+            stream_layout: false,
+            token_batches: None,
+            max_batch_tokens: None,
             device,
             book_files: Vec::new(),
+            documents: DocumentSpans::default(),
+            document_order: Vec::new(),
         }
     }
     
-    /// Extract text from a single book file
-    fn extract_book_text(path: &Path, preserve_structure: bool) -> Result<String> {
-        let ext = path.extension()
-            .and_then(|s| s.to_str())
-            .unwrap_or("")
-            .to_lowercase();
-        
-        let text = match ext.as_str() {
-            "pdf" => {
-                let content = extract_text_from_pdf(path)?;
-                
-                if !content.has_text {
-                    anyhow::bail!("PDF has no extractable text (may need OCR)");
-                }
-                
-                if preserve_structure {
-                    // Try to extract structured content
-                    match crate::utils::pdf_parser::extract_structured_content(path) {
-                        Ok(sections) => add_structure_markers(sections),
-                        Err(_) => clean_text(&content.text),
-                    }
-                } else {
-                    clean_text(&content.text)
-                }
-            }
-            "epub" => {
-                let content = extract_text_from_epub(path)?;
-                
-                if preserve_structure {
-                    add_structure_markers(content.chapters)
-                } else {
-                    // Concatenate all chapters
-                    content.chapters
-                        .into_iter()
-                        .map(|(_, text)| clean_text(&text))
-                        .collect::<Vec<_>>()
-                        .join("\n\n")
-                }
-            }
-            _ => {
-                anyhow::bail!("Unsupported file format: {}", ext);
+    /// Load a `corpus.jsonl` written by `preprocess` (offline mode),
+    /// keeping per-document boundaries
+    pub fn from_corpus(
+        corpus_path: &Path,
+        batch_size: usize,
+        seq_len: usize,
+        device: B::Device,
+    ) -> Result<Self> {
+        info!("Loading preprocessed corpus: {:?}", corpus_path);
+        
+        let corpus = fs::read_to_string(corpus_path)
+            .with_context(|| format!("Failed to read corpus file: {:?}", corpus_path))?;
+        
+        let mut tokens = Vec::new();
+        let mut documents = DocumentSpans::default();
+        
+        for (line_no, line) in corpus.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
             }
-        };
+            
+            let entry: CorpusEntry = serde_json::from_str(line)
+                .with_context(|| format!("Invalid corpus entry at line {}", line_no + 1))?;
+            // Corpora written before stable IDs numbered documents by position
+            let id = match entry.id {
+                Some(CorpusId::Stable(id)) => id,
+                Some(CorpusId::Positional(_)) | None => document_id(&entry.text),
+            };
+            documents.push(tokens.len(), id, entry.filename);
+            tokens.extend(entry.tokens);
+        }
+        
+        if tokens.is_empty() {
+            anyhow::bail!("Corpus is empty: {:?}", corpus_path);
+        }
+        
+        info!("Loaded {} documents ({} tokens)", documents.len(), tokens.len());
         
-        Ok(text)
+        Ok(Self {
+            tokens,
+            batch_size,
+            seq_len,
+            current_pos: 0,
+            stream_layout: false,
+            token_batches: None,
+            max_batch_tokens: None,
+            device,
+            book_files: Vec::new(),
+            documents,
+            document_order: Vec::new(),
+        })
+    }
+    
+    /// Largest token id in the loaded data
+    pub fn max_token_id(&self) -> Option<i64> {
+        self.tokens.iter().copied().max()
+    }
+    
+    /// Use the stream layout (see `batch_offsets`) for stateful training
+    pub fn with_stream_layout(mut self, stream_layout: bool) -> Self {
+        self.stream_layout = stream_layout;
+        self
+    }
+    
+    /// Pack batches to at most `max_batch_tokens` tokens instead of
+    /// `batch_size` full sequences (see `pack_batches`)
+    pub fn with_token_budget(mut self, max_batch_tokens: Option<usize>) -> Self {
+        self.max_batch_tokens = max_batch_tokens;
+        self.token_batches = max_batch_tokens
+            .map(|budget| pack_batches(self.tokens.len(), &self.documents, self.seq_len, budget));
+        self
     }
     
     /// Get list of processed book files
@@ -162,36 +226,44 @@ impl<B: Backend> BookDataLoader<B> {
 
 impl<B: Backend> DataLoader<B> for BookDataLoader<B> {
     fn next_batch(&mut self) -> Result<Option<BatchData<B>>> {
-        // Check if we have enough data for a full batch
-        let required_len = self.batch_size * (self.seq_len + 1);
+        if let Some(ref batches) = self.token_batches {
+            let Some(sequences) = batches.get(self.current_pos) else {
+                return Ok(None);
+            };
+            self.current_pos += 1;
+            return Ok(Some(packed_batch(&self.tokens, sequences, &self.device)));
+        }
         
-        if self.current_pos + required_len > self.tokens.len() {
+        let Some(offsets) = batch_offsets(
+            self.current_pos,
+            self.tokens.len(),
+            self.batch_size,
+            self.seq_len,
+            self.stream_layout,
+        ) else {
             return Ok(None);
-        }
+        };
         
         // Extract batch data
         let mut batch_tokens = Vec::new();
         let mut batch_targets = Vec::new();
         
-        for _ in 0..self.batch_size {
-            let start = self.current_pos;
-            let end = start + self.seq_len + 1;
-            
-            if end > self.tokens.len() {
-                return Ok(None);
-            }
-            
-            let sequence = &self.tokens[start..end];
+        for &start in &offsets {
+            let sequence = &self.tokens[start..start + self.seq_len + 1];
             
             // Input tokens
             batch_tokens.extend_from_slice(&sequence[..self.seq_len]);
             
             // Target tokens (shifted by 1)
             batch_targets.extend_from_slice(&sequence[1..]);
-            
-            self.current_pos += self.seq_len;
         }
         
+        self.current_pos += if self.stream_layout {
+            self.seq_len
+        } else {
+            self.batch_size * self.seq_len
+        };
+        
         // Convert to tensors
         let tokens_tensor = Tensor::<B, 1, Int>::from_ints(
             batch_tokens.as_slice(),
@@ -206,6 +278,8 @@ impl<B: Backend> DataLoader<B> for BookDataLoader<B> {
         Ok(Some(BatchData {
             tokens: tokens_tensor,
             targets: targets_tensor,
+            offsets,
+            lengths: Vec::new(),
         }))
     }
     
@@ -213,14 +287,78 @@ impl<B: Backend> DataLoader<B> for BookDataLoader<B> {
         self.current_pos = 0;
     }
     
+    fn position(&self) -> Option<usize> {
+        Some(self.current_pos)
+    }
+    
+    fn seek(&mut self, position: usize) {
+        self.current_pos = position;
+    }
+    
+    fn start_epoch(&mut self, epoch: usize, shuffle_seed: Option<u64>) {
+        reorder_documents(&mut self.tokens, &mut self.documents, &mut self.document_order, epoch, shuffle_seed);
+        self.token_batches = self.max_batch_tokens
+            .map(|budget| pack_batches(self.tokens.len(), &self.documents, self.seq_len, budget));
+        self.current_pos = 0;
+    }
+    
     fn num_batches(&self) -> Option<usize> {
-        let required_len = self.batch_size * (self.seq_len + 1);
-        if self.tokens.len() < required_len {
-            return Some(0);
+        if let Some(ref batches) = self.token_batches {
+            return Some(batches.len());
+        }
+        Some(layout_num_batches(self.tokens.len(), self.batch_size, self.seq_len, self.stream_layout))
+    }
+    
+    fn documents(&self) -> Option<&DocumentSpans> {
+        if self.documents.is_empty() {
+            None
+        } else {
+            Some(&self.documents)
         }
-        
-        let available_sequences = (self.tokens.len() - self.seq_len) / self.seq_len;
-        Some(available_sequences / self.batch_size)
     }
 }
 
+/// One line of `corpus.jsonl`
+#[derive(Deserialize)]
+struct CorpusEntry {
+    #[serde(default)]
+    id: Option<CorpusId>,
+    filename: String,
+    #[serde(default)]
+    text: String,
+    tokens: Vec<i64>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CorpusId {
+    /// `document_id` of the text
+    Stable(String),
+    /// Enumeration index (older corpora)
+    Positional(serde::de::IgnoredAny),
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use burn_ndarray::NdArray;
+    use tempfile::NamedTempFile;
+    use std::io::Write;
+    
+    type TestBackend = NdArray<f32>;
+    
+    #[test]
+    fn test_corpus_documents_keep_stable_ids() {
+        let mut corpus = NamedTempFile::new().unwrap();
+        // Positional ID from an older corpus, then a stable one
+        writeln!(corpus, r#"{{"id": 0, "filename": "a", "text": "ab", "tokens": [1, 2, 3]}}"#).unwrap();
+        writeln!(corpus, r#"{{"id": "0123456789abcdef", "filename": "b", "text": "cd", "tokens": [4, 5, 6]}}"#).unwrap();
+        
+        let loader = BookDataLoader::<TestBackend>::from_corpus(corpus.path(), 1, 2, Default::default()).unwrap();
+        let documents = loader.documents().unwrap();
+        assert_eq!(documents.id_at(0), Some(document_id("ab").as_str()));
+        assert_eq!(documents.id_at(3), Some("0123456789abcdef"));
+        assert_eq!(documents.document_at(3), Some("b"));
+    }
+}
@@ -0,0 +1,269 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use tracing::warn;
+use walkdir::WalkDir;
+
+use crate::utils::{
+    document_to_text, for_each_archive_entry, is_archive, is_supported_document, parse_document, parse_document_bytes,
+    CleaningPipeline, Document,
+};
+
+/// N-gram length used by `hope-train contamination` (13-grams, as in the
+/// GPT-3 contamination analysis)
+pub const DEFAULT_NGRAM: usize = 13;
+
+/// Words of `text` for n-gram matching: lowercased alphanumeric runs, with
+/// every CJK character as a word of its own (those scripts have no spaces)
+pub fn ngram_words(text: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    for c in text.chars() {
+        if is_cjk(c) {
+            if !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+            words.push(c.to_string());
+        } else if c.is_alphanumeric() {
+            word.extend(c.to_lowercase());
+        } else if !word.is_empty() {
+            words.push(std::mem::take(&mut word));
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x3040..=0x30FF      // Hiragana, Katakana
+        | 0x3400..=0x4DBF    // CJK Extension A
+        | 0x4E00..=0x9FFF    // CJK Unified Ideographs
+        | 0xAC00..=0xD7AF    // Hangul syllables
+        | 0xF900..=0xFAFF    // CJK Compatibility Ideographs
+        | 0x20000..=0x2FA1F) // CJK Extensions B-F, supplement
+}
+
+/// Hash of every `n`-word window of `text` (none if it has fewer words)
+pub fn ngram_hashes(text: &str, n: usize) -> Vec<u64> {
+    let words: Vec<u64> = ngram_words(text).iter().map(hash_of).collect();
+    if n == 0 {
+        return Vec::new();
+    }
+    words.windows(n).map(|window| hash_of(&window)).collect()
+}
+
+fn hash_of<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Call `f` with the name and text of every document at `path`: a file, a
+/// directory, or a `.zip`/`.tar.zst` archive of any supported format
+pub fn for_each_text(path: &Path, mut f: impl FnMut(&Path, &str)) -> Result<()> {
+    if !path.exists() {
+        anyhow::bail!("No such file or directory: {:?}", path);
+    }
+    let pipeline = CleaningPipeline::default();
+    let to_text = |name: &Path, document: Result<Document>| match document {
+        Ok(document) => Some(document_to_text(&document, false, &pipeline).0),
+        Err(e) => {
+            warn!("Failed to read {:?}: {}", name, e);
+            None
+        }
+    };
+
+    for entry in WalkDir::new(path)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+    {
+        let file = entry.path();
+        if is_archive(file) {
+            for_each_archive_entry(file, |name, bytes| {
+                let name = file.join(name);
+                if is_plain_text(&name) {
+                    f(&name, &String::from_utf8_lossy(&bytes));
+                } else if is_supported_document(&name) {
+                    if let Some(text) = to_text(&name, parse_document_bytes(&name, &bytes)) {
+                        f(&name, &text);
+                    }
+                }
+                Ok(())
+            })?;
+        } else if is_plain_text(file) {
+            let text = fs::read_to_string(file)
+                .with_context(|| format!("Failed to read text file: {:?}", file))?;
+            f(file, &text);
+        } else if is_supported_document(file) {
+            if let Some(text) = to_text(file, parse_document(file)) {
+                f(file, &text);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Plain text is matched as-is, without the document cleaning pipeline
+fn is_plain_text(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("txt"))
+}
+
+/// Overlap of one evaluation document with the training corpus
+#[derive(Debug, Clone, Serialize)]
+pub struct DocumentContamination {
+    pub name: String,
+    pub ngrams: usize,
+    pub contaminated_ngrams: usize,
+    /// Percentage of the document's n-grams found in the corpus
+    pub contamination: f64,
+}
+
+/// Overlap of one evaluation set with the training corpus
+#[derive(Debug, Clone, Serialize)]
+pub struct ContaminationReport {
+    pub name: String,
+    pub documents: usize,
+    /// Documents sharing at least one n-gram with the corpus
+    pub contaminated_documents: usize,
+    pub ngrams: usize,
+    pub contaminated_ngrams: usize,
+    /// Percentage of the set's n-grams found in the corpus
+    pub contamination: f64,
+    /// Contaminated documents, most contaminated first
+    pub worst_documents: Vec<DocumentContamination>,
+}
+
+struct EvalSet {
+    name: String,
+    documents: Vec<(String, Vec<u64>)>,
+}
+
+/// N-gram overlap between evaluation sets and a training corpus.
+///
+/// The n-grams of the (small) evaluation sets are kept in memory and the
+/// corpus is streamed past them, so corpus size does not bound memory.
+pub struct ContaminationChecker {
+    n: usize,
+    sets: Vec<EvalSet>,
+    /// N-grams of every evaluation document
+    wanted: HashSet<u64>,
+    /// Wanted n-grams seen in the corpus so far
+    found: HashSet<u64>,
+}
+
+impl ContaminationChecker {
+    pub fn new(n: usize) -> Self {
+        assert!(n > 0, "n-gram length must be > 0");
+        Self { n, sets: Vec::new(), wanted: HashSet::new(), found: HashSet::new() }
+    }
+
+    /// Add a document to the evaluation set `set` (created on first use)
+    pub fn add_eval_document(&mut self, set: &str, name: &str, text: &str) {
+        let hashes = ngram_hashes(text, self.n);
+        self.wanted.extend(&hashes);
+        let index = match self.sets.iter().position(|s| s.name == set) {
+            Some(index) => index,
+            None => {
+                self.sets.push(EvalSet { name: set.to_string(), documents: Vec::new() });
+                self.sets.len() - 1
+            }
+        };
+        self.sets[index].documents.push((name.to_string(), hashes));
+    }
+
+    /// Record the evaluation n-grams that occur in a corpus document
+    pub fn scan_corpus_text(&mut self, text: &str) {
+        for hash in ngram_hashes(text, self.n) {
+            if self.wanted.contains(&hash) {
+                self.found.insert(hash);
+            }
+        }
+    }
+
+    /// Per-set overlap with everything scanned so far, listing at most
+    /// `max_documents` contaminated documents per set
+    pub fn reports(&self, max_documents: usize) -> Vec<ContaminationReport> {
+        self.sets
+            .iter()
+            .map(|set| {
+                let mut documents: Vec<DocumentContamination> = set.documents
+                    .iter()
+                    .map(|(name, hashes)| {
+                        let contaminated = hashes.iter().filter(|hash| self.found.contains(hash)).count();
+                        DocumentContamination {
+                            name: name.clone(),
+                            ngrams: hashes.len(),
+                            contaminated_ngrams: contaminated,
+                            contamination: percentage(contaminated, hashes.len()),
+                        }
+                    })
+                    .collect();
+                let ngrams = documents.iter().map(|d| d.ngrams).sum();
+                let contaminated_ngrams = documents.iter().map(|d| d.contaminated_ngrams).sum();
+
+                documents.retain(|d| d.contaminated_ngrams > 0);
+                documents.sort_by(|a, b| b.contamination.total_cmp(&a.contamination));
+                let contaminated_documents = documents.len();
+                documents.truncate(max_documents);
+
+                ContaminationReport {
+                    name: set.name.clone(),
+                    documents: set.documents.len(),
+                    contaminated_documents,
+                    ngrams,
+                    contaminated_ngrams,
+                    contamination: percentage(contaminated_ngrams, ngrams),
+                    worst_documents: documents,
+                }
+            })
+            .collect()
+    }
+}
+
+fn percentage(part: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        100.0 * part as f64 / total as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ngram_words_split_cjk_characters() {
+        assert_eq!(ngram_words("Hello, World! 从前有山"), vec!["hello", "world", "从", "前", "有", "山"]);
+    }
+
+    #[test]
+    fn test_reports_overlap_per_eval_set() {
+        let mut checker = ContaminationChecker::new(3);
+        checker.add_eval_document("clean", "a.txt", "one two three four");
+        checker.add_eval_document("leaked", "b.txt", "the quick brown fox jumps");
+        checker.add_eval_document("leaked", "c.txt", "nothing in common here at all");
+        // Punctuation and case do not hide the copy
+        checker.scan_corpus_text("...THE QUICK, brown fox! and more");
+
+        let reports = checker.reports(10);
+        assert_eq!(reports[0].name, "clean");
+        assert_eq!(reports[0].contaminated_ngrams, 0);
+        assert_eq!(reports[0].ngrams, 2);
+
+        let leaked = &reports[1];
+        assert_eq!((leaked.documents, leaked.contaminated_documents), (2, 1));
+        // "the quick brown", "quick brown fox" of b's 3 + c's 4 n-grams
+        assert_eq!((leaked.contaminated_ngrams, leaked.ngrams), (2, 7));
+        assert_eq!(leaked.worst_documents[0].name, "b.txt");
+        assert!((leaked.worst_documents[0].contamination - 200.0 / 3.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,200 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+
+/// Metadata file written by `preprocess` next to the corpus
+pub const CORPUS_METADATA_FILE: &str = "metadata.json";
+
+/// Inputs that determine a corpus version
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CorpusFingerprint {
+    /// Content hash of every document, keyed by document name
+    pub document_hashes: Vec<(String, String)>,
+    /// Hash of the cleaning/preprocessing settings
+    pub cleaning_hash: String,
+    /// Hash of the serialized tokenizer
+    pub tokenizer_hash: String,
+}
+
+impl CorpusFingerprint {
+    /// Short, order-independent version identifier for the corpus
+    pub fn version(&self) -> String {
+        let mut documents = self.document_hashes.clone();
+        documents.sort();
+
+        let mut hasher = Sha256::new();
+        for (name, hash) in &documents {
+            hasher.update(name.as_bytes());
+            hasher.update([0]);
+            hasher.update(hash.as_bytes());
+            hasher.update([0]);
+        }
+        hasher.update(self.cleaning_hash.as_bytes());
+        hasher.update([0]);
+        hasher.update(self.tokenizer_hash.as_bytes());
+
+        hex_digest(hasher)[..16].to_string()
+    }
+}
+
+/// SHA-256 of a byte slice, hex encoded
+pub fn content_hash(bytes: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(bytes);
+    hex_digest(hasher)
+}
+
+/// Stable document ID: the first 16 hex digits of the SHA-256 of the
+/// document's text (its `content_hash`), so adding, removing or reordering
+/// other documents never changes it and identical documents share one
+pub fn document_id(text: &str) -> String {
+    content_hash(text.as_bytes())[..16].to_string()
+}
+
+/// SHA-256 of a file's contents, hex encoded
+pub fn file_hash(path: &Path) -> Result<String> {
+    let bytes = fs::read(path)
+        .with_context(|| format!("Failed to read file for hashing: {:?}", path))?;
+    Ok(content_hash(&bytes))
+}
+
+/// Read the corpus version recorded in a preprocessed data directory.
+/// Returns `None` if the directory has no metadata or predates versioning.
+pub fn read_corpus_version(data_dir: &Path) -> Result<Option<String>> {
+    let metadata_path = data_dir.join(CORPUS_METADATA_FILE);
+    if !metadata_path.exists() {
+        return Ok(None);
+    }
+
+    let metadata_json = fs::read_to_string(&metadata_path)
+        .with_context(|| format!("Failed to read corpus metadata: {:?}", metadata_path))?;
+    let metadata: serde_json::Value = serde_json::from_str(&metadata_json)
+        .with_context(|| format!("Failed to parse corpus metadata: {:?}", metadata_path))?;
+
+    Ok(metadata
+        .get("corpus_version")
+        .and_then(|v| v.as_str())
+        .map(str::to_string))
+}
+
+/// Replace the positional `id`s of a `preprocess` output directory
+/// written before stable IDs (in `corpus.jsonl` and the `documents` of
+/// `metadata.json`) with `document_id`s. Returns the number of corpus
+/// entries changed; running it again changes nothing.
+pub fn migrate_document_ids(data_dir: &Path) -> Result<usize> {
+    let corpus_path = data_dir.join("corpus.jsonl");
+    let corpus = fs::read_to_string(&corpus_path)
+        .with_context(|| format!("Failed to read corpus file: {:?}", corpus_path))?;
+
+    let mut migrated = 0;
+    let mut lines = Vec::new();
+    for (line_no, line) in corpus.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut entry: serde_json::Value = serde_json::from_str(line)
+            .with_context(|| format!("Invalid corpus entry at line {}", line_no + 1))?;
+        if !entry.get("id").is_some_and(|id| id.is_string()) {
+            let text = entry.get("text").and_then(|text| text.as_str()).unwrap_or_default();
+            entry["id"] = document_id(text).into();
+            migrated += 1;
+        }
+        lines.push(serde_json::to_string(&entry)?);
+    }
+    if migrated > 0 {
+        fs::write(&corpus_path, lines.join("\n") + "\n")
+            .with_context(|| format!("Failed to write corpus file: {:?}", corpus_path))?;
+    }
+
+    let metadata_path = data_dir.join(CORPUS_METADATA_FILE);
+    if metadata_path.exists() {
+        let metadata_json = fs::read_to_string(&metadata_path)
+            .with_context(|| format!("Failed to read corpus metadata: {:?}", metadata_path))?;
+        let mut metadata: serde_json::Value = serde_json::from_str(&metadata_json)
+            .with_context(|| format!("Failed to parse corpus metadata: {:?}", metadata_path))?;
+        let mut changed = false;
+        if let Some(documents) = metadata.get_mut("documents").and_then(|d| d.as_array_mut()) {
+            for document in documents.iter_mut().filter(|d| d.get("id").is_none()) {
+                // `document_id` is a prefix of the recorded content hash
+                if let Some(hash) = document.get("content_hash").and_then(|h| h.as_str()).filter(|h| h.len() >= 16) {
+                    document["id"] = hash[..16].into();
+                    changed = true;
+                }
+            }
+        }
+        if changed {
+            fs::write(&metadata_path, serde_json::to_string_pretty(&metadata)?)
+                .with_context(|| format!("Failed to write corpus metadata: {:?}", metadata_path))?;
+        }
+    }
+
+    Ok(migrated)
+}
+
+fn hex_digest(hasher: Sha256) -> String {
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fingerprint(documents: &[(&str, &str)], cleaning: &str) -> CorpusFingerprint {
+        CorpusFingerprint {
+            document_hashes: documents
+                .iter()
+                .map(|(name, text)| (name.to_string(), content_hash(text.as_bytes())))
+                .collect(),
+            cleaning_hash: content_hash(cleaning.as_bytes()),
+            tokenizer_hash: content_hash(b"vocab"),
+        }
+    }
+
+    #[test]
+    fn test_version_is_order_independent() {
+        let a = fingerprint(&[("a", "one"), ("b", "two")], "default");
+        let b = fingerprint(&[("b", "two"), ("a", "one")], "default");
+        assert_eq!(a.version(), b.version());
+        assert_eq!(a.version().len(), 16);
+    }
+
+    #[test]
+    fn test_migrate_replaces_positional_ids() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let corpus = [
+            r#"{"id": 0, "filename": "a", "text": "one", "tokens": [1]}"#,
+            r#"{"id": 1, "filename": "b", "text": "two", "tokens": [2]}"#,
+        ];
+        fs::write(dir.path().join("corpus.jsonl"), corpus.join("\n")).unwrap();
+        let metadata = serde_json::json!({
+            "documents": [{"filename": "a", "content_hash": content_hash(b"one")}],
+        });
+        fs::write(dir.path().join(CORPUS_METADATA_FILE), metadata.to_string()).unwrap();
+
+        assert_eq!(migrate_document_ids(dir.path()).unwrap(), 2);
+        assert_eq!(migrate_document_ids(dir.path()).unwrap(), 0);
+
+        let corpus = fs::read_to_string(dir.path().join("corpus.jsonl")).unwrap();
+        let ids: Vec<String> = corpus
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["id"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(ids, [document_id("one"), document_id("two")]);
+        let metadata: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(dir.path().join(CORPUS_METADATA_FILE)).unwrap()).unwrap();
+        assert_eq!(metadata["documents"][0]["id"], document_id("one"));
+    }
+
+    #[test]
+    fn test_version_changes_with_inputs() {
+        let base = fingerprint(&[("a", "one")], "default");
+        assert_ne!(base.version(), fingerprint(&[("a", "one!")], "default").version());
+        assert_ne!(base.version(), fingerprint(&[("a", "one")], "no-dehyphenation").version());
+    }
+}
@@ -0,0 +1,248 @@
+use anyhow::Result;
+use burn::tensor::{Int, Tensor, backend::Backend};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+use walkdir::WalkDir;
+
+use super::corpus::document_id;
+use super::loader::{DataLoader, DocumentSpans, batch_offsets};
+use super::tokenizer::{CharTokenizer, Tokenizer};
+use crate::training::BatchData;
+
+/// Text data loader over a growing directory: new `.txt` files are tokenized
+/// and appended to the token stream as they appear. When the stream runs
+/// out, `next_batch` waits for more data instead of ending the epoch, and
+/// ends the stream once nothing arrives within `timeout` (if set).
+///
+/// The directory is polled (listed every `poll_interval`) rather than
+/// watched through file system events, which also works on network and
+/// container mounts; a new file costs at most two intervals of latency.
+/// Tokens are dropped once consumed, so memory stays bounded by the data
+/// not trained on yet.
+pub struct FollowDataLoader<B: Backend> {
+    dir: PathBuf,
+    tokenizer: CharTokenizer,
+    /// Tokens not consumed yet; `tokens[0]` is at stream position `start`
+    tokens: Vec<i64>,
+    start: usize,
+    batch_size: usize,
+    seq_len: usize,
+    current_pos: usize,
+    poll_interval: Duration,
+    timeout: Option<Duration>,
+    last_poll: Instant,
+    ingested: HashSet<PathBuf>,
+    /// New files and their size when first seen; ingested once the size is stable
+    pending: HashMap<PathBuf, u64>,
+    device: B::Device,
+    documents: DocumentSpans,
+}
+
+impl<B: Backend> FollowDataLoader<B> {
+    /// Ingest the files already in `dir` and start following it
+    pub fn new(
+        dir: &Path,
+        tokenizer: CharTokenizer,
+        batch_size: usize,
+        seq_len: usize,
+        poll_interval: Duration,
+        timeout: Option<Duration>,
+        device: B::Device,
+    ) -> Result<Self> {
+        let mut loader = Self {
+            dir: dir.to_path_buf(),
+            tokenizer,
+            tokens: Vec::new(),
+            start: 0,
+            batch_size,
+            seq_len,
+            current_pos: 0,
+            poll_interval,
+            timeout,
+            last_poll: Instant::now(),
+            ingested: HashSet::new(),
+            pending: HashMap::new(),
+            device,
+            documents: DocumentSpans::default(),
+        };
+
+        // Files present at startup are taken as complete
+        for path in loader.text_files() {
+            loader.ingest(&path);
+        }
+        info!("Loaded {} text files from {:?} ({} total tokens)",
+            loader.ingested.len(), dir, loader.tokens.len());
+
+        Ok(loader)
+    }
+
+    /// Look for new files; returns how many were appended to the stream.
+    /// A file is appended once its size is unchanged between two polls, so
+    /// files still being written are not read half-way.
+    pub fn poll(&mut self) -> usize {
+        self.last_poll = Instant::now();
+        let mut added = 0;
+
+        for path in self.text_files() {
+            let Ok(size) = fs::metadata(&path).map(|m| m.len()) else {
+                continue;
+            };
+            match self.pending.insert(path.clone(), size) {
+                Some(previous) if previous == size => {
+                    self.pending.remove(&path);
+                    if self.ingest(&path) {
+                        added += 1;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if added > 0 {
+            info!("Appended {} new file(s) from {:?} ({} total tokens)", added, self.dir, self.start + self.tokens.len());
+        }
+        added
+    }
+
+    /// Text files in the directory that are not part of the stream yet, in path order
+    fn text_files(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = WalkDir::new(&self.dir)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .map(|e| e.into_path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "txt"))
+            .filter(|path| !self.ingested.contains(path))
+            .collect();
+        files.sort();
+        files
+    }
+
+    fn ingest(&mut self, path: &Path) -> bool {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => {
+                warn!("Skipping unreadable file {:?}: {}", path, e);
+                self.ingested.insert(path.to_path_buf());
+                return false;
+            }
+        };
+
+        self.documents.push(self.start + self.tokens.len(), document_id(&text), path.to_string_lossy());
+        self.tokens.extend(self.tokenizer.encode(&text));
+        self.ingested.insert(path.to_path_buf());
+        true
+    }
+
+    /// Offsets of the next batch into `tokens`
+    fn batch_at_current_pos(&self) -> Option<Vec<usize>> {
+        batch_offsets(self.current_pos - self.start, self.tokens.len(), self.batch_size, self.seq_len, false)
+    }
+}
+
+impl<B: Backend> DataLoader<B> for FollowDataLoader<B> {
+    fn next_batch(&mut self) -> Result<Option<BatchData<B>>> {
+        if self.last_poll.elapsed() >= self.poll_interval {
+            self.poll();
+        }
+
+        let offsets = match self.batch_at_current_pos() {
+            Some(offsets) => offsets,
+            None => {
+                info!("Waiting for new files in {:?}...", self.dir);
+                let waiting = Instant::now();
+                loop {
+                    if let Some(timeout) = self.timeout.filter(|&timeout| waiting.elapsed() >= timeout) {
+                        info!("No new files in {:?} for {:?}, ending the stream", self.dir, timeout);
+                        return Ok(None);
+                    }
+                    std::thread::sleep(self.poll_interval);
+                    self.poll();
+                    if let Some(offsets) = self.batch_at_current_pos() {
+                        break offsets;
+                    }
+                }
+            }
+        };
+
+        let mut batch_tokens = Vec::with_capacity(self.batch_size * self.seq_len);
+        let mut batch_targets = Vec::with_capacity(self.batch_size * self.seq_len);
+        for &start in &offsets {
+            let sequence = &self.tokens[start..start + self.seq_len + 1];
+            batch_tokens.extend_from_slice(&sequence[..self.seq_len]);
+            batch_targets.extend_from_slice(&sequence[1..]);
+        }
+        let offsets = offsets.into_iter().map(|offset| self.start + offset).collect();
+        self.current_pos += self.batch_size * self.seq_len;
+
+        // The next batch starts at `current_pos`; nothing before it is read again
+        self.tokens.drain(..self.current_pos - self.start);
+        self.start = self.current_pos;
+
+        let tokens = Tensor::<B, 1, Int>::from_ints(batch_tokens.as_slice(), &self.device)
+            .reshape([self.batch_size, self.seq_len]);
+        let targets = Tensor::<B, 1, Int>::from_ints(batch_targets.as_slice(), &self.device)
+            .reshape([self.batch_size, self.seq_len]);
+
+        Ok(Some(BatchData { tokens, targets, offsets, lengths: Vec::new() }))
+    }
+
+    /// Consumed tokens are dropped, so the stream continues where it is
+    fn reset(&mut self) {}
+
+    /// Unknown: the stream keeps growing
+    fn num_batches(&self) -> Option<usize> {
+        None
+    }
+
+    fn documents(&self) -> Option<&DocumentSpans> {
+        (!self.documents.is_empty()).then_some(&self.documents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use burn_ndarray::NdArray;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_new_files_join_the_stream_once_stable() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("a.txt"), "abcdefghij").unwrap();
+        let tokenizer = CharTokenizer::from_text("abcdefghijklmnopqrstuvwxyz");
+
+        let mut loader = FollowDataLoader::<NdArray<f32>>::new(
+            temp_dir.path(),
+            tokenizer,
+            2,
+            4,
+            Duration::from_millis(1),
+            Some(Duration::from_millis(20)),
+            Default::default(),
+        )
+        .unwrap();
+        assert_eq!(loader.tokens.len(), 10);
+        assert!(loader.next_batch().unwrap().is_some());
+        // The 8 consumed tokens are dropped
+        assert_eq!(loader.tokens.len(), 2);
+
+        // Seen on the first poll, appended on the second
+        fs::write(temp_dir.path().join("b.txt"), "klmnopqrst").unwrap();
+        assert_eq!(loader.poll(), 0);
+        assert_eq!(loader.poll(), 1);
+        assert_eq!(loader.tokens.len(), 12);
+
+        // Offsets stay positions in the whole stream
+        let batch = loader.next_batch().unwrap().unwrap();
+        assert_eq!(batch.offsets, vec![8, 12]);
+        let documents = loader.documents().unwrap();
+        assert!(documents.document_at(12).unwrap().ends_with("b.txt"));
+
+        // Without new files the stream ends after the timeout
+        assert!(loader.next_batch().unwrap().is_none());
+    }
+}
@@ -1,9 +1,26 @@
 use anyhow::Result;
-use burn::tensor::backend::Backend;
+use burn::tensor::{Int, Tensor, backend::Backend};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use super::book_loader::BookDataLoader;
+use super::follow_loader::FollowDataLoader;
+use super::mixture_loader::{MixtureDataLoader, mixture_probabilities};
+use super::prefetch_loader::PrefetchDataLoader;
+use super::text_loader::TextDataLoader;
+use super::tokenizer::{CharTokenizer, Tokenizer};
+use crate::config::{DataType, TrainConfig};
 use crate::training::BatchData;
+use crate::utils::is_archive;
 
-/// Trait for data loading
-pub trait DataLoader<B: Backend> {
+/// Trait for data loading (loaders are `Send` so they can be prefetched on a
+/// background thread)
+pub trait DataLoader<B: Backend>: Send {
     /// Get the next batch of data
     fn next_batch(&mut self) -> Result<Option<BatchData<B>>>;
     
@@ -12,6 +29,248 @@ pub trait DataLoader<B: Backend> {
     
     /// Get the total number of batches (if known)
     fn num_batches(&self) -> Option<usize>;
+    
+    /// Document boundaries in the token stream (if known)
+    fn documents(&self) -> Option<&DocumentSpans> {
+        None
+    }
+    
+    /// Position of the next batch, for `seek` after resuming (`None` if the
+    /// loader cannot resume)
+    fn position(&self) -> Option<usize> {
+        None
+    }
+    
+    /// Continue from a position returned by `position`
+    fn seek(&mut self, _position: usize) {}
+    
+    /// Rewind for pass `epoch` (0-based) over the data. Loaders with
+    /// document boundaries visit the documents in an order drawn from
+    /// `shuffle_seed` and `epoch` in every pass after the first; the same
+    /// arguments give the same order, so `seek` positions stay valid.
+    fn start_epoch(&mut self, _epoch: usize, _shuffle_seed: Option<u64>) {
+        self.reset();
+    }
+}
+
+/// Where training is in the data, saved in checkpoints so a resumed run
+/// continues with the next unseen batch instead of the start of the corpus
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoaderState {
+    /// `DataLoader::position` of the next batch
+    pub position: usize,
+    /// Completed passes over the data
+    pub epoch: usize,
+}
+
+/// Start offsets of the documents concatenated into a loader's token stream
+#[derive(Debug, Clone, Default)]
+pub struct DocumentSpans {
+    starts: Vec<usize>,
+    /// Stable IDs (see `document_id`)
+    ids: Vec<String>,
+    names: Vec<String>,
+}
+
+impl DocumentSpans {
+    /// Register a document starting at `start`; documents must be pushed in order
+    pub fn push(&mut self, start: usize, id: impl Into<String>, name: impl Into<String>) {
+        self.starts.push(start);
+        self.ids.push(id.into());
+        self.names.push(name.into());
+    }
+    
+    /// Name of the document containing the token at `offset`
+    pub fn document_at(&self, offset: usize) -> Option<&str> {
+        self.index_at(offset).map(|i| self.names[i].as_str())
+    }
+    
+    /// Stable ID of the document containing the token at `offset`
+    pub fn id_at(&self, offset: usize) -> Option<&str> {
+        self.index_at(offset).map(|i| self.ids[i].as_str())
+    }
+    
+    fn index_at(&self, offset: usize) -> Option<usize> {
+        self.starts.partition_point(|&start| start <= offset).checked_sub(1)
+    }
+    
+    /// `tokens` with its documents rearranged: document k of the result is
+    /// document `permutation[k]` of `tokens` (tokens before the first
+    /// document stay in front)
+    pub(crate) fn reorder(&self, tokens: &[i64], permutation: &[usize]) -> (Vec<i64>, DocumentSpans) {
+        let prefix = self.starts.first().copied().unwrap_or(tokens.len());
+        let end = |i: usize| self.starts.get(i + 1).copied().unwrap_or(tokens.len());
+        
+        let mut reordered = tokens[..prefix].to_vec();
+        let mut documents = DocumentSpans::default();
+        for &i in permutation {
+            documents.push(reordered.len(), self.ids[i].clone(), self.names[i].clone());
+            reordered.extend_from_slice(&tokens[self.starts[i]..end(i)]);
+        }
+        (reordered, documents)
+    }
+    
+    /// Append the documents of `other` with their offsets moved up by `base`
+    pub(crate) fn extend_shifted(&mut self, other: &DocumentSpans, base: usize) {
+        for i in 0..other.len() {
+            self.push(base + other.starts[i], other.ids[i].clone(), other.names[i].clone());
+        }
+    }
+    
+    pub fn len(&self) -> usize {
+        self.starts.len()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.starts.is_empty()
+    }
+}
+
+/// Order of the loaded documents in pass `epoch`: as loaded in the first
+/// pass (or without a seed), shuffled by `seed` and `epoch` afterwards
+pub(crate) fn epoch_document_order(num_documents: usize, epoch: usize, seed: Option<u64>) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..num_documents).collect();
+    if let Some(seed) = seed.filter(|_| epoch > 0) {
+        order.shuffle(&mut StdRng::seed_from_u64(seed.wrapping_add(epoch as u64)));
+    }
+    order
+}
+
+/// Rearrange a loader's token stream into the document order of pass
+/// `epoch`. `order` tracks the current arrangement (stream document k is
+/// loaded document `order[k]`; empty = as loaded).
+pub(crate) fn reorder_documents(
+    tokens: &mut Vec<i64>,
+    documents: &mut DocumentSpans,
+    order: &mut Vec<usize>,
+    epoch: usize,
+    seed: Option<u64>,
+) {
+    if order.is_empty() {
+        *order = (0..documents.len()).collect();
+    }
+    let target = epoch_document_order(documents.len(), epoch, seed);
+    if target == *order {
+        return;
+    }
+    
+    // Where each loaded document currently is in the stream
+    let mut position = vec![0; order.len()];
+    for (k, &document) in order.iter().enumerate() {
+        position[document] = k;
+    }
+    let permutation: Vec<usize> = target.iter().map(|&document| position[document]).collect();
+    (*tokens, *documents) = documents.reorder(tokens, &permutation);
+    *order = target;
+}
+
+/// Start offsets of the sequences of the batch at `position`.
+///
+/// The sequential layout reads consecutive windows (`position` is the first
+/// token of the batch). The stream layout splits the tokens into
+/// `batch_size` contiguous streams and `position` is the offset within each
+/// stream, so row i of consecutive batches continues the same text, which
+/// stateful training relies on. Returns `None` when the data is exhausted.
+pub(crate) fn batch_offsets(
+    position: usize,
+    num_tokens: usize,
+    batch_size: usize,
+    seq_len: usize,
+    stream_layout: bool,
+) -> Option<Vec<usize>> {
+    if stream_layout {
+        let stream_len = num_tokens.saturating_sub(1) / batch_size.max(1);
+        if position + seq_len > stream_len {
+            return None;
+        }
+        Some((0..batch_size).map(|row| row * stream_len + position).collect())
+    } else {
+        // +1 for the target of the last token
+        if position + batch_size * (seq_len + 1) > num_tokens {
+            return None;
+        }
+        Some((0..batch_size).map(|row| position + row * seq_len).collect())
+    }
+}
+
+/// Number of batches `batch_offsets` yields before returning `None`
+pub(crate) fn layout_num_batches(
+    num_tokens: usize,
+    batch_size: usize,
+    seq_len: usize,
+    stream_layout: bool,
+) -> usize {
+    if stream_layout {
+        (num_tokens.saturating_sub(1) / batch_size.max(1)) / seq_len
+    } else if num_tokens < batch_size * (seq_len + 1) {
+        0
+    } else {
+        (num_tokens - seq_len) / seq_len / batch_size
+    }
+}
+
+/// Batches of `(start, len)` sequences packed to a token budget: every
+/// document is cut into sequences of at most `seq_len` input tokens (targets
+/// stay inside the document), and consecutive sequences share a batch while
+/// `rows * longest sequence <= max_batch_tokens`, so short documents batch
+/// larger while the padded batch size stays bounded.
+pub(crate) fn pack_batches(
+    num_tokens: usize,
+    documents: &DocumentSpans,
+    seq_len: usize,
+    max_batch_tokens: usize,
+) -> Vec<Vec<(usize, usize)>> {
+    let mut bounds: Vec<usize> = if documents.is_empty() { vec![0] } else { documents.starts.clone() };
+    bounds.push(num_tokens);
+    
+    let mut batches = Vec::new();
+    let mut batch: Vec<(usize, usize)> = Vec::new();
+    let mut longest = 0;
+    for doc in bounds.windows(2) {
+        let (doc_start, doc_end) = (doc[0], doc[1]);
+        let mut start = doc_start;
+        // +1 for the target of the last token
+        while start + 1 < doc_end {
+            let len = seq_len.min(doc_end - start - 1);
+            if !batch.is_empty() && (batch.len() + 1) * longest.max(len) > max_batch_tokens {
+                batches.push(std::mem::take(&mut batch));
+                longest = 0;
+            }
+            batch.push((start, len));
+            longest = longest.max(len);
+            start += len;
+        }
+    }
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
+    batches
+}
+
+/// Padded batch of `(start, len)` sequences from `tokens`, with the real
+/// lengths recorded in `BatchData::lengths`
+pub(crate) fn packed_batch<B: Backend>(
+    tokens: &[i64],
+    sequences: &[(usize, usize)],
+    device: &B::Device,
+) -> BatchData<B> {
+    let width = sequences.iter().map(|&(_, len)| len).max().unwrap_or(0);
+    let mut batch_tokens = vec![0; sequences.len() * width];
+    let mut batch_targets = vec![0; sequences.len() * width];
+    
+    for (row, &(start, len)) in sequences.iter().enumerate() {
+        let offset = row * width;
+        batch_tokens[offset..offset + len].copy_from_slice(&tokens[start..start + len]);
+        batch_targets[offset..offset + len].copy_from_slice(&tokens[start + 1..start + len + 1]);
+    }
+    
+    let shape = [sequences.len(), width];
+    BatchData {
+        tokens: Tensor::<B, 1, Int>::from_ints(batch_tokens.as_slice(), device).reshape(shape),
+        targets: Tensor::<B, 1, Int>::from_ints(batch_targets.as_slice(), device).reshape(shape),
+        offsets: sequences.iter().map(|&(start, _)| start).collect(),
+        lengths: sequences.iter().map(|&(_, len)| len).collect(),
+    }
 }
 
 /// Random data loader for testing (existing functionality)
@@ -69,5 +328,330 @@ impl<B: Backend> DataLoader<B> for RandomDataLoader<B> {
     fn num_batches(&self) -> Option<usize> {
         Some(self.num_batches)
     }
+    
+    fn position(&self) -> Option<usize> {
+        Some(self.current_batch)
+    }
+    
+    fn seek(&mut self, position: usize) {
+        self.current_batch = position;
+    }
+}
+
+
+/// Build the data loader selected by `config.data`
+pub fn create_data_loader<B: Backend>(
+    config: &TrainConfig,
+    device: &B::Device,
+) -> Result<Box<dyn DataLoader<B>>> {
+    let batch_size = config.training.batch_size;
+    let seq_len = config.model.seq_len;
+    let vocab_size = config.model.vocab_size;
+    // Stateful training needs row i of consecutive batches to continue the same text
+    let stream_layout = config.training.stateful.enabled;
+    
+    // Random tokens only on request, as a smoke test of the model and loop
+    if config.training.use_random_data {
+        if !matches!(config.data.data_type, DataType::Random) || !config.data.mixture.is_empty() {
+            warn!("training.use_random_data is set; the configured data is ignored");
+        }
+        info!("Using random data (smoke test)");
+        let loader = RandomDataLoader::new(
+            batch_size,
+            seq_len,
+            vocab_size,
+            config.training.num_steps,
+            device.clone(),
+        );
+        return Ok(with_prefetch(Box::new(loader), config));
+    }
+    
+    if !config.data.mixture.is_empty() {
+        return create_mixture_loader(config, device);
+    }
+    
+    if let DataType::Random = config.data.data_type {
+        anyhow::bail!(
+            "No training data: set data.data_type and data.data_path (or data.mixture), \
+             or training.use_random_data for a smoke test on random tokens"
+        );
+    }
+    
+    let data_path = config.data.data_path.as_deref()
+        .ok_or_else(|| anyhow::anyhow!("data.data_path is required for {:?} data", config.data.data_type))?;
+    
+    if config.data.max_batch_tokens.is_some() {
+        if stream_layout {
+            anyhow::bail!("data.max_batch_tokens cannot be combined with training.stateful (rows no longer continue each other)");
+        }
+        if config.data.follow {
+            anyhow::bail!("data.max_batch_tokens cannot be combined with data.follow");
+        }
+    }
+    if config.data.max_batch_tokens.is_some_and(|budget| budget < seq_len) {
+        anyhow::bail!("data.max_batch_tokens must be at least model.seq_len ({})", seq_len);
+    }
+    let max_batch_tokens = config.data.max_batch_tokens;
+    
+    if config.data.follow {
+        if !matches!(config.data.data_type, DataType::Text) || !data_path.is_dir() {
+            anyhow::bail!("data.follow requires text data in a directory");
+        }
+        if stream_layout {
+            anyhow::bail!("data.follow cannot be combined with training.stateful (the stream keeps growing)");
+        }
+        let tokenizer = load_tokenizer(config)?;
+        if tokenizer.vocab_size() > vocab_size {
+            anyhow::bail!(
+                "Tokenizer has {} tokens but model.vocab_size is {}; increase vocab_size to at least {}",
+                tokenizer.vocab_size(), vocab_size, tokenizer.vocab_size()
+            );
+        }
+        let poll_interval = Duration::from_secs(config.data.follow_poll_secs);
+        let timeout = config.data.follow_timeout_secs.map(Duration::from_secs);
+        info!("Following {:?} for new text files (polling every {:?})", data_path, poll_interval);
+        let loader = FollowDataLoader::new(data_path, tokenizer, batch_size, seq_len, poll_interval, timeout, device.clone())?;
+        return Ok(with_prefetch(Box::new(loader), config));
+    }
+    
+    let (loader, max_token_id): (Box<dyn DataLoader<B>>, Option<i64>) = match config.data.data_type {
+        DataType::Text => {
+            let tokenizer = load_tokenizer(config)?;
+            let loader = if data_path.is_dir() || is_archive(data_path) {
+                TextDataLoader::from_directory(data_path, &tokenizer, batch_size, seq_len, device.clone())?
+            } else {
+                TextDataLoader::from_file(data_path, &tokenizer, batch_size, seq_len, device.clone())?
+            }
+            .with_stream_layout(stream_layout)
+            .with_token_budget(max_batch_tokens);
+            let max_token_id = loader.max_token_id();
+            (Box::new(loader), max_token_id)
+        }
+        DataType::Books => {
+            // Prefer the preprocessed corpus when the path points at `preprocess` output
+            let corpus_path = data_path.join("corpus.jsonl");
+            let loader = if corpus_path.exists() {
+                BookDataLoader::from_corpus(&corpus_path, batch_size, seq_len, device.clone())?
+            } else {
+                let tokenizer = load_tokenizer(config)?;
+                BookDataLoader::from_directory(data_path, &tokenizer, batch_size, seq_len, device.clone(), true)?
+            }
+            .with_stream_layout(stream_layout)
+            .with_token_budget(max_batch_tokens);
+            let max_token_id = loader.max_token_id();
+            (Box::new(loader), max_token_id)
+        }
+        DataType::Random => unreachable!(),
+    };
+    
+    if let Some(max_id) = max_token_id {
+        if max_id >= vocab_size as i64 {
+            anyhow::bail!(
+                "Data contains token id {} but model.vocab_size is {}; increase vocab_size to at least {}",
+                max_id, vocab_size, max_id + 1
+            );
+        }
+    }
+    
+    if loader.num_batches() == Some(0) {
+        anyhow::bail!("Not enough data in {:?} for a single batch", data_path);
+    }
+    
+    Ok(with_prefetch(loader, config))
+}
+
+/// Build a `MixtureDataLoader` over the sources of `data.mixture`, each
+/// loaded like a single corpus with one-row batches
+fn create_mixture_loader<B: Backend>(
+    config: &TrainConfig,
+    device: &B::Device,
+) -> Result<Box<dyn DataLoader<B>>> {
+    if config.training.stateful.enabled {
+        anyhow::bail!("data.mixture cannot be combined with training.stateful (rows come from different sources)");
+    }
+    if config.data.max_batch_tokens.is_some() {
+        anyhow::bail!("data.mixture cannot be combined with data.max_batch_tokens");
+    }
+    if config.data.follow {
+        anyhow::bail!("data.mixture cannot be combined with data.follow");
+    }
+    let temperature = config.data.mixture_temperature;
+    if temperature.is_nan() || temperature <= 0.0 {
+        anyhow::bail!("data.mixture_temperature must be > 0");
+    }
+    if let Some(source) = config.data.mixture.iter().find(|source| !(source.weight.is_finite() && source.weight > 0.0)) {
+        anyhow::bail!("Mixture source {:?} needs a finite weight > 0", source.data_path);
+    }
+    
+    let weights: Vec<f32> = config.data.mixture.iter().map(|source| source.weight).collect();
+    let probabilities = mixture_probabilities(&weights, config.data.mixture_temperature);
+    let mut sources = Vec::with_capacity(weights.len());
+    for (source, probability) in config.data.mixture.iter().zip(probabilities) {
+        info!("Mixture source {:?} ({:?}): {:.1}% of rows", source.data_path, source.data_type, probability * 100.0);
+        let mut source_config = config.clone();
+        source_config.training.batch_size = 1;
+        source_config.data.data_type = source.data_type.clone();
+        source_config.data.data_path = Some(source.data_path.clone());
+        if let Some(ref tokenizer_path) = source.tokenizer_path {
+            source_config.data.tokenizer_path = Some(tokenizer_path.clone());
+        }
+        source_config.data.prefetch = 0;
+        source_config.data.mixture.clear();
+        sources.push(create_data_loader(&source_config, device)?);
+    }
+    
+    let loader = MixtureDataLoader::new(
+        sources,
+        &weights,
+        config.data.mixture_temperature,
+        config.training.batch_size,
+        config.training.seed.unwrap_or(0),
+    );
+    if loader.num_batches() == Some(0) {
+        anyhow::bail!("Not enough data in data.mixture for a single batch");
+    }
+    Ok(with_prefetch(Box::new(loader), config))
 }
 
+/// Wrap `loader` in a prefetcher when `data.prefetch` is enabled
+fn with_prefetch<B: Backend>(loader: Box<dyn DataLoader<B>>, config: &TrainConfig) -> Box<dyn DataLoader<B>> {
+    match config.data.prefetch {
+        0 => loader,
+        depth => Box::new(PrefetchDataLoader::new(loader, depth)),
+    }
+}
+
+/// Build the validation loader for `training.val_data`, using the same data
+/// type and tokenizer as the training data
+pub fn create_validation_loader<B: Backend>(
+    config: &TrainConfig,
+    device: &B::Device,
+) -> Result<Option<Box<dyn DataLoader<B>>>> {
+    let Some(ref val_data) = config.training.val_data else {
+        return Ok(None);
+    };
+    
+    if let DataType::Random = config.data.data_type {
+        anyhow::bail!("training.val_data requires text or books training data");
+    }
+    
+    info!("Loading validation data from: {:?}", val_data);
+    let mut val_config = config.clone();
+    val_config.training.use_random_data = false;
+    val_config.data.data_path = Some(val_data.clone());
+    val_config.data.mixture.clear();
+    create_data_loader(&val_config, device).map(Some)
+}
+
+fn load_tokenizer(config: &TrainConfig) -> Result<CharTokenizer> {
+    let tokenizer_path: &Path = config.data.tokenizer_path.as_deref()
+        .ok_or_else(|| anyhow::anyhow!("data.tokenizer_path is required for {:?} data", config.data.data_type))?;
+    info!("Loading tokenizer from: {:?}", tokenizer_path);
+    CharTokenizer::load(tokenizer_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HopeConfig;
+    
+    #[test]
+    fn test_random_data_only_on_request() {
+        let device = Default::default();
+        let mut config = TrainConfig::for_test(HopeConfig::tiny(), r#"{"batch_size": 2, "num_steps": 3}"#);
+        assert!(create_data_loader::<burn_ndarray::NdArray>(&config, &device).is_err());
+        
+        config.training.use_random_data = true;
+        let mut loader = create_data_loader::<burn_ndarray::NdArray>(&config, &device).unwrap();
+        assert_eq!(loader.next_batch().unwrap().unwrap().tokens.dims(), [2, 4]);
+    }
+    
+    #[test]
+    fn test_document_spans_lookup() {
+        let mut spans = DocumentSpans::default();
+        spans.push(0, "1", "a");
+        spans.push(10, "2", "b");
+        
+        assert_eq!(spans.document_at(0), Some("a"));
+        assert_eq!(spans.id_at(12), Some("2"));
+        assert_eq!(spans.document_at(9), Some("a"));
+        assert_eq!(spans.document_at(10), Some("b"));
+        assert_eq!(spans.document_at(1000), Some("b"));
+        assert_eq!(DocumentSpans::default().document_at(0), None);
+    }
+    
+    #[test]
+    fn test_stream_layout_continues_rows() {
+        // 2 streams of 10 tokens (the 21st token is only used as a target)
+        let first = batch_offsets(0, 21, 2, 4, true).unwrap();
+        let second = batch_offsets(4, 21, 2, 4, true).unwrap();
+        assert_eq!(first, vec![0, 10]);
+        assert_eq!(second, vec![4, 14]);
+        assert_eq!(batch_offsets(8, 21, 2, 4, true), None);
+        assert_eq!(layout_num_batches(21, 2, 4, true), 2);
+        
+        assert_eq!(batch_offsets(0, 21, 2, 4, false).unwrap(), vec![0, 4]);
+    }
+    
+    #[test]
+    fn test_token_budget_packs_short_documents_together() {
+        // One 10-token document, then three 3-token documents
+        let mut spans = DocumentSpans::default();
+        spans.push(0, "0", "long");
+        spans.push(10, "1", "a");
+        spans.push(13, "2", "b");
+        spans.push(16, "3", "c");
+        
+        let batches = pack_batches(19, &spans, 4, 8);
+        assert_eq!(batches, vec![
+            vec![(0, 4), (4, 4)],
+            vec![(8, 1), (10, 2), (13, 2), (16, 2)],
+        ]);
+        
+        let batch = packed_batch::<burn_ndarray::NdArray>(&(0..19).collect::<Vec<_>>(), &batches[1], &Default::default());
+        assert_eq!(batch.tokens.dims(), [4, 2]);
+        assert_eq!(batch.lengths, vec![1, 2, 2, 2]);
+        assert_eq!(batch.num_tokens(), 7);
+        assert_eq!(batch.tokens.to_data().to_vec::<i64>().unwrap(), vec![8, 0, 10, 11, 13, 14, 16, 17]);
+        assert_eq!(batch.targets.to_data().to_vec::<i64>().unwrap(), vec![9, 0, 11, 12, 14, 15, 17, 18]);
+    }
+    
+    #[test]
+    fn test_epochs_reshuffle_documents_reproducibly() {
+        // Documents of 1, 2, 3 and 4 tokens, each token its document's index
+        let mut spans = DocumentSpans::default();
+        let mut tokens = Vec::new();
+        for (doc, len) in [1, 2, 3, 4].into_iter().enumerate() {
+            spans.push(tokens.len(), doc.to_string(), format!("doc{}", doc));
+            tokens.extend(std::iter::repeat_n(doc as i64, len));
+        }
+        let original = tokens.clone();
+        let mut order = Vec::new();
+        
+        // The first pass keeps the loaded order
+        reorder_documents(&mut tokens, &mut spans, &mut order, 0, Some(7));
+        assert_eq!(tokens, original);
+        
+        let mut reshuffled = None;
+        for epoch in 1..20 {
+            reorder_documents(&mut tokens, &mut spans, &mut order, epoch, Some(7));
+            assert_eq!(order, epoch_document_order(4, epoch, Some(7)));
+            // Documents move whole, and spans follow them
+            for (k, &doc) in order.iter().enumerate() {
+                assert_eq!(spans.id_at(spans.starts[k]), Some(doc.to_string().as_str()));
+                assert_eq!(tokens[spans.starts[k]], doc as i64);
+            }
+            let mut sorted = tokens.clone();
+            sorted.sort();
+            assert_eq!(sorted, original);
+            if order != [0, 1, 2, 3] {
+                reshuffled.get_or_insert(epoch);
+            }
+        }
+        assert!(reshuffled.is_some());
+        
+        // Without a seed every pass keeps the loaded order
+        reorder_documents(&mut tokens, &mut spans, &mut order, 3, None);
+        assert_eq!(tokens, original);
+    }
+}
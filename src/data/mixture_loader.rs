@@ -0,0 +1,217 @@
+use anyhow::Result;
+use burn::tensor::{Tensor, backend::Backend};
+use rand::SeedableRng;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
+
+use super::loader::{DataLoader, DocumentSpans};
+use crate::training::BatchData;
+
+/// Blends several corpora into every batch: each row is drawn from a source
+/// picked at random with probability `weight^(1/temperature)` (normalized).
+///
+/// Sources yield one row per batch and start over on their own when they
+/// run out. An epoch of the mixture is as many rows as all sources hold
+/// together. Batch offsets are shifted into a separate range per source so
+/// document attribution and quarantine can tell the sources apart.
+pub struct MixtureDataLoader<B: Backend> {
+    sources: Vec<Box<dyn DataLoader<B>>>,
+    sampler: WeightedIndex<f64>,
+    batch_size: usize,
+    seed: u64,
+    rng: StdRng,
+    epoch: usize,
+    /// Batches drawn in the current epoch
+    drawn: usize,
+    /// Offset range of each source in the mixture's offsets
+    stride: usize,
+    documents: DocumentSpans,
+}
+
+impl<B: Backend> MixtureDataLoader<B> {
+    /// Mix `sources` (each yielding single-row batches) with sampling
+    /// `weights`; rows are drawn from an RNG seeded with `seed` and the epoch
+    pub fn new(
+        sources: Vec<Box<dyn DataLoader<B>>>,
+        weights: &[f32],
+        temperature: f32,
+        batch_size: usize,
+        seed: u64,
+    ) -> Self {
+        assert_eq!(sources.len(), weights.len(), "one weight per mixture source");
+        let sampler = WeightedIndex::new(mixture_probabilities(weights, temperature))
+            .expect("mixture weights must be finite and > 0");
+        let stride = usize::MAX / sources.len().max(1);
+
+        let mut loader = Self {
+            sources,
+            sampler,
+            batch_size,
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            epoch: 0,
+            drawn: 0,
+            stride,
+            documents: DocumentSpans::default(),
+        };
+        loader.documents = loader.combined_documents();
+        loader
+    }
+
+    /// Next row of `source`, starting it over when it runs out
+    fn next_row(&mut self, source: usize) -> Result<BatchData<B>> {
+        let loader = &mut self.sources[source];
+        if let Some(row) = loader.next_batch()? {
+            return Ok(row);
+        }
+        loader.reset();
+        loader.next_batch()?
+            .ok_or_else(|| anyhow::anyhow!("Mixture source {} produced no batches", source))
+    }
+
+    /// Document spans of every source, shifted into the source's offset range
+    fn combined_documents(&self) -> DocumentSpans {
+        let mut documents = DocumentSpans::default();
+        for (i, source) in self.sources.iter().enumerate() {
+            if let Some(spans) = source.documents() {
+                documents.extend_shifted(spans, i * self.stride);
+            }
+        }
+        documents
+    }
+}
+
+/// Sampling probability of each source: `weight^(1/temperature)`, normalized
+pub fn mixture_probabilities(weights: &[f32], temperature: f32) -> Vec<f64> {
+    let scaled: Vec<f64> = weights
+        .iter()
+        .map(|&weight| f64::from(weight).powf(1.0 / f64::from(temperature)))
+        .collect();
+    let total: f64 = scaled.iter().sum();
+    scaled.iter().map(|weight| weight / total).collect()
+}
+
+impl<B: Backend> DataLoader<B> for MixtureDataLoader<B> {
+    fn next_batch(&mut self) -> Result<Option<BatchData<B>>> {
+        if self.num_batches().is_some_and(|batches| self.drawn >= batches) {
+            return Ok(None);
+        }
+
+        let mut tokens = Vec::with_capacity(self.batch_size);
+        let mut targets = Vec::with_capacity(self.batch_size);
+        let mut offsets = Vec::with_capacity(self.batch_size);
+        for _ in 0..self.batch_size {
+            let source = self.sampler.sample(&mut self.rng);
+            let row = self.next_row(source)?;
+            offsets.push(source * self.stride + row.offsets.first().copied().unwrap_or(0));
+            tokens.push(row.tokens);
+            targets.push(row.targets);
+        }
+        self.drawn += 1;
+
+        Ok(Some(BatchData {
+            tokens: Tensor::cat(tokens, 0),
+            targets: Tensor::cat(targets, 0),
+            offsets,
+            lengths: Vec::new(),
+        }))
+    }
+
+    fn reset(&mut self) {
+        for source in &mut self.sources {
+            source.reset();
+        }
+        self.rng = StdRng::seed_from_u64(self.seed.wrapping_add(self.epoch as u64));
+        self.drawn = 0;
+    }
+
+    /// Rows of all sources together, in batches
+    fn num_batches(&self) -> Option<usize> {
+        self.sources
+            .iter()
+            .map(|source| source.num_batches())
+            .sum::<Option<usize>>()
+            .map(|rows| rows / self.batch_size)
+    }
+
+    fn documents(&self) -> Option<&DocumentSpans> {
+        if self.documents.is_empty() {
+            None
+        } else {
+            Some(&self.documents)
+        }
+    }
+
+    fn position(&self) -> Option<usize> {
+        Some(self.drawn)
+    }
+
+    /// Replays the epoch's draws up to `position`
+    fn seek(&mut self, position: usize) {
+        self.reset();
+        while self.drawn < position {
+            if !matches!(self.next_batch(), Ok(Some(_))) {
+                break;
+            }
+        }
+    }
+
+    fn start_epoch(&mut self, epoch: usize, shuffle_seed: Option<u64>) {
+        for source in &mut self.sources {
+            source.start_epoch(epoch, shuffle_seed);
+        }
+        self.epoch = epoch;
+        self.documents = self.combined_documents();
+        self.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::TextDataLoader;
+    use burn_ndarray::NdArray;
+
+    type B = NdArray<f32>;
+
+    fn source(token: i64, len: usize) -> Box<dyn DataLoader<B>> {
+        Box::new(TextDataLoader::<B>::from_tokens(vec![token; len], 1, 4, Default::default()))
+    }
+
+    #[test]
+    fn test_temperature_flattens_weights() {
+        let probabilities = mixture_probabilities(&[3.0, 1.0], 1.0);
+        assert!((probabilities[0] - 0.75).abs() < 1e-9);
+        let flattened = mixture_probabilities(&[3.0, 1.0], 2.0);
+        assert!(flattened[0] < 0.75 && flattened[0] > 0.5);
+    }
+
+    #[test]
+    fn test_batches_blend_sources_by_weight() {
+        // 50 rows of token 1 and 10 rows of token 2, sampled 3:1
+        let mut mixture = MixtureDataLoader::new(vec![source(1, 205), source(2, 45)], &[3.0, 1.0], 1.0, 4, 7);
+        assert_eq!(mixture.num_batches(), Some(15));
+
+        let mut rows = [0usize; 3];
+        let mut first_epoch = Vec::new();
+        while let Some(batch) = mixture.next_batch().unwrap() {
+            assert_eq!(batch.tokens.dims(), [4, 4]);
+            let tokens = batch.tokens.into_data().to_vec::<i64>().unwrap();
+            for row in tokens.chunks(4) {
+                rows[row[0] as usize] += 1;
+            }
+            // Offsets of the second source are in their own range
+            for (row, &offset) in tokens.chunks(4).zip(&batch.offsets) {
+                assert_eq!(offset >= mixture.stride, row[0] == 2);
+            }
+            first_epoch.push(tokens);
+        }
+        assert_eq!(rows[1] + rows[2], 60);
+        assert!(rows[1] > 2 * rows[2], "{:?}", rows);
+
+        // Seeking replays the same draws
+        mixture.seek(5);
+        let batch = mixture.next_batch().unwrap().unwrap();
+        assert_eq!(batch.tokens.into_data().to_vec::<i64>().unwrap(), first_epoch[5]);
+    }
+}
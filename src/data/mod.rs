@@ -1,10 +1,21 @@
 mod book_loader;
+pub mod contamination;
+mod corpus;
+mod follow_loader;
 mod loader;
+mod mixture_loader;
+mod prefetch_loader;
+pub mod preprocess;
+pub mod sample;
 mod text_loader;
 mod tokenizer;
 
 pub use book_loader::BookDataLoader;
-pub use loader::{DataLoader, RandomDataLoader};
+pub use corpus::{CorpusFingerprint, CORPUS_METADATA_FILE, content_hash, document_id, file_hash, migrate_document_ids, read_corpus_version};
+pub use follow_loader::FollowDataLoader;
+pub use mixture_loader::{MixtureDataLoader, mixture_probabilities};
+pub use prefetch_loader::PrefetchDataLoader;
+pub use loader::{DataLoader, DocumentSpans, LoaderState, RandomDataLoader, create_data_loader, create_validation_loader};
 pub use text_loader::TextDataLoader;
-pub use tokenizer::{Tokenizer, CharTokenizer};
+pub use tokenizer::{Tokenizer, CharTokenizer, vocab_mapping};
 
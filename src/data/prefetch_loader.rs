@@ -0,0 +1,204 @@
+use anyhow::Result;
+use burn::tensor::backend::Backend;
+use std::sync::mpsc::{self, Receiver};
+use std::thread::{self, JoinHandle};
+
+use super::loader::{DataLoader, DocumentSpans};
+use crate::training::BatchData;
+
+/// A batch produced by the worker, with the loader's position after it and
+/// its document spans when they changed since the previous batch (follow
+/// mode keeps adding documents)
+struct Prefetched<B: Backend> {
+    batch: Result<Option<BatchData<B>>>,
+    position: Option<usize>,
+    documents: Option<DocumentSpans>,
+}
+
+/// Builds the next batches of a wrapped loader on a worker thread, so
+/// tokenization and tensor construction overlap with the training step.
+///
+/// Batches arrive in the same order as from the wrapped loader; at most
+/// `depth` of them are prepared ahead of the consumer.
+pub struct PrefetchDataLoader<B: Backend> {
+    depth: usize,
+    receiver: Option<Receiver<Prefetched<B>>>,
+    worker: Option<JoinHandle<Box<dyn DataLoader<B>>>>,
+    /// The wrapped loader once its worker has finished
+    idle: Option<Box<dyn DataLoader<B>>>,
+    num_batches: Option<usize>,
+    /// Wrapped loader's position after the last batch handed out
+    position: Option<usize>,
+    documents: Option<DocumentSpans>,
+}
+
+impl<B: Backend> PrefetchDataLoader<B> {
+    pub fn new(loader: Box<dyn DataLoader<B>>, depth: usize) -> Self {
+        assert!(depth > 0, "prefetch depth must be > 0");
+        let mut prefetcher = Self {
+            depth,
+            receiver: None,
+            worker: None,
+            idle: None,
+            num_batches: loader.num_batches(),
+            position: loader.position(),
+            documents: loader.documents().cloned(),
+        };
+        prefetcher.spawn(loader);
+        prefetcher
+    }
+
+    fn spawn(&mut self, mut loader: Box<dyn DataLoader<B>>) {
+        let (sender, receiver) = mpsc::sync_channel(self.depth);
+        let mut documents_len = loader.documents().map_or(0, DocumentSpans::len);
+
+        let worker = thread::Builder::new()
+            .name("data-prefetch".to_string())
+            .spawn(move || {
+                loop {
+                    let batch = loader.next_batch();
+                    let more = matches!(batch, Ok(Some(_)));
+
+                    let documents = match loader.documents() {
+                        Some(spans) if spans.len() != documents_len => {
+                            documents_len = spans.len();
+                            Some(spans.clone())
+                        }
+                        _ => None,
+                    };
+
+                    // Fails once the consumer resets or drops the loader
+                    let position = loader.position();
+                    if sender.send(Prefetched { batch, position, documents }).is_err() || !more {
+                        break;
+                    }
+                }
+                loader
+            })
+            .expect("Failed to spawn data prefetch thread");
+
+        self.receiver = Some(receiver);
+        self.worker = Some(worker);
+    }
+
+    /// Stop the worker and take back the wrapped loader
+    fn take_loader(&mut self) -> thread::Result<Box<dyn DataLoader<B>>> {
+        // Dropping the receiver unblocks a worker waiting to send
+        self.receiver = None;
+        match self.idle.take() {
+            Some(loader) => Ok(loader),
+            None => self.worker.take().expect("data prefetch worker panicked").join(),
+        }
+    }
+}
+
+impl<B: Backend> DataLoader<B> for PrefetchDataLoader<B> {
+    fn next_batch(&mut self) -> Result<Option<BatchData<B>>> {
+        let Some(ref receiver) = self.receiver else {
+            return Ok(None);
+        };
+
+        match receiver.recv() {
+            Ok(prefetched) => {
+                self.position = prefetched.position;
+                if let Some(documents) = prefetched.documents {
+                    self.documents = Some(documents);
+                }
+                prefetched.batch
+            }
+            // The worker stops after the last batch, or panicked
+            Err(_) => match self.take_loader() {
+                Ok(loader) => {
+                    self.idle = Some(loader);
+                    Ok(None)
+                }
+                Err(_) => anyhow::bail!("Data prefetch worker panicked"),
+            },
+        }
+    }
+
+    fn reset(&mut self) {
+        let mut loader = self.take_loader().unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+        loader.reset();
+        self.position = loader.position();
+        self.spawn(loader);
+    }
+
+    fn num_batches(&self) -> Option<usize> {
+        self.num_batches
+    }
+
+    fn documents(&self) -> Option<&DocumentSpans> {
+        self.documents.as_ref()
+    }
+    
+    fn position(&self) -> Option<usize> {
+        self.position
+    }
+    
+    fn seek(&mut self, position: usize) {
+        let mut loader = self.take_loader().unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+        loader.seek(position);
+        self.position = loader.position();
+        self.spawn(loader);
+    }
+    
+    fn start_epoch(&mut self, epoch: usize, shuffle_seed: Option<u64>) {
+        let mut loader = self.take_loader().unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+        loader.start_epoch(epoch, shuffle_seed);
+        self.position = loader.position();
+        // Same documents, new offsets
+        self.documents = loader.documents().cloned();
+        self.spawn(loader);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{RandomDataLoader, TextDataLoader};
+    use burn::backend::NdArray;
+
+    #[test]
+    fn test_prefetch_preserves_order_and_resets() {
+        let device = Default::default();
+        let mut direct = RandomDataLoader::<NdArray>::new(2, 4, 8, 3, device);
+        let mut prefetched = PrefetchDataLoader::new(
+            Box::new(RandomDataLoader::<NdArray>::new(2, 4, 8, 3, device)),
+            2,
+        );
+        assert_eq!(prefetched.num_batches(), Some(3));
+
+        for _ in 0..2 {
+            for _ in 0..3 {
+                let expected = direct.next_batch().unwrap().unwrap();
+                let batch = prefetched.next_batch().unwrap().unwrap();
+                assert_eq!(batch.tokens.to_data(), expected.tokens.to_data());
+            }
+            assert!(prefetched.next_batch().unwrap().is_none());
+            assert!(prefetched.next_batch().unwrap().is_none());
+            direct.reset();
+            prefetched.reset();
+        }
+    }
+    
+    #[test]
+    fn test_position_tracks_consumed_batches() {
+        let device = Default::default();
+        let tokens: Vec<i64> = (0..100).collect();
+        let loader = |tokens: Vec<i64>| Box::new(TextDataLoader::<NdArray>::from_tokens(tokens, 2, 4, device));
+        let mut prefetched = PrefetchDataLoader::new(loader(tokens.clone()), 3);
+        
+        prefetched.next_batch().unwrap();
+        prefetched.next_batch().unwrap();
+        // The worker is ahead, but the position is that of the third batch
+        let position = prefetched.position().unwrap();
+        assert_eq!(position, 16);
+        let expected = prefetched.next_batch().unwrap().unwrap();
+        
+        let mut resumed = PrefetchDataLoader::new(loader(tokens), 3);
+        resumed.seek(position);
+        let batch = resumed.next_batch().unwrap().unwrap();
+        assert_eq!(batch.tokens.to_data(), expected.tokens.to_data());
+    }
+}
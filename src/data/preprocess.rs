@@ -0,0 +1,571 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use tracing::{info, warn};
+use walkdir::WalkDir;
+
+use crate::config::PreprocessConfig;
+use crate::utils::{auto_ocr_if_needed, extract_text_from_pdf};
+use crate::utils::{document_to_text, is_supported_document, parse_document, Document};
+use crate::utils::document::{file_title, output_stem};
+use crate::utils::pdf_parser::{extract_text_from_pdf_bytes, split_sections};
+use crate::utils::{PiiReport, PiiScrubber};
+use crate::utils::{BoilerplateReport, remove_boilerplate, top_words};
+use crate::utils::{CleaningPipeline, CleaningReport};
+use crate::utils::{DownloadConfig, fetch_articles};
+use crate::utils::{for_each_archive_entry, is_archive, parse_document_bytes};
+use super::corpus::{CorpusFingerprint, CORPUS_METADATA_FILE, content_hash, document_id, file_hash};
+use super::tokenizer::{CharTokenizer, Tokenizer};
+
+/// Output of processing a single book (also the delta-rebuild cache format)
+#[derive(Debug, Serialize, Deserialize)]
+struct ProcessedBook {
+    text: String,
+    pii_redactions: Option<PiiReport>,
+    boilerplate: Option<BoilerplateReport>,
+    cleaning: CleaningReport,
+}
+
+/// One document of the corpus, as recorded in `metadata.json`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DocumentMetadata {
+    /// Stable ID derived from the text (`content_hash` prefix); metadata
+    /// written before IDs existed gets it from `content_hash`
+    #[serde(default)]
+    pub id: String,
+    /// Output name (`<filename>.txt`), sanitized and unique per source
+    pub filename: String,
+    /// Source document path relative to the input directory
+    pub source_path: String,
+    pub file_type: String,
+    pub character_count: usize,
+    pub token_count: usize,
+    pub processed_at: u64,
+    pub source_hash: String,
+    pub content_hash: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pii_redactions: Option<PiiReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub boilerplate: Option<BoilerplateReport>,
+    pub cleaning: CleaningReport,
+}
+
+/// `metadata.json` of a preprocessed corpus
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CorpusMetadata {
+    pub total_documents: usize,
+    pub total_characters: usize,
+    pub total_tokens: usize,
+    pub vocab_size: usize,
+    pub corpus_version: String,
+    pub cleaning_hash: String,
+    pub tokenizer_hash: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pii_redactions: Option<PiiReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub boilerplate: Option<BoilerplateReport>,
+    pub top_words: Vec<(String, usize)>,
+    pub cleaning: CleaningReport,
+    pub documents: Vec<DocumentMetadata>,
+}
+
+/// Hash of the source documents under `input` (relative paths, sizes and
+/// modification times) and of `config`'s settings: a corpus preprocessed
+/// when the fingerprint was the same is up to date
+pub fn source_fingerprint(input: &Path, config: &PreprocessConfig) -> Result<String> {
+    let mut sources = Vec::new();
+    for entry in WalkDir::new(input)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+    {
+        let path = entry.path();
+        if !(is_supported_document(path) || is_archive(path)) {
+            continue;
+        }
+        let metadata = entry.metadata()
+            .with_context(|| format!("Failed to read file metadata: {:?}", path))?;
+        let modified = metadata.modified()
+            .ok()
+            .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+            .map_or(0, |time| time.as_nanos());
+        let relative = path.strip_prefix(input).unwrap_or(path);
+        sources.push(format!("{}\t{}\t{}", relative.display(), metadata.len(), modified));
+    }
+    sources.sort();
+    
+    let settings = serde_json::to_string(config).with_context(|| "Failed to serialize preprocess settings")?;
+    Ok(content_hash(format!("{}\n{}", settings, sources.join("\n")).as_bytes()))
+}
+
+/// Parse, clean and tokenize the documents under `input` into `output`:
+/// one `<name>.txt` per document, `corpus.jsonl`, `vocab.json` and
+/// `metadata.json`. Unchanged documents are reused from `<output>/.cache`.
+/// Without `config.build_vocab`, `existing_tokenizer` (or the output's
+/// `vocab.json`) is reused when present.
+pub fn preprocess_corpus(
+    input: &Path,
+    output: &Path,
+    config: &PreprocessConfig,
+    existing_tokenizer: Option<&Path>,
+) -> Result<CorpusMetadata> {
+    config.validate();
+    
+    // Create output directory
+    fs::create_dir_all(output)
+        .with_context(|| format!("Failed to create output directory: {:?}", output))?;
+    
+    // Download web articles into the input directory so they go through the same pipeline
+    if let Some(ref url_file) = config.fetch {
+        let urls: Vec<String> = fs::read_to_string(url_file)
+            .with_context(|| format!("Failed to read URL list: {:?}", url_file))?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(String::from)
+            .collect();
+        info!("Fetching {} URL(s) from {:?}", urls.len(), url_file);
+        
+        // Raw responses and their manifest stay out of the input so they aren't parsed twice
+        let download_config = DownloadConfig {
+            concurrency: config.fetch_concurrency,
+            max_retries: config.fetch_retries,
+            requests_per_sec: config.fetch_rate,
+            ..Default::default()
+        };
+        let download_dir = output.join(".downloads");
+        let report = fetch_articles(&urls, &download_dir, &input.join("web"), &download_config)?;
+        info!("Fetched {} new article(s), {} unchanged, {} failed",
+            report.saved.len(), report.skipped, report.failed.len());
+    }
+    
+    // Find all book files
+    let mut book_files = Vec::new();
+    
+    for entry in WalkDir::new(input)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+    {
+        let path = entry.path();
+        
+        if is_supported_document(path) || is_archive(path) {
+            book_files.push(path.to_path_buf());
+        }
+    }
+    
+    info!("Found {} book files and archives", book_files.len());
+    
+    if book_files.is_empty() {
+        anyhow::bail!("No book files found in {:?}", input);
+    }
+    
+    // Process each book
+    let mut all_text = String::new();
+    let mut documents = Vec::new();
+    let scrubber = config.scrub_pii.then(PiiScrubber::new);
+    let mut pii_total = PiiReport::default();
+    let pipeline = CleaningPipeline::new(config.cleaning_stages.clone());
+    let mut cleaning_total = CleaningReport::default();
+    
+    info!("Cleaning stages: {}", pipeline.stages()
+        .iter()
+        .map(|s| s.name())
+        .collect::<Vec<_>>()
+        .join(" -> "));
+    
+    // Settings that affect per-document output; part of the corpus version
+    let cleaning_hash = content_hash(format!(
+        "stages={:?};structure={};ocr={};pii={};boilerplate={:?}",
+        pipeline.stages(),
+        config.preserve_structure,
+        config.enable_ocr,
+        config.scrub_pii,
+        config.boilerplate_threshold,
+    ).as_bytes());
+    
+    // Per-document cache for delta rebuilds
+    let cache_dir = output.join(".cache");
+    fs::create_dir_all(&cache_dir)
+        .with_context(|| format!("Failed to create cache directory: {:?}", cache_dir))?;
+    let mut reused = 0;
+    
+    // `contents` is set for documents read from inside an archive
+    let mut process_source = |book_path: &Path, contents: Option<Vec<u8>>| -> Result<()> {
+        let source_hash = match contents {
+            Some(ref bytes) => content_hash(bytes),
+            None => match file_hash(book_path) {
+                Ok(hash) => hash,
+                Err(e) => {
+                    warn!("Failed to process {:?}: {}", book_path, e);
+                    return Ok(());
+                }
+            },
+        };
+        let cache_key = content_hash(format!("{}:{}", source_hash, cleaning_hash).as_bytes());
+        let cache_path = cache_dir.join(format!("{}.json", &cache_key[..32]));
+        
+        let cached = if config.full_rebuild {
+            None
+        } else {
+            fs::read_to_string(&cache_path)
+                .ok()
+                .and_then(|json| serde_json::from_str::<ProcessedBook>(&json).ok())
+        };
+        
+        let book = match cached {
+            Some(book) => {
+                info!("Unchanged since last run, reusing cached output");
+                reused += 1;
+                book
+            }
+            None => match process_book(book_path, contents.as_deref(), config.preserve_structure, config.enable_ocr, config.boilerplate_threshold, &pipeline) {
+                Ok(mut book) => {
+                    // Optional PII scrubbing pass
+                    if let Some(ref scrubber) = scrubber {
+                        let (scrubbed, report) = scrubber.scrub(&book.text);
+                        info!("Redacted {} PII item(s) from {:?}", report.total(), book_path);
+                        book.text = scrubbed;
+                        book.pii_redactions = Some(report);
+                    }
+                    
+                    if let Err(e) = fs::write(&cache_path, serde_json::to_string(&book)?) {
+                        warn!("Failed to cache {:?}: {}", book_path, e);
+                    }
+                    book
+                }
+                Err(e) => {
+                    warn!("Failed to process {:?}: {}", book_path, e);
+                    return Ok(());
+                }
+            },
+        };
+        
+        cleaning_total.merge(&book.cleaning);
+        if let Some(ref report) = book.pii_redactions {
+            pii_total.merge(report);
+        }
+        
+        let char_count = book.text.len();
+        
+        // Save individual document
+        let filename = output_stem(book_path, input);
+        
+        let doc_path = output.join(format!("{}.txt", filename));
+        fs::write(&doc_path, &book.text)
+            .with_context(|| format!("Failed to write document: {:?}", doc_path))?;
+        
+        all_text.push_str(&book.text);
+        all_text.push_str("\n\n");
+        
+        documents.push(DocumentMetadata {
+            id: String::new(),  // Will be filled later
+            filename,
+            source_path: book_path.strip_prefix(input)
+                .unwrap_or(book_path)
+                .to_string_lossy()
+                .to_string(),
+            file_type: book_path.extension()
+                .and_then(|s| s.to_str())
+                .unwrap_or("unknown")
+                .to_string(),
+            character_count: char_count,
+            token_count: 0,  // Will be filled later
+            processed_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            source_hash,
+            content_hash: String::new(),  // Will be filled later
+            pii_redactions: book.pii_redactions,
+            boilerplate: book.boilerplate,
+            cleaning: book.cleaning,
+        });
+        Ok(())
+    };
+    
+    for (idx, source) in book_files.iter().enumerate() {
+        info!("Processing {}/{}: {:?}", idx + 1, book_files.len(), source);
+        
+        if is_archive(source) {
+            // Parse members straight from the archive stream
+            for_each_archive_entry(source, |name, bytes| {
+                let book_path = source.join(name);
+                if is_supported_document(&book_path) {
+                    info!("Processing archive member {:?}", name);
+                    process_source(&book_path, Some(bytes))?;
+                }
+                Ok(())
+            })
+            .with_context(|| format!("Failed to read archive: {:?}", source))?;
+        } else {
+            process_source(source, None)?;
+        }
+    }
+    
+    if reused > 0 {
+        info!("Reused {} unchanged document(s) from cache", reused);
+    }
+    
+    if all_text.is_empty() {
+        anyhow::bail!("No text extracted from any books");
+    }
+    
+    // Cross-document boilerplate pass (publisher notices, license pages, ...)
+    let corpus_boilerplate = match config.boilerplate_threshold {
+        Some(threshold) => {
+            let mut doc_texts = Vec::new();
+            for doc_meta in &documents {
+                let doc_path = output.join(format!("{}.txt", doc_meta.filename));
+                doc_texts.push(fs::read_to_string(&doc_path)?);
+            }
+            
+            let (cleaned, report) = remove_boilerplate(&doc_texts, threshold);
+            info!("Removed {} boilerplate line(s) repeated across documents", report.removed_lines);
+            
+            all_text.clear();
+            for (doc_meta, text) in documents.iter_mut().zip(cleaned) {
+                let doc_path = output.join(format!("{}.txt", doc_meta.filename));
+                fs::write(&doc_path, &text)
+                    .with_context(|| format!("Failed to write document: {:?}", doc_path))?;
+                doc_meta.character_count = text.len();
+                all_text.push_str(&text);
+                all_text.push_str("\n\n");
+            }
+            Some(report)
+        }
+        None => None,
+    };
+    
+    // Stable IDs from the final text; documents with identical text
+    // (e.g. the same book in two formats) are kept once
+    let mut first_by_id: HashMap<String, String> = HashMap::new();
+    let mut unique_documents = Vec::with_capacity(documents.len());
+    let total_documents = documents.len();
+    all_text.clear();
+    for mut doc_meta in documents {
+        let doc_path = output.join(format!("{}.txt", doc_meta.filename));
+        let text = fs::read_to_string(&doc_path)?;
+        doc_meta.content_hash = content_hash(text.as_bytes());
+        doc_meta.id = document_id(&text);
+        if let Some(first) = first_by_id.get(&doc_meta.id) {
+            info!("Skipping {:?}: same text as {:?}", doc_meta.source_path, first);
+            fs::remove_file(&doc_path)
+                .with_context(|| format!("Failed to remove duplicate document: {:?}", doc_path))?;
+            continue;
+        }
+        first_by_id.insert(doc_meta.id.clone(), doc_meta.source_path.clone());
+        all_text.push_str(&text);
+        all_text.push_str("\n\n");
+        unique_documents.push(doc_meta);
+    }
+    if unique_documents.len() < total_documents {
+        info!("Removed {} duplicate document(s)", total_documents - unique_documents.len());
+    }
+    let mut documents = unique_documents;
+    
+    info!("Total text length: {} characters", all_text.len());
+    
+    // Build or load tokenizer
+    let tokenizer = if config.build_vocab {
+        info!("Building vocabulary from corpus...");
+        CharTokenizer::from_text(&all_text)
+    } else {
+        // Try to load existing tokenizer
+        let tokenizer_path = existing_tokenizer.map_or_else(|| output.join("vocab.json"), Path::to_path_buf);
+        if tokenizer_path.exists() {
+            info!("Loading existing tokenizer {:?}...", tokenizer_path);
+            CharTokenizer::load(&tokenizer_path)?
+        } else {
+            info!("No existing tokenizer found, building new one...");
+            CharTokenizer::from_text(&all_text)
+        }
+    };
+    
+    info!("Vocabulary size: {}", tokenizer.vocab_size());
+    
+    // Save tokenizer
+    let tokenizer_path = output.join("vocab.json");
+    tokenizer.save(&tokenizer_path)?;
+    info!("Tokenizer saved to: {:?}", tokenizer_path);
+    let tokenizer_hash = file_hash(&tokenizer_path)?;
+    
+    // Tokenize the entire corpus
+    info!("Tokenizing corpus...");
+    let tokens = tokenizer.encode(&all_text);
+    info!("Total tokens: {}", tokens.len());
+    
+    // Save corpus as JSONL
+    let corpus_path = output.join("corpus.jsonl");
+    let mut corpus_file = fs::File::create(&corpus_path)?;
+    
+    for doc_meta in documents.iter_mut() {
+        let doc_path = output.join(format!("{}.txt", doc_meta.filename));
+        let doc_text = fs::read_to_string(&doc_path)?;
+        let doc_tokens = tokenizer.encode(&doc_text);
+        
+        doc_meta.token_count = doc_tokens.len();
+        
+        let json_line = serde_json::json!({
+            "id": doc_meta.id,
+            "filename": doc_meta.filename,
+            "source_path": doc_meta.source_path,
+            "text": doc_text,
+            "tokens": doc_tokens,
+        });
+        
+        writeln!(corpus_file, "{}", serde_json::to_string(&json_line)?)?;
+    }
+    
+    info!("Corpus saved to: {:?}", corpus_path);
+    
+    let fingerprint = CorpusFingerprint {
+        document_hashes: documents
+            .iter()
+            .map(|d| (d.filename.clone(), d.content_hash.clone()))
+            .collect(),
+        cleaning_hash: cleaning_hash.clone(),
+        tokenizer_hash: tokenizer_hash.clone(),
+    };
+    let corpus_version = fingerprint.version();
+    
+    // Save metadata
+    let metadata = CorpusMetadata {
+        total_documents: documents.len(),
+        total_characters: all_text.len(),
+        total_tokens: tokens.len(),
+        vocab_size: tokenizer.vocab_size(),
+        corpus_version,
+        cleaning_hash,
+        tokenizer_hash,
+        pii_redactions: config.scrub_pii.then(|| pii_total.clone()),
+        boilerplate: corpus_boilerplate,
+        top_words: top_words(&all_text, 50),
+        cleaning: cleaning_total,
+        documents,
+    };
+    
+    let metadata_path = output.join(CORPUS_METADATA_FILE);
+    let metadata_json = serde_json::to_string_pretty(&metadata)?;
+    fs::write(&metadata_path, metadata_json)?;
+    info!("Metadata saved to: {:?}", metadata_path);
+    
+    Ok(metadata)
+}
+
+fn process_book(
+    path: &Path,
+    contents: Option<&[u8]>,
+    preserve_structure: bool,
+    enable_ocr: bool,
+    boilerplate_threshold: Option<f32>,
+    pipeline: &CleaningPipeline,
+) -> Result<ProcessedBook> {
+    let ext = path.extension()
+        .and_then(|s| s.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    
+    let mut boilerplate = None;
+    
+    let (text, cleaning) = match (ext.as_str(), boilerplate_threshold) {
+        ("pdf", _) if enable_ocr && contents.is_none() => {
+            // Try OCR if needed
+            pipeline.run(&auto_ocr_if_needed(path)?)
+        }
+        ("pdf", Some(threshold)) => {
+            let content = match contents {
+                Some(bytes) => extract_text_from_pdf_bytes(bytes)?,
+                None => extract_text_from_pdf(path)?,
+            };
+            
+            if !content.has_text {
+                anyhow::bail!("PDF has no extractable text (enable OCR with --enable-ocr)");
+            }
+            
+            // Strip running headers/footers repeated across pages
+            let (pages, report) = remove_boilerplate(&content.pages, threshold);
+            info!("Removed {} repeated header/footer line(s) across {} pages",
+                report.removed_lines, report.units);
+            boilerplate = Some(report);
+            
+            let document = Document {
+                title: file_title(path),
+                author: "Unknown".to_string(),
+                language: None,
+                sections: split_sections(&pages.join("\n")),
+            };
+            document_to_text(&document, preserve_structure, pipeline)
+        }
+        _ => {
+            let document = match contents {
+                Some(bytes) => parse_document_bytes(path, bytes)?,
+                None => parse_document(path)?,
+            };
+            document_to_text(&document, preserve_structure, pipeline)
+        }
+    };
+    
+    Ok(ProcessedBook {
+        text,
+        pii_redactions: None,
+        boilerplate,
+        cleaning,
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preprocess_corpus_writes_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("raw");
+        let output = dir.path().join("out");
+        fs::create_dir_all(input.join("nested")).unwrap();
+        fs::write(input.join("a.txt"), "The first book.\n\nIt has two paragraphs.").unwrap();
+        fs::write(input.join("nested/b.txt"), "A second, different book.").unwrap();
+        // Same text as a.txt: kept once
+        fs::write(input.join("copy.txt"), "The first book.\n\nIt has two paragraphs.").unwrap();
+
+        let metadata = preprocess_corpus(&input, &output, &PreprocessConfig::default(), None).unwrap();
+        assert_eq!(metadata.total_documents, 2);
+        assert!(output.join("vocab.json").exists());
+        let corpus = fs::read_to_string(output.join("corpus.jsonl")).unwrap();
+        assert_eq!(corpus.lines().count(), 2);
+        let written: CorpusMetadata =
+            serde_json::from_str(&fs::read_to_string(output.join(CORPUS_METADATA_FILE)).unwrap()).unwrap();
+        assert_eq!(written.corpus_version, metadata.corpus_version);
+
+        // Cached by source content, so the copy shares a.txt's entry
+        assert_eq!(fs::read_dir(output.join(".cache")).unwrap().count(), 2);
+        let again = preprocess_corpus(&input, &output, &PreprocessConfig::default(), None).unwrap();
+        assert_eq!(again.total_documents, 2);
+        assert_eq!(again.documents[0].content_hash, metadata.documents[0].content_hash);
+    }
+
+    #[test]
+    fn test_source_fingerprint_tracks_sources_and_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = PreprocessConfig::default();
+        fs::write(dir.path().join("a.txt"), "One book.").unwrap();
+        fs::write(dir.path().join("notes.bin"), [0u8, 1, 2]).unwrap();
+        let fingerprint = source_fingerprint(dir.path(), &config).unwrap();
+        assert_eq!(source_fingerprint(dir.path(), &config).unwrap(), fingerprint);
+
+        // Unsupported files are not sources
+        fs::write(dir.path().join("notes.bin"), [3u8]).unwrap();
+        assert_eq!(source_fingerprint(dir.path(), &config).unwrap(), fingerprint);
+
+        let scrubbed = PreprocessConfig { scrub_pii: true, ..PreprocessConfig::default() };
+        assert_ne!(source_fingerprint(dir.path(), &scrubbed).unwrap(), fingerprint);
+
+        fs::write(dir.path().join("b.txt"), "Another book.").unwrap();
+        assert_ne!(source_fingerprint(dir.path(), &config).unwrap(), fingerprint);
+    }
+}
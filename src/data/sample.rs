@@ -0,0 +1,148 @@
+use anyhow::{Context, Result};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Deserialize;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use super::contamination::for_each_text;
+
+/// Uniform random sample of `capacity` items from a stream of unknown
+/// length, holding only the sample in memory (Algorithm R)
+pub struct Reservoir<T> {
+    capacity: usize,
+    seen: usize,
+    items: Vec<T>,
+    rng: StdRng,
+}
+
+impl<T> Reservoir<T> {
+    pub fn new(capacity: usize, seed: u64) -> Self {
+        Self { capacity, seen: 0, items: Vec::with_capacity(capacity), rng: StdRng::seed_from_u64(seed) }
+    }
+
+    pub fn push(&mut self, item: T) {
+        self.seen += 1;
+        if self.items.len() < self.capacity {
+            self.items.push(item);
+        } else {
+            let slot = self.rng.gen_range(0..self.seen);
+            if slot < self.capacity {
+                self.items[slot] = item;
+            }
+        }
+    }
+
+    /// Number of items pushed so far
+    pub fn seen(&self) -> usize {
+        self.seen
+    }
+
+    pub fn into_items(self) -> Vec<T> {
+        self.items
+    }
+}
+
+/// A passage (non-empty line) of a corpus document
+#[derive(Debug, Clone)]
+pub struct Passage {
+    /// Document name (`filename` in `corpus.jsonl`, otherwise its path)
+    pub document: String,
+    /// Original file the document was extracted from, when known
+    pub source: Option<String>,
+    /// 1-based line of the passage in the document
+    pub line: usize,
+    pub text: String,
+}
+
+/// One line of `corpus.jsonl`, without the tokens
+#[derive(Deserialize)]
+struct CorpusText {
+    filename: String,
+    #[serde(default)]
+    source_path: Option<String>,
+    text: String,
+}
+
+/// Sample `n` passages uniformly from the corpus at `path`: a `preprocess`
+/// output directory (read from its `corpus.jsonl`), a `corpus.jsonl` file, or
+/// any document, directory or archive. Returns the sample in corpus order
+/// and the total number of passages.
+pub fn sample_passages(path: &Path, n: usize, seed: u64) -> Result<(Vec<Passage>, usize)> {
+    let mut reservoir = Reservoir::new(n, seed);
+    let mut index = 0;
+    let mut add = |document: &str, source: Option<&str>, text: &str| {
+        for (line, passage) in text.lines().enumerate() {
+            let passage = passage.trim();
+            if !passage.is_empty() {
+                let passage = Passage {
+                    document: document.to_string(),
+                    source: source.map(str::to_string),
+                    line: line + 1,
+                    text: passage.to_string(),
+                };
+                reservoir.push((index, passage));
+                index += 1;
+            }
+        }
+    };
+
+    let corpus_path = if path.is_dir() { path.join("corpus.jsonl") } else { path.to_path_buf() };
+    if corpus_path.extension().is_some_and(|ext| ext == "jsonl") && corpus_path.is_file() {
+        let file = File::open(&corpus_path)
+            .with_context(|| format!("Failed to open corpus file: {:?}", corpus_path))?;
+        for (line_no, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.with_context(|| format!("Failed to read corpus file: {:?}", corpus_path))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: CorpusText = serde_json::from_str(&line)
+                .with_context(|| format!("Invalid corpus entry at line {}", line_no + 1))?;
+            add(&entry.filename, entry.source_path.as_deref(), &entry.text);
+        }
+    } else {
+        for_each_text(path, |name, text| add(&name.to_string_lossy(), None, text))?;
+    }
+
+    let total = reservoir.seen();
+    let mut sample = reservoir.into_items();
+    sample.sort_by_key(|(index, _)| *index);
+    Ok((sample.into_iter().map(|(_, passage)| passage).collect(), total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reservoir_samples_uniformly() {
+        let mut counts = [0usize; 10];
+        for seed in 0..2000 {
+            let mut reservoir = Reservoir::new(3, seed);
+            (0..10).for_each(|i| reservoir.push(i));
+            assert_eq!(reservoir.seen(), 10);
+            for i in reservoir.into_items() {
+                counts[i] += 1;
+            }
+        }
+        // Each item is kept with probability 3/10, i.e. ~600 times
+        assert!(counts.iter().all(|&count| (500..700).contains(&count)), "{:?}", counts);
+    }
+
+    #[test]
+    fn test_sample_passages_from_corpus_jsonl() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let lines = [
+            r#"{"id": 0, "filename": "a", "source_path": "books/a.epub", "text": "first\n\nsecond", "tokens": [1]}"#,
+            r#"{"id": 1, "filename": "b", "source_path": "b.pdf", "text": "third", "tokens": [2]}"#,
+        ];
+        std::fs::write(dir.path().join("corpus.jsonl"), lines.join("\n")).unwrap();
+
+        let (passages, total) = sample_passages(dir.path(), 5, 0).unwrap();
+        assert_eq!(total, 3);
+        let found: Vec<_> = passages.iter().map(|p| (p.document.as_str(), p.line, p.text.as_str())).collect();
+        assert_eq!(found, [("a", 1, "first"), ("a", 3, "second"), ("b", 1, "third")]);
+        assert_eq!(passages[0].source.as_deref(), Some("books/a.epub"));
+    }
+}
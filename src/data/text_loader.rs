@@ -1,13 +1,15 @@
 use anyhow::{Context, Result};
 use burn::tensor::{Int, Tensor, backend::Backend};
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::Path;
 use tracing::info;
 use walkdir::WalkDir;
 
-use super::loader::DataLoader;
+use super::corpus::document_id;
+use super::loader::{DataLoader, DocumentSpans, batch_offsets, layout_num_batches, pack_batches, packed_batch, reorder_documents};
 use super::tokenizer::Tokenizer;
 use crate::training::BatchData;
+use crate::utils::{for_each_archive_entry, is_archive};
 
 /// Text data loader that loads data from text files
 pub struct TextDataLoader<B: Backend> {
@@ -15,7 +17,14 @@ pub struct TextDataLoader<B: Backend> {
     batch_size: usize,
     seq_len: usize,
     current_pos: usize,
+    stream_layout: bool,
+    /// Batches packed by `with_token_budget` (`current_pos` indexes them)
+    token_batches: Option<Vec<Vec<(usize, usize)>>>,
+    max_batch_tokens: Option<usize>,
     device: B::Device,
+    documents: DocumentSpans,
+    /// Loaded document of each document in `tokens` (see `start_epoch`)
+    document_order: Vec<usize>,
 }
 
 impl<B: Backend> TextDataLoader<B> {
@@ -35,16 +44,26 @@ impl<B: Backend> TextDataLoader<B> {
         let tokens = tokenizer.encode(&text);
         info!("Tokenized to {} tokens", tokens.len());
         
+        let mut documents = DocumentSpans::default();
+        documents.push(0, document_id(&text), path.to_string_lossy());
+        
         Ok(Self {
             tokens,
             batch_size,
             seq_len,
             current_pos: 0,
+            stream_layout: false,
+            token_batches: None,
+            max_batch_tokens: None,
             device,
+            documents,
+            document_order: Vec::new(),
         })
     }
     
-    /// Create a new text data loader from multiple files
+    /// Create a new text data loader from multiple files (`.txt` files in
+    /// `dir_path` and in `.zip`/`.tar.zst` archives, which may also be
+    /// `dir_path` itself)
     pub fn from_directory<T: Tokenizer>(
         dir_path: &Path,
         tokenizer: &T,
@@ -53,8 +72,19 @@ impl<B: Backend> TextDataLoader<B> {
         device: B::Device,
     ) -> Result<Self> {
         let mut all_tokens = Vec::new();
+        let mut documents = DocumentSpans::default();
         let mut file_count = 0;
         
+        let mut add_text = |name: &Path, text: &str| {
+            documents.push(all_tokens.len(), document_id(text), name.to_string_lossy());
+            all_tokens.extend(tokenizer.encode(text));
+            file_count += 1;
+            
+            if file_count % 10 == 0 {
+                info!("Processed {} files, {} tokens so far", file_count, all_tokens.len());
+            }
+        };
+        
         for entry in WalkDir::new(dir_path)
             .into_iter()
             .filter_map(|e| e.ok())
@@ -62,18 +92,17 @@ impl<B: Backend> TextDataLoader<B> {
         {
             let path = entry.path();
             
-            // Only process text files
-            if let Some(ext) = path.extension() {
-                if ext == "txt" {
-                    if let Ok(text) = fs::read_to_string(path) {
-                        let tokens = tokenizer.encode(&text);
-                        all_tokens.extend(tokens);
-                        file_count += 1;
-                        
-                        if file_count % 10 == 0 {
-                            info!("Processed {} files, {} tokens so far", file_count, all_tokens.len());
-                        }
+            // Only process text files, including those inside archives
+            if is_archive(path) {
+                for_each_archive_entry(path, |name, bytes| {
+                    if name.extension().is_some_and(|ext| ext == "txt") {
+                        add_text(&path.join(name), &String::from_utf8_lossy(&bytes));
                     }
+                    Ok(())
+                })?;
+            } else if path.extension().is_some_and(|ext| ext == "txt") {
+                if let Ok(text) = fs::read_to_string(path) {
+                    add_text(path, &text);
                 }
             }
         }
@@ -90,7 +119,12 @@ impl<B: Backend> TextDataLoader<B> {
             batch_size,
             seq_len,
             current_pos: 0,
+            stream_layout: false,
+            token_batches: None,
+            max_batch_tokens: None,
             device,
+            documents,
+            document_order: Vec::new(),
         })
     }
     
@@ -106,43 +140,76 @@ impl<B: Backend> TextDataLoader<B> {
             batch_size,
             seq_len,
             current_pos: 0,
+            stream_layout: false,
+            token_batches: None,
+            max_batch_tokens: None,
             device,
+            documents: DocumentSpans::default(),
+            document_order: Vec::new(),
         }
     }
+    
+    /// Largest token id in the loaded data
+    pub fn max_token_id(&self) -> Option<i64> {
+        self.tokens.iter().copied().max()
+    }
+    
+    /// Use the stream layout (see `batch_offsets`) for stateful training
+    pub fn with_stream_layout(mut self, stream_layout: bool) -> Self {
+        self.stream_layout = stream_layout;
+        self
+    }
+    
+    /// Pack batches to at most `max_batch_tokens` tokens instead of
+    /// `batch_size` full sequences (see `pack_batches`)
+    pub fn with_token_budget(mut self, max_batch_tokens: Option<usize>) -> Self {
+        self.max_batch_tokens = max_batch_tokens;
+        self.token_batches = max_batch_tokens
+            .map(|budget| pack_batches(self.tokens.len(), &self.documents, self.seq_len, budget));
+        self
+    }
 }
 
 impl<B: Backend> DataLoader<B> for TextDataLoader<B> {
     fn next_batch(&mut self) -> Result<Option<BatchData<B>>> {
-        // Check if we have enough data for a full batch
-        let required_len = self.batch_size * (self.seq_len + 1);  // +1 for target
+        if let Some(ref batches) = self.token_batches {
+            let Some(sequences) = batches.get(self.current_pos) else {
+                return Ok(None);
+            };
+            self.current_pos += 1;
+            return Ok(Some(packed_batch(&self.tokens, sequences, &self.device)));
+        }
         
-        if self.current_pos + required_len > self.tokens.len() {
+        let Some(offsets) = batch_offsets(
+            self.current_pos,
+            self.tokens.len(),
+            self.batch_size,
+            self.seq_len,
+            self.stream_layout,
+        ) else {
             return Ok(None);
-        }
+        };
         
         // Extract batch data
         let mut batch_tokens = Vec::new();
         let mut batch_targets = Vec::new();
         
-        for _ in 0..self.batch_size {
-            let start = self.current_pos;
-            let end = start + self.seq_len + 1;
-            
-            if end > self.tokens.len() {
-                return Ok(None);
-            }
-            
-            let sequence = &self.tokens[start..end];
+        for &start in &offsets {
+            let sequence = &self.tokens[start..start + self.seq_len + 1];
             
             // Input tokens
             batch_tokens.extend_from_slice(&sequence[..self.seq_len]);
             
             // Target tokens (shifted by 1)
             batch_targets.extend_from_slice(&sequence[1..]);
-            
-            self.current_pos += self.seq_len;
         }
         
+        self.current_pos += if self.stream_layout {
+            self.seq_len
+        } else {
+            self.batch_size * self.seq_len
+        };
+        
         // Convert to tensors
         let tokens_tensor = Tensor::<B, 1, Int>::from_ints(
             batch_tokens.as_slice(),
@@ -157,6 +224,8 @@ impl<B: Backend> DataLoader<B> for TextDataLoader<B> {
         Ok(Some(BatchData {
             tokens: tokens_tensor,
             targets: targets_tensor,
+            offsets,
+            lengths: Vec::new(),
         }))
     }
     
@@ -164,15 +233,34 @@ impl<B: Backend> DataLoader<B> for TextDataLoader<B> {
         self.current_pos = 0;
     }
     
+    fn position(&self) -> Option<usize> {
+        Some(self.current_pos)
+    }
+    
+    fn seek(&mut self, position: usize) {
+        self.current_pos = position;
+    }
+    
+    fn start_epoch(&mut self, epoch: usize, shuffle_seed: Option<u64>) {
+        reorder_documents(&mut self.tokens, &mut self.documents, &mut self.document_order, epoch, shuffle_seed);
+        self.token_batches = self.max_batch_tokens
+            .map(|budget| pack_batches(self.tokens.len(), &self.documents, self.seq_len, budget));
+        self.current_pos = 0;
+    }
+    
     fn num_batches(&self) -> Option<usize> {
-        let required_len = self.batch_size * (self.seq_len + 1);
-        if self.tokens.len() < required_len {
-            return Some(0);
+        if let Some(ref batches) = self.token_batches {
+            return Some(batches.len());
+        }
+        Some(layout_num_batches(self.tokens.len(), self.batch_size, self.seq_len, self.stream_layout))
+    }
+    
+    fn documents(&self) -> Option<&DocumentSpans> {
+        if self.documents.is_empty() {
+            None
+        } else {
+            Some(&self.documents)
         }
-        
-        // Calculate how many complete batches we can make
-        let available_sequences = (self.tokens.len() - self.seq_len) / self.seq_len;
-        Some(available_sequences / self.batch_size)
     }
 }
 
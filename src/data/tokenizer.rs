@@ -160,6 +160,29 @@ impl Tokenizer for CharTokenizer {
     }
 }
 
+/// For each of `vocab_size` token ids of `new`, the id of the same token in
+/// `old`, or `None` for tokens `old` does not have (and ids past `new`'s
+/// vocabulary). Padding and unknown map to their counterparts.
+pub fn vocab_mapping(old: &dyn Tokenizer, new: &dyn Tokenizer, vocab_size: usize) -> Vec<Option<usize>> {
+    (0..vocab_size as i64)
+        .map(|id| {
+            if id == new.pad_id() {
+                return Some(old.pad_id() as usize);
+            }
+            if id == new.unk_id() {
+                return Some(old.unk_id() as usize);
+            }
+            if id as usize >= new.vocab_size() {
+                return None;
+            }
+            match old.encode(&new.decode(&[id]))[..] {
+                [old_id] if old_id != old.unk_id() => Some(old_id as usize),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -183,5 +206,19 @@ mod tests {
         // All characters should be unknown
         assert!(encoded.iter().all(|&id| id == tokenizer.unk_id()));
     }
+    
+    #[test]
+    fn test_vocab_mapping() {
+        let old = CharTokenizer::from_vocab(vec!['a', 'b', 'c']);
+        let new = CharTokenizer::from_vocab(vec!['c', 'x', 'a']);
+        let mapping = vocab_mapping(&old, &new, 6);
+        
+        let id = |tokenizer: &CharTokenizer, ch: char| Some(tokenizer.encode(&ch.to_string())[0] as usize);
+        assert_eq!(mapping[new.pad_id() as usize], Some(old.pad_id() as usize));
+        assert_eq!(mapping[new.unk_id() as usize], Some(old.unk_id() as usize));
+        assert_eq!(mapping[id(&new, 'c').unwrap()], id(&old, 'c'));
+        assert_eq!(mapping[id(&new, 'a').unwrap()], id(&old, 'a'));
+        assert_eq!(mapping[id(&new, 'x').unwrap()], None);
+        assert_eq!(mapping[5], None);
+    }
 }
-
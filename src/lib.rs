@@ -1,5 +1,6 @@
 // Library exports for use in scripts and other binaries
 
+pub mod backend;
 pub mod checkpoint;
 pub mod config;
 pub mod data;
@@ -9,6 +10,6 @@ pub mod utils;
 
 // Re-export commonly used types
 pub use config::{TrainConfig, HopeConfig};
-pub use model::HopeModel;
+pub use model::{HopeModel, HopeCarry, CarryReset};
 pub use training::{HopeTrainer, BatchData};
 
@@ -1,3 +1,4 @@
+mod backend;
 mod checkpoint;
 mod config;
 mod data;
@@ -6,21 +7,57 @@ mod training;
 mod utils;
 
 use anyhow::{Context, Result};
-use burn::backend::Autodiff;
-use burn_ndarray::NdArray;
-use clap::{Args, Parser, Subcommand};
+use burn::module::AutodiffModule;
+use burn::tensor::backend::{AutodiffBackend, Backend};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use std::fs;
-use std::path::PathBuf;
-use tracing::info;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
 use tracing_subscriber::EnvFilter;
 
-use checkpoint::{save_checkpoint, load_checkpoint, list_checkpoints};
-use config::TrainConfig;
+use backend::{BackendKind, CpuAutodiffBackend, CpuBackend, DeviceIndex, TrainingTask, devices, run_training};
+use checkpoint::{
+    BEST_CHECKPOINT_NAME, RunManifest, list_checkpoints, load_checkpoint, load_memory_state, read_best_val_loss, read_checkpoint_metadata,
+    save_best_checkpoint, save_checkpoint, save_snapshot_checkpoint, save_swa_checkpoint, step_rng_seed, verify_corpus_version, write_run_manifest,
+    load_lora_adapters, read_lora_metadata, save_lora_adapters, save_model_checkpoint,
+    read_pipeline_manifest, write_pipeline_manifest, export_safetensors, export_onnx, import_pretrained,
+};
+use config::{CheckpointPrecision, DataConfig, DataType, HopeConfig, LrScheduleKind, Precision, RobustLossKind, TrainConfig};
+use data::contamination::{ContaminationChecker, DEFAULT_NGRAM, for_each_text};
+use data::preprocess::{preprocess_corpus, source_fingerprint};
+use data::sample::sample_passages;
+use data::{CharTokenizer, DataLoader, LoaderState, migrate_document_ids, Tokenizer, create_data_loader, create_validation_loader, read_corpus_version, vocab_mapping};
 use model::HopeModel;
-use training::{HopeTrainer, BatchData, generate_random_batch};
-
-// 使用单层 Autodiff 包装 - 模型使用 Backend trait，只在训练时需要 AutodiffBackend
-type Backend = Autodiff<NdArray<f32>>;
+use model::flops::format_flops;
+use model::prune::PruneOptions;
+use model::generate::GenerationConfig;
+use model::json_constraint::JsonConstraint;
+use model::lora::LoraConfig;
+use model::parity::{export_fixture, load_fixture, save_fixture, verify_fixture};
+use model::profile::save_profile_report;
+use training::HopeTrainer;
+use utils::CleaningStage;
+use training::attribution::DocumentLossTracker;
+use training::bench::{BenchConfig, BenchReport, run_bench};
+use training::callbacks::Callbacks;
+use training::early_stopping::EarlyStopping;
+use training::eval::{EvalMetrics, TOP_K_ACCURACY, TokenChars, evaluate};
+use training::lora::LoraTrainer;
+use training::loss_weights::TokenLossWeights;
+use training::lr_finder::{LrSweep, plot_lr_curve, suggest_learning_rate, write_lr_csv};
+use training::memory::{MemoryTracker, format_mib};
+use training::metrics_export::{MetricsExporter, MetricsRecord};
+use training::forgetting::{ForgettingLoaders, ForgettingVariant, measure_forgetting};
+use training::nan_guard::{NanGuard, NanGuardAction};
+use training::online::{OnlineConfig, OnlineLearner};
+use training::quarantine::{BatchQuarantine, describe_batch};
+use training::runs::{DivergenceKind, align_series, diff_manifests, divergence_points, load_run};
+use training::scheduler::LrScheduler;
+use training::swa::WeightAverage;
+use training::tensorboard::TensorBoardWriter;
 
 #[derive(Debug, Parser)]
 #[command(author, version, about = "HOPE Model Training CLI")]
@@ -31,59 +68,1768 @@ struct Cli {
 
 #[derive(Debug, Subcommand)]
 enum Commands {
+    /// Preprocess documents (PDF/EPUB/HTML/DOCX/TXT) into a training corpus
+    Preprocess(PreprocessArgs),
     /// Train the HOPE model
     Train(TrainArgs),
-    /// Evaluate the model (placeholder)
+    /// From a folder of documents to an exported checkpoint in one run:
+    /// preprocess (when the corpus is stale), train, evaluate, export
+    Pipeline(PipelineArgs),
+    /// Train on a new corpus from a checkpoint's weights, with selected
+    /// modules frozen
+    Finetune(FinetuneArgs),
+    /// Low-rank adapters: train them on a frozen checkpoint, merge them into it
+    #[command(subcommand)]
+    Lora(LoraCommand),
+    /// LR range test: sweep the learning rate exponentially for a few hundred
+    /// steps, record loss vs learning rate and suggest a value
+    LrFind(LrFindArgs),
+    /// Evaluate a checkpoint on held-out data
     Eval(EvalArgs),
+    /// Measure throughput and per-module forward time of a model configuration
+    Bench(BenchArgs),
+    /// Write a checkpoint's weights to a `.safetensors` file with its model
+    /// config, or its forward pass to an ONNX model, for tools outside Burn
+    Export(ExportArgs),
+    /// Prune the lowest-magnitude feed-forward neurons and attention heads
+    /// of a checkpoint and compare perplexity before and after
+    Prune(PruneArgs),
+    /// Resize a checkpoint's token embeddings and output head to a new
+    /// tokenizer vocabulary, keeping the rows of shared characters
+    ResizeVocab(ResizeVocabArgs),
+    /// Forward-pass fixtures of a checkpoint on a pinned input, for checking
+    /// ports of the model to other runtimes
+    #[command(subcommand)]
+    Parity(ParityCommand),
+    /// Show how a text is tokenized and verify the round trip
+    Tokenize(TokenizeArgs),
+    /// Generate continuations of a prompt from a checkpoint
+    Generate(GenerateArgs),
+    /// Measure forgetting of an old corpus after fine-tuning on a new one
+    Forgetting(ForgettingArgs),
+    /// Interactive generation that fine-tunes on accepted completions
+    Online(OnlineArgs),
+    /// Check n-gram overlap between the training corpus and evaluation sets
+    Contamination(ContaminationArgs),
+    /// Inspect the (preprocessed) training corpus
+    #[command(subcommand)]
+    Corpus(CorpusCommand),
+    /// Inspect recorded training runs
+    #[command(subcommand)]
+    Runs(RunsCommand),
+}
+
+#[derive(Debug, Subcommand)]
+enum LoraCommand {
+    /// Train LoRA adapters on top of a frozen checkpoint
+    Train(LoraTrainArgs),
+    /// Merge trained adapters into their base checkpoint, producing a full checkpoint
+    Merge(LoraMergeArgs),
+}
+
+#[derive(Debug, Subcommand)]
+enum ParityCommand {
+    /// Write the tokens, logits hash and loss of a checkpoint's forward pass
+    Export(ParityExportArgs),
+    /// Re-run the forward pass of a fixture and compare it with the fixture
+    Verify(ParityVerifyArgs),
+}
+
+#[derive(Debug, Subcommand)]
+enum CorpusCommand {
+    /// Print randomly sampled passages with their source documents
+    Sample(CorpusSampleArgs),
+    /// Give a corpus preprocessed before stable document IDs its `document_id`s
+    MigrateIds(CorpusMigrateArgs),
+}
+
+#[derive(Debug, Args)]
+struct PreprocessArgs {
+    /// Configuration JSON whose `data.preprocess` settings are used; the
+    /// output defaults to its `data.data_path`
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Input directory containing PDF/EPUB/HTML/DOCX/TXT files (default: `data.preprocess.input`)
+    #[arg(short, long)]
+    input: Option<PathBuf>,
+    /// Output directory for preprocessed files (default: `data.data_path`)
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Whether to preserve structure markers
+    #[arg(long)]
+    preserve_structure: Option<bool>,
+    /// Enable OCR for scanned PDFs
+    #[arg(long)]
+    enable_ocr: bool,
+    /// Build vocabulary from scratch (otherwise reuse `data.tokenizer_path`
+    /// or the output's vocab.json)
+    #[arg(long)]
+    build_vocab: Option<bool>,
+    /// Redact emails, phone numbers, ID numbers and addresses before saving
+    #[arg(long)]
+    scrub_pii: bool,
+    /// Remove lines repeating on at least this fraction of pages/documents (e.g. 0.5)
+    #[arg(long)]
+    boilerplate_threshold: Option<f32>,
+    /// Ordered, comma-separated text-cleaning stages
+    /// (whitespace, page_numbers, headers, dehyphenation, unicode)
+    #[arg(long, value_delimiter = ',')]
+    cleaning_stages: Option<Vec<CleaningStage>>,
+    /// Reprocess every document instead of reusing unchanged ones from the cache
+    #[arg(long)]
+    full_rebuild: bool,
+    /// File with article or RSS/Atom feed URLs (one per line); their readable
+    /// text is downloaded into `<input>/web` before processing
+    #[arg(long)]
+    fetch: Option<PathBuf>,
+    /// Parallel downloads for --fetch
+    #[arg(long)]
+    fetch_concurrency: Option<usize>,
+    /// Retries (with exponential backoff) for failed downloads
+    #[arg(long)]
+    fetch_retries: Option<usize>,
+    /// Maximum requests per second across all downloads
+    #[arg(long)]
+    fetch_rate: Option<f64>,
+}
+
+#[derive(Debug, Args)]
+struct TrainArgs {
+    /// Path to configuration JSON file
+    #[arg(long)]
+    config: PathBuf,
+    /// Compute backend
+    #[arg(long, value_enum, default_value = "ndarray")]
+    backend: BackendKind,
+    /// GPU index for the wgpu/tch backends
+    #[arg(long, default_value = "0")]
+    device: usize,
+    /// Keep watching the data directory and train on new files as they arrive
+    #[arg(long)]
+    follow: bool,
+    /// Run an LR range test first (weights are restored afterwards) and
+    /// train with the suggested learning rate
+    #[arg(long)]
+    find_lr: bool,
+    /// Precision of the saved model weights (overrides
+    /// `training.checkpoint_precision`)
+    #[arg(long, value_enum)]
+    checkpoint_precision: Option<CheckpointPrecisionArg>,
+    #[command(flatten)]
+    sweep: LrSweepArgs,
+}
+
+#[derive(Debug, Args)]
+struct PipelineArgs {
+    /// Training configuration JSON with `data.preprocess.input`, `data.data_path`
+    /// and an optional `pipeline` section
+    #[arg(long)]
+    config: PathBuf,
+    /// Compute backend
+    #[arg(long, value_enum, default_value = "ndarray")]
+    backend: BackendKind,
+    /// GPU index for the wgpu/tch backends
+    #[arg(long, default_value = "0")]
+    device: usize,
+    /// Preprocess even if the corpus is up to date with the input
+    #[arg(long)]
+    force_preprocess: bool,
+}
+
+#[derive(Debug, Args)]
+struct FinetuneArgs {
+    /// Checkpoint whose weights training starts from (its step count and
+    /// optimizer state are not carried over)
+    #[arg(long)]
+    checkpoint: PathBuf,
+    /// Configuration JSON for the fine-tuning run (data, steps, checkpoint_dir)
+    #[arg(long)]
+    config: PathBuf,
+    /// Module path prefixes to freeze, comma-separated (e.g.
+    /// `token_embed,level_encoders`), on top of `training.freeze`
+    #[arg(long, value_delimiter = ',')]
+    freeze: Vec<String>,
+    /// Compute backend
+    #[arg(long, value_enum, default_value = "ndarray")]
+    backend: BackendKind,
+    /// GPU index for the wgpu/tch backends
+    #[arg(long, default_value = "0")]
+    device: usize,
+}
+
+#[derive(Debug, Args)]
+struct LoraTrainArgs {
+    /// Checkpoint whose weights stay frozen
+    #[arg(long)]
+    checkpoint: PathBuf,
+    /// Configuration JSON for the run (data, steps, learning rate,
+    /// checkpoint_dir); the model comes from the checkpoint
+    #[arg(long)]
+    config: PathBuf,
+    /// Rank of the adapters
+    #[arg(long, default_value = "8")]
+    rank: usize,
+    /// Adapter updates are scaled by alpha / rank
+    #[arg(long, default_value = "16")]
+    alpha: f32,
+    /// Module path prefixes whose Linear weights get adapters, comma-separated
+    #[arg(long, value_delimiter = ',', default_value = "level_encoders,head")]
+    targets: Vec<String>,
+    /// Compute backend
+    #[arg(long, value_enum, default_value = "ndarray")]
+    backend: BackendKind,
+    /// GPU index for the wgpu/tch backends
+    #[arg(long, default_value = "0")]
+    device: usize,
+}
+
+#[derive(Debug, Args)]
+struct LoraMergeArgs {
+    /// Adapter checkpoint (`lora_step_<n>.json`)
+    #[arg(long)]
+    adapters: PathBuf,
+    /// Base checkpoint (default: the one the adapters were trained on)
+    #[arg(long)]
+    base: Option<PathBuf>,
+    /// Directory of the merged checkpoint (default: `merged/` next to the adapters)
+    #[arg(long)]
+    output_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct LrSweepArgs {
+    /// Smallest learning rate of the range test
+    #[arg(long, default_value = "1e-7")]
+    lr_min: f32,
+    /// Largest learning rate of the range test
+    #[arg(long, default_value = "1.0")]
+    lr_max: f32,
+    /// Steps of the range test (it stops early once the loss diverges)
+    #[arg(long, default_value = "200")]
+    lr_steps: usize,
+}
+
+impl LrSweepArgs {
+    fn sweep(&self) -> LrSweep {
+        LrSweep::new(self.lr_min, self.lr_max, self.lr_steps)
+    }
+}
+
+#[derive(Debug, Args)]
+struct LrFindArgs {
+    /// Path to configuration JSON file (training starts from `resume_from` if set)
+    #[arg(long)]
+    config: PathBuf,
+    /// Compute backend
+    #[arg(long, value_enum, default_value = "ndarray")]
+    backend: BackendKind,
+    /// GPU index for the wgpu/tch backends
+    #[arg(long, default_value = "0")]
+    device: usize,
+    #[command(flatten)]
+    sweep: LrSweepArgs,
+    /// Loss vs learning rate CSV (default: `<checkpoint_dir>/lr_find.csv`)
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct EvalArgs {
+    /// Path to model checkpoint
+    #[arg(long)]
+    checkpoint: PathBuf,
+    /// Path to evaluation data
+    #[arg(long)]
+    data: PathBuf,
+    /// Tokenizer vocab.json for bits per character (default: the data's tokenizer)
+    #[arg(long)]
+    tokenizer: Option<PathBuf>,
+    /// Where to write the eval JSON (default: next to the checkpoint)
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct BenchArgs {
+    /// Model configuration JSON: a training config (its `model`) or a bare model config
+    #[arg(long)]
+    config: PathBuf,
+    /// Sequences per pass (default: `training.batch_size`, or 8)
+    #[arg(long)]
+    batch_size: Option<usize>,
+    /// Timed passes
+    #[arg(long, default_value = "20")]
+    steps: usize,
+    /// Untimed passes first
+    #[arg(long, default_value = "3")]
+    warmup: usize,
+    /// Time forward+backward passes
+    #[arg(long)]
+    backward: bool,
+    /// Override `num_layers`
+    #[arg(long)]
+    num_layers: Option<usize>,
+    /// Override `level_timescales` (and `num_levels`), comma-separated
+    #[arg(long, value_delimiter = ',')]
+    level_timescales: Option<Vec<usize>>,
+    /// Compute backend
+    #[arg(long, value_enum, default_value = "ndarray")]
+    backend: BackendKind,
+    /// GPU index for the wgpu/tch backends
+    #[arg(long, default_value = "0")]
+    device: usize,
+    /// Where to write the JSON report
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct ExportArgs {
+    /// Path to model checkpoint
+    #[arg(long)]
+    checkpoint: PathBuf,
+    /// Output format
+    #[arg(long, value_enum, default_value = "safetensors")]
+    format: ExportFormat,
+    /// File to write (default: `model.safetensors` or `model.onnx`); a
+    /// safetensors export writes the config to `<name>.config.json` next to it
+    #[arg(long)]
+    output: Option<PathBuf>,
+    /// Sequence length of the ONNX graph (default: the model's `seq_len`)
+    #[arg(long)]
+    seq_len: Option<usize>,
+}
+
+#[derive(Debug, Args)]
+struct PruneArgs {
+    /// Path to model checkpoint
+    #[arg(long)]
+    checkpoint: PathBuf,
+    /// Fraction of the feed-forward neurons of every layer to prune
+    #[arg(long, default_value = "0.0")]
+    ffn_ratio: f32,
+    /// Fraction of the attention heads of every layer to prune (zeroed)
+    #[arg(long, default_value = "0.0")]
+    head_ratio: f32,
+    /// Remove the pruned neurons from the weights instead of zeroing them,
+    /// shrinking the checkpoint
+    #[arg(long)]
+    remove: bool,
+    /// Held-out data to evaluate the checkpoint on before and after pruning
+    #[arg(long)]
+    data: Option<PathBuf>,
+    /// Tokenizer vocab.json for bits per character (default: the data's tokenizer)
+    #[arg(long)]
+    tokenizer: Option<PathBuf>,
+    /// Directory of the pruned checkpoint (default: `pruned/` next to the checkpoint)
+    #[arg(long)]
+    output_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct ResizeVocabArgs {
+    /// Path to model checkpoint
+    #[arg(long)]
+    checkpoint: PathBuf,
+    /// The new tokenizer vocab.json
+    #[arg(long)]
+    tokenizer: PathBuf,
+    /// Tokenizer vocab.json the checkpoint was trained with (default: its data's tokenizer)
+    #[arg(long)]
+    old_tokenizer: Option<PathBuf>,
+    /// Vocabulary size of the resized model (default: the checkpoint's, or the
+    /// new tokenizer's if that is larger)
+    #[arg(long)]
+    vocab_size: Option<usize>,
+    /// Directory of the resized checkpoint (default: `resized/` next to the checkpoint)
+    #[arg(long)]
+    output_dir: Option<PathBuf>,
+}
+
+/// `--checkpoint-precision` values
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum CheckpointPrecisionArg {
+    /// f32 weights
+    Full,
+    /// f16 weights
+    Half,
+}
+
+impl From<CheckpointPrecisionArg> for CheckpointPrecision {
+    fn from(precision: CheckpointPrecisionArg) -> Self {
+        match precision {
+            CheckpointPrecisionArg::Full => CheckpointPrecision::Full,
+            CheckpointPrecisionArg::Half => CheckpointPrecision::Half,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ExportFormat {
+    /// Weights and model config
+    Safetensors,
+    /// Forward pass from tokens to logits with a fresh carry
+    Onnx,
+}
+
+#[derive(Debug, Args)]
+struct ParityExportArgs {
+    /// Path to model checkpoint
+    #[arg(long)]
+    checkpoint: PathBuf,
+    /// Input text, at most `seq_len + 1` tokens
+    #[arg(long)]
+    text: String,
+    /// Tokenizer vocab.json (default: the data's tokenizer)
+    #[arg(long)]
+    tokenizer: Option<PathBuf>,
+    /// Decimal places the logits are rounded to before hashing
+    #[arg(long, default_value = "4")]
+    decimals: usize,
+    /// Where to write the fixture JSON
+    #[arg(long, default_value = "parity_fixture.json")]
+    output: PathBuf,
+}
+
+#[derive(Debug, Args)]
+struct ParityVerifyArgs {
+    /// Fixture written by `parity export`
+    #[arg(long)]
+    fixture: PathBuf,
+    /// Checkpoint to verify (default: the fixture's)
+    #[arg(long)]
+    checkpoint: Option<PathBuf>,
+    /// Largest accepted difference of the loss and per-position logits
+    #[arg(long, default_value = "1e-4")]
+    tolerance: f32,
+}
+
+#[derive(Debug, Args)]
+struct ContaminationArgs {
+    /// Training corpus: a document, directory or archive
+    #[arg(long)]
+    corpus: PathBuf,
+    /// Evaluation sets, each a document, directory or archive
+    #[arg(long, required = true, num_args = 1..)]
+    eval: Vec<PathBuf>,
+    /// N-gram length in words
+    #[arg(long, default_value_t = DEFAULT_NGRAM)]
+    ngram: usize,
+    /// Where to write the JSON report
+    #[arg(long, default_value = "contamination.json")]
+    output: PathBuf,
+}
+
+#[derive(Debug, Args)]
+struct CorpusSampleArgs {
+    /// `preprocess` output directory, `corpus.jsonl`, or any document, directory or archive
+    #[arg(long)]
+    data: PathBuf,
+    /// Number of passages to print
+    #[arg(long, default_value = "50")]
+    n: usize,
+    /// Random seed for the sample
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Truncate passages longer than this many characters
+    #[arg(long, default_value = "500")]
+    max_chars: usize,
+}
+
+#[derive(Debug, Subcommand)]
+enum RunsCommand {
+    /// Diff two runs' manifests and compare their loss curves by tokens seen
+    Compare(RunsCompareArgs),
+}
+
+#[derive(Debug, Args)]
+struct RunsCompareArgs {
+    /// Run manifest (`run_<ts>.manifest.json`) or checkpoint directory of the first run
+    run_a: PathBuf,
+    /// Run manifest or checkpoint directory of the second run
+    run_b: PathBuf,
+    /// Relative loss gap that counts as a divergence
+    #[arg(long, default_value = "0.05")]
+    threshold: f32,
+    /// Rows of the loss table
+    #[arg(long, default_value = "10")]
+    rows: usize,
+}
+
+#[derive(Debug, Args)]
+struct CorpusMigrateArgs {
+    /// `preprocess` output directory
+    #[arg(long)]
+    data: PathBuf,
+}
+
+#[derive(Debug, Args)]
+struct GenerateArgs {
+    /// Path to model checkpoint
+    #[arg(long)]
+    checkpoint: PathBuf,
+    /// Path to tokenizer vocabulary JSON (default: the checkpoint's training tokenizer)
+    #[arg(long)]
+    tokenizer: Option<PathBuf>,
+    /// Start from an empty continuum memory instead of the one saved with the checkpoint
+    #[arg(long)]
+    fresh_memory: bool,
+    /// Pre-read this text file into episodic memory before generating
+    /// (instead of the memory saved with the checkpoint)
+    #[arg(long)]
+    reference: Option<PathBuf>,
+    /// Prompt text
+    #[arg(long)]
+    prompt: String,
+    /// Number of tokens to generate
+    #[arg(long, default_value = "200")]
+    max_new_tokens: usize,
+    /// Sampling temperature (0 = greedy)
+    #[arg(long, default_value = "1.0")]
+    temperature: f32,
+    /// Sample only among the k most likely tokens
+    #[arg(long)]
+    top_k: Option<usize>,
+    /// Sample only among the most likely tokens covering this probability mass (nucleus sampling)
+    #[arg(long)]
+    top_p: Option<f32>,
+    /// Number of candidates to return, ranked by log-prob
+    #[arg(long, default_value = "1")]
+    num_return_sequences: usize,
+    /// Penalty for picking the same token as another candidate at the same position
+    #[arg(long, default_value = "0.0")]
+    diversity_penalty: f32,
+    /// Random seed for reproducible sampling
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Reject prompts longer than this many tokens
+    #[arg(long)]
+    max_prompt_tokens: Option<usize>,
+    /// Stop generating after this many seconds, keeping the output so far
+    #[arg(long)]
+    timeout_secs: Option<f64>,
+    /// Only emit a single valid JSON object or array
+    #[arg(long)]
+    json: bool,
+    /// Stop a sequence when the mean probability of its last
+    /// --confidence-window tokens drops below this
+    #[arg(long)]
+    min_confidence: Option<f32>,
+    /// Tokens averaged for --min-confidence
+    #[arg(long, default_value = "8")]
+    confidence_window: usize,
+}
+
+#[derive(Debug, Args)]
+struct ForgettingArgs {
+    /// Path to model checkpoint (trained on the old corpus)
+    #[arg(long)]
+    checkpoint: PathBuf,
+    /// Earlier task/corpus to measure forgetting on
+    #[arg(long)]
+    old_data: PathBuf,
+    /// New corpus to fine-tune on
+    #[arg(long)]
+    new_data: PathBuf,
+    /// Fine-tuning steps per variant
+    #[arg(long, default_value = "100")]
+    steps: usize,
+    /// Fine-tuning learning rate (default: the checkpoint's)
+    #[arg(long)]
+    learning_rate: Option<f32>,
+    /// Where to write the report (default: next to the checkpoint)
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct OnlineArgs {
+    /// Path to model checkpoint
+    #[arg(long)]
+    checkpoint: PathBuf,
+    /// Path to tokenizer vocabulary JSON (default: the checkpoint's training tokenizer)
+    #[arg(long)]
+    tokenizer: Option<PathBuf>,
+    /// Pre-read this text file into episodic memory before each generation
+    #[arg(long)]
+    reference: Option<PathBuf>,
+    /// Learning rate for online updates
+    #[arg(long, default_value = "1e-5")]
+    learning_rate: f32,
+    /// Hard cap on the online learning rate
+    #[arg(long, default_value = "1e-4")]
+    max_learning_rate: f32,
+    /// Optimizer steps per accepted completion
+    #[arg(long, default_value = "1")]
+    steps_per_example: usize,
+    /// Stop learning after this many optimizer steps
+    #[arg(long, default_value = "100")]
+    max_updates: usize,
+    /// Save a checkpoint every N optimizer steps (0 = only on exit)
+    #[arg(long, default_value = "10")]
+    save_every: usize,
+    /// Where to save online checkpoints (default: the training checkpoint_dir)
+    #[arg(long)]
+    checkpoint_dir: Option<PathBuf>,
+    /// Number of tokens to generate per prompt
+    #[arg(long, default_value = "200")]
+    max_new_tokens: usize,
+    /// Sampling temperature (0 = greedy)
+    #[arg(long, default_value = "1.0")]
+    temperature: f32,
+    /// Sample only among the k most likely tokens
+    #[arg(long)]
+    top_k: Option<usize>,
+    /// Sample only among the most likely tokens covering this probability mass (nucleus sampling)
+    #[arg(long)]
+    top_p: Option<f32>,
+    /// Random seed for reproducible sampling
+    #[arg(long)]
+    seed: Option<u64>,
+}
+
+#[derive(Debug, Args)]
+struct TokenizeArgs {
+    /// Path to tokenizer vocabulary JSON (built from the input if omitted)
+    #[arg(long)]
+    tokenizer: Option<PathBuf>,
+    /// Text to tokenize
+    #[arg(long, conflicts_with = "file")]
+    text: Option<String>,
+    /// File to tokenize
+    #[arg(long)]
+    file: Option<PathBuf>,
+}
+
+fn main() -> Result<()> {
+    // Initialize tracing
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
+
+    let cli = Cli::parse();
+
+    match cli.command {
+        Commands::Preprocess(args) => preprocess_command(args),
+        Commands::Train(args) => train_command(args),
+        Commands::Pipeline(args) => pipeline_command(args),
+        Commands::Finetune(args) => finetune_command(args),
+        Commands::Lora(LoraCommand::Train(args)) => lora_train_command(args),
+        Commands::Lora(LoraCommand::Merge(args)) => lora_merge_command(args),
+        Commands::LrFind(args) => lr_find_command(args),
+        Commands::Eval(args) => eval_command(args),
+        Commands::Bench(args) => bench_command(args),
+        Commands::Export(args) => export_command(args),
+        Commands::Prune(args) => prune_command(args),
+        Commands::ResizeVocab(args) => resize_vocab_command(args),
+        Commands::Parity(ParityCommand::Export(args)) => parity_export_command(args),
+        Commands::Parity(ParityCommand::Verify(args)) => parity_verify_command(args),
+        Commands::Tokenize(args) => tokenize_command(args),
+        Commands::Generate(args) => generate_command(args),
+        Commands::Forgetting(args) => forgetting_command(args),
+        Commands::Online(args) => online_command(args),
+        Commands::Contamination(args) => contamination_command(args),
+        Commands::Corpus(CorpusCommand::Sample(args)) => corpus_sample_command(args),
+        Commands::Corpus(CorpusCommand::MigrateIds(args)) => {
+            let migrated = migrate_document_ids(&args.data)?;
+            info!("Assigned stable IDs to {} document(s) in {:?}", migrated, args.data);
+            Ok(())
+        }
+        Commands::Runs(RunsCommand::Compare(args)) => runs_compare_command(args),
+    }
+}
+
+fn tokenize_command(args: TokenizeArgs) -> Result<()> {
+    let text = match (args.text, args.file) {
+        (Some(text), _) => text,
+        (None, Some(path)) => fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read input file: {:?}", path))?,
+        (None, None) => anyhow::bail!("Either --text or --file must be provided"),
+    };
+    
+    let tokenizer = match args.tokenizer {
+        Some(ref path) => CharTokenizer::load(path)?,
+        None => {
+            info!("No tokenizer given, building vocabulary from the input text");
+            CharTokenizer::from_text(&text)
+        }
+    };
+    
+    let tokens = tokenizer.encode(&text);
+    let color = std::io::stdout().is_terminal();
+    
+    // Token boundaries, alternating colors in a terminal; unknown tokens in red
+    let mut boundaries = String::from("|");
+    for (idx, &id) in tokens.iter().enumerate() {
+        let piece = tokenizer.decode(&[id]).escape_debug().to_string();
+        let piece = if id == tokenizer.unk_id() { format!("<unk:{}>", piece) } else { piece };
+        if color {
+            let style = if id == tokenizer.unk_id() {
+                "41;97"
+            } else if idx % 2 == 0 {
+                "44;97"
+            } else {
+                "46;30"
+            };
+            boundaries.push_str(&format!("\x1b[{}m{}\x1b[0m|", style, piece));
+        } else {
+            boundaries.push_str(&piece);
+            boundaries.push('|');
+        }
+    }
+    
+    println!("{}", boundaries);
+    println!("{:?}", tokens);
+    
+    // Summary and round-trip verification
+    let unk_count = tokens.iter().filter(|&&id| id == tokenizer.unk_id()).count();
+    let decoded = tokenizer.decode(&tokens);
+    println!();
+    println!("Characters: {}", text.chars().count());
+    println!("Tokens: {}", tokens.len());
+    println!("Vocabulary size: {}", tokenizer.vocab_size());
+    println!("Unknown tokens: {}", unk_count);
+    
+    if decoded == text {
+        println!("Round trip: OK");
+    } else {
+        let mismatch = decoded
+            .chars()
+            .zip(text.chars())
+            .position(|(a, b)| a != b)
+            .unwrap_or_else(|| decoded.chars().count().min(text.chars().count()));
+        println!("Round trip: FAILED (first mismatch at character {})", mismatch);
+    }
+    
+    Ok(())
+}
+
+fn generate_command(args: GenerateArgs) -> Result<()> {
+    let timeout = args.timeout_secs
+        .map(Duration::try_from_secs_f64)
+        .transpose()
+        .map_err(|e| anyhow::anyhow!("--timeout-secs must be a finite, non-negative number of seconds: {}", e))?;
+    let device = Default::default();
+    let (model, _, config) = load_checkpoint::<CpuBackend>(&args.checkpoint, &device)?;
+    let model = model.eval();
+    
+    let tokenizer = match args.tokenizer {
+        Some(ref path) => CharTokenizer::load(path)?,
+        None => load_data_tokenizer(&config)
+            .ok_or_else(|| anyhow::anyhow!("No tokenizer found for the checkpoint; pass --tokenizer"))?,
+    };
+    
+    let prompt = tokenizer.encode(&args.prompt);
+    if prompt.is_empty() {
+        anyhow::bail!("Prompt must not be empty");
+    }
+    if let Some(max) = args.max_prompt_tokens {
+        if prompt.len() > max {
+            anyhow::bail!("Prompt has {} tokens, more than the limit of {}", prompt.len(), max);
+        }
+    }
+    
+    if args.json && !["{", "["].iter().any(|open| tokenizer.encode(open)[0] != tokenizer.unk_id()) {
+        anyhow::bail!("--json needs `{{` or `[` in the tokenizer vocabulary");
+    }
+    
+    let mut generation = GenerationConfig {
+        max_new_tokens: args.max_new_tokens,
+        temperature: args.temperature,
+        top_k: args.top_k,
+        top_p: args.top_p,
+        num_return_sequences: args.num_return_sequences,
+        diversity_penalty: args.diversity_penalty,
+        seed: args.seed,
+        timeout,
+        json: args.json.then(|| Arc::new(json_constraint(&tokenizer, config.model.vocab_size))),
+        min_confidence: args.min_confidence,
+        confidence_window: args.confidence_window,
+    };
+    // Echo the seed so any sample can be reproduced with --seed
+    println!("Seed: {}", generation.resolved_seed());
+    let reference = args.reference.as_deref().map(|path| load_reference(path, &tokenizer, &config)).transpose()?;
+    let memory = if args.fresh_memory || reference.is_some() {
+        None
+    } else {
+        load_memory_state::<CpuBackend>(&args.checkpoint, &device)?
+    };
+    let candidates = match (reference, memory) {
+        (Some(reference), _) => {
+            info!("Pre-reading the reference document ({} tokens) into episodic memory", reference.len());
+            model.generate_with_reference(&reference, &prompt, &generation, &device)
+        }
+        (None, Some(ref memory)) => {
+            info!("Resuming the continuum memory saved with the checkpoint");
+            model.generate_with_memory(memory, &prompt, &generation, &device)
+        }
+        (None, None) => model.generate(&prompt, &generation, &device),
+    };
+    
+    for (rank, candidate) in candidates.iter().enumerate() {
+        println!("=== #{} (log-prob {:.3}, mean {:.4}) ===", rank + 1, candidate.log_prob, candidate.mean_log_prob);
+        if candidate.timed_out {
+            println!("(stopped after {} tokens: timeout)", candidate.tokens.len());
+        }
+        if candidate.json_complete == Some(false) {
+            println!("(incomplete JSON after {} tokens)", candidate.tokens.len());
+        }
+        if let Some(confidence) = candidate.low_confidence {
+            println!("(stopped after {} tokens: mean confidence {:.3} below threshold)", candidate.tokens.len(), confidence);
+        }
+        println!("{}{}", args.prompt, tokenizer.decode(&candidate.tokens));
+    }
+    
+    Ok(())
+}
+
+/// Tokens of a `--reference` document to pre-read into episodic memory
+fn load_reference(path: &Path, tokenizer: &CharTokenizer, config: &TrainConfig) -> Result<Vec<i64>> {
+    if !config.model.continuum_mem.enabled {
+        anyhow::bail!("--reference needs a model with continuum memory (model.continuum_mem.enabled)");
+    }
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read reference document: {:?}", path))?;
+    let tokens = tokenizer.encode(&text);
+    if tokens.is_empty() {
+        anyhow::bail!("Reference document {:?} is empty", path);
+    }
+    Ok(tokens)
+}
+
+fn forgetting_command(args: ForgettingArgs) -> Result<()> {
+    let device = Default::default();
+    let metadata = read_checkpoint_metadata(&args.checkpoint)?;
+    
+    let mut config = metadata.config.clone();
+    if let Some(lr) = args.learning_rate {
+        config.training.learning_rate = lr;
+    }
+    config.training.val_data = Some(args.old_data.clone());
+    let old_config = config.clone();
+    config.training.val_data = Some(args.new_data.clone());
+    config.data.data_path = Some(args.new_data.clone());
+    
+    let mut results = Vec::new();
+    for variant in ForgettingVariant::ablations(&config) {
+        info!("Variant {} (continuum memory: {}, self-modify: {})",
+            variant.name, variant.continuum_mem, variant.self_modify);
+        // Every variant starts from the same weights
+        let (model, _, _) = load_checkpoint::<CpuAutodiffBackend>(&args.checkpoint, &device)?;
+        let mut old_eval = create_validation_loader::<CpuBackend>(&old_config, &device)?
+            .ok_or_else(|| anyhow::anyhow!("No old-corpus data"))?;
+        let mut new_eval = create_validation_loader::<CpuBackend>(&config, &device)?
+            .ok_or_else(|| anyhow::anyhow!("No new-corpus data"))?;
+        let mut new_train = create_data_loader::<CpuAutodiffBackend>(&config, &device)?;
+        
+        let loaders = ForgettingLoaders {
+            old_eval: old_eval.as_mut(),
+            new_eval: new_eval.as_mut(),
+            new_train: new_train.as_mut(),
+        };
+        let result = measure_forgetting(model, &config, &variant, loaders, args.steps, &device)?;
+        info!("[{}] Old corpus perplexity {:.2} -> {:.2} | New corpus perplexity {:.2} -> {:.2} | Forgetting = {:+.4}",
+            variant.name, result.old_before.perplexity, result.old_after.perplexity,
+            result.new_before.perplexity, result.new_after.perplexity, result.forgetting);
+        results.push(result);
+    }
+    
+    let output = args.output.unwrap_or_else(|| args.checkpoint.with_extension("forgetting.json"));
+    let report = serde_json::json!({
+        "checkpoint": args.checkpoint,
+        "step": metadata.step,
+        "old_data": args.old_data,
+        "new_data": args.new_data,
+        "steps": args.steps,
+        "learning_rate": config.training.learning_rate,
+        "results": results,
+    });
+    fs::write(&output, serde_json::to_string_pretty(&report)?)
+        .with_context(|| format!("Failed to write forgetting report: {:?}", output))?;
+    info!("Forgetting report saved to: {:?}", output);
+    
+    Ok(())
+}
+
+fn online_command(args: OnlineArgs) -> Result<()> {
+    let device = Default::default();
+    let (model, step, config) = load_checkpoint::<CpuAutodiffBackend>(&args.checkpoint, &device)?;
+    
+    let tokenizer = match args.tokenizer {
+        Some(ref path) => CharTokenizer::load(path)?,
+        None => load_data_tokenizer(&config)
+            .ok_or_else(|| anyhow::anyhow!("No tokenizer found for the checkpoint; pass --tokenizer"))?,
+    };
+    
+    let reference = args.reference.as_deref().map(|path| load_reference(path, &tokenizer, &config)).transpose()?;
+    
+    let online = OnlineConfig {
+        learning_rate: args.learning_rate,
+        max_learning_rate: args.max_learning_rate,
+        steps_per_example: args.steps_per_example,
+        max_updates: args.max_updates,
+        save_every: args.save_every,
+        checkpoint_dir: args
+            .checkpoint_dir
+            .unwrap_or_else(|| config.training.checkpoint_dir.clone()),
+    };
+    info!("Online learning: lr = {:e} (cap {:e}), {} step(s) per example, at most {} updates",
+        online.effective_learning_rate(), online.max_learning_rate, online.steps_per_example, online.max_updates);
+    let mut learner = OnlineLearner::new(model, config, online, step, tokenizer.pad_id(), &device);
+    
+    let mut generation = GenerationConfig {
+        max_new_tokens: args.max_new_tokens,
+        temperature: args.temperature,
+        top_k: args.top_k,
+        top_p: args.top_p,
+        seed: args.seed,
+        ..Default::default()
+    };
+    info!("Sampling seed: {}", generation.resolved_seed());
+    
+    let stdin = std::io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("prompt> ");
+        std::io::stdout().flush()?;
+        let Some(prompt) = lines.next().transpose()? else { break };
+        if prompt.is_empty() {
+            continue;
+        }
+        
+        let prompt_tokens = tokenizer.encode(&prompt);
+        let model = learner.model().valid().eval();
+        // The reference is re-read by the current weights, which change as the learner updates
+        let candidates = match reference {
+            Some(ref reference) => model.generate_with_reference(reference, &prompt_tokens, &generation, &device),
+            None => model.generate(&prompt_tokens, &generation, &device),
+        };
+        let candidate = candidates
+            .into_iter()
+            .next()
+            .expect("generate returns at least one candidate");
+        // Vary the sample across prompts while staying reproducible
+        generation.seed = generation.seed.map(|seed| seed.wrapping_add(1));
+        println!("{}{}", prompt, tokenizer.decode(&candidate.tokens));
+        
+        if learner.remaining_updates() == 0 {
+            continue;
+        }
+        print!("accept? [y/N] ");
+        std::io::stdout().flush()?;
+        let Some(answer) = lines.next().transpose()? else { break };
+        if !answer.trim().eq_ignore_ascii_case("y") {
+            continue;
+        }
+        
+        let mut tokens = prompt_tokens;
+        tokens.extend_from_slice(&candidate.tokens);
+        match learner.learn(&tokens)? {
+            Some(loss) => info!("Update {}/{} | Loss = {:.6}",
+                learner.updates(), learner.updates() + learner.remaining_updates(), loss),
+            None => info!("Nothing to learn from this completion"),
+        }
+        if learner.remaining_updates() == 0 {
+            info!("Update cap reached; continuing without learning");
+        }
+    }
+    
+    if learner.has_unsaved_updates() {
+        learner.save()?;
+    }
+    
+    Ok(())
+}
+
+fn eval_command(args: EvalArgs) -> Result<()> {
+    evaluate_checkpoint(&args.checkpoint, &args.data, args.tokenizer.as_deref(), args.output)?;
+    Ok(())
+}
+
+/// Evaluate `checkpoint` on `data` and write the results to `output`
+/// (default: next to the checkpoint); returns the metrics and that path
+fn evaluate_checkpoint(
+    checkpoint: &Path,
+    data: &Path,
+    tokenizer: Option<&Path>,
+    output: Option<PathBuf>,
+) -> Result<(EvalMetrics, PathBuf)> {
+    let device = Default::default();
+    let (model, step, mut config) = load_checkpoint::<CpuBackend>(checkpoint, &device)?;
+    let model = model.eval();
+    
+    // Reuse the validation loader so eval data is read exactly like val_data
+    config.training.val_data = Some(data.to_path_buf());
+    let mut loader = create_validation_loader::<CpuBackend>(&config, &device)?
+        .ok_or_else(|| anyhow::anyhow!("No evaluation data"))?;
+    
+    let tokenizer = match tokenizer {
+        Some(path) => Some(CharTokenizer::load(path)?),
+        None => load_data_tokenizer(&config),
+    };
+    if tokenizer.is_none() {
+        warn!("No tokenizer found for the checkpoint; bits per character are not reported (pass --tokenizer)");
+    }
+    let token_chars = tokenizer.as_ref().map(|tokenizer| TokenChars::new(tokenizer));
+    
+    let metrics = evaluate(&model, loader.as_mut(), token_chars.as_ref())?;
+    info!("Eval loss = {:.6} | Perplexity = {:.2} | ECE = {:.4} ({} tokens)",
+        metrics.loss, metrics.perplexity, metrics.calibration.ece, metrics.tokens);
+    info!("Accuracy = {:.4} | Top-{} accuracy = {:.4} | Bits/token = {:.4} | Bits/char = {}",
+        metrics.accuracy, TOP_K_ACCURACY, metrics.top5_accuracy, metrics.bits_per_token,
+        metrics.bits_per_char.map_or("-".to_string(), |bits| format!("{:.4}", bits)));
+    for bucket in metrics.calibration.buckets.iter().filter(|b| b.count > 0) {
+        info!("  Confidence [{:.1}, {:.1}): {} predictions, confidence {:.3}, accuracy {:.3}",
+            bucket.lower, bucket.upper, bucket.count, bucket.confidence, bucket.accuracy);
+    }
+    
+    let output = output.unwrap_or_else(|| checkpoint.with_extension("eval.json"));
+    let report = serde_json::json!({
+        "checkpoint": checkpoint,
+        "step": step,
+        "data": data,
+        "metrics": metrics,
+    });
+    fs::write(&output, serde_json::to_string_pretty(&report)?)
+        .with_context(|| format!("Failed to write eval results: {:?}", output))?;
+    info!("Eval results saved to: {:?}", output);
+    
+    Ok((metrics, output))
+}
+
+fn bench_command(args: BenchArgs) -> Result<()> {
+    let config_str = fs::read_to_string(&args.config)
+        .with_context(|| format!("Failed to read config file: {:?}", args.config))?;
+    let mut value: serde_json::Value = serde_json::from_str(&config_str)
+        .with_context(|| "Failed to parse config JSON")?;
+    let batch_size = args.batch_size
+        .or_else(|| value.pointer("/training/batch_size").and_then(|size| size.as_u64()).map(|size| size as usize))
+        .unwrap_or(8);
+    let mut model: HopeConfig = match value.get_mut("model") {
+        Some(model) => serde_json::from_value(model.take()),
+        None => serde_json::from_value(value),
+    }
+    .with_context(|| "Failed to parse model config")?;
+    if let Some(num_layers) = args.num_layers {
+        model.num_layers = num_layers;
+    }
+    if let Some(timescales) = args.level_timescales {
+        model.num_levels = timescales.len();
+        model.level_timescales = timescales;
+    }
+    
+    let bench = BenchConfig {
+        batch_size,
+        steps: args.steps,
+        warmup: args.warmup,
+        backward: args.backward,
+    };
+    info!("Benchmarking {} {} passes of {} x {} tokens on {:?}: hidden_size={}, num_layers={}, level_timescales={:?}",
+        bench.steps, if bench.backward { "forward+backward" } else { "forward" }, bench.batch_size, model.seq_len,
+        args.backend, model.hidden_size, model.num_layers, model.level_timescales);
+    let report = run_training(args.backend, false, BenchRun { model, bench, device: args.device });
+    
+    println!("Parameters: {}", report.num_params);
+    println!("Forward FLOPs/token: {}", format_flops(report.flops_per_token));
+    println!("Throughput: {:.0} tokens/s, {:.2} steps/s", report.tokens_per_sec, report.steps_per_sec);
+    match report.backward_ms {
+        Some(backward_ms) => println!("Forward: {:.2} ms | Backward: {:.2} ms", report.forward_ms, backward_ms),
+        None => println!("Forward: {:.2} ms", report.forward_ms),
+    }
+    if let Some(bytes) = report.peak_memory_bytes {
+        println!("Peak memory (host): {:.1} MiB", bytes as f64 / (1024.0 * 1024.0));
+    }
+    println!("\nForward time by module:");
+    for stage in &report.stages {
+        println!("  {:<18} {:>9.3} ms  {:>5.1}%", stage.stage, stage.ms_per_pass, stage.share * 100.0);
+    }
+    
+    if let Some(ref output) = args.output {
+        fs::write(output, serde_json::to_string_pretty(&report)?)
+            .with_context(|| format!("Failed to write bench report: {:?}", output))?;
+        info!("Bench report saved to: {:?}", output);
+    }
+    
+    Ok(())
+}
+
+/// `run_bench` on the backend picked with `--backend`
+struct BenchRun {
+    model: HopeConfig,
+    bench: BenchConfig,
+    device: usize,
+}
+
+impl TrainingTask for BenchRun {
+    type Output = BenchReport;
+
+    fn run<B: AutodiffBackend + DeviceIndex>(self) -> BenchReport {
+        run_bench::<B>(&self.model, &self.bench, &B::device(self.device))
+    }
+}
+
+fn export_command(args: ExportArgs) -> Result<()> {
+    let device = Default::default();
+    let (model, step, config) = load_checkpoint::<CpuBackend>(&args.checkpoint, &device)?;
+    let model = model.eval();
+    match args.format {
+        ExportFormat::Safetensors => {
+            let output = args.output.unwrap_or_else(|| PathBuf::from("model.safetensors"));
+            let config_path = export_safetensors(&model, &config.model, step, &output)?;
+            info!("Exported {:?} (step {}) to {:?} with config {:?}", args.checkpoint, step, output, config_path);
+        }
+        ExportFormat::Onnx => {
+            let output = args.output.unwrap_or_else(|| PathBuf::from("model.onnx"));
+            let seq_len = args.seq_len.unwrap_or(config.model.seq_len);
+            export_onnx(&model, seq_len, &output)?;
+            info!("Exported {:?} (step {}) to {:?} for inputs of {} tokens", args.checkpoint, step, output, seq_len);
+        }
+    }
+    Ok(())
+}
+
+fn prune_command(args: PruneArgs) -> Result<()> {
+    let options = PruneOptions { ffn_ratio: args.ffn_ratio, head_ratio: args.head_ratio, remove: args.remove };
+    if !(0.0..1.0).contains(&options.ffn_ratio) || !(0.0..1.0).contains(&options.head_ratio) {
+        anyhow::bail!("--ffn-ratio and --head-ratio must be within [0, 1)");
+    }
+    let device = Default::default();
+    let (model, step, mut config) = load_checkpoint::<CpuBackend>(&args.checkpoint, &device)?;
+    let corpus_version = read_checkpoint_metadata(&args.checkpoint)?.corpus_version;
+
+    let (pruned, report) = model.prune(&options);
+    config.model = pruned.config().clone();
+    let output_dir = args.output_dir.unwrap_or_else(|| {
+        args.checkpoint.parent().unwrap_or(Path::new(".")).join("pruned")
+    });
+    let stem = args.checkpoint.file_stem().and_then(|stem| stem.to_str()).unwrap_or("checkpoint");
+    let name = format!("{}_pruned", stem);
+    let path = save_model_checkpoint(&pruned, &config, step, corpus_version.as_deref(), &name, &output_dir)?;
+    info!("Pruned {} feed-forward neurons ({}) and zeroed {} attention heads; {} -> {} parameters: {:?}",
+        report.ffn_neurons, if options.remove { "removed" } else { "zeroed" }, report.heads,
+        report.params_before, report.params_after, path);
+
+    if let Some(ref data) = args.data {
+        let tokenizer = args.tokenizer.as_deref();
+        let (before, _) = evaluate_checkpoint(&args.checkpoint, data, tokenizer, Some(path.with_extension("base.eval.json")))?;
+        let (after, _) = evaluate_checkpoint(&path, data, tokenizer, None)?;
+        info!("Perplexity {:.2} -> {:.2} ({:+.2}), loss {:.6} -> {:.6}",
+            before.perplexity, after.perplexity, after.perplexity - before.perplexity, before.loss, after.loss);
+    }
+    Ok(())
+}
+
+fn resize_vocab_command(args: ResizeVocabArgs) -> Result<()> {
+    let device = Default::default();
+    let (model, step, mut config) = load_checkpoint::<CpuBackend>(&args.checkpoint, &device)?;
+    let old_tokenizer = match args.old_tokenizer {
+        Some(ref path) => CharTokenizer::load(path)?,
+        None => load_data_tokenizer(&config)
+            .ok_or_else(|| anyhow::anyhow!("No tokenizer found for the checkpoint; pass --old-tokenizer"))?,
+    };
+    let tokenizer = CharTokenizer::load(&args.tokenizer)?;
+    let vocab_size = args.vocab_size.unwrap_or(config.model.vocab_size.max(tokenizer.vocab_size()));
+    if vocab_size < tokenizer.vocab_size() {
+        anyhow::bail!("--vocab-size {} is smaller than the new tokenizer's vocabulary of {}", vocab_size, tokenizer.vocab_size());
+    }
+
+    let sources: Vec<Option<usize>> = vocab_mapping(&old_tokenizer, &tokenizer, vocab_size)
+        .into_iter()
+        .map(|source| source.filter(|&old| old < config.model.vocab_size))
+        .collect();
+    let kept = sources[..tokenizer.vocab_size()].iter().flatten().count();
+    let resized = model.resize_vocab(&sources);
+    config.model = resized.config().clone();
+    config.data.tokenizer_path = Some(args.tokenizer.clone());
+
+    let output_dir = args.output_dir.unwrap_or_else(|| {
+        args.checkpoint.parent().unwrap_or(Path::new(".")).join("resized")
+    });
+    let stem = args.checkpoint.file_stem().and_then(|stem| stem.to_str()).unwrap_or("checkpoint");
+    let name = format!("{}_resized", stem);
+    // The checkpoint now belongs to the corpus of the new tokenizer
+    let path = save_model_checkpoint(&resized, &config, step, None, &name, &output_dir)?;
+    info!("Resized the vocabulary from {} to {} tokens ({} of the new tokenizer's {} copied, {} new): {:?}",
+        old_tokenizer.vocab_size(), vocab_size, kept, tokenizer.vocab_size(), tokenizer.vocab_size() - kept, path);
+    Ok(())
+}
+
+fn parity_export_command(args: ParityExportArgs) -> Result<()> {
+    let device = Default::default();
+    let (model, step, config) = load_checkpoint::<CpuBackend>(&args.checkpoint, &device)?;
+    let model = model.eval();
+    let tokenizer = match args.tokenizer {
+        Some(ref path) => CharTokenizer::load(path)?,
+        None => load_data_tokenizer(&config)
+            .ok_or_else(|| anyhow::anyhow!("No tokenizer found for the checkpoint; pass --tokenizer"))?,
+    };
+    
+    let tokens = tokenizer.encode(&args.text);
+    let unknown = tokens.iter().filter(|&&token| token == tokenizer.unk_id()).count();
+    if unknown > 0 {
+        warn!("{} character(s) of the text are not in the vocabulary and encode as <UNK>", unknown);
+    }
+    let fixture = export_fixture(&model, &args.checkpoint, step, &args.text, tokens, args.decimals, &device)?;
+    save_fixture(&fixture, &args.output)?;
+    info!("Parity fixture of {} tokens (loss = {:.6}, logits sha256 = {}) saved to: {:?}",
+        fixture.tokens.len(), fixture.loss, fixture.logits_sha256, args.output);
+    
+    Ok(())
+}
+
+fn parity_verify_command(args: ParityVerifyArgs) -> Result<()> {
+    let fixture = load_fixture(&args.fixture)?;
+    let checkpoint = args.checkpoint.unwrap_or_else(|| fixture.checkpoint.clone());
+    let device = Default::default();
+    let (model, _, _) = load_checkpoint::<CpuBackend>(&checkpoint, &device)?;
+    let model = model.eval();
+    
+    let report = verify_fixture(&model, &fixture, &device)?;
+    info!("Loss diff = {:.3e} | Max logit diff = {:.3e} | Argmax mismatches = {}/{} | Logits hash {}",
+        report.loss_diff, report.max_abs_diff, report.argmax_mismatches, fixture.argmax.len(),
+        if report.hash_matches { "matches" } else { "differs" });
+    if !report.passed(args.tolerance) {
+        anyhow::bail!("{:?} does not reproduce fixture {:?} within {:e}", checkpoint, args.fixture, args.tolerance);
+    }
+    info!("Parity check passed");
+    
+    Ok(())
+}
+
+fn contamination_command(args: ContaminationArgs) -> Result<()> {
+    if args.ngram == 0 {
+        anyhow::bail!("--ngram must be > 0");
+    }
+    let mut checker = ContaminationChecker::new(args.ngram);
+    for eval in &args.eval {
+        let set = eval.to_string_lossy();
+        for_each_text(eval, |name, text| checker.add_eval_document(&set, &name.to_string_lossy(), text))?;
+    }
+    
+    info!("Scanning corpus {:?} for {}-grams of {} evaluation set(s)", args.corpus, args.ngram, args.eval.len());
+    let mut corpus_documents = 0;
+    for_each_text(&args.corpus, |_, text| {
+        checker.scan_corpus_text(text);
+        corpus_documents += 1;
+    })?;
+    info!("Scanned {} corpus documents", corpus_documents);
+    
+    let reports = checker.reports(20);
+    for report in &reports {
+        if report.ngrams == 0 {
+            warn!("{}: no {}-grams (no documents, or all shorter than {} words)", report.name, args.ngram, args.ngram);
+            continue;
+        }
+        info!("{}: {:.2}% of {} {}-grams occur in the corpus; {}/{} documents contaminated",
+            report.name, report.contamination, report.ngrams, args.ngram,
+            report.contaminated_documents, report.documents);
+        for document in report.worst_documents.iter().take(5) {
+            info!("  {:.2}% {}", document.contamination, document.name);
+        }
+    }
+    
+    let report = serde_json::json!({
+        "corpus": args.corpus,
+        "ngram": args.ngram,
+        "corpus_documents": corpus_documents,
+        "eval_sets": reports,
+    });
+    fs::write(&args.output, serde_json::to_string_pretty(&report)?)
+        .with_context(|| format!("Failed to write contamination report: {:?}", args.output))?;
+    info!("Contamination report saved to: {:?}", args.output);
+    
+    Ok(())
+}
+
+fn corpus_sample_command(args: CorpusSampleArgs) -> Result<()> {
+    let seed = args.seed.unwrap_or_else(rand::random);
+    let (passages, total) = sample_passages(&args.data, args.n, seed)?;
+    if total == 0 {
+        anyhow::bail!("No text found in {:?}", args.data);
+    }
+    
+    // Echo the seed so the same sample can be drawn again with --seed
+    println!("Seed: {}", seed);
+    println!("Sampled {} of {} passages from {:?}", passages.len(), total, args.data);
+    for (i, passage) in passages.iter().enumerate() {
+        let source = passage.source.as_ref().map(|source| format!(" ({})", source)).unwrap_or_default();
+        println!("\n[{}/{}] {}{}, line {}", i + 1, passages.len(), passage.document, source, passage.line);
+        let chars = passage.text.chars().count();
+        if chars > args.max_chars {
+            let text: String = passage.text.chars().take(args.max_chars).collect();
+            println!("{}... [{} more characters]", text, chars - args.max_chars);
+        } else {
+            println!("{}", passage.text);
+        }
+    }
+    
+    Ok(())
+}
+
+fn runs_compare_command(args: RunsCompareArgs) -> Result<()> {
+    let run_a = load_run(&args.run_a)?;
+    let run_b = load_run(&args.run_b)?;
+    println!("A: {:?}", run_a.manifest_path);
+    println!("B: {:?}", run_b.manifest_path);
+    
+    let differences = diff_manifests(&run_a.manifest, &run_b.manifest);
+    if differences.is_empty() {
+        println!("\nManifests match (config, data, corpus version, seed)");
+    } else {
+        println!("\nManifest differences:");
+        let show = |value: &Option<serde_json::Value>| value.as_ref().map_or("-".to_string(), |v| v.to_string());
+        for difference in &differences {
+            println!("  {}: {} -> {}", difference.path, show(&difference.a), show(&difference.b));
+        }
+    }
+    
+    if run_a.metrics.is_empty() || run_b.metrics.is_empty() {
+        println!("\nNo metrics to compare; runs need training.metrics_format");
+        return Ok(());
+    }
+    let aligned = align_series(&run_a.metrics, &run_b.metrics);
+    if aligned.is_empty() {
+        println!("\nThe runs' metrics cover no common range of tokens seen");
+        return Ok(());
+    }
+    
+    // Evenly spaced rows, always including the last common point
+    println!("\n{:>14} {:>10} {:>10} {:>10}", "tokens", "loss A", "loss B", "B - A");
+    let rows = args.rows.clamp(1, aligned.len());
+    for i in 0..rows {
+        let point = aligned[(i + 1) * aligned.len() / rows - 1];
+        println!("{:>14} {:>10.4} {:>10.4} {:>+10.4}", point.tokens, point.a, point.b, point.b - point.a);
+    }
+    
+    let divergences = divergence_points(&aligned, args.threshold);
+    if divergences.is_empty() {
+        println!("\nNo divergence: losses stay within {:.1}% of each other", args.threshold * 100.0);
+    } else {
+        println!("\nDivergence points (threshold {:.1}%):", args.threshold * 100.0);
+        for divergence in divergences {
+            let point = divergence.point;
+            let event = match divergence.kind {
+                DivergenceKind::GapOpens => "gap opens",
+                DivergenceKind::GapCloses => "gap closes",
+                DivergenceKind::Crossover if point.a < point.b => "A takes the lead",
+                DivergenceKind::Crossover => "B takes the lead",
+            };
+            println!("  {:>14} tokens: {} (A {:.4}, B {:.4})", point.tokens, event, point.a, point.b);
+        }
+    }
+    
+    Ok(())
+}
+
+/// Read a training config file and validate its `training` section before
+/// any model or data is built
+fn load_train_config(path: &Path) -> Result<TrainConfig> {
+    let config_str = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file: {:?}", path))?;
+    let train_config: TrainConfig = serde_json::from_str(&config_str)
+        .with_context(|| "Failed to parse config JSON")?;
+    train_config.training.validate();
+    train_config.model.self_modify.warn_legacy_meta_lr();
+    Ok(train_config)
+}
+
+fn lora_train_command(args: LoraTrainArgs) -> Result<()> {
+    let train_config = load_train_config(&args.config)?;
+    let lora = LoraConfig { rank: args.rank, alpha: args.alpha, targets: args.targets };
+
+    info!("Using backend: {:?}", args.backend);
+    let checkpointing = train_config.training.activation_checkpointing;
+    run_training(args.backend, checkpointing, LoraRun {
+        checkpoint: args.checkpoint,
+        train_config,
+        lora,
+        device: args.device,
+    })
+}
+
+/// `lora_train` on the backend picked with `--backend`
+struct LoraRun {
+    checkpoint: PathBuf,
+    train_config: TrainConfig,
+    lora: LoraConfig,
+    device: usize,
+}
+
+impl TrainingTask for LoraRun {
+    type Output = Result<()>;
+
+    fn run<B: AutodiffBackend + DeviceIndex>(self) -> Result<()> {
+        let device = B::device(self.device);
+        lora_train::<B>(&self.checkpoint, self.train_config, &self.lora, &device)
+    }
+}
+
+/// Train adapters for `lora` on the frozen `checkpoint`, saving them every
+/// `save_every` steps and at the end
+fn lora_train<B: AutodiffBackend>(
+    checkpoint: &Path,
+    mut train_config: TrainConfig,
+    lora: &LoraConfig,
+    device: &B::Device,
+) -> Result<()> {
+    let (model, base_step, base_config) = load_checkpoint::<B>(checkpoint, device)
+        .with_context(|| "Failed to load checkpoint")?;
+    // The adapters fit the checkpoint's architecture, whatever the config says
+    train_config.model = base_config.model;
+    let training = &train_config.training;
+
+    let mut trainer = LoraTrainer::new(model, lora, training, device);
+    info!("LoRA rank {} (alpha {}) on {} weight(s) of {:?} (step {}): {} trainable parameters",
+        lora.rank, lora.alpha, trainer.adapters().paths().len(), checkpoint, base_step, trainer.adapters().num_params());
+    if trainer.adapters().paths().is_empty() {
+        anyhow::bail!("No weights under {:?} to adapt", lora.targets);
+    }
+
+    // Recorded in the adapter metadata, so `lora merge` finds it from any directory
+    let checkpoint = checkpoint.canonicalize().unwrap_or_else(|_| checkpoint.to_path_buf());
+    let mut loader = create_data_loader::<B>(&train_config, device)?;
+    let scheduler = LrScheduler::new(training.learning_rate, &training.lr_schedule);
+    let num_steps = training.num_steps;
+    let mut loss_sum = 0.0;
+    let mut loss_count = 0;
+    for step in 0..num_steps {
+        let batch = match loader.next_batch()? {
+            Some(batch) => batch,
+            None => {
+                loader.reset();
+                loader.next_batch()?.ok_or_else(|| anyhow::anyhow!("No training data"))?
+            }
+        };
+        trainer.set_learning_rate(scheduler.learning_rate(step));
+        let loss = trainer.train_step(batch);
+        if !loss.is_finite() {
+            anyhow::bail!("Non-finite loss at LoRA step {}", step + 1);
+        }
+        loss_sum += loss;
+        loss_count += 1;
+
+        if (step + 1) % training.log_every.max(1) == 0 {
+            info!("LoRA step {}/{}: Loss = {:.6} (avg: {:.6})", step + 1, num_steps, loss, loss_sum / loss_count as f32);
+            loss_sum = 0.0;
+            loss_count = 0;
+        }
+        if training.save_every > 0 && (step + 1) % training.save_every == 0 && step + 1 < num_steps {
+            let path = save_lora_adapters(trainer.adapters(), lora, &checkpoint, step + 1, &training.checkpoint_dir)?;
+            info!("Adapters saved: {:?}", path);
+        }
+    }
+
+    let path = save_lora_adapters(trainer.adapters(), lora, &checkpoint, num_steps, &training.checkpoint_dir)?;
+    info!("Final adapters saved: {:?} (merge with `lora merge --adapters {}`)", path, path.display());
+    Ok(())
 }
 
-#[derive(Debug, Args)]
-struct TrainArgs {
-    /// Path to configuration JSON file
-    #[arg(long)]
-    config: PathBuf,
+fn lora_merge_command(args: LoraMergeArgs) -> Result<()> {
+    let device = Default::default();
+    let data = read_lora_metadata(&args.adapters)?;
+    let base = args.base.unwrap_or_else(|| data.base_checkpoint.clone());
+    let (model, base_step, config) = load_checkpoint::<CpuBackend>(&base, &device)?;
+    let (adapters, _) = load_lora_adapters::<CpuBackend>(&args.adapters, &model, &device)?;
+
+    let merged = adapters.apply(&model);
+    let output_dir = args.output_dir.unwrap_or_else(|| {
+        args.adapters.parent().unwrap_or(Path::new(".")).join("merged")
+    });
+    let name = format!("lora_merged_step_{}", data.step);
+    let path = save_model_checkpoint(&merged, &config, base_step + data.step, None, &name, &output_dir)?;
+    info!("Merged {} adapter(s) (rank {}) into {:?}: {:?}", adapters.paths().len(), data.rank, base, path);
+    Ok(())
 }
 
-#[derive(Debug, Args)]
-struct EvalArgs {
-    /// Path to model checkpoint
-    #[arg(long)]
-    checkpoint: PathBuf,
-    /// Path to evaluation data
-    #[arg(long)]
-    data: PathBuf,
+fn lr_find_command(args: LrFindArgs) -> Result<()> {
+    let train_config = load_train_config(&args.config)?;
+    let output = args.output.unwrap_or_else(|| train_config.training.checkpoint_dir.join("lr_find.csv"));
+    
+    info!("Using backend: {:?}", args.backend);
+    let checkpointing = train_config.training.activation_checkpointing;
+    run_training(args.backend, checkpointing, LrFindRun {
+        train_config,
+        device: args.device,
+        sweep: args.sweep.sweep(),
+        output,
+    })
 }
 
-fn main() -> Result<()> {
-    // Initialize tracing
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| EnvFilter::new("info")),
-        )
-        .init();
+/// `lr-find` on the backend picked with `--backend`
+struct LrFindRun {
+    train_config: TrainConfig,
+    device: usize,
+    sweep: LrSweep,
+    output: PathBuf,
+}
 
-    let cli = Cli::parse();
+impl TrainingTask for LrFindRun {
+    type Output = Result<()>;
 
-    match cli.command {
-        Commands::Train(args) => train_command(args),
-        Commands::Eval(args) => {
-            info!("Evaluation not yet implemented: {:?}", args);
-            Ok(())
+    fn run<B: AutodiffBackend + DeviceIndex>(self) -> Result<()> {
+        let device = B::device(self.device);
+        if let Some(seed) = self.train_config.training.seed {
+            B::seed(&device, seed);
+        }
+        let model = match self.train_config.training.resume_from {
+            Some(ref checkpoint_path) => load_checkpoint::<B>(checkpoint_path, &device)?.0,
+            None => HopeModel::<B>::new(self.train_config.model.clone(), &device),
+        };
+        let mut trainer = HopeTrainer::new(model, self.train_config.clone(), &device);
+        if let Some(ref checkpoint_path) = self.train_config.training.resume_from {
+            trainer.restore_optimizer(checkpoint_path, &device)?;
         }
+        
+        if lr_range_test(&mut trainer, &self.train_config, &self.sweep, &self.output, &device)?.is_none() {
+            println!("No suggestion: the loss did not fall anywhere in the sweep; try a wider range");
+        }
+        Ok(())
+    }
+}
+
+fn preprocess_command(args: PreprocessArgs) -> Result<()> {
+    let data = match args.config {
+        Some(ref path) => {
+            info!("Loading configuration from: {:?}", path);
+            let config_str = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read config file: {:?}", path))?;
+            let train_config: TrainConfig = serde_json::from_str(&config_str)
+                .with_context(|| "Failed to parse config JSON")?;
+            train_config.data
+        }
+        None => DataConfig::default(),
+    };
+    
+    // Flags override the config's settings
+    let mut config = data.preprocess;
+    if let Some(preserve_structure) = args.preserve_structure {
+        config.preserve_structure = preserve_structure;
     }
+    config.enable_ocr |= args.enable_ocr;
+    if let Some(build_vocab) = args.build_vocab {
+        config.build_vocab = build_vocab;
+    }
+    config.scrub_pii |= args.scrub_pii;
+    config.boilerplate_threshold = args.boilerplate_threshold.or(config.boilerplate_threshold);
+    if let Some(stages) = args.cleaning_stages {
+        config.cleaning_stages = stages;
+    }
+    config.full_rebuild |= args.full_rebuild;
+    config.fetch = args.fetch.or(config.fetch);
+    config.fetch_concurrency = args.fetch_concurrency.unwrap_or(config.fetch_concurrency);
+    config.fetch_retries = args.fetch_retries.unwrap_or(config.fetch_retries);
+    config.fetch_rate = args.fetch_rate.or(config.fetch_rate);
+    
+    let input = args.input.or_else(|| config.input.clone())
+        .ok_or_else(|| anyhow::anyhow!("No input directory; pass --input or set data.preprocess.input"))?;
+    let output = args.output.or(data.data_path)
+        .ok_or_else(|| anyhow::anyhow!("No output directory; pass --output or set data.data_path"))?;
+    
+    info!("Starting book preprocessing");
+    info!("Input directory: {:?}", input);
+    info!("Output directory: {:?}", output);
+    let metadata = preprocess_corpus(&input, &output, &config, data.tokenizer_path.as_deref())?;
+    
+    info!("Preprocessing complete!");
+    info!("Summary:");
+    info!("  - Documents: {}", metadata.total_documents);
+    info!("  - Characters: {}", metadata.total_characters);
+    info!("  - Tokens: {}", metadata.total_tokens);
+    info!("  - Vocabulary size: {}", metadata.vocab_size);
+    info!("  - Corpus version: {}", metadata.corpus_version);
+    if let Some(ref pii) = metadata.pii_redactions {
+        info!("  - PII redactions: {} (emails: {}, phones: {}, IDs: {}, addresses: {})",
+            pii.total(),
+            pii.emails,
+            pii.phone_numbers,
+            pii.id_numbers,
+            pii.addresses);
+    }
+    info!("  - Cleaning stages:");
+    for stage in &metadata.cleaning.stages {
+        info!("      {:<14} {} -> {} chars (-{})",
+            stage.stage.name(), stage.chars_before, stage.chars_after, stage.removed());
+    }
+    
+    Ok(())
 }
 
 fn train_command(args: TrainArgs) -> Result<()> {
     info!("Loading configuration from: {:?}", args.config);
     
-    let config_str = fs::read_to_string(&args.config)
-        .with_context(|| format!("Failed to read config file: {:?}", args.config))?;
+    let mut train_config = load_train_config(&args.config)?;
+    if args.follow {
+        train_config.data.follow = true;
+    }
+    if let Some(precision) = args.checkpoint_precision {
+        train_config.training.checkpoint_precision = precision.into();
+    }
+    info!("Configuration loaded successfully");
+
+    let lr_sweep = args.find_lr.then(|| args.sweep.sweep());
+    start_training(train_config, args.backend, args.device, lr_sweep)
+}
+
+/// Preprocess `data.preprocess.input` into `data.data_path` unless the
+/// pipeline manifest shows the corpus is up to date, train, evaluate the
+/// best (or final) checkpoint and export it with the tokenizer. The stages
+/// share `<checkpoint_dir>/pipeline.manifest.json`, also copied to the export.
+fn pipeline_command(args: PipelineArgs) -> Result<()> {
+    info!("Loading configuration from: {:?}", args.config);
     
-    let train_config: TrainConfig = serde_json::from_str(&config_str)
-        .with_context(|| "Failed to parse config JSON")?;
+    let mut train_config = load_train_config(&args.config)?;
+    let pipeline = train_config.pipeline.clone();
+    let checkpoint_dir = train_config.training.checkpoint_dir.clone();
+    let data_path = train_config.data.data_path.clone()
+        .ok_or_else(|| anyhow::anyhow!("pipeline needs data.data_path (where the corpus is preprocessed to)"))?;
+    let mut manifest = read_pipeline_manifest(&checkpoint_dir).unwrap_or_default();
+    
+    // Preprocess when the sources or settings changed since the last run
+    let preprocess = &train_config.data.preprocess;
+    match preprocess.input.clone() {
+        Some(input) => {
+            let fingerprint = source_fingerprint(&input, preprocess)?;
+            let corpus_version = read_corpus_version(&data_path)?;
+            let up_to_date = !args.force_preprocess
+                && preprocess.fetch.is_none()
+                && corpus_version.is_some()
+                && manifest.source_fingerprint.as_deref() == Some(fingerprint.as_str())
+                && manifest.corpus_version == corpus_version;
+            if up_to_date {
+                info!("[1/4] Corpus {:?} is up to date with {:?}; skipping preprocessing", data_path, input);
+                manifest.source_fingerprint = Some(fingerprint);
+            } else {
+                info!("[1/4] Preprocessing {:?} into {:?}", input, data_path);
+                let metadata = preprocess_corpus(&input, &data_path, preprocess, train_config.data.tokenizer_path.as_deref())?;
+                info!("Corpus {}: {} documents, {} tokens, vocabulary of {}",
+                    metadata.corpus_version, metadata.total_documents, metadata.total_tokens, metadata.vocab_size);
+                // Fetched articles are part of the input from now on
+                manifest.source_fingerprint = Some(source_fingerprint(&input, preprocess)?);
+            }
+            manifest.input = Some(input);
+        }
+        None => info!("[1/4] No data.preprocess.input; training on {:?} as it is", data_path),
+    }
+    manifest.data_path = Some(data_path.clone());
+    manifest.corpus_version = read_corpus_version(&data_path)?;
+    write_pipeline_manifest(&mut manifest, &checkpoint_dir)?;
+    
+    // Train on the corpus, with a model that covers its vocabulary
+    if matches!(train_config.data.data_type, DataType::Random) {
+        train_config.data.data_type = DataType::Books;
+    }
+    train_config.training.use_random_data = false;
+    let tokenizer = load_data_tokenizer(&train_config);
+    if let Some(ref tokenizer) = tokenizer {
+        if tokenizer.vocab_size() > train_config.model.vocab_size {
+            info!("model.vocab_size raised from {} to the corpus vocabulary's {}",
+                train_config.model.vocab_size, tokenizer.vocab_size());
+            train_config.model.vocab_size = tokenizer.vocab_size();
+        }
+    }
+    if train_config.training.val_data.is_none() {
+        train_config.training.val_data = pipeline.eval_data.clone();
+    }
+    let eval_data = pipeline.eval_data.clone()
+        .or_else(|| train_config.training.val_data.clone())
+        .unwrap_or_else(|| data_path.clone());
+    
+    info!("[2/4] Training for {} steps into {:?}", train_config.training.num_steps, checkpoint_dir);
+    let started_at = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    start_training(train_config.clone(), args.backend, args.device, None)?;
+    
+    // Checkpoints left in the directory by earlier runs are not candidates
+    let best = checkpoint_dir.join(BEST_CHECKPOINT_NAME).with_extension("json");
+    let best = (pipeline.export_best && best.exists())
+        .then(|| read_checkpoint_metadata(&best).map(|data| (best, data.timestamp)))
+        .transpose()?
+        .filter(|(_, timestamp)| *timestamp >= started_at)
+        .map(|(path, _)| path);
+    let checkpoint = match best {
+        Some(path) => path,
+        None => list_checkpoints(&checkpoint_dir)?
+            .into_iter()
+            .rfind(|(_, _, timestamp)| *timestamp >= started_at)
+            .map(|(path, _, _)| path)
+            .ok_or_else(|| anyhow::anyhow!("Training wrote no checkpoint to {:?}", checkpoint_dir))?,
+    };
+    let step = read_checkpoint_metadata(&checkpoint)?.step;
+    manifest.checkpoint = Some(checkpoint.clone());
+    manifest.step = Some(step);
+    
+    info!("[3/4] Evaluating {:?} (step {}) on {:?}", checkpoint, step, eval_data);
+    let tokenizer_path = train_config.data.tokenizer_path.clone()
+        .or_else(|| Some(data_path.join("vocab.json")))
+        .filter(|path| path.exists());
+    let (metrics, eval_path) = evaluate_checkpoint(&checkpoint, &eval_data, tokenizer_path.as_deref(), None)?;
+    manifest.eval_data = Some(eval_data);
+    manifest.eval_loss = Some(metrics.loss);
+    manifest.eval_perplexity = Some(metrics.perplexity);
+    write_pipeline_manifest(&mut manifest, &checkpoint_dir)?;
+    
+    // Weights without optimizer state, next to what is needed to use them
+    let export_dir = pipeline.export_dir.unwrap_or_else(|| checkpoint_dir.join("export"));
+    info!("[4/4] Exporting to {:?}", export_dir);
+    let device = Default::default();
+    let (model, step, config) = load_checkpoint::<CpuBackend>(&checkpoint, &device)?;
+    let model_path = save_model_checkpoint(
+        &model,
+        &config,
+        step,
+        manifest.corpus_version.as_deref(),
+        "model",
+        &export_dir,
+    )?;
+    if let Some(ref path) = tokenizer_path {
+        fs::copy(path, export_dir.join("vocab.json"))
+            .with_context(|| format!("Failed to copy tokenizer {:?}", path))?;
+    }
+    fs::copy(&eval_path, export_dir.join("eval.json"))
+        .with_context(|| format!("Failed to copy eval results {:?}", eval_path))?;
+    manifest.export_dir = Some(export_dir.clone());
+    write_pipeline_manifest(&mut manifest, &checkpoint_dir)?;
+    write_pipeline_manifest(&mut manifest, &export_dir)?;
+    
+    info!("Pipeline complete!");
+    info!("  - Model: {:?} (step {})", model_path, step);
+    info!("  - Eval loss = {:.6} | Perplexity = {:.2} on {:?}", metrics.loss, metrics.perplexity,
+        manifest.eval_data.as_deref().unwrap_or(&data_path));
+    
+    Ok(())
+}
 
-    info!("Configuration loaded successfully");
+/// Train on new data starting from a checkpoint's weights, with the
+/// `--freeze` modules kept as they are
+fn finetune_command(args: FinetuneArgs) -> Result<()> {
+    info!("Loading configuration from: {:?}", args.config);
+
+    let mut train_config = load_train_config(&args.config)?;
+    if train_config.training.resume_from.is_some() {
+        anyhow::bail!("finetune starts a new run from --checkpoint; remove training.resume_from");
+    }
+
+    train_config.training.init_from = Some(args.checkpoint);
+    for prefix in args.freeze {
+        if !train_config.training.freeze.contains(&prefix) {
+            train_config.training.freeze.push(prefix);
+        }
+    }
+    start_training(train_config, args.backend, args.device, None)
+}
+
+/// Log the configuration and run `train` on the `backend_kind` backend
+fn start_training(
+    train_config: TrainConfig,
+    backend_kind: BackendKind,
+    device: usize,
+    lr_sweep: Option<LrSweep>,
+) -> Result<()> {
     info!("Model config: hidden_size={}, vocab_size={}, seq_len={}", 
         train_config.model.hidden_size,
         train_config.model.vocab_size,
@@ -93,13 +1839,104 @@ fn train_command(args: TrainArgs) -> Result<()> {
         train_config.training.num_steps,
         train_config.training.learning_rate);
 
-    // Initialize device (CPU for now)
-    let device = Default::default();
+    info!("Using backend: {:?}", backend_kind);
+    let checkpointing = train_config.training.activation_checkpointing;
+    if checkpointing {
+        info!("Activation checkpointing enabled: memory-bound activations are recomputed in the backward pass");
+    }
+    // One device per data-parallel replica, starting at --device
+    let replicas = train_config.training.data_parallel.max(1);
+    if replicas > 1 {
+        info!("Data-parallel training across {} replicas", replicas);
+    }
+    run_training(backend_kind, checkpointing, TrainRun { train_config, first_device: device, replicas, lr_sweep })
+}
+
+/// `train` on the backend picked with `--backend`
+struct TrainRun {
+    train_config: TrainConfig,
+    first_device: usize,
+    replicas: usize,
+    lr_sweep: Option<LrSweep>,
+}
+
+impl TrainingTask for TrainRun {
+    type Output = Result<()>;
 
+    fn run<B: AutodiffBackend + DeviceIndex>(self) -> Result<()> {
+        train::<B>(self.train_config, devices::<B>(self.first_device, self.replicas), self.lr_sweep, Callbacks::default())
+    }
+}
+
+/// Train on `devices[0]`; more devices run data-parallel replicas. With
+/// `lr_sweep`, an LR range test picks the learning rate first. `callbacks`
+/// are invoked at every step, checkpoint, validation and error.
+fn train<B: AutodiffBackend + DeviceIndex>(
+    train_config: TrainConfig,
+    devices: Vec<B::Device>,
+    lr_sweep: Option<LrSweep>,
+    callbacks: Callbacks,
+) -> Result<()> {
+    let run_start = std::time::Instant::now();
+    let device = devices[0].clone();
+    if devices.len() > 1 {
+        if train_config.training.stateful.enabled {
+            anyhow::bail!("training.data_parallel cannot be combined with training.stateful");
+        }
+        if train_config.training.batch_size < devices.len() {
+            anyhow::bail!(
+                "training.batch_size ({}) must be at least training.data_parallel ({})",
+                train_config.training.batch_size, devices.len()
+            );
+        }
+    }
+    let precision = train_config.training.precision;
+    if precision != Precision::F32 {
+        if devices.len() > 1 {
+            anyhow::bail!("training.data_parallel requires f32 precision");
+        }
+        if train_config.training.stateful.enabled {
+            anyhow::bail!("training.stateful requires f32 precision");
+        }
+        if B::half_precision::<B>(precision, &train_config.model, &device).is_none() {
+            anyhow::bail!(
+                "training.precision {:?} is not available on this backend (f16: wgpu or tch, bf16: tch)",
+                precision
+            );
+        }
+    }
+    
+    // Seed before anything draws random numbers (model initialization, dropout)
+    if let Some(seed) = train_config.training.seed {
+        info!("Seeding RNG with {}", seed);
+        B::seed(&device, seed);
+    }
+    // The backend RNG (dropout) is reseeded from this at every checkpoint, so
+    // a resumed run can continue the same random stream
+    let mut run_seed = train_config.training.seed.unwrap_or_else(rand::random);
+    
+    // Version of the preprocessed corpus, if the data directory records one
+    let corpus_version = match train_config.data.data_path {
+        Some(ref data_path) => read_corpus_version(data_path)?,
+        None => None,
+    };
+    if let Some(ref version) = corpus_version {
+        info!("Corpus version: {}", version);
+    }
+    
     // Check if we should resume from a checkpoint
     let (model, start_step) = if let Some(ref checkpoint_path) = train_config.training.resume_from {
         info!("Resuming training from checkpoint: {:?}", checkpoint_path);
-        let (loaded_model, step, loaded_config) = load_checkpoint::<Backend>(checkpoint_path, &device)
+        
+        // Refuse to silently continue on different data
+        let checkpoint_data = read_checkpoint_metadata(checkpoint_path)?;
+        verify_corpus_version(
+            checkpoint_data.corpus_version.as_deref(),
+            corpus_version.as_deref(),
+            train_config.training.corpus_mismatch,
+        )?;
+        
+        let (loaded_model, step, loaded_config) = load_checkpoint::<B>(checkpoint_path, &device)
             .with_context(|| "Failed to load checkpoint")?;
         
         // Verify configs are compatible (optional, could be relaxed)
@@ -108,8 +1945,27 @@ fn train_command(args: TrainArgs) -> Result<()> {
             anyhow::bail!("Checkpoint model config doesn't match current config");
         }
         
+        match checkpoint_data.rng_seed {
+            Some(seed) => {
+                if train_config.training.seed.is_some_and(|configured| configured != seed) {
+                    warn!("training.seed differs from the checkpoint's run seed {}; continuing the checkpoint's random stream", seed);
+                }
+                run_seed = seed;
+            }
+            None => info!("Checkpoint has no RNG seed; random numbers will differ from the original run"),
+        }
+        
         info!("Resumed from step {}", step);
         (loaded_model, step)
+    } else if let Some(ref checkpoint_path) = train_config.training.init_from {
+        let (loaded_model, step, loaded_config) = load_checkpoint::<B>(checkpoint_path, &device)
+            .with_context(|| "Failed to load checkpoint")?;
+        if loaded_config.model.hidden_size != train_config.model.hidden_size ||
+           loaded_config.model.vocab_size != train_config.model.vocab_size {
+            anyhow::bail!("Checkpoint model config doesn't match current config");
+        }
+        info!("Fine-tuning from the weights of {:?} (step {}); starting a new run at step 0", checkpoint_path, step);
+        (loaded_model, 0)
     } else {
         // List available checkpoints for information
         if let Ok(checkpoints) = list_checkpoints(&train_config.training.checkpoint_dir) {
@@ -132,55 +1988,349 @@ fn train_command(args: TrainArgs) -> Result<()> {
         info!("  - Number of layers: {}", train_config.model.num_layers);
         
         let start_time = std::time::Instant::now();
-        let model = HopeModel::<Backend>::new(train_config.model.clone(), &device);
+        let mut model = HopeModel::<B>::new(train_config.model.clone(), &device);
         let init_duration = start_time.elapsed();
         info!("Model initialized successfully in {:.2}s", init_duration.as_secs_f64());
+        info!("  - Forward FLOPs per token: {}", format_flops(model.flops_per_token().total));
+
+        if let Some(ref pretrained) = train_config.training.init_from_pretrained {
+            let (imported, report) = import_pretrained(model, pretrained, train_config.training.pretrained_blocks)?;
+            info!("Warm-started {} parameter(s) from {:?}", report.loaded.len(), pretrained);
+            for (target, reason) in &report.skipped {
+                warn!("  Kept the initialization of {}: {}", target, reason);
+            }
+            model = imported;
+        }
         
         (model, 0)
     };
 
+    write_run_manifest(
+        &RunManifest::new(&train_config, start_step, corpus_version.clone(), run_seed),
+        &train_config.training.checkpoint_dir,
+    )?;
+    
     // Create trainer
     info!("Creating trainer...");
-    let mut trainer = HopeTrainer::new(model, train_config.clone(), &device);
+    let loss_weights = if train_config.training.loss_weights.is_enabled() {
+        let Some(tokenizer) = load_data_tokenizer(&train_config) else {
+            anyhow::bail!("training.loss_weights needs the data's tokenizer (data.tokenizer_path or vocab.json)");
+        };
+        info!("Weighting the loss of {} token string(s), padding at {}",
+            train_config.training.loss_weights.tokens.len(),
+            train_config.training.loss_weights.pad);
+        Some(TokenLossWeights::new(&train_config.training.loss_weights, &tokenizer))
+    } else {
+        None
+    };
+    match train_config.training.robust_loss.kind {
+        RobustLossKind::None => {}
+        RobustLossKind::Clipped => info!("Robust loss: per-token loss clipped at {}",
+            train_config.training.robust_loss.max_loss),
+        RobustLossKind::Generalized => info!("Robust loss: generalized cross-entropy with q = {}",
+            train_config.training.robust_loss.q),
+    }
+    if train_config.training.profile {
+        info!("Profiling forward stages; the report is written when training ends");
+    }
+    let create_trainer = |model: HopeModel<B>, checkpoint: Option<&PathBuf>, step: usize| -> Result<HopeTrainer<B>> {
+        let mut trainer = HopeTrainer::new(model, train_config.clone(), &device).with_start_step(step);
+        if devices.len() > 1 {
+            trainer = trainer.with_data_parallel(devices.clone());
+        }
+        if let Some(compute) = B::half_precision::<B>(precision, &train_config.model, &device) {
+            trainer = trainer.with_half_precision(compute);
+        }
+        if let Some(ref weights) = loss_weights {
+            trainer = trainer.with_loss_weights(weights.clone());
+        }
+        if let Some(checkpoint_path) = checkpoint {
+            trainer.restore_optimizer(checkpoint_path, &device)?;
+        }
+        Ok(trainer)
+    };
+    let mut trainer = create_trainer(model, train_config.training.resume_from.as_ref(), start_step)?
+        .with_callbacks(callbacks);
     info!("Trainer created");
 
     // Training loop
-    info!("Starting training for {} steps...", train_config.training.num_steps);
+    let num_steps = train_config.steps_to_run(start_step);
+    match train_config.training.max_tokens {
+        Some(budget) => info!("Starting training for {} steps (token budget: {} tokens, {} per step)...",
+            num_steps, budget, train_config.tokens_per_step()),
+        None => info!("Starting training for {} steps...", num_steps),
+    }
+    let time_budget = train_config.time_budget();
+    if let Some(budget) = time_budget {
+        info!("  - Time budget: {:.2} min (stops early and saves a resumable checkpoint)", budget.as_secs_f64() / 60.0);
+    }
     info!("  - Batch size: {}", train_config.training.batch_size);
-    info!("  - Learning rate: {}", train_config.training.learning_rate);
+    
+    // LR range test on its own pass over the data; the trainer restores its weights afterwards
+    let mut base_lr = train_config.training.learning_rate;
+    if let Some(ref sweep) = lr_sweep {
+        let csv_path = train_config.training.checkpoint_dir.join("lr_find.csv");
+        match lr_range_test(&mut trainer, &train_config, sweep, &csv_path, &device)? {
+            Some(lr) => {
+                info!("Training with the suggested learning rate {:.3e} instead of {}", lr, base_lr);
+                base_lr = lr;
+            }
+            None => warn!("LR range test found no falling loss; keeping learning_rate {}", base_lr),
+        }
+        // Same random stream as a run without the range test
+        B::seed(&device, step_rng_seed(run_seed, start_step));
+    }
+    info!("  - Learning rate: {}", base_lr);
+    let lr_schedule = &train_config.training.lr_schedule;
+    if lr_schedule.kind == LrScheduleKind::CosineRestarts {
+        info!("  - LR schedule: cosine with warm restarts (first cycle {} steps, x{} per cycle, min ratio {})",
+            lr_schedule.cycle_steps, lr_schedule.cycle_mult, lr_schedule.min_lr_ratio);
+    }
+    let scheduler = LrScheduler::new(base_lr, lr_schedule);
+    info!("  - Optimizer: {:?}", train_config.training.optimizer);
+    if precision != Precision::F32 {
+        info!("  - Precision: {:?} (f32 master weights, initial loss scale {})",
+            precision, train_config.training.loss_scale);
+    }
     info!("  - Logging every {} steps", train_config.training.log_every);
     info!("  - Checkpoint directory: {:?}", train_config.training.checkpoint_dir);
     info!("  - Save checkpoint every {} steps", train_config.training.save_every);
+    if train_config.training.stateful.enabled {
+        info!("  - Stateful carry (reset every {} batches)", train_config.training.stateful.reset_every);
+    }
+    
+    // Training data
+    let mut data_loader = create_data_loader::<B>(&train_config, &device)?;
+    // Passes over the data completed so far; continue where the checkpoint left off
+    let mut epoch = 0;
+    // Documents are reshuffled between epochs from the run seed, so a resumed
+    // run sees the same order as the original
+    let shuffle_seed = train_config.data.shuffle_documents.then_some(run_seed);
+    if let Some(ref checkpoint_path) = train_config.training.resume_from {
+        match read_checkpoint_metadata(checkpoint_path)?.loader_state {
+            Some(state) if data_loader.position().is_some() => {
+                data_loader.start_epoch(state.epoch, shuffle_seed);
+                data_loader.seek(state.position);
+                epoch = state.epoch;
+                info!("Resuming data at position {} (epoch {})", state.position, epoch + 1);
+            }
+            Some(_) => warn!("Data loader cannot resume its position; starting from the beginning of the data"),
+            None => info!("Checkpoint has no data position; starting from the beginning of the data"),
+        }
+    }
+    let mut val_loader = create_validation_loader::<B::InnerBackend>(&train_config, &device)?;
+    if val_loader.is_some() {
+        info!("  - Validating every {} steps", train_config.training.val_every);
+    }
+    let val_token_chars = val_loader
+        .as_ref()
+        .and_then(|_| load_data_tokenizer(&train_config))
+        .map(|tokenizer| TokenChars::new(&tokenizer));
+    
+    // Lowest validation loss so far; a resumed run keeps the existing best.json
+    let mut best_val_loss = train_config.training.resume_from
+        .as_ref()
+        .and_then(|_| read_best_val_loss(&train_config.training.checkpoint_dir));
+    
+    let mut early_stopping = match (&train_config.training.early_stopping, &val_loader) {
+        (config, Some(_)) if config.enabled => Some(EarlyStopping::new(config)),
+        (config, None) if config.enabled => {
+            warn!("Early stopping requires training.val_data; disabled");
+            None
+        }
+        _ => None,
+    };
+    
+    // Per-document loss attribution
+    let mut loss_tracker = (train_config.training.document_loss_window > 0)
+        .then(|| DocumentLossTracker::new(train_config.training.document_loss_window));
+    let document_losses_path = train_config.training.checkpoint_dir.join("document_losses.json");
+    if loss_tracker.is_some() && data_loader.documents().is_none() {
+        warn!("Document loss attribution enabled, but the data has no document boundaries");
+    }
+    
+    // Bad-batch quarantine
+    let mut quarantine = train_config.training.quarantine.enabled.then(|| BatchQuarantine::new(
+        train_config.training.quarantine.clone(),
+        &train_config.training.checkpoint_dir,
+        train_config.model.seq_len,
+    ));
+    let tokenizer = quarantine.as_ref().and_then(|_| load_data_tokenizer(&train_config));
+    if let Some(ref quarantine) = quarantine {
+        info!("Quarantining repeated loss spikes to: {:?}", quarantine.dir());
+    }
+    
+    let mut metrics_log = match train_config.training.log_dir {
+        Some(ref log_dir) => {
+            let writer = TensorBoardWriter::create(log_dir)?;
+            info!("Writing TensorBoard metrics to: {:?}", writer.path());
+            Some(writer)
+        }
+        None => None,
+    };
+    
+    let mut metrics_export = match train_config.training.metrics_format {
+        Some(format) => {
+            let exporter = MetricsExporter::open(&train_config.training.checkpoint_dir, format)?;
+            info!("Appending training metrics to: {:?}", exporter.path());
+            Some(exporter)
+        }
+        None => None,
+    };
+    let tokens_per_step = train_config.tokens_per_step() as f64;
+    
+    // Non-finite losses: skip, back off the LR, and eventually roll back
+    let mut nan_guard = train_config.training.nan_guard.enabled
+        .then(|| NanGuard::new(&train_config.training.nan_guard));
+    let mut last_checkpoint = train_config.training.resume_from.clone();
+    
+    // Stochastic weight averaging over the final phase of the run
+    let mut swa = train_config.training.swa.enabled
+        .then(|| WeightAverage::<B::InnerBackend>::new(&train_config.training.swa, start_step + num_steps));
+    if let Some(ref swa) = swa {
+        info!("  - SWA: averaging weights every {} steps from step {}", train_config.training.swa.every, swa.first_step());
+    }
     
     let mut total_loss = 0.0;
     let mut loss_count = 0;
     let training_start = std::time::Instant::now();
+    let mut memory_tracker = MemoryTracker::new();
 
-    for step in start_step..(start_step + train_config.training.num_steps) {
+    let mut final_step = start_step + num_steps;
+    
+    // Same RNG stream the original run used after writing the checkpoint
+    if train_config.training.resume_from.is_some() {
+        B::seed(&device, step_rng_seed(run_seed, start_step));
+    }
+    
+    'training: for step in start_step..(start_step + num_steps) {
         let step_start = std::time::Instant::now();
+        let lr_scale = nan_guard.as_ref().map_or(1.0, NanGuard::lr_scale);
+        trainer.set_learning_rate(scheduler.learning_rate(step) * lr_scale);
         
-        // Generate random batch data for testing
-        let batch = generate_random_batch::<Backend>(
-            train_config.training.batch_size,
-            train_config.model.seq_len,
-            train_config.model.vocab_size,
-            &device,
-        );
-
-        // Use batch data directly
-        let batch_data = BatchData {
-            tokens: batch.tokens,
-            targets: batch.targets,
+        // Next batch, wrapping around to a new epoch when the data runs out
+        // and skipping batches that overlap quarantined data
+        let mut skipped = 0;
+        let batch_data = loop {
+            let next = match data_loader.next_batch() {
+                Ok(Some(batch)) => Ok(batch),
+                // A followed directory has no epochs: the stream ended
+                Ok(None) if train_config.data.follow => {
+                    info!("Stopping at step {}: no new data within data.follow_timeout_secs", step);
+                    final_step = step;
+                    break 'training;
+                }
+                Ok(None) => {
+                    epoch += 1;
+                    match shuffle_seed.filter(|_| data_loader.documents().is_some()) {
+                        Some(_) => info!("Reached end of data, starting epoch {} (documents reshuffled)", epoch + 1),
+                        None => info!("Reached end of data, starting epoch {}", epoch + 1),
+                    }
+                    data_loader.start_epoch(epoch, shuffle_seed);
+                    data_loader.next_batch().and_then(|batch| {
+                        batch.ok_or_else(|| anyhow::anyhow!("Data loader produced no batches"))
+                    })
+                }
+                Err(e) => Err(e),
+            };
+            let batch = next.map_err(|e| trainer.callbacks_mut().on_exception(step + 1, e))?;
+            
+            if !quarantine.as_ref().is_some_and(|q| q.should_skip(&batch.offsets)) {
+                break batch;
+            }
+            
+            skipped += 1;
+            if skipped > data_loader.num_batches().unwrap_or(usize::MAX) {
+                let error = anyhow::anyhow!("Every batch overlaps quarantined data");
+                return Err(trainer.callbacks_mut().on_exception(step + 1, error));
+            }
         };
+        if skipped > 0 {
+            info!("Skipped {} batch(es) overlapping quarantined data", skipped);
+        }
+        let offsets = batch_data.offsets.clone();
+        let batch_tokens = quarantine.is_some().then(|| batch_data.tokens.clone());
 
         // Training step
         let output = trainer.train_step(batch_data);
+        
+        // Attribute sequence losses to their source documents
+        if let (Some(tracker), Some(documents)) = (loss_tracker.as_mut(), data_loader.documents()) {
+            for (offset, &loss) in offsets.iter().zip(&output.sequence_losses) {
+                if let (Some(id), Some(document)) = (documents.id_at(*offset), documents.document_at(*offset)) {
+                    tracker.record(id, document, loss);
+                }
+            }
+        }
+        
         let loss_data = output.loss.into_data();
         let loss_value = loss_data.to_vec::<f32>().unwrap_or_default().first().copied().unwrap_or(0.0);
-        total_loss += loss_value;
-        loss_count += 1;
+        
+        match nan_guard.as_mut().map(|guard| (guard.observe(loss_value), guard.lr_scale())) {
+            None | Some((NanGuardAction::Continue, _)) => {
+                total_loss += loss_value;
+                loss_count += 1;
+                if let Some(swa) = swa.as_mut().filter(|swa| swa.should_collect(step + 1)) {
+                    swa.collect(&trainer.model().valid());
+                }
+            }
+            Some((NanGuardAction::Skipped, lr_scale)) => {
+                warn!("Step {}: loss is {}; optimizer step skipped (learning rate x{})", step + 1, loss_value, lr_scale);
+            }
+            Some((NanGuardAction::Rollback, _)) => {
+                let max_consecutive = train_config.training.nan_guard.max_consecutive;
+                let checkpoint_path = last_checkpoint.clone().ok_or_else(|| anyhow::anyhow!(
+                    "{} consecutive non-finite losses at step {} and no checkpoint to roll back to",
+                    max_consecutive, step + 1
+                )).map_err(|e| trainer.callbacks_mut().on_exception(step + 1, e))?;
+                warn!("Step {}: {} consecutive non-finite losses; rolling back to {:?}",
+                    step + 1, max_consecutive, checkpoint_path);
+                let rolled_back = load_checkpoint::<B>(&checkpoint_path, &device)
+                    .with_context(|| "Failed to load checkpoint for rollback")
+                    .and_then(|(model, _, _)| create_trainer(model, Some(&checkpoint_path), step + 1))
+                    .map_err(|e| trainer.callbacks_mut().on_exception(step + 1, e))?;
+                trainer = rolled_back
+                    .with_callbacks(trainer.take_callbacks())
+                    .with_profile(trainer.profile().clone());
+            }
+        }
+        
+        // Dump batches whose loss keeps spiking
+        if let (Some(quarantine), Some(tokens)) = (quarantine.as_mut(), batch_tokens) {
+            quarantine.observe(step + 1, loss_value, || {
+                describe_batch(
+                    tokens,
+                    &offsets,
+                    data_loader.documents(),
+                    tokenizer.as_ref().map(|t| t as &dyn Tokenizer),
+                )
+            }).map_err(|e| trainer.callbacks_mut().on_exception(step + 1, e))?;
+        }
         
         let step_duration = step_start.elapsed();
+        
+        if let Some(ref mut writer) = metrics_log {
+            let mut scalars = vec![
+                ("train/loss", loss_value),
+                ("train/learning_rate", trainer.learning_rate()),
+                ("train/step_time", step_duration.as_secs_f32()),
+                ("train/epoch", epoch as f32),
+            ];
+            if let Some(grad_norm) = output.grad_norm {
+                scalars.push(("train/grad_norm", grad_norm));
+            }
+            let module_tags: Vec<(String, f32)> = output.module_norms
+                .iter()
+                .flat_map(|m| [
+                    (format!("grad_norm/{}", m.module), m.grad_norm),
+                    (format!("param_norm/{}", m.module), m.param_norm),
+                ])
+                .collect();
+            scalars.extend(module_tags.iter().map(|(tag, value)| (tag.as_str(), *value)));
+            if let Err(e) = writer.add_scalars(&scalars, step + 1) {
+                warn!("Failed to write TensorBoard metrics: {}", e);
+            }
+        }
 
         // Logging
         if (step + 1) % train_config.training.log_every == 0 {
@@ -188,9 +2338,10 @@ fn train_command(args: TrainArgs) -> Result<()> {
             let elapsed = training_start.elapsed();
             let steps_per_sec = (step + 1 - start_step) as f64 / elapsed.as_secs_f64();
             info!(
-                "Step {}/{}: Loss = {:.6} (avg: {:.6}) | Step time: {:.3}s | Speed: {:.2} steps/s",
+                "Step {}/{} (epoch {}): Loss = {:.6} (avg: {:.6}) | Step time: {:.3}s | Speed: {:.2} steps/s",
                 step + 1,
-                start_step + train_config.training.num_steps,
+                start_step + num_steps,
+                epoch + 1,
                 loss_value,
                 avg_loss,
                 step_duration.as_secs_f64(),
@@ -198,6 +2349,56 @@ fn train_command(args: TrainArgs) -> Result<()> {
             );
             total_loss = 0.0;
             loss_count = 0;
+            
+            let memory = memory_tracker.sample(B::allocated_bytes(&device));
+            match memory.device_bytes {
+                Some(device_bytes) => info!("  Memory: RSS {} (peak {}) | Device: {}",
+                    format_mib(memory.rss_bytes), format_mib(memory.peak_rss_bytes), format_mib(Some(device_bytes))),
+                None => info!("  Memory: RSS {} (peak {})",
+                    format_mib(memory.rss_bytes), format_mib(memory.peak_rss_bytes)),
+            }
+            
+            if let Some(ref mut exporter) = metrics_export {
+                let record = MetricsRecord {
+                    step: step + 1,
+                    loss: loss_value,
+                    avg_loss,
+                    learning_rate: trainer.learning_rate(),
+                    steps_per_sec,
+                    tokens_per_sec: steps_per_sec * tokens_per_step,
+                    grad_norm: output.grad_norm,
+                    modules: output.module_norms.clone(),
+                    rss_bytes: memory.rss_bytes,
+                    device_bytes: memory.device_bytes,
+                };
+                if let Err(e) = exporter.append(&record) {
+                    warn!("Failed to export metrics: {}", e);
+                }
+            }
+            
+            if let Some(ref mut writer) = metrics_log {
+                let mib = |bytes: u64| (bytes as f64 / (1024.0 * 1024.0)) as f32;
+                let scalars: Vec<(&str, f32)> = [
+                    ("memory/rss_mib", memory.rss_bytes),
+                    ("memory/device_mib", memory.device_bytes),
+                ]
+                .into_iter()
+                .filter_map(|(tag, bytes)| bytes.map(|bytes| (tag, mib(bytes))))
+                .collect();
+                if let Err(e) = writer.add_scalars(&scalars, step + 1).and_then(|_| writer.flush()) {
+                    warn!("Failed to write TensorBoard metrics: {}", e);
+                }
+            }
+            
+            if let Some(ref tracker) = loss_tracker {
+                for (rank, entry) in tracker.ranking().iter().take(3).enumerate() {
+                    info!("  Hardest document #{}: {} (avg loss {:.4} over {} sequences)",
+                        rank + 1, entry.document, entry.average_loss, entry.sequences);
+                }
+                if let Err(e) = tracker.write_ranking(&document_losses_path, step + 1) {
+                    warn!("Failed to write document losses: {}", e);
+                }
+            }
         } else {
             // 每步都输出简单进度（不输出详细日志）
             eprint!(".");
@@ -206,46 +2407,269 @@ fn train_command(args: TrainArgs) -> Result<()> {
             }
         }
         
+        // Periodic validation (no gradients)
+        if let Some(ref mut val_loader) = val_loader {
+            let val_every = train_config.training.val_every;
+            if val_every > 0 && (step + 1) % val_every == 0 {
+                let metrics = evaluate(&trainer.model().valid().eval(), val_loader.as_mut(), val_token_chars.as_ref())
+                    .map_err(|e| trainer.callbacks_mut().on_exception(step + 1, e))?;
+                info!(
+                    "Validation at step {}: Val loss = {:.6} | Perplexity = {:.2} | Accuracy = {:.4} | Top-{} = {:.4} | ECE = {:.4} | Train loss = {:.6}",
+                    step + 1,
+                    metrics.loss,
+                    metrics.perplexity,
+                    metrics.accuracy,
+                    TOP_K_ACCURACY,
+                    metrics.top5_accuracy,
+                    metrics.calibration.ece,
+                    loss_value
+                );
+                
+                if let Some(ref mut writer) = metrics_log {
+                    let mut scalars = vec![
+                        ("val/loss", metrics.loss),
+                        ("val/perplexity", metrics.perplexity),
+                        ("val/accuracy", metrics.accuracy),
+                        ("val/top5_accuracy", metrics.top5_accuracy),
+                        ("val/ece", metrics.calibration.ece),
+                    ];
+                    if let Some(bits_per_char) = metrics.bits_per_char {
+                        scalars.push(("val/bits_per_char", bits_per_char));
+                    }
+                    if let Err(e) = writer.add_scalars(&scalars, step + 1).and_then(|_| writer.flush()) {
+                        warn!("Failed to write TensorBoard metrics: {}", e);
+                    }
+                }
+                trainer.callbacks_mut().on_eval(step + 1, &metrics);
+                
+                if best_val_loss.is_none_or(|best| metrics.loss < best) {
+                    best_val_loss = Some(metrics.loss);
+                    B::seed(&device, step_rng_seed(run_seed, step + 1));
+                    match save_best_checkpoint(
+                        &trainer,
+                        step + 1,
+                        metrics.loss,
+                        corpus_version.as_deref(),
+                        loader_state(data_loader.as_ref(), epoch),
+                        Some(run_seed),
+                        &train_config.training.checkpoint_dir,
+                    ) {
+                        Ok(path) => trainer.callbacks_mut().on_checkpoint(step + 1, &path),
+                        Err(e) => warn!("Failed to save best checkpoint: {}", e),
+                    }
+                }
+                
+                if let Some(ref mut early_stopping) = early_stopping {
+                    if early_stopping.update(metrics.loss) {
+                        info!("Early stopping at step {}: no validation improvement (best val loss: {:.6})",
+                            step + 1, early_stopping.best_loss().unwrap_or(f32::NAN));
+                        final_step = step + 1;
+                        break;
+                    }
+                }
+            }
+        }
+        
         // Save checkpoint
         if train_config.training.save_every > 0 && (step + 1) % train_config.training.save_every == 0 {
-            info!("Saving checkpoint at step {}...", step + 1);
+            info!("Saving checkpoint at step {} (epoch {})...", step + 1, epoch + 1);
+            B::seed(&device, step_rng_seed(run_seed, step + 1));
             match save_checkpoint(
-                trainer.model(),
+                &trainer,
                 step + 1,
-                &train_config,
+                corpus_version.as_deref(),
+                loader_state(data_loader.as_ref(), epoch),
+                Some(run_seed),
                 &train_config.training.checkpoint_dir,
             ) {
                 Ok(checkpoint_path) => {
                     info!("Checkpoint saved: {:?}", checkpoint_path);
+                    trainer.callbacks_mut().on_checkpoint(step + 1, &checkpoint_path);
+                    last_checkpoint = Some(checkpoint_path);
                 }
                 Err(e) => {
                     warn!("Failed to save checkpoint: {}", e);
                 }
             }
         }
+        
+        // Snapshot at the end of every LR cycle
+        if let Some(cycle) = scheduler.cycle_end(step + 1).filter(|_| lr_schedule.snapshot_every_cycle) {
+            B::seed(&device, step_rng_seed(run_seed, step + 1));
+            match save_snapshot_checkpoint(
+                &trainer,
+                step + 1,
+                cycle,
+                corpus_version.as_deref(),
+                loader_state(data_loader.as_ref(), epoch),
+                Some(run_seed),
+                &train_config.training.checkpoint_dir,
+            ) {
+                Ok(path) => trainer.callbacks_mut().on_checkpoint(step + 1, &path),
+                Err(e) => warn!("Failed to save snapshot checkpoint: {}", e),
+            }
+        }
+        
+        if trainer.callbacks_mut().stop_requested() {
+            info!("Stopping at step {}: requested by a training callback", step + 1);
+            final_step = step + 1;
+            break;
+        }
+        
+        // Stop while the next step (as slow as the slower of the last and the
+        // average step) still fits in the wall-clock budget
+        if let Some(budget) = time_budget {
+            let elapsed = run_start.elapsed();
+            let average_step = training_start.elapsed() / (step + 1 - start_step) as u32;
+            let next_step = step_duration.max(average_step);
+            if step + 1 < start_step + num_steps && elapsed + next_step > budget {
+                info!("Stopping at step {}: the next step would exceed the time budget ({:.2} of {:.2} min used)",
+                    step + 1, elapsed.as_secs_f64() / 60.0, budget.as_secs_f64() / 60.0);
+                final_step = step + 1;
+                break;
+            }
+        }
     }
     
     // Save final checkpoint
     info!("Saving final checkpoint...");
-    let final_step = start_step + train_config.training.num_steps;
     match save_checkpoint(
-        trainer.model(),
+        &trainer,
         final_step,
-        &train_config,
+        corpus_version.as_deref(),
+        loader_state(data_loader.as_ref(), epoch),
+        Some(run_seed),
         &train_config.training.checkpoint_dir,
     ) {
         Ok(checkpoint_path) => {
             info!("Final checkpoint saved: {:?}", checkpoint_path);
+            trainer.callbacks_mut().on_checkpoint(final_step, &checkpoint_path);
         }
         Err(e) => {
             warn!("Failed to save final checkpoint: {}", e);
         }
     }
     
+    if let Some(ref swa) = swa {
+        match swa.average() {
+            Some(average) => match save_swa_checkpoint(
+                average,
+                &train_config,
+                final_step,
+                corpus_version.as_deref(),
+                &train_config.training.checkpoint_dir,
+            ) {
+                Ok(checkpoint_path) => {
+                    info!("SWA checkpoint ({} averaged models) saved: {:?}", swa.models(), checkpoint_path);
+                    trainer.callbacks_mut().on_checkpoint(final_step, &checkpoint_path);
+                }
+                Err(e) => warn!("Failed to save SWA checkpoint: {}", e),
+            },
+            None => warn!("Training ended before SWA step {}; no averaged model saved", swa.first_step()),
+        }
+    }
+    
+    if let Some(ref tracker) = loss_tracker {
+        tracker.write_ranking(&document_losses_path, final_step)?;
+        info!("Document loss ranking saved to: {:?}", document_losses_path);
+    }
+
+    if trainer.profile().is_enabled() {
+        let report = trainer.profile().report();
+        info!("Forward time by module ({} passes, {:.3} ms per pass):", report.passes, report.forward_ms);
+        for stage in &report.stages {
+            info!("  {:<18} {:>9.3} ms  (max {:>9.3} ms)  {:>5.1}%",
+                stage.stage, stage.ms_per_pass, stage.max_ms, stage.share * 100.0);
+        }
+        let profile_path = train_config.training.checkpoint_dir.join("profile.json");
+        match save_profile_report(&report, &profile_path) {
+            Ok(()) => info!("Profile report saved to: {:?}", profile_path),
+            Err(e) => warn!("Failed to save profile report: {}", e),
+        }
+    }
+    
     let total_duration = training_start.elapsed();
     info!("Training completed in {:.2}s", total_duration.as_secs_f64());
+    let memory = memory_tracker.sample(B::allocated_bytes(&device));
+    match memory_tracker.peak_device_bytes() {
+        Some(device_bytes) => info!("Peak memory: RSS {} | Device: {}",
+            format_mib(memory.peak_rss_bytes), format_mib(Some(device_bytes))),
+        None => info!("Peak memory: RSS {}", format_mib(memory.peak_rss_bytes)),
+    }
 
     info!("Training completed!");
     Ok(())
 }
 
+/// Run an LR range test with `trainer` on a fresh pass over the training
+/// data, write the loss vs learning rate to `csv_path`, plot the curve and
+/// return the suggested learning rate
+fn lr_range_test<B: AutodiffBackend>(
+    trainer: &mut HopeTrainer<B>,
+    train_config: &TrainConfig,
+    sweep: &LrSweep,
+    csv_path: &Path,
+    device: &B::Device,
+) -> Result<Option<f32>> {
+    info!("LR range test: up to {} steps from {} to {}", sweep.steps, sweep.min_lr, sweep.max_lr);
+    let mut loader = create_data_loader::<B>(train_config, device)?;
+    let points = trainer.find_lr(sweep, || {
+        if let Some(batch) = loader.next_batch()? {
+            return Ok(batch);
+        }
+        loader.reset();
+        loader.next_batch()?.ok_or_else(|| anyhow::anyhow!("Data loader produced no batches"))
+    })?;
+    let suggestion = suggest_learning_rate(&points);
+    
+    if let Some(dir) = csv_path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create directory: {:?}", dir))?;
+    }
+    write_lr_csv(&points, csv_path)?;
+    info!("LR range test: {} steps, loss vs learning rate saved to {:?}", points.len(), csv_path);
+    
+    println!("Smoothed loss vs learning rate (log scale):");
+    print!("{}", plot_lr_curve(&points, suggestion, 60, 12));
+    if let Some(lr) = suggestion {
+        println!("Suggested learning rate: {:.3e}", lr);
+    }
+    Ok(suggestion)
+}
+
+/// Data position to save with a checkpoint, if the loader can resume
+fn loader_state<B: Backend>(loader: &dyn DataLoader<B>, epoch: usize) -> Option<LoaderState> {
+    loader.position().map(|position| LoaderState { position, epoch })
+}
+
+/// JSON constraint over the model's vocabulary, with the text of each token
+/// (padding and unknown tokens are never sampled)
+fn json_constraint(tokenizer: &CharTokenizer, vocab_size: usize) -> JsonConstraint {
+    let pieces = (0..vocab_size as i64)
+        .map(|id| {
+            if id == tokenizer.pad_id() || id == tokenizer.unk_id() {
+                String::new()
+            } else {
+                tokenizer.decode(&[id])
+            }
+        })
+        .collect();
+    JsonConstraint::new(pieces)
+}
+
+/// Tokenizer matching the training data, used to decode batches for inspection
+fn load_data_tokenizer(train_config: &TrainConfig) -> Option<CharTokenizer> {
+    let path = train_config.data.tokenizer_path.clone().or_else(|| {
+        train_config.data.data_path
+            .as_ref()
+            .map(|dir| dir.join("vocab.json"))
+            .filter(|path| path.exists())
+    })?;
+    
+    match CharTokenizer::load(&path) {
+        Ok(tokenizer) => Some(tokenizer),
+        Err(e) => {
+            warn!("Failed to load tokenizer {:?}: {}", path, e);
+            None
+        }
+    }
+}
@@ -1,12 +1,21 @@
 use burn::constant;
 use burn::module::Module;
 use burn::nn::{LayerNorm, LayerNormConfig, Linear, LinearConfig};
-use burn::tensor::{Tensor, activation, backend::Backend};
-use crate::config::ContinuumMemConfig;
+use burn::record::Record;
+use burn::tensor::module::adaptive_avg_pool1d;
+use burn::tensor::{Int, Tensor, activation, backend::Backend};
+use crate::config::{ContinuumMemConfig, MemoryWritePolicy};
+use super::onnx::OnnxGraph;
+use super::profile::{ForwardStage, StageTimes};
 
 constant!(ContinuumMemConfig);
 
-#[derive(Clone, Debug)]
+/// Blocks of `retrieval_top_k` slots whose slots are scored exactly
+const RETRIEVAL_BLOCKS: usize = 2;
+
+/// Memory banks, [batch, seq_len, hidden] each; with `memory_slots`, the
+/// long and episodic banks are [batch, memory_slots, slot_dim]
+#[derive(Record, Clone, Debug)]
 pub struct ContinuumMemoryState<B: Backend> {
     pub ultra_short: Tensor<B, 3>,
     pub short: Tensor<B, 3>,
@@ -23,6 +32,14 @@ pub struct ContinuumMemory<B: Backend> {
     key_proj: Linear<B>,
     value_proj: Linear<B>,
     norm: LayerNorm<B>,
+    /// Salience scorer used to prune banks to their top-k positions
+    salience: Option<Linear<B>>,
+    /// Projection out of compressed slots narrower than the hidden size
+    /// (`slot_dim`); slots are written by fixed pooling, since the writes
+    /// come after the logits and carry no gradient
+    slot_up: Option<Linear<B>>,
+    /// Per-token gate on the retrieved memory (`gated`)
+    gate: Option<Linear<B>>,
 }
 
 impl<B: Backend> ContinuumMemory<B> {
@@ -32,6 +49,11 @@ impl<B: Backend> ContinuumMemory<B> {
         let key_proj = LinearConfig::new(hidden_size, hidden_size).init(device);
         let value_proj = LinearConfig::new(hidden_size, hidden_size).init(device);
         let norm = LayerNormConfig::new(hidden_size).init(device);
+        let salience = (!config.top_k.is_empty())
+            .then(|| LinearConfig::new(hidden_size, 1).init(device));
+        let slot_dim = config.slot_dim.filter(|&dim| dim != hidden_size);
+        let slot_up = slot_dim.map(|dim| LinearConfig::new(dim, hidden_size).init(device));
+        let gate = config.gated.then(|| LinearConfig::new(2 * hidden_size, 1).init(device));
 
         Self {
             config,
@@ -39,6 +61,9 @@ impl<B: Backend> ContinuumMemory<B> {
             key_proj,
             value_proj,
             norm,
+            salience,
+            slot_up,
+            gate,
         }
     }
 
@@ -50,12 +75,16 @@ impl<B: Backend> ContinuumMemory<B> {
         device: &B::Device,
     ) -> ContinuumMemoryState<B> {
         let zeros = || Tensor::zeros([batch, seq_len, hidden_size], device);
+        let slots = || {
+            let width = self.config.slot_dim.unwrap_or(hidden_size);
+            Tensor::zeros([batch, self.config.memory_slots.unwrap_or(seq_len), width], device)
+        };
         ContinuumMemoryState {
             ultra_short: zeros(),
             short: zeros(),
             mid: zeros(),
-            long: zeros(),
-            episodic: zeros(),
+            long: slots(),
+            episodic: slots(),
         }
     }
 
@@ -77,35 +106,40 @@ impl<B: Backend> ContinuumMemory<B> {
 
         // Mid: medium EMA (16-64 steps)
         let mid_alpha = self.compute_alpha(self.config.mid_span);
-        state.mid = self.ema_update(&state.mid, new_hidden, mid_alpha);
+        state.mid = self.write(&state.mid, new_hidden, mid_alpha);
+
+        // Long and episodic banks may hold compressed slots
+        let compressed = self.compress(new_hidden.clone());
 
         // Long: slow EMA (64-256 steps)
         let long_alpha = self.compute_alpha(self.config.long_span);
-        state.long = self.ema_update(&state.long, new_hidden, long_alpha);
+        state.long = self.write(&state.long, &compressed, long_alpha);
 
         // Episodic: very slow EMA (>256 steps)
         let episodic_alpha = self.compute_alpha(self.config.episodic_span);
-        state.episodic = self.ema_update(&state.episodic, new_hidden, episodic_alpha);
+        state.episodic = self.write(&state.episodic, &compressed, episodic_alpha);
     }
 
     pub fn retrieve(
         &self,
         state: &ContinuumMemoryState<B>,
         query: &Tensor<B, 3>,
+    ) -> Tensor<B, 3> {
+        self.retrieve_timed(state, query, &mut StageTimes::disabled())
+    }
+
+    /// `retrieve`, adding the time of the query projection, the bank
+    /// projections and the attention to `times`
+    pub fn retrieve_timed(
+        &self,
+        state: &ContinuumMemoryState<B>,
+        query: &Tensor<B, 3>,
+        times: &mut StageTimes,
     ) -> Tensor<B, 3> {
         if !self.config.enabled {
             return query.clone();
         }
 
-        // Compute attention over all memory banks
-        let memories = vec![
-            &state.ultra_short,
-            &state.short,
-            &state.mid,
-            &state.long,
-            &state.episodic,
-        ];
-
         let batch = query.dims()[0];
         let seq_len = query.dims()[1];
         let hidden = query.dims()[2];
@@ -115,11 +149,102 @@ impl<B: Backend> ContinuumMemory<B> {
         let query_proj = self.query_proj.forward(query_2d);
         let query_proj = self.norm.forward(query_proj);
         let query_proj = query_proj.reshape([batch, seq_len, hidden]);
+        let device = query.device();
+        times.lap::<B>(ForwardStage::MemoryQuery, &device);
+
+        let (keys, values) = self.memory_keys_values(state);
+        times.lap::<B>(ForwardStage::MemoryKeys, &device);
+
+        // Simplified attention: compute weighted sum over all memory banks
+        let _batch = query.dims()[0];
+        let _seq_len = query.dims()[1];
+        let hidden = query.dims()[2];
+        let _mem_seq_len = keys.dims()[1];
+
+        // Compute attention scores: [batch, seq_len, hidden] x [batch, hidden, mem_seq_len]
+        let query_expanded = query_proj.clone(); // [batch, seq_len, hidden]
+        let keys_transposed = keys.swap_dims(1, 2); // [batch, hidden, mem_seq_len]
+        
+        // Compute scores: [batch, seq_len, mem_seq_len]
+        let attended = match self.config.retrieval_top_k {
+            Some(k) if k < keys_transposed.dims()[2] => self.attend_top_k(query_proj, keys_transposed.swap_dims(1, 2), values, k),
+            _ => {
+                let scores = query_expanded.matmul(keys_transposed);
+                let scores = (scores * self.score_scale(hidden))
+                    .clamp(-self.config.score_clamp, self.config.score_clamp);
+                let attn_weights = activation::softmax(scores, 2);
+
+                // Apply attention to values: [batch, seq_len, mem_seq_len] x [batch, mem_seq_len, hidden]
+                attn_weights.matmul(values) // [batch, seq_len, hidden]
+            }
+        };
+        let attended = match self.gate {
+            Some(ref gate) => {
+                let gate = activation::sigmoid(gate.forward(Tensor::cat(vec![query.clone(), attended.clone()], 2)));
+                attended * gate
+            }
+            None => attended,
+        };
+        times.lap::<B>(ForwardStage::MemoryAttention, &device);
+
+        // Residual connection
+        query.clone() + attended
+    }
+
+    /// ONNX nodes of `retrieve` for `query` ([batch, len, hidden]) from the
+    /// first row of `state`, whose keys and values become constants. Top-k
+    /// retrieval (`retrieval_top_k`) has no export.
+    pub fn onnx_retrieve(&self, graph: &mut OnnxGraph, state: &ContinuumMemoryState<B>, query: &str) -> anyhow::Result<String> {
+        if !self.config.enabled {
+            return Ok(query.to_string());
+        }
+        let (keys, values) = self.memory_keys_values(state);
+        let [batch, mem_seq_len, hidden] = keys.dims();
+        if self.config.retrieval_top_k.is_some_and(|k| k < mem_seq_len) {
+            anyhow::bail!("ONNX export does not support continuum_mem.retrieval_top_k");
+        }
+        let first = |t: Tensor<B, 3>| t.slice([0..batch.min(1), 0..mem_seq_len, 0..hidden]);
+        let keys = graph.tensor(first(keys).swap_dims(1, 2));
+        let values = graph.tensor(first(values));
+
+        let query_proj = graph.linear(&self.query_proj, query);
+        let query_proj = graph.layer_norm(&self.norm, &query_proj);
+        let scores = graph.matmul(&query_proj, &keys);
+        let scores = graph.scale(&scores, self.score_scale(hidden));
+        let min = graph.scalar(-self.config.score_clamp);
+        let max = graph.scalar(self.config.score_clamp);
+        let scores = graph.node("Clip", &[&scores, &min, &max], Vec::new());
+        let weights = graph.softmax(&scores);
+        let attended = graph.matmul(&weights, &values);
+        let attended = match self.gate {
+            Some(ref gate) => {
+                let both = graph.concat(&[query, &attended], 2);
+                let logits = graph.linear(gate, &both);
+                let gate = graph.node("Sigmoid", &[&logits], Vec::new());
+                graph.mul(&attended, &gate)
+            }
+            None => attended,
+        };
+        Ok(graph.add(query, &attended))
+    }
+
+    /// Keys and values ([batch, mem_seq_len, hidden]) of all memory banks,
+    /// after decompression and top-k pruning
+    fn memory_keys_values(&self, state: &ContinuumMemoryState<B>) -> (Tensor<B, 3>, Tensor<B, 3>) {
+        let long = self.decompress(&state.long);
+        let episodic = self.decompress(&state.episodic);
+        let memories = [
+            &state.ultra_short,
+            &state.short,
+            &state.mid,
+            &long,
+            &episodic,
+        ];
 
         let mut all_keys = Vec::new();
         let mut all_values = Vec::new();
 
-        for memory in &memories {
+        for (bank, memory) in memories.iter().enumerate() {
             let mem_batch = memory.dims()[0];
             let mem_seq_len = memory.dims()[1];
             let mem_hidden = memory.dims()[2];
@@ -128,38 +253,132 @@ impl<B: Backend> ContinuumMemory<B> {
             let mem_clone = (*memory).clone();
             let mem_2d = mem_clone.reshape([mem_batch * mem_seq_len, mem_hidden]);
             let keys_2d = self.key_proj.forward(mem_2d.clone());
-            let values_2d = self.value_proj.forward(mem_2d);
+            let values_2d = self.value_proj.forward(mem_2d.clone());
+            let hidden = keys_2d.dims()[1];
             let keys = keys_2d.reshape([mem_batch, mem_seq_len, hidden]);
             let values = values_2d.reshape([mem_batch, mem_seq_len, hidden]);
+            
+            let top_k = self.config.top_k.get(bank).copied().unwrap_or(0);
+            let (keys, values) = match self.salience {
+                Some(ref salience) if top_k > 0 && top_k < mem_seq_len => {
+                    let scores = salience.forward(mem_2d).reshape([mem_batch, mem_seq_len]);
+                    Self::prune_top_k(keys, values, scores, top_k)
+                }
+                _ => (keys, values),
+            };
             all_keys.push(keys);
             all_values.push(values);
         }
 
         // Concatenate all memories
-        let keys = Tensor::cat(all_keys, 1);
-        let values = Tensor::cat(all_values, 1);
+        (Tensor::cat(all_keys, 1), Tensor::cat(all_values, 1))
+    }
 
-        // Simplified attention: compute weighted sum over all memory banks
-        let _batch = query.dims()[0];
-        let _seq_len = query.dims()[1];
-        let hidden = query.dims()[2];
-        let _mem_seq_len = keys.dims()[1];
+    /// Keep the `k` positions with the highest salience. Kept values are
+    /// gated by sigmoid(salience) so the scorer receives gradients.
+    fn prune_top_k(
+        keys: Tensor<B, 3>,
+        values: Tensor<B, 3>,
+        scores: Tensor<B, 2>,
+        k: usize,
+    ) -> (Tensor<B, 3>, Tensor<B, 3>) {
+        let hidden = keys.dims()[2];
+        let (top_scores, indices) = scores.topk_with_indices(k, 1);
+        let gather_indices: Tensor<B, 3, Int> = indices.unsqueeze_dim(2).repeat_dim(2, hidden);
 
-        // Compute attention scores: [batch, seq_len, hidden] x [batch, hidden, mem_seq_len]
-        let query_expanded = query_proj.clone(); // [batch, seq_len, hidden]
-        let keys_transposed = keys.swap_dims(1, 2); // [batch, hidden, mem_seq_len]
-        
-        // Compute scores: [batch, seq_len, mem_seq_len]
-        let scores = query_expanded.matmul(keys_transposed);
-        let scale = (hidden as f32).sqrt().recip();
-        let scores = scores * scale;
-        let attn_weights = activation::softmax(scores, 2);
+        let keys = keys.gather(1, gather_indices.clone());
+        let gate = activation::sigmoid(top_scores).unsqueeze_dim(2);
+        let values = values.gather(1, gather_indices) * gate;
+        (keys, values)
+    }
 
-        // Apply attention to values: [batch, seq_len, mem_seq_len] x [batch, mem_seq_len, hidden]
-        let attended = attn_weights.matmul(values); // [batch, seq_len, hidden]
+    /// Attention of each query ([batch, seq_len, hidden]) over only its `k`
+    /// highest-scoring slots of `keys` and `values` ([batch, mem_seq_len,
+    /// hidden]). Candidates are found first on blocks of `k` consecutive
+    /// slots: the query is scored against each block's mean key, and only
+    /// the slots of the `RETRIEVAL_BLOCKS` best blocks are scored exactly, so
+    /// a query costs about mem_seq_len / k + RETRIEVAL_BLOCKS * k dot
+    /// products instead of mem_seq_len
+    fn attend_top_k(&self, query: Tensor<B, 3>, keys: Tensor<B, 3>, values: Tensor<B, 3>, k: usize) -> Tensor<B, 3> {
+        let [batch, seq_len, hidden] = query.dims();
+        let mem_seq_len = keys.dims()[1];
+        let device = query.device();
+        let num_blocks = mem_seq_len.div_ceil(k);
+        let padded = num_blocks * k;
+        let pad = |t: Tensor<B, 3>| match padded - mem_seq_len {
+            0 => t,
+            extra => Tensor::cat(vec![t, Tensor::zeros([batch, extra, hidden], &device)], 1),
+        };
+        let (keys, values) = (pad(keys), pad(values));
 
-        // Residual connection
-        query.clone() + attended
+        let centroids = keys.clone().reshape([batch, num_blocks, k, hidden]).mean_dim(2).reshape([batch, num_blocks, hidden]);
+        let blocks = RETRIEVAL_BLOCKS.min(num_blocks);
+        let (_, top_blocks) = query.clone().matmul(centroids.swap_dims(1, 2)).topk_with_indices(blocks, 2);
+        let within = Tensor::<B, 1, Int>::arange(0..k as i64, &device).reshape([1, 1, 1, k]);
+        let candidates = (top_blocks.mul_scalar(k as i64).unsqueeze_dim::<4>(3) + within)
+            .reshape([batch, seq_len, blocks * k]);
+
+        // Slot indices into the rows of all batch rows at once
+        let offsets = Tensor::<B, 1, Int>::arange(0..batch as i64, &device)
+            .mul_scalar(padded as i64)
+            .reshape([batch, 1, 1]);
+        let rows = (candidates.clone() + offsets.clone()).reshape([batch * seq_len * blocks * k]);
+        let candidate_keys = keys
+            .reshape([batch * padded, hidden])
+            .select(0, rows)
+            .reshape([batch * seq_len, blocks * k, hidden]);
+        let scores = query
+            .reshape([batch * seq_len, 1, hidden])
+            .matmul(candidate_keys.swap_dims(1, 2))
+            .reshape([batch, seq_len, blocks * k]);
+        let clamp = self.config.score_clamp;
+        let scores = (scores * self.score_scale(hidden)).clamp(-clamp, clamp);
+        // At least two blocks are candidates, so padding never displaces a slot
+        let scores = scores.mask_fill(candidates.clone().greater_equal_elem(mem_seq_len as i64), -clamp - 1.0);
+        let (top_scores, picks) = scores.topk_with_indices(k, 2);
+
+        let slots = (candidates.gather(2, picks) + offsets).reshape([batch * seq_len * k]);
+        let selected = values
+            .reshape([batch * padded, hidden])
+            .select(0, slots)
+            .reshape([batch * seq_len, k, hidden]);
+
+        activation::softmax(top_scores, 2)
+            .reshape([batch * seq_len, 1, k])
+            .matmul(selected)
+            .reshape([batch, seq_len, hidden])
+    }
+
+    /// `hidden` ([batch, len, hidden]) in the layout of the long and episodic
+    /// banks: with `memory_slots`, average pools of consecutive positions
+    /// (and, for a `slot_dim`, of consecutive features); otherwise unchanged
+    pub fn compress(&self, hidden: Tensor<B, 3>) -> Tensor<B, 3> {
+        let Some(slots) = self.config.memory_slots else {
+            return hidden;
+        };
+        let pooled = adaptive_avg_pool1d(hidden.swap_dims(1, 2), slots).swap_dims(1, 2);
+        match self.config.slot_dim {
+            Some(dim) => adaptive_avg_pool1d(pooled, dim),
+            None => pooled,
+        }
+    }
+
+    /// Compressed slots back at the hidden size, to be read like the other banks
+    fn decompress(&self, bank: &Tensor<B, 3>) -> Tensor<B, 3> {
+        match self.slot_up {
+            Some(ref up) => up.forward(bank.clone()),
+            None => bank.clone(),
+        }
+    }
+
+    /// Combined attention scale and softmax temperature for retrieval scores
+    fn score_scale(&self, hidden: usize) -> f32 {
+        let epsilon = self.config.epsilon;
+        let scale = self
+            .config
+            .attention_scale
+            .unwrap_or_else(|| (hidden as f32).sqrt().max(epsilon).recip());
+        scale / self.config.temperature.max(epsilon)
     }
 
     fn compute_alpha(&self, span: usize) -> f32 {
@@ -175,9 +394,185 @@ impl<B: Backend> ContinuumMemory<B> {
         old.clone() * one_minus_alpha + new.clone() * alpha
     }
 
+    /// EMA write of a mid, long or episodic bank under the write policy
+    fn write(&self, old: &Tensor<B, 3>, new: &Tensor<B, 3>, alpha: f32) -> Tensor<B, 3> {
+        match self.config.write_policy {
+            MemoryWritePolicy::Uniform => self.ema_update(old, new, alpha),
+            MemoryWritePolicy::Novelty => {
+                // The surprise is a write signal only, not trained through
+                let rate = (self.novelty(old, new).detach() * (alpha * self.config.surprise_gain) + alpha)
+                    .clamp_max(1.0);
+                old.clone() + (new.clone() - old.clone()) * rate
+            }
+        }
+    }
+
+    /// Per-position novelty ([batch, len, 1], in [0, 1]) of `new` against
+    /// `old`: their distance over the sum of their norms
+    fn novelty(&self, old: &Tensor<B, 3>, new: &Tensor<B, 3>) -> Tensor<B, 3> {
+        let norm = |x: Tensor<B, 3>| x.powf_scalar(2.0).sum_dim(2).sqrt();
+        let distance = norm(new.clone() - old.clone());
+        distance / (norm(new.clone()) + norm(old.clone()) + self.config.epsilon)
+    }
+
     #[allow(dead_code)]
     pub fn config(&self) -> &ContinuumMemConfig {
         &self.config
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use burn::tensor::Distribution;
+    use burn_ndarray::NdArray;
+
+    #[test]
+    fn test_score_scale_uses_config() {
+        let device = Default::default();
+        let config = ContinuumMemConfig {
+            temperature: 2.0,
+            ..Default::default()
+        };
+        let mem = ContinuumMemory::<NdArray<f32>>::new(config, 4, &device);
+        assert!((mem.score_scale(4) - 0.25).abs() < 1e-6);
+
+        let config = ContinuumMemConfig {
+            attention_scale: Some(0.1),
+            ..Default::default()
+        };
+        let mem = ContinuumMemory::<NdArray<f32>>::new(config, 4, &device);
+        assert!((mem.score_scale(4) - 0.1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_retrieve_stays_finite_for_tiny_hidden() {
+        let device = Default::default();
+        let mem = ContinuumMemory::<NdArray<f32>>::new(ContinuumMemConfig::default(), 2, &device);
+        let mut state = mem.init_state(1, 4, 2, &device);
+        let hidden = Tensor::<NdArray<f32>, 3>::ones([1, 4, 2], &device) * 1e4;
+        mem.update(&mut state, &hidden);
+
+        let values = mem.retrieve(&state, &hidden).into_data().to_vec::<f32>().unwrap();
+        assert!(values.iter().all(|v| v.is_finite()));
+    }
+
+    #[test]
+    fn test_retrieval_top_k_attends_to_best_slots() {
+        let device = Default::default();
+        let config = ContinuumMemConfig { attention_scale: Some(1.0), temperature: 1.0, ..Default::default() };
+        let mem = ContinuumMemory::<NdArray<f32>>::new(config, 1, &device);
+        let weight = |a: f32, b: f32| a.exp() / (a.exp() + b.exp());
+        let query = Tensor::<NdArray<f32>, 3>::ones([2, 1, 1], &device);
+
+        // Three slots in two blocks of two, the second padded
+        let keys = Tensor::<NdArray<f32>, 3>::from_floats([[[0.0], [2.0], [1.0]], [[3.0], [0.0], [-1.0]]], &device);
+        let values = Tensor::<NdArray<f32>, 3>::from_floats(
+            [[[1.0], [10.0], [100.0]], [[2.0], [20.0], [200.0]]],
+            &device,
+        );
+        let attended = mem.attend_top_k(query.clone(), keys, values, 2).into_data().to_vec::<f32>().unwrap();
+        let expected = [
+            weight(2.0, 1.0) * 10.0 + weight(1.0, 2.0) * 100.0,
+            weight(3.0, 0.0) * 2.0 + weight(0.0, 3.0) * 20.0,
+        ];
+        assert!(attended.iter().zip(expected).all(|(a, b)| (a - b).abs() < 1e-4));
+
+        // Only the slots of the two best of four blocks are scored, so the
+        // 4.5 in the last block is not found
+        let keys = Tensor::<NdArray<f32>, 1>::from_floats([0.0, 0.0, 5.0, 4.0, -3.0, -3.0, 4.5, -6.5], &device);
+        let values = Tensor::<NdArray<f32>, 1>::from_floats([1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0], &device);
+        let attended = mem.attend_top_k(
+            query.slice([0..1, 0..1, 0..1]),
+            keys.reshape([1, 8, 1]),
+            values.reshape([1, 8, 1]),
+            2,
+        );
+        let expected = weight(5.0, 4.0) * 3.0 + weight(4.0, 5.0) * 4.0;
+        assert!((attended.into_scalar() - expected).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_compressed_banks_hold_fixed_slots() {
+        let device = Default::default();
+        let config = ContinuumMemConfig {
+            memory_slots: Some(3),
+            slot_dim: Some(4),
+            ..Default::default()
+        };
+        let mem = ContinuumMemory::<NdArray<f32>>::new(config, 8, &device);
+
+        for seq_len in [2, 7] {
+            let mut state = mem.init_state(2, seq_len, 8, &device);
+            let hidden = Tensor::<NdArray<f32>, 3>::random([2, seq_len, 8], Distribution::Default, &device);
+            mem.update(&mut state, &hidden);
+            assert_eq!(state.short.dims(), [2, seq_len, 8]);
+            assert_eq!(state.episodic.dims(), [2, 3, 4]);
+            assert_eq!(mem.retrieve(&state, &hidden).dims(), [2, seq_len, 8]);
+        }
+
+        // Without a slot width, slots average the positions themselves
+        let config = ContinuumMemConfig { memory_slots: Some(2), ..Default::default() };
+        let mem = ContinuumMemory::<NdArray<f32>>::new(config, 1, &device);
+        let hidden = Tensor::<NdArray<f32>, 1>::from_floats([1.0, 3.0, 5.0, 7.0], &device).reshape([1, 4, 1]);
+        assert_eq!(mem.compress(hidden).into_data().to_vec::<f32>().unwrap(), vec![2.0, 6.0]);
+
+        // A slot width averages groups of features
+        let config = ContinuumMemConfig { memory_slots: Some(1), slot_dim: Some(2), ..Default::default() };
+        let mem = ContinuumMemory::<NdArray<f32>>::new(config, 4, &device);
+        let hidden = Tensor::<NdArray<f32>, 1>::from_floats([1.0, 3.0, 5.0, 7.0, 3.0, 5.0, 7.0, 9.0], &device).reshape([1, 2, 4]);
+        assert_eq!(mem.compress(hidden).into_data().to_vec::<f32>().unwrap(), vec![3.0, 7.0]);
+    }
+
+    #[test]
+    fn test_gate_scales_retrieved_memory() {
+        let device = Default::default();
+        let hidden = Tensor::<NdArray<f32>, 3>::random([2, 3, 4], Distribution::Default, &device);
+        let config = ContinuumMemConfig { gated: true, ..Default::default() };
+        let mem = ContinuumMemory::<NdArray<f32>>::new(config, 4, &device);
+        let state = mem.init_state(2, 3, 4, &device);
+        let gated = mem.retrieve(&state, &hidden) - hidden.clone();
+
+        // The same memory without the gate adds the whole retrieved vector
+        let ungated = ContinuumMemory { gate: None, ..mem.clone() }.retrieve(&state, &hidden) - hidden;
+        let ratios = (gated / ungated).into_data().to_vec::<f32>().unwrap();
+        assert!(ratios.iter().all(|ratio| *ratio > 0.0 && *ratio < 1.0));
+        // One gate per token, shared by its channels
+        for token in ratios.chunks(4) {
+            assert!(token.iter().all(|ratio| (ratio - token[0]).abs() < 1e-4));
+        }
+    }
+
+    #[test]
+    fn test_novelty_writes_novel_positions_faster() {
+        let device = Default::default();
+        let config = ContinuumMemConfig {
+            write_policy: MemoryWritePolicy::Novelty,
+            surprise_gain: 3.0,
+            ..Default::default()
+        };
+        let mem = ContinuumMemory::<NdArray<f32>>::new(config, 1, &device);
+        // The first position repeats the bank, the second opposes it
+        let old = Tensor::<NdArray<f32>, 3>::ones([1, 2, 1], &device);
+        let new = Tensor::<NdArray<f32>, 1>::from_floats([1.0, -1.0], &device).reshape([1, 2, 1]);
+
+        let novelty = mem.novelty(&old, &new).into_data().to_vec::<f32>().unwrap();
+        assert!(novelty[0].abs() < 1e-5 && (novelty[1] - 1.0).abs() < 1e-5);
+        // Rate 0.25 at full novelty: 1 + (-1 - 1) * 0.25
+        let written = mem.write(&old, &new, 0.0625).into_data().to_vec::<f32>().unwrap();
+        assert!((written[0] - 1.0).abs() < 1e-5 && (written[1] - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_prune_top_k_keeps_most_salient() {
+        let device = Default::default();
+        let keys = Tensor::<NdArray<f32>, 1>::from_floats([1.0, 2.0, 3.0, 4.0], &device)
+            .reshape([1, 4, 1]);
+        let scores = Tensor::<NdArray<f32>, 2>::from_floats([[0.0, 5.0, -1.0, 3.0]], &device);
+
+        let (pruned, values) =
+            ContinuumMemory::<NdArray<f32>>::prune_top_k(keys.clone(), keys, scores, 2);
+        assert_eq!(pruned.into_data().to_vec::<f32>().unwrap(), vec![2.0, 4.0]);
+        assert_eq!(values.dims(), [1, 2, 1]);
+    }
+}
@@ -0,0 +1,701 @@
+use burn::constant;
+use burn::module::Module;
+use burn::nn::attention::{MultiHeadAttention, MultiHeadAttentionConfig};
+use burn::nn::{Dropout, DropoutConfig, LayerNorm, LayerNormConfig, Linear, LinearConfig};
+use burn::tensor::activation::{gelu, quiet_softmax, relu, silu, softmax};
+use burn::tensor::{Bool, Int, Tensor, TensorData, backend::Backend};
+use crate::config::{FfnActivation, HopeConfig, LevelShape};
+use super::onnx::{Attribute, OnnxGraph};
+use super::prune::{PruneOptions, PruneReport, lowest, prune_count};
+use std::ops::Range;
+
+constant!(FfnActivation);
+
+/// Transformer encoder of one level: pre-norm layers with the parameters
+/// (and record layout) of burn's `TransformerEncoder`, so existing
+/// checkpoints load unchanged, plus an additive attention bias (ALiBi) and
+/// cached keys/values for incremental decoding. A level with its own width
+/// (`level_hidden_sizes`) projects from and back to the model's.
+#[derive(Module, Debug)]
+pub struct LevelEncoder<B: Backend> {
+    input_proj: Option<Linear<B>>,
+    layers: Vec<EncoderLayer<B>>,
+    output_proj: Option<Linear<B>>,
+}
+
+#[derive(Module, Debug)]
+struct EncoderLayer<B: Backend> {
+    mha: MultiHeadAttention<B>,
+    pwff: FeedForward<B>,
+    norm_1: LayerNorm<B>,
+    norm_2: LayerNorm<B>,
+    dropout: Dropout,
+}
+
+/// Position-wise feed-forward block, laid out like burn's
+/// `PositionWiseFeedForward` (which is the GELU case)
+#[derive(Module, Debug)]
+struct FeedForward<B: Backend> {
+    /// With SwiGLU, projects to the gate and the value side by side
+    linear_inner: Linear<B>,
+    linear_outer: Linear<B>,
+    dropout: Dropout,
+    activation: FfnActivation,
+}
+
+impl<B: Backend> FeedForward<B> {
+    fn new(config: &HopeConfig, shape: LevelShape, device: &B::Device) -> Self {
+        let d_ff = shape.feedforward_dim;
+        let inner = match config.ffn_activation {
+            FfnActivation::Swiglu => 2 * d_ff,
+            _ => d_ff,
+        };
+        Self {
+            linear_inner: LinearConfig::new(shape.hidden_size, inner).init(device),
+            linear_outer: LinearConfig::new(d_ff, shape.hidden_size).init(device),
+            dropout: DropoutConfig::new(config.dropout).init(),
+            activation: config.ffn_activation,
+        }
+    }
+
+    fn forward(&self, input: Tensor<B, 3>) -> Tensor<B, 3> {
+        let x = self.linear_inner.forward(input);
+        let x = match self.activation {
+            FfnActivation::Gelu => gelu(x),
+            FfnActivation::Relu => relu(x),
+            FfnActivation::Silu => silu(x),
+            FfnActivation::Swiglu => {
+                let [gate, value]: [Tensor<B, 3>; 2] = x.chunk(2, 2).try_into().expect("inner projection has an even width");
+                silu(gate) * value
+            }
+        };
+        self.linear_outer.forward(self.dropout.forward(x))
+    }
+
+    /// Magnitude of each neuron: the norm of its inner weights (gate and
+    /// value with SwiGLU) times the norm of its outer weights
+    fn neuron_scores(&self) -> Vec<f32> {
+        let [_, inner_width] = self.linear_inner.weight.dims();
+        let d_ff = self.linear_outer.weight.dims()[0];
+        let inner = self.linear_inner.weight.val().powf_scalar(2.0).sum_dim(0).reshape([inner_width / d_ff, d_ff]).sum_dim(0);
+        let outer = self.linear_outer.weight.val().powf_scalar(2.0).sum_dim(1).reshape([1, d_ff]);
+        (inner * outer).sqrt().into_data().convert::<f32>().to_vec().expect("f32 tensor data")
+    }
+
+    /// Zero the outer weights of the `pruned` neurons, or remove them
+    fn prune(mut self, pruned: &[usize], remove: bool) -> Self {
+        let d_ff = self.linear_outer.weight.dims()[0];
+        let device = self.linear_outer.weight.device();
+        if !remove {
+            let keep: Vec<f32> = (0..d_ff).map(|neuron| if pruned.contains(&neuron) { 0.0 } else { 1.0 }).collect();
+            let keep = Tensor::<B, 1>::from_floats(keep.as_slice(), &device).reshape([d_ff, 1]);
+            self.linear_outer.weight = self.linear_outer.weight.map(|weight| weight * keep);
+            return self;
+        }
+
+        let kept: Vec<i64> = (0..d_ff).filter(|neuron| !pruned.contains(neuron)).map(|neuron| neuron as i64).collect();
+        // With SwiGLU, each neuron has a gate and a value column
+        let halves = self.linear_inner.weight.dims()[1] / d_ff;
+        let inner: Vec<i64> = (0..halves as i64).flat_map(|half| kept.iter().map(move |&neuron| half * d_ff as i64 + neuron)).collect();
+        let kept = Tensor::<B, 1, Int>::from_ints(kept.as_slice(), &device);
+        let inner = Tensor::<B, 1, Int>::from_ints(inner.as_slice(), &device);
+
+        self.linear_inner.weight = self.linear_inner.weight.map(|weight| weight.select(1, inner.clone()));
+        self.linear_inner.bias = self.linear_inner.bias.map(|bias| bias.map(|bias| bias.select(0, inner)));
+        self.linear_outer.weight = self.linear_outer.weight.map(|weight| weight.select(0, kept));
+        self
+    }
+
+    fn onnx(&self, graph: &mut OnnxGraph, input: &str) -> String {
+        let x = graph.linear(&self.linear_inner, input);
+        let x = match self.activation {
+            FfnActivation::Gelu => graph.gelu(&x),
+            FfnActivation::Relu => graph.node("Relu", &[&x], Vec::new()),
+            FfnActivation::Silu => graph.silu(&x),
+            FfnActivation::Swiglu => {
+                let width = self.linear_outer.weight.dims()[0] as i64;
+                let split = graph.int64s(&[width, width]);
+                let halves = graph.node_outputs("Split", &[&x, &split], vec![("axis", Attribute::Int(-1))], 2);
+                let gate = graph.silu(&halves[0]);
+                graph.mul(&gate, &halves[1])
+            }
+        };
+        graph.linear(&self.linear_outer, &x)
+    }
+}
+
+/// Which keys each query attends to, the same for every layer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AttentionPattern {
+    /// Only attend to the same and earlier positions
+    pub causal: bool,
+    /// Only attend to positions less than this far away. Long inputs are
+    /// then encoded block by block, in O(len * window) time and memory.
+    pub window: Option<usize>,
+    /// Add ALiBi distance biases to the scores
+    pub alibi: bool,
+}
+
+impl AttentionPattern {
+    fn masks(&self, query: usize, key: usize) -> bool {
+        (self.causal && key > query) || self.window.is_some_and(|window| query.abs_diff(key) >= window)
+    }
+}
+
+/// Keys and values ([batch, heads, len, d_k]) of every layer for the
+/// positions encoded so far; with a window, only for the last `window`
+pub struct EncoderCache<B: Backend> {
+    /// Positions encoded so far
+    len: usize,
+    layers: Vec<Option<(Tensor<B, 4>, Tensor<B, 4>)>>,
+}
+
+/// Mask (true = hidden) and bias of the attention scores of one encoder
+/// call, shared by its layers
+enum ScoreLayout<B: Backend> {
+    /// Every query against every key kept: [1, 1 | heads, queries, keys]
+    Dense {
+        mask: Option<Tensor<B, 4, Bool>>,
+        bias: Option<Tensor<B, 4>>,
+    },
+    /// Blocks of `window` queries against their own block and the
+    /// blocks around it (`neighbours` in all): [1, 1 | heads, blocks, window, neighbours * window]
+    Blocked {
+        window: usize,
+        neighbours: usize,
+        mask: Tensor<B, 5, Bool>,
+        bias: Option<Tensor<B, 5>>,
+    },
+}
+
+impl<B: Backend> LevelEncoder<B> {
+    /// Encoder of level `level` of `config`
+    pub fn new(config: &HopeConfig, level: usize, device: &B::Device) -> Self {
+        let shape = config.level_shape(level);
+        let layers = (0..shape.num_layers)
+            .map(|_| EncoderLayer {
+                mha: MultiHeadAttentionConfig::new(shape.hidden_size, shape.num_heads)
+                    .with_dropout(config.dropout)
+                    .init(device),
+                norm_1: LayerNormConfig::new(shape.hidden_size).init(device),
+                norm_2: LayerNormConfig::new(shape.hidden_size).init(device),
+                dropout: DropoutConfig::new(config.dropout).init(),
+                pwff: FeedForward::new(config, shape, device),
+            })
+            .collect();
+        let projection = |from, to| (from != to).then(|| LinearConfig::new(from, to).init(device));
+
+        Self {
+            input_proj: projection(config.hidden_size, shape.hidden_size),
+            layers,
+            output_proj: projection(shape.hidden_size, config.hidden_size),
+        }
+    }
+
+    /// Encode `input` ([batch, len, hidden]), attending as `pattern` allows
+    pub fn forward(&self, input: Tensor<B, 3>, pattern: &AttentionPattern) -> Tensor<B, 3> {
+        let layout = self.layout(pattern, 0, input.dims()[1], &input.device());
+        self.encode(input, &layout)
+    }
+
+    /// ONNX nodes of `forward` over `len` positions of `input`. Windows are
+    /// encoded densely, with the out-of-window scores masked: the same
+    /// result as the blocked layout, in O(len^2) memory.
+    pub fn onnx(&self, graph: &mut OnnxGraph, input: &str, pattern: &AttentionPattern, len: usize, device: &B::Device) -> String {
+        // Masked scores are lowered by `min_float` instead of replaced by it:
+        // either way they vanish from the softmax
+        let bias = match self.dense_layout(pattern, 0, len, device) {
+            ScoreLayout::Dense { mask, bias } => {
+                let min_float = self.layers.first().map_or(-1.0e4, |layer| layer.mha.min_float) as f32;
+                let mask = mask.map(|mask| mask.float() * min_float);
+                match (mask, bias) {
+                    (Some(mask), Some(bias)) => Some(graph.tensor(mask + bias)),
+                    (mask, bias) => mask.or(bias).map(|scores| graph.tensor(scores)),
+                }
+            }
+            ScoreLayout::Blocked { .. } => unreachable!("dense layout from the first position"),
+        };
+
+        let mut x = match self.input_proj {
+            Some(ref proj) => graph.linear(proj, input),
+            None => input.to_string(),
+        };
+        for layer in &self.layers {
+            x = layer.onnx(graph, &x, bias.as_deref());
+        }
+        match self.output_proj {
+            Some(ref proj) => graph.linear(proj, &x),
+            None => x,
+        }
+    }
+
+    /// Prune the lowest-magnitude feed-forward neurons and attention heads
+    /// of every layer; the report counts the pruned units
+    pub fn prune(mut self, options: &PruneOptions) -> (Self, PruneReport) {
+        let mut report = PruneReport::default();
+        self.layers = self
+            .layers
+            .into_iter()
+            .map(|mut layer| {
+                let scores = layer.pwff.neuron_scores();
+                let pruned = lowest(&scores, prune_count(scores.len(), options.ffn_ratio));
+                report.ffn_neurons += pruned.len();
+                layer.pwff = layer.pwff.prune(&pruned, options.remove);
+
+                let scores = layer.head_scores();
+                let pruned = lowest(&scores, prune_count(scores.len(), options.head_ratio));
+                report.heads += pruned.len();
+                layer.zero_heads(&pruned)
+            })
+            .collect();
+        (self, report)
+    }
+
+    /// The encoder with dropout probability `prob` in every layer
+    pub fn with_dropout(mut self, prob: f64) -> Self {
+        for layer in &mut self.layers {
+            layer.dropout.prob = prob;
+            layer.mha.dropout.prob = prob;
+            layer.pwff.dropout.prob = prob;
+        }
+        self
+    }
+
+    /// Feed-forward width of the layers
+    pub fn feedforward_dim(&self) -> usize {
+        self.layers.first().map_or(0, |layer| layer.pwff.linear_outer.weight.dims()[0])
+    }
+
+    fn encode(&self, input: Tensor<B, 3>, layout: &ScoreLayout<B>) -> Tensor<B, 3> {
+        let x = self
+            .layers
+            .iter()
+            .fold(self.project_in(input), |x, layer| layer.forward(x, layout, None));
+        self.project_out(x)
+    }
+
+    pub fn new_cache(&self) -> EncoderCache<B> {
+        EncoderCache { len: 0, layers: vec![None; self.layers.len()] }
+    }
+
+    /// `forward` of positions following those in `cache`: `input` holds only
+    /// the new positions, which attend to the cached ones as well. Their keys
+    /// and values are added to `cache`.
+    pub fn forward_cached(
+        &self,
+        input: Tensor<B, 3>,
+        pattern: &AttentionPattern,
+        cache: &mut EncoderCache<B>,
+    ) -> Tensor<B, 3> {
+        let len = input.dims()[1];
+        let layout = self.layout(pattern, cache.len, len, &input.device());
+        let x = self
+            .layers
+            .iter()
+            .zip(cache.layers.iter_mut())
+            .fold(self.project_in(input), |x, (layer, cache)| layer.forward(x, &layout, Some(cache)));
+        cache.len += len;
+
+        // Later positions never attend further back than the window
+        if let Some(window) = pattern.window {
+            for (key, value) in cache.layers.iter_mut().flatten() {
+                let [batch, heads, kept, d_k] = key.dims();
+                if kept > window {
+                    let last = [0..batch, 0..heads, kept - window..kept, 0..d_k];
+                    *key = key.clone().slice(last.clone());
+                    *value = value.clone().slice(last);
+                }
+            }
+        }
+        self.project_out(x)
+    }
+
+    /// Scores layout of `queries` positions from `start`, after the cached
+    /// ones. A window shorter than a full pass splits it into blocks.
+    fn layout(&self, pattern: &AttentionPattern, start: usize, queries: usize, device: &B::Device) -> ScoreLayout<B> {
+        match pattern.window.filter(|&window| start == 0 && queries > window) {
+            Some(window) => self.blocked_layout(pattern, window, queries, device),
+            None => self.dense_layout(pattern, start, queries, device),
+        }
+    }
+
+    fn heads(&self) -> usize {
+        self.layers.first().map_or(1, |layer| layer.mha.n_heads)
+    }
+
+    /// Queries in blocks of `window`, each against the previous block, its
+    /// own and (unless causal) the next: every key within the window of its
+    /// queries, and only out-of-window keys are masked
+    fn blocked_layout(&self, pattern: &AttentionPattern, window: usize, queries: usize, device: &B::Device) -> ScoreLayout<B> {
+        let heads = self.heads();
+        let blocks = queries.div_ceil(window);
+        let neighbours = if pattern.causal { 2 } else { 3 };
+        let context = neighbours * window;
+        // Key `k` of a block starting at `first` sits at `first + k - window`
+        let key_position = |first: usize, k: usize| (first + k).checked_sub(window).filter(|&key| key < queries);
+
+        let mut mask = Vec::with_capacity(blocks * window * context);
+        for first in (0..blocks).map(|block| block * window) {
+            for query in first..first + window {
+                mask.extend((0..context).map(|k| match key_position(first, k) {
+                    Some(key) => pattern.masks(query, key),
+                    None => true,
+                }));
+            }
+        }
+        let bias = pattern.alibi.then(|| {
+            let mut values = Vec::with_capacity(heads * blocks * window * context);
+            for slope in alibi_slopes(heads) {
+                for first in (0..blocks).map(|block| block * window) {
+                    for query in first..first + window {
+                        values.extend((0..context).map(|k| {
+                            let key = (first + k).saturating_sub(window);
+                            -slope * query.abs_diff(key) as f32
+                        }));
+                    }
+                }
+            }
+            Tensor::from_data(TensorData::new(values, [1, heads, blocks, window, context]), device)
+        });
+        let mask = Tensor::from_data(TensorData::new(mask, [1, 1, blocks, window, context]), device);
+        ScoreLayout::Blocked { window, neighbours, mask, bias }
+    }
+
+    fn dense_layout(&self, pattern: &AttentionPattern, start: usize, queries: usize, device: &B::Device) -> ScoreLayout<B> {
+        // Keys kept from earlier calls start at `first_key`
+        let first_key = pattern.window.map_or(0, |window| start.saturating_sub(window));
+        let keys = first_key..start + queries;
+        let mask: Vec<bool> = (start..start + queries)
+            .flat_map(|query| keys.clone().map(move |key| pattern.masks(query, key)))
+            .collect();
+        let mask = mask.contains(&true).then(|| {
+            Tensor::from_data(TensorData::new(mask, [1, 1, queries, keys.len()]), device)
+        });
+        let bias = pattern.alibi.then(|| alibi_bias(self.heads(), start..start + queries, keys, device));
+        ScoreLayout::Dense { mask, bias }
+    }
+
+    fn project_in(&self, input: Tensor<B, 3>) -> Tensor<B, 3> {
+        match self.input_proj {
+            Some(ref proj) => proj.forward(input),
+            None => input,
+        }
+    }
+
+    fn project_out(&self, output: Tensor<B, 3>) -> Tensor<B, 3> {
+        match self.output_proj {
+            Some(ref proj) => proj.forward(output),
+            None => output,
+        }
+    }
+}
+
+impl<B: Backend> EncoderLayer<B> {
+    fn forward(
+        &self,
+        input: Tensor<B, 3>,
+        layout: &ScoreLayout<B>,
+        cache: Option<&mut Option<(Tensor<B, 4>, Tensor<B, 4>)>>,
+    ) -> Tensor<B, 3> {
+        let residual = self.attention(self.norm_2.forward(input.clone()), layout, cache);
+        let x = input + self.dropout.forward(residual);
+
+        let residual = self.pwff.forward(self.norm_1.forward(x.clone()));
+        x + self.dropout.forward(residual)
+    }
+
+    /// Magnitude of each head: the norm of its value projection columns
+    /// times the norm of its output projection rows
+    fn head_scores(&self) -> Vec<f32> {
+        let mha = &self.mha;
+        let d_model = mha.n_heads * mha.d_k;
+        let value = mha.value.weight.val().powf_scalar(2.0).reshape([d_model, mha.n_heads, mha.d_k]).sum_dim(2).sum_dim(0);
+        let output = mha.output.weight.val().powf_scalar(2.0).reshape([mha.n_heads, mha.d_k * d_model]).sum_dim(1);
+        (value.reshape([mha.n_heads]) * output.reshape([mha.n_heads]))
+            .sqrt()
+            .into_data()
+            .convert::<f32>()
+            .to_vec()
+            .expect("f32 tensor data")
+    }
+
+    /// Zero the output projection rows of the `pruned` heads, so they no
+    /// longer contribute to the layer's output
+    fn zero_heads(mut self, pruned: &[usize]) -> Self {
+        if pruned.is_empty() {
+            return self;
+        }
+        let (heads, d_k) = (self.mha.n_heads, self.mha.d_k);
+        let keep: Vec<f32> = (0..heads * d_k).map(|row| if pruned.contains(&(row / d_k)) { 0.0 } else { 1.0 }).collect();
+        let keep = Tensor::<B, 1>::from_floats(keep.as_slice(), &self.mha.output.weight.device()).reshape([heads * d_k, 1]);
+        self.mha.output.weight = self.mha.output.weight.map(|weight| weight * keep);
+        self
+    }
+
+    fn onnx(&self, graph: &mut OnnxGraph, input: &str, bias: Option<&str>) -> String {
+        let normed = graph.layer_norm(&self.norm_2, input);
+        let residual = self.attention_onnx(graph, &normed, bias);
+        let x = graph.add(input, &residual);
+
+        let normed = graph.layer_norm(&self.norm_1, &x);
+        let residual = self.pwff.onnx(graph, &normed);
+        graph.add(&x, &residual)
+    }
+
+    /// ONNX nodes of dense `attention` with the combined mask and bias
+    /// `bias` ([1, 1 | heads, len, len])
+    fn attention_onnx(&self, graph: &mut OnnxGraph, x: &str, bias: Option<&str>) -> String {
+        let mha = &self.mha;
+        let heads = |graph: &mut OnnxGraph, linear: &Linear<B>, perm: &[i64]| {
+            let projected = graph.linear(linear, x);
+            let split = graph.reshape(&projected, &[0, 0, mha.n_heads as i64, mha.d_k as i64]);
+            graph.transpose(&split, perm)
+        };
+        let query = heads(graph, &mha.query, &[0, 2, 1, 3]);
+        let key = heads(graph, &mha.key, &[0, 2, 3, 1]);
+        let value = heads(graph, &mha.value, &[0, 2, 1, 3]);
+
+        let scores = graph.matmul(&query, &key);
+        let scale = graph.scalar((mha.d_k as f32).sqrt());
+        let mut scores = graph.node("Div", &[&scores, &scale], Vec::new());
+        if let Some(bias) = bias {
+            scores = graph.add(&scores, bias);
+        }
+        let weights = graph.softmax(&scores);
+        let context = graph.matmul(&weights, &value);
+        let context = graph.transpose(&context, &[0, 2, 1, 3]);
+        let context = graph.reshape(&context, &[0, 0, (mha.n_heads * mha.d_k) as i64]);
+        graph.linear(&mha.output, &context)
+    }
+
+    /// Self-attention of `MultiHeadAttention`, with the layout's bias added
+    /// to the scaled scores before masking
+    fn attention(
+        &self,
+        x: Tensor<B, 3>,
+        layout: &ScoreLayout<B>,
+        cache: Option<&mut Option<(Tensor<B, 4>, Tensor<B, 4>)>>,
+    ) -> Tensor<B, 3> {
+        let mha = &self.mha;
+        let [batch, len, d_model] = x.dims();
+        let heads = |linear: &Linear<B>| {
+            linear
+                .forward(x.clone())
+                .reshape([batch, len, mha.n_heads, mha.d_k])
+                .swap_dims(1, 2)
+        };
+        let query = heads(&mha.query);
+        let mut key = heads(&mha.key);
+        let mut value = heads(&mha.value);
+        if let Some(cache) = cache {
+            if let Some((past_key, past_value)) = cache.take() {
+                key = Tensor::cat(vec![past_key, key], 2);
+                value = Tensor::cat(vec![past_value, value], 2);
+            }
+            *cache = Some((key.clone(), value.clone()));
+        }
+        let scale = (mha.d_k as f32).sqrt();
+
+        let context = match layout {
+            ScoreLayout::Dense { mask, bias } => {
+                let mut scores = mha.dropout.forward(query.matmul(key.transpose()).div_scalar(scale));
+                if let Some(bias) = bias {
+                    scores = scores + bias.clone();
+                }
+                if let Some(mask) = mask {
+                    scores = scores.mask_fill(mask.clone(), mha.min_float);
+                }
+                self.softmax(scores).matmul(value)
+            }
+            ScoreLayout::Blocked { window, neighbours, mask, bias } => {
+                let window = *window;
+                let blocks = len.div_ceil(window);
+                let blocked = |t: Tensor<B, 4>| {
+                    let t = match blocks * window - len {
+                        0 => t,
+                        pad => Tensor::cat(vec![t, Tensor::zeros([batch, mha.n_heads, pad, mha.d_k], &x.device())], 2),
+                    };
+                    t.reshape([batch, mha.n_heads, blocks, window, mha.d_k])
+                };
+                // Each block's keys: the previous block, its own (and the next)
+                let around = |t: Tensor<B, 4>| {
+                    let t = blocked(t);
+                    let dims = [batch, mha.n_heads, 1, window, mha.d_k];
+                    let zeros = Tensor::<B, 5>::zeros(dims, &t.device());
+                    let shifted = |from: usize| t.clone().slice([0..batch, 0..mha.n_heads, from..from + blocks - 1]);
+                    let mut parts = vec![Tensor::cat(vec![zeros.clone(), shifted(0)], 2), t.clone()];
+                    if *neighbours == 3 {
+                        parts.push(Tensor::cat(vec![shifted(1), zeros], 2));
+                    }
+                    Tensor::cat(parts, 3)
+                };
+
+                let (key, value) = (around(key), around(value));
+                let mut scores = mha.dropout.forward(blocked(query).matmul(key.swap_dims(3, 4)).div_scalar(scale));
+                if let Some(bias) = bias {
+                    scores = scores + bias.clone();
+                }
+                let scores = scores.mask_fill(mask.clone(), mha.min_float);
+                self.softmax(scores)
+                    .matmul(value)
+                    .reshape([batch, mha.n_heads, blocks * window, mha.d_k])
+                    .slice([0..batch, 0..mha.n_heads, 0..len])
+            }
+        };
+
+        let context = context.swap_dims(1, 2).reshape([batch, len, d_model]);
+        mha.output.forward(context)
+    }
+
+    /// Softmax of `scores` over the keys (the last dimension)
+    fn softmax<const D: usize>(&self, scores: Tensor<B, D>) -> Tensor<B, D> {
+        if self.mha.quiet_softmax {
+            quiet_softmax(scores, D - 1)
+        } else {
+            softmax(scores, D - 1)
+        }
+    }
+}
+
+/// ALiBi head slopes (Press et al., 2022): the geometric sequence
+/// `2^(-8/n), 2^(-16/n), ..` for `n` heads, completed from the sequence of
+/// twice as many heads when `n` is not a power of two
+pub fn alibi_slopes(num_heads: usize) -> Vec<f32> {
+    let geometric = |n: usize| -> Vec<f32> {
+        (1..=n).map(|i| 2f32.powf(-8.0 * i as f32 / n as f32)).collect()
+    };
+    let closest = 1 << num_heads.max(1).ilog2();
+    let mut slopes = geometric(closest);
+    slopes.extend(geometric(2 * closest).into_iter().step_by(2).take(num_heads - closest));
+    slopes
+}
+
+/// ALiBi attention bias ([1, heads, queries, keys]) of queries over keys at
+/// the given positions: each score is lowered by the head's slope times the
+/// query-key distance
+pub fn alibi_bias<B: Backend>(
+    num_heads: usize,
+    queries: Range<usize>,
+    keys: Range<usize>,
+    device: &B::Device,
+) -> Tensor<B, 4> {
+    let shape = [1, num_heads, queries.len(), keys.len()];
+    let mut values = Vec::with_capacity(shape.iter().product());
+    for slope in alibi_slopes(num_heads) {
+        for query in queries.clone() {
+            values.extend(keys.clone().map(|key| -slope * query.abs_diff(key) as f32));
+        }
+    }
+    Tensor::from_data(TensorData::new(values, shape), device)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use burn::nn::attention::generate_autoregressive_mask;
+    use burn::nn::transformer::{TransformerEncoderConfig, TransformerEncoderInput};
+    use burn::record::{FullPrecisionSettings, NamedMpkBytesRecorder, Recorder};
+    use burn::tensor::Distribution;
+    use burn_ndarray::NdArray;
+
+    type B = NdArray<f32>;
+
+    fn close(a: Tensor<B, 3>, b: Tensor<B, 3>) -> bool {
+        let (a, b) = (a.into_data().to_vec::<f32>().unwrap(), b.into_data().to_vec::<f32>().unwrap());
+        a.len() == b.len() && a.iter().zip(&b).all(|(x, y)| (x - y).abs() < 1e-4)
+    }
+
+    #[test]
+    fn test_loads_burn_encoder_records() {
+        let device = Default::default();
+        let config = HopeConfig { num_layers: 2, ..HopeConfig::tiny() };
+        let burn_encoder = TransformerEncoderConfig::new(16, config.feedforward_dim(), 2, 2)
+            .with_norm_first(true)
+            .init::<B>(&device);
+
+        let recorder = NamedMpkBytesRecorder::<FullPrecisionSettings>::default();
+        let bytes = recorder.record(burn_encoder.clone().into_record(), ()).unwrap();
+        let encoder = LevelEncoder::<B>::new(&config, 0, &device)
+            .load_record(recorder.load(bytes, &device).unwrap());
+
+        let input = Tensor::<B, 3>::random([2, 5, 16], Distribution::Default, &device);
+        let mask = generate_autoregressive_mask::<B>(2, 5, &device);
+        let expected = burn_encoder.forward(TransformerEncoderInput::new(input.clone()).mask_attn(mask));
+        let causal = AttentionPattern { causal: true, ..Default::default() };
+        assert!(close(encoder.forward(input, &causal), expected));
+    }
+
+    #[test]
+    fn test_cached_positions_match_full_pass() {
+        let device = Default::default();
+        let config = HopeConfig { num_heads: 4, num_layers: 2, ..HopeConfig::tiny() };
+        let encoder = LevelEncoder::<B>::new(&config, 0, &device);
+        let input = Tensor::<B, 3>::random([2, 9, 16], Distribution::Default, &device);
+
+        for window in [None, Some(3)] {
+            let pattern = AttentionPattern { causal: true, window, alibi: true };
+            let full = encoder.forward(input.clone(), &pattern);
+
+            // Five positions at once, then one at a time
+            let mut cache = encoder.new_cache();
+            let mut rows = vec![encoder.forward_cached(input.clone().slice([0..2, 0..5, 0..16]), &pattern, &mut cache)];
+            for position in 5..9 {
+                let row = input.clone().slice([0..2, position..position + 1, 0..16]);
+                rows.push(encoder.forward_cached(row, &pattern, &mut cache));
+            }
+            assert!(close(Tensor::cat(rows, 1), full));
+        }
+    }
+
+    #[test]
+    fn test_windowed_blocks_match_dense_attention() {
+        let device = Default::default();
+        let config = HopeConfig { num_heads: 4, num_layers: 2, ..HopeConfig::tiny() };
+        let encoder = LevelEncoder::<B>::new(&config, 0, &device);
+        let input = Tensor::<B, 3>::random([2, 11, 16], Distribution::Default, &device);
+
+        for causal in [false, true] {
+            for alibi in [false, true] {
+                let pattern = AttentionPattern { causal, window: Some(3), alibi };
+                let dense = encoder.encode(input.clone(), &encoder.dense_layout(&pattern, 0, 11, &device));
+                assert!(close(encoder.forward(input.clone(), &pattern), dense));
+
+                // A window spanning the input changes nothing
+                let spanning = AttentionPattern { window: Some(11), ..pattern };
+                let unwindowed = AttentionPattern { window: None, ..pattern };
+                assert!(close(encoder.forward(input.clone(), &spanning), encoder.forward(input.clone(), &unwindowed)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_ffn_activations() {
+        let device = Default::default();
+        let input = Tensor::<B, 3>::random([2, 3, 16], Distribution::Default, &device);
+        let encoder = |ffn_activation| {
+            let config = HopeConfig { num_layers: 2, ffn_activation, ..HopeConfig::tiny() };
+            LevelEncoder::<B>::new(&config, 0, &device)
+        };
+
+        for activation in [FfnActivation::Gelu, FfnActivation::Relu, FfnActivation::Silu, FfnActivation::Swiglu] {
+            let output = encoder(activation).forward(input.clone(), &AttentionPattern::default());
+            assert_eq!(output.dims(), [2, 3, 16]);
+            assert!(output.into_data().to_vec::<f32>().unwrap().iter().all(|v| v.is_finite()));
+        }
+        // The gate doubles the inner projection: 16 -> 64 weights and biases per layer
+        let extra = encoder(FfnActivation::Swiglu).num_params() - encoder(FfnActivation::Gelu).num_params();
+        assert_eq!(extra, 2 * (16 * 64 + 64));
+    }
+
+    #[test]
+    fn test_alibi_slopes() {
+        assert_eq!(alibi_slopes(4), [0.25, 0.0625, 0.015625, 0.00390625]);
+        // Two heads past a power of two take the odd slopes of 16 heads
+        let slopes = alibi_slopes(10);
+        assert_eq!(slopes.len(), 10);
+        assert_eq!(slopes[8..], [2f32.powf(-0.5), 2f32.powf(-1.5)]);
+
+        let bias = alibi_bias::<B>(2, 1..3, 0..3, &Default::default()).into_data().to_vec::<f32>().unwrap();
+        assert_eq!(bias[..6], [-0.0625, 0.0, -0.0625, -0.125, -0.0625, 0.0]);
+    }
+}
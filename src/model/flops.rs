@@ -0,0 +1,152 @@
+use serde::Serialize;
+use crate::config::{FfnActivation, HopeConfig, LevelFusionMode};
+
+/// Analytical forward FLOPs per token of a model configuration, for
+/// sequences of `seq_len` tokens. A multiply-add counts as 2 FLOPs;
+/// element-wise work (norms, softmaxes, EMAs) and the self-modification
+/// meta network, which reads one position per sequence, are left out. A
+/// training step (forward and backward) costs about three times as much.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FlopsEstimate {
+    /// Encoder and self-modification of each level, over all of its
+    /// timescale steps
+    pub levels: Vec<f64>,
+    /// Continuum memory retrievals and update
+    pub memory: f64,
+    /// Level fusion and output head
+    pub head: f64,
+    pub total: f64,
+}
+
+impl FlopsEstimate {
+    pub fn new(config: &HopeConfig) -> Self {
+        let hidden = config.hidden_size as f64;
+        let seq_len = config.seq_len.max(1) as f64;
+        // Keys each query is scored against
+        let context = config.attention_window.map_or(seq_len, |window| (window as f64).min(seq_len));
+
+        let self_modify = if config.self_modify.enabled {
+            let meta = config.self_modify.weight_mod_dim as f64;
+            2.0 * (2.0 * hidden * meta + meta * meta)
+        } else {
+            0.0
+        };
+        let levels: Vec<f64> = (0..config.num_levels)
+            .map(|level| {
+                let shape = config.level_shape(level);
+                let width = shape.hidden_size as f64;
+                let ff = shape.feedforward_dim as f64;
+                let ffn = match config.ffn_activation {
+                    FfnActivation::Swiglu => 6.0 * width * ff,
+                    _ => 4.0 * width * ff,
+                };
+                // Q, K, V and output projections, scores and weighted values
+                let layer = 8.0 * width * width + 4.0 * context * width + ffn;
+                let projections = if shape.hidden_size == config.hidden_size { 0.0 } else { 4.0 * hidden * width };
+                let step = shape.num_layers as f64 * layer + projections + self_modify;
+                config.level_timescales[level] as f64 * step
+            })
+            .collect();
+
+        let memory = if config.continuum_mem.enabled { memory_flops(config) } else { 0.0 };
+
+        let levels_fused = match config.level_fusion {
+            LevelFusionMode::Concat => 2.0 * config.num_levels as f64 * hidden * hidden,
+            _ => 0.0,
+        };
+        let head = levels_fused + 2.0 * hidden * config.vocab_size as f64;
+
+        let total = levels.iter().sum::<f64>() + memory + head;
+        Self { levels, memory, head, total }
+    }
+}
+
+/// Per-token FLOPs of the continuum memory: every retrieval projects all
+/// banks to keys and values (amortized over the sequence) and scores the
+/// kept slots (compressing the update is a negligible pooling)
+fn memory_flops(config: &HopeConfig) -> f64 {
+    let mem = &config.continuum_mem;
+    let hidden = config.hidden_size as f64;
+    let seq_len = config.seq_len.max(1) as f64;
+    let slots = mem.memory_slots.map_or(seq_len, |slots| slots as f64);
+    let slot_dim = mem.slot_dim.map(|dim| dim as f64);
+
+    let banks = [seq_len, seq_len, seq_len, slots, slots];
+    let mut per_sequence = 0.0;
+    let mut kept = 0.0;
+    for (bank, &size) in banks.iter().enumerate() {
+        // Key and value projections
+        per_sequence += 4.0 * size * hidden * hidden;
+        match mem.top_k.get(bank).copied() {
+            Some(k) if k > 0 && (k as f64) < size => {
+                // Salience scores
+                per_sequence += 2.0 * size * hidden;
+                kept += k as f64;
+            }
+            _ => kept += size,
+        }
+    }
+    if let Some(dim) = slot_dim {
+        // Up-projection of the long and episodic slots
+        per_sequence += 2.0 * 2.0 * slots * dim * hidden;
+    }
+
+    // Query scores: with top-k retrieval, one per block mean plus the slots
+    // of the two best blocks
+    let (scored, attended) = match mem.retrieval_top_k.map(|k| k as f64) {
+        Some(k) if k < kept => {
+            // Block means
+            per_sequence += kept * hidden;
+            ((kept / k).ceil() + 2.0 * k, k)
+        }
+        _ => (kept, kept),
+    };
+    let gate = if mem.gated { 4.0 * hidden } else { 0.0 };
+    let retrieval = per_sequence / seq_len + 2.0 * hidden * hidden + 2.0 * scored * hidden + 2.0 * attended * hidden + gate;
+    let retrievals = if mem.retrieve_every_level { config.num_levels } else { 1 };
+
+    retrievals as f64 * retrieval
+}
+
+/// `flops` with an SI prefix, e.g. `12.3 MFLOP`
+pub fn format_flops(flops: f64) -> String {
+    let units = [(1e12, "TFLOP"), (1e9, "GFLOP"), (1e6, "MFLOP"), (1e3, "KFLOP")];
+    match units.iter().find(|(scale, _)| flops >= *scale) {
+        Some((scale, unit)) => format!("{:.1} {}", flops / scale, unit),
+        None => format!("{:.0} FLOP", flops),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flops_follow_timescales_and_memory() {
+        let mut config = HopeConfig {
+            ff_multiplier: 2.0,
+            num_levels: 2,
+            level_timescales: vec![1, 3],
+            ..HopeConfig::tiny()
+        };
+        config.continuum_mem.enabled = false;
+        config.self_modify.enabled = false;
+
+        // Projections 8*16^2, attention 4*4*16, feed-forward 4*16*32
+        let layer = 2048.0 + 256.0 + 2048.0;
+        let estimate = FlopsEstimate::new(&config);
+        assert_eq!(estimate.levels, [layer, 3.0 * layer]);
+        assert_eq!(estimate.memory, 0.0);
+        assert_eq!(estimate.head, 2.0 * 16.0 * 8.0);
+        assert_eq!(estimate.total, 4.0 * layer + 256.0);
+
+        config.continuum_mem.enabled = true;
+        let with_memory = FlopsEstimate::new(&config);
+        assert!(with_memory.memory > 0.0);
+        config.continuum_mem.retrieve_every_level = true;
+        assert_eq!(FlopsEstimate::new(&config).memory, 2.0 * with_memory.memory);
+
+        assert_eq!(format_flops(1.5e6), "1.5 MFLOP");
+        assert_eq!(format_flops(512.0), "512 FLOP");
+    }
+}
@@ -0,0 +1,91 @@
+use burn::module::{Module, Param};
+use burn::nn::{Linear, LinearConfig};
+use burn::tensor::{Tensor, activation, backend::Backend};
+use crate::config::LevelFusionMode;
+use super::onnx::OnnxGraph;
+
+/// Combines the outputs of all levels ([batch, seq_len, hidden] each) into
+/// the input of the head (`HopeConfig::level_fusion`)
+#[derive(Module, Debug)]
+pub struct LevelFusion<B: Backend> {
+    /// Softmax logits of the level weights (`weighted`), starting at a mean
+    weights: Option<Param<Tensor<B, 1>>>,
+    /// Projection of the concatenated levels (`concat`)
+    proj: Option<Linear<B>>,
+}
+
+impl<B: Backend> LevelFusion<B> {
+    /// `None` for `last`, which feeds the last level to the head unchanged
+    pub fn new(mode: LevelFusionMode, num_levels: usize, hidden_size: usize, device: &B::Device) -> Option<Self> {
+        let (weights, proj) = match mode {
+            LevelFusionMode::Last => return None,
+            LevelFusionMode::Sum => (None, None),
+            LevelFusionMode::Weighted => (Some(Param::from_tensor(Tensor::zeros([num_levels], device))), None),
+            LevelFusionMode::Concat => (None, Some(LinearConfig::new(num_levels * hidden_size, hidden_size).init(device))),
+        };
+        Some(Self { weights, proj })
+    }
+
+    pub fn forward(&self, levels: Vec<Tensor<B, 3>>) -> Tensor<B, 3> {
+        if let Some(ref proj) = self.proj {
+            return proj.forward(Tensor::cat(levels, 2));
+        }
+        let stacked = Tensor::stack::<4>(levels, 0);
+        match self.weights {
+            Some(ref weights) => {
+                let weights = activation::softmax(weights.val(), 0).reshape([-1, 1, 1, 1]);
+                (stacked * weights).sum_dim(0).squeeze_dim(0)
+            }
+            None => stacked.sum_dim(0).squeeze_dim(0),
+        }
+    }
+
+    /// ONNX nodes of `forward`; the level weights become constants
+    pub fn onnx(&self, graph: &mut OnnxGraph, levels: &[String]) -> String {
+        let levels: Vec<&str> = levels.iter().map(String::as_str).collect();
+        if let Some(ref proj) = self.proj {
+            let concatenated = graph.concat(&levels, 2);
+            return graph.linear(proj, &concatenated);
+        }
+        let weighted: Vec<String> = match self.weights {
+            Some(ref weights) => {
+                let weights = activation::softmax(weights.val(), 0).into_data().convert::<f32>().to_vec::<f32>().expect("f32 tensor data");
+                levels.iter().zip(weights).map(|(level, weight)| graph.scale(level, weight)).collect()
+            }
+            None => levels.iter().map(|level| level.to_string()).collect(),
+        };
+        weighted
+            .into_iter()
+            .reduce(|sum, level| graph.add(&sum, &level))
+            .expect("a model has at least one level")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use burn::tensor::Distribution;
+    use burn_ndarray::NdArray;
+
+    type B = NdArray<f32>;
+
+    #[test]
+    fn test_fusion_modes() {
+        let device = Default::default();
+        let levels: Vec<Tensor<B, 3>> = (0..3)
+            .map(|_| Tensor::random([2, 4, 8], Distribution::Default, &device))
+            .collect();
+        let sum = levels.iter().cloned().reduce(|a, b| a + b).unwrap();
+
+        assert!(LevelFusion::<B>::new(LevelFusionMode::Last, 3, 8, &device).is_none());
+        let fused = LevelFusion::<B>::new(LevelFusionMode::Sum, 3, 8, &device).unwrap().forward(levels.clone());
+        fused.into_data().assert_approx_eq::<f32>(&sum.clone().into_data(), Default::default());
+
+        // Weights start uniform: the mean of the levels
+        let fused = LevelFusion::<B>::new(LevelFusionMode::Weighted, 3, 8, &device).unwrap().forward(levels.clone());
+        fused.into_data().assert_approx_eq::<f32>(&(sum / 3.0).into_data(), Default::default());
+
+        let fused = LevelFusion::<B>::new(LevelFusionMode::Concat, 3, 8, &device).unwrap().forward(levels);
+        assert_eq!(fused.dims(), [2, 4, 8]);
+    }
+}
@@ -0,0 +1,500 @@
+use burn::tensor::{Int, Tensor, backend::Backend};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
+use std::ops::ControlFlow;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::json_constraint::{JsonConstraint, JsonValidator};
+use super::HopeModel;
+use super::hope::HopeCarry;
+
+/// Sampling settings for `generate`
+#[derive(Debug, Clone)]
+pub struct GenerationConfig {
+    pub max_new_tokens: usize,
+    /// Softmax temperature; 0 picks the most likely token (greedy)
+    pub temperature: f32,
+    /// Sample only among the `top_k` most likely tokens
+    pub top_k: Option<usize>,
+    /// Nucleus sampling: sample only among the most likely tokens whose
+    /// probabilities (after temperature) add up to at least `top_p`
+    pub top_p: Option<f32>,
+    pub num_return_sequences: usize,
+    /// Subtracted from the score of a token for every other sequence that
+    /// already picked it at the same position, pushing samples apart
+    pub diversity_penalty: f32,
+    /// Seed for the sampling RNG (random when unset; see `resolved_seed`)
+    pub seed: Option<u64>,
+    /// Stop generating (keeping the tokens so far) once this much wall-clock
+    /// time has passed
+    pub timeout: Option<Duration>,
+    /// Constrain every sequence to a single JSON object or array, ending it
+    /// once the document is closed
+    pub json: Option<Arc<JsonConstraint>>,
+    /// End a sequence once the mean probability the model gave its last
+    /// `confidence_window` tokens drops below this (it has likely derailed)
+    pub min_confidence: Option<f32>,
+    pub confidence_window: usize,
+}
+
+impl Default for GenerationConfig {
+    fn default() -> Self {
+        Self {
+            max_new_tokens: 200,
+            temperature: 1.0,
+            top_k: None,
+            top_p: None,
+            num_return_sequences: 1,
+            diversity_penalty: 0.0,
+            seed: None,
+            timeout: None,
+            json: None,
+            min_confidence: None,
+            confidence_window: 8,
+        }
+    }
+}
+
+impl GenerationConfig {
+    pub fn validate(&self) {
+        assert!(self.temperature >= 0.0, "temperature must be >= 0");
+        assert!(self.top_k != Some(0), "top_k must be > 0");
+        assert!(
+            self.top_p.is_none_or(|p| p > 0.0 && p <= 1.0),
+            "top_p must be in (0, 1]"
+        );
+        assert!(self.num_return_sequences > 0, "num_return_sequences must be > 0");
+        assert!(self.diversity_penalty >= 0.0, "diversity_penalty must be >= 0");
+        assert!(self.confidence_window > 0, "confidence_window must be > 0");
+    }
+
+    /// Fix the seed, drawing a random one if unset, so the output can be
+    /// reproduced by passing the returned seed back in
+    pub fn resolved_seed(&mut self) -> u64 {
+        *self.seed.get_or_insert_with(rand::random)
+    }
+}
+
+/// A generated continuation
+#[derive(Debug, Clone, Serialize)]
+pub struct Candidate {
+    /// Generated tokens (without the prompt)
+    pub tokens: Vec<i64>,
+    /// Sum of the model's log-probs of the generated tokens (before
+    /// temperature and penalties)
+    pub log_prob: f32,
+    pub mean_log_prob: f32,
+    /// Generation hit `timeout` before `max_new_tokens`
+    pub timed_out: bool,
+    /// A `generate_with_callback` callback stopped generation early
+    pub stopped: bool,
+    /// JSON mode: whether the output is a complete document (false when the
+    /// token limit, the timeout or the vocabulary cut it short)
+    pub json_complete: Option<bool>,
+    /// Set when the sequence was truncated by `min_confidence`: the mean
+    /// confidence over the window that fell below the threshold
+    pub low_confidence: Option<f32>,
+}
+
+/// Sample `num_return_sequences` continuations of `prompt`, ranked by
+/// log-prob (most likely first). All sequences are decoded as one batch.
+pub fn generate<B: Backend>(
+    model: &HopeModel<B>,
+    prompt: &[i64],
+    config: &GenerationConfig,
+    device: &B::Device,
+) -> Vec<Candidate> {
+    let mut rng = match config.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    generate_with_rng(model, prompt, config, &mut rng, device)
+}
+
+/// `generate` starting from `carry` (one row per returned sequence), e.g. a
+/// persisted memory from `HopeModel::carry_from_memory`
+pub fn generate_from<B: Backend>(
+    model: &HopeModel<B>,
+    carry: HopeCarry<B>,
+    prompt: &[i64],
+    config: &GenerationConfig,
+    device: &B::Device,
+) -> Vec<Candidate> {
+    let mut rng = match config.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    decode(model, carry, prompt, config, &mut rng, device, |_, _, _| ControlFlow::Continue(()))
+}
+
+/// `generate` drawing samples from `rng` instead of an RNG seeded from
+/// `config.seed`
+pub fn generate_with_rng<B: Backend, R: Rng + ?Sized>(
+    model: &HopeModel<B>,
+    prompt: &[i64],
+    config: &GenerationConfig,
+    rng: &mut R,
+    device: &B::Device,
+) -> Vec<Candidate> {
+    let carry = model.initial_carry(config.num_return_sequences, device);
+    decode(model, carry, prompt, config, rng, device, |_, _, _| ControlFlow::Continue(()))
+}
+
+/// Generate a single continuation of `prompt` (`num_return_sequences` is
+/// ignored), calling `callback` with every token and its log-prob as soon as
+/// it is sampled. Returning `ControlFlow::Break` stops generation after that
+/// token, for streaming output or custom stop conditions.
+pub fn generate_with_callback<B: Backend, F>(
+    model: &HopeModel<B>,
+    prompt: &[i64],
+    config: &GenerationConfig,
+    device: &B::Device,
+    mut callback: F,
+) -> Candidate
+where
+    F: FnMut(i64, f32) -> ControlFlow<()>,
+{
+    let config = GenerationConfig { num_return_sequences: 1, ..config.clone() };
+    let mut rng = match config.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    decode(model, model.initial_carry(1, device), prompt, &config, &mut rng, device, |_, token, log_prob| {
+        callback(token, log_prob)
+    })
+        .pop()
+        .expect("decode returns one candidate per sequence")
+}
+
+/// Decode all sequences as one batch from `carry` with a `DecodeCache`,
+/// reporting every sampled token to
+/// `on_token(sequence, token, log_prob)`; a `Break` ends generation once the
+/// current position is filled for every sequence
+fn decode<B: Backend, R: Rng + ?Sized>(
+    model: &HopeModel<B>,
+    carry: HopeCarry<B>,
+    prompt: &[i64],
+    config: &GenerationConfig,
+    rng: &mut R,
+    device: &B::Device,
+    mut on_token: impl FnMut(usize, i64, f32) -> ControlFlow<()>,
+) -> Vec<Candidate> {
+    config.validate();
+    assert!(!prompt.is_empty(), "prompt must contain at least one token");
+
+    let n = config.num_return_sequences;
+    let mut sequences: Vec<Vec<i64>> = vec![prompt.to_vec(); n];
+    let mut log_probs = vec![0.0f32; n];
+    let deadline = config.timeout.map(|timeout| Instant::now() + timeout);
+    let mut timed_out = false;
+    let mut stopped = false;
+    // Length of each sequence once it has ended early (JSON mode or low confidence)
+    let mut ended: Vec<Option<usize>> = vec![None; n];
+    let mut validators = vec![JsonValidator::default(); n];
+    let mut confidences: Vec<Vec<f32>> = vec![Vec::new(); n];
+    let mut low_confidence: Vec<Option<f32>> = vec![None; n];
+    let mut cache = model.decode_cache(carry);
+    // Tokens of each sequence already in the decode cache
+    let mut fed = 0;
+
+    while !stopped
+        && ended.iter().any(Option::is_none)
+        && sequences[0].len() - prompt.len() < config.max_new_tokens
+    {
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            timed_out = true;
+            break;
+        }
+
+        let len = sequences[0].len();
+        let flat: Vec<i64> = sequences.iter().flat_map(|sequence| sequence[fed..].iter().copied()).collect();
+        let tokens = Tensor::<B, 1, Int>::from_ints(flat.as_slice(), device).reshape([n, len - fed]);
+        fed = len;
+
+        let logits = model
+            .decode(&mut cache, tokens)
+            .into_data()
+            .convert::<f32>()
+            .to_vec::<f32>()
+            .unwrap_or_default();
+        let vocab = logits.len() / n;
+
+        let mut chosen = Vec::with_capacity(n);
+        for (i, row) in logits.chunks(vocab).enumerate() {
+            let generated = sequences[i].len() - prompt.len();
+            let mut scores: Vec<f32> = row.to_vec();
+            if let Some(ref json) = config.json {
+                json.mask(&validators[i], &mut scores);
+                // Nothing in the vocabulary can continue the document
+                if ended[i].is_none() && scores.iter().all(|score| *score == f32::NEG_INFINITY) {
+                    ended[i] = Some(generated);
+                }
+            }
+            // Ended sequences keep the batch rectangular; the filler is cut off below
+            if ended[i].is_some() {
+                let filler = sequences[i][sequences[i].len() - 1];
+                sequences[i].push(filler);
+                continue;
+            }
+            for &token in &chosen {
+                scores[token] -= config.diversity_penalty;
+            }
+
+            let token = sample(&scores, config, rng);
+            let log_prob = log_softmax_at(row, token);
+            log_probs[i] += log_prob;
+            sequences[i].push(token as i64);
+            chosen.push(token);
+            if let Some(ref json) = config.json {
+                for c in json.piece(token).chars() {
+                    validators[i].push(c);
+                }
+                if validators[i].is_complete() {
+                    ended[i] = Some(generated + 1);
+                }
+            }
+            if let Some(threshold) = config.min_confidence {
+                confidences[i].push(log_prob.exp());
+                let window = config.confidence_window;
+                if confidences[i].len() >= window {
+                    let mean = confidences[i][confidences[i].len() - window..].iter().sum::<f32>() / window as f32;
+                    if mean < threshold && ended[i].is_none() {
+                        ended[i] = Some(generated + 1);
+                        low_confidence[i] = Some(mean);
+                    }
+                }
+            }
+            stopped |= on_token(i, token as i64, log_prob).is_break();
+        }
+    }
+
+    let mut candidates: Vec<Candidate> = sequences
+        .into_iter()
+        .zip(log_probs)
+        .zip(ended.into_iter().zip(&validators).zip(low_confidence))
+        .map(|((sequence, log_prob), ((ended, validator), low_confidence))| {
+            let mut tokens = sequence[prompt.len()..].to_vec();
+            if let Some(len) = ended {
+                tokens.truncate(len);
+            }
+            Candidate {
+                mean_log_prob: log_prob / tokens.len().max(1) as f32,
+                tokens,
+                log_prob,
+                timed_out,
+                stopped,
+                json_complete: config.json.is_some().then(|| validator.is_complete()),
+                low_confidence,
+            }
+        })
+        .collect();
+    candidates.sort_by(|a, b| b.log_prob.total_cmp(&a.log_prob));
+    candidates
+}
+
+/// Pick a token from raw scores: greedy at temperature 0, otherwise sampled
+/// from the `top_k` / `top_p` head of the tempered distribution
+fn sample<R: Rng + ?Sized>(scores: &[f32], config: &GenerationConfig, rng: &mut R) -> usize {
+    let mut ranked: Vec<usize> = (0..scores.len()).collect();
+    ranked.sort_by(|&a, &b| scores[b].total_cmp(&scores[a]));
+
+    if config.temperature == 0.0 {
+        return ranked[0];
+    }
+    if let Some(k) = config.top_k {
+        ranked.truncate(k);
+    }
+
+    let max = scores[ranked[0]];
+    let mut weights: Vec<f32> = ranked
+        .iter()
+        .map(|&token| ((scores[token] - max) / config.temperature).exp())
+        .collect();
+    if let Some(top_p) = config.top_p {
+        let total = weights.iter().sum::<f32>();
+        let mut mass = 0.0;
+        let nucleus = weights
+            .iter()
+            .position(|weight| {
+                mass += weight / total;
+                mass >= top_p
+            })
+            .map_or(weights.len(), |last| last + 1);
+        ranked.truncate(nucleus);
+        weights.truncate(nucleus);
+    }
+
+    let mut threshold = rng.gen::<f32>() * weights.iter().sum::<f32>();
+    for (&token, weight) in ranked.iter().zip(&weights) {
+        threshold -= weight;
+        if threshold <= 0.0 {
+            return token;
+        }
+    }
+    ranked[ranked.len() - 1]
+}
+
+fn log_softmax_at(logits: &[f32], index: usize) -> f32 {
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let log_sum = logits.iter().map(|l| (l - max).exp()).sum::<f32>().ln();
+    logits[index] - max - log_sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HopeConfig;
+    use burn_ndarray::NdArray;
+
+    #[test]
+    fn test_diversity_penalty_separates_greedy_candidates() {
+        let device = Default::default();
+        let model = HopeModel::<NdArray<f32>>::new(HopeConfig::tiny(), &device);
+        let generation = GenerationConfig {
+            max_new_tokens: 6,
+            temperature: 0.0,
+            num_return_sequences: 3,
+            diversity_penalty: 100.0,
+            ..Default::default()
+        };
+
+        let candidates = generate(&model, &[1, 2, 3], &generation, &device);
+        assert_eq!(candidates.len(), 3);
+        assert!(candidates.iter().all(|c| c.tokens.len() == 6));
+        assert!(candidates.windows(2).all(|w| w[0].log_prob >= w[1].log_prob));
+        // Each candidate starts with a different token
+        assert_ne!(candidates[0].tokens[0], candidates[1].tokens[0]);
+        assert_ne!(candidates[1].tokens[0], candidates[2].tokens[0]);
+        assert_ne!(candidates[0].tokens[0], candidates[2].tokens[0]);
+    }
+
+    #[test]
+    fn test_same_seed_reproduces_samples() {
+        let device = Default::default();
+        let model = HopeModel::<NdArray<f32>>::new(HopeConfig::tiny(), &device);
+        let mut generation = GenerationConfig {
+            max_new_tokens: 12,
+            num_return_sequences: 2,
+            ..Default::default()
+        };
+        let seed = generation.resolved_seed();
+        assert_eq!(generation.seed, Some(seed));
+
+        let first = generate(&model, &[1, 2], &generation, &device);
+        let second = generate(&model, &[1, 2], &generation, &device);
+        let injected = generate_with_rng(&model, &[1, 2], &generation, &mut StdRng::seed_from_u64(seed), &device);
+        for (a, b) in first.iter().zip(&second).chain(first.iter().zip(&injected)) {
+            assert_eq!(a.tokens, b.tokens);
+        }
+    }
+
+    #[test]
+    fn test_timeout_keeps_partial_output() {
+        let device = Default::default();
+        let model = HopeModel::<NdArray<f32>>::new(HopeConfig::tiny(), &device);
+        let generation = GenerationConfig {
+            max_new_tokens: 1000,
+            timeout: Some(Duration::ZERO),
+            ..Default::default()
+        };
+
+        let candidates = generate(&model, &[1, 2], &generation, &device);
+        assert!(candidates[0].timed_out);
+        assert!(candidates[0].tokens.is_empty());
+    }
+
+    #[test]
+    fn test_callback_streams_and_stops_generation() {
+        let device = Default::default();
+        let model = HopeModel::<NdArray<f32>>::new(HopeConfig::tiny(), &device);
+        let generation = GenerationConfig {
+            max_new_tokens: 10,
+            seed: Some(3),
+            ..Default::default()
+        };
+
+        let mut streamed = Vec::new();
+        let candidate = generate_with_callback(&model, &[1, 2], &generation, &device, |token, log_prob| {
+            assert!(log_prob <= 0.0);
+            streamed.push(token);
+            if streamed.len() == 4 {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+        assert!(candidate.stopped);
+        assert_eq!(candidate.tokens, streamed);
+        // Same samples as an uninterrupted run with the same seed
+        let full = generate(&model, &[1, 2], &generation, &device);
+        assert_eq!(full[0].tokens[..4], streamed[..]);
+    }
+
+    #[test]
+    fn test_json_mode_emits_valid_documents() {
+        let device = Default::default();
+        let model = HopeModel::<NdArray<f32>>::new(HopeConfig::tiny(), &device);
+        let pieces = ["", "{", "}", "[", "]", "1", ",", " "].map(String::from).to_vec();
+        let generation = GenerationConfig {
+            max_new_tokens: 40,
+            num_return_sequences: 3,
+            seed: Some(5),
+            json: Some(Arc::new(JsonConstraint::new(pieces.clone()))),
+            ..Default::default()
+        };
+
+        for candidate in generate(&model, &[1], &generation, &device) {
+            let text: String = candidate.tokens.iter().map(|&id| pieces[id as usize].as_str()).collect();
+            match candidate.json_complete {
+                Some(true) => assert!(serde_json::from_str::<serde_json::Value>(&text).is_ok(), "{}", text),
+                _ => assert!(JsonValidator::default().accepts(&text) || text.is_empty(), "{}", text),
+            }
+        }
+    }
+
+    #[test]
+    fn test_low_confidence_truncates() {
+        let device = Default::default();
+        let model = HopeModel::<NdArray<f32>>::new(HopeConfig::tiny(), &device);
+        let generation = GenerationConfig {
+            max_new_tokens: 20,
+            seed: Some(2),
+            confidence_window: 3,
+            ..Default::default()
+        };
+
+        // An untrained model over 8 tokens is never sure; a threshold of 1
+        // cuts every sequence after the first full window
+        let candidates = generate(&model, &[1], &GenerationConfig { min_confidence: Some(1.0), ..generation.clone() }, &device);
+        assert_eq!(candidates[0].tokens.len(), 3);
+        assert!(candidates[0].low_confidence.is_some_and(|mean| mean < 1.0));
+
+        let candidates = generate(&model, &[1], &GenerationConfig { min_confidence: Some(0.0), ..generation }, &device);
+        assert_eq!(candidates[0].tokens.len(), 20);
+        assert_eq!(candidates[0].low_confidence, None);
+    }
+
+    #[test]
+    fn test_top_p_keeps_the_nucleus() {
+        let mut rng = StdRng::seed_from_u64(0);
+        // Tempered probabilities of about 0.64, 0.24, 0.09, 0.03
+        let scores = [1.0, 3.0, 0.0, 2.0];
+        let config = GenerationConfig { top_p: Some(0.8), ..Default::default() };
+        for _ in 0..100 {
+            assert!(matches!(sample(&scores, &config, &mut rng), 1 | 3));
+        }
+
+        let config = GenerationConfig { top_p: Some(0.5), ..Default::default() };
+        assert!((0..20).all(|_| sample(&scores, &config, &mut rng) == 1));
+        let config = GenerationConfig { top_k: Some(3), top_p: Some(1.0), ..Default::default() };
+        assert!((0..100).all(|_| sample(&scores, &config, &mut rng) != 2));
+    }
+
+    #[test]
+    fn test_log_softmax_at() {
+        let value = log_softmax_at(&[0.0, 0.0], 1);
+        assert!((value - 0.5f32.ln()).abs() < 1e-6);
+    }
+}
@@ -1,10 +1,17 @@
 use burn::constant;
 use burn::module::Module;
-use burn::nn::transformer::{TransformerEncoder, TransformerEncoderConfig, TransformerEncoderInput};
 use burn::nn::{Embedding, EmbeddingConfig, Linear, LinearConfig};
+use burn::record::Record;
 use burn::tensor::{Int, Tensor, backend::Backend};
-use crate::config::HopeConfig;
+use crate::config::{HopeConfig, PositionEncoding};
 use super::continuum_mem::{ContinuumMemory, ContinuumMemoryState};
+use super::encoder::{AttentionPattern, EncoderCache, LevelEncoder};
+use super::flops::FlopsEstimate;
+use super::fusion::LevelFusion;
+use super::generate::{self, Candidate, GenerationConfig};
+use super::onnx::{Dim, OnnxGraph};
+use super::profile::{ForwardStage, StageTimes};
+use super::prune::{PruneOptions, PruneReport};
 use super::self_modify::{SelfModifyModule, SelfModifyState};
 
 constant!(HopeConfig);
@@ -21,26 +28,156 @@ pub struct HopeOutput<B: Backend> {
     pub hidden_states: Tensor<B, 3>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Record, Clone, Debug)]
 pub struct HopeCarry<B: Backend> {
     pub level_states: Vec<Tensor<B, 3>>,
     pub continuum_memory: Option<ContinuumMemoryState<B>>,
     pub self_modify: Option<SelfModifyState<B>>,
+    /// Per-level additive inputs from the deep optimizer's fast parameters
+    /// (`[1, seq_len, hidden]`, empty outside training)
+    pub level_biases: Vec<Tensor<B, 3>>,
     pub step_count: usize,
 }
 
+impl<B: Backend> HopeCarry<B> {
+    /// Drop the autodiff history so the carry can be reused in a later step
+    /// (truncated backpropagation through time)
+    pub fn detach(self) -> Self {
+        Self {
+            level_states: self.level_states.into_iter().map(Tensor::detach).collect(),
+            continuum_memory: self.continuum_memory.map(|mem| ContinuumMemoryState {
+                ultra_short: mem.ultra_short.detach(),
+                short: mem.short.detach(),
+                mid: mem.mid.detach(),
+                long: mem.long.detach(),
+                episodic: mem.episodic.detach(),
+            }),
+            self_modify: self.self_modify.map(|sm| SelfModifyState {
+                meta_state: sm.meta_state.detach(),
+                update_count: sm.update_count,
+                frozen: sm.frozen,
+            }),
+            level_biases: Vec::new(),
+            step_count: self.step_count,
+        }
+    }
+
+    /// Clear the parts selected by `reset` back to their initial (zero)
+    /// state; the rest persists (e.g. long-term memory across documents)
+    pub fn reset(mut self, reset: CarryReset) -> Self {
+        if reset.level_states {
+            self.level_states = self.level_states.into_iter().map(|state| state.zeros_like()).collect();
+        }
+        if let Some(ref mut mem) = self.continuum_memory {
+            let banks = [
+                (reset.ultra_short, &mut mem.ultra_short),
+                (reset.short, &mut mem.short),
+                (reset.mid, &mut mem.mid),
+                (reset.long, &mut mem.long),
+                (reset.episodic, &mut mem.episodic),
+            ];
+            for (clear, bank) in banks {
+                if clear {
+                    *bank = bank.zeros_like();
+                }
+            }
+        }
+        if let Some(ref mut sm) = self.self_modify {
+            if reset.self_modify {
+                sm.meta_state = sm.meta_state.zeros_like();
+                sm.update_count = 0;
+            }
+        }
+        self
+    }
+
+    /// Hold (or release) the self-modification state: while frozen, a forward
+    /// pass still updates the meta state between its level steps but returns
+    /// the state it was given
+    pub fn freeze_self_modify(mut self, frozen: bool) -> Self {
+        if let Some(ref mut sm) = self.self_modify {
+            sm.frozen = frozen;
+        }
+        self
+    }
+}
+
+/// Parts of a `HopeCarry` cleared by `HopeCarry::reset`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CarryReset {
+    pub level_states: bool,
+    pub ultra_short: bool,
+    pub short: bool,
+    pub mid: bool,
+    pub long: bool,
+    pub episodic: bool,
+    /// Meta state and update count of self-modification
+    pub self_modify: bool,
+}
+
+impl CarryReset {
+    /// Everything: the same as a fresh carry
+    pub fn all() -> Self {
+        Self {
+            level_states: true,
+            ultra_short: true,
+            short: true,
+            mid: true,
+            long: true,
+            episodic: true,
+            self_modify: true,
+        }
+    }
+
+    /// Level states and the fast banks (ultra-short, short, mid), keeping
+    /// the long and episodic memory and the self-modification state: the
+    /// boundary between two documents of a continual-learning stream
+    pub fn document() -> Self {
+        Self { level_states: true, ultra_short: true, short: true, mid: true, ..Self::default() }
+    }
+}
+
+/// State of incremental decoding (`HopeModel::decode`): the tokens in the
+/// current window and, per level and timescale step, the encoder's
+/// key/value cache and the self-modification state it ran with
+pub struct DecodeCache<B: Backend> {
+    carry: HopeCarry<B>,
+    tokens: Option<Tensor<B, 2, Int>>,
+    steps: Vec<Vec<StepCache<B>>>,
+}
+
+struct StepCache<B: Backend> {
+    encoder: EncoderCache<B>,
+    meta_state: Option<Tensor<B, 2>>,
+}
+
+impl<B: Backend> DecodeCache<B> {
+    /// Tokens in the current window
+    pub fn len(&self) -> usize {
+        self.tokens.as_ref().map_or(0, |tokens| tokens.dims()[1])
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
 #[derive(Module, Debug)]
 pub struct HopeModel<B: Backend> {
     #[module(skip)]
     config: HopeConfig,
     token_embed: Embedding<B>,
     pos_embed: Embedding<B>,
-    level_encoders: Vec<TransformerEncoder<B>>,
+    level_encoders: Vec<LevelEncoder<B>>,
     continuum_memory: Option<ContinuumMemory<B>>,
     self_modify: Option<SelfModifyModule<B>>,
+    level_fusion: Option<LevelFusion<B>>,
     head: Linear<B>,
     #[module(skip)]
     embed_scale: f32,
+    /// `train` or `eval` mode
+    #[module(skip)]
+    training: bool,
 }
 
 impl<B: Backend> HopeModel<B> {
@@ -48,21 +185,13 @@ impl<B: Backend> HopeModel<B> {
         config.validate();
         
         let token_embed = EmbeddingConfig::new(config.vocab_size, config.hidden_size).init(device);
+        // Unused with ALiBi, but kept so every configuration has the same parameters
         let pos_embed = EmbeddingConfig::new(config.seq_len.max(1), config.hidden_size).init(device);
         
         // Create encoders for each level
         let mut level_encoders = Vec::new();
-        for _ in 0..config.num_levels {
-            let encoder = TransformerEncoderConfig::new(
-                config.hidden_size,
-                config.feedforward_dim(),
-                config.num_heads,
-                config.num_layers,
-            )
-            .with_dropout(config.dropout)
-            .with_norm_first(true)
-            .init(device);
-            level_encoders.push(encoder);
+        for level in 0..config.num_levels {
+            level_encoders.push(LevelEncoder::new(&config, level, device));
         }
 
         let continuum_memory = if config.continuum_mem.enabled {
@@ -85,6 +214,7 @@ impl<B: Backend> HopeModel<B> {
             None
         };
 
+        let level_fusion = LevelFusion::new(config.level_fusion, config.num_levels, config.hidden_size, device);
         let head = LinearConfig::new(config.hidden_size, config.vocab_size).init(device);
         let embed_scale = (config.hidden_size as f32).sqrt().recip();
 
@@ -95,14 +225,23 @@ impl<B: Backend> HopeModel<B> {
             level_encoders,
             continuum_memory,
             self_modify,
+            level_fusion,
             head,
             embed_scale,
+            training: true,
         }
     }
 
+    /// Fresh carry for `seq_len` tokens: level states and memory banks at
+    /// zero (`carry_from_memory` resumes a persisted memory instead)
     pub fn initial_carry(&self, batch: usize, device: &B::Device) -> HopeCarry<B> {
+        self.carry_with_len(batch, self.config.seq_len, device)
+    }
+
+    /// Fresh carry for sequences of `seq_len` tokens (shorter than
+    /// `config.seq_len` in generation and token-budget batches)
+    pub fn carry_with_len(&self, batch: usize, seq_len: usize, device: &B::Device) -> HopeCarry<B> {
         let hidden_size = self.config.hidden_size;
-        let seq_len = self.config.seq_len;
         
         let mut level_states = Vec::new();
         for _ in 0..self.config.num_levels {
@@ -115,67 +254,83 @@ impl<B: Backend> HopeModel<B> {
             None
         };
 
-        let self_modify = if let Some(ref sm) = self.self_modify {
-            Some(sm.init_state(batch, hidden_size, device))
-        } else {
-            None
-        };
+        // Carries for evaluation start frozen, so the meta state is not carried
+        // from one call to the next
+        let self_modify = self.self_modify.as_ref().map(|sm| SelfModifyState {
+            frozen: !self.training,
+            ..sm.init_state(batch, hidden_size, device)
+        });
 
         HopeCarry {
             level_states,
             continuum_memory,
             self_modify,
+            level_biases: Vec::new(),
             step_count: 0,
         }
     }
 
-    pub fn forward(&self, input: HopeInput<B>, mut carry: HopeCarry<B>) -> (HopeCarry<B>, HopeOutput<B>) {
-        let batch = input.tokens.dims()[0];
+    pub fn forward(&self, input: HopeInput<B>, carry: HopeCarry<B>) -> (HopeCarry<B>, HopeOutput<B>) {
+        self.forward_timed(input, carry, &mut StageTimes::disabled())
+    }
+
+    /// `forward`, adding the time of each stage to `times`
+    pub fn forward_timed(
+        &self,
+        input: HopeInput<B>,
+        mut carry: HopeCarry<B>,
+        times: &mut StageTimes,
+    ) -> (HopeCarry<B>, HopeOutput<B>) {
         let device = input.tokens.device();
-        let seq_len = input.tokens.dims()[1];
+        times.start::<B>(&device);
+        // A frozen self-modification state advances within the pass but is
+        // handed back as it came in
+        let held_self_modify = carry.self_modify.clone().filter(|state| state.frozen);
 
-        // Embed tokens
-        let token_embeds = self.token_embed.forward(input.tokens.clone()) * self.embed_scale;
-        
-        // Add positional embeddings
-        let positions = Tensor::arange(0..seq_len as i64, &device)
-            .reshape([1, seq_len])
-            .repeat_dim(0, batch);
-        let pos_embeds = self.pos_embed.forward(positions);
-        let mut hidden = token_embeds + pos_embeds;
+        let mut hidden = self.embed(input.tokens, 0);
+        let pattern = self.attention_pattern();
+        times.lap::<B>(ForwardStage::Embedding, &device);
 
         // Retrieve from continuum memory if enabled
         if let Some(ref mem) = self.continuum_memory {
             if let Some(ref mem_state) = carry.continuum_memory {
-                hidden = mem.retrieve(mem_state, &hidden);
+                hidden = mem.retrieve_timed(mem_state, &hidden, times);
             }
         }
 
         // Process through nested levels
         let mut prev_level_output = hidden.clone();
+        let mut level_outputs = Vec::new();
         for (level_idx, (encoder, timescale)) in self.level_encoders.iter()
             .zip(self.config.level_timescales.iter())
             .enumerate() 
         {
+            prev_level_output = self.level_retrieval(level_idx, carry.continuum_memory.as_ref(), prev_level_output, times);
+            prev_level_output = self.level_skip(level_idx, prev_level_output, &hidden);
             let mut level_state = carry.level_states[level_idx].clone();
+            let level_bias = carry.level_biases.get(level_idx).cloned();
             
             // Process multiple timescale steps
             for _ in 0..*timescale {
-                let level_input = level_state.clone() + prev_level_output.clone();
+                let level_input = match level_bias {
+                    Some(ref bias) => level_state.clone() + prev_level_output.clone() + bias.clone(),
+                    None => level_state.clone() + prev_level_output.clone(),
+                };
                 
                 // Transformer encoding
-                let encoded = encoder.forward(TransformerEncoderInput::new(level_input));
+                let encoded = encoder.forward(level_input, &pattern);
+                times.lap::<B>(ForwardStage::Level(level_idx), &device);
                 
                 // Self-modification if enabled
                 let modified = if let Some(ref sm) = self.self_modify {
                     if let Some(ref mut sm_state) = carry.self_modify {
-                        // Compute update rule
-                        let meta_state = sm.compute_update_rule(&encoded, sm_state);
-                        sm_state.meta_state = meta_state;
-                        sm_state.update_count += 1;
-                        
+                        // Update the meta state (every update_frequency steps)
+                        sm.update_state(&encoded, sm_state);
+
                         // Apply weight modification
-                        sm.apply_weight_modification(&encoded, &sm_state.meta_state)
+                        let modified = sm.apply_weight_modification(&encoded, &sm_state.meta_state);
+                        times.lap::<B>(ForwardStage::SelfModify, &device);
+                        modified
                     } else {
                         encoded
                     }
@@ -187,6 +342,7 @@ impl<B: Backend> HopeModel<B> {
             }
             
             carry.level_states[level_idx] = level_state.clone();
+            level_outputs.push(level_state.clone());
             prev_level_output = level_state;
         }
 
@@ -194,25 +350,800 @@ impl<B: Backend> HopeModel<B> {
         if let Some(ref mem) = self.continuum_memory {
             if let Some(ref mut mem_state) = carry.continuum_memory {
                 mem.update(mem_state, &prev_level_output);
+                times.lap::<B>(ForwardStage::MemoryUpdate, &device);
             }
         }
 
         // Generate logits
-        let logits = self.head.forward(prev_level_output.clone());
+        let head_input = self.fuse_levels(level_outputs);
+        let logits = self.head.forward(head_input.clone());
+        times.lap::<B>(ForwardStage::Head, &device);
 
+        if held_self_modify.is_some() {
+            carry.self_modify = held_self_modify;
+        }
         carry.step_count += 1;
 
         let output = HopeOutput {
             logits,
-            hidden_states: prev_level_output,
+            hidden_states: head_input,
         };
 
         (carry, output)
     }
 
+    /// Scaled token embeddings of `tokens` ([batch, len]) at positions from
+    /// `start`, plus their learned position embeddings
+    fn embed(&self, tokens: Tensor<B, 2, Int>, start: usize) -> Tensor<B, 3> {
+        let [batch, len] = tokens.dims();
+        let device = tokens.device();
+        let token_embeds = self.token_embed.forward(tokens) * self.embed_scale;
+
+        match self.config.position_encoding {
+            PositionEncoding::Learned => {
+                let positions = Tensor::arange(start as i64..(start + len) as i64, &device)
+                    .reshape([1, len])
+                    .repeat_dim(0, batch);
+                token_embeds + self.pos_embed.forward(positions)
+            }
+            PositionEncoding::Alibi => token_embeds,
+        }
+    }
+
+    /// Input of level `level` with memory retrieved into it, for levels after
+    /// the first with `retrieve_every_level` (the first reads the memory
+    /// from the embeddings)
+    fn level_retrieval(
+        &self,
+        level: usize,
+        memory: Option<&ContinuumMemoryState<B>>,
+        input: Tensor<B, 3>,
+        times: &mut StageTimes,
+    ) -> Tensor<B, 3> {
+        match (&self.continuum_memory, memory) {
+            (Some(mem), Some(state)) if level > 0 && self.config.continuum_mem.retrieve_every_level => {
+                mem.retrieve_timed(state, &input, times)
+            }
+            _ => input,
+        }
+    }
+
+    /// `input` of level `level` with the level-0 input `skip` added, for
+    /// levels after the first with `level_skip`
+    fn level_skip(&self, level: usize, input: Tensor<B, 3>, skip: &Tensor<B, 3>) -> Tensor<B, 3> {
+        if level > 0 && self.config.level_skip {
+            input + skip.clone()
+        } else {
+            input
+        }
+    }
+
+    /// Input of the head: the last level's output, or the outputs of all
+    /// levels fused by `level_fusion`
+    fn fuse_levels(&self, mut level_outputs: Vec<Tensor<B, 3>>) -> Tensor<B, 3> {
+        match self.level_fusion {
+            Some(ref fusion) => fusion.forward(level_outputs),
+            None => level_outputs.pop().expect("a model has at least one level"),
+        }
+    }
+
+    /// Which keys the level encoders attend to
+    fn attention_pattern(&self) -> AttentionPattern {
+        AttentionPattern {
+            causal: self.config.causal,
+            window: self.config.attention_window,
+            alibi: self.config.position_encoding == PositionEncoding::Alibi,
+        }
+    }
+
+    /// Logits for the next token after each row of `tokens` ([batch, len]).
+    /// Only the last `context_len` tokens are read, with a fresh carry.
+    pub fn next_token_logits(&self, tokens: Tensor<B, 2, Int>) -> Tensor<B, 2> {
+        let [batch, len] = tokens.dims();
+        let start = self.config.context_len().map_or(0, |context| len.saturating_sub(context));
+        let window = tokens.slice([0..batch, start..len]);
+
+        let carry = self.carry_with_len(batch, len - start, &window.device());
+        let (_, output) = self.forward(HopeInput { tokens: window }, carry);
+
+        let [_, window_len, vocab] = output.logits.dims();
+        output.logits
+            .slice([0..batch, window_len - 1..window_len, 0..vocab])
+            .reshape([batch, vocab])
+    }
+
+    /// Start incremental decoding from `carry` (`initial_carry`, or a
+    /// `prime_memory` carry). Its continuum memory is read but not updated,
+    /// and level states start at zero, as in `next_token_logits`.
+    pub fn decode_cache(&self, carry: HopeCarry<B>) -> DecodeCache<B> {
+        DecodeCache { carry, tokens: None, steps: Vec::new() }
+    }
+
+    /// Append `tokens` ([batch, n]) to the window and return the logits for
+    /// the next token ([batch, vocab]), the same as `next_token_logits` on the
+    /// whole window. A single token reuses the cached keys and values, so its
+    /// projections and feed-forward run once per level and timescale step
+    /// (attention still reads the window, or its last `attention_window`
+    /// positions); the prompt and other multi-token
+    /// calls run a full pass. Once a single token would overflow the context
+    /// (`seq_len`, unless ALiBi lifts the limit), the window is refilled from
+    /// its last `seq_len / 2` tokens rather than sliding by one. Without
+    /// `causal`, every call is a full pass.
+    pub fn decode(&self, cache: &mut DecodeCache<B>, tokens: Tensor<B, 2, Int>) -> Tensor<B, 2> {
+        let [batch, new] = tokens.dims();
+        let window = match cache.tokens.take() {
+            Some(window) => Tensor::cat(vec![window, tokens], 1),
+            None => tokens,
+        };
+        let len = window.dims()[1];
+        let context = self.config.context_len().unwrap_or(usize::MAX);
+
+        if new == 1 && len <= context && self.config.causal && !cache.steps.is_empty() {
+            cache.tokens = Some(window.clone());
+            return self.decode_block(cache, window.slice([0..batch, len - 1..len]), len - 1);
+        }
+
+        let keep = if len > context && new == 1 && self.config.causal {
+            (self.config.seq_len / 2).max(1)
+        } else {
+            len.min(context)
+        };
+        let window = window.slice([0..batch, len - keep..len]);
+        cache.tokens = Some(window.clone());
+        cache.steps.clear();
+        self.decode_block(cache, window, 0)
+    }
+
+    /// Run `tokens` ([batch, n], at positions from `start`) through the model,
+    /// filling the step caches when empty and extending them otherwise;
+    /// returns the logits after the last token
+    fn decode_block(&self, cache: &mut DecodeCache<B>, tokens: Tensor<B, 2, Int>, start: usize) -> Tensor<B, 2> {
+        let [batch, len] = tokens.dims();
+        let prefill = cache.steps.is_empty();
+
+        let mut hidden = self.embed(tokens, start);
+        if let (Some(mem), Some(mem_state)) = (&self.continuum_memory, &cache.carry.continuum_memory) {
+            hidden = mem.retrieve(mem_state, &hidden);
+        }
+        let pattern = self.attention_pattern();
+
+        // Self-modification only reads the first position, so its meta states
+        // are fixed by the full pass
+        let mut sm_state = cache.carry.self_modify.clone();
+        let mut prev_level_output = hidden.clone();
+        let mut level_outputs = Vec::new();
+        for (level_idx, (encoder, timescale)) in self.level_encoders.iter()
+            .zip(self.config.level_timescales.iter())
+            .enumerate()
+        {
+            if prefill {
+                cache.steps.push(Vec::new());
+            }
+            let memory = cache.carry.continuum_memory.as_ref();
+            prev_level_output = self.level_retrieval(level_idx, memory, prev_level_output, &mut StageTimes::disabled());
+            prev_level_output = self.level_skip(level_idx, prev_level_output, &hidden);
+            let mut level_state: Option<Tensor<B, 3>> = None;
+
+            for step in 0..*timescale {
+                let level_input = match level_state {
+                    Some(state) => state + prev_level_output.clone(),
+                    None => prev_level_output.clone(),
+                };
+                if prefill {
+                    cache.steps[level_idx].push(StepCache { encoder: encoder.new_cache(), meta_state: None });
+                }
+                let step_cache = &mut cache.steps[level_idx][step];
+                let encoded = encoder.forward_cached(level_input, &pattern, &mut step_cache.encoder);
+
+                let modified = match (&self.self_modify, sm_state.as_mut()) {
+                    (Some(sm), Some(sm_state)) => {
+                        if prefill {
+                            sm.update_state(&encoded, sm_state);
+                            step_cache.meta_state = Some(sm_state.meta_state.clone());
+                        }
+                        let meta_state = step_cache.meta_state.as_ref().expect("meta state is set by the full pass");
+                        sm.apply_weight_modification(&encoded, meta_state)
+                    }
+                    _ => encoded,
+                };
+                level_state = Some(modified);
+            }
+
+            // A level without steps passes on its (zero) state
+            prev_level_output = level_state.unwrap_or_else(|| prev_level_output.zeros_like());
+            level_outputs.push(prev_level_output.clone());
+        }
+
+        let logits = self.head.forward(self.fuse_levels(level_outputs));
+        let [_, _, vocab] = logits.dims();
+        logits.slice([0..batch, len - 1..len, 0..vocab]).reshape([batch, vocab])
+    }
+
+    /// Summary embedding of a document: hidden states of a first pass over
+    /// `tokens` ([batch, len], any length), mean-pooled over all positions.
+    /// Windows of `seq_len` are read independently with a fresh carry.
+    pub fn document_summary(&self, tokens: Tensor<B, 2, Int>) -> Tensor<B, 2> {
+        let [batch, len] = tokens.dims();
+        let device = tokens.device();
+        let window = self.config.seq_len.max(1);
+
+        let mut sum = Tensor::zeros([batch, self.config.hidden_size], &device);
+        for start in (0..len).step_by(window) {
+            let end = (start + window).min(len);
+            let chunk = tokens.clone().slice([0..batch, start..end]);
+            let carry = self.carry_with_len(batch, end - start, &device);
+            let (_, output) = self.forward(HopeInput { tokens: chunk }, carry);
+            sum = sum + output.hidden_states.sum_dim(1).squeeze_dim(1);
+        }
+
+        sum / len.max(1) as f32
+    }
+
+    /// Initial carry whose episodic memory holds `summary` ([batch, hidden])
+    /// at every position. Without continuum memory this is `initial_carry`.
+    pub fn carry_from_summary(&self, summary: Tensor<B, 2>) -> HopeCarry<B> {
+        let [batch, _] = summary.dims();
+        let mut carry = self.initial_carry(batch, &summary.device());
+
+        if let (Some(mem), Some(mem_state)) = (&self.continuum_memory, carry.continuum_memory.as_mut()) {
+            mem_state.episodic = mem.compress(summary.unsqueeze_dim::<3>(1).repeat_dim(1, self.config.seq_len));
+        }
+
+        carry
+    }
+
+    /// Initial carry whose continuum memory resumes `memory` (banks of one
+    /// row, as saved with a checkpoint; see `load_memory_state`), repeated
+    /// over `batch`. Without continuum memory this is `initial_carry`.
+    pub fn carry_from_memory(&self, batch: usize, memory: &ContinuumMemoryState<B>) -> HopeCarry<B> {
+        let mut carry = self.initial_carry(batch, &memory.episodic.device());
+
+        if let Some(ref mut mem_state) = carry.continuum_memory {
+            let rows = |bank: &Tensor<B, 3>| bank.clone().repeat_dim(0, batch);
+            *mem_state = ContinuumMemoryState {
+                ultra_short: rows(&memory.ultra_short),
+                short: rows(&memory.short),
+                mid: rows(&memory.mid),
+                long: rows(&memory.long),
+                episodic: rows(&memory.episodic),
+            };
+        }
+
+        carry
+    }
+
+    /// "Pre-read" a reference document: warm-start episodic memory with the
+    /// document summary so generation can attend to it
+    pub fn prime_memory(&self, tokens: Tensor<B, 2, Int>) -> HopeCarry<B> {
+        self.carry_from_summary(self.document_summary(tokens))
+    }
+
+    /// Sample continuations of `prompt` (see `generate::generate`): up to
+    /// `max_new_tokens` each, with temperature, top-k and top-p sampling
+    pub fn generate(&self, prompt: &[i64], config: &GenerationConfig, device: &B::Device) -> Vec<Candidate> {
+        generate::generate(self, prompt, config, device)
+    }
+
+    /// `generate` reading `memory` (see `carry_from_memory`) instead of an
+    /// empty continuum memory
+    pub fn generate_with_memory(
+        &self,
+        memory: &ContinuumMemoryState<B>,
+        prompt: &[i64],
+        config: &GenerationConfig,
+        device: &B::Device,
+    ) -> Vec<Candidate> {
+        let carry = self.carry_from_memory(config.num_return_sequences, memory);
+        generate::generate_from(self, carry, prompt, config, device)
+    }
+
+    /// `generate` after pre-reading `reference` (see `prime_memory`): every
+    /// returned sequence starts from its summary in episodic memory
+    pub fn generate_with_reference(
+        &self,
+        reference: &[i64],
+        prompt: &[i64],
+        config: &GenerationConfig,
+        device: &B::Device,
+    ) -> Vec<Candidate> {
+        let tokens = Tensor::<B, 1, Int>::from_ints(reference, device)
+            .reshape([1, reference.len()])
+            .repeat_dim(0, config.num_return_sequences);
+        generate::generate_from(self, self.prime_memory(tokens), prompt, config, device)
+    }
+
+    /// Training mode, the default: dropout at `config.dropout` (on autodiff
+    /// backends) and forward passes advance the self-modification meta state
+    pub fn train(self) -> Self {
+        self.with_training(true)
+    }
+
+    /// Inference mode for evaluation, generation and export: no dropout on
+    /// any backend, and new carries start with a frozen self-modification
+    /// state, so a forward pass computes exactly as in training but does not
+    /// carry the meta state over to the next call
+    pub fn eval(self) -> Self {
+        self.with_training(false)
+    }
+
+    fn with_training(mut self, training: bool) -> Self {
+        let dropout = if training { self.config.dropout } else { 0.0 };
+        self.level_encoders = self.level_encoders.into_iter().map(|encoder| encoder.with_dropout(dropout)).collect();
+        self.training = training;
+        self
+    }
+
+    /// Drop continuum memory and/or self-modification (ablation). Components
+    /// can only be turned off; `true` keeps a component as it is.
+    pub fn with_components(mut self, continuum_mem: bool, self_modify: bool) -> Self {
+        if !continuum_mem {
+            self.continuum_memory = None;
+            self.config.continuum_mem.enabled = false;
+        }
+        if !self_modify {
+            self.self_modify = None;
+            self.config.self_modify.enabled = false;
+        }
+        self
+    }
+
     #[allow(dead_code)]
+    /// Analytical forward FLOPs per token of this model's configuration
+    pub fn flops_per_token(&self) -> FlopsEstimate {
+        FlopsEstimate::new(&self.config)
+    }
+
+    /// Structured magnitude pruning of the level encoders (see
+    /// `PruneOptions`). Removing neurons records the new widths in the
+    /// config's `level_feedforward_dims`.
+    pub fn prune(mut self, options: &PruneOptions) -> (Self, PruneReport) {
+        options.validate();
+        let params_before = self.num_params();
+        let mut report = PruneReport::default();
+        self.level_encoders = self
+            .level_encoders
+            .into_iter()
+            .map(|encoder| {
+                let (encoder, pruned) = encoder.prune(options);
+                report.ffn_neurons += pruned.ffn_neurons;
+                report.heads += pruned.heads;
+                encoder
+            })
+            .collect();
+        if options.remove {
+            self.config.level_feedforward_dims = self.level_encoders.iter().map(LevelEncoder::feedforward_dim).collect();
+        }
+        report.params_before = params_before;
+        report.params_after = self.num_params();
+        (self, report)
+    }
+
+    /// Resize the vocabulary to `sources.len()` tokens: token `i` takes the
+    /// embedding row and output column of the old token `sources[i]`, and
+    /// tokens without one start from the mean over the old tokens, so their
+    /// logits start at the average instead of a random value.
+    pub fn resize_vocab(mut self, sources: &[Option<usize>]) -> Self {
+        assert!(!sources.is_empty(), "the vocabulary needs at least one token");
+        assert!(
+            sources.iter().flatten().all(|&old| old < self.config.vocab_size),
+            "source tokens must be within the current vocabulary"
+        );
+        let vocab_size = sources.len();
+        let device = self.head.weight.device();
+        let index: Vec<i64> = sources.iter().map(|source| source.unwrap_or(0) as i64).collect();
+        let index = Tensor::<B, 1, Int>::from_ints(index.as_slice(), &device);
+        let copied: Vec<f32> = sources.iter().map(|source| if source.is_some() { 1.0 } else { 0.0 }).collect();
+        let copied = Tensor::<B, 1>::from_floats(copied.as_slice(), &device);
+
+        self.token_embed.weight = self.token_embed.weight.map(|weight| {
+            let mask = copied.clone().reshape([vocab_size, 1]);
+            let mean = weight.clone().mean_dim(0);
+            weight.select(0, index.clone()) * mask.clone() + mean * (mask.neg() + 1.0)
+        });
+        self.head.weight = self.head.weight.map(|weight| {
+            let mask = copied.clone().reshape([1, vocab_size]);
+            let mean = weight.clone().mean_dim(1);
+            weight.select(1, index.clone()) * mask.clone() + mean * (mask.neg() + 1.0)
+        });
+        self.head.bias = self.head.bias.map(|bias| {
+            bias.map(|bias| {
+                let mean = bias.clone().mean();
+                bias.select(0, index) * copied.clone() + mean * (copied.neg() + 1.0)
+            })
+        });
+        self.config.vocab_size = vocab_size;
+        self
+    }
+
+    /// ONNX graph of `forward` with a fresh carry (`carry_with_len`) over
+    /// `len` tokens: input `tokens` (int64, [batch, len]), output `logits`
+    /// ([batch, len, vocab]). The initial memory banks become constants;
+    /// carried state, memory writes and the deep optimizer's level biases
+    /// are not part of the graph.
+    pub fn onnx_graph(&self, len: usize) -> anyhow::Result<OnnxGraph> {
+        if len == 0 {
+            anyhow::bail!("ONNX export needs a sequence length of at least 1");
+        }
+        if self.config.position_encoding == PositionEncoding::Learned && len > self.config.seq_len {
+            anyhow::bail!("Learned positions cover {} tokens, the export asks for {}", self.config.seq_len, len);
+        }
+        let device = self.head.weight.device();
+        let carry = self.carry_with_len(1, len, &device);
+        let pattern = self.attention_pattern();
+        let mut graph = OnnxGraph::new();
+        let tokens = graph.int64_input("tokens", vec![Dim::Named("batch"), Dim::Fixed(len)]);
+
+        let table = graph.tensor(self.token_embed.weight.val());
+        let embeds = graph.node("Gather", &[&table, &tokens], Vec::new());
+        let mut hidden = graph.scale(&embeds, self.embed_scale);
+        if self.config.position_encoding == PositionEncoding::Learned {
+            let positions = graph.tensor(self.pos_embed.weight.val().slice([0..len, 0..self.config.hidden_size]).unsqueeze_dim::<3>(0));
+            hidden = graph.add(&hidden, &positions);
+        }
+
+        let memory = self.continuum_memory.as_ref().zip(carry.continuum_memory.as_ref());
+        if let Some((mem, state)) = memory {
+            hidden = mem.onnx_retrieve(&mut graph, state, &hidden)?;
+        }
+
+        let mut prev_level_output = hidden.clone();
+        let mut level_outputs = Vec::new();
+        let mut meta_state: Option<String> = None;
+        let mut update_count = 0;
+        for (level_idx, (encoder, timescale)) in self.level_encoders.iter()
+            .zip(self.config.level_timescales.iter())
+            .enumerate()
+        {
+            if let Some((mem, state)) = memory.filter(|_| level_idx > 0 && self.config.continuum_mem.retrieve_every_level) {
+                prev_level_output = mem.onnx_retrieve(&mut graph, state, &prev_level_output)?;
+            }
+            if level_idx > 0 && self.config.level_skip {
+                prev_level_output = graph.add(&prev_level_output, &hidden);
+            }
+
+            // The first step reads the zero level state
+            let mut level_state: Option<String> = None;
+            for _ in 0..*timescale {
+                let level_input = match level_state {
+                    Some(ref state) => graph.add(state, &prev_level_output),
+                    None => prev_level_output.clone(),
+                };
+                let encoded = encoder.onnx(&mut graph, &level_input, &pattern, len, &device);
+
+                let modified = match self.self_modify {
+                    Some(ref sm) => {
+                        if update_count % self.config.self_modify.update_frequency == 0 {
+                            meta_state = Some(sm.onnx_update_rule(&mut graph, &encoded, meta_state.as_deref()));
+                        }
+                        update_count += 1;
+                        sm.onnx_apply(&mut graph, &encoded, meta_state.as_deref())
+                    }
+                    None => encoded,
+                };
+                level_state = Some(modified);
+            }
+
+            // A level without steps outputs its zero state
+            let level_state = level_state.unwrap_or_else(|| graph.scale(&prev_level_output, 0.0));
+            level_outputs.push(level_state.clone());
+            prev_level_output = level_state;
+        }
+
+        let head_input = match self.level_fusion {
+            Some(ref fusion) => fusion.onnx(&mut graph, &level_outputs),
+            None => level_outputs.pop().expect("a model has at least one level"),
+        };
+        let logits = graph.linear(&self.head, &head_input);
+        graph.float_output(&logits, "logits", vec![Dim::Named("batch"), Dim::Fixed(len), Dim::Fixed(self.config.vocab_size)]);
+        graph.metadata("format", "hope");
+        graph.metadata("seq_len", len);
+        Ok(graph)
+    }
+
     pub fn config(&self) -> &HopeConfig {
         &self.config
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ContinuumMemConfig, LevelFusionMode, SelfModifyConfig};
+    use burn::backend::Autodiff;
+    use burn::module::AutodiffModule;
+    use burn_ndarray::NdArray;
+
+    #[test]
+    fn test_prime_memory_fills_episodic_bank() {
+        let device = Default::default();
+        let config = HopeConfig {
+            seq_len: 8,
+            continuum_mem: ContinuumMemConfig {
+                enabled: true,
+                ..Default::default()
+            },
+            ..HopeConfig::tiny()
+        };
+        let model = HopeModel::<NdArray<f32>>::new(config, &device);
+        // Longer than seq_len, with a partial last window
+        let tokens = Tensor::<NdArray<f32>, 1, Int>::arange(0..20, &device)
+            .remainder_scalar(8)
+            .reshape([1, 20]);
+
+        let carry = model.prime_memory(tokens);
+        let episodic = carry.continuum_memory.unwrap().episodic;
+        assert_eq!(episodic.dims(), [1, 8, 16]);
+
+        let values = episodic.into_data().to_vec::<f32>().unwrap();
+        assert!(values.iter().any(|v| *v != 0.0));
+        assert_eq!(values[..16], values[16..32]);
+    }
+
+    #[test]
+    fn test_generate_with_reference_primes_every_sequence() {
+        let device = Default::default();
+        let config = HopeConfig {
+            seq_len: 8,
+            continuum_mem: ContinuumMemConfig {
+                enabled: true,
+                ..Default::default()
+            },
+            ..HopeConfig::tiny()
+        };
+        let model = HopeModel::<NdArray<f32>>::new(config, &device).eval();
+        let generation = GenerationConfig {
+            max_new_tokens: 4,
+            temperature: 0.0,
+            num_return_sequences: 2,
+            ..Default::default()
+        };
+
+        let candidates = model.generate_with_reference(&[1, 2, 3, 4, 5, 6, 7, 0, 1, 2], &[1, 2], &generation, &device);
+        assert_eq!(candidates.len(), 2);
+        assert!(candidates.iter().all(|c| c.tokens.len() == 4));
+    }
+
+    #[test]
+    fn test_reset_and_freeze_carry() {
+        let device = Default::default();
+        let config = HopeConfig {
+            level_timescales: vec![2],
+            continuum_mem: ContinuumMemConfig { enabled: true, ..Default::default() },
+            self_modify: SelfModifyConfig { enabled: true, update_frequency: 1, ..Default::default() },
+            ..HopeConfig::tiny()
+        };
+        let model = HopeModel::<NdArray<f32>>::new(config, &device);
+        let tokens = || HopeInput { tokens: Tensor::<NdArray<f32>, 2, Int>::from_ints([[1, 2, 3, 4]], &device) };
+        let is_zero = |tensor: &Tensor<NdArray<f32>, 3>| tensor.clone().abs().sum().into_scalar() == 0.0;
+
+        let (carry, _) = model.forward(tokens(), model.initial_carry(1, &device));
+        let carry = carry.reset(CarryReset::document());
+        assert!(is_zero(&carry.level_states[0]));
+        let mem = carry.continuum_memory.as_ref().unwrap();
+        assert!(is_zero(&mem.ultra_short) && is_zero(&mem.short) && is_zero(&mem.mid));
+        assert!(!is_zero(&mem.long) && !is_zero(&mem.episodic));
+        assert_eq!(carry.self_modify.as_ref().unwrap().update_count, 2);
+
+        // A frozen state is held through the forward pass
+        let meta_state = carry.self_modify.as_ref().unwrap().meta_state.clone();
+        let (carry, _) = model.forward(tokens(), carry.freeze_self_modify(true));
+        let sm = carry.self_modify.as_ref().unwrap();
+        assert_eq!(sm.update_count, 2);
+        sm.meta_state.clone().into_data().assert_eq(&meta_state.into_data(), true);
+
+        let (carry, _) = model.forward(tokens(), carry.freeze_self_modify(false));
+        assert_eq!(carry.self_modify.as_ref().unwrap().update_count, 4);
+
+        let carry = carry.reset(CarryReset::all());
+        assert!(is_zero(&carry.continuum_memory.unwrap().episodic));
+        assert_eq!(carry.self_modify.unwrap().update_count, 0);
+    }
+
+    #[test]
+    fn test_eval_mode_disables_dropout_and_holds_self_modify() {
+        type A = Autodiff<NdArray<f32>>;
+        let device = Default::default();
+        let config = HopeConfig {
+            level_timescales: vec![2],
+            dropout: 0.5,
+            self_modify: SelfModifyConfig { enabled: true, update_frequency: 1, ..Default::default() },
+            ..HopeConfig::tiny()
+        };
+        let model = HopeModel::<A>::new(config, &device);
+        let run = |model: &HopeModel<A>| {
+            let tokens = Tensor::<A, 2, Int>::from_ints([[1, 2, 3, 4]], &device);
+            let (carry, output) = model.forward(HopeInput { tokens }, model.initial_carry(1, &device));
+            (carry.self_modify.unwrap().update_count, output.logits.into_data())
+        };
+
+        // Dropout on the autodiff backend makes training passes differ
+        let (updates, a) = run(&model);
+        let (_, b) = run(&model);
+        assert_eq!(updates, 2);
+        assert_ne!(a, b);
+
+        // Without dropout (inner backend) training computes the same logits
+        let train_valid = model.valid();
+        let tokens = || HopeInput { tokens: Tensor::from_ints([[1, 2, 3, 4]], &device) };
+        let (_, train_output) = train_valid.forward(tokens(), train_valid.initial_carry(1, &device));
+
+        // Eval mode computes the same function, but the carry it returns
+        // still holds the initial meta state
+        let model = model.eval();
+        let (updates, a) = run(&model);
+        let (_, b) = run(&model);
+        assert_eq!(updates, 0);
+        a.assert_eq(&b, true);
+        a.assert_approx_eq::<f32>(&train_output.logits.into_data(), Default::default());
+
+        // The mode survives `valid` and can be switched back
+        let valid = model.valid();
+        let (carry, output) = valid.forward(tokens(), valid.initial_carry(1, &device));
+        assert_eq!(carry.self_modify.unwrap().update_count, 0);
+        output.logits.into_data().assert_approx_eq::<f32>(&a, Default::default());
+        assert_eq!(run(&model.train()).0, 2);
+    }
+
+    #[test]
+    fn test_decode_matches_full_forward() {
+        let device = Default::default();
+        // Memory retrieved once from the embeddings, and gated before every
+        // level with skip connections and fused level outputs
+        let gated = ContinuumMemConfig { gated: true, retrieve_every_level: true, ..Default::default() };
+        for (continuum_mem, level_skip, level_fusion) in [
+            (ContinuumMemConfig::default(), false, LevelFusionMode::Last),
+            (gated, true, LevelFusionMode::Concat),
+        ] {
+            let config = HopeConfig {
+                seq_len: 6,
+                num_layers: 2,
+                num_levels: 2,
+                level_timescales: vec![1, 2],
+                level_skip,
+                level_fusion,
+                continuum_mem,
+                self_modify: SelfModifyConfig {
+                    enabled: true,
+                    ..Default::default()
+                },
+                ..HopeConfig::tiny()
+            };
+            let model = HopeModel::<NdArray<f32>>::new(config, &device);
+            let sequence = [3i64, 1, 4, 1, 5, 2, 6, 5];
+            let row = |tokens: &[i64]| Tensor::<NdArray<f32>, 1, Int>::from_ints(tokens, &device).reshape([1, tokens.len()]);
+            let close = |a: Tensor<NdArray<f32>, 2>, b: Tensor<NdArray<f32>, 2>| {
+                let (a, b) = (a.into_data().to_vec::<f32>().unwrap(), b.into_data().to_vec::<f32>().unwrap());
+                a.len() == b.len() && a.iter().zip(&b).all(|(x, y)| (x - y).abs() < 1e-4)
+            };
+
+            // Prompt of two tokens, then one token at a time up to the window
+            let mut cache = model.decode_cache(model.initial_carry(1, &device));
+            let logits = model.decode(&mut cache, row(&sequence[..2]));
+            assert!(close(logits, model.next_token_logits(row(&sequence[..2]))));
+            for len in 3..=6 {
+                let logits = model.decode(&mut cache, row(&sequence[len - 1..len]));
+                assert_eq!(cache.len(), len);
+                assert!(close(logits, model.next_token_logits(row(&sequence[..len]))));
+            }
+
+            // Overflowing the window refills it from its last half
+            let logits = model.decode(&mut cache, row(&sequence[6..7]));
+            assert_eq!(cache.len(), 3);
+            assert!(close(logits, model.next_token_logits(row(&sequence[4..7]))));
+            let logits = model.decode(&mut cache, row(&sequence[7..8]));
+            assert!(close(logits, model.next_token_logits(row(&sequence[4..8]))));
+        }
+    }
+
+    #[test]
+    fn test_alibi_reads_past_seq_len() {
+        let device = Default::default();
+        let tokens = Tensor::<NdArray<f32>, 1, Int>::from_ints([1, 2, 3, 4, 5, 6, 7], &device).reshape([1, 7]);
+
+        // Unwindowed, and with a window that full passes split into blocks
+        for attention_window in [None, Some(3)] {
+            let config = HopeConfig {
+                position_encoding: PositionEncoding::Alibi,
+                attention_window,
+                ..HopeConfig::tiny()
+            };
+            let model = HopeModel::<NdArray<f32>>::new(config, &device);
+
+            let (_, output) = model.forward(HopeInput { tokens: tokens.clone() }, model.carry_with_len(1, 7, &device));
+            assert_eq!(output.logits.dims(), [1, 7, 8]);
+
+            // Decoding keeps the whole sequence instead of refilling at seq_len
+            let mut cache = model.decode_cache(model.initial_carry(1, &device));
+            let mut logits = model.decode(&mut cache, tokens.clone().slice([0..1, 0..2]));
+            for position in 2..7 {
+                logits = model.decode(&mut cache, tokens.clone().slice([0..1, position..position + 1]));
+            }
+            assert_eq!(cache.len(), 7);
+            let expected = model.next_token_logits(tokens.clone()).into_data().to_vec::<f32>().unwrap();
+            let logits = logits.into_data().to_vec::<f32>().unwrap();
+            assert!(logits.iter().zip(&expected).all(|(a, b)| (a - b).abs() < 1e-4));
+        }
+    }
+
+    #[test]
+    fn test_levels_with_their_own_shape() {
+        let device = Default::default();
+        let config = HopeConfig {
+            num_layers: 2,
+            num_levels: 2,
+            level_timescales: vec![1, 2],
+            level_num_layers: vec![2, 1],
+            level_num_heads: vec![2, 4],
+            level_hidden_sizes: vec![16, 8],
+            position_encoding: PositionEncoding::Alibi,
+            ..HopeConfig::tiny()
+        };
+        let model = HopeModel::<NdArray<f32>>::new(config.clone(), &device);
+        let uniform = HopeModel::<NdArray<f32>>::new(
+            HopeConfig { level_num_layers: Vec::new(), level_num_heads: Vec::new(), level_hidden_sizes: Vec::new(), ..config },
+            &device,
+        );
+        assert!(model.num_params() < uniform.num_params());
+
+        let tokens = Tensor::<NdArray<f32>, 1, Int>::from_ints([1, 2, 3, 4], &device).reshape([1, 4]);
+        let (carry, output) = model.forward(HopeInput { tokens: tokens.clone() }, model.initial_carry(1, &device));
+        assert_eq!(output.logits.dims(), [1, 4, 8]);
+        assert_eq!(carry.level_states[1].dims(), [1, 4, 16]);
+
+        let mut cache = model.decode_cache(model.initial_carry(1, &device));
+        model.decode(&mut cache, tokens.clone().slice([0..1, 0..3]));
+        let logits = model.decode(&mut cache, tokens.clone().slice([0..1, 3..4])).into_data().to_vec::<f32>().unwrap();
+        let expected = model.next_token_logits(tokens).into_data().to_vec::<f32>().unwrap();
+        assert!(logits.iter().zip(&expected).all(|(a, b)| (a - b).abs() < 1e-4));
+    }
+
+    #[test]
+    fn test_causal_forward_ignores_future_tokens() {
+        let device = Default::default();
+        // Whether the first three positions' logits survive changing the last token
+        let prefix_unchanged = |causal: bool| {
+            let config = HopeConfig { num_levels: 2, level_timescales: vec![1, 2], causal, ..HopeConfig::tiny() };
+            let model = HopeModel::<NdArray<f32>>::new(config, &device);
+            let logits = |tokens: [i64; 4]| {
+                let tokens = Tensor::<NdArray<f32>, 1, Int>::from_ints(tokens, &device).reshape([1, 4]);
+                let (_, output) = model.forward(HopeInput { tokens }, model.initial_carry(1, &device));
+                output.logits.slice([0..1, 0..3, 0..8]).into_data().to_vec::<f32>().unwrap()
+            };
+            let (a, b) = (logits([1, 2, 3, 4]), logits([1, 2, 3, 7]));
+            a.iter().zip(&b).all(|(x, y)| (x - y).abs() < 1e-5)
+        };
+
+        assert!(prefix_unchanged(true));
+        assert!(!prefix_unchanged(false));
+    }
+
+    #[test]
+    fn test_resize_vocab_keeps_shared_tokens() {
+        let device = Default::default();
+        let config = HopeConfig { num_levels: 2, level_timescales: vec![1, 2], ..HopeConfig::tiny() };
+        let model = HopeModel::<NdArray<f32>>::new(config, &device);
+        // Old tokens 2, 4 and 6 are dropped, two new tokens come in
+        let sources = [Some(0), Some(1), Some(3), None, Some(5), Some(7), None];
+        let resized = model.clone().resize_vocab(&sources);
+        assert_eq!(resized.config().vocab_size, 7);
+
+        let logits = |model: &HopeModel<NdArray<f32>>, tokens: [i64; 4]| {
+            let vocab_size = model.config().vocab_size;
+            let tokens = Tensor::<NdArray<f32>, 1, Int>::from_ints(tokens, &device).reshape([1, 4]);
+            let (_, output) = model.forward(HopeInput { tokens }, model.initial_carry(1, &device));
+            output.logits.reshape([4, vocab_size]).into_data().to_vec::<f32>().unwrap()
+        };
+        let old = logits(&model, [1, 3, 5, 7]);
+        let new = logits(&resized, [1, 2, 4, 5]);
+        for (old, new) in old.chunks(8).zip(new.chunks(7)) {
+            for (token, source) in sources.iter().enumerate() {
+                let expected = match source {
+                    Some(source) => old[*source],
+                    None => old.iter().sum::<f32>() / 8.0,
+                };
+                assert!((new[token] - expected).abs() < 1e-4);
+            }
+        }
+    }
+}
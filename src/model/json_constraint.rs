@@ -0,0 +1,307 @@
+/// Containers open around the current position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Container {
+    Object,
+    Array,
+}
+
+/// Progress inside a string literal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    None,
+    /// After `\`
+    Backslash,
+    /// Hex digits of a `\u` escape still to come
+    Unicode(u8),
+}
+
+/// Progress inside a number literal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Number {
+    Minus,
+    Zero,
+    Integer,
+    Dot,
+    Fraction,
+    Exponent,
+    ExponentSign,
+    ExponentDigits,
+}
+
+impl Number {
+    /// Whether the number may end here
+    fn is_complete(self) -> bool {
+        matches!(self, Number::Zero | Number::Integer | Number::Fraction | Number::ExponentDigits)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Before the top-level object or array (no leading whitespace, so a
+    /// vocabulary without `{` and `[` ends generation immediately)
+    Start,
+    /// A value is expected (after `:`, or `,` in an array)
+    Value,
+    /// After `[`: a value or `]`
+    ArrayStart,
+    /// After `{`: a key or `}`
+    ObjectStart,
+    /// After `,` in an object
+    Key,
+    /// After an object key
+    Colon,
+    /// After a value inside a container: `,` or the closing bracket
+    AfterValue,
+    String { key: bool, escape: Escape },
+    Number(Number),
+    /// Inside `true`/`false`/`null`, with the remaining letters
+    Literal(&'static str),
+    /// The top-level value is closed
+    Done,
+}
+
+/// Incremental checker for a JSON prefix: a stack of open containers plus
+/// the state of the token being read. `push` rejects any character after
+/// which the text could no longer be completed to valid JSON.
+///
+/// Only an object or array is accepted at the top level, so the end of the
+/// document is unambiguous (a bare number could always continue).
+#[derive(Debug, Clone)]
+pub struct JsonValidator {
+    stack: Vec<Container>,
+    state: State,
+}
+
+impl Default for JsonValidator {
+    fn default() -> Self {
+        Self { stack: Vec::new(), state: State::Start }
+    }
+}
+
+impl JsonValidator {
+    /// Append `c`, returning false (and leaving the validator unusable) if
+    /// it cannot continue a valid JSON document
+    pub fn push(&mut self, c: char) -> bool {
+        match self.state {
+            State::Done => false,
+            State::Start => matches!(c, '{' | '[') && self.begin_value(c),
+            State::Value => is_whitespace(c) || self.begin_value(c),
+            State::ArrayStart => match c {
+                ']' => self.close(Container::Array),
+                _ => is_whitespace(c) || self.begin_value(c),
+            },
+            State::ObjectStart | State::Key => match c {
+                '"' => {
+                    self.state = State::String { key: true, escape: Escape::None };
+                    true
+                }
+                '}' if self.state == State::ObjectStart => self.close(Container::Object),
+                _ => is_whitespace(c),
+            },
+            State::Colon => match c {
+                ':' => {
+                    self.state = State::Value;
+                    true
+                }
+                _ => is_whitespace(c),
+            },
+            State::AfterValue => match c {
+                ',' => {
+                    self.state = match self.stack.last() {
+                        Some(Container::Object) => State::Key,
+                        _ => State::Value,
+                    };
+                    true
+                }
+                '}' => self.close(Container::Object),
+                ']' => self.close(Container::Array),
+                _ => is_whitespace(c),
+            },
+            State::String { key, escape } => {
+                let escape = match escape {
+                    Escape::None => match c {
+                        '"' => {
+                            if key {
+                                self.state = State::Colon;
+                            } else {
+                                self.end_value();
+                            }
+                            return true;
+                        }
+                        '\\' => Escape::Backslash,
+                        c if (c as u32) < 0x20 => return false,
+                        _ => Escape::None,
+                    },
+                    Escape::Backslash => match c {
+                        '"' | '\\' | '/' | 'b' | 'f' | 'n' | 'r' | 't' => Escape::None,
+                        'u' => Escape::Unicode(4),
+                        _ => return false,
+                    },
+                    Escape::Unicode(left) => {
+                        if !c.is_ascii_hexdigit() {
+                            return false;
+                        }
+                        if left == 1 { Escape::None } else { Escape::Unicode(left - 1) }
+                    }
+                };
+                self.state = State::String { key, escape };
+                true
+            }
+            State::Number(number) => {
+                let next = match (number, c) {
+                    (Number::Minus, '0') => Number::Zero,
+                    (Number::Minus, '1'..='9') => Number::Integer,
+                    (Number::Integer, '0'..='9') => Number::Integer,
+                    (Number::Zero | Number::Integer, '.') => Number::Dot,
+                    (Number::Dot | Number::Fraction, '0'..='9') => Number::Fraction,
+                    (Number::Zero | Number::Integer | Number::Fraction, 'e' | 'E') => Number::Exponent,
+                    (Number::Exponent, '+' | '-') => Number::ExponentSign,
+                    (Number::Exponent | Number::ExponentSign | Number::ExponentDigits, '0'..='9') => {
+                        Number::ExponentDigits
+                    }
+                    // Any other character ends the number and is read after it
+                    _ if number.is_complete() => {
+                        self.end_value();
+                        return self.push(c);
+                    }
+                    _ => return false,
+                };
+                self.state = State::Number(next);
+                true
+            }
+            State::Literal(rest) => {
+                let mut chars = rest.chars();
+                if chars.next() != Some(c) {
+                    return false;
+                }
+                if chars.as_str().is_empty() {
+                    self.end_value();
+                } else {
+                    self.state = State::Literal(chars.as_str());
+                }
+                true
+            }
+        }
+    }
+
+    /// Whether `text` can be appended, without modifying the validator
+    pub fn accepts(&self, text: &str) -> bool {
+        let mut validator = self.clone();
+        !text.is_empty() && text.chars().all(|c| validator.push(c))
+    }
+
+    /// Whether the top-level object or array has been closed
+    pub fn is_complete(&self) -> bool {
+        self.state == State::Done
+    }
+
+    fn begin_value(&mut self, c: char) -> bool {
+        self.state = match c {
+            '{' => {
+                self.stack.push(Container::Object);
+                State::ObjectStart
+            }
+            '[' => {
+                self.stack.push(Container::Array);
+                State::ArrayStart
+            }
+            '"' => State::String { key: false, escape: Escape::None },
+            '-' => State::Number(Number::Minus),
+            '0' => State::Number(Number::Zero),
+            '1'..='9' => State::Number(Number::Integer),
+            't' => State::Literal("rue"),
+            'f' => State::Literal("alse"),
+            'n' => State::Literal("ull"),
+            _ => return false,
+        };
+        true
+    }
+
+    fn close(&mut self, container: Container) -> bool {
+        if self.stack.pop() != Some(container) {
+            return false;
+        }
+        self.end_value();
+        true
+    }
+
+    fn end_value(&mut self) {
+        self.state = if self.stack.is_empty() { State::Done } else { State::AfterValue };
+    }
+}
+
+fn is_whitespace(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\n' | '\r')
+}
+
+/// Restricts generation to a single JSON object or array: at every step
+/// only tokens whose text keeps the output a valid JSON prefix can be
+/// sampled, and a sequence ends as soon as the document is closed.
+#[derive(Debug, Clone)]
+pub struct JsonConstraint {
+    /// Text of every token id (empty = never allowed, e.g. padding)
+    pieces: Vec<String>,
+}
+
+impl JsonConstraint {
+    /// `pieces[id]` is the decoded text of token `id`; ids past the end and
+    /// empty pieces are never sampled
+    pub fn new(pieces: Vec<String>) -> Self {
+        Self { pieces }
+    }
+
+    /// Text of token `id`
+    pub fn piece(&self, id: usize) -> &str {
+        self.pieces.get(id).map_or("", String::as_str)
+    }
+
+    /// Set the score of every token `validator` would reject to -inf
+    pub fn mask(&self, validator: &JsonValidator, scores: &mut [f32]) {
+        for (id, score) in scores.iter_mut().enumerate() {
+            if !validator.accepts(self.piece(id)) {
+                *score = f32::NEG_INFINITY;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(text: &str) -> Option<bool> {
+        let mut validator = JsonValidator::default();
+        text.chars().all(|c| validator.push(c)).then(|| validator.is_complete())
+    }
+
+    #[test]
+    fn test_validator_tracks_json_prefixes() {
+        for complete in [
+            r#"{}"#,
+            r#"[1, -2.5e+3, 0.25, true, null, "a\"bé"]"#,
+            r#"{"a": {"b": [false, {}]}, "c": 10}"#,
+        ] {
+            assert_eq!(validate(complete), Some(true), "{}", complete);
+        }
+        for prefix in [r#"{"a": [1, 2"#, r#"["tr"#, r#"{"key"#, "[1.", "[-"] {
+            assert_eq!(validate(prefix), Some(false), "{}", prefix);
+        }
+        for invalid in ["1", " {}", r#"{"a" 1}"#, "[1,]", "[01]", "{]", r#"["\x"]"#, "[nul1]", "{} x", "[1.e5]"] {
+            assert_eq!(validate(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_mask_keeps_only_valid_pieces() {
+        let constraint = JsonConstraint::new(["", "{", "}", "\"", "a", "x"].map(String::from).to_vec());
+        let mut validator = JsonValidator::default();
+        let mut scores = vec![0.0; 7];
+        constraint.mask(&validator, &mut scores);
+        assert_eq!(scores.iter().map(|s| s.is_finite()).collect::<Vec<_>>(), [false, true, false, false, false, false, false]);
+
+        validator.push('{');
+        let mut scores = vec![0.0; 6];
+        constraint.mask(&validator, &mut scores);
+        assert_eq!(scores.iter().map(|s| s.is_finite()).collect::<Vec<_>>(), [false, false, true, true, false, false]);
+    }
+}
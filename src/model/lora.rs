@@ -0,0 +1,201 @@
+use burn::module::{Module, ModuleMapper, ModuleVisitor, Param};
+use burn::tensor::{Distribution, Tensor, backend::Backend};
+
+use super::HopeModel;
+
+/// Which weights get low-rank adapters, and their size
+#[derive(Debug, Clone)]
+pub struct LoraConfig {
+    pub rank: usize,
+    /// The adapter's update is scaled by `alpha / rank`
+    pub alpha: f32,
+    /// Module path prefixes whose 2-D weights (the `Linear` layers of the
+    /// level encoders and `head` by default) are adapted
+    pub targets: Vec<String>,
+}
+
+impl Default for LoraConfig {
+    fn default() -> Self {
+        Self {
+            rank: 8,
+            alpha: 16.0,
+            targets: vec!["level_encoders".to_string(), "head".to_string()],
+        }
+    }
+}
+
+impl LoraConfig {
+    pub fn validate(&self) {
+        assert!(self.rank > 0, "LoRA rank must be > 0");
+        assert!(self.alpha.is_finite() && self.alpha > 0.0, "LoRA alpha must be > 0");
+        assert!(!self.targets.is_empty(), "LoRA needs at least one target module");
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.alpha / self.rank as f32
+    }
+}
+
+/// Low-rank update `down · up` of one `[d_input, d_output]` weight
+#[derive(Module, Debug)]
+pub struct LoraAdapter<B: Backend> {
+    down: Param<Tensor<B, 2>>,
+    up: Param<Tensor<B, 2>>,
+}
+
+impl<B: Backend> LoraAdapter<B> {
+    /// `down` starts random and `up` at zero, so a fresh adapter leaves the
+    /// weight unchanged
+    fn new(d_input: usize, d_output: usize, rank: usize, device: &B::Device) -> Self {
+        let std = (1.0 / d_input as f64).sqrt();
+        Self {
+            down: Param::from_tensor(Tensor::random([d_input, rank], Distribution::Normal(0.0, std), device)),
+            up: Param::from_tensor(Tensor::zeros([rank, d_output], device)),
+        }
+    }
+
+    fn delta(&self, scale: f32) -> Tensor<B, 2> {
+        self.down.val().matmul(self.up.val()) * scale
+    }
+}
+
+/// Adapters of the targeted weights of a `HopeModel`. The base model stays
+/// frozen: `apply` builds a copy whose adapted weights are `W + down · up ·
+/// alpha / rank` with `W` detached, so only the adapters receive gradients.
+#[derive(Module, Debug)]
+pub struct LoraAdapters<B: Backend> {
+    adapters: Vec<LoraAdapter<B>>,
+    /// Module path of the weight each adapter updates
+    #[module(skip)]
+    paths: Vec<String>,
+    #[module(skip)]
+    scale: f32,
+}
+
+impl<B: Backend> LoraAdapters<B> {
+    /// Fresh adapters for every 2-D weight of `model` under `config.targets`
+    pub fn new(model: &HopeModel<B>, config: &LoraConfig, device: &B::Device) -> Self {
+        config.validate();
+
+        let mut visitor = AdaptedWeights { targets: &config.targets, path: Vec::new(), weights: Vec::new() };
+        model.visit(&mut visitor);
+        let (paths, adapters) = visitor
+            .weights
+            .into_iter()
+            .map(|(path, [d_input, d_output])| (path, LoraAdapter::new(d_input, d_output, config.rank, device)))
+            .unzip();
+
+        Self { adapters, paths, scale: config.scale() }
+    }
+
+    /// Module paths of the adapted weights
+    pub fn paths(&self) -> &[String] {
+        &self.paths
+    }
+
+    /// Number of trainable adapter parameters
+    pub fn num_params(&self) -> usize {
+        self.adapters
+            .iter()
+            .map(|adapter| adapter.down.val().shape().num_elements() + adapter.up.val().shape().num_elements())
+            .sum()
+    }
+
+    /// `model` with the adapters' updates added to its weights; every
+    /// weight of `model` itself is detached
+    pub fn apply(&self, model: &HopeModel<B>) -> HopeModel<B> {
+        let mut mapper = ApplyAdapters { adapters: self, path: Vec::new() };
+        model.clone().map(&mut mapper)
+    }
+}
+
+/// Path and shape of every 2-D weight under the target prefixes
+struct AdaptedWeights<'a> {
+    targets: &'a [String],
+    path: Vec<String>,
+    weights: Vec<(String, [usize; 2])>,
+}
+
+impl<B: Backend> ModuleVisitor<B> for AdaptedWeights<'_> {
+    fn enter_module(&mut self, name: &str, _container_type: &str) {
+        self.path.push(name.to_string());
+    }
+
+    fn exit_module(&mut self, _name: &str, _container_type: &str) {
+        self.path.pop();
+    }
+
+    fn visit_float<const D: usize>(&mut self, param: &Param<Tensor<B, D>>) {
+        let path = self.path.join(".");
+        let targeted = self.targets.iter().any(|prefix| {
+            path == *prefix || path.strip_prefix(prefix.as_str()).is_some_and(|rest| rest.starts_with('.'))
+        });
+        if D == 2 && targeted && path.ends_with(".weight") {
+            let dims = param.val().dims();
+            self.weights.push((path, [dims[0], dims[1]]));
+        }
+    }
+}
+
+struct ApplyAdapters<'a, B: Backend> {
+    adapters: &'a LoraAdapters<B>,
+    path: Vec<String>,
+}
+
+impl<B: Backend> ModuleMapper<B> for ApplyAdapters<'_, B> {
+    fn enter_module(&mut self, name: &str, _container_type: &str) {
+        self.path.push(name.to_string());
+    }
+
+    fn exit_module(&mut self, _name: &str, _container_type: &str) {
+        self.path.pop();
+    }
+
+    fn map_float<const D: usize>(&mut self, param: Param<Tensor<B, D>>) -> Param<Tensor<B, D>> {
+        let (id, weight, mapper) = param.consume();
+        let weight = weight.detach();
+        let path = self.path.join(".");
+        let adapted = match self.adapters.paths.iter().position(|adapted| *adapted == path) {
+            Some(i) => {
+                let delta = self.adapters.adapters[i].delta(self.adapters.scale);
+                let dims = weight.dims();
+                weight + delta.reshape(dims)
+            }
+            None => weight,
+        };
+        Param::from_mapped_value(id, adapted, mapper)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HopeConfig;
+    use crate::model::HopeInput;
+    use burn::tensor::Int;
+    use burn_ndarray::NdArray;
+
+    type B = NdArray<f32>;
+
+    #[test]
+    fn test_adapters_target_linear_weights() {
+        let device = Default::default();
+        let config = HopeConfig::tiny();
+        let model = HopeModel::<B>::new(config, &device);
+        let lora = LoraAdapters::new(&model, &LoraConfig { rank: 2, ..Default::default() }, &device);
+
+        // 4 attention projections + 2 feed-forward layers, and the head
+        assert_eq!(lora.paths().len(), 7);
+        assert!(lora.paths().iter().all(|path| path.ends_with(".weight")));
+        assert!(lora.paths().contains(&"head.weight".to_string()));
+        assert!(!lora.paths().iter().any(|path| path.starts_with("token_embed")));
+        assert!(lora.num_params() > 0);
+
+        // Fresh adapters leave the outputs unchanged
+        let tokens = Tensor::<B, 1, Int>::arange(0..4, &device).reshape([1, 4]);
+        let (_, base) = model.forward(HopeInput { tokens: tokens.clone() }, model.initial_carry(1, &device));
+        let adapted = lora.apply(&model);
+        let (_, output) = adapted.forward(HopeInput { tokens }, adapted.initial_carry(1, &device));
+        base.logits.into_data().assert_approx_eq::<f32>(&output.logits.into_data(), Default::default());
+    }
+}
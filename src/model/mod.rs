@@ -1,6 +1,16 @@
 pub mod continuum_mem;
+pub mod encoder;
+pub mod flops;
+pub mod fusion;
+pub mod generate;
 pub mod hope;
+pub mod json_constraint;
+pub mod lora;
+pub mod onnx;
 pub mod optimizer;
+pub mod parity;
+pub mod profile;
+pub mod prune;
 pub mod self_modify;
 
-pub use hope::{HopeModel, HopeInput};
+pub use hope::{HopeModel, HopeInput, HopeCarry, CarryReset};
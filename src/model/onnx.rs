@@ -0,0 +1,944 @@
+use burn::nn::{LayerNorm, Linear};
+use burn::tensor::{Tensor, backend::Backend};
+
+/// ONNX IR version and default-domain opset of exported models
+pub const ONNX_IR_VERSION: i64 = 8;
+pub const ONNX_OPSET: i64 = 17;
+
+/// An ONNX graph under construction: the modules of a model add their
+/// nodes, with their parameters as initializers, and `to_bytes` encodes the
+/// `ModelProto`. Node outputs get unique names (`t<n>`), returned by the
+/// builder methods to be used as inputs of the following nodes.
+#[derive(Debug, Default)]
+pub struct OnnxGraph {
+    nodes: Vec<OnnxNode>,
+    initializers: Vec<Initializer>,
+    inputs: Vec<ValueInfo>,
+    outputs: Vec<ValueInfo>,
+    metadata: Vec<(String, String)>,
+    next_name: usize,
+}
+
+#[derive(Debug, Clone)]
+struct OnnxNode {
+    op_type: &'static str,
+    inputs: Vec<String>,
+    outputs: Vec<String>,
+    attributes: Vec<(&'static str, Attribute)>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Attribute {
+    Float(f32),
+    Int(i64),
+    Ints(Vec<i64>),
+}
+
+#[derive(Debug, Clone)]
+struct Initializer {
+    name: String,
+    dims: Vec<usize>,
+    data: TensorValues,
+}
+
+#[derive(Debug, Clone)]
+enum TensorValues {
+    Float(Vec<f32>),
+    Int64(Vec<i64>),
+}
+
+/// Name, element type and shape of a graph input or output
+#[derive(Debug, Clone)]
+struct ValueInfo {
+    name: String,
+    elem_type: i64,
+    dims: Vec<Dim>,
+}
+
+/// A fixed dimension or a named one (e.g. the batch) left to the runtime
+#[derive(Debug, Clone)]
+pub enum Dim {
+    Fixed(usize),
+    Named(&'static str),
+}
+
+// TensorProto.DataType
+const FLOAT: i64 = 1;
+const INT64: i64 = 7;
+
+impl OnnxGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declare the int64 graph input `name`
+    pub fn int64_input(&mut self, name: &str, dims: Vec<Dim>) -> String {
+        self.inputs.push(ValueInfo { name: name.to_string(), elem_type: INT64, dims });
+        name.to_string()
+    }
+
+    /// Expose `value` as the float graph output `name`
+    pub fn float_output(&mut self, value: &str, name: &str, dims: Vec<Dim>) {
+        self.nodes.push(OnnxNode {
+            op_type: "Identity",
+            inputs: vec![value.to_string()],
+            outputs: vec![name.to_string()],
+            attributes: Vec::new(),
+        });
+        self.outputs.push(ValueInfo { name: name.to_string(), elem_type: FLOAT, dims });
+    }
+
+    /// Add a `metadata_props` entry of the model
+    pub fn metadata(&mut self, key: &str, value: impl ToString) {
+        self.metadata.push((key.to_string(), value.to_string()));
+    }
+
+    fn fresh_name(&mut self, prefix: &str) -> String {
+        self.next_name += 1;
+        format!("{}{}", prefix, self.next_name)
+    }
+
+    /// Float constant of the given shape
+    pub fn floats(&mut self, dims: &[usize], values: Vec<f32>) -> String {
+        assert_eq!(dims.iter().product::<usize>(), values.len(), "constant shape and values differ");
+        let name = self.fresh_name("c");
+        self.initializers.push(Initializer { name: name.clone(), dims: dims.to_vec(), data: TensorValues::Float(values) });
+        name
+    }
+
+    /// Float scalar constant
+    pub fn scalar(&mut self, value: f32) -> String {
+        self.floats(&[], vec![value])
+    }
+
+    /// 1-D int64 constant (shapes, axes, split sizes)
+    pub fn int64s(&mut self, values: &[i64]) -> String {
+        let name = self.fresh_name("c");
+        self.initializers.push(Initializer { name: name.clone(), dims: vec![values.len()], data: TensorValues::Int64(values.to_vec()) });
+        name
+    }
+
+    /// Constant holding the values of `tensor`
+    pub fn tensor<B: Backend, const D: usize>(&mut self, tensor: Tensor<B, D>) -> String {
+        let dims = tensor.dims().to_vec();
+        let values = tensor.into_data().convert::<f32>().to_vec::<f32>().expect("f32 tensor data");
+        self.floats(&dims, values)
+    }
+
+    /// Node `op_type` with a single output
+    pub fn node(&mut self, op_type: &'static str, inputs: &[&str], attributes: Vec<(&'static str, Attribute)>) -> String {
+        self.node_outputs(op_type, inputs, attributes, 1).remove(0)
+    }
+
+    /// Node `op_type` with `outputs` outputs
+    pub fn node_outputs(
+        &mut self,
+        op_type: &'static str,
+        inputs: &[&str],
+        attributes: Vec<(&'static str, Attribute)>,
+        outputs: usize,
+    ) -> Vec<String> {
+        let outputs: Vec<String> = (0..outputs).map(|_| self.fresh_name("t")).collect();
+        self.nodes.push(OnnxNode {
+            op_type,
+            inputs: inputs.iter().map(|input| input.to_string()).collect(),
+            outputs: outputs.clone(),
+            attributes,
+        });
+        outputs
+    }
+
+    pub fn add(&mut self, a: &str, b: &str) -> String {
+        self.node("Add", &[a, b], Vec::new())
+    }
+
+    pub fn mul(&mut self, a: &str, b: &str) -> String {
+        self.node("Mul", &[a, b], Vec::new())
+    }
+
+    pub fn matmul(&mut self, a: &str, b: &str) -> String {
+        self.node("MatMul", &[a, b], Vec::new())
+    }
+
+    /// `input` times a constant
+    pub fn scale(&mut self, input: &str, factor: f32) -> String {
+        let factor = self.scalar(factor);
+        self.mul(input, &factor)
+    }
+
+    /// `Reshape` to `shape`, where 0 keeps the input's dimension and -1 is inferred
+    pub fn reshape(&mut self, input: &str, shape: &[i64]) -> String {
+        let shape = self.int64s(shape);
+        self.node("Reshape", &[input, &shape], Vec::new())
+    }
+
+    pub fn transpose(&mut self, input: &str, perm: &[i64]) -> String {
+        self.node("Transpose", &[input], vec![("perm", Attribute::Ints(perm.to_vec()))])
+    }
+
+    /// Softmax over the last dimension
+    pub fn softmax(&mut self, input: &str) -> String {
+        self.node("Softmax", &[input], vec![("axis", Attribute::Int(-1))])
+    }
+
+    pub fn concat(&mut self, inputs: &[&str], axis: i64) -> String {
+        self.node("Concat", inputs, vec![("axis", Attribute::Int(axis))])
+    }
+
+    /// `linear` applied to the last dimension of `input`
+    pub fn linear<B: Backend>(&mut self, linear: &Linear<B>, input: &str) -> String {
+        let weight = self.tensor(linear.weight.val());
+        let output = self.matmul(input, &weight);
+        match linear.bias {
+            Some(ref bias) => {
+                let bias = self.tensor(bias.val());
+                self.add(&output, &bias)
+            }
+            None => output,
+        }
+    }
+
+    /// `norm` over the last dimension of `input` (burn's default epsilon)
+    pub fn layer_norm<B: Backend>(&mut self, norm: &LayerNorm<B>, input: &str) -> String {
+        let gamma = self.tensor(norm.gamma.val());
+        let beta = self.tensor(norm.beta.val());
+        self.node(
+            "LayerNormalization",
+            &[input, &gamma, &beta],
+            vec![("axis", Attribute::Int(-1)), ("epsilon", Attribute::Float(1e-5))],
+        )
+    }
+
+    /// Exact GELU: x * (1 + erf(x / sqrt(2))) / 2
+    pub fn gelu(&mut self, input: &str) -> String {
+        let sqrt_2 = self.scalar(std::f32::consts::SQRT_2);
+        let scaled = self.node("Div", &[input, &sqrt_2], Vec::new());
+        let erf = self.node("Erf", &[&scaled], Vec::new());
+        let one = self.scalar(1.0);
+        let shifted = self.add(&erf, &one);
+        let product = self.mul(input, &shifted);
+        self.scale(&product, 0.5)
+    }
+
+    /// SiLU: x * sigmoid(x)
+    pub fn silu(&mut self, input: &str) -> String {
+        let sigmoid = self.node("Sigmoid", &[input], Vec::new());
+        self.mul(input, &sigmoid)
+    }
+
+    /// Encoded `ModelProto`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut graph = Message::default();
+        for node in &self.nodes {
+            graph.message(1, node_proto(node));
+        }
+        graph.string(2, "hope");
+        for initializer in &self.initializers {
+            graph.message(5, tensor_proto(initializer));
+        }
+        for input in &self.inputs {
+            graph.message(11, value_info_proto(input));
+        }
+        for output in &self.outputs {
+            graph.message(12, value_info_proto(output));
+        }
+
+        let mut model = Message::default();
+        model.int(1, ONNX_IR_VERSION);
+        model.string(2, "hope");
+        model.string(3, env!("CARGO_PKG_VERSION"));
+        model.message(7, graph);
+        let mut opset = Message::default();
+        opset.string(1, "");
+        opset.int(2, ONNX_OPSET);
+        model.message(8, opset);
+        for (key, value) in &self.metadata {
+            let mut entry = Message::default();
+            entry.string(1, key);
+            entry.string(2, value);
+            model.message(14, entry);
+        }
+        model.0
+    }
+}
+
+fn node_proto(node: &OnnxNode) -> Message {
+    let mut proto = Message::default();
+    for input in &node.inputs {
+        proto.string(1, input);
+    }
+    for output in &node.outputs {
+        proto.string(2, output);
+    }
+    proto.string(3, &node.outputs[0]);
+    proto.string(4, node.op_type);
+    for (name, attribute) in &node.attributes {
+        let mut attr = Message::default();
+        attr.string(1, name);
+        // AttributeProto.AttributeType: FLOAT = 1, INT = 2, INTS = 7
+        match attribute {
+            Attribute::Float(value) => {
+                attr.float(2, *value);
+                attr.int(20, 1);
+            }
+            Attribute::Int(value) => {
+                attr.int(3, *value);
+                attr.int(20, 2);
+            }
+            Attribute::Ints(values) => {
+                attr.packed_ints(8, values);
+                attr.int(20, 7);
+            }
+        }
+        proto.message(5, attr);
+    }
+    proto
+}
+
+fn tensor_proto(initializer: &Initializer) -> Message {
+    let mut proto = Message::default();
+    let dims: Vec<i64> = initializer.dims.iter().map(|&dim| dim as i64).collect();
+    proto.packed_ints(1, &dims);
+    let (data_type, raw): (i64, Vec<u8>) = match initializer.data {
+        TensorValues::Float(ref values) => (FLOAT, values.iter().flat_map(|value| value.to_le_bytes()).collect()),
+        TensorValues::Int64(ref values) => (INT64, values.iter().flat_map(|value| value.to_le_bytes()).collect()),
+    };
+    proto.int(2, data_type);
+    proto.string(8, &initializer.name);
+    proto.bytes(9, &raw);
+    proto
+}
+
+fn value_info_proto(info: &ValueInfo) -> Message {
+    let mut shape = Message::default();
+    for dim in &info.dims {
+        let mut dimension = Message::default();
+        match dim {
+            Dim::Fixed(size) => dimension.int(1, *size as i64),
+            Dim::Named(name) => dimension.string(2, name),
+        }
+        shape.message(1, dimension);
+    }
+    let mut tensor_type = Message::default();
+    tensor_type.int(1, info.elem_type);
+    tensor_type.message(2, shape);
+    let mut type_proto = Message::default();
+    type_proto.message(1, tensor_type);
+
+    let mut proto = Message::default();
+    proto.string(1, &info.name);
+    proto.message(2, type_proto);
+    proto
+}
+
+/// Protocol Buffers wire encoding of one message
+#[derive(Default)]
+struct Message(Vec<u8>);
+
+impl Message {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+
+    fn tag(&mut self, field: u32, wire_type: u32) {
+        self.varint(((field << 3) | wire_type) as u64);
+    }
+
+    fn int(&mut self, field: u32, value: i64) {
+        self.tag(field, 0);
+        self.varint(value as u64);
+    }
+
+    fn float(&mut self, field: u32, value: f32) {
+        self.tag(field, 5);
+        self.0.extend(value.to_le_bytes());
+    }
+
+    fn bytes(&mut self, field: u32, bytes: &[u8]) {
+        self.tag(field, 2);
+        self.varint(bytes.len() as u64);
+        self.0.extend(bytes);
+    }
+
+    fn string(&mut self, field: u32, value: &str) {
+        self.bytes(field, value.as_bytes());
+    }
+
+    fn message(&mut self, field: u32, message: Message) {
+        self.bytes(field, &message.0);
+    }
+
+    fn packed_ints(&mut self, field: u32, values: &[i64]) {
+        let mut packed = Message::default();
+        for &value in values {
+            packed.varint(value as u64);
+        }
+        self.bytes(field, &packed.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{
+        ContinuumMemConfig, FfnActivation, HopeConfig, LevelFusionMode, PositionEncoding, SelfModifyConfig,
+    };
+    use crate::model::{HopeInput, HopeModel};
+    use burn::tensor::Int;
+    use anyhow::{anyhow, bail, ensure, Result};
+    use burn_ndarray::NdArray;
+    use std::collections::HashMap;
+    use std::ops::RangeInclusive;
+
+    type B = NdArray<f32>;
+
+    /// Dense tensor of the reference interpreter (int64 values as floats)
+    #[derive(Debug, Clone)]
+    struct Value {
+        shape: Vec<usize>,
+        data: Vec<f32>,
+    }
+
+    fn unravel(mut flat: usize, shape: &[usize]) -> Vec<usize> {
+        let mut index = vec![0; shape.len()];
+        for (i, &dim) in shape.iter().enumerate().rev() {
+            index[i] = flat % dim;
+            flat /= dim;
+        }
+        index
+    }
+
+    /// Flat offset of `index` into `shape`, broadcasting its size-1 dimensions
+    fn offset(index: &[usize], shape: &[usize]) -> usize {
+        index.iter().zip(shape).fold(0, |acc, (&i, &dim)| acc * dim + if dim == 1 { 0 } else { i })
+    }
+
+    fn padded(shape: &[usize], rank: usize) -> Vec<usize> {
+        let mut padded = vec![1; rank - shape.len()];
+        padded.extend(shape);
+        padded
+    }
+
+    fn binary(a: &Value, b: &Value, f: impl Fn(f32, f32) -> f32) -> Value {
+        let rank = a.shape.len().max(b.shape.len());
+        let (sa, sb) = (padded(&a.shape, rank), padded(&b.shape, rank));
+        let shape: Vec<usize> = sa.iter().zip(&sb).map(|(&x, &y)| x.max(y)).collect();
+        let data = (0..shape.iter().product())
+            .map(|flat| {
+                let index = unravel(flat, &shape);
+                f(a.data[offset(&index, &sa)], b.data[offset(&index, &sb)])
+            })
+            .collect();
+        Value { shape, data }
+    }
+
+    fn matmul(a: &Value, b: &Value) -> Value {
+        let rank = a.shape.len().max(b.shape.len());
+        let (sa, sb) = (padded(&a.shape, rank), padded(&b.shape, rank));
+        let (n, k, m) = (sa[rank - 2], sa[rank - 1], sb[rank - 1]);
+        assert_eq!(sb[rank - 2], k, "MatMul inner dimensions");
+        let batch: Vec<usize> = sa[..rank - 2].iter().zip(&sb[..rank - 2]).map(|(&x, &y)| x.max(y)).collect();
+        let mut data = Vec::new();
+        for flat in 0..batch.iter().product() {
+            let index = unravel(flat, &batch);
+            let a_start = offset(&index, &sa[..rank - 2]) * n * k;
+            let b_start = offset(&index, &sb[..rank - 2]) * k * m;
+            for row in 0..n {
+                for col in 0..m {
+                    data.push((0..k).map(|i| a.data[a_start + row * k + i] * b.data[b_start + i * m + col]).sum());
+                }
+            }
+        }
+        let mut shape = batch;
+        shape.extend([n, m]);
+        Value { shape, data }
+    }
+
+    /// Rows of the last dimension mapped by `f`
+    fn rows(x: &Value, f: impl Fn(&[f32]) -> Vec<f32>) -> Value {
+        let width = *x.shape.last().unwrap();
+        Value { shape: x.shape.clone(), data: x.data.chunks(width).flat_map(f).collect() }
+    }
+
+    fn axis(node: &OnnxNode, rank: usize) -> usize {
+        let axis = node
+            .attributes
+            .iter()
+            .find_map(|(name, attribute)| match attribute {
+                Attribute::Int(axis) if *name == "axis" => Some(*axis),
+                _ => None,
+            })
+            .unwrap_or(0);
+        if axis < 0 { (rank as i64 + axis) as usize } else { axis as usize }
+    }
+
+    /// Split `x` along `axis` into parts of the given sizes
+    fn split(x: &Value, axis: usize, sizes: &[usize]) -> Vec<Value> {
+        let outer: usize = x.shape[..axis].iter().product();
+        let inner: usize = x.shape[axis + 1..].iter().product();
+        let mut start = 0;
+        sizes
+            .iter()
+            .map(|&size| {
+                let mut data = Vec::new();
+                for o in 0..outer {
+                    let base = (o * x.shape[axis] + start) * inner;
+                    data.extend_from_slice(&x.data[base..base + size * inner]);
+                }
+                start += size;
+                let mut shape = x.shape.clone();
+                shape[axis] = size;
+                Value { shape, data }
+            })
+            .collect()
+    }
+
+    /// Abramowitz and Stegun 7.1.26 (absolute error below 1.5e-7)
+    fn erf(x: f32) -> f32 {
+        let x = f64::from(x);
+        let t = 1.0 / (1.0 + 0.3275911 * x.abs());
+        let poly = t * (0.254829592 + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+        (1.0 - poly * (-x * x).exp()).copysign(x) as f32
+    }
+
+    /// Reference evaluation of `graph` on `inputs`
+    fn run(graph: &OnnxGraph, inputs: Vec<(&str, Value)>) -> HashMap<String, Value> {
+        let mut values: HashMap<String, Value> = inputs.into_iter().map(|(name, value)| (name.to_string(), value)).collect();
+        for initializer in &graph.initializers {
+            let data = match initializer.data {
+                TensorValues::Float(ref data) => data.clone(),
+                TensorValues::Int64(ref data) => data.iter().map(|&value| value as f32).collect(),
+            };
+            values.insert(initializer.name.clone(), Value { shape: initializer.dims.clone(), data });
+        }
+        for node in &graph.nodes {
+            let input = |i: usize| &values[&node.inputs[i]];
+            let ints = |i: usize| -> Vec<i64> { input(i).data.iter().map(|&value| value as i64).collect() };
+            let unary = |f: fn(f32) -> f32| {
+                let x = input(0);
+                Value { shape: x.shape.clone(), data: x.data.iter().map(|&v| f(v)).collect() }
+            };
+            let outputs = match node.op_type {
+                "Identity" => vec![input(0).clone()],
+                "Add" => vec![binary(input(0), input(1), |a, b| a + b)],
+                "Mul" => vec![binary(input(0), input(1), |a, b| a * b)],
+                "Div" => vec![binary(input(0), input(1), |a, b| a / b)],
+                "MatMul" => vec![matmul(input(0), input(1))],
+                "Relu" => vec![unary(|v| v.max(0.0))],
+                "Tanh" => vec![unary(f32::tanh)],
+                "Sigmoid" => vec![unary(|v| 1.0 / (1.0 + (-v).exp()))],
+                "Erf" => vec![unary(erf)],
+                "Clip" => {
+                    let (min, max) = (input(1).data[0], input(2).data[0]);
+                    let x = input(0);
+                    vec![Value { shape: x.shape.clone(), data: x.data.iter().map(|v| v.clamp(min, max)).collect() }]
+                }
+                "Softmax" => vec![rows(input(0), |row| {
+                    let max = row.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+                    let exp: Vec<f32> = row.iter().map(|v| (v - max).exp()).collect();
+                    let sum: f32 = exp.iter().sum();
+                    exp.iter().map(|v| v / sum).collect()
+                })],
+                "LayerNormalization" => {
+                    let (gamma, beta) = (input(1), input(2));
+                    vec![rows(input(0), |row| {
+                        let mean = row.iter().sum::<f32>() / row.len() as f32;
+                        let var = row.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / row.len() as f32;
+                        row.iter()
+                            .enumerate()
+                            .map(|(i, v)| (v - mean) / (var + 1e-5).sqrt() * gamma.data[i] + beta.data[i])
+                            .collect()
+                    })]
+                }
+                "Reshape" => {
+                    let x = input(0);
+                    let mut shape: Vec<i64> = ints(1);
+                    for (i, dim) in shape.iter_mut().enumerate() {
+                        if *dim == 0 {
+                            *dim = x.shape[i] as i64;
+                        }
+                    }
+                    let known: i64 = shape.iter().filter(|&&dim| dim != -1).product();
+                    let shape = shape.iter().map(|&dim| if dim == -1 { x.data.len() / known as usize } else { dim as usize }).collect();
+                    vec![Value { shape, data: x.data.clone() }]
+                }
+                "Transpose" => {
+                    let Attribute::Ints(ref perm) = node.attributes[0].1 else { panic!("Transpose perm") };
+                    let x = input(0);
+                    let shape: Vec<usize> = perm.iter().map(|&p| x.shape[p as usize]).collect();
+                    let data = (0..x.data.len())
+                        .map(|flat| {
+                            let out = unravel(flat, &shape);
+                            let mut index = vec![0; out.len()];
+                            for (i, &p) in perm.iter().enumerate() {
+                                index[p as usize] = out[i];
+                            }
+                            x.data[offset(&index, &x.shape)]
+                        })
+                        .collect();
+                    vec![Value { shape, data }]
+                }
+                "Gather" => {
+                    let (table, indices) = (input(0), input(1));
+                    let width = table.shape[1];
+                    let data = indices.data.iter().flat_map(|&i| table.data[i as usize * width..(i as usize + 1) * width].to_vec()).collect();
+                    let mut shape = indices.shape.clone();
+                    shape.push(width);
+                    vec![Value { shape, data }]
+                }
+                "Concat" => {
+                    let parts: Vec<&Value> = (0..node.inputs.len()).map(input).collect();
+                    let axis = axis(node, parts[0].shape.len());
+                    let outer: usize = parts[0].shape[..axis].iter().product();
+                    let mut data = Vec::new();
+                    for o in 0..outer {
+                        for part in &parts {
+                            let chunk: usize = part.shape[axis..].iter().product();
+                            data.extend_from_slice(&part.data[o * chunk..(o + 1) * chunk]);
+                        }
+                    }
+                    let mut shape = parts[0].shape.clone();
+                    shape[axis] = parts.iter().map(|part| part.shape[axis]).sum();
+                    vec![Value { shape, data }]
+                }
+                "Split" => {
+                    let x = input(0);
+                    let sizes: Vec<usize> = ints(1).iter().map(|&size| size as usize).collect();
+                    split(x, axis(node, x.shape.len()), &sizes)
+                }
+                "Slice" => {
+                    let x = input(0);
+                    let (start, end, axis) = (ints(1)[0] as usize, ints(2)[0] as usize, ints(3)[0] as usize);
+                    let end = end.min(x.shape[axis]);
+                    let parts = split(x, axis, &[start, end - start, x.shape[axis] - end]);
+                    vec![parts[1].clone()]
+                }
+                op_type => panic!("no reference for {}", op_type),
+            };
+            for (name, value) in node.outputs.iter().zip(outputs) {
+                values.insert(name.clone(), value);
+            }
+        }
+        values
+    }
+
+    /// One field of a decoded protobuf message
+    #[derive(Debug)]
+    enum Field<'a> {
+        Varint(u64),
+        Fixed32,
+        Bytes(&'a [u8]),
+    }
+
+    type Fields<'a> = Vec<(u32, Field<'a>)>;
+
+    fn varint(bytes: &mut &[u8]) -> u64 {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = bytes.split_first().expect("truncated varint");
+            *bytes = rest;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte < 0x80 {
+                return value;
+            }
+        }
+        panic!("varint longer than 10 bytes")
+    }
+
+    /// Wire-format decoding written from the protobuf spec, independent of `Message`
+    fn decode(mut bytes: &[u8]) -> Fields<'_> {
+        let mut fields = Vec::new();
+        while !bytes.is_empty() {
+            let key = varint(&mut bytes);
+            let field = match key & 7 {
+                0 => Field::Varint(varint(&mut bytes)),
+                2 => {
+                    let len = varint(&mut bytes) as usize;
+                    assert!(len <= bytes.len(), "truncated field {}", key >> 3);
+                    let (data, rest) = bytes.split_at(len);
+                    bytes = rest;
+                    Field::Bytes(data)
+                }
+                5 => {
+                    assert!(bytes.len() >= 4, "truncated field {}", key >> 3);
+                    bytes = &bytes[4..];
+                    Field::Fixed32
+                }
+                wire_type => panic!("unexpected wire type {}", wire_type),
+            };
+            fields.push(((key >> 3) as u32, field));
+        }
+        fields
+    }
+
+    fn bytes_fields<'a>(fields: &[(u32, Field<'a>)], number: u32) -> Vec<&'a [u8]> {
+        fields
+            .iter()
+            .filter(|(field, _)| *field == number)
+            .map(|(_, value)| match value {
+                Field::Bytes(bytes) => *bytes,
+                other => panic!("field {} is {:?}, not length-delimited", number, other),
+            })
+            .collect()
+    }
+
+    fn messages<'a>(fields: &[(u32, Field<'a>)], number: u32) -> Vec<Fields<'a>> {
+        bytes_fields(fields, number).into_iter().map(decode).collect()
+    }
+
+    fn strings(fields: &[(u32, Field)], number: u32) -> Vec<String> {
+        bytes_fields(fields, number)
+            .into_iter()
+            .map(|bytes| String::from_utf8(bytes.to_vec()).expect("invalid UTF-8"))
+            .collect()
+    }
+
+    fn string(fields: &[(u32, Field)], number: u32) -> String {
+        strings(fields, number).pop().unwrap_or_default()
+    }
+
+    fn int(fields: &[(u32, Field)], number: u32) -> Option<i64> {
+        fields.iter().rev().find_map(|(field, value)| match value {
+            Field::Varint(value) if *field == number => Some(*value as i64),
+            _ => None,
+        })
+    }
+
+    fn packed(fields: &[(u32, Field)], number: u32) -> Vec<i64> {
+        let mut values = Vec::new();
+        for mut bytes in bytes_fields(fields, number) {
+            while !bytes.is_empty() {
+                values.push(varint(&mut bytes) as i64);
+            }
+        }
+        values
+    }
+
+    /// Element type of a `ValueInfoProto` holding a tensor type with a shape
+    fn tensor_elem_type(info: &[(u32, Field)]) -> Option<i64> {
+        let type_proto = messages(info, 2).pop()?;
+        let tensor_type = messages(&type_proto, 1).pop()?;
+        messages(&tensor_type, 2).pop()?;
+        int(&tensor_type, 1)
+    }
+
+    /// Input counts, inputs that must be int64, output counts and attributes of an operator
+    type Signature = (RangeInclusive<usize>, &'static [usize], RangeInclusive<usize>, &'static [&'static str]);
+
+    /// Signatures of the default-domain operators in opset 17 the exporter uses
+    fn signature(op_type: &str) -> Option<Signature> {
+        let signature: Signature = match op_type {
+            "Identity" | "Relu" | "Tanh" | "Sigmoid" | "Erf" => (1..=1, &[], 1..=1, &[]),
+            "Add" | "Mul" | "Div" | "MatMul" => (2..=2, &[], 1..=1, &[]),
+            "Clip" => (1..=3, &[], 1..=1, &[]),
+            "Softmax" => (1..=1, &[], 1..=1, &["axis"]),
+            "LayerNormalization" => (2..=3, &[], 1..=3, &["axis", "epsilon", "stash_type"]),
+            "Reshape" => (2..=2, &[1], 1..=1, &["allowzero"]),
+            "Transpose" => (1..=1, &[], 1..=1, &["perm"]),
+            "Gather" => (2..=2, &[1], 1..=1, &["axis"]),
+            "Concat" => (1..=usize::MAX, &[], 1..=1, &["axis"]),
+            "Split" => (1..=2, &[1], 1..=usize::MAX, &["axis"]),
+            "Slice" => (3..=5, &[1, 2, 3, 4], 1..=1, &[]),
+            _ => return None,
+        };
+        Some(signature)
+    }
+
+    fn define(defined: &mut HashMap<String, (i64, Option<Vec<i64>>)>, name: String, value: (i64, Option<Vec<i64>>)) -> Result<()> {
+        ensure!(!name.is_empty(), "unnamed value");
+        ensure!(defined.insert(name.clone(), value).is_none(), "{} is defined twice", name);
+        Ok(())
+    }
+
+    /// Check encoded model bytes against the rules `onnx.checker` enforces on
+    /// the IR: decoded from the wire format alone, the graph must only use
+    /// operators of the declared opset with their inputs, outputs and
+    /// attributes, read values after they are defined, define each value once,
+    /// and hold initializers whose data matches their type and shape
+    fn check_model(bytes: &[u8]) -> Result<()> {
+        let model = decode(bytes);
+        ensure!(int(&model, 1) == Some(ONNX_IR_VERSION), "ir_version is not {}", ONNX_IR_VERSION);
+        ensure!(
+            messages(&model, 8).iter().any(|opset| string(opset, 1).is_empty() && int(opset, 2) == Some(ONNX_OPSET)),
+            "no default-domain opset {}",
+            ONNX_OPSET
+        );
+        let mut graphs = messages(&model, 7);
+        ensure!(graphs.len() == 1, "{} graphs", graphs.len());
+        let graph = graphs.pop().unwrap();
+        ensure!(!string(&graph, 2).is_empty(), "unnamed graph");
+
+        // Element type and, for initializers, the shape of each defined value
+        let mut defined = HashMap::new();
+        for initializer in messages(&graph, 5) {
+            let dims = packed(&initializer, 1);
+            let elem_type = int(&initializer, 2).unwrap_or_default();
+            let width = match elem_type {
+                FLOAT => 4,
+                INT64 => 8,
+                other => bail!("initializer of data type {}", other),
+            };
+            let raw = bytes_fields(&initializer, 9).concat();
+            ensure!(raw.len() as i64 == dims.iter().product::<i64>() * width, "initializer data does not match {:?}", dims);
+            define(&mut defined, string(&initializer, 8), (elem_type, Some(dims)))?;
+        }
+        for input in messages(&graph, 11) {
+            let elem_type = tensor_elem_type(&input).ok_or_else(|| anyhow!("input without a tensor type"))?;
+            define(&mut defined, string(&input, 1), (elem_type, None))?;
+        }
+
+        for node in messages(&graph, 1) {
+            let op_type = string(&node, 4);
+            let (input_counts, int64_inputs, output_counts, attributes) =
+                signature(&op_type).ok_or_else(|| anyhow!("{} is not an opset {} operator", op_type, ONNX_OPSET))?;
+            let inputs = strings(&node, 1);
+            let outputs = strings(&node, 2);
+            ensure!(input_counts.contains(&inputs.len()), "{} with {} inputs", op_type, inputs.len());
+            ensure!(output_counts.contains(&outputs.len()), "{} with {} outputs", op_type, outputs.len());
+            for input in &inputs {
+                ensure!(defined.contains_key(input), "{} reads {} before it is defined", op_type, input);
+            }
+            for &i in int64_inputs {
+                if let Some(input) = inputs.get(i) {
+                    ensure!(defined[input].0 == INT64, "input {} of {} must be an int64 input or initializer", i, op_type);
+                }
+            }
+            if op_type == "Clip" {
+                for bound in &inputs[1..] {
+                    ensure!(defined[bound] == (FLOAT, Some(Vec::new())), "Clip bounds must be float scalars");
+                }
+            }
+
+            let mut names = Vec::new();
+            for attribute in messages(&node, 5) {
+                let name = string(&attribute, 1);
+                ensure!(attributes.contains(&name.as_str()), "{} has no attribute {}", op_type, name);
+                ensure!(!names.contains(&name), "attribute {} of {} repeated", name, op_type);
+                // AttributeProto.type selects the field holding the value
+                let field = match int(&attribute, 20) {
+                    Some(1) => 2,
+                    Some(2) => 3,
+                    Some(7) => 8,
+                    other => bail!("attribute {} of type {:?}", name, other),
+                };
+                ensure!(attribute.iter().any(|(number, _)| *number == field), "attribute {} has no value", name);
+                names.push(name);
+            }
+            if op_type == "Concat" {
+                ensure!(names.iter().any(|name| name == "axis"), "Concat without an axis");
+            }
+            // Node outputs are not typed by the graph
+            for output in outputs {
+                define(&mut defined, output, (0, None))?;
+            }
+        }
+
+        for output in messages(&graph, 12) {
+            let name = string(&output, 1);
+            ensure!(defined.contains_key(&name), "output {} is never computed", name);
+            ensure!(tensor_elem_type(&output) == Some(FLOAT), "output {} is not a float tensor", name);
+        }
+        Ok(())
+    }
+
+    /// `HopeConfig::tiny` with two levels of two layers
+    fn config() -> HopeConfig {
+        HopeConfig { seq_len: 6, num_layers: 2, num_levels: 2, level_timescales: vec![1, 2], ..HopeConfig::tiny() }
+    }
+
+    #[test]
+    fn test_onnx_graph_matches_forward() {
+        let device = Default::default();
+        let gated = ContinuumMemConfig { gated: true, retrieve_every_level: true, ..Default::default() };
+        let compressed = ContinuumMemConfig {
+            memory_slots: Some(2),
+            slot_dim: Some(4),
+            top_k: vec![0, 2, 0, 1, 0],
+            ..Default::default()
+        };
+        let self_modify = SelfModifyConfig { enabled: true, update_frequency: 2, ..Default::default() };
+        let configs = [
+            // Learned positions, causal GELU levels with gated memory,
+            // self-modification, skips and concatenated levels
+            HopeConfig {
+                continuum_mem: gated,
+                self_modify: self_modify.clone(),
+                level_skip: true,
+                level_fusion: LevelFusionMode::Concat,
+                ..config()
+            },
+            // Windowed bidirectional ALiBi with SwiGLU, a narrower level,
+            // compressed and pruned memory and weighted levels
+            HopeConfig {
+                causal: false,
+                position_encoding: PositionEncoding::Alibi,
+                attention_window: Some(2),
+                ffn_activation: FfnActivation::Swiglu,
+                level_hidden_sizes: vec![16, 8],
+                continuum_mem: compressed,
+                self_modify,
+                level_fusion: LevelFusionMode::Weighted,
+                ..config()
+            },
+            HopeConfig {
+                ffn_activation: FfnActivation::Silu,
+                continuum_mem: ContinuumMemConfig { enabled: false, ..Default::default() },
+                level_fusion: LevelFusionMode::Sum,
+                ..config()
+            },
+        ];
+
+        for config in configs {
+            let model = HopeModel::<B>::new(config, &device);
+            let tokens = [3i64, 1, 4, 1, 5, 2, 6, 5, 0, 7];
+            let len = 5;
+            let graph = model.onnx_graph(len).unwrap();
+            check_model(&graph.to_bytes()).unwrap();
+            let input = Value { shape: vec![2, len], data: tokens.iter().map(|&token| token as f32).collect() };
+            let logits = run(&graph, vec![("tokens", input)]).remove("logits").unwrap();
+
+            let tokens = Tensor::<B, 1, Int>::from_ints(tokens, &device).reshape([2, len]);
+            let (_, output) = model.forward(HopeInput { tokens }, model.carry_with_len(2, len, &device));
+            assert_eq!(logits.shape, output.logits.dims());
+            let expected = output.logits.into_data().to_vec::<f32>().unwrap();
+            for (actual, expected) in logits.data.iter().zip(&expected) {
+                assert!((actual - expected).abs() < 1e-4, "{} != {}", actual, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_onnx_model_encoding_and_limits() {
+        let device = Default::default();
+        let model = HopeModel::<B>::new(config(), &device);
+        let bytes = model.onnx_graph(4).unwrap().to_bytes();
+        // ir_version (field 1, varint) first, then the producer name
+        assert_eq!(&bytes[..4], &[0x08, ONNX_IR_VERSION as u8, 0x12, 4]);
+        assert_eq!(&bytes[4..8], b"hope");
+        check_model(&bytes).unwrap();
+
+        // The checker rejects reads of undefined values and unknown operators
+        let mut graph = OnnxGraph::new();
+        let tokens = graph.int64_input("tokens", vec![Dim::Fixed(1)]);
+        let sum = graph.add(&tokens, "missing");
+        graph.float_output(&sum, "logits", vec![Dim::Fixed(1)]);
+        assert!(check_model(&graph.to_bytes()).is_err());
+        let mut graph = OnnxGraph::new();
+        let tokens = graph.int64_input("tokens", vec![Dim::Fixed(1)]);
+        let gelu = graph.node("Gelu", &[&tokens], Vec::new());
+        graph.float_output(&gelu, "logits", vec![Dim::Fixed(1)]);
+        assert!(check_model(&graph.to_bytes()).is_err());
+
+        // Learned positions only cover seq_len tokens
+        assert!(model.onnx_graph(7).is_err());
+        let config = HopeConfig {
+            continuum_mem: ContinuumMemConfig { retrieval_top_k: Some(2), ..Default::default() },
+            ..config()
+        };
+        assert!(HopeModel::<B>::new(config, &device).onnx_graph(4).is_err());
+    }
+}
@@ -1,8 +1,11 @@
+use burn::record::Record;
 use burn::tensor::{Tensor, backend::Backend};
 use crate::config::DeepOptimizerConfig;
 
-#[allow(dead_code)]
-#[derive(Clone, Debug)]
+/// Per-level fast/slow parameters in hidden-state space. During training the
+/// fast parameters are added to each level's input (see `HopeCarry::level_biases`)
+/// and updated from their gradients.
+#[derive(Record, Clone, Debug)]
 pub struct DeepOptimizerState<B: Backend> {
     pub fast_params: Vec<Tensor<B, 3>>,
     pub slow_params: Vec<Tensor<B, 3>>,
@@ -11,19 +14,16 @@ pub struct DeepOptimizerState<B: Backend> {
     pub step_count: usize,
 }
 
-#[allow(dead_code)]
 pub struct DeepOptimizer {
     config: DeepOptimizerConfig,
 }
 
 impl DeepOptimizer {
-    #[allow(dead_code)]
     pub fn new(config: DeepOptimizerConfig) -> Self {
         config.validate();
         Self { config }
     }
 
-    #[allow(dead_code)]
     pub fn init_state<B: Backend>(
         &self,
         num_levels: usize,
@@ -43,7 +43,6 @@ impl DeepOptimizer {
         }
     }
 
-    #[allow(dead_code)]
     pub fn update_fast_params<B: Backend>(
         &self,
         state: &mut DeepOptimizerState<B>,
@@ -74,7 +73,6 @@ impl DeepOptimizer {
         state.step_count += 1;
     }
 
-    #[allow(dead_code)]
     pub fn update_slow_params<B: Backend>(
         &self,
         state: &mut DeepOptimizerState<B>,
@@ -125,12 +123,10 @@ impl DeepOptimizer {
         grad_avg.slice([0..batch, 0..compress_dim])
     }
 
-    #[allow(dead_code)]
     pub fn should_sync<B: Backend>(&self, state: &DeepOptimizerState<B>) -> bool {
-        self.config.enabled && (state.step_count % self.config.sync_interval == 0)
+        self.config.enabled && state.step_count.is_multiple_of(self.config.sync_interval)
     }
 
-    #[allow(dead_code)]
     pub fn sync<B: Backend>(
         &self,
         state: &mut DeepOptimizerState<B>,
@@ -0,0 +1,230 @@
+use anyhow::{Context, Result};
+use burn::tensor::{Int, Tensor, backend::Backend};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::HopeConfig;
+use super::{HopeInput, HopeModel};
+
+/// Reference output of a checkpoint's forward pass on a pinned input, for
+/// checking another implementation (e.g. a Python port) against this one.
+///
+/// The model reads `tokens[..n-1]` from a fresh carry and predicts
+/// `tokens[1..]`; every per-position field has `n - 1` entries.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParityFixture {
+    pub checkpoint: PathBuf,
+    pub step: usize,
+    pub config: HopeConfig,
+    /// Text the tokens were encoded from, for reference only
+    pub text: String,
+    pub tokens: Vec<i64>,
+    /// Decimal places the logits are rounded to before hashing
+    pub decimals: usize,
+    /// SHA-256 of the `[n-1, vocab_size]` logits, see `logits_hash`
+    pub logits_sha256: String,
+    /// Mean cross-entropy of the predictions
+    pub loss: f32,
+    /// Per position: index of the largest logit
+    pub argmax: Vec<usize>,
+    /// Per position: log-sum-exp of the logits
+    pub logsumexp: Vec<f32>,
+    /// Per position: logit of the next token
+    pub target_logits: Vec<f32>,
+}
+
+/// How far a forward pass is from a fixture
+#[derive(Debug, Clone, Serialize)]
+pub struct ParityReport {
+    /// The rounded logits hash to the fixture's value
+    pub hash_matches: bool,
+    pub loss_diff: f32,
+    /// Largest difference of `logsumexp` and `target_logits`
+    pub max_abs_diff: f32,
+    /// Positions whose argmax differs
+    pub argmax_mismatches: usize,
+}
+
+impl ParityReport {
+    /// Matching up to `tolerance`: the hash may differ from float rounding
+    /// alone, so it is not required
+    pub fn passed(&self, tolerance: f32) -> bool {
+        self.loss_diff <= tolerance && self.max_abs_diff <= tolerance && self.argmax_mismatches == 0
+    }
+}
+
+/// SHA-256 (hex) of `logits` written one value per line with `decimals`
+/// decimal places (`"\n".join(f"{x:.4f}" for x in logits.flatten())` in
+/// Python), so that runtimes agreeing up to rounding produce the same hash
+pub fn logits_hash(logits: &[f32], decimals: usize) -> String {
+    let mut hasher = Sha256::new();
+    for (i, value) in logits.iter().enumerate() {
+        if i > 0 {
+            hasher.update(b"\n");
+        }
+        hasher.update(format!("{:.*}", decimals, value).as_bytes());
+    }
+    hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Per-position statistics of one forward pass over `tokens`
+struct ForwardStats {
+    logits_sha256: String,
+    loss: f32,
+    argmax: Vec<usize>,
+    logsumexp: Vec<f32>,
+    target_logits: Vec<f32>,
+}
+
+fn forward_stats<B: Backend>(
+    model: &HopeModel<B>,
+    tokens: &[i64],
+    decimals: usize,
+    device: &B::Device,
+) -> Result<ForwardStats> {
+    let seq_len = tokens.len().saturating_sub(1);
+    if seq_len == 0 {
+        anyhow::bail!("Parity input needs at least 2 tokens");
+    }
+    if seq_len > model.config().seq_len {
+        anyhow::bail!("Parity input has {} tokens, the model reads at most {} + 1", tokens.len(), model.config().seq_len);
+    }
+    if let Some(&token) = tokens.iter().find(|&&token| token < 0 || token as usize >= model.config().vocab_size) {
+        anyhow::bail!("Token {} is outside the model's vocabulary of {}", token, model.config().vocab_size);
+    }
+
+    let input = Tensor::<B, 1, Int>::from_ints(&tokens[..seq_len], device).reshape([1, seq_len]);
+    let carry = model.carry_with_len(1, seq_len, device);
+    let (_, output) = model.forward(HopeInput { tokens: input }, carry);
+
+    let vocab_size = output.logits.dims()[2];
+    let logits = output.logits.into_data().convert::<f32>().to_vec::<f32>().unwrap_or_default();
+
+    let mut argmax = Vec::with_capacity(seq_len);
+    let mut logsumexp = Vec::with_capacity(seq_len);
+    let mut target_logits = Vec::with_capacity(seq_len);
+    let mut total_loss = 0.0f64;
+    for (position, row) in logits.chunks(vocab_size).enumerate() {
+        let (best, &max) = row
+            .iter()
+            .enumerate()
+            .fold((0, &f32::NEG_INFINITY), |best, (i, value)| if *value > *best.1 { (i, value) } else { best });
+        let sum: f64 = row.iter().map(|&value| f64::from(value - max).exp()).sum();
+        let lse = max + sum.ln() as f32;
+        let target = row[tokens[position + 1] as usize];
+
+        argmax.push(best);
+        logsumexp.push(lse);
+        target_logits.push(target);
+        total_loss += f64::from(lse - target);
+    }
+
+    Ok(ForwardStats {
+        logits_sha256: logits_hash(&logits, decimals),
+        loss: (total_loss / seq_len as f64) as f32,
+        argmax,
+        logsumexp,
+        target_logits,
+    })
+}
+
+/// Fixture of `model`'s forward pass over `tokens`
+pub fn export_fixture<B: Backend>(
+    model: &HopeModel<B>,
+    checkpoint: &Path,
+    step: usize,
+    text: &str,
+    tokens: Vec<i64>,
+    decimals: usize,
+    device: &B::Device,
+) -> Result<ParityFixture> {
+    let stats = forward_stats(model, &tokens, decimals, device)?;
+    Ok(ParityFixture {
+        checkpoint: checkpoint.to_path_buf(),
+        step,
+        config: model.config().clone(),
+        text: text.to_string(),
+        tokens,
+        decimals,
+        logits_sha256: stats.logits_sha256,
+        loss: stats.loss,
+        argmax: stats.argmax,
+        logsumexp: stats.logsumexp,
+        target_logits: stats.target_logits,
+    })
+}
+
+/// Compare `model`'s forward pass over the fixture's tokens with the fixture
+pub fn verify_fixture<B: Backend>(
+    model: &HopeModel<B>,
+    fixture: &ParityFixture,
+    device: &B::Device,
+) -> Result<ParityReport> {
+    let stats = forward_stats(model, &fixture.tokens, fixture.decimals, device)?;
+    let max_abs_diff = stats
+        .logsumexp
+        .iter()
+        .zip(&fixture.logsumexp)
+        .chain(stats.target_logits.iter().zip(&fixture.target_logits))
+        .map(|(a, b)| (a - b).abs())
+        .fold(0.0f32, f32::max);
+
+    Ok(ParityReport {
+        hash_matches: stats.logits_sha256 == fixture.logits_sha256,
+        loss_diff: (stats.loss - fixture.loss).abs(),
+        max_abs_diff,
+        argmax_mismatches: stats.argmax.iter().zip(&fixture.argmax).filter(|(a, b)| a != b).count(),
+    })
+}
+
+pub fn save_fixture(fixture: &ParityFixture, path: &Path) -> Result<()> {
+    let json = serde_json::to_string_pretty(fixture).with_context(|| "Failed to serialize parity fixture")?;
+    fs::write(path, json).with_context(|| format!("Failed to write parity fixture: {:?}", path))
+}
+
+pub fn load_fixture(path: &Path) -> Result<ParityFixture> {
+    let json = fs::read_to_string(path).with_context(|| format!("Failed to read parity fixture: {:?}", path))?;
+    serde_json::from_str(&json).with_context(|| format!("Failed to parse parity fixture: {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use burn_ndarray::NdArray;
+
+    type B = NdArray<f32>;
+
+    #[test]
+    fn test_fixture_round_trip() {
+        let device = Default::default();
+        let config = HopeConfig { seq_len: 6, dropout: 0.0, ..HopeConfig::tiny() };
+        let model = HopeModel::<B>::new(config.clone(), &device);
+        let tokens = vec![1, 5, 2, 7, 0, 3];
+        let fixture = export_fixture(&model, Path::new("ckpt.json"), 3, "", tokens.clone(), 4, &device).unwrap();
+        assert_eq!(fixture.argmax.len(), 5);
+        assert!((fixture.loss - 8f32.ln()).abs() < 1.0);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fixture.json");
+        save_fixture(&fixture, &path).unwrap();
+        let report = verify_fixture(&model, &load_fixture(&path).unwrap(), &device).unwrap();
+        assert!(report.hash_matches && report.passed(1e-6), "{:?}", report);
+
+        // Different weights are caught
+        let other = HopeModel::<B>::new(config, &device);
+        let report = verify_fixture(&other, &fixture, &device).unwrap();
+        assert!(!report.hash_matches && !report.passed(1e-4), "{:?}", report);
+
+        assert!(export_fixture(&model, Path::new(""), 0, "", vec![1; 8], 4, &device).is_err());
+        assert!(export_fixture(&model, Path::new(""), 0, "", vec![1, 9], 4, &device).is_err());
+    }
+
+    #[test]
+    fn test_logits_hash_is_rounded_text() {
+        let expected: String = Sha256::digest(b"1.0000\n-0.2500").iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(logits_hash(&[1.0, -0.25], 4), expected);
+        assert_eq!(logits_hash(&[1.00001, -0.25], 4), expected);
+    }
+}
@@ -0,0 +1,218 @@
+use anyhow::{Context, Result};
+use burn::tensor::backend::Backend;
+use serde::Serialize;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Part of `HopeModel::forward` timed by `StageTimes`, in forward order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ForwardStage {
+    /// Token and position embeddings
+    Embedding,
+    /// Continuum memory retrieval: projection of the query
+    MemoryQuery,
+    /// Continuum memory retrieval: key/value projection (and top-k pruning)
+    /// of the memory banks
+    MemoryKeys,
+    /// Continuum memory retrieval: attention over the banks
+    MemoryAttention,
+    /// Encoder of one level, over all of its timescale steps
+    Level(usize),
+    SelfModify,
+    /// Writing the last level's output into the memory banks
+    MemoryUpdate,
+    Head,
+}
+
+impl fmt::Display for ForwardStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Embedding => write!(f, "embedding"),
+            Self::MemoryQuery => write!(f, "memory_query"),
+            Self::MemoryKeys => write!(f, "memory_keys"),
+            Self::MemoryAttention => write!(f, "memory_attention"),
+            Self::Level(level) => write!(f, "level_{}", level),
+            Self::SelfModify => write!(f, "self_modify"),
+            Self::MemoryUpdate => write!(f, "memory_update"),
+            Self::Head => write!(f, "head"),
+        }
+    }
+}
+
+/// Timings of one stage; a stage may run several times per pass (a level
+/// once per timescale step)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StageStats {
+    pub calls: usize,
+    pub total: Duration,
+    /// Longest single call
+    pub max: Duration,
+}
+
+/// One row of a profile report
+#[derive(Debug, Clone, Serialize)]
+pub struct StageSummary {
+    pub stage: String,
+    pub calls: usize,
+    pub total_ms: f64,
+    /// Mean milliseconds per forward pass
+    pub ms_per_pass: f64,
+    pub max_ms: f64,
+    /// Fraction of the forward time
+    pub share: f64,
+}
+
+/// Forward stage times aggregated over a training run (`training.profile`)
+#[derive(Debug, Clone, Serialize)]
+pub struct ProfileReport {
+    /// Forward passes timed
+    pub passes: usize,
+    /// Mean milliseconds per forward pass
+    pub forward_ms: f64,
+    /// Stages in forward order
+    pub stages: Vec<StageSummary>,
+}
+
+pub fn save_profile_report(report: &ProfileReport, path: &Path) -> Result<()> {
+    let json = serde_json::to_string_pretty(report).with_context(|| "Failed to serialize profile report")?;
+    fs::write(path, json).with_context(|| format!("Failed to write profile report: {:?}", path))
+}
+
+/// Wall-clock time per `ForwardStage`, aggregated over forward passes.
+/// Each lap synchronizes the device, so asynchronous backends are timed
+/// where the work happens (at the cost of some overlap). A disabled
+/// instance records nothing and never synchronizes.
+#[derive(Debug, Clone)]
+pub struct StageTimes {
+    enabled: bool,
+    passes: usize,
+    stages: Vec<(ForwardStage, StageStats)>,
+    last: Option<Instant>,
+}
+
+impl Default for StageTimes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StageTimes {
+    pub fn new() -> Self {
+        Self { enabled: true, passes: 0, stages: Vec::new(), last: None }
+    }
+
+    pub fn disabled() -> Self {
+        Self { enabled: false, ..Self::new() }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Start timing a forward pass after the device's pending work
+    pub fn start<B: Backend>(&mut self, device: &B::Device) {
+        if !self.enabled {
+            return;
+        }
+        B::sync(device);
+        self.passes += 1;
+        self.last = Some(Instant::now());
+    }
+
+    /// Charge the time since the previous lap (or `start`) to `stage`
+    pub fn lap<B: Backend>(&mut self, stage: ForwardStage, device: &B::Device) {
+        if !self.enabled {
+            return;
+        }
+        B::sync(device);
+        let now = Instant::now();
+        let elapsed = self.last.map(|last| now - last).unwrap_or_default();
+        self.last = Some(now);
+
+        let stats = match self.stages.iter_mut().find(|(timed, _)| *timed == stage) {
+            Some((_, stats)) => stats,
+            None => {
+                self.stages.push((stage, StageStats::default()));
+                &mut self.stages.last_mut().expect("just pushed").1
+            }
+        };
+        stats.calls += 1;
+        stats.total += elapsed;
+        stats.max = stats.max.max(elapsed);
+    }
+
+    /// Forward passes timed so far
+    pub fn passes(&self) -> usize {
+        self.passes
+    }
+
+    /// Stages in forward order
+    pub fn stats(&self) -> Vec<(ForwardStage, StageStats)> {
+        let mut stages = self.stages.clone();
+        stages.sort_by_key(|(stage, _)| *stage);
+        stages
+    }
+
+    pub fn total(&self) -> Duration {
+        self.stages.iter().map(|(_, stats)| stats.total).sum()
+    }
+
+    /// Report rows in forward order
+    pub fn summary(&self) -> Vec<StageSummary> {
+        let passes = self.passes.max(1) as f64;
+        let total = self.total().as_secs_f64().max(f64::EPSILON);
+        self.stats()
+            .into_iter()
+            .map(|(stage, stats)| StageSummary {
+                stage: stage.to_string(),
+                calls: stats.calls,
+                total_ms: stats.total.as_secs_f64() * 1e3,
+                ms_per_pass: stats.total.as_secs_f64() * 1e3 / passes,
+                max_ms: stats.max.as_secs_f64() * 1e3,
+                share: stats.total.as_secs_f64() / total,
+            })
+            .collect()
+    }
+
+    pub fn report(&self) -> ProfileReport {
+        ProfileReport {
+            passes: self.passes,
+            forward_ms: self.total().as_secs_f64() * 1e3 / self.passes.max(1) as f64,
+            stages: self.summary(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use burn_ndarray::NdArray;
+
+    type B = NdArray<f32>;
+
+    #[test]
+    fn test_stage_times_aggregate_calls() {
+        let device = Default::default();
+        let mut times = StageTimes::new();
+        for _ in 0..2 {
+            times.start::<B>(&device);
+            times.lap::<B>(ForwardStage::Head, &device);
+            times.lap::<B>(ForwardStage::Level(0), &device);
+            times.lap::<B>(ForwardStage::Level(0), &device);
+        }
+
+        assert_eq!(times.passes(), 2);
+        let summary = times.summary();
+        let stages: Vec<_> = summary.iter().map(|row| (row.stage.as_str(), row.calls)).collect();
+        assert_eq!(stages, [("level_0", 4), ("head", 2)]);
+        assert!(summary.iter().all(|row| row.max_ms <= row.total_ms));
+
+        let mut disabled = StageTimes::disabled();
+        disabled.start::<B>(&device);
+        disabled.lap::<B>(ForwardStage::Head, &device);
+        assert_eq!(disabled.passes(), 0);
+        assert!(disabled.summary().is_empty());
+    }
+}
@@ -0,0 +1,104 @@
+use serde::Serialize;
+
+/// Structured magnitude pruning of the encoder layers (`HopeModel::prune`):
+/// in every layer, the feed-forward neurons and attention heads with the
+/// smallest weights are pruned
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PruneOptions {
+    /// Fraction of the feed-forward neurons of every layer to prune, in [0, 1)
+    pub ffn_ratio: f32,
+    /// Fraction of the attention heads of every layer to prune, in [0, 1)
+    pub head_ratio: f32,
+    /// Remove the pruned neurons from the weights (shrinking the level's
+    /// `level_feedforward_dims`) instead of zeroing them. Heads are always
+    /// zeroed: a level's head width follows from its hidden size.
+    pub remove: bool,
+}
+
+impl PruneOptions {
+    pub fn validate(&self) {
+        assert!((0.0..1.0).contains(&self.ffn_ratio), "ffn_ratio must be within [0,1)");
+        assert!((0.0..1.0).contains(&self.head_ratio), "head_ratio must be within [0,1)");
+    }
+}
+
+/// Units pruned by `HopeModel::prune`, over all layers
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct PruneReport {
+    pub ffn_neurons: usize,
+    pub heads: usize,
+    pub params_before: usize,
+    /// Fewer than `params_before` only when neurons are removed
+    pub params_after: usize,
+}
+
+/// Number of `total` units pruned at `ratio`, always keeping one
+pub(crate) fn prune_count(total: usize, ratio: f32) -> usize {
+    ((total as f32 * ratio).round() as usize).min(total.saturating_sub(1))
+}
+
+/// Indices of the `count` lowest `scores`, in increasing index order
+pub(crate) fn lowest(scores: &[f32], count: usize) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..scores.len()).collect();
+    order.sort_by(|&a, &b| scores[a].total_cmp(&scores[b]).then(a.cmp(&b)));
+    let mut pruned = order[..count].to_vec();
+    pruned.sort_unstable();
+    pruned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{FfnActivation, HopeConfig};
+    use crate::model::{HopeCarry, HopeInput, HopeModel};
+    use burn::module::Module;
+    use burn::tensor::{Int, Tensor};
+    use burn_ndarray::NdArray;
+
+    type B = NdArray<f32>;
+
+    #[test]
+    fn test_lowest_and_counts() {
+        assert_eq!(lowest(&[0.5, 0.1, 0.3, 0.1], 2), [1, 3]);
+        assert_eq!(prune_count(8, 0.25), 2);
+        assert_eq!(prune_count(2, 0.9), 1);
+        assert_eq!(prune_count(4, 0.0), 0);
+    }
+
+    #[test]
+    fn test_removing_neurons_matches_zeroing_them() {
+        let device = Default::default();
+        for ffn_activation in [FfnActivation::Gelu, FfnActivation::Swiglu] {
+            let config = HopeConfig {
+                num_heads: 4,
+                num_layers: 2,
+                num_levels: 2,
+                level_timescales: vec![1, 2],
+                ffn_activation,
+                ..HopeConfig::tiny()
+            };
+            let model = HopeModel::<B>::new(config, &device);
+            let zeroing = PruneOptions { ffn_ratio: 0.25, head_ratio: 0.5, remove: false };
+            let (zeroed, report) = model.clone().prune(&zeroing);
+            // 64 neurons and 4 heads in each of the 4 layers
+            assert_eq!(report.ffn_neurons, 4 * 16);
+            assert_eq!(report.heads, 4 * 2);
+            assert_eq!(report.params_after, report.params_before);
+
+            let (removed, report) = model.prune(&PruneOptions { remove: true, ..zeroing });
+            assert_eq!(removed.config().level_feedforward_dims, [48, 48]);
+            assert!(report.params_after < report.params_before);
+            assert_eq!(removed.num_params(), report.params_after);
+
+            let tokens = Tensor::<B, 1, Int>::from_ints([3, 1, 4, 1, 5, 2, 6, 5], &device).reshape([2, 4]);
+            let logits = |model: &HopeModel<B>| {
+                let carry: HopeCarry<B> = model.initial_carry(2, &device);
+                let (_, output) = model.forward(HopeInput { tokens: tokens.clone() }, carry);
+                output.logits
+            };
+            logits(&removed)
+                .into_data()
+                .assert_approx_eq::<f32>(&logits(&zeroed).into_data(), Default::default());
+        }
+    }
+}
@@ -1,15 +1,20 @@
 use burn::constant;
 use burn::module::Module;
 use burn::nn::{Dropout, DropoutConfig, LayerNorm, LayerNormConfig, Linear, LinearConfig};
+use burn::record::Record;
 use burn::tensor::{Tensor, activation, backend::Backend};
 use crate::config::SelfModifyConfig;
+use super::onnx::OnnxGraph;
 
 constant!(SelfModifyConfig);
 
-#[derive(Clone, Debug)]
+#[derive(Record, Clone, Debug)]
 pub struct SelfModifyState<B: Backend> {
     pub meta_state: Tensor<B, 2>,
     pub update_count: usize,
+    /// Hold the meta state and update count across forward passes (e.g.
+    /// during evaluation); within a pass the state still advances as usual
+    pub frozen: bool,
 }
 
 #[derive(Module, Debug)]
@@ -87,6 +92,7 @@ impl<B: Backend> SelfModifyModule<B> {
         SelfModifyState {
             meta_state: Tensor::zeros([batch, self.config.weight_mod_dim], device),
             update_count: 0,
+            frozen: false,
         }
     }
 
@@ -116,9 +122,18 @@ impl<B: Backend> SelfModifyModule<B> {
         let x = self.meta_network.layer3.forward(x);
         let update_rule = activation::tanh(x);
 
-        // Combine with previous meta state
-        let meta_state = state.meta_state.clone() * 0.9 + update_rule.clone() * 0.1;
-        meta_state
+        // Move the previous meta state towards the new rule at rate meta_lr
+        let rate = self.config.meta_lr;
+        state.meta_state.clone() * (1.0 - rate) + update_rule * rate
+    }
+
+    /// Advance `state` by one step: the meta state takes a new update rule
+    /// from `hidden` every `update_frequency` steps and is held in between
+    pub fn update_state(&self, hidden: &Tensor<B, 3>, state: &mut SelfModifyState<B>) {
+        if self.should_update(state) {
+            state.meta_state = self.compute_update_rule(hidden, state);
+        }
+        state.update_count += 1;
     }
 
     pub fn apply_weight_modification(
@@ -155,6 +170,51 @@ impl<B: Backend> SelfModifyModule<B> {
         self.norm.forward(modified)
     }
 
+    /// ONNX nodes of `compute_update_rule` from `hidden`, blended into
+    /// `meta_state` (zeros when `None`)
+    pub fn onnx_update_rule(&self, graph: &mut OnnxGraph, hidden: &str, meta_state: Option<&str>) -> String {
+        let starts = graph.int64s(&[0]);
+        let ends = graph.int64s(&[1]);
+        let axes = graph.int64s(&[1]);
+        let first = graph.node("Slice", &[hidden, &starts, &ends, &axes], Vec::new());
+        let meta_input = graph.reshape(&first, &[0, -1]);
+
+        let x = graph.linear(&self.meta_network.layer1, &meta_input);
+        let x = graph.node("Relu", &[&x], Vec::new());
+        let x = graph.linear(&self.meta_network.layer2, &x);
+        let x = graph.node("Relu", &[&x], Vec::new());
+        let x = graph.linear(&self.meta_network.layer3, &x);
+        let update_rule = graph.node("Tanh", &[&x], Vec::new());
+
+        let rate = self.config.meta_lr;
+        let update_rule = graph.scale(&update_rule, rate);
+        match meta_state {
+            Some(meta_state) => {
+                let kept = graph.scale(meta_state, 1.0 - rate);
+                graph.add(&kept, &update_rule)
+            }
+            None => update_rule,
+        }
+    }
+
+    /// ONNX nodes of `apply_weight_modification` of `hidden` by
+    /// `meta_state` ([batch, weight_mod_dim]; zeros when `None`)
+    pub fn onnx_apply(&self, graph: &mut OnnxGraph, hidden: &str, meta_state: Option<&str>) -> String {
+        let x = graph.linear(&self.weight_mod_network.input_proj, hidden);
+        let mut x = graph.node("Relu", &[&x], Vec::new());
+        if let Some(meta_state) = meta_state {
+            let meta_expanded = graph.reshape(meta_state, &[0, 1, -1]);
+            x = graph.add(&x, &meta_expanded);
+        }
+        let x = graph.linear(&self.weight_mod_network.hidden, &x);
+        let x = graph.node("Relu", &[&x], Vec::new());
+        let weight_mod = graph.linear(&self.weight_mod_network.output_proj, &x);
+
+        let weight_mod = graph.scale(&weight_mod, 0.1);
+        let modified = graph.add(hidden, &weight_mod);
+        graph.layer_norm(&self.norm, &modified)
+    }
+
     #[allow(dead_code)]
     pub fn compress_gradients(
         &self,
@@ -202,9 +262,8 @@ impl<B: Backend> SelfModifyModule<B> {
             .reshape(shape)
     }
 
-    #[allow(dead_code)]
     pub fn should_update(&self, state: &SelfModifyState<B>) -> bool {
-        self.config.enabled && (state.update_count % self.config.update_frequency == 0)
+        self.config.enabled && state.update_count.is_multiple_of(self.config.update_frequency)
     }
 
     #[allow(dead_code)]
@@ -212,3 +271,34 @@ impl<B: Backend> SelfModifyModule<B> {
         &self.config
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use burn_ndarray::NdArray;
+
+    type B = NdArray<f32>;
+
+    #[test]
+    fn test_meta_state_updates_every_update_frequency_steps() {
+        let device = Default::default();
+        let config = SelfModifyConfig { meta_lr: 0.5, update_frequency: 2, weight_mod_dim: 4, ..Default::default() };
+        let sm = SelfModifyModule::<B>::new(config, 8, &device);
+        let hidden = Tensor::<B, 3>::random([2, 3, 8], burn::tensor::Distribution::Default, &device);
+        let mut state = sm.init_state(2, 8, &device);
+
+        let mut meta_states = Vec::new();
+        for _ in 0..3 {
+            sm.update_state(&hidden, &mut state);
+            meta_states.push(state.meta_state.clone());
+        }
+        assert_eq!(state.update_count, 3);
+
+        // Step 0 updates from zero at rate meta_lr, step 1 holds, step 2 updates again
+        let rule = meta_states[0].clone() * 2.0;
+        meta_states[1].clone().into_data().assert_approx_eq::<f32>(&meta_states[0].clone().into_data(), Default::default());
+        let expected = meta_states[1].clone() * 0.5 + rule * 0.5;
+        meta_states[2].clone().into_data().assert_approx_eq::<f32>(&expected.into_data(), Default::default());
+        assert!(meta_states[0].clone().abs().sum().into_scalar() > 0.0);
+    }
+}
@@ -0,0 +1,95 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::Path;
+
+/// Windowed average loss of one document
+#[derive(Debug, Clone, Serialize)]
+pub struct DocumentLoss {
+    /// Stable document ID (see `data::document_id`)
+    pub id: String,
+    pub document: String,
+    pub average_loss: f32,
+    pub sequences: usize,
+}
+
+/// Attributes per-sequence losses to their source documents, keeping the
+/// last `window` losses of each document
+#[derive(Debug, Clone)]
+pub struct DocumentLossTracker {
+    window: usize,
+    /// Name and recent losses, keyed by document ID
+    losses: HashMap<String, (String, VecDeque<f32>)>,
+}
+
+impl DocumentLossTracker {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window: window.max(1),
+            losses: HashMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, id: &str, document: &str, loss: f32) {
+        if !loss.is_finite() {
+            return;
+        }
+
+        let (_, losses) = self.losses
+            .entry(id.to_string())
+            .or_insert_with(|| (document.to_string(), VecDeque::new()));
+        if losses.len() == self.window {
+            losses.pop_front();
+        }
+        losses.push_back(loss);
+    }
+
+    /// Documents ordered from highest to lowest windowed average loss
+    pub fn ranking(&self) -> Vec<DocumentLoss> {
+        let mut ranking: Vec<DocumentLoss> = self
+            .losses
+            .iter()
+            .map(|(id, (document, losses))| DocumentLoss {
+                id: id.clone(),
+                document: document.clone(),
+                average_loss: losses.iter().sum::<f32>() / losses.len() as f32,
+                sequences: losses.len(),
+            })
+            .collect();
+        ranking.sort_by(|a, b| b.average_loss.total_cmp(&a.average_loss).then(a.document.cmp(&b.document)));
+        ranking
+    }
+
+    /// Write the hardest-documents ranking as JSON
+    pub fn write_ranking(&self, path: &Path, step: usize) -> Result<()> {
+        let report = serde_json::json!({
+            "step": step,
+            "window": self.window,
+            "documents": self.ranking(),
+        });
+        fs::write(path, serde_json::to_string_pretty(&report)?)
+            .with_context(|| format!("Failed to write document loss ranking: {:?}", path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ranking_uses_window() {
+        let mut tracker = DocumentLossTracker::new(2);
+        tracker.record("1", "clean.txt", 2.0);
+        tracker.record("2", "ocr.txt", 9.0);
+        tracker.record("2", "ocr.txt", 1.0);
+        // Same document under a new name (renamed file)
+        tracker.record("2", "scan.txt", 1.0);
+
+        let ranking = tracker.ranking();
+        assert_eq!(ranking[0].document, "clean.txt");
+        assert_eq!((ranking[1].id.as_str(), ranking[1].document.as_str()), ("2", "ocr.txt"));
+        assert_eq!(ranking[1].average_loss, 1.0);
+        assert_eq!(ranking[1].sequences, 2);
+    }
+}
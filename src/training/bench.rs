@@ -0,0 +1,154 @@
+use burn::module::{AutodiffModule, Module};
+use burn::nn::loss::CrossEntropyLossConfig;
+use burn::tensor::backend::{AutodiffBackend, Backend};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+use crate::config::HopeConfig;
+use crate::model::profile::{StageSummary, StageTimes};
+use crate::model::{HopeInput, HopeModel};
+use super::generate_random_batch;
+use super::memory::peak_memory_bytes;
+
+/// What `run_bench` measures
+#[derive(Debug, Clone)]
+pub struct BenchConfig {
+    pub batch_size: usize,
+    /// Timed passes
+    pub steps: usize,
+    /// Untimed passes before the timed ones (allocation, kernel compilation)
+    pub warmup: usize,
+    /// Time forward+backward passes instead of forward passes alone
+    pub backward: bool,
+}
+
+/// Throughput of a model configuration
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchReport {
+    pub batch_size: usize,
+    pub seq_len: usize,
+    pub steps: usize,
+    pub backward: bool,
+    pub num_params: usize,
+    /// Analytical forward FLOPs per token
+    pub flops_per_token: f64,
+    pub tokens_per_sec: f64,
+    pub steps_per_sec: f64,
+    /// Mean milliseconds per forward pass
+    pub forward_ms: f64,
+    /// Mean milliseconds per backward pass (with `backward`)
+    pub backward_ms: Option<f64>,
+    /// Peak resident memory of the process (host memory only; Linux)
+    pub peak_memory_bytes: Option<u64>,
+    /// Forward time per module, in forward order
+    pub stages: Vec<StageSummary>,
+}
+
+/// Run `bench.warmup + bench.steps` passes of a fresh model of `config` on
+/// random tokens. Forward-only passes run on the inner backend, so no
+/// autodiff graph is recorded.
+pub fn run_bench<B: AutodiffBackend>(config: &HopeConfig, bench: &BenchConfig, device: &B::Device) -> BenchReport {
+    let model = HopeModel::<B>::new(config.clone(), device);
+    let num_params = model.num_params();
+    let flops_per_token = model.flops_per_token().total;
+    let seq_len = config.seq_len;
+
+    let (times, forward, backward) = if bench.backward {
+        let loss_fn = CrossEntropyLossConfig::new().init(device);
+        time_passes::<B>(&model, bench, |model, times| {
+            let batch = generate_random_batch::<B>(bench.batch_size, seq_len, config.vocab_size, device);
+            let carry = model.carry_with_len(bench.batch_size, seq_len, device);
+            let (_, output) = model.forward_timed(HopeInput { tokens: batch.tokens }, carry, times);
+            let logits = output.logits.reshape([bench.batch_size * seq_len, config.vocab_size]);
+            let loss = loss_fn.forward(logits, batch.targets.reshape([bench.batch_size * seq_len]));
+
+            let start = Instant::now();
+            let _grads = loss.backward();
+            B::sync(device);
+            start.elapsed()
+        })
+    } else {
+        time_passes::<B::InnerBackend>(&model.valid(), bench, |model, times| {
+            let batch = generate_random_batch::<B::InnerBackend>(bench.batch_size, seq_len, config.vocab_size, device);
+            let carry = model.carry_with_len(bench.batch_size, seq_len, device);
+            model.forward_timed(HopeInput { tokens: batch.tokens }, carry, times);
+            Duration::ZERO
+        })
+    };
+
+    let steps = bench.steps.max(1) as f64;
+    let total = (forward + backward).as_secs_f64().max(f64::EPSILON);
+
+    BenchReport {
+        batch_size: bench.batch_size,
+        seq_len,
+        steps: bench.steps,
+        backward: bench.backward,
+        num_params,
+        flops_per_token,
+        tokens_per_sec: (bench.batch_size * seq_len) as f64 * steps / total,
+        steps_per_sec: steps / total,
+        forward_ms: forward.as_secs_f64() * 1e3 / steps,
+        backward_ms: bench.backward.then(|| backward.as_secs_f64() * 1e3 / steps),
+        peak_memory_bytes: peak_memory_bytes(),
+        stages: times.summary(),
+    }
+}
+
+/// Run `pass` (which returns its backward time) for the warmup and timed
+/// steps; returns the stage times, total forward and total backward time
+/// of the timed steps
+fn time_passes<B: Backend>(
+    model: &HopeModel<B>,
+    bench: &BenchConfig,
+    mut pass: impl FnMut(&HopeModel<B>, &mut StageTimes) -> Duration,
+) -> (StageTimes, Duration, Duration) {
+    let mut untimed = StageTimes::disabled();
+    for _ in 0..bench.warmup {
+        pass(model, &mut untimed);
+    }
+
+    let mut times = StageTimes::new();
+    let mut backward = Duration::ZERO;
+    for _ in 0..bench.steps {
+        backward += pass(model, &mut times);
+    }
+    let forward = times.total();
+    (times, forward, backward)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use burn::backend::Autodiff;
+    use burn_ndarray::NdArray;
+
+    #[test]
+    fn test_bench_reports_every_stage() {
+        let device = Default::default();
+        let mut config = HopeConfig { num_levels: 2, level_timescales: vec![1, 2], ..HopeConfig::tiny() };
+        config.continuum_mem.enabled = true;
+        config.self_modify.enabled = true;
+        let bench = BenchConfig { batch_size: 2, steps: 2, warmup: 1, backward: true };
+
+        let report = run_bench::<Autodiff<NdArray<f32>>>(&config, &bench, &device);
+        let stages: Vec<&str> = report.stages.iter().map(|stage| stage.stage.as_str()).collect();
+        assert_eq!(
+            stages,
+            [
+                "embedding",
+                "memory_query",
+                "memory_keys",
+                "memory_attention",
+                "level_0",
+                "level_1",
+                "self_modify",
+                "memory_update",
+                "head",
+            ]
+        );
+        assert!((report.stages.iter().map(|stage| stage.share).sum::<f64>() - 1.0).abs() < 1e-6);
+        assert!(report.tokens_per_sec > 0.0 && report.backward_ms.is_some());
+        assert!(report.flops_per_token > 0.0);
+    }
+}
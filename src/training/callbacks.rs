@@ -0,0 +1,102 @@
+use std::path::Path;
+
+use super::eval::EvalMetrics;
+
+/// Whether training goes on after a callback
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CallbackAction {
+    #[default]
+    Continue,
+    /// Stop after the current step (with a final checkpoint, as at the step limit)
+    Stop,
+}
+
+/// A finished training step
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StepEnd {
+    /// 1-based step number, counting from the start of the run (not the resume)
+    pub step: usize,
+    pub loss: f32,
+    pub learning_rate: f32,
+    /// Only computed when metrics are logged (`log_dir`/`metrics_format`)
+    pub grad_norm: Option<f32>,
+}
+
+/// Hooks into the training loop, for custom logging, metric streaming or
+/// early exits without forking it. `HopeTrainer::train_step` calls the step
+/// hooks; the checkpoint, eval and exception hooks come from whoever saves,
+/// evaluates and handles errors (the `train` command's loop).
+pub trait TrainerCallback {
+    /// Before the forward pass of `step` (1-based)
+    fn on_step_start(&mut self, _step: usize) {}
+
+    fn on_step_end(&mut self, _step: &StepEnd) -> CallbackAction {
+        CallbackAction::Continue
+    }
+
+    /// A checkpoint of `step` was written to `path`
+    fn on_checkpoint(&mut self, _step: usize, _path: &Path) {}
+
+    /// Validation after `step`
+    fn on_eval(&mut self, _step: usize, _metrics: &EvalMetrics) -> CallbackAction {
+        CallbackAction::Continue
+    }
+
+    /// Training is about to fail at `step` with `error`
+    fn on_exception(&mut self, _step: usize, _error: &anyhow::Error) {}
+}
+
+/// Registered callbacks, invoked in registration order. Training stops if
+/// any of them asks to.
+#[derive(Default)]
+pub struct Callbacks {
+    callbacks: Vec<Box<dyn TrainerCallback>>,
+    stop_requested: bool,
+}
+
+impl Callbacks {
+    pub fn push(&mut self, callback: Box<dyn TrainerCallback>) {
+        self.callbacks.push(callback);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.callbacks.is_empty()
+    }
+
+    /// Whether a callback returned `CallbackAction::Stop`
+    pub fn stop_requested(&self) -> bool {
+        self.stop_requested
+    }
+
+    pub fn on_step_start(&mut self, step: usize) {
+        for callback in &mut self.callbacks {
+            callback.on_step_start(step);
+        }
+    }
+
+    pub fn on_step_end(&mut self, step: &StepEnd) {
+        for callback in &mut self.callbacks {
+            self.stop_requested |= callback.on_step_end(step) == CallbackAction::Stop;
+        }
+    }
+
+    pub fn on_checkpoint(&mut self, step: usize, path: &Path) {
+        for callback in &mut self.callbacks {
+            callback.on_checkpoint(step, path);
+        }
+    }
+
+    pub fn on_eval(&mut self, step: usize, metrics: &EvalMetrics) {
+        for callback in &mut self.callbacks {
+            self.stop_requested |= callback.on_eval(step, metrics) == CallbackAction::Stop;
+        }
+    }
+
+    /// Report `error` at `step` and hand it back, for `map_err`
+    pub fn on_exception(&mut self, step: usize, error: anyhow::Error) -> anyhow::Error {
+        for callback in &mut self.callbacks {
+            callback.on_exception(step, &error);
+        }
+        error
+    }
+}
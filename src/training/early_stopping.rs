@@ -0,0 +1,67 @@
+use crate::config::EarlyStoppingConfig;
+
+/// Stops training once validation loss has not improved by at least
+/// `min_delta` for `patience` consecutive evaluations
+#[derive(Debug, Clone)]
+pub struct EarlyStopping {
+    patience: usize,
+    min_delta: f32,
+    best_loss: Option<f32>,
+    evaluations_without_improvement: usize,
+}
+
+impl EarlyStopping {
+    pub fn new(config: &EarlyStoppingConfig) -> Self {
+        config.validate();
+
+        Self {
+            patience: config.patience,
+            min_delta: config.min_delta,
+            best_loss: None,
+            evaluations_without_improvement: 0,
+        }
+    }
+
+    pub fn best_loss(&self) -> Option<f32> {
+        self.best_loss
+    }
+
+    /// Record a validation loss. Returns `true` when training should stop.
+    pub fn update(&mut self, val_loss: f32) -> bool {
+        let improved = match self.best_loss {
+            Some(best) => val_loss < best - self.min_delta,
+            None => val_loss.is_finite(),
+        };
+
+        if improved {
+            self.best_loss = Some(val_loss);
+            self.evaluations_without_improvement = 0;
+        } else {
+            self.evaluations_without_improvement += 1;
+        }
+
+        self.evaluations_without_improvement >= self.patience
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stops_after_patience() {
+        let config = EarlyStoppingConfig {
+            enabled: true,
+            patience: 2,
+            min_delta: 0.1,
+        };
+        let mut early_stopping = EarlyStopping::new(&config);
+
+        assert!(!early_stopping.update(3.0));
+        assert!(!early_stopping.update(2.5));
+        // Improvements smaller than min_delta don't count
+        assert!(!early_stopping.update(2.45));
+        assert!(early_stopping.update(2.6));
+        assert_eq!(early_stopping.best_loss(), Some(2.5));
+    }
+}
@@ -0,0 +1,303 @@
+use anyhow::Result;
+use burn::nn::loss::CrossEntropyLoss;
+use burn::tensor::{ElementConversion, activation::softmax, backend::Backend};
+use serde::Serialize;
+
+use crate::data::{DataLoader, Tokenizer};
+use crate::model::{HopeInput, HopeModel};
+use super::trainer::real_positions;
+
+/// Loss statistics of an evaluation pass
+#[derive(Debug, Clone, Serialize)]
+pub struct EvalMetrics {
+    pub loss: f32,
+    pub perplexity: f32,
+    /// Fraction of positions whose most likely token is the target
+    pub accuracy: f32,
+    /// Fraction of positions with the target among the `TOP_K_ACCURACY`
+    /// most likely tokens
+    pub top5_accuracy: f32,
+    pub bits_per_token: f32,
+    /// Bits per character of the targets' text, comparable across
+    /// tokenizers (only with the tokenizer's `TokenChars`)
+    pub bits_per_char: Option<f32>,
+    pub batches: usize,
+    pub tokens: usize,
+    pub calibration: CalibrationMetrics,
+}
+
+/// Number of top predictions counted by `EvalMetrics::top5_accuracy`
+pub const TOP_K_ACCURACY: usize = 5;
+
+/// Number of characters each token ID decodes to, for bits per character
+#[derive(Debug, Clone)]
+pub struct TokenChars(Vec<usize>);
+
+impl TokenChars {
+    /// Padding counts as no characters
+    pub fn new(tokenizer: &dyn Tokenizer) -> Self {
+        let chars = (0..tokenizer.vocab_size() as i64)
+            .map(|id| if id == tokenizer.pad_id() { 0 } else { tokenizer.decode(&[id]).chars().count() })
+            .collect();
+        Self(chars)
+    }
+
+    fn get(&self, id: i64) -> usize {
+        usize::try_from(id).ok().and_then(|id| self.0.get(id)).copied().unwrap_or(1)
+    }
+}
+
+/// Number of equal-width confidence buckets used for calibration
+pub const CALIBRATION_BUCKETS: usize = 10;
+
+/// Top-1 predictions whose confidence falls in `[lower, upper)`
+#[derive(Debug, Clone, Serialize)]
+pub struct CalibrationBucket {
+    pub lower: f32,
+    pub upper: f32,
+    pub count: usize,
+    /// Mean top-1 probability (expected accuracy)
+    pub confidence: f32,
+    /// Fraction of top-1 predictions that were correct (observed accuracy)
+    pub accuracy: f32,
+}
+
+/// Calibration of top-1 predictions: an over-confident model has
+/// `confidence > accuracy` in most buckets and benefits from a higher
+/// sampling temperature
+#[derive(Debug, Clone, Serialize)]
+pub struct CalibrationMetrics {
+    /// Expected calibration error: count-weighted mean |accuracy - confidence|
+    pub ece: f32,
+    pub buckets: Vec<CalibrationBucket>,
+}
+
+/// Accumulates top-1 confidence/correctness over batches
+#[derive(Debug, Clone)]
+pub struct CalibrationAccumulator {
+    counts: Vec<usize>,
+    confidence_sums: Vec<f64>,
+    correct: Vec<usize>,
+}
+
+impl CalibrationAccumulator {
+    pub fn new(num_buckets: usize) -> Self {
+        let num_buckets = num_buckets.max(1);
+        Self {
+            counts: vec![0; num_buckets],
+            confidence_sums: vec![0.0; num_buckets],
+            correct: vec![0; num_buckets],
+        }
+    }
+
+    pub fn add(&mut self, confidence: f32, correct: bool) {
+        if !confidence.is_finite() {
+            return;
+        }
+
+        let num_buckets = self.counts.len();
+        let bucket = ((confidence.clamp(0.0, 1.0) * num_buckets as f32) as usize).min(num_buckets - 1);
+        self.counts[bucket] += 1;
+        self.confidence_sums[bucket] += f64::from(confidence);
+        if correct {
+            self.correct[bucket] += 1;
+        }
+    }
+
+    pub fn finish(&self) -> CalibrationMetrics {
+        let num_buckets = self.counts.len();
+        let total: usize = self.counts.iter().sum();
+
+        let mut ece = 0.0f64;
+        let buckets = (0..num_buckets)
+            .map(|i| {
+                let count = self.counts[i];
+                let (confidence, accuracy) = if count > 0 {
+                    (
+                        self.confidence_sums[i] / count as f64,
+                        self.correct[i] as f64 / count as f64,
+                    )
+                } else {
+                    (0.0, 0.0)
+                };
+                if total > 0 {
+                    ece += (accuracy - confidence).abs() * count as f64 / total as f64;
+                }
+
+                CalibrationBucket {
+                    lower: i as f32 / num_buckets as f32,
+                    upper: (i + 1) as f32 / num_buckets as f32,
+                    count,
+                    confidence: confidence as f32,
+                    accuracy: accuracy as f32,
+                }
+            })
+            .collect();
+
+        CalibrationMetrics {
+            ece: ece as f32,
+            buckets,
+        }
+    }
+}
+
+/// Run the model over every batch of `loader` without tracking gradients.
+/// Pass a non-autodiff model in eval mode (e.g. `model.valid().eval()`) so
+/// no graph is built, without dropout and with the self-modification meta
+/// state held.
+/// Bits per character are reported with `token_chars`.
+pub fn evaluate<B: Backend>(
+    model: &HopeModel<B>,
+    loader: &mut dyn DataLoader<B>,
+    token_chars: Option<&TokenChars>,
+) -> Result<EvalMetrics> {
+    loader.reset();
+
+    let mut total_loss = 0.0f64;
+    let mut tokens = 0;
+    let mut scored = 0;
+    let mut correct = 0;
+    let mut top_k_correct = 0;
+    let mut chars = 0;
+    let mut batches = 0;
+    let mut calibration = CalibrationAccumulator::new(CALIBRATION_BUCKETS);
+
+    while let Some(batch) = loader.next_batch()? {
+        let device = batch.tokens.device();
+        let [batch_size, seq_len] = batch.tokens.dims();
+        let batch_tokens = batch.num_tokens();
+
+        let carry = model.carry_with_len(batch_size, seq_len, &device);
+        let (_, output) = model.forward(HopeInput { tokens: batch.tokens }, carry);
+
+        let vocab_size = output.logits.dims()[2];
+        let logits = output.logits.reshape([batch_size * seq_len, vocab_size]);
+        let targets = batch.targets.reshape([batch_size * seq_len]);
+        // Padding of token-budget batches is not scored
+        let (logits, targets) = match real_positions::<B>(&batch.lengths, seq_len, &device) {
+            Some(positions) => (logits.select(0, positions.clone()), targets.select(0, positions)),
+            None => (logits, targets),
+        };
+
+        let (confidences, predictions) = softmax(logits.clone(), 1).max_dim_with_indices(1);
+        let confidences = confidences.into_data().convert::<f32>().to_vec::<f32>().unwrap_or_default();
+        let predictions = predictions.into_data().convert::<i64>().to_vec::<i64>().unwrap_or_default();
+        let target_ids = targets.clone().into_data().convert::<i64>().to_vec::<i64>().unwrap_or_default();
+        for ((confidence, prediction), target) in confidences.iter().zip(&predictions).zip(&target_ids) {
+            calibration.add(*confidence, prediction == target);
+            if prediction == target {
+                correct += 1;
+            }
+        }
+        scored += target_ids.len();
+        if let Some(token_chars) = token_chars {
+            chars += target_ids.iter().map(|&id| token_chars.get(id)).sum::<usize>();
+        }
+
+        let k = TOP_K_ACCURACY.min(vocab_size);
+        let (_, top_k) = logits.clone().topk_with_indices(k, 1);
+        let top_k = top_k.into_data().convert::<i64>().to_vec::<i64>().unwrap_or_default();
+        top_k_correct += top_k
+            .chunks(k)
+            .zip(&target_ids)
+            .filter(|(top, target)| top.contains(target))
+            .count();
+
+        let loss = CrossEntropyLoss::new(None, &device).forward(logits, targets);
+
+        let loss_value: f32 = loss.into_scalar().elem();
+        total_loss += f64::from(loss_value) * batch_tokens as f64;
+        tokens += batch_tokens;
+        batches += 1;
+    }
+
+    if tokens == 0 {
+        anyhow::bail!("Evaluation data produced no batches");
+    }
+
+    let loss = (total_loss / tokens as f64) as f32;
+    let bits = total_loss / std::f64::consts::LN_2;
+
+    Ok(EvalMetrics {
+        loss,
+        perplexity: loss.exp(),
+        accuracy: correct as f32 / scored.max(1) as f32,
+        top5_accuracy: top_k_correct as f32 / scored.max(1) as f32,
+        bits_per_token: loss / std::f32::consts::LN_2,
+        bits_per_char: (token_chars.is_some() && chars > 0).then(|| (bits / chars as f64) as f32),
+        batches,
+        tokens,
+        calibration: calibration.finish(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HopeConfig;
+    use crate::data::{CharTokenizer, TextDataLoader};
+    use burn_ndarray::NdArray;
+
+    #[test]
+    fn test_evaluate_reports_perplexity() {
+        let device = Default::default();
+        let config = HopeConfig {
+            hidden_size: 32,
+            vocab_size: 16,
+            seq_len: 8,
+            num_heads: 4,
+            num_layers: 1,
+            num_levels: 1,
+            level_timescales: vec![1],
+            ..Default::default()
+        };
+        let model = HopeModel::<NdArray<f32>>::new(config, &device);
+        let tokens = (0..100).map(|i| i % 16).collect();
+        let mut loader = TextDataLoader::<NdArray<f32>>::from_tokens(tokens, 2, 8, device);
+
+        let metrics = evaluate(&model, &mut loader, None).unwrap();
+        assert!(metrics.batches > 0);
+        assert!((metrics.perplexity - metrics.loss.exp()).abs() < 1e-3);
+        assert!(metrics.accuracy <= metrics.top5_accuracy && metrics.top5_accuracy <= 1.0);
+        assert!((metrics.bits_per_token - metrics.loss / std::f32::consts::LN_2).abs() < 1e-4);
+        assert!(metrics.bits_per_char.is_none());
+        let counted: usize = metrics.calibration.buckets.iter().map(|b| b.count).sum();
+        assert_eq!(counted, metrics.tokens);
+    }
+
+    #[test]
+    fn test_bits_per_char() {
+        let device = Default::default();
+        let config = HopeConfig { vocab_size: 6, ..HopeConfig::tiny() };
+        let tokenizer = CharTokenizer::from_vocab("abcd".chars().collect());
+        let token_chars = TokenChars::new(&tokenizer);
+        assert_eq!(token_chars.get(tokenizer.pad_id()), 0);
+        assert_eq!(token_chars.get(2), 1);
+
+        // One character per token: bits per character equal bits per token
+        let model = HopeModel::<NdArray<f32>>::new(config, &device);
+        let tokens = (0..40).map(|i| 2 + i % 4).collect();
+        let mut loader = TextDataLoader::<NdArray<f32>>::from_tokens(tokens, 2, 4, device);
+        let metrics = evaluate(&model, &mut loader, Some(&token_chars)).unwrap();
+        let bits_per_char = metrics.bits_per_char.unwrap();
+        assert!((bits_per_char - metrics.bits_per_token).abs() < 1e-4, "{} vs {}", bits_per_char, metrics.bits_per_token);
+    }
+
+    #[test]
+    fn test_calibration_ece() {
+        let mut calibration = CalibrationAccumulator::new(10);
+        // 90% confident and always right: under-confident by 0.1
+        for _ in 0..4 {
+            calibration.add(0.9, true);
+        }
+        // 25% confident and right half of the time: under-confident by 0.25
+        for i in 0..4 {
+            calibration.add(0.25, i % 2 == 0);
+        }
+
+        let metrics = calibration.finish();
+        assert_eq!(metrics.buckets[9].count, 4);
+        assert_eq!(metrics.buckets[2].accuracy, 0.5);
+        assert!((metrics.ece - 0.175).abs() < 1e-6);
+    }
+}
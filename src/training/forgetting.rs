@@ -0,0 +1,186 @@
+use anyhow::Result;
+use burn::module::AutodiffModule;
+use burn::tensor::ElementConversion;
+use burn::tensor::backend::{AutodiffBackend, Backend};
+use serde::Serialize;
+use tracing::info;
+
+use crate::config::TrainConfig;
+use crate::data::DataLoader;
+use crate::model::HopeModel;
+use super::HopeTrainer;
+use super::eval::evaluate;
+
+/// Which optional components are kept while fine-tuning
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ForgettingVariant {
+    pub name: String,
+    pub continuum_mem: bool,
+    pub self_modify: bool,
+}
+
+impl ForgettingVariant {
+    fn new(name: &str, continuum_mem: bool, self_modify: bool) -> Self {
+        Self {
+            name: name.to_string(),
+            continuum_mem,
+            self_modify,
+        }
+    }
+
+    /// The full model plus every ablation of the components enabled in `config`
+    pub fn ablations(config: &TrainConfig) -> Vec<Self> {
+        let mem = config.model.continuum_mem.enabled;
+        let sm = config.model.self_modify.enabled;
+
+        let mut variants = vec![Self::new("full", mem, sm)];
+        if mem {
+            variants.push(Self::new("no_continuum_mem", false, sm));
+        }
+        if sm {
+            variants.push(Self::new("no_self_modify", mem, false));
+        }
+        if mem && sm {
+            variants.push(Self::new("no_memory_no_self_modify", false, false));
+        }
+        variants
+    }
+}
+
+/// Loss and perplexity on one corpus
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct CorpusScore {
+    pub loss: f32,
+    pub perplexity: f32,
+}
+
+/// Scores on the old and new corpus before and after fine-tuning on the new one
+#[derive(Debug, Clone, Serialize)]
+pub struct ForgettingResult {
+    pub variant: ForgettingVariant,
+    pub steps: usize,
+    pub old_before: CorpusScore,
+    pub old_after: CorpusScore,
+    pub new_before: CorpusScore,
+    pub new_after: CorpusScore,
+    /// Increase in old-corpus loss caused by fine-tuning (> 0 means forgetting)
+    pub forgetting: f32,
+    /// Decrease in new-corpus loss (how much was learned)
+    pub learning: f32,
+}
+
+/// Corpora of a forgetting measurement: the old and new evaluation sets and
+/// the new corpus to fine-tune on
+pub struct ForgettingLoaders<'a, B: AutodiffBackend> {
+    pub old_eval: &'a mut dyn DataLoader<B::InnerBackend>,
+    pub new_eval: &'a mut dyn DataLoader<B::InnerBackend>,
+    pub new_train: &'a mut dyn DataLoader<B>,
+}
+
+/// Fine-tune `model` (restricted to `variant`) on `new_train` for `steps`
+/// steps and measure how its old- and new-corpus scores change
+pub fn measure_forgetting<B: AutodiffBackend>(
+    model: HopeModel<B>,
+    config: &TrainConfig,
+    variant: &ForgettingVariant,
+    loaders: ForgettingLoaders<'_, B>,
+    steps: usize,
+    device: &B::Device,
+) -> Result<ForgettingResult> {
+    let ForgettingLoaders { old_eval, new_eval, new_train } = loaders;
+    let model = model.with_components(variant.continuum_mem, variant.self_modify);
+    let mut config = config.clone();
+    config.model = model.config().clone();
+    config.training.stateful.enabled = false;
+
+    let eval_model = model.valid().eval();
+    let old_before = score(&eval_model, old_eval)?;
+    let new_before = score(&eval_model, new_eval)?;
+
+    let mut trainer = HopeTrainer::new(model, config, device);
+    new_train.reset();
+    for step in 0..steps {
+        let batch = match new_train.next_batch()? {
+            Some(batch) => batch,
+            None => {
+                new_train.reset();
+                new_train
+                    .next_batch()?
+                    .ok_or_else(|| anyhow::anyhow!("Fine-tuning corpus produced no batches"))?
+            }
+        };
+        let output = trainer.train_step(batch);
+        if (step + 1) % 10 == 0 || step + 1 == steps {
+            info!("[{}] Fine-tune step {}/{} | Loss = {:.6}",
+                variant.name, step + 1, steps, output.loss.into_scalar().elem::<f32>());
+        }
+    }
+
+    let model = trainer.model().valid().eval();
+    let old_after = score(&model, old_eval)?;
+    let new_after = score(&model, new_eval)?;
+
+    Ok(ForgettingResult {
+        variant: variant.clone(),
+        steps,
+        old_before,
+        old_after,
+        new_before,
+        new_after,
+        forgetting: old_after.loss - old_before.loss,
+        learning: new_before.loss - new_after.loss,
+    })
+}
+
+fn score<B: Backend>(
+    model: &HopeModel<B>,
+    loader: &mut dyn DataLoader<B>,
+) -> Result<CorpusScore> {
+    let metrics = evaluate(model, loader, None)?;
+    Ok(CorpusScore {
+        loss: metrics.loss,
+        perplexity: metrics.perplexity,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HopeConfig;
+    use crate::data::RandomDataLoader;
+    use burn::backend::Autodiff;
+    use burn_ndarray::NdArray;
+
+    type B = Autodiff<NdArray<f32>>;
+
+    #[test]
+    fn test_ablations_cover_enabled_components() {
+        let mut config = TrainConfig::for_test(HopeConfig::tiny(), "{}");
+        assert_eq!(ForgettingVariant::ablations(&config).len(), 4);
+
+        config.model.self_modify.enabled = false;
+        let names: Vec<_> = ForgettingVariant::ablations(&config)
+            .into_iter()
+            .map(|v| v.name)
+            .collect();
+        assert_eq!(names, vec!["full", "no_continuum_mem"]);
+    }
+
+    #[test]
+    fn test_measure_forgetting_without_memory() {
+        let device = Default::default();
+        let config = TrainConfig::for_test(HopeConfig::tiny(), "{}");
+        let model = HopeModel::<B>::new(config.model.clone(), &device);
+        let variant = ForgettingVariant::new("no_continuum_mem", false, true);
+
+        let loader = || RandomDataLoader::<NdArray<f32>>::new(2, 4, 8, 2, device);
+        let (mut old_eval, mut new_eval) = (loader(), loader());
+        let mut new_train = RandomDataLoader::<B>::new(2, 4, 8, 1, device);
+
+        let loaders = ForgettingLoaders { old_eval: &mut old_eval, new_eval: &mut new_eval, new_train: &mut new_train };
+        let result = measure_forgetting(model, &config, &variant, loaders, 3, &device).unwrap();
+        assert_eq!(result.steps, 3);
+        assert!(result.old_after.perplexity.is_finite());
+        assert!((result.forgetting - (result.old_after.loss - result.old_before.loss)).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,109 @@
+use burn::module::AutodiffModule;
+use burn::optim::adaptor::OptimizerAdaptor;
+use burn::optim::{AdamW, AdamWConfig, GradientsParams, Optimizer};
+use burn::tensor::{ElementConversion, backend::AutodiffBackend};
+
+use crate::config::TrainingConfig;
+use crate::model::lora::{LoraAdapters, LoraConfig};
+use crate::model::{HopeInput, HopeModel};
+use super::BatchData;
+use super::trainer::TokenLoss;
+
+/// Trains low-rank adapters on top of a frozen model. Only the adapters are
+/// optimized (AdamW with `training.weight_decay`), so a step costs the
+/// forward pass and the input gradients but no weight gradients of the
+/// base model.
+pub struct LoraTrainer<B: AutodiffBackend> {
+    model: HopeModel<B>,
+    adapters: LoraAdapters<B>,
+    optimizer: OptimizerAdaptor<AdamW, LoraAdapters<B>, B>,
+    loss_fn: TokenLoss<B>,
+    learning_rate: f32,
+}
+
+impl<B: AutodiffBackend> LoraTrainer<B> {
+    pub fn new(model: HopeModel<B>, config: &LoraConfig, training: &TrainingConfig, device: &B::Device) -> Self {
+        let adapters = LoraAdapters::new(&model, config, device);
+        let optimizer = AdamWConfig::new()
+            .with_weight_decay(training.weight_decay.unwrap_or(0.0))
+            .init();
+
+        Self {
+            model,
+            adapters,
+            optimizer,
+            loss_fn: TokenLoss::new(training, device),
+            learning_rate: training.learning_rate,
+        }
+    }
+
+    pub fn adapters(&self) -> &LoraAdapters<B> {
+        &self.adapters
+    }
+
+    pub fn set_learning_rate(&mut self, learning_rate: f32) {
+        self.learning_rate = learning_rate;
+    }
+
+    /// One optimizer step of the adapters; returns the loss
+    pub fn train_step(&mut self, batch: BatchData<B>) -> f32 {
+        let [rows, seq_len] = batch.tokens.dims();
+        let device = batch.tokens.device();
+
+        let model = self.adapters.apply(&self.model);
+        let carry = model.carry_with_len(rows, seq_len, &device);
+        let (_, output) = model.forward(HopeInput { tokens: batch.tokens }, carry);
+        let loss = self.loss_fn.forward(output.logits, batch.targets, &batch.lengths);
+        let value = loss.clone().into_scalar().elem::<f32>();
+
+        let grads = GradientsParams::from_grads(loss.backward(), &self.adapters);
+        self.adapters = self.optimizer.step(f64::from(self.learning_rate), self.adapters.clone(), grads);
+        value
+    }
+
+    /// The base model with the adapters merged into its weights
+    pub fn merged(&self) -> HopeModel<B::InnerBackend> {
+        self.adapters.valid().apply(&self.model.valid())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HopeConfig;
+    use crate::training::generate_random_batch;
+    use burn::module::Module;
+    use burn::tensor::{Int, Tensor};
+    use burn::backend::Autodiff;
+    use burn_ndarray::NdArray;
+
+    type B = Autodiff<NdArray<f32>>;
+
+    #[test]
+    fn test_trains_adapters_only() {
+        let device = Default::default();
+        let config = HopeConfig { dropout: 0.0, ..HopeConfig::tiny() };
+        let training: TrainingConfig = serde_json::from_str(r#"{"batch_size": 2, "learning_rate": 0.01}"#).unwrap();
+        let model = HopeModel::<B>::new(config, &device);
+        let base = model.valid();
+        let mut trainer = LoraTrainer::new(model, &LoraConfig { rank: 2, ..Default::default() }, &training, &device);
+
+        let batch = generate_random_batch::<B>(2, 4, 8, &device);
+        let first = trainer.train_step(batch.clone());
+        let mut last = first;
+        for _ in 0..20 {
+            last = trainer.train_step(batch.clone());
+        }
+        assert!(last < first, "{} -> {}", first, last);
+
+        // The base weights are untouched; the merged model carries the adapters
+        let tokens = Tensor::<NdArray<f32>, 2, Int>::from_ints([[1, 2, 3, 4]], &device);
+        let logits = |model: &HopeModel<NdArray<f32>>| {
+            model.forward(HopeInput { tokens: tokens.clone() }, model.initial_carry(1, &device)).1.logits
+        };
+        logits(&base).into_data().assert_approx_eq::<f32>(&logits(&trainer.model.valid()).into_data(), Default::default());
+        let moved = (logits(&trainer.merged()) - logits(&base)).abs().max().into_scalar();
+        assert!(moved > 1e-4, "{}", moved);
+        assert!(trainer.adapters().num_params() < base.num_params());
+    }
+}
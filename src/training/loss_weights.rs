@@ -0,0 +1,165 @@
+use burn::tensor::activation::log_softmax;
+use burn::tensor::{Int, Tensor, backend::Backend};
+use std::collections::HashMap;
+use tracing::warn;
+
+use crate::config::LossWeightsConfig;
+use crate::data::Tokenizer;
+
+/// `LossWeightsConfig` resolved to token IDs
+#[derive(Debug, Clone)]
+pub struct TokenLossWeights {
+    tokens: HashMap<i64, f32>,
+    /// Multi-token strings, shortest first so longer matches take precedence
+    sequences: Vec<(Vec<i64>, f32)>,
+    pad_id: i64,
+    pad: f32,
+}
+
+impl TokenLossWeights {
+    pub fn new(config: &LossWeightsConfig, tokenizer: &dyn Tokenizer) -> Self {
+        config.validate();
+
+        let mut tokens = HashMap::new();
+        let mut sequences = Vec::new();
+        for (text, &weight) in &config.tokens {
+            let ids = tokenizer.encode(text);
+            if ids.contains(&tokenizer.unk_id()) {
+                warn!("loss_weights: {:?} has characters outside the vocabulary", text);
+            }
+            match ids.as_slice() {
+                [] => {}
+                [id] => {
+                    tokens.insert(*id, weight);
+                }
+                _ => sequences.push((ids, weight)),
+            }
+        }
+        sequences.sort_by_key(|(ids, _)| ids.len());
+
+        Self { tokens, sequences, pad_id: tokenizer.pad_id(), pad: config.pad }
+    }
+
+    /// Weight of every target of one row
+    pub fn row_weights(&self, targets: &[i64]) -> Vec<f32> {
+        let mut weights: Vec<f32> = targets
+            .iter()
+            .map(|id| match self.tokens.get(id) {
+                Some(&weight) => weight,
+                None if *id == self.pad_id => self.pad,
+                None => 1.0,
+            })
+            .collect();
+        for (ids, weight) in &self.sequences {
+            for start in 0..targets.len().saturating_sub(ids.len() - 1) {
+                if targets[start..].starts_with(ids) {
+                    weights[start..start + ids.len()].fill(*weight);
+                }
+            }
+        }
+        weights
+    }
+
+    /// Weights of flat `[batch * seq_len]` targets; positions past
+    /// `lengths[i]` of a padded row get 0
+    pub fn weights(&self, targets: &[i64], seq_len: usize, lengths: &[usize]) -> Vec<f32> {
+        targets
+            .chunks(seq_len)
+            .enumerate()
+            .flat_map(|(row, targets)| {
+                let mut weights = self.row_weights(targets);
+                if let Some(&len) = lengths.get(row) {
+                    weights[len.min(seq_len)..].fill(0.0);
+                }
+                weights
+            })
+            .collect()
+    }
+}
+
+/// Weight 1 for the real positions of flat `[batch * seq_len]` targets: all
+/// of them, or the first `lengths[i]` of row i when the batch is padded
+pub fn length_mask(batch_size: usize, seq_len: usize, lengths: &[usize]) -> Vec<f32> {
+    (0..batch_size)
+        .flat_map(|row| {
+            let len = lengths.get(row).copied().unwrap_or(seq_len);
+            (0..seq_len).map(move |pos| if pos < len { 1.0 } else { 0.0 })
+        })
+        .collect()
+}
+
+/// Cross-entropy with label `smoothing` of every position, flattened to
+/// `[batch * seq_len]`
+pub fn token_losses<B: Backend>(logits: Tensor<B, 3>, targets: Tensor<B, 2, Int>, smoothing: f32) -> Tensor<B, 1> {
+    let [batch_size, seq_len, vocab_size] = logits.dims();
+    let positions = batch_size * seq_len;
+
+    let log_probs = log_softmax(logits.reshape([positions, vocab_size]), 1);
+    let losses: Tensor<B, 1> = log_probs.clone().gather(1, targets.reshape([positions, 1])).neg().reshape([positions]);
+    if smoothing == 0.0 {
+        return losses;
+    }
+    // Smoothed targets put `smoothing / vocab_size` on every class
+    let uniform: Tensor<B, 1> = log_probs.mean_dim(1).neg().reshape([positions]);
+    losses * (1.0 - smoothing) + uniform * smoothing
+}
+
+/// Mean of `losses` weighted by `weights` (so down- or upweighting keeps
+/// the loss on the usual scale)
+pub fn weighted_mean<B: Backend>(losses: Tensor<B, 1>, weights: &[f32]) -> Tensor<B, 1> {
+    let total: f32 = weights.iter().sum();
+    let weights = Tensor::<B, 1>::from_floats(weights, &losses.device());
+    (losses * weights).sum() / total.max(f32::EPSILON)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::CharTokenizer;
+    use burn::nn::loss::CrossEntropyLossConfig;
+    use burn::tensor::Distribution;
+    use burn_ndarray::NdArray;
+    use std::collections::BTreeMap;
+
+    type B = NdArray<f32>;
+
+    #[test]
+    fn test_markers_and_padding_are_weighted() {
+        let tokenizer = CharTokenizer::from_text("<CHAPTER>ab");
+        let config = LossWeightsConfig {
+            tokens: BTreeMap::from([("<CHAPTER>".to_string(), 0.1), ("b".to_string(), 2.0)]),
+            pad: 0.0,
+        };
+        let weights = TokenLossWeights::new(&config, &tokenizer);
+
+        let targets = tokenizer.encode("a<CHAPTER>b\0");
+        let expected = [1.0, 0.1, 0.1, 0.1, 0.1, 0.1, 0.1, 0.1, 0.1, 0.1, 2.0, 0.0];
+        assert_eq!(weights.row_weights(&targets), expected);
+        // Padded rows drop everything past their length
+        let targets = tokenizer.encode("abbb");
+        assert_eq!(weights.weights(&targets, 2, &[2, 1]), [1.0, 2.0, 2.0, 0.0]);
+    }
+
+    #[test]
+    fn test_uniform_weights_match_cross_entropy() {
+        let device = Default::default();
+        let tokenizer = CharTokenizer::from_text("abc");
+        let weights = TokenLossWeights::new(&LossWeightsConfig::default(), &tokenizer);
+        let logits = Tensor::<B, 3>::random([2, 3, 5], Distribution::Normal(0.0, 1.0), &device);
+        let targets = Tensor::<B, 2, Int>::from_ints([[1, 2, 3], [4, 0, 2]], &device);
+
+        for smoothing in [0.0, 0.1] {
+            let expected: f32 = CrossEntropyLossConfig::new()
+                .with_smoothing((smoothing > 0.0).then_some(smoothing))
+                .init(&device)
+                .forward(logits.clone().reshape([6, 5]), targets.clone().reshape([6]))
+                .into_scalar();
+            let target_ids = targets.clone().into_data().to_vec::<i64>().unwrap();
+            let weighted: f32 = weighted_mean(
+                token_losses(logits.clone(), targets.clone(), smoothing),
+                &weights.weights(&target_ids, 3, &[]),
+            ).into_scalar();
+            assert!((weighted - expected).abs() < 1e-5, "{} vs {}", weighted, expected);
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+
+/// Learning rates of an LR range test: `steps` rates growing exponentially
+/// from `min_lr` to `max_lr`, one training step each
+#[derive(Debug, Clone, Copy)]
+pub struct LrSweep {
+    pub min_lr: f32,
+    pub max_lr: f32,
+    pub steps: usize,
+}
+
+impl LrSweep {
+    pub fn new(min_lr: f32, max_lr: f32, steps: usize) -> Self {
+        assert!(min_lr > 0.0 && max_lr > min_lr, "LR sweep needs 0 < min_lr < max_lr");
+        assert!(steps > 1, "LR sweep needs at least 2 steps");
+        Self { min_lr, max_lr, steps }
+    }
+
+    pub fn learning_rate(&self, step: usize) -> f32 {
+        let progress = step as f32 / (self.steps - 1) as f32;
+        self.min_lr * (self.max_lr / self.min_lr).powf(progress)
+    }
+}
+
+/// One step of the sweep
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LrPoint {
+    pub learning_rate: f32,
+    pub loss: f32,
+    /// Bias-corrected exponential moving average of the loss
+    pub smoothed_loss: f32,
+}
+
+/// Loss recorded against learning rate. The sweep stops once the smoothed
+/// loss diverges (non-finite, or 4x the best so far), as larger rates only
+/// get worse.
+pub struct LrRecorder {
+    points: Vec<LrPoint>,
+    average: f32,
+    best: f32,
+}
+
+const SMOOTHING: f32 = 0.98;
+const DIVERGENCE_FACTOR: f32 = 4.0;
+
+impl LrRecorder {
+    pub fn new() -> Self {
+        Self { points: Vec::new(), average: 0.0, best: f32::INFINITY }
+    }
+
+    /// Record the loss of a step; returns `false` once the loss has diverged
+    pub fn record(&mut self, learning_rate: f32, loss: f32) -> bool {
+        self.average = SMOOTHING * self.average + (1.0 - SMOOTHING) * loss;
+        let smoothed_loss = self.average / (1.0 - SMOOTHING.powi(self.points.len() as i32 + 1));
+        self.points.push(LrPoint { learning_rate, loss, smoothed_loss });
+
+        if !smoothed_loss.is_finite() || smoothed_loss > DIVERGENCE_FACTOR * self.best {
+            return false;
+        }
+        self.best = self.best.min(smoothed_loss);
+        true
+    }
+
+    pub fn into_points(self) -> Vec<LrPoint> {
+        self.points
+    }
+}
+
+impl Default for LrRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Suggested learning rate: where the smoothed loss falls fastest per unit
+/// of log learning rate, before the loss reaches its minimum
+pub fn suggest_learning_rate(points: &[LrPoint]) -> Option<f32> {
+    let finite: Vec<&LrPoint> = points.iter().take_while(|p| p.smoothed_loss.is_finite()).collect();
+    let lowest = finite
+        .iter()
+        .enumerate()
+        .min_by(|(_, a), (_, b)| a.smoothed_loss.total_cmp(&b.smoothed_loss))?
+        .0;
+    finite[..=lowest]
+        .windows(2)
+        .map(|pair| {
+            let slope = (pair[1].smoothed_loss - pair[0].smoothed_loss)
+                / (pair[1].learning_rate.ln() - pair[0].learning_rate.ln());
+            (slope, pair[1].learning_rate)
+        })
+        .filter(|(slope, _)| *slope < 0.0)
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, learning_rate)| learning_rate)
+}
+
+/// Write the sweep as `learning_rate,loss,smoothed_loss` rows
+pub fn write_lr_csv(points: &[LrPoint], path: &Path) -> Result<()> {
+    let mut csv = String::from("learning_rate,loss,smoothed_loss\n");
+    for point in points {
+        csv.push_str(&format!("{},{},{}\n", point.learning_rate, point.loss, point.smoothed_loss));
+    }
+    fs::write(path, csv).with_context(|| format!("Failed to write LR sweep: {:?}", path))
+}
+
+/// Text plot of the smoothed loss against log learning rate, `height` rows
+/// of `width` columns, with a `|` column at `suggestion`
+pub fn plot_lr_curve(points: &[LrPoint], suggestion: Option<f32>, width: usize, height: usize) -> String {
+    let finite: Vec<&LrPoint> = points.iter().filter(|p| p.smoothed_loss.is_finite()).collect();
+    let (Some(first), Some(last)) = (finite.first(), finite.last()) else {
+        return String::new();
+    };
+    let (min_x, max_x) = (first.learning_rate.ln(), last.learning_rate.ln());
+    let min_y = finite.iter().map(|p| p.smoothed_loss).fold(f32::INFINITY, f32::min);
+    let max_y = finite.iter().map(|p| p.smoothed_loss).fold(f32::NEG_INFINITY, f32::max);
+    let column = |lr: f32| {
+        let x = (lr.ln() - min_x) / (max_x - min_x).max(f32::EPSILON);
+        ((x * (width - 1) as f32).round() as usize).min(width - 1)
+    };
+
+    let mut grid = vec![vec![' '; width]; height];
+    for point in &finite {
+        let y = (point.smoothed_loss - min_y) / (max_y - min_y).max(f32::EPSILON);
+        let row = height - 1 - ((y * (height - 1) as f32).round() as usize).min(height - 1);
+        grid[row][column(point.learning_rate)] = '.';
+    }
+    if let Some(lr) = suggestion {
+        for row in grid.iter_mut() {
+            if row[column(lr)] == ' ' {
+                row[column(lr)] = '|';
+            }
+        }
+    }
+
+    let mut plot = String::new();
+    for (i, row) in grid.iter().enumerate() {
+        let label = match i {
+            0 => format!("{:>9.4}", max_y),
+            i if i == height - 1 => format!("{:>9.4}", min_y),
+            _ => " ".repeat(9),
+        };
+        plot.push_str(&format!("{} |{}\n", label, row.iter().collect::<String>()));
+    }
+    plot.push_str(&format!("{} +{}\n", " ".repeat(9), "-".repeat(width)));
+    plot.push_str(&format!(
+        "{}  {:<w$}{:>9.1e}\n",
+        " ".repeat(9),
+        format!("{:.1e}", first.learning_rate),
+        last.learning_rate,
+        w = width.saturating_sub(9)
+    ));
+    plot
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_suggests_steepest_descent_before_divergence() {
+        let sweep = LrSweep::new(1e-5, 1.0, 50);
+        assert!((sweep.learning_rate(0) - 1e-5).abs() < 1e-10);
+        assert!((sweep.learning_rate(49) - 1.0).abs() < 1e-5);
+
+        // Flat, falling fastest around 3e-4, then blowing up past 1e-2
+        let loss = |lr: f32| {
+            let x = lr.log10();
+            if x < -2.0 {
+                5.0 - 3.0 / (1.0 + (-(x + 3.5) * 4.0).exp())
+            } else {
+                2.0 * ((x + 2.0) * 3.0).exp()
+            }
+        };
+        let mut recorder = LrRecorder::new();
+        let steps = (0..sweep.steps)
+            .take_while(|&step| recorder.record(sweep.learning_rate(step), loss(sweep.learning_rate(step))))
+            .count();
+        assert!(steps < sweep.steps);
+
+        let points = recorder.into_points();
+        let suggestion = suggest_learning_rate(&points).unwrap();
+        assert!((1e-4..=2e-3).contains(&suggestion), "{}", suggestion);
+        assert!(!plot_lr_curve(&points, Some(suggestion), 40, 8).is_empty());
+    }
+}
@@ -0,0 +1,95 @@
+use serde::Serialize;
+use std::fs;
+
+/// Memory use at one point of a run. Process figures come from
+/// `/proc/self/status` (Linux only); device memory is what the backend's
+/// allocator has handed out to tensors, for backends that report it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct MemorySample {
+    /// Resident set size (`VmRSS`)
+    pub rss_bytes: Option<u64>,
+    /// Peak resident set size so far (`VmHWM`)
+    pub peak_rss_bytes: Option<u64>,
+    pub device_bytes: Option<u64>,
+}
+
+/// Keeps the highest device memory seen across samples (the process peak
+/// is tracked by the kernel)
+#[derive(Debug, Clone, Default)]
+pub struct MemoryTracker {
+    peak_device_bytes: Option<u64>,
+}
+
+impl MemoryTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Current process memory, with `device_bytes` from the backend
+    pub fn sample(&mut self, device_bytes: Option<u64>) -> MemorySample {
+        if let Some(bytes) = device_bytes {
+            self.peak_device_bytes = Some(self.peak_device_bytes.map_or(bytes, |peak| peak.max(bytes)));
+        }
+        let (rss_bytes, peak_rss_bytes) = process_memory_bytes();
+        MemorySample { rss_bytes, peak_rss_bytes, device_bytes }
+    }
+
+    /// Highest device memory of all samples
+    pub fn peak_device_bytes(&self) -> Option<u64> {
+        self.peak_device_bytes
+    }
+}
+
+/// Peak resident set size (`VmHWM`) of this process
+pub fn peak_memory_bytes() -> Option<u64> {
+    process_memory_bytes().1
+}
+
+/// `VmRSS` and `VmHWM` of this process
+fn process_memory_bytes() -> (Option<u64>, Option<u64>) {
+    let Ok(status) = fs::read_to_string("/proc/self/status") else {
+        return (None, None);
+    };
+    (status_kb(&status, "VmRSS:"), status_kb(&status, "VmHWM:"))
+}
+
+fn status_kb(status: &str, key: &str) -> Option<u64> {
+    let line = status.lines().find(|line| line.starts_with(key))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+/// `bytes` in MiB with one decimal, or `-` when unknown
+pub fn format_mib(bytes: Option<u64>) -> String {
+    bytes.map_or("-".to_string(), |bytes| format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracker_keeps_peak_device_memory() {
+        let mut tracker = MemoryTracker::new();
+        tracker.sample(None);
+        assert_eq!(tracker.peak_device_bytes(), None);
+        tracker.sample(Some(300));
+        let sample = tracker.sample(Some(100));
+        assert_eq!(sample.device_bytes, Some(100));
+        assert_eq!(tracker.peak_device_bytes(), Some(300));
+
+        if cfg!(target_os = "linux") {
+            assert!(sample.rss_bytes.unwrap() <= sample.peak_rss_bytes.unwrap());
+        }
+        assert_eq!(format_mib(Some(3 * 1024 * 1024 / 2)), "1.5 MiB");
+        assert_eq!(format_mib(None), "-");
+    }
+
+    #[test]
+    fn test_status_kb() {
+        let status = "Name:\thope\nVmHWM:\t  2048 kB\nVmRSS:\t  1024 kB\n";
+        assert_eq!(status_kb(status, "VmRSS:"), Some(1024 * 1024));
+        assert_eq!(status_kb(status, "VmHWM:"), Some(2048 * 1024));
+        assert_eq!(status_kb(status, "VmSwap:"), None);
+    }
+}
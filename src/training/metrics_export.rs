@@ -0,0 +1,135 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use crate::config::MetricsFormat;
+use super::trainer::ModuleNorm;
+
+/// One row of exported training metrics
+#[derive(Debug, Clone, Serialize)]
+pub struct MetricsRecord {
+    pub step: usize,
+    pub loss: f32,
+    /// Mean loss since the previous record
+    pub avg_loss: f32,
+    pub learning_rate: f32,
+    pub steps_per_sec: f64,
+    pub tokens_per_sec: f64,
+    /// Global gradient norm of the step (JSONL only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grad_norm: Option<f32>,
+    /// Gradient and parameter norms per submodule (JSONL only)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub modules: Vec<ModuleNorm>,
+    /// Resident memory of the process (JSONL only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rss_bytes: Option<u64>,
+    /// Device memory allocated to tensors, on backends that report it (JSONL only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_bytes: Option<u64>,
+}
+
+const CSV_HEADER: &str = "step,loss,avg_loss,learning_rate,steps_per_sec,tokens_per_sec";
+
+/// Appends metrics records to `metrics.csv` or `metrics.jsonl`, so resumed
+/// runs keep extending the same file
+pub struct MetricsExporter {
+    file: File,
+    format: MetricsFormat,
+    path: PathBuf,
+}
+
+impl MetricsExporter {
+    pub fn open(dir: &Path, format: MetricsFormat) -> Result<Self> {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create metrics directory: {:?}", dir))?;
+
+        let path = dir.join(match format {
+            MetricsFormat::Csv => "metrics.csv",
+            MetricsFormat::Jsonl => "metrics.jsonl",
+        });
+        let is_new = fs::metadata(&path).map(|m| m.len() == 0).unwrap_or(true);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open metrics file: {:?}", path))?;
+
+        if is_new && format == MetricsFormat::Csv {
+            writeln!(file, "{}", CSV_HEADER)?;
+        }
+
+        Ok(Self { file, format, path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn append(&mut self, record: &MetricsRecord) -> Result<()> {
+        let line = match self.format {
+            MetricsFormat::Csv => format!(
+                "{},{},{},{},{},{}",
+                record.step,
+                record.loss,
+                record.avg_loss,
+                record.learning_rate,
+                record.steps_per_sec,
+                record.tokens_per_sec
+            ),
+            MetricsFormat::Jsonl => serde_json::to_string(record)?,
+        };
+        writeln!(self.file, "{}", line)
+            .with_context(|| format!("Failed to write metrics: {:?}", self.path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn record(step: usize) -> MetricsRecord {
+        MetricsRecord {
+            step,
+            loss: 2.5,
+            avg_loss: 3.0,
+            learning_rate: 1e-4,
+            steps_per_sec: 2.0,
+            tokens_per_sec: 512.0,
+            grad_norm: Some(0.5),
+            modules: vec![ModuleNorm { module: "head".to_string(), grad_norm: 0.5, param_norm: 4.0 }],
+            rss_bytes: Some(1 << 20),
+            device_bytes: None,
+        }
+    }
+
+    #[test]
+    fn test_csv_header_written_once_across_reopens() {
+        let temp_dir = TempDir::new().unwrap();
+        MetricsExporter::open(temp_dir.path(), MetricsFormat::Csv).unwrap().append(&record(10)).unwrap();
+        MetricsExporter::open(temp_dir.path(), MetricsFormat::Csv).unwrap().append(&record(20)).unwrap();
+
+        let content = fs::read_to_string(temp_dir.path().join("metrics.csv")).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines, vec![CSV_HEADER, "10,2.5,3,0.0001,2,512", "20,2.5,3,0.0001,2,512"]);
+    }
+
+    #[test]
+    fn test_jsonl_records_parse() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut exporter = MetricsExporter::open(temp_dir.path(), MetricsFormat::Jsonl).unwrap();
+        exporter.append(&record(10)).unwrap();
+
+        let content = fs::read_to_string(exporter.path()).unwrap();
+        let value: serde_json::Value = serde_json::from_str(content.trim()).unwrap();
+        assert_eq!(value["step"], 10);
+        assert_eq!(value["tokens_per_sec"], 512.0);
+        assert_eq!(value["grad_norm"], 0.5);
+        assert_eq!(value["modules"][0]["module"], "head");
+        assert_eq!(value["modules"][0]["param_norm"], 4.0);
+        assert_eq!(value["rss_bytes"], 1 << 20);
+        assert!(value.get("device_bytes").is_none());
+    }
+}
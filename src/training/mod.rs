@@ -1,3 +1,24 @@
+pub mod attribution;
+pub mod bench;
+pub mod callbacks;
+pub mod early_stopping;
+pub mod eval;
+pub mod metrics_export;
+pub mod forgetting;
+pub mod lora;
+pub mod loss_weights;
+pub mod lr_finder;
+pub mod memory;
+pub mod nan_guard;
+pub mod online;
+pub mod optimizer;
+pub mod precision;
+pub mod quarantine;
+pub mod robust_loss;
+pub mod runs;
+pub mod scheduler;
+pub mod swa;
+pub mod tensorboard;
 pub mod trainer;
 
 pub use trainer::{HopeTrainer, BatchData, generate_random_batch};
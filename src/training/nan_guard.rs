@@ -0,0 +1,80 @@
+use crate::config::NanGuardConfig;
+
+/// What the training loop does after a step
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NanGuardAction {
+    /// Finite loss
+    Continue,
+    /// Non-finite loss; the trainer skipped the optimizer step
+    Skipped,
+    /// `max_consecutive` non-finite losses in a row: restore the last checkpoint
+    Rollback,
+}
+
+/// Counts consecutive non-finite losses and backs off the learning rate
+#[derive(Debug, Clone)]
+pub struct NanGuard {
+    lr_backoff: f32,
+    max_consecutive: usize,
+    consecutive: usize,
+    lr_scale: f32,
+}
+
+impl NanGuard {
+    pub fn new(config: &NanGuardConfig) -> Self {
+        config.validate();
+
+        Self {
+            lr_backoff: config.lr_backoff,
+            max_consecutive: config.max_consecutive,
+            consecutive: 0,
+            lr_scale: 1.0,
+        }
+    }
+
+    /// Factor applied to the scheduled learning rate for the rest of the run
+    pub fn lr_scale(&self) -> f32 {
+        self.lr_scale
+    }
+
+    /// Record the loss of a step
+    pub fn observe(&mut self, loss: f32) -> NanGuardAction {
+        if loss.is_finite() {
+            self.consecutive = 0;
+            return NanGuardAction::Continue;
+        }
+
+        self.consecutive += 1;
+        self.lr_scale *= self.lr_backoff;
+        if self.consecutive >= self.max_consecutive {
+            self.consecutive = 0;
+            NanGuardAction::Rollback
+        } else {
+            NanGuardAction::Skipped
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rolls_back_after_consecutive_failures() {
+        let config = NanGuardConfig {
+            enabled: true,
+            lr_backoff: 0.5,
+            max_consecutive: 2,
+        };
+        let mut guard = NanGuard::new(&config);
+
+        assert_eq!(guard.observe(f32::NAN), NanGuardAction::Skipped);
+        assert_eq!(guard.observe(2.0), NanGuardAction::Continue);
+        assert_eq!(guard.observe(f32::INFINITY), NanGuardAction::Skipped);
+        assert_eq!(guard.observe(f32::NAN), NanGuardAction::Rollback);
+        // The count starts over after a rollback
+        assert_eq!(guard.observe(f32::NAN), NanGuardAction::Skipped);
+        // Backed off once per non-finite loss, and kept after the rollback
+        assert_eq!(guard.lr_scale(), 0.0625);
+    }
+}
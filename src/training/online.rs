@@ -0,0 +1,197 @@
+use anyhow::Result;
+use burn::tensor::{ElementConversion, Int, Tensor, backend::AutodiffBackend};
+use std::path::PathBuf;
+use tracing::info;
+
+use crate::checkpoint::save_checkpoint;
+use crate::config::TrainConfig;
+use crate::model::HopeModel;
+use super::{BatchData, HopeTrainer};
+
+/// Guard rails for learning from user feedback
+#[derive(Debug, Clone)]
+pub struct OnlineConfig {
+    pub learning_rate: f32,
+    /// Upper bound for `learning_rate`, whatever the caller asks for
+    pub max_learning_rate: f32,
+    /// Optimizer steps per accepted completion
+    pub steps_per_example: usize,
+    /// Stop learning after this many optimizer steps in total
+    pub max_updates: usize,
+    /// Save a checkpoint every N optimizer steps (0 = only on `save`)
+    pub save_every: usize,
+    pub checkpoint_dir: PathBuf,
+}
+
+impl OnlineConfig {
+    pub fn validate(&self) {
+        assert!(self.learning_rate > 0.0, "learning_rate must be > 0");
+        assert!(self.max_learning_rate > 0.0, "max_learning_rate must be > 0");
+        assert!(self.steps_per_example > 0, "steps_per_example must be > 0");
+    }
+
+    pub fn effective_learning_rate(&self) -> f32 {
+        self.learning_rate.min(self.max_learning_rate)
+    }
+}
+
+/// Fine-tunes a model on accepted completions, one small update at a time
+pub struct OnlineLearner<B: AutodiffBackend> {
+    trainer: HopeTrainer<B>,
+    config: OnlineConfig,
+    seq_len: usize,
+    pad_id: i64,
+    start_step: usize,
+    updates: usize,
+    device: B::Device,
+}
+
+impl<B: AutodiffBackend> OnlineLearner<B> {
+    /// `start_step` is the training step of the loaded checkpoint, so online
+    /// checkpoints continue its numbering
+    pub fn new(
+        model: HopeModel<B>,
+        mut train_config: TrainConfig,
+        config: OnlineConfig,
+        start_step: usize,
+        pad_id: i64,
+        device: &B::Device,
+    ) -> Self {
+        config.validate();
+        train_config.training.learning_rate = config.effective_learning_rate();
+        train_config.training.stateful.enabled = false;
+        let seq_len = train_config.model.seq_len;
+
+        Self {
+            trainer: HopeTrainer::new(model, train_config, device).with_pad_token(pad_id, device),
+            config,
+            seq_len,
+            pad_id,
+            start_step,
+            updates: 0,
+            device: device.clone(),
+        }
+    }
+
+    pub fn model(&self) -> &HopeModel<B> {
+        self.trainer.model()
+    }
+
+    pub fn updates(&self) -> usize {
+        self.updates
+    }
+
+    pub fn remaining_updates(&self) -> usize {
+        self.config.max_updates.saturating_sub(self.updates)
+    }
+
+    /// Train on an accepted text (prompt + completion). Returns the mean
+    /// loss of the steps taken, or `None` once `max_updates` is reached.
+    pub fn learn(&mut self, tokens: &[i64]) -> Result<Option<f32>> {
+        if tokens.len() < 2 || self.remaining_updates() == 0 {
+            return Ok(None);
+        }
+
+        let steps = self.config.steps_per_example.min(self.remaining_updates());
+        let mut total_loss = 0.0;
+        for _ in 0..steps {
+            let output = self.trainer.train_step(self.example_batch(tokens));
+            total_loss += output.loss.into_scalar().elem::<f32>();
+            self.updates += 1;
+
+            if self.config.save_every > 0 && self.updates.is_multiple_of(self.config.save_every) {
+                self.save()?;
+            }
+        }
+
+        Ok(Some(total_loss / steps as f32))
+    }
+
+    /// Save the current model under `checkpoint_dir`
+    pub fn save(&self) -> Result<PathBuf> {
+        let path = save_checkpoint(
+            &self.trainer,
+            self.start_step + self.updates,
+            None,
+            None,
+            None,
+            &self.config.checkpoint_dir,
+        )?;
+        info!("Online checkpoint saved after {} updates: {:?}", self.updates, path);
+        Ok(path)
+    }
+
+    /// Whether updates were made since the last periodic save
+    pub fn has_unsaved_updates(&self) -> bool {
+        self.updates > 0 && (self.config.save_every == 0 || !self.updates.is_multiple_of(self.config.save_every))
+    }
+
+    /// Split `tokens` into windows of `seq_len` (+1 target), left-padding
+    /// the first window; padded targets are ignored by the loss
+    fn example_batch(&self, tokens: &[i64]) -> BatchData<B> {
+        let window = self.seq_len + 1;
+        let pad = (self.seq_len - (tokens.len() - 1) % self.seq_len) % self.seq_len;
+        let padded: Vec<i64> = vec![self.pad_id; pad]
+            .into_iter()
+            .chain(tokens.iter().copied())
+            .collect();
+
+        let rows: Vec<&[i64]> = (0..padded.len() - 1)
+            .step_by(self.seq_len)
+            .map(|start| &padded[start..start + window])
+            .collect();
+        let batch_size = rows.len();
+
+        let inputs: Vec<i64> = rows.iter().flat_map(|row| row[..self.seq_len].to_vec()).collect();
+        let targets: Vec<i64> = rows.iter().flat_map(|row| row[1..].to_vec()).collect();
+
+        let to_tensor = |ids: &[i64]| {
+            Tensor::<B, 1, Int>::from_ints(ids, &self.device).reshape([batch_size, self.seq_len])
+        };
+        BatchData::new(to_tensor(&inputs), to_tensor(&targets))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HopeConfig;
+    use burn::backend::Autodiff;
+    use burn_ndarray::NdArray;
+    use tempfile::TempDir;
+
+    type B = Autodiff<NdArray<f32>>;
+
+    #[test]
+    fn test_learning_respects_update_cap() {
+        let temp_dir = TempDir::new().unwrap();
+        let device = Default::default();
+        let train_config = TrainConfig::for_test(HopeConfig::tiny(), "{}");
+        let config = OnlineConfig {
+            learning_rate: 1.0,
+            max_learning_rate: 1e-3,
+            steps_per_example: 2,
+            max_updates: 3,
+            save_every: 2,
+            checkpoint_dir: temp_dir.path().to_path_buf(),
+        };
+        assert_eq!(config.effective_learning_rate(), 1e-3);
+
+        let model = HopeModel::<B>::new(train_config.model.clone(), &device);
+        let mut learner = OnlineLearner::new(model, train_config, config, 10, 0, &device);
+
+        // 7 tokens: one padded window plus one full window
+        let batch = learner.example_batch(&[1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(batch.tokens.dims(), [2, 4]);
+
+        assert!(learner.learn(&[1, 2, 3, 4, 5, 6, 7]).unwrap().is_some());
+        assert!(learner.learn(&[1, 2, 3]).unwrap().is_some());
+        assert_eq!(learner.updates(), 3);
+        assert!(learner.learn(&[1, 2, 3]).unwrap().is_none());
+        assert!(learner.has_unsaved_updates());
+        // Periodic save after the second update, continuing the step count
+        let saved = crate::checkpoint::list_checkpoints(temp_dir.path()).unwrap();
+        assert_eq!(saved.len(), 1);
+        assert_eq!(saved[0].1, 12);
+    }
+}
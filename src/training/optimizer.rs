@@ -0,0 +1,536 @@
+use anyhow::{Context, Result};
+use burn::module::{Module, ModuleVisitor, Param};
+use burn::optim::adaptor::OptimizerAdaptor;
+use burn::optim::decay::WeightDecayConfig;
+use burn::optim::momentum::MomentumConfig;
+use burn::optim::{
+    Adam, AdamConfig, AdamW, AdamWConfig, GradientsParams, Optimizer, SimpleOptimizer, Sgd,
+    SgdConfig,
+};
+use burn::record::{Record, Recorder};
+use burn::tensor::{Tensor, backend::{AutodiffBackend, Backend}};
+use std::collections::BTreeMap;
+use std::path::Path;
+use crate::backend::checkpoint_recorder;
+use crate::config::{OptimizerKind, TrainingConfig};
+use crate::model::HopeModel;
+
+/// Optimizer used by `HopeTrainer`, selected by `training.optimizer`
+pub enum TrainingOptimizer<B: AutodiffBackend> {
+    Sgd(OptimizerAdaptor<Sgd<B::InnerBackend>, HopeModel<B>, B>),
+    Adam(OptimizerAdaptor<Adam, HopeModel<B>, B>),
+    AdamW(OptimizerAdaptor<AdamW, HopeModel<B>, B>),
+    Lion(OptimizerAdaptor<Lion, HopeModel<B>, B>),
+    RAdam(OptimizerAdaptor<RAdam, HopeModel<B>, B>),
+}
+
+impl<B: AutodiffBackend> TrainingOptimizer<B> {
+    pub fn step(&mut self, lr: f64, model: HopeModel<B>, grads: GradientsParams) -> HopeModel<B> {
+        match self {
+            TrainingOptimizer::Sgd(optim) => optim.step(lr, model, grads),
+            TrainingOptimizer::Adam(optim) => optim.step(lr, model, grads),
+            TrainingOptimizer::AdamW(optim) => optim.step(lr, model, grads),
+            TrainingOptimizer::Lion(optim) => optim.step(lr, model, grads),
+            TrainingOptimizer::RAdam(optim) => optim.step(lr, model, grads),
+        }
+    }
+
+    pub fn kind(&self) -> OptimizerKind {
+        match self {
+            TrainingOptimizer::Sgd(_) => OptimizerKind::Sgd,
+            TrainingOptimizer::Adam(_) => OptimizerKind::Adam,
+            TrainingOptimizer::AdamW(_) => OptimizerKind::AdamW,
+            TrainingOptimizer::Lion(_) => OptimizerKind::Lion,
+            TrainingOptimizer::RAdam(_) => OptimizerKind::RAdam,
+        }
+    }
+
+    /// Save the optimizer state (momentum buffers, step counts) to `path`
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let recorder = checkpoint_recorder();
+        let path = path.to_path_buf();
+        match self {
+            TrainingOptimizer::Sgd(optim) => recorder.record(optim.to_record(), path.clone()),
+            TrainingOptimizer::Adam(optim) => recorder.record(optim.to_record(), path.clone()),
+            TrainingOptimizer::AdamW(optim) => recorder.record(optim.to_record(), path.clone()),
+            TrainingOptimizer::Lion(optim) => recorder.record(optim.to_record(), path.clone()),
+            TrainingOptimizer::RAdam(optim) => recorder.record(optim.to_record(), path.clone()),
+        }
+        .with_context(|| format!("Failed to save optimizer state to: {:?}", path))
+    }
+
+    /// Restore optimizer state previously written by `save`
+    pub fn load(self, path: &Path, device: &B::Device) -> Result<Self> {
+        let recorder = checkpoint_recorder();
+        let path = path.to_path_buf();
+        let context = || format!("Failed to load optimizer state from: {:?}", path);
+        Ok(match self {
+            TrainingOptimizer::Sgd(optim) => TrainingOptimizer::Sgd(
+                optim.load_record(recorder.load(path.clone(), device).with_context(context)?),
+            ),
+            TrainingOptimizer::Adam(optim) => TrainingOptimizer::Adam(
+                optim.load_record(recorder.load(path.clone(), device).with_context(context)?),
+            ),
+            TrainingOptimizer::AdamW(optim) => TrainingOptimizer::AdamW(
+                optim.load_record(recorder.load(path.clone(), device).with_context(context)?),
+            ),
+            TrainingOptimizer::Lion(optim) => TrainingOptimizer::Lion(
+                optim.load_record(recorder.load(path.clone(), device).with_context(context)?),
+            ),
+            TrainingOptimizer::RAdam(optim) => TrainingOptimizer::RAdam(
+                optim.load_record(recorder.load(path.clone(), device).with_context(context)?),
+            ),
+        })
+    }
+}
+
+/// Build the optimizer selected in the training config
+pub fn build_optimizer<B: AutodiffBackend>(config: &TrainingConfig) -> TrainingOptimizer<B> {
+    let weight_decay = config.weight_decay.map(WeightDecayConfig::new);
+    let params = &config.optimizer_params;
+    params.validate();
+
+    match config.optimizer {
+        OptimizerKind::Sgd => TrainingOptimizer::Sgd(
+            SgdConfig::new()
+                .with_momentum(Some(MomentumConfig::new()))
+                .with_weight_decay(weight_decay)
+                .init(),
+        ),
+        OptimizerKind::Adam => {
+            let mut adam = AdamConfig::new().with_weight_decay(weight_decay);
+            if let Some(beta_1) = params.beta_1 {
+                adam = adam.with_beta_1(beta_1);
+            }
+            if let Some(beta_2) = params.beta_2 {
+                adam = adam.with_beta_2(beta_2);
+            }
+            if let Some(epsilon) = params.epsilon {
+                adam = adam.with_epsilon(epsilon);
+            }
+            TrainingOptimizer::Adam(adam.init())
+        }
+        OptimizerKind::AdamW => {
+            let mut adamw = AdamWConfig::new();
+            if let Some(penalty) = config.weight_decay {
+                adamw = adamw.with_weight_decay(penalty);
+            }
+            if let Some(beta_1) = params.beta_1 {
+                adamw = adamw.with_beta_1(beta_1);
+            }
+            if let Some(beta_2) = params.beta_2 {
+                adamw = adamw.with_beta_2(beta_2);
+            }
+            if let Some(epsilon) = params.epsilon {
+                adamw = adamw.with_epsilon(epsilon);
+            }
+            TrainingOptimizer::AdamW(adamw.init())
+        }
+        OptimizerKind::Lion => TrainingOptimizer::Lion(OptimizerAdaptor::from(
+            Lion::new(config.weight_decay.unwrap_or(0.0))
+                .with_betas(params.beta_1.unwrap_or(0.9), params.beta_2.unwrap_or(0.99)),
+        )),
+        OptimizerKind::RAdam => TrainingOptimizer::RAdam(OptimizerAdaptor::from(
+            RAdam::new(config.weight_decay.unwrap_or(0.0))
+                .with_betas(params.beta_1.unwrap_or(0.9), params.beta_2.unwrap_or(0.999))
+                .with_epsilon(params.epsilon.unwrap_or(1e-8)),
+        )),
+    }
+}
+
+/// LR multiplier of the parameter at module path `path` (e.g.
+/// `level_encoders.0.layers.1.mha.query.weight`): that of the longest
+/// matching prefix in `multipliers`, 1.0 if none matches
+pub fn lr_multiplier(multipliers: &BTreeMap<String, f32>, path: &str) -> f32 {
+    multipliers
+        .iter()
+        .filter(|(prefix, _)| path_matches(prefix, path))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map_or(1.0, |(_, &multiplier)| multiplier)
+}
+
+/// Whether the module path `prefix` is `path` or one of its parent modules
+/// (`level_encoders.1` matches `level_encoders.1.norm.gamma`, not
+/// `level_encoders.10.norm.gamma`)
+pub fn path_matches(prefix: &str, path: &str) -> bool {
+    path == prefix || path.strip_prefix(prefix).is_some_and(|rest| rest.starts_with('.'))
+}
+
+/// Layer-wise LR decay multiplier of the parameter at `path`, by depth
+/// from the output: 1.0 for the head (and the memory/self-modification
+/// modules applied alongside the levels' outputs), `decay^(num_levels - i)`
+/// for level `i` and `decay^(num_levels + 1)` for the embeddings
+pub fn layer_decay_multiplier(decay: f32, num_levels: usize, path: &str) -> f32 {
+    let mut segments = path.split('.');
+    let depth = match segments.next() {
+        Some("token_embed" | "pos_embed") => num_levels + 1,
+        Some("level_encoders") => {
+            let level = segments.next().and_then(|i| i.parse::<usize>().ok()).unwrap_or(0);
+            num_levels.saturating_sub(level)
+        }
+        _ => 0,
+    };
+    decay.powi(depth as i32)
+}
+
+/// Combined LR multiplier of the parameter at `path`: its `lr_multipliers`
+/// entry times its layer-wise decay
+pub fn parameter_lr_multiplier(config: &TrainingConfig, num_levels: usize, path: &str) -> f32 {
+    let decay = config
+        .optimizer_params
+        .layer_decay
+        .map_or(1.0, |decay| layer_decay_multiplier(decay, num_levels, path));
+    lr_multiplier(&config.lr_multipliers, path) * decay
+}
+
+/// Split `grads` into one set per distinct LR multiplier of their
+/// parameters, for a separate optimizer step per group
+pub fn group_gradients<B: AutodiffBackend, M: Module<B>>(
+    model: &M,
+    grads: GradientsParams,
+    multiplier: &dyn Fn(&str) -> f32,
+) -> Vec<(f32, GradientsParams)> {
+    let mut visitor = GroupGradients { multiplier, path: Vec::new(), grads, groups: Vec::new() };
+    model.visit(&mut visitor);
+    visitor.groups
+}
+
+struct GroupGradients<'a> {
+    multiplier: &'a dyn Fn(&str) -> f32,
+    path: Vec<String>,
+    grads: GradientsParams,
+    groups: Vec<(f32, GradientsParams)>,
+}
+
+impl<B: AutodiffBackend> ModuleVisitor<B> for GroupGradients<'_> {
+    fn enter_module(&mut self, name: &str, _container_type: &str) {
+        self.path.push(name.to_string());
+    }
+
+    fn exit_module(&mut self, _name: &str, _container_type: &str) {
+        self.path.pop();
+    }
+
+    fn visit_float<const D: usize>(&mut self, param: &Param<Tensor<B, D>>) {
+        let Some(grad) = self.grads.remove::<B::InnerBackend, D>(param.id) else {
+            return;
+        };
+        let multiplier = (self.multiplier)(&self.path.join("."));
+        let index = match self.groups.iter().position(|(m, _)| *m == multiplier) {
+            Some(index) => index,
+            None => {
+                self.groups.push((multiplier, GradientsParams::new()));
+                self.groups.len() - 1
+            }
+        };
+        self.groups[index].1.register::<B::InnerBackend, D>(param.id, grad);
+    }
+}
+
+/// Drop the gradients of parameters under any of the `frozen` module path
+/// prefixes, so the optimizer leaves them (and their moments) untouched
+pub fn freeze_gradients<B: AutodiffBackend, M: Module<B>>(
+    model: &M,
+    grads: GradientsParams,
+    frozen: &[String],
+) -> GradientsParams {
+    let mut visitor = FreezeGradients { frozen, path: Vec::new(), grads };
+    model.visit(&mut visitor);
+    visitor.grads
+}
+
+struct FreezeGradients<'a> {
+    frozen: &'a [String],
+    path: Vec<String>,
+    grads: GradientsParams,
+}
+
+impl<B: AutodiffBackend> ModuleVisitor<B> for FreezeGradients<'_> {
+    fn enter_module(&mut self, name: &str, _container_type: &str) {
+        self.path.push(name.to_string());
+    }
+
+    fn exit_module(&mut self, _name: &str, _container_type: &str) {
+        self.path.pop();
+    }
+
+    fn visit_float<const D: usize>(&mut self, param: &Param<Tensor<B, D>>) {
+        let path = self.path.join(".");
+        if self.frozen.iter().any(|prefix| path_matches(prefix, &path)) {
+            self.grads.remove::<B::InnerBackend, D>(param.id);
+        }
+    }
+}
+
+/// Module path of every float parameter of `model`
+pub fn parameter_paths<B: Backend, M: Module<B>>(model: &M) -> Vec<String> {
+    let mut visitor = ParameterPaths { path: Vec::new(), paths: Vec::new() };
+    model.visit(&mut visitor);
+    visitor.paths
+}
+
+struct ParameterPaths {
+    path: Vec<String>,
+    paths: Vec<String>,
+}
+
+impl<B: Backend> ModuleVisitor<B> for ParameterPaths {
+    fn enter_module(&mut self, name: &str, _container_type: &str) {
+        self.path.push(name.to_string());
+    }
+
+    fn exit_module(&mut self, _name: &str, _container_type: &str) {
+        self.path.pop();
+    }
+
+    fn visit_float<const D: usize>(&mut self, _param: &Param<Tensor<B, D>>) {
+        self.paths.push(self.path.join("."));
+    }
+}
+
+/// Lion optimizer (Chen et al., 2023, "Symbolic Discovery of Optimization Algorithms")
+///
+/// Updates with the sign of an interpolation between the momentum and the
+/// current gradient, so every parameter moves by exactly `lr` per step.
+/// Typically needs a 3-10x smaller learning rate than Adam.
+#[derive(Clone)]
+pub struct Lion {
+    beta_1: f32,
+    beta_2: f32,
+    weight_decay: f32,
+}
+
+impl Lion {
+    pub fn new(weight_decay: f32) -> Self {
+        Self {
+            beta_1: 0.9,
+            beta_2: 0.99,
+            weight_decay,
+        }
+    }
+
+    pub fn with_betas(mut self, beta_1: f32, beta_2: f32) -> Self {
+        self.beta_1 = beta_1;
+        self.beta_2 = beta_2;
+        self
+    }
+}
+
+#[derive(Record, Clone)]
+pub struct LionState<B: Backend, const D: usize> {
+    pub momentum: Tensor<B, D>,
+}
+
+impl<B: Backend> SimpleOptimizer<B> for Lion {
+    type State<const D: usize> = LionState<B, D>;
+
+    fn step<const D: usize>(
+        &self,
+        lr: f64,
+        tensor: Tensor<B, D>,
+        grad: Tensor<B, D>,
+        state: Option<Self::State<D>>,
+    ) -> (Tensor<B, D>, Option<Self::State<D>>) {
+        let momentum = match state {
+            Some(state) => state.momentum,
+            None => grad.zeros_like(),
+        };
+
+        // Update direction from the interpolated momentum
+        let update = momentum
+            .clone()
+            .mul_scalar(self.beta_1)
+            .add(grad.clone().mul_scalar(1.0 - self.beta_1))
+            .sign();
+        let update = if self.weight_decay > 0.0 {
+            update.add(tensor.clone().mul_scalar(self.weight_decay))
+        } else {
+            update
+        };
+
+        // Momentum tracks the gradient with the slower beta_2
+        let momentum = momentum
+            .mul_scalar(self.beta_2)
+            .add(grad.mul_scalar(1.0 - self.beta_2));
+
+        (tensor - update.mul_scalar(lr), Some(LionState { momentum }))
+    }
+
+    fn to_device<const D: usize>(mut state: Self::State<D>, device: &B::Device) -> Self::State<D> {
+        state.momentum = state.momentum.to_device(device);
+        state
+    }
+}
+
+/// RAdam optimizer (Liu et al., 2020, "On the Variance of the Adaptive
+/// Learning Rate and Beyond")
+///
+/// Adam whose adaptive step is scaled by a rectification term that is 0
+/// while the second-moment estimate is still too noisy (SGD with momentum
+/// for the first steps) and approaches 1 later, which makes a learning-rate
+/// warm-up unnecessary. Weight decay is decoupled, as in AdamW.
+#[derive(Clone)]
+pub struct RAdam {
+    beta_1: f32,
+    beta_2: f32,
+    epsilon: f32,
+    weight_decay: f32,
+}
+
+impl RAdam {
+    pub fn new(weight_decay: f32) -> Self {
+        Self {
+            beta_1: 0.9,
+            beta_2: 0.999,
+            epsilon: 1e-8,
+            weight_decay,
+        }
+    }
+
+    pub fn with_betas(mut self, beta_1: f32, beta_2: f32) -> Self {
+        self.beta_1 = beta_1;
+        self.beta_2 = beta_2;
+        self
+    }
+
+    pub fn with_epsilon(mut self, epsilon: f32) -> Self {
+        self.epsilon = epsilon;
+        self
+    }
+
+    /// Variance rectification of step `time` (1-based), `None` while the
+    /// approximated SMA length is <= 4 and the variance is intractable
+    fn rectification(&self, time: i32) -> Option<f32> {
+        let beta_2_t = self.beta_2.powi(time);
+        let rho_inf = 2.0 / (1.0 - self.beta_2) - 1.0;
+        let rho = rho_inf - 2.0 * time as f32 * beta_2_t / (1.0 - beta_2_t);
+        (rho > 4.0).then(|| {
+            (((rho - 4.0) * (rho - 2.0) * rho_inf) / ((rho_inf - 4.0) * (rho_inf - 2.0) * rho)).sqrt()
+        })
+    }
+}
+
+#[derive(Record, Clone)]
+pub struct RAdamState<B: Backend, const D: usize> {
+    pub moment_1: Tensor<B, D>,
+    pub moment_2: Tensor<B, D>,
+    pub time: usize,
+}
+
+impl<B: Backend> SimpleOptimizer<B> for RAdam {
+    type State<const D: usize> = RAdamState<B, D>;
+
+    fn step<const D: usize>(
+        &self,
+        lr: f64,
+        tensor: Tensor<B, D>,
+        grad: Tensor<B, D>,
+        state: Option<Self::State<D>>,
+    ) -> (Tensor<B, D>, Option<Self::State<D>>) {
+        let (moment_1, moment_2, time) = match state {
+            Some(state) => (state.moment_1, state.moment_2, state.time + 1),
+            None => (grad.zeros_like(), grad.zeros_like(), 1),
+        };
+        let moment_1 = moment_1.mul_scalar(self.beta_1).add(grad.clone().mul_scalar(1.0 - self.beta_1));
+        let moment_2 = moment_2.mul_scalar(self.beta_2).add(grad.powf_scalar(2.0).mul_scalar(1.0 - self.beta_2));
+
+        let t = time as i32;
+        let corrected_1 = moment_1.clone().div_scalar(1.0 - self.beta_1.powi(t));
+        let update = match self.rectification(t) {
+            Some(rectification) => {
+                let corrected_2 = moment_2.clone().div_scalar(1.0 - self.beta_2.powi(t));
+                corrected_1
+                    .div(corrected_2.sqrt().add_scalar(self.epsilon))
+                    .mul_scalar(rectification)
+            }
+            None => corrected_1,
+        };
+        let update = if self.weight_decay > 0.0 {
+            update.add(tensor.clone().mul_scalar(self.weight_decay))
+        } else {
+            update
+        };
+
+        (tensor - update.mul_scalar(lr), Some(RAdamState { moment_1, moment_2, time }))
+    }
+
+    fn to_device<const D: usize>(mut state: Self::State<D>, device: &B::Device) -> Self::State<D> {
+        state.moment_1 = state.moment_1.to_device(device);
+        state.moment_2 = state.moment_2.to_device(device);
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use burn_ndarray::NdArray;
+
+    #[test]
+    fn test_lion_moves_by_lr() {
+        let device = Default::default();
+        let lion = Lion::new(0.0);
+        let tensor = Tensor::<NdArray<f32>, 1>::from_floats([1.0, 1.0, 1.0], &device);
+        let grad = Tensor::<NdArray<f32>, 1>::from_floats([0.5, -2.0, 0.0], &device);
+
+        let (updated, state) = lion.step(0.1, tensor, grad, None);
+        let values = updated.into_data().to_vec::<f32>().unwrap();
+
+        assert!((values[0] - 0.9).abs() < 1e-6);
+        assert!((values[1] - 1.1).abs() < 1e-6);
+        assert!((values[2] - 1.0).abs() < 1e-6);
+        assert!(state.is_some());
+    }
+
+    #[test]
+    fn test_radam_rectifies_after_warmup_free_start() {
+        let device = Default::default();
+        let radam = RAdam::new(0.0);
+        // The variance estimate is only trusted after a few steps
+        assert!(radam.rectification(1).is_none());
+        assert!(radam.rectification(4).is_none());
+        assert!(radam.rectification(5).is_some_and(|r| r > 0.0 && r < 1.0));
+        assert!(radam.rectification(100_000).is_some_and(|r| (r - 1.0).abs() < 1e-2));
+
+        // First step is a bias-corrected momentum (SGD) step: lr * grad
+        let tensor = Tensor::<NdArray<f32>, 1>::from_floats([1.0, 1.0], &device);
+        let grad = Tensor::<NdArray<f32>, 1>::from_floats([0.5, -2.0], &device);
+        let (updated, state) = radam.step(0.1, tensor, grad, None);
+        let values = updated.into_data().to_vec::<f32>().unwrap();
+        assert!((values[0] - 0.95).abs() < 1e-6);
+        assert!((values[1] - 1.2).abs() < 1e-6);
+        assert_eq!(state.unwrap().time, 1);
+    }
+
+    #[test]
+    fn test_lr_multiplier_uses_longest_prefix() {
+        let multipliers = BTreeMap::from([
+            ("level_encoders".to_string(), 0.5),
+            ("level_encoders.0.layers.1".to_string(), 2.0),
+            ("token_embed".to_string(), 0.1),
+        ]);
+        assert_eq!(lr_multiplier(&multipliers, "token_embed.weight"), 0.1);
+        assert_eq!(lr_multiplier(&multipliers, "level_encoders.0.layers.1.mha.query.weight"), 2.0);
+        assert_eq!(lr_multiplier(&multipliers, "level_encoders.0.layers.10.mha.query.weight"), 0.5);
+        // Prefixes match whole path segments only
+        assert_eq!(lr_multiplier(&multipliers, "token_embeddings.weight"), 1.0);
+        assert_eq!(lr_multiplier(&multipliers, "head.weight"), 1.0);
+    }
+
+    #[test]
+    fn test_layer_decay_scales_by_depth() {
+        let mut config: TrainingConfig = serde_json::from_str(
+            r#"{"batch_size": 2, "learning_rate": 0.01, "lr_multipliers": {"token_embed": 0.0, "level_encoders.1": 2.0}}"#,
+        ).unwrap();
+        config.optimizer_params.layer_decay = Some(0.5);
+
+        let multiplier = |path: &str| parameter_lr_multiplier(&config, 2, path);
+        assert_eq!(multiplier("head.weight"), 1.0);
+        assert_eq!(multiplier("self_modify.gate.weight"), 1.0);
+        assert_eq!(multiplier("level_encoders.1.layers.0.mha.query.weight"), 1.0);
+        assert_eq!(multiplier("level_encoders.0.layers.0.mha.query.weight"), 0.25);
+        assert_eq!(multiplier("pos_embed.weight"), 0.125);
+        // lr_multipliers still apply on top, including freezing
+        assert_eq!(multiplier("token_embed.weight"), 0.0);
+    }
+}
@@ -0,0 +1,282 @@
+use burn::module::{Module, ModuleMapper, ModuleVisitor, Param, ParamId};
+use burn::optim::GradientsParams;
+use burn::tensor::{ElementConversion, Int, Tensor, TensorData, backend::{AutodiffBackend, Backend}};
+use std::marker::PhantomData;
+use crate::config::HopeConfig;
+use crate::model::{HopeInput, HopeModel};
+use super::trainer::{BatchData, TokenLoss, fit_level_biases, level_gradients, sequence_losses};
+
+/// Clean steps before the loss scale doubles
+const GROWTH_INTERVAL: usize = 2000;
+
+/// Dynamic loss scale for half-precision training
+///
+/// The loss is multiplied by `scale` before the backward pass so small
+/// gradients do not flush to zero in f16. The scale halves whenever the
+/// gradients overflow (that step is skipped) and doubles again after
+/// `GROWTH_INTERVAL` clean steps.
+#[derive(Clone, Debug)]
+pub struct LossScaler {
+    scale: f32,
+    good_steps: usize,
+}
+
+impl LossScaler {
+    pub fn new(initial_scale: f32) -> Self {
+        assert!(initial_scale.is_finite() && initial_scale >= 1.0, "loss_scale must be finite and >= 1");
+        Self { scale: initial_scale, good_steps: 0 }
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// Update the scale after a step
+    pub fn update(&mut self, overflowed: bool) {
+        if overflowed {
+            self.scale = (self.scale / 2.0).max(1.0);
+            self.good_steps = 0;
+        } else {
+            self.good_steps += 1;
+            if self.good_steps == GROWTH_INTERVAL {
+                self.scale *= 2.0;
+                self.good_steps = 0;
+            }
+        }
+    }
+}
+
+/// Forward/backward pass of the f32 master model of backend `B` in half
+/// precision. The trainer holds it as a trait object, since the half
+/// backend is picked at runtime together with `B` (see `DeviceIndex::half_precision`).
+pub trait HalfPrecisionStep<B: AutodiffBackend>: Send {
+    /// Loss of `model` on `batch` and its f32 gradients, with the loss
+    /// multiplied by `scale` for the backward pass and the gradients divided by it
+    fn step(
+        &mut self,
+        model: &HopeModel<B>,
+        loss_fn: &TokenLoss<B>,
+        batch: BatchData<B>,
+        fast_params: Vec<Tensor<B::InnerBackend, 3>>,
+        scale: f32,
+        track_sequences: bool,
+    ) -> HalfStepOutput<B>;
+}
+
+pub struct HalfStepOutput<B: AutodiffBackend> {
+    pub loss: f32,
+    /// Gradients of the master model's parameters
+    pub grads: GradientsParams,
+    pub level_grads: Vec<Tensor<B::InnerBackend, 3>>,
+    pub sequence_losses: Vec<f32>,
+    /// Whether every gradient is finite; the step is skipped otherwise
+    pub finite: bool,
+}
+
+/// Compute copy of the model on `H`, a backend whose float element is f16
+/// or bf16. Every step rounds the master weights into it (keeping their
+/// parameter ids), runs the forward/backward pass there and converts the
+/// gradients back to f32.
+pub struct HalfCompute<H: AutodiffBackend> {
+    model: HopeModel<H>,
+    device: <H as Backend>::Device,
+}
+
+impl<H: AutodiffBackend> HalfCompute<H> {
+    pub fn new(config: &HopeConfig, device: <H as Backend>::Device) -> Self {
+        Self { model: HopeModel::new(config.clone(), &device).train(), device }
+    }
+}
+
+impl<B: AutodiffBackend, H: AutodiffBackend> HalfPrecisionStep<B> for HalfCompute<H> {
+    fn step(
+        &mut self,
+        model: &HopeModel<B>,
+        loss_fn: &TokenLoss<B>,
+        batch: BatchData<B>,
+        fast_params: Vec<Tensor<B::InnerBackend, 3>>,
+        scale: f32,
+        track_sequences: bool,
+    ) -> HalfStepOutput<B> {
+        let master_device = batch.tokens.device();
+        let mut weights = MasterWeights(Vec::new());
+        model.visit(&mut weights);
+        let mut rounding = RoundWeights { weights: weights.0.into_iter(), device: &self.device };
+        self.model = self.model.clone().map(&mut rounding);
+        assert!(rounding.weights.next().is_none(), "half-precision copy has fewer parameters than the model");
+
+        let tokens = Tensor::<H, 2, Int>::from_data(batch.tokens.into_data().convert::<H::IntElem>(), &self.device);
+        let targets = Tensor::<H, 2, Int>::from_data(batch.targets.into_data().convert::<H::IntElem>(), &self.device);
+        let [rows, seq_len] = tokens.dims();
+        let level_biases: Vec<Tensor<H, 3>> = fast_params
+            .into_iter()
+            .map(|params| Tensor::from_inner(convert(params, &self.device)).require_grad())
+            .collect();
+        let mut carry = self.model.carry_with_len(rows, seq_len, &self.device);
+        carry.level_biases = fit_level_biases(&level_biases, seq_len);
+
+        let (_, output) = self.model.forward(HopeInput { tokens }, carry);
+        let logits = output.logits;
+        let sequence_losses = if track_sequences {
+            sequence_losses(logits.clone().inner(), targets.clone().inner(), &batch.lengths)
+        } else {
+            Vec::new()
+        };
+        let loss = loss_fn.on_backend::<H>(&self.device).forward(logits, targets, &batch.lengths);
+        let loss_value: f32 = loss.clone().into_scalar().elem();
+        let raw_grads = (loss * scale).backward();
+
+        let inv_scale = scale.recip();
+        let mut finite = true;
+        let level_grads = level_gradients(&level_biases, &raw_grads)
+            .into_iter()
+            .map(|grad| {
+                let (grad, grad_finite) = unscale(grad.into_data(), inv_scale, &master_device);
+                finite &= grad_finite;
+                grad
+            })
+            .collect();
+        let mut unscaling = UnscaleGradients::<B::Device, H> {
+            half: GradientsParams::from_grads(raw_grads, &self.model),
+            grads: GradientsParams::new(),
+            inv_scale,
+            finite,
+            device: &master_device,
+            half_backend: PhantomData,
+        };
+        model.visit(&mut unscaling);
+
+        HalfStepOutput {
+            loss: loss_value,
+            grads: unscaling.grads,
+            level_grads,
+            sequence_losses,
+            finite: unscaling.finite,
+        }
+    }
+}
+
+/// `tensor` moved to backend `T`, converted to its float type
+fn convert<S: Backend, T: Backend, const D: usize>(tensor: Tensor<S, D>, device: &T::Device) -> Tensor<T, D> {
+    Tensor::from_data(tensor.into_data().convert::<T::FloatElem>(), device)
+}
+
+/// Scaled half-precision gradient `data` as an f32 tensor divided by the
+/// scale, and whether it is finite
+fn unscale<T: Backend, const D: usize>(data: TensorData, inv_scale: f32, device: &T::Device) -> (Tensor<T, D>, bool) {
+    let data = data.convert::<f32>();
+    let finite = data.iter::<f32>().all(f32::is_finite);
+    let grad = Tensor::<T, D>::from_data(data.convert::<T::FloatElem>(), device).mul_scalar(inv_scale);
+    (grad, finite)
+}
+
+/// Ids and values of the master model's float parameters, in visiting order
+struct MasterWeights(Vec<(ParamId, TensorData)>);
+
+impl<B: Backend> ModuleVisitor<B> for MasterWeights {
+    fn visit_float<const D: usize>(&mut self, param: &Param<Tensor<B, D>>) {
+        self.0.push((param.id, param.val().into_data()));
+    }
+}
+
+/// Replace the compute copy's parameters with the master weights, rounded
+/// to its float type; both models share the architecture, so they visit
+/// their parameters in the same order
+struct RoundWeights<'a, D> {
+    weights: std::vec::IntoIter<(ParamId, TensorData)>,
+    device: &'a D,
+}
+
+impl<H: AutodiffBackend> ModuleMapper<H> for RoundWeights<'_, <H as Backend>::Device> {
+    fn map_float<const D: usize>(&mut self, param: Param<Tensor<H, D>>) -> Param<Tensor<H, D>> {
+        let (_, tensor, mapper) = param.consume();
+        let (id, data) = self.weights.next().expect("half-precision copy has more parameters than the model");
+        assert_eq!(data.shape, tensor.dims().to_vec(), "half-precision copy does not match the model");
+        let value = Tensor::from_data(data.convert::<H::FloatElem>(), self.device).require_grad();
+        Param::from_mapped_value(id, value, mapper)
+    }
+}
+
+/// Gradients of the compute copy, looked up by the master's parameter ids
+/// and converted to unscaled f32 gradients on the master's device
+struct UnscaleGradients<'a, D, H> {
+    half: GradientsParams,
+    grads: GradientsParams,
+    inv_scale: f32,
+    finite: bool,
+    device: &'a D,
+    half_backend: PhantomData<H>,
+}
+
+impl<B: AutodiffBackend, H: AutodiffBackend> ModuleVisitor<B> for UnscaleGradients<'_, <B as Backend>::Device, H> {
+    fn visit_float<const D: usize>(&mut self, param: &Param<Tensor<B, D>>) {
+        if let Some(grad) = self.half.remove::<H::InnerBackend, D>(param.id) {
+            let (grad, finite) = unscale::<B::InnerBackend, D>(grad.into_data(), self.inv_scale, self.device);
+            self.finite &= finite;
+            self.grads.register::<B::InnerBackend, D>(param.id, grad);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TrainConfig;
+    use burn::backend::Autodiff;
+    use burn::tensor::Distribution;
+    use burn_ndarray::NdArray;
+
+    type B = Autodiff<NdArray<f32>>;
+
+    struct CompareGradients {
+        actual: GradientsParams,
+        expected: GradientsParams,
+        compared: usize,
+    }
+
+    impl ModuleVisitor<B> for CompareGradients {
+        fn visit_float<const D: usize>(&mut self, param: &Param<Tensor<B, D>>) {
+            let expected = self.expected.remove::<NdArray<f32>, D>(param.id);
+            let actual = self.actual.remove::<NdArray<f32>, D>(param.id);
+            assert_eq!(actual.is_some(), expected.is_some());
+            if let (Some(actual), Some(expected)) = (actual, expected) {
+                actual.into_data().assert_approx_eq::<f32>(&expected.into_data(), Default::default());
+                self.compared += 1;
+            }
+        }
+    }
+
+    #[test]
+    fn test_loss_scaler_backoff_and_growth() {
+        let mut scaler = LossScaler::new(1024.0);
+        scaler.update(true);
+        assert_eq!(scaler.scale(), 512.0);
+        for _ in 0..GROWTH_INTERVAL {
+            scaler.update(false);
+        }
+        assert_eq!(scaler.scale(), 1024.0);
+    }
+
+    #[test]
+    fn test_compute_copy_gradients_match_the_master() {
+        let device = Default::default();
+        // Without dropout both passes see the same activations
+        let config = TrainConfig::for_test(HopeConfig { dropout: 0.0, ..HopeConfig::tiny() }, "{}");
+        let model = HopeModel::<B>::new(config.model.clone(), &device).train();
+        let loss_fn = TokenLoss::<B>::new(&config.training, &device);
+        let tokens = Tensor::<B, 2>::random([2, 4], Distribution::Uniform(0.0, 8.0), &device).int();
+        let targets = Tensor::<B, 2>::random([2, 4], Distribution::Uniform(0.0, 8.0), &device).int();
+
+        let (_, output) = model.forward(HopeInput { tokens: tokens.clone() }, model.carry_with_len(2, 4, &device));
+        let expected = GradientsParams::from_grads(loss_fn.forward(output.logits, targets.clone(), &[]).backward(), &model);
+
+        // A compute copy on the same float type, so only the plumbing differs
+        let mut compute = HalfCompute::<B>::new(&config.model, device);
+        let output = compute.step(&model, &loss_fn, BatchData::new(tokens, targets), Vec::new(), 64.0, false);
+        assert!(output.finite && output.loss.is_finite());
+
+        let mut compare = CompareGradients { actual: output.grads, expected, compared: 0 };
+        model.visit(&mut compare);
+        assert!(compare.compared > 0);
+    }
+}
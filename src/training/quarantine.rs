@@ -0,0 +1,190 @@
+use anyhow::{Context, Result};
+use burn::tensor::{Int, Tensor, backend::Backend};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+use crate::config::QuarantineConfig;
+use crate::data::{DocumentSpans, Tokenizer};
+
+/// One sequence of a quarantined batch
+#[derive(Debug, Clone, Serialize)]
+pub struct QuarantinedSequence {
+    pub offset: Option<usize>,
+    pub document: Option<String>,
+    pub tokens: Vec<i64>,
+    pub text: Option<String>,
+}
+
+/// A batch whose loss spiked, as written to the quarantine folder
+#[derive(Debug, Clone, Serialize)]
+pub struct QuarantinedBatch {
+    pub step: usize,
+    pub loss: f32,
+    pub running_average: f32,
+    pub sequences: Vec<QuarantinedSequence>,
+}
+
+/// Detects repeated loss spikes and dumps the offending batches
+pub struct BatchQuarantine {
+    config: QuarantineConfig,
+    dir: PathBuf,
+    seq_len: usize,
+    running_average: Option<f32>,
+    pending: Vec<QuarantinedBatch>,
+    quarantined_offsets: Vec<usize>,
+}
+
+impl BatchQuarantine {
+    pub fn new(config: QuarantineConfig, checkpoint_dir: &Path, seq_len: usize) -> Self {
+        config.validate();
+        let dir = config.dir.clone().unwrap_or_else(|| checkpoint_dir.join("quarantine"));
+
+        Self {
+            config,
+            dir,
+            seq_len,
+            running_average: None,
+            pending: Vec::new(),
+            quarantined_offsets: Vec::new(),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Whether the batch overlaps a quarantined region and should be skipped
+    pub fn should_skip(&self, offsets: &[usize]) -> bool {
+        self.config.skip_offsets
+            && offsets.iter().any(|&offset| {
+                self.quarantined_offsets
+                    .iter()
+                    .any(|&bad| offset.abs_diff(bad) < self.seq_len)
+            })
+    }
+
+    /// Feed the loss of a step. `batch` is only called when the loss spikes,
+    /// so token/text extraction is skipped for normal steps. Returns the
+    /// files written when a spike streak reaches `patience`.
+    pub fn observe(
+        &mut self,
+        step: usize,
+        loss: f32,
+        batch: impl FnOnce() -> Vec<QuarantinedSequence>,
+    ) -> Result<Vec<PathBuf>> {
+        let average = match self.running_average {
+            Some(average) => average,
+            None => {
+                self.running_average = Some(loss);
+                return Ok(Vec::new());
+            }
+        };
+
+        let spiked = !loss.is_finite() || loss > average * self.config.spike_factor;
+        if !spiked {
+            // Only normal batches update the running average
+            let decay = self.config.ema_decay;
+            self.running_average = Some(decay * average + (1.0 - decay) * loss);
+            self.pending.clear();
+            return Ok(Vec::new());
+        }
+
+        self.pending.push(QuarantinedBatch {
+            step,
+            loss,
+            running_average: average,
+            sequences: batch(),
+        });
+
+        if self.pending.len() < self.config.patience {
+            return Ok(Vec::new());
+        }
+
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create quarantine directory: {:?}", self.dir))?;
+
+        let mut written = Vec::new();
+        for batch in self.pending.drain(..) {
+            let path = self.dir.join(format!("step_{}.json", batch.step));
+            fs::write(&path, serde_json::to_string_pretty(&batch)?)
+                .with_context(|| format!("Failed to write quarantined batch: {:?}", path))?;
+            warn!("Quarantined batch from step {} (loss {:.4}, running average {:.4}): {:?}",
+                batch.step, batch.loss, batch.running_average, path);
+
+            self.quarantined_offsets
+                .extend(batch.sequences.iter().filter_map(|s| s.offset));
+            written.push(path);
+        }
+
+        Ok(written)
+    }
+}
+
+/// Collect token IDs, decoded text and source locations of a batch
+pub fn describe_batch<B: Backend>(
+    tokens: Tensor<B, 2, Int>,
+    offsets: &[usize],
+    documents: Option<&DocumentSpans>,
+    tokenizer: Option<&dyn Tokenizer>,
+) -> Vec<QuarantinedSequence> {
+    let [batch_size, seq_len] = tokens.dims();
+    let ids = tokens
+        .into_data()
+        .convert::<i64>()
+        .to_vec::<i64>()
+        .unwrap_or_default();
+
+    ids.chunks(seq_len.max(1))
+        .take(batch_size)
+        .enumerate()
+        .map(|(i, sequence)| {
+            let offset = offsets.get(i).copied();
+            QuarantinedSequence {
+                offset,
+                document: offset
+                    .and_then(|o| documents.and_then(|d| d.document_at(o)))
+                    .map(str::to_string),
+                tokens: sequence.to_vec(),
+                text: tokenizer.map(|t| t.decode(sequence)),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_repeated_spikes_are_quarantined() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = QuarantineConfig {
+            enabled: true,
+            skip_offsets: true,
+            ..Default::default()
+        };
+        let mut quarantine = BatchQuarantine::new(config, temp_dir.path(), 8);
+        let sequence = |offset| vec![QuarantinedSequence {
+            offset: Some(offset),
+            document: None,
+            tokens: vec![1, 2, 3],
+            text: None,
+        }];
+
+        assert!(quarantine.observe(0, 2.0, || sequence(0)).unwrap().is_empty());
+        // A single spike is tolerated
+        assert!(quarantine.observe(1, 10.0, || sequence(8)).unwrap().is_empty());
+        assert!(quarantine.observe(2, 2.0, || sequence(16)).unwrap().is_empty());
+        // Two in a row are dumped
+        assert!(quarantine.observe(3, 10.0, || sequence(24)).unwrap().is_empty());
+        let written = quarantine.observe(4, 12.0, || sequence(32)).unwrap();
+
+        assert_eq!(written.len(), 2);
+        assert!(written[1].exists());
+        assert!(quarantine.should_skip(&[30]));
+        assert!(!quarantine.should_skip(&[100]));
+    }
+}
@@ -0,0 +1,46 @@
+use burn::tensor::{Tensor, backend::Backend};
+
+use crate::config::{RobustLossConfig, RobustLossKind};
+
+/// Apply `config` to per-token cross-entropy `losses`
+pub fn robust_token_losses<B: Backend>(losses: Tensor<B, 1>, config: &RobustLossConfig) -> Tensor<B, 1> {
+    match config.kind {
+        RobustLossKind::None => losses,
+        RobustLossKind::Clipped => losses.clamp_max(config.max_loss),
+        // p^q = exp(-q * loss)
+        RobustLossKind::Generalized => (losses * -config.q).exp().neg().add_scalar(1.0) / config.q,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use burn::backend::Autodiff;
+    use burn_ndarray::NdArray;
+
+    type B = Autodiff<NdArray<f32>>;
+
+    fn gradients(config: &RobustLossConfig) -> (Vec<f32>, Vec<f32>) {
+        let device = Default::default();
+        let losses = Tensor::<B, 1>::from_floats([0.5, 2.0, 25.0], &device).require_grad();
+        let robust = robust_token_losses(losses.clone(), config);
+        let grads = robust.clone().sum().backward();
+        let grad = losses.grad(&grads).unwrap();
+        (robust.into_data().to_vec().unwrap(), grad.into_data().to_vec().unwrap())
+    }
+
+    #[test]
+    fn test_outliers_lose_their_pull() {
+        let clipped = RobustLossConfig { kind: RobustLossKind::Clipped, max_loss: 10.0, ..Default::default() };
+        let (losses, grads) = gradients(&clipped);
+        assert_eq!(losses, [0.5, 2.0, 10.0]);
+        assert_eq!(grads, [1.0, 1.0, 0.0]);
+
+        // Bounded by 1/q, and the gradient shrinks with the token's loss
+        let generalized = RobustLossConfig { kind: RobustLossKind::Generalized, q: 0.5, ..Default::default() };
+        let (losses, grads) = gradients(&generalized);
+        assert!(losses.iter().all(|&loss| loss < 2.0));
+        assert!((grads[0] - (-0.25f32).exp()).abs() < 1e-5);
+        assert!(grads[0] > grads[1] && grads[2] < 1e-5);
+    }
+}
@@ -0,0 +1,305 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::TrainConfig;
+
+/// A training run as recorded in its checkpoint directory: the run manifest
+/// and the metrics exported with `metrics_format`
+pub struct RunRecord {
+    pub manifest_path: PathBuf,
+    pub manifest: Value,
+    /// Exported metrics by step (empty without `metrics_format`)
+    pub metrics: Vec<MetricPoint>,
+}
+
+/// Average loss of a logging interval, positioned by tokens seen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MetricPoint {
+    pub step: usize,
+    pub tokens: u64,
+    pub loss: f32,
+}
+
+/// The columns of an exported metrics record that are compared
+#[derive(Deserialize)]
+struct MetricsLine {
+    step: usize,
+    avg_loss: f32,
+}
+
+/// Load a run from its `run_<ts>.manifest.json`, or from a checkpoint
+/// directory (its latest manifest)
+pub fn load_run(path: &Path) -> Result<RunRecord> {
+    let manifest_path = if path.is_dir() {
+        latest_manifest(path)?
+    } else {
+        path.to_path_buf()
+    };
+    let content = fs::read_to_string(&manifest_path)
+        .with_context(|| format!("Failed to read run manifest: {:?}", manifest_path))?;
+    let manifest: Value = serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse run manifest: {:?}", manifest_path))?;
+    let config: TrainConfig = serde_json::from_value(manifest["config"].clone())
+        .with_context(|| format!("Run manifest has no valid config: {:?}", manifest_path))?;
+
+    let dir = manifest_path.parent().unwrap_or(Path::new("."));
+    let metrics = read_metrics(dir, config.tokens_per_step())?;
+
+    Ok(RunRecord { manifest_path, manifest, metrics })
+}
+
+fn latest_manifest(dir: &Path) -> Result<PathBuf> {
+    let mut manifests: Vec<(u64, PathBuf)> = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read directory: {:?}", dir))?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let started_at = name.strip_prefix("run_")?.strip_suffix(".manifest.json")?.parse().ok()?;
+            Some((started_at, entry.path()))
+        })
+        .collect();
+    manifests.sort();
+    manifests
+        .pop()
+        .map(|(_, path)| path)
+        .ok_or_else(|| anyhow::anyhow!("No run manifest in {:?}", dir))
+}
+
+/// Read `metrics.jsonl` or `metrics.csv` from `dir`. Resumed runs append to
+/// the same file, so a repeated step keeps its latest record.
+pub fn read_metrics(dir: &Path, tokens_per_step: u64) -> Result<Vec<MetricPoint>> {
+    let jsonl = dir.join("metrics.jsonl");
+    let csv = dir.join("metrics.csv");
+    let lines: Vec<MetricsLine> = if jsonl.is_file() {
+        fs::read_to_string(&jsonl)
+            .with_context(|| format!("Failed to read metrics: {:?}", jsonl))?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).with_context(|| format!("Invalid metrics record in {:?}", jsonl)))
+            .collect::<Result<_>>()?
+    } else if csv.is_file() {
+        let content = fs::read_to_string(&csv)
+            .with_context(|| format!("Failed to read metrics: {:?}", csv))?;
+        let mut rows = content.lines();
+        let header: Vec<&str> = rows.next().unwrap_or_default().split(',').collect();
+        let column = |name: &str| {
+            header.iter()
+                .position(|&column| column == name)
+                .ok_or_else(|| anyhow::anyhow!("{:?} has no {} column", csv, name))
+        };
+        let (step, avg_loss) = (column("step")?, column("avg_loss")?);
+        rows.filter(|row| !row.trim().is_empty())
+            .map(|row| {
+                let fields: Vec<&str> = row.split(',').collect();
+                let field = |i: usize| fields.get(i).copied().unwrap_or_default();
+                Ok(MetricsLine {
+                    step: field(step).parse().with_context(|| format!("Invalid step in {:?}", csv))?,
+                    avg_loss: field(avg_loss).parse().with_context(|| format!("Invalid avg_loss in {:?}", csv))?,
+                })
+            })
+            .collect::<Result<_>>()?
+    } else {
+        return Ok(Vec::new());
+    };
+
+    let by_step: BTreeMap<usize, f32> = lines.into_iter().map(|line| (line.step, line.avg_loss)).collect();
+    Ok(by_step
+        .into_iter()
+        .map(|(step, loss)| MetricPoint { step, tokens: step as u64 * tokens_per_step, loss })
+        .collect())
+}
+
+/// A manifest entry that differs between two runs (`None` = absent)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestDifference {
+    pub path: String,
+    pub a: Option<Value>,
+    pub b: Option<Value>,
+}
+
+/// Differences in what determines a run's results: config, data path,
+/// corpus version and RNG seed
+pub fn diff_manifests(a: &Value, b: &Value) -> Vec<ManifestDifference> {
+    let comparable = |manifest: &Value| {
+        // Manifests written before the run seed was recorded only have the configured one
+        let seed = match &manifest["rng_seed"] {
+            Value::Null => manifest["config"]["training"]["seed"].clone(),
+            seed => seed.clone(),
+        };
+        serde_json::json!({
+            "data_path": manifest["data_path"],
+            "corpus_version": manifest["corpus_version"],
+            "rng_seed": seed,
+            "config": manifest["config"],
+        })
+    };
+    let mut differences = Vec::new();
+    diff_values("", Some(&comparable(a)), Some(&comparable(b)), &mut differences);
+    differences
+}
+
+fn diff_values(path: &str, a: Option<&Value>, b: Option<&Value>, out: &mut Vec<ManifestDifference>) {
+    match (a, b) {
+        (Some(Value::Object(a)), Some(Value::Object(b))) => {
+            let keys: BTreeSet<&String> = a.keys().chain(b.keys()).collect();
+            for key in keys {
+                let child = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                diff_values(&child, a.get(key), b.get(key), out);
+            }
+        }
+        (a, b) if a != b => out.push(ManifestDifference {
+            path: path.to_string(),
+            a: a.cloned(),
+            b: b.cloned(),
+        }),
+        _ => {}
+    }
+}
+
+/// Losses of both runs at the same number of tokens seen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AlignedPoint {
+    pub tokens: u64,
+    pub a: f32,
+    pub b: f32,
+}
+
+/// Loss of `series` at `tokens`, interpolated linearly between the
+/// surrounding records (`None` outside the series)
+pub fn loss_at(series: &[MetricPoint], tokens: u64) -> Option<f32> {
+    let after = series.iter().position(|point| point.tokens >= tokens)?;
+    let next = series[after];
+    if next.tokens == tokens {
+        return Some(next.loss);
+    }
+    let previous = series[..after].last()?;
+    let t = (tokens - previous.tokens) as f32 / (next.tokens - previous.tokens) as f32;
+    Some(previous.loss + t * (next.loss - previous.loss))
+}
+
+/// Both series at every token count of `a` that `b` also covers. Runs with
+/// different batch sizes are compared at equal data, not equal steps.
+pub fn align_series(a: &[MetricPoint], b: &[MetricPoint]) -> Vec<AlignedPoint> {
+    a.iter()
+        .filter_map(|point| {
+            loss_at(b, point.tokens).map(|loss| AlignedPoint { tokens: point.tokens, a: point.loss, b: loss })
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DivergenceKind {
+    /// The relative gap grows beyond the threshold
+    GapOpens,
+    /// The gap closes back within the threshold
+    GapCloses,
+    /// The run with the lower loss changes
+    Crossover,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Divergence {
+    pub kind: DivergenceKind,
+    pub point: AlignedPoint,
+}
+
+/// Where the loss curves part or cross: the gap relative to the lower loss
+/// moving past `threshold` (either way), and changes of the leading run
+pub fn divergence_points(aligned: &[AlignedPoint], threshold: f32) -> Vec<Divergence> {
+    let mut points = Vec::new();
+    let mut apart = false;
+    let mut leader = None;
+    for &point in aligned {
+        let gap = (point.a - point.b).abs() / point.a.min(point.b).abs().max(f32::EPSILON);
+        if (gap > threshold) != apart {
+            apart = !apart;
+            let kind = if apart { DivergenceKind::GapOpens } else { DivergenceKind::GapCloses };
+            points.push(Divergence { kind, point });
+        }
+
+        let lower = if point.a < point.b {
+            Some('a')
+        } else if point.b < point.a {
+            Some('b')
+        } else {
+            None
+        };
+        if lower.is_some() {
+            if leader.is_some() && leader != lower {
+                points.push(Divergence { kind: DivergenceKind::Crossover, point });
+            }
+            leader = lower;
+        }
+    }
+    points
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn series(tokens_per_step: u64, losses: &[(usize, f32)]) -> Vec<MetricPoint> {
+        losses
+            .iter()
+            .map(|&(step, loss)| MetricPoint { step, tokens: step as u64 * tokens_per_step, loss })
+            .collect()
+    }
+
+    #[test]
+    fn test_diff_manifests_reports_changed_leaves() {
+        let a = json!({
+            "started_at": 1, "corpus_version": "v1", "rng_seed": 7,
+            "config": {"training": {"learning_rate": 0.001, "seed": null}, "model": {"hidden_size": 64}},
+        });
+        let b = json!({
+            "started_at": 2, "corpus_version": "v1",
+            "config": {"training": {"learning_rate": 0.002, "seed": 9, "nan_guard": {"enabled": true}}, "model": {"hidden_size": 64}},
+        });
+
+        let differences = diff_manifests(&a, &b);
+        let paths: Vec<&str> = differences.iter().map(|d| d.path.as_str()).collect();
+        assert_eq!(paths, [
+            "config.training.learning_rate",
+            "config.training.nan_guard",
+            "config.training.seed",
+            "rng_seed",
+        ]);
+        assert_eq!(differences[3].b, Some(json!(9)));
+    }
+
+    #[test]
+    fn test_series_aligned_by_tokens() {
+        // B takes twice as many tokens per step as A
+        let a = series(100, &[(1, 4.0), (2, 3.0), (3, 3.0), (4, 2.45)]);
+        let b = series(200, &[(1, 3.0), (2, 2.5)]);
+
+        let aligned = align_series(&a, &b);
+        assert_eq!(aligned.iter().map(|p| p.tokens).collect::<Vec<_>>(), [200, 300, 400]);
+        assert_eq!(aligned[1].b, 2.75);
+
+        let kinds: Vec<(u64, DivergenceKind)> = divergence_points(&aligned, 0.05)
+            .iter()
+            .map(|d| (d.point.tokens, d.kind))
+            .collect();
+        // Equal at 200 tokens, B ahead by 9% at 300, A ahead by 2% at 400
+        assert_eq!(kinds, [(300, DivergenceKind::GapOpens), (400, DivergenceKind::GapCloses), (400, DivergenceKind::Crossover)]);
+    }
+
+    #[test]
+    fn test_read_metrics_keeps_latest_record_per_step() {
+        let dir = tempfile::TempDir::new().unwrap();
+        fs::write(
+            dir.path().join("metrics.csv"),
+            "step,loss,avg_loss,learning_rate,steps_per_sec,tokens_per_sec\n10,3,3.5,0.1,1,1\n20,2,2.5,0.1,1,1\n10,3,3.2,0.1,1,1\n",
+        )
+        .unwrap();
+
+        let metrics = read_metrics(dir.path(), 64).unwrap();
+        assert_eq!(metrics, series(64, &[(10, 3.2), (20, 2.5)]));
+    }
+}
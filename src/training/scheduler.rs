@@ -0,0 +1,96 @@
+use std::f32::consts::PI;
+
+use crate::config::{LrScheduleConfig, LrScheduleKind};
+
+/// Learning rate for each training step. With `cosine_restarts` the rate
+/// anneals from the base rate to `min_lr_ratio * base` over every cycle and
+/// jumps back up at the start of the next (SGDR, Loshchilov & Hutter 2017).
+#[derive(Debug, Clone)]
+pub struct LrScheduler {
+    base_lr: f32,
+    kind: LrScheduleKind,
+    cycle_steps: usize,
+    cycle_mult: usize,
+    min_lr_ratio: f32,
+}
+
+impl LrScheduler {
+    pub fn new(base_lr: f32, config: &LrScheduleConfig) -> Self {
+        config.validate();
+
+        Self {
+            base_lr,
+            kind: config.kind,
+            cycle_steps: config.cycle_steps,
+            cycle_mult: config.cycle_mult,
+            min_lr_ratio: config.min_lr_ratio,
+        }
+    }
+
+    /// Learning rate of the step taken after `step` completed steps
+    pub fn learning_rate(&self, step: usize) -> f32 {
+        match self.kind {
+            LrScheduleKind::Constant => self.base_lr,
+            LrScheduleKind::CosineRestarts => {
+                let (_, position, length) = self.cycle(step);
+                let min_lr = self.base_lr * self.min_lr_ratio;
+                let progress = position as f32 / length as f32;
+                min_lr + (self.base_lr - min_lr) * 0.5 * (1.0 + (PI * progress).cos())
+            }
+        }
+    }
+
+    /// Index of the cycle that ends once `steps` steps are completed, if any
+    pub fn cycle_end(&self, steps: usize) -> Option<usize> {
+        if self.kind == LrScheduleKind::Constant || steps == 0 {
+            return None;
+        }
+        let (cycle, position, length) = self.cycle(steps - 1);
+        (position + 1 == length).then_some(cycle)
+    }
+
+    /// Cycle containing the step after `step` completed steps, as
+    /// (cycle index, position within the cycle, cycle length)
+    fn cycle(&self, step: usize) -> (usize, usize, usize) {
+        if self.cycle_mult == 1 {
+            return (step / self.cycle_steps, step % self.cycle_steps, self.cycle_steps);
+        }
+        let (mut cycle, mut start, mut length) = (0, 0, self.cycle_steps);
+        while step >= start + length {
+            start += length;
+            length *= self.cycle_mult;
+            cycle += 1;
+        }
+        (cycle, step - start, length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cosine_restarts_anneal_and_restart() {
+        let config = LrScheduleConfig {
+            kind: LrScheduleKind::CosineRestarts,
+            cycle_steps: 4,
+            cycle_mult: 2,
+            min_lr_ratio: 0.1,
+            snapshot_every_cycle: true,
+        };
+        let scheduler = LrScheduler::new(1.0, &config);
+
+        // Cycles cover steps 0..4 and 4..12
+        let rates: Vec<f32> = (0..13).map(|step| scheduler.learning_rate(step)).collect();
+        assert_eq!(rates[0], 1.0);
+        assert!((rates[2] - 0.55).abs() < 1e-6);
+        assert!(rates[1..4].windows(2).all(|w| w[1] < w[0]));
+        assert_eq!(rates[4], 1.0);
+        assert!((rates[8] - 0.55).abs() < 1e-6);
+        assert_eq!(rates[12], 1.0);
+
+        let ends: Vec<usize> = (0..=12).filter(|&steps| scheduler.cycle_end(steps).is_some()).collect();
+        assert_eq!(ends, [4, 12]);
+        assert_eq!(scheduler.cycle_end(12), Some(1));
+    }
+}
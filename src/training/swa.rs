@@ -0,0 +1,133 @@
+use burn::module::{Module, ModuleMapper, ModuleVisitor, Param};
+use burn::tensor::{Tensor, backend::Backend};
+
+use crate::config::SwaConfig;
+use crate::model::HopeModel;
+
+/// Running mean of the weights at every `every`-th step from `first_step`
+/// on (stochastic weight averaging). Kept on the inner backend, outside the
+/// autodiff graph.
+pub struct WeightAverage<B: Backend> {
+    first_step: usize,
+    every: usize,
+    average: Option<HopeModel<B>>,
+    models: usize,
+}
+
+impl<B: Backend> WeightAverage<B> {
+    /// Averaging window of a run ending at `final_step`
+    pub fn new(config: &SwaConfig, final_step: usize) -> Self {
+        config.validate();
+
+        Self {
+            first_step: config.first_step(final_step),
+            every: config.every,
+            average: None,
+            models: 0,
+        }
+    }
+
+    pub fn first_step(&self) -> usize {
+        self.first_step
+    }
+
+    /// Whether the weights after `step` (1-based) belong in the average
+    pub fn should_collect(&self, step: usize) -> bool {
+        step >= self.first_step && (step - self.first_step).is_multiple_of(self.every)
+    }
+
+    /// Add `model` to the average
+    pub fn collect(&mut self, model: &HopeModel<B>) {
+        self.models += 1;
+        self.average = Some(match self.average.take() {
+            None => model.clone(),
+            Some(average) => {
+                let mut weights = FlatWeights(Vec::new());
+                model.visit(&mut weights);
+                let mut mapper = RunningMean {
+                    weights: weights.0.into_iter(),
+                    weight: 1.0 / self.models as f32,
+                };
+                average.map(&mut mapper)
+            }
+        });
+    }
+
+    /// The averaged model (`None` before the first collected step)
+    pub fn average(&self) -> Option<&HopeModel<B>> {
+        self.average.as_ref()
+    }
+
+    /// Number of collected models
+    pub fn models(&self) -> usize {
+        self.models
+    }
+}
+
+/// Float parameters of a module, flattened, in visiting order
+struct FlatWeights<B: Backend>(Vec<Tensor<B, 1>>);
+
+impl<B: Backend> ModuleVisitor<B> for FlatWeights<B> {
+    fn visit_float<const D: usize>(&mut self, param: &Param<Tensor<B, D>>) {
+        self.0.push(param.val().flatten(0, D - 1));
+    }
+}
+
+/// Moves every parameter `weight` of the way towards the next of `weights`
+/// (same module structure, so the visiting order matches)
+struct RunningMean<B: Backend> {
+    weights: std::vec::IntoIter<Tensor<B, 1>>,
+    weight: f32,
+}
+
+impl<B: Backend> ModuleMapper<B> for RunningMean<B> {
+    fn map_float<const D: usize>(&mut self, param: Param<Tensor<B, D>>) -> Param<Tensor<B, D>> {
+        let (id, average, mapper) = param.consume();
+        let Some(weights) = self.weights.next() else {
+            return Param::from_mapped_value(id, average, mapper);
+        };
+        let weights = weights.reshape(average.dims());
+        let updated = average.clone() + (weights - average) * self.weight;
+        Param::from_mapped_value(id, updated, mapper)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HopeConfig;
+    use burn_ndarray::NdArray;
+
+    type B = NdArray<f32>;
+
+    fn weights(model: &HopeModel<B>) -> Vec<f32> {
+        let mut weights = FlatWeights(Vec::new());
+        model.visit(&mut weights);
+        weights.0.into_iter().flat_map(|w| w.into_data().to_vec::<f32>().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_averages_weights_in_window() {
+        let config = SwaConfig { enabled: true, start_fraction: 0.5, start_step: None, every: 5 };
+        let mut swa = WeightAverage::<B>::new(&config, 100);
+        assert_eq!(swa.first_step(), 50);
+        assert!(!swa.should_collect(45));
+        assert!(swa.should_collect(50));
+        assert!(!swa.should_collect(52));
+        assert!(swa.should_collect(55));
+
+        let device = Default::default();
+        let model_config = HopeConfig::tiny();
+        let models: Vec<HopeModel<B>> = (0..3).map(|_| HopeModel::new(model_config.clone(), &device)).collect();
+        for model in &models {
+            swa.collect(model);
+        }
+        assert_eq!(swa.models(), 3);
+
+        let expected: Vec<Vec<f32>> = models.iter().map(weights).collect();
+        for (i, average) in weights(swa.average().unwrap()).into_iter().enumerate() {
+            let mean = expected.iter().map(|w| w[i]).sum::<f32>() / 3.0;
+            assert!((average - mean).abs() < 1e-5, "{} vs {}", average, mean);
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use anyhow::{Context, Result};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Writes scalar summaries as TensorBoard event files
+/// (`events.out.tfevents.*`, TFRecord framing around `Event` protos)
+pub struct TensorBoardWriter {
+    writer: BufWriter<File>,
+    path: PathBuf,
+}
+
+impl TensorBoardWriter {
+    /// Start a new event file in `log_dir`
+    pub fn create(log_dir: &Path) -> Result<Self> {
+        fs::create_dir_all(log_dir)
+            .with_context(|| format!("Failed to create log directory: {:?}", log_dir))?;
+
+        let timestamp = wall_time() as u64;
+        let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "hope".to_string());
+        let path = log_dir.join(format!("events.out.tfevents.{}.{}", timestamp, host));
+        let file = File::create(&path)
+            .with_context(|| format!("Failed to create event file: {:?}", path))?;
+
+        let mut writer = Self {
+            writer: BufWriter::new(file),
+            path,
+        };
+        let mut event = event_header(wall_time(), 0);
+        write_bytes_field(&mut event, 3, b"brain.Event:2");
+        writer.write_record(&event)?;
+        Ok(writer)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn add_scalar(&mut self, tag: &str, value: f32, step: usize) -> Result<()> {
+        let mut summary_value = Vec::new();
+        write_bytes_field(&mut summary_value, 1, tag.as_bytes());
+        summary_value.push(0x15); // field 2 (simple_value), fixed32
+        summary_value.extend_from_slice(&value.to_le_bytes());
+
+        let mut summary = Vec::new();
+        write_bytes_field(&mut summary, 1, &summary_value);
+
+        let mut event = event_header(wall_time(), step as i64);
+        write_bytes_field(&mut event, 5, &summary);
+        self.write_record(&event)
+    }
+
+    pub fn add_scalars(&mut self, scalars: &[(&str, f32)], step: usize) -> Result<()> {
+        for &(tag, value) in scalars {
+            self.add_scalar(tag, value, step)?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush().context("Failed to flush event file")
+    }
+
+    fn write_record(&mut self, data: &[u8]) -> Result<()> {
+        let len = (data.len() as u64).to_le_bytes();
+        self.writer.write_all(&len)?;
+        self.writer.write_all(&masked_crc32c(&len).to_le_bytes())?;
+        self.writer.write_all(data)?;
+        self.writer.write_all(&masked_crc32c(data).to_le_bytes())?;
+        Ok(())
+    }
+}
+
+impl Drop for TensorBoardWriter {
+    fn drop(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+fn wall_time() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+/// `Event.wall_time` (field 1, double) and `Event.step` (field 2, int64)
+fn event_header(wall_time: f64, step: i64) -> Vec<u8> {
+    let mut event = vec![0x09];
+    event.extend_from_slice(&wall_time.to_le_bytes());
+    event.push(0x10);
+    write_varint(&mut event, step as u64);
+    event
+}
+
+fn write_bytes_field(buf: &mut Vec<u8>, field: u8, bytes: &[u8]) {
+    buf.push((field << 3) | 2);
+    write_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0x82F6_3B78 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// TFRecord checksum: CRC-32C, rotated and offset
+fn masked_crc32c(data: &[u8]) -> u32 {
+    let crc = crc32c(data);
+    crc.rotate_right(15).wrapping_add(0xA282_EAD8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_crc32c_check_value() {
+        assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+    }
+
+    #[test]
+    fn test_event_file_records_are_framed() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut writer = TensorBoardWriter::create(temp_dir.path()).unwrap();
+        writer.add_scalar("train/loss", 1.5, 300).unwrap();
+        writer.flush().unwrap();
+
+        let bytes = fs::read(writer.path()).unwrap();
+        let mut records = Vec::new();
+        let mut pos = 0;
+        while pos < bytes.len() {
+            let len_bytes = &bytes[pos..pos + 8];
+            let len = u64::from_le_bytes(len_bytes.try_into().unwrap()) as usize;
+            assert_eq!(bytes[pos + 8..pos + 12], masked_crc32c(len_bytes).to_le_bytes());
+            let data = &bytes[pos + 12..pos + 12 + len];
+            assert_eq!(bytes[pos + 12 + len..pos + 16 + len], masked_crc32c(data).to_le_bytes());
+            records.push(data.to_vec());
+            pos += 16 + len;
+        }
+
+        assert_eq!(records.len(), 2);
+        assert!(records[0].windows(13).any(|w| w == b"brain.Event:2"));
+        // step 300 as a varint, then the tag and the value
+        assert!(records[1].windows(3).any(|w| w == [0x10, 0xAC, 0x02]));
+        assert!(records[1].windows(10).any(|w| w == b"train/loss"));
+        assert!(records[1].windows(4).any(|w| w == 1.5f32.to_le_bytes()));
+    }
+}
@@ -1,105 +1,986 @@
-use burn::nn::loss::CrossEntropyLoss;
-use burn::optim::adaptor::OptimizerAdaptor;
-use burn::optim::{Adam, AdamConfig, GradientsParams, Optimizer};
-use burn::tensor::{Int, Tensor, backend::{AutodiffBackend, Backend}};
-use crate::config::TrainConfig;
+use anyhow::Result;
+use burn::nn::loss::{CrossEntropyLoss, CrossEntropyLossConfig};
+use burn::module::{Module, ModuleVisitor, Param};
+use burn::optim::GradientsParams;
+use burn::tensor::activation::log_softmax;
+use burn::tensor::{ElementConversion, Int, Tensor, backend::{AutodiffBackend, Backend}};
+use serde::Serialize;
+use std::path::Path;
+use std::thread;
+use tracing::{info, warn};
+use crate::checkpoint::{load_deep_optimizer_state, load_optimizer_state};
+use crate::config::{RobustLossConfig, RobustLossKind, TrainConfig, TrainingConfig};
+use crate::model::optimizer::{DeepOptimizer, DeepOptimizerState};
+use crate::model::continuum_mem::ContinuumMemoryState;
+use crate::model::hope::HopeCarry;
+use crate::model::profile::StageTimes;
 use crate::model::{HopeModel, HopeInput};
+use super::callbacks::{Callbacks, StepEnd, TrainerCallback};
+use super::loss_weights::{TokenLossWeights, length_mask, token_losses, weighted_mean};
+use super::lr_finder::{LrPoint, LrRecorder, LrSweep};
+use super::optimizer::{
+    TrainingOptimizer, build_optimizer, freeze_gradients, group_gradients, parameter_lr_multiplier, parameter_paths,
+    path_matches,
+};
+use super::precision::{HalfPrecisionStep, LossScaler};
+use super::robust_loss::robust_token_losses;
 
 #[derive(Clone, Debug)]
 pub struct TrainOutput<B: Backend> {
     pub loss: Tensor<B, 1>,
     #[allow(dead_code)]
     pub step: usize,
+    /// Mean loss of each sequence in the batch (only when document attribution is enabled)
+    pub sequence_losses: Vec<f32>,
+    /// Global L2 norm of the parameter gradients (only when metrics are
+    /// logged to `log_dir` or exported with `metrics_format`)
+    pub grad_norm: Option<f32>,
+    /// Gradient and parameter norms per submodule (same condition)
+    pub module_norms: Vec<ModuleNorm>,
 }
 
 impl<B: Backend> TrainOutput<B> {
     pub fn new(loss: Tensor<B, 1>, step: usize) -> Self {
-        Self { loss, step }
+        Self { loss, step, sequence_losses: Vec::new(), grad_norm: None, module_norms: Vec::new() }
     }
+
+    fn with_norms(mut self, norms: Option<(f32, Vec<ModuleNorm>)>) -> Self {
+        if let Some((grad_norm, module_norms)) = norms {
+            self.grad_norm = Some(grad_norm);
+            self.module_norms = module_norms;
+        }
+        self
+    }
+}
+
+/// L2 norms of one submodule's gradients and weights: `embeddings`,
+/// `level_<i>`, `continuum_memory`, `self_modify` or `head`. A gradient
+/// norm stuck at 0 means the module is not learning.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ModuleNorm {
+    pub module: String,
+    pub grad_norm: f32,
+    pub param_norm: f32,
 }
 
 pub struct HopeTrainer<B: AutodiffBackend> {
     model: HopeModel<B>,
-    optimizer: OptimizerAdaptor<Adam, HopeModel<B>, B>,
-    loss_fn: CrossEntropyLoss<B>,
+    optimizer: TrainingOptimizer<B>,
+    loss_fn: TokenLoss<B>,
+    deep_optimizer: Option<DeepOptimizer>,
+    deep_state: Option<DeepOptimizerState<B::InnerBackend>>,
+    /// Detached carry of the previous batch (stateful training)
+    carry: Option<StreamCarry<B>>,
+    /// Devices of the data-parallel replicas (empty = train on one device)
+    replica_devices: Vec<<B as Backend>::Device>,
+    /// Half-precision copy running the forward/backward pass (`training.precision`)
+    half_precision: Option<Box<dyn HalfPrecisionStep<B>>>,
+    loss_scaler: LossScaler,
+    /// Forward stage times across steps (`training.profile`)
+    profile: StageTimes,
+    /// Learning rate of the next step (`training.learning_rate` unless scheduled)
+    learning_rate: f32,
+    /// Steps taken, counting from the step the run was resumed at
+    step: usize,
+    callbacks: Callbacks,
     config: TrainConfig,
 }
 
+/// Carry kept between batches, with the offsets it was produced from
+struct StreamCarry<B: Backend> {
+    carry: HopeCarry<B>,
+    offsets: Vec<usize>,
+    batches: usize,
+}
+
 impl<B: AutodiffBackend> HopeTrainer<B> {
     pub fn new(
         model: HopeModel<B>,
         config: TrainConfig,
         device: &<B as Backend>::Device,
     ) -> Self {
-        let optimizer = AdamConfig::new().init::<B, HopeModel<B>>();
-        let loss_fn = CrossEntropyLoss::new(None, device);
+        config.training.validate();
+        let model = model.train();
+        let optimizer = build_optimizer::<B>(&config.training);
+        let paths = parameter_paths(&model);
+        for (prefix, multiplier) in &config.training.lr_multipliers {
+            let matches = paths
+                .iter()
+                .filter(|path| path_matches(prefix, path))
+                .count();
+            if matches == 0 {
+                warn!("lr_multipliers: {:?} matches no parameter", prefix);
+            } else {
+                info!("lr_multipliers: {} parameter(s) under {:?} train at {}x the learning rate", matches, prefix, multiplier);
+            }
+        }
+        for prefix in &config.training.freeze {
+            let matches = paths.iter().filter(|path| path_matches(prefix, path)).count();
+            if matches == 0 {
+                warn!("freeze: {:?} matches no parameter", prefix);
+            } else {
+                info!("freeze: {} parameter(s) under {:?} are frozen", matches, prefix);
+            }
+        }
+        if let Some(decay) = config.training.optimizer_params.layer_decay {
+            info!(
+                "layer_decay: {} -> embeddings train at {}x the learning rate",
+                decay,
+                decay.powi(config.model.num_levels as i32 + 1)
+            );
+        }
+        let loss_fn = TokenLoss::new(&config.training, device);
+
+        let deep_optimizer = config.model.deep_optimizer.enabled
+            .then(|| DeepOptimizer::new(config.model.deep_optimizer.clone()));
+        let deep_state = deep_optimizer.as_ref().map(|deep| fresh_deep_state::<B::InnerBackend>(deep, &config, device));
 
         Self {
             model,
             optimizer,
             loss_fn,
+            deep_optimizer,
+            deep_state,
+            carry: None,
+            replica_devices: Vec::new(),
+            half_precision: None,
+            loss_scaler: LossScaler::new(config.training.loss_scale),
+            profile: if config.training.profile { StageTimes::new() } else { StageTimes::disabled() },
+            learning_rate: config.training.learning_rate,
+            step: 0,
+            callbacks: Callbacks::default(),
             config,
         }
     }
 
+    /// Split every batch across replicas of the model on `devices` and
+    /// average their gradients before the optimizer step (data parallelism).
+    /// Not supported with stateful training.
+    pub fn with_data_parallel(mut self, devices: Vec<<B as Backend>::Device>) -> Self {
+        if self.profile.is_enabled() && devices.len() > 1 {
+            warn!("profile: data-parallel steps are not timed");
+        }
+        self.replica_devices = devices;
+        self
+    }
+
+    /// Run the forward/backward pass on `compute`, a half-precision copy of
+    /// the f32 master weights, with a dynamic loss scale; steps whose
+    /// gradients overflow are skipped. Not supported with stateful training
+    /// or data parallelism.
+    pub fn with_half_precision(mut self, compute: Box<dyn HalfPrecisionStep<B>>) -> Self {
+        if self.profile.is_enabled() {
+            warn!("profile: half-precision steps are not timed");
+        }
+        self.half_precision = Some(compute);
+        self
+    }
+
+    /// Number steps from `step` on (the step a checkpoint was saved at)
+    pub fn with_start_step(mut self, step: usize) -> Self {
+        self.step = step;
+        self
+    }
+
+    /// Call `callback` around every step
+    pub fn with_callback(mut self, callback: Box<dyn TrainerCallback>) -> Self {
+        self.callbacks.push(callback);
+        self
+    }
+
+    pub fn with_callbacks(mut self, callbacks: Callbacks) -> Self {
+        self.callbacks = callbacks;
+        self
+    }
+
     pub fn train_step(
         &mut self,
         batch: BatchData<B>,
     ) -> TrainOutput<B> {
+        self.step += 1;
+        self.callbacks.on_step_start(self.step);
+        let output = if self.replica_devices.len() > 1 {
+            self.data_parallel_step(batch)
+        } else if self.half_precision.is_some() {
+            self.half_precision_step(batch)
+        } else {
+            self.single_device_step(batch)
+        };
+        if !self.callbacks.is_empty() {
+            self.callbacks.on_step_end(&StepEnd {
+                step: self.step,
+                loss: output.loss.clone().into_scalar().elem(),
+                learning_rate: self.learning_rate,
+                grad_norm: output.grad_norm,
+            });
+        }
+        output
+    }
+
+    fn single_device_step(&mut self, batch: BatchData<B>) -> TrainOutput<B> {
         let device = batch.tokens.device();
         let batch_size = batch.tokens.dims()[0];
 
-        // Initialize carry state
-        let carry = self.model.initial_carry(batch_size, &device);
+        // Stateful training continues from the previous batch's carry
+        let offsets = batch.offsets.clone();
+        let continued = self.continued_carry(&offsets);
+
+        // Initialize carry state; the deep optimizer's fast parameters enter
+        // as tracked leaves so their gradients can be read after backward
+        let (mut carry, carried_batches) = match continued {
+            Some(stream) => (stream.carry, stream.batches),
+            None => (self.model.carry_with_len(batch_size, batch.tokens.dims()[1], &device), 0),
+        };
+        let level_biases: Vec<Tensor<B, 3>> = self.deep_state
+            .as_ref()
+            .map(|state| {
+                state.fast_params
+                    .iter()
+                    .map(|params| Tensor::from_inner(params.clone()).require_grad())
+                    .collect()
+            })
+            .unwrap_or_default();
+        carry.level_biases = fit_level_biases(&level_biases, batch.tokens.dims()[1]);
 
         // Forward pass
-        let (_, output) = self.model.forward(
+        let (next_carry, output) = self.model.forward_timed(
             HopeInput {
                 tokens: batch.tokens,
             },
             carry,
+            &mut self.profile,
         );
 
+        if self.config.training.stateful.enabled {
+            self.carry = Some(StreamCarry {
+                carry: next_carry.detach(),
+                offsets,
+                batches: carried_batches + 1,
+            });
+        }
+
         // Compute loss
         let logits = output.logits;
         let targets = batch.targets;
 
-        // Reshape for loss computation: [batch, seq_len, vocab_size] -> [batch * seq_len, vocab_size]
-        let batch_size = logits.dims()[0];
-        let seq_len = logits.dims()[1];
-        let vocab_size = logits.dims()[2];
+        // Per-sequence losses for document attribution, computed outside the autodiff graph
+        let sequence_losses = if self.config.training.document_loss_window > 0 {
+            sequence_losses(logits.clone().inner(), targets.clone().inner(), &batch.lengths)
+        } else {
+            Vec::new()
+        };
 
-        let logits_flat = logits.reshape([batch_size * seq_len, vocab_size]);
-        let targets_flat = targets.reshape([batch_size * seq_len]);
+        let loss = self.loss_fn.forward(logits, targets, &batch.lengths);
 
-        // Avoid unnecessary clones - loss_fn may need ownership, but we can avoid cloning inputs
-        let loss = self.loss_fn.forward(logits_flat, targets_flat);
+        // A NaN/Inf loss would poison the weights and optimizer moments
+        if self.config.training.nan_guard.enabled && !is_finite(&loss) {
+            self.carry = None;
+            return TrainOutput { sequence_losses, ..TrainOutput::new(loss, self.step) };
+        }
 
         // Backward pass
-        let grads = GradientsParams::from_grads(loss.backward(), &self.model);
+        let raw_grads = loss.backward();
+        let level_grads = level_gradients(&level_biases, &raw_grads);
+        let grads = GradientsParams::from_grads(raw_grads, &self.model);
+        let norms = self.apply_gradients(grads, level_grads);
+
+        TrainOutput { sequence_losses, ..TrainOutput::new(loss, self.step) }.with_norms(norms)
+    }
+
+    /// `train_step` with the batch split row-wise across the replica devices:
+    /// each replica runs forward/backward on its shard in its own thread, the
+    /// gradients are averaged (weighted by shard size) on the batch's device
+    /// and a single optimizer step updates the shared weights
+    fn data_parallel_step(&mut self, batch: BatchData<B>) -> TrainOutput<B> {
+        let device = batch.tokens.device();
+        let batch_size = batch.tokens.dims()[0];
+        let shards = self.replica_devices.len().min(batch_size);
+        let track_sequences = self.config.training.document_loss_window > 0;
+        let fast_params = self.deep_state
+            .as_ref()
+            .map(|state| state.fast_params.clone())
+            .unwrap_or_default();
+
+        // Each shard keeps the lengths of its own rows (token-budget batches)
+        let mut lengths = batch.lengths.as_slice();
+        let shards: Vec<_> = batch.tokens.chunk(shards, 0)
+            .into_iter()
+            .zip(batch.targets.chunk(shards, 0))
+            .map(|(tokens, targets)| {
+                let rows = if lengths.is_empty() { 0 } else { tokens.dims()[0] };
+                let (shard_lengths, rest) = lengths.split_at(rows);
+                lengths = rest;
+                ReplicaShard { tokens, targets, lengths: shard_lengths.to_vec() }
+            })
+            .collect();
+        let total_tokens = shards.iter()
+            .map(|shard| loss_tokens(shard.tokens.dims(), &shard.lengths))
+            .sum::<usize>();
 
-        // Optimizer step - use std::mem::take to avoid cloning the entire model
-        let lr = f64::from(self.config.training.learning_rate);
-        let model = std::mem::take(&mut self.model);
-        self.model = self.optimizer.step(lr, model, grads);
+        let replicas: Vec<ReplicaOutput<B>> = thread::scope(|scope| {
+            let handles: Vec<_> = self.replica_devices
+                .iter()
+                .zip(shards)
+                .map(|(replica_device, shard)| {
+                    let model = self.model.clone().fork(replica_device);
+                    let loss_fn = self.loss_fn.clone();
+                    let fast_params = fast_params.clone();
+                    scope.spawn(move || {
+                        replica_step(model, &loss_fn, shard, fast_params, track_sequences, replica_device)
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("data-parallel replica panicked"))
+                .collect()
+        });
 
-        TrainOutput::new(loss, 1)
+        let mut loss = 0.0;
+        let mut sequence_losses = Vec::new();
+        let mut level_grads: Vec<Tensor<B::InnerBackend, 3>> = Vec::new();
+        let mut replica_grads = Vec::with_capacity(replicas.len());
+        for replica in replicas {
+            let weight = replica.tokens as f32 / total_tokens as f32;
+            loss += replica.loss * weight;
+            sequence_losses.extend(replica.sequence_losses);
+            for (i, grad) in replica.level_grads.into_iter().enumerate() {
+                let grad = grad.to_device(&device) * weight;
+                match level_grads.get_mut(i) {
+                    Some(sum) => *sum = sum.clone() + grad,
+                    None => level_grads.push(grad),
+                }
+            }
+            replica_grads.push((weight, replica.grads));
+        }
+        if self.config.training.nan_guard.enabled && !loss.is_finite() {
+            let loss = Tensor::<B, 1>::from_floats([loss], &device);
+            return TrainOutput { sequence_losses, ..TrainOutput::new(loss, self.step) };
+        }
+
+        let mut averaging = AverageGradients {
+            replicas: replica_grads,
+            device: &device,
+            averaged: GradientsParams::new(),
+        };
+        self.model.visit(&mut averaging);
+        let norms = self.apply_gradients(averaging.averaged, level_grads);
+
+        let loss = Tensor::<B, 1>::from_floats([loss], &device);
+        TrainOutput { sequence_losses, ..TrainOutput::new(loss, self.step) }.with_norms(norms)
+    }
+
+    /// `train_step` on the half-precision copy: the loss is scaled for the
+    /// backward pass, and the unscaled f32 gradients update the master weights
+    fn half_precision_step(&mut self, batch: BatchData<B>) -> TrainOutput<B> {
+        let device = batch.tokens.device();
+        let scale = self.loss_scaler.scale();
+        let track_sequences = self.config.training.document_loss_window > 0;
+        let fast_params = self.deep_state
+            .as_ref()
+            .map(|state| state.fast_params.clone())
+            .unwrap_or_default();
+        let compute = self.half_precision.as_mut().expect("half-precision step without a compute copy");
+        let output = compute.step(&self.model, &self.loss_fn, batch, fast_params, scale, track_sequences);
+
+        let loss = Tensor::<B, 1>::from_floats([output.loss], &device);
+        let sequence_losses = output.sequence_losses;
+        if self.config.training.nan_guard.enabled && !output.loss.is_finite() {
+            return TrainOutput { sequence_losses, ..TrainOutput::new(loss, self.step) };
+        }
+        self.loss_scaler.update(!output.finite);
+        if !output.finite {
+            warn!(
+                "Step {}: gradients overflow at loss scale {}, skipping the step (scale is now {})",
+                self.step, scale, self.loss_scaler.scale()
+            );
+            return TrainOutput { sequence_losses, ..TrainOutput::new(loss, self.step) };
+        }
+
+        let norms = self.apply_gradients(output.grads, output.level_grads);
+        TrainOutput { sequence_losses, ..TrainOutput::new(loss, self.step) }.with_norms(norms)
+    }
+
+    /// Optimizer step on the model weights and the deep optimizer's level
+    /// parameters; returns the global and per-module norms when metrics are logged
+    fn apply_gradients(
+        &mut self,
+        grads: GradientsParams,
+        level_grads: Vec<Tensor<B::InnerBackend, 3>>,
+    ) -> Option<(f32, Vec<ModuleNorm>)> {
+        let training = &self.config.training;
+        let grads = if training.freeze.is_empty() {
+            grads
+        } else {
+            freeze_gradients(&self.model, grads, &training.freeze)
+        };
+        let norms = (training.log_dir.is_some() || training.metrics_format.is_some())
+            .then(|| module_norms(&self.model, &grads));
+
+        // Optimizer step; the clone shares the parameter tensors, the step
+        // returns the updated model
+        let lr = f64::from(self.learning_rate);
+        let model = self.model.clone();
+        self.model = if training.lr_multipliers.is_empty() && training.optimizer_params.layer_decay.is_none() {
+            self.optimizer.step(lr, model, grads)
+        } else {
+            // One step per parameter group; frozen groups (multiplier 0) are skipped
+            let num_levels = self.config.model.num_levels;
+            group_gradients(&model, grads, &|path| parameter_lr_multiplier(training, num_levels, path))
+                .into_iter()
+                .filter(|(multiplier, _)| *multiplier > 0.0)
+                .fold(model, |model, (multiplier, grads)| {
+                    self.optimizer.step(lr * f64::from(multiplier), model, grads)
+                })
+        };
+
+        // Deep optimizer: fast params follow their gradients, slow params
+        // follow the fast EMA and are synced every `sync_interval` steps
+        if let (Some(deep), Some(state)) = (&self.deep_optimizer, &mut self.deep_state) {
+            let lr = self.learning_rate;
+            deep.update_fast_params(state, &level_grads, lr);
+            deep.update_slow_params(state, lr);
+            if deep.should_sync(state) {
+                deep.sync(state);
+            }
+        }
+
+        norms
+    }
+
+    /// Exclude targets equal to `pad_id` from the loss (for left-padded batches)
+    pub fn with_pad_token(mut self, pad_id: i64, device: &<B as Backend>::Device) -> Self {
+        let smoothing = self.config.training.label_smoothing;
+        self.loss_fn.cross_entropy = CrossEntropyLossConfig::new()
+            .with_smoothing((smoothing > 0.0).then_some(smoothing))
+            .with_pad_tokens(Some(vec![pad_id as usize]))
+            .init(device);
+        self.loss_fn.pad_id = Some(pad_id);
+        self
+    }
+
+    /// Weight the loss of every target position by `weights`
+    pub fn with_loss_weights(mut self, weights: TokenLossWeights) -> Self {
+        self.loss_fn.weights = Some(weights);
+        self
+    }
+
+    /// Carry of the previous batch if this batch continues its streams:
+    /// every row starts right where the same row of the previous batch ended
+    fn continued_carry(&mut self, offsets: &[usize]) -> Option<StreamCarry<B>> {
+        let stream = self.carry.take()?;
+        let stateful = &self.config.training.stateful;
+        let seq_len = self.config.model.seq_len;
+
+        let continues = !offsets.is_empty()
+            && offsets.len() == stream.offsets.len()
+            && offsets.iter().zip(&stream.offsets).all(|(&new, &old)| new == old + seq_len);
+        let expired = stateful.reset_every > 0 && stream.batches >= stateful.reset_every;
+
+        (continues && !expired).then_some(stream)
     }
 
     pub fn model(&self) -> &HopeModel<B> {
         &self.model
     }
+
+    pub fn optimizer(&self) -> &TrainingOptimizer<B> {
+        &self.optimizer
+    }
+
+    pub fn deep_optimizer_state(&self) -> Option<&DeepOptimizerState<B::InnerBackend>> {
+        self.deep_state.as_ref()
+    }
+
+    /// Continuum memory accumulated by stateful training, averaged over the
+    /// batch rows (each row's stream builds its own); saved with checkpoints
+    pub fn memory_state(&self) -> Option<ContinuumMemoryState<B::InnerBackend>> {
+        let memory = self.carry.as_ref()?.carry.continuum_memory.as_ref()?;
+        let mean = |bank: &Tensor<B, 3>| bank.clone().inner().mean_dim(0);
+        Some(ContinuumMemoryState {
+            ultra_short: mean(&memory.ultra_short),
+            short: mean(&memory.short),
+            mid: mean(&memory.mid),
+            long: mean(&memory.long),
+            episodic: mean(&memory.episodic),
+        })
+    }
+
+    pub fn config(&self) -> &TrainConfig {
+        &self.config
+    }
+
+    pub fn learning_rate(&self) -> f32 {
+        self.learning_rate
+    }
+
+    /// Use `learning_rate` from the next step on (set by the LR schedule)
+    pub fn set_learning_rate(&mut self, learning_rate: f32) {
+        self.learning_rate = learning_rate;
+    }
+
+    /// Steps taken, including those before the resumed checkpoint
+    pub fn step(&self) -> usize {
+        self.step
+    }
+
+    /// Forward stage times of the steps so far (disabled unless
+    /// `training.profile`)
+    pub fn profile(&self) -> &StageTimes {
+        &self.profile
+    }
+
+    /// Continue aggregating into `profile`, e.g. after a rollback
+    pub fn with_profile(mut self, profile: StageTimes) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    /// Callbacks, for the hooks raised outside `train_step` (checkpoints,
+    /// evaluation, errors) and to check whether one asked to stop
+    pub fn callbacks_mut(&mut self) -> &mut Callbacks {
+        &mut self.callbacks
+    }
+
+    /// Remove the callbacks, e.g. to move them to a new trainer
+    pub fn take_callbacks(&mut self) -> Callbacks {
+        std::mem::take(&mut self.callbacks)
+    }
+
+    /// LR range test: one step at each learning rate of `sweep` until the
+    /// loss diverges, from fresh optimizer moments. The weights, optimizer
+    /// and deep optimizer state are put back afterwards, so training
+    /// continues as if the sweep had not run.
+    pub fn find_lr(
+        &mut self,
+        sweep: &LrSweep,
+        mut next_batch: impl FnMut() -> Result<BatchData<B>>,
+    ) -> Result<Vec<LrPoint>> {
+        let model = self.model.clone();
+        let optimizer = std::mem::replace(&mut self.optimizer, build_optimizer::<B>(&self.config.training));
+        let deep_state = self.deep_state.clone();
+        let loss_scaler = self.loss_scaler.clone();
+        let carry = self.carry.take();
+        let learning_rate = self.learning_rate;
+        // The sweep steps are not training steps
+        let step = self.step;
+        let callbacks = self.take_callbacks();
+
+        let mut recorder = LrRecorder::new();
+        let mut sweep_steps = || -> Result<()> {
+            for step in 0..sweep.steps {
+                let batch = next_batch()?;
+                self.learning_rate = sweep.learning_rate(step);
+                let loss: f32 = self.train_step(batch).loss.into_scalar().elem();
+                if !recorder.record(self.learning_rate, loss) {
+                    break;
+                }
+            }
+            Ok(())
+        };
+        let result = sweep_steps();
+
+        self.model = model;
+        self.optimizer = optimizer;
+        self.deep_state = deep_state;
+        self.loss_scaler = loss_scaler;
+        self.carry = carry;
+        self.learning_rate = learning_rate;
+        self.step = step;
+        self.callbacks = callbacks;
+        result.map(|_| recorder.into_points())
+    }
+
+    /// Restore optimizer state saved alongside a checkpoint, so Adam moments
+    /// (etc.) carry over instead of restarting from zero
+    pub fn restore_optimizer(
+        &mut self,
+        checkpoint_path: &Path,
+        device: &<B as Backend>::Device,
+    ) -> Result<()> {
+        let fresh = build_optimizer::<B>(&self.config.training);
+        let optimizer = std::mem::replace(&mut self.optimizer, fresh);
+        if let Some(optimizer) = load_optimizer_state(checkpoint_path, optimizer, device)? {
+            self.optimizer = optimizer;
+        }
+
+        if let Some(ref deep) = self.deep_optimizer {
+            let fresh = fresh_deep_state::<B::InnerBackend>(deep, &self.config, device);
+            match load_deep_optimizer_state::<B::InnerBackend>(checkpoint_path, device)? {
+                Some(state) if same_shapes(&state, &fresh) => {
+                    self.deep_state = Some(state);
+                    info!("Deep optimizer state restored");
+                }
+                Some(_) => warn!("Checkpoint deep optimizer state has different shapes; starting fresh"),
+                None => warn!("Checkpoint has no deep optimizer state; starting fresh"),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Deep optimizer state shared by every batch (`[1, seq_len, hidden]` per level)
+fn fresh_deep_state<B: Backend>(
+    deep: &DeepOptimizer,
+    config: &TrainConfig,
+    device: &B::Device,
+) -> DeepOptimizerState<B> {
+    deep.init_state(
+        config.model.num_levels,
+        1,
+        config.model.seq_len,
+        config.model.hidden_size,
+        device,
+    )
+}
+
+fn same_shapes<B: Backend>(a: &DeepOptimizerState<B>, b: &DeepOptimizerState<B>) -> bool {
+    a.fast_params.len() == b.fast_params.len()
+        && a.fast_params.iter().zip(&b.fast_params).all(|(x, y)| x.dims() == y.dims())
+}
+
+/// Gradients of the level biases
+pub(super) fn level_gradients<B: AutodiffBackend>(
+    level_biases: &[Tensor<B, 3>],
+    grads: &B::Gradients,
+) -> Vec<Tensor<B::InnerBackend, 3>> {
+    level_biases
+        .iter()
+        .filter_map(|bias| bias.grad(grads))
+        .collect()
+}
+
+/// Level biases cut to the batch's sequence length (token-budget batches
+/// can be shorter than `seq_len`); gradients still reach the full leaves
+pub(super) fn fit_level_biases<B: Backend>(level_biases: &[Tensor<B, 3>], seq_len: usize) -> Vec<Tensor<B, 3>> {
+    level_biases
+        .iter()
+        .map(|bias| {
+            let [rows, len, hidden] = bias.dims();
+            if len == seq_len {
+                bias.clone()
+            } else {
+                bias.clone().slice([0..rows, 0..seq_len, 0..hidden])
+            }
+        })
+        .collect()
+}
+
+/// Training loss: cross-entropy, or a weighted mean of per-token losses
+/// with `training.loss_weights` and/or `training.robust_loss`
+#[derive(Clone)]
+pub struct TokenLoss<B: Backend> {
+    cross_entropy: CrossEntropyLoss<B>,
+    weights: Option<TokenLossWeights>,
+    robust: RobustLossConfig,
+    smoothing: f32,
+    /// Targets excluded from the per-token loss (see `with_pad_token`)
+    pad_id: Option<i64>,
+}
+
+impl<B: Backend> TokenLoss<B> {
+    pub(crate) fn new(config: &TrainingConfig, device: &B::Device) -> Self {
+        config.robust_loss.validate();
+        let smoothing = config.label_smoothing;
+        Self {
+            cross_entropy: CrossEntropyLossConfig::new()
+                .with_smoothing((smoothing > 0.0).then_some(smoothing))
+                .init(device),
+            weights: None,
+            robust: config.robust_loss.clone(),
+            smoothing,
+            pad_id: None,
+        }
+    }
+
+    /// The same loss on backend `H` (the half-precision compute copy)
+    pub(crate) fn on_backend<H: Backend>(&self, device: &H::Device) -> TokenLoss<H> {
+        TokenLoss {
+            cross_entropy: CrossEntropyLossConfig::new()
+                .with_smoothing((self.smoothing > 0.0).then_some(self.smoothing))
+                .with_pad_tokens(self.pad_id.map(|pad_id| vec![pad_id as usize]))
+                .init(device),
+            weights: self.weights.clone(),
+            robust: self.robust.clone(),
+            smoothing: self.smoothing,
+            pad_id: self.pad_id,
+        }
+    }
+
+    /// Loss over the positions holding real tokens: all of them, or the
+    /// first `lengths[i]` of row i when the batch is padded
+    pub(crate) fn forward(&self, logits: Tensor<B, 3>, targets: Tensor<B, 2, Int>, lengths: &[usize]) -> Tensor<B, 1> {
+        if self.weights.is_some() || self.robust.kind != RobustLossKind::None {
+            return self.per_token_loss(logits, targets, lengths);
+        }
+
+        // Reshape for loss computation: [batch, seq_len, vocab_size] -> [batch * seq_len, vocab_size]
+        let [batch_size, seq_len, vocab_size] = logits.dims();
+        let logits_flat = logits.reshape([batch_size * seq_len, vocab_size]);
+        let targets_flat = targets.reshape([batch_size * seq_len]);
+
+        match real_positions::<B>(lengths, seq_len, &logits_flat.device()) {
+            Some(positions) => self.cross_entropy.forward(
+                logits_flat.select(0, positions.clone()),
+                targets_flat.select(0, positions),
+            ),
+            None => self.cross_entropy.forward(logits_flat, targets_flat),
+        }
+    }
+
+    fn per_token_loss(&self, logits: Tensor<B, 3>, targets: Tensor<B, 2, Int>, lengths: &[usize]) -> Tensor<B, 1> {
+        let [batch_size, seq_len] = targets.dims();
+        let target_ids = targets.clone().into_data().convert::<i64>().to_vec::<i64>().unwrap_or_default();
+        let mut position_weights = match self.weights {
+            Some(ref weights) => weights.weights(&target_ids, seq_len, lengths),
+            None => length_mask(batch_size, seq_len, lengths),
+        };
+        if let Some(pad_id) = self.pad_id {
+            for (weight, &id) in position_weights.iter_mut().zip(&target_ids) {
+                if id == pad_id {
+                    *weight = 0.0;
+                }
+            }
+        }
+
+        let losses = robust_token_losses(token_losses(logits, targets, self.smoothing), &self.robust);
+        weighted_mean(losses, &position_weights)
+    }
+}
+
+/// Flat `[batch * seq_len]` indices of the real tokens of a padded batch
+/// (`None` when `lengths` is empty and every position is real)
+pub(crate) fn real_positions<B: Backend>(
+    lengths: &[usize],
+    seq_len: usize,
+    device: &B::Device,
+) -> Option<Tensor<B, 1, Int>> {
+    if lengths.is_empty() {
+        return None;
+    }
+    let positions: Vec<i64> = lengths
+        .iter()
+        .enumerate()
+        .flat_map(|(row, &len)| (0..len).map(move |pos| (row * seq_len + pos) as i64))
+        .collect();
+    Some(Tensor::from_ints(positions.as_slice(), device))
+}
+
+/// Number of positions `token_loss` averages over for a batch of `dims`
+fn loss_tokens(dims: [usize; 2], lengths: &[usize]) -> usize {
+    if lengths.is_empty() {
+        dims[0] * dims[1]
+    } else {
+        lengths.iter().sum()
+    }
+}
+
+/// One data-parallel replica's rows of the batch
+struct ReplicaShard<B: Backend> {
+    tokens: Tensor<B, 2, Int>,
+    targets: Tensor<B, 2, Int>,
+    /// Real tokens per row (empty when the rows are not padded)
+    lengths: Vec<usize>,
+}
+
+/// Result of one data-parallel replica's forward/backward pass
+struct ReplicaOutput<B: AutodiffBackend> {
+    /// Loss positions in the shard, its weight in the averaged gradient
+    tokens: usize,
+    loss: f32,
+    grads: GradientsParams,
+    level_grads: Vec<Tensor<B::InnerBackend, 3>>,
+    sequence_losses: Vec<f32>,
+}
+
+/// Forward/backward pass of a model replica on its shard of the batch
+fn replica_step<B: AutodiffBackend>(
+    model: HopeModel<B>,
+    loss_fn: &TokenLoss<B>,
+    shard: ReplicaShard<B>,
+    fast_params: Vec<Tensor<B::InnerBackend, 3>>,
+    track_sequences: bool,
+    device: &<B as Backend>::Device,
+) -> ReplicaOutput<B> {
+    let lengths = shard.lengths.as_slice();
+    let tokens = shard.tokens.to_device(device);
+    let targets = shard.targets.to_device(device);
+    let [rows, seq_len] = tokens.dims();
+
+    let level_biases: Vec<Tensor<B, 3>> = fast_params
+        .into_iter()
+        .map(|params| Tensor::from_inner(params.to_device(device)).require_grad())
+        .collect();
+    let mut carry = model.carry_with_len(rows, seq_len, device);
+    carry.level_biases = fit_level_biases(&level_biases, seq_len);
+
+    let (_, output) = model.forward(HopeInput { tokens }, carry);
+    let logits = output.logits;
+
+    let sequence_losses = if track_sequences {
+        sequence_losses(logits.clone().inner(), targets.clone().inner(), lengths)
+    } else {
+        Vec::new()
+    };
+
+    let loss = loss_fn.forward(logits, targets, lengths);
+    let raw_grads = loss.backward();
+
+    ReplicaOutput {
+        tokens: loss_tokens([rows, seq_len], lengths),
+        loss: loss.into_scalar().elem(),
+        level_grads: level_gradients(&level_biases, &raw_grads),
+        grads: GradientsParams::from_grads(raw_grads, &model),
+        sequence_losses,
+    }
+}
+
+/// Weighted sum of the replicas' gradients, moved to `device`
+struct AverageGradients<'a, D> {
+    replicas: Vec<(f32, GradientsParams)>,
+    device: &'a D,
+    averaged: GradientsParams,
+}
+
+impl<B: AutodiffBackend> ModuleVisitor<B> for AverageGradients<'_, <B as Backend>::Device> {
+    fn visit_float<const D: usize>(&mut self, param: &Param<Tensor<B, D>>) {
+        let mut sum: Option<Tensor<B::InnerBackend, D>> = None;
+        for (weight, grads) in &mut self.replicas {
+            if let Some(grad) = grads.remove::<B::InnerBackend, D>(param.id) {
+                let grad = grad.to_device(self.device) * *weight;
+                sum = Some(match sum {
+                    Some(sum) => sum + grad,
+                    None => grad,
+                });
+            }
+        }
+        if let Some(sum) = sum {
+            self.averaged.register::<B::InnerBackend, D>(param.id, sum);
+        }
+    }
+}
+
+fn is_finite<B: Backend>(loss: &Tensor<B, 1>) -> bool {
+    let loss: f32 = loss.clone().into_scalar().elem();
+    loss.is_finite()
+}
+
+/// Global L2 norm over all parameter gradients of `model`, and the
+/// gradient and weight norms of each submodule
+fn module_norms<B: AutodiffBackend, M: Module<B>>(model: &M, grads: &GradientsParams) -> (f32, Vec<ModuleNorm>) {
+    let mut visitor = ModuleNorms { grads, path: Vec::new(), sum_squares: Vec::new() };
+    model.visit(&mut visitor);
+
+    let total: f64 = visitor.sum_squares.iter().map(|(_, grad, _)| grad).sum();
+    let modules = visitor.sum_squares
+        .into_iter()
+        .map(|(module, grad, param)| ModuleNorm {
+            module,
+            grad_norm: grad.sqrt() as f32,
+            param_norm: param.sqrt() as f32,
+        })
+        .collect();
+    (total.sqrt() as f32, modules)
+}
+
+/// Submodule a parameter at `path` is reported under
+fn norm_group(path: &[String]) -> String {
+    match path.first().map(String::as_str) {
+        Some("level_encoders") => format!("level_{}", path.get(1).map_or("0", String::as_str)),
+        Some("token_embed" | "pos_embed") => "embeddings".to_string(),
+        Some(module) => module.to_string(),
+        None => "model".to_string(),
+    }
+}
+
+struct ModuleNorms<'a> {
+    grads: &'a GradientsParams,
+    path: Vec<String>,
+    /// Module, sum of squared gradients, sum of squared weights
+    sum_squares: Vec<(String, f64, f64)>,
+}
+
+impl<B: AutodiffBackend> ModuleVisitor<B> for ModuleNorms<'_> {
+    fn enter_module(&mut self, name: &str, _container_type: &str) {
+        self.path.push(name.to_string());
+    }
+
+    fn exit_module(&mut self, _name: &str, _container_type: &str) {
+        self.path.pop();
+    }
+
+    fn visit_float<const D: usize>(&mut self, param: &Param<Tensor<B, D>>) {
+        let grad: f32 = match self.grads.get::<B::InnerBackend, D>(param.id) {
+            Some(grad) => grad.powf_scalar(2.0).sum().into_scalar().elem(),
+            None => 0.0,
+        };
+        let weight: f32 = param.val().inner().powf_scalar(2.0).sum().into_scalar().elem();
+
+        let module = norm_group(&self.path);
+        let index = match self.sum_squares.iter().position(|(m, _, _)| *m == module) {
+            Some(index) => index,
+            None => {
+                self.sum_squares.push((module, 0.0, 0.0));
+                self.sum_squares.len() - 1
+            }
+        };
+        self.sum_squares[index].1 += f64::from(grad);
+        self.sum_squares[index].2 += f64::from(weight);
+    }
+}
+
+/// Mean cross-entropy of each sequence: logits `[batch, seq_len, vocab]`, targets `[batch, seq_len]`
+pub(super) fn sequence_losses<B: Backend>(logits: Tensor<B, 3>, targets: Tensor<B, 2, Int>, lengths: &[usize]) -> Vec<f32> {
+    let [batch_size, seq_len, _] = logits.dims();
+    let device = logits.device();
+    let log_probs = log_softmax(logits, 2);
+    let target_log_probs: Tensor<B, 2> = log_probs.gather(2, targets.unsqueeze_dim(2)).squeeze_dim(2);
+    if lengths.is_empty() {
+        return target_log_probs
+            .neg()
+            .mean_dim(1)
+            .into_data()
+            .convert::<f32>()
+            .to_vec::<f32>()
+            .unwrap_or_default();
+    }
+
+    // Padded batch: average over each row's real tokens only
+    let mask: Vec<f32> = lengths
+        .iter()
+        .flat_map(|&len| (0..seq_len).map(move |pos| if pos < len { 1.0 } else { 0.0 }))
+        .collect();
+    let mask = Tensor::<B, 1>::from_floats(mask.as_slice(), &device).reshape([batch_size, seq_len]);
+    let sums = (target_log_probs * mask)
+        .neg()
+        .sum_dim(1)
+        .into_data()
+        .convert::<f32>()
+        .to_vec::<f32>()
+        .unwrap_or_default();
+    sums.iter().zip(lengths).map(|(sum, &len)| sum / len.max(1) as f32).collect()
 }
 
 #[derive(Clone, Debug)]
 pub struct BatchData<B: Backend> {
     pub tokens: Tensor<B, 2, Int>,
     pub targets: Tensor<B, 2, Int>,
+    /// Token-stream offset of each sequence (empty for synthetic data)
+    pub offsets: Vec<usize>,
+    /// Real (unpadded) length of each sequence in batches packed to a token
+    /// budget; positions past it are padding excluded from the loss (empty =
+    /// every sequence fills the whole row)
+    pub lengths: Vec<usize>,
 }
 
 impl<B: Backend> BatchData<B> {
     pub fn new(tokens: Tensor<B, 2, Int>, targets: Tensor<B, 2, Int>) -> Self {
-        Self { tokens, targets }
+        Self { tokens, targets, offsets: Vec::new(), lengths: Vec::new() }
+    }
+
+    /// Number of real tokens in the batch
+    pub fn num_tokens(&self) -> usize {
+        loss_tokens(self.tokens.dims(), &self.lengths)
     }
 }
 
@@ -128,6 +1009,292 @@ pub fn generate_random_batch<B: Backend>(
     BatchData::new(tokens, targets)
 }
 
-w(tokens, targets)
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HopeConfig;
+    use burn::backend::Autodiff;
+    use burn_ndarray::NdArray;
+
+    type B = Autodiff<NdArray<f32>>;
 
+    #[test]
+    fn test_train_step_updates_deep_optimizer_state() {
+        let device = Default::default();
+        let config = TrainConfig::for_test(
+            HopeConfig { num_levels: 2, level_timescales: vec![1, 2], ..HopeConfig::tiny() },
+            r#"{"batch_size": 2, "learning_rate": 0.1}"#,
+        );
+        let model = HopeModel::<B>::new(config.model.clone(), &device);
+        let mut trainer = HopeTrainer::new(model, config, &device);
+
+        trainer.train_step(generate_random_batch(2, 4, 8, &device));
+
+        let state = trainer.deep_optimizer_state().unwrap();
+        assert_eq!(state.step_count, 1);
+        assert_eq!(state.fast_params[0].dims(), [1, 4, 16]);
+        for params in &state.fast_params {
+            let values = params.clone().into_data().to_vec::<f32>().unwrap();
+            assert!(values.iter().any(|v| *v != 0.0));
+        }
+    }
+
+    #[test]
+    fn test_grad_norm_reported_when_logging() {
+        let device = Default::default();
+        let config = TrainConfig::for_test(HopeConfig::tiny(), r#"{"batch_size": 2, "log_dir": "runs"}"#);
+        let model = HopeModel::<B>::new(config.model.clone(), &device);
+        let mut trainer = HopeTrainer::new(model, config, &device);
+
+        let output = trainer.train_step(generate_random_batch(2, 4, 8, &device));
+        let grad_norm = output.grad_norm.unwrap();
+        assert!(grad_norm.is_finite() && grad_norm > 0.0);
+
+        let modules: Vec<&str> = output.module_norms.iter().map(|m| m.module.as_str()).collect();
+        assert_eq!(modules, ["embeddings", "level_0", "continuum_memory", "self_modify", "head"]);
+        assert!(output.module_norms.iter().all(|m| m.param_norm > 0.0));
+        // Module norms add up to the global norm
+        let total: f32 = output.module_norms.iter().map(|m| m.grad_norm.powi(2)).sum();
+        assert!((total.sqrt() - grad_norm).abs() < 1e-3 * grad_norm);
+    }
+
+    /// Values of every float parameter, in `parameter_paths` order
+    struct ParamValues(Vec<Vec<f32>>);
+
+    impl ModuleVisitor<B> for ParamValues {
+        fn visit_float<const D: usize>(&mut self, param: &Param<Tensor<B, D>>) {
+            self.0.push(param.val().into_data().to_vec::<f32>().unwrap());
+        }
+    }
+
+    fn param_values(model: &HopeModel<B>) -> Vec<(String, Vec<f32>)> {
+        let mut values = ParamValues(Vec::new());
+        model.visit(&mut values);
+        parameter_paths(model).into_iter().zip(values.0).collect()
+    }
+
+    #[test]
+    fn test_lr_multipliers_group_parameters() {
+        let device = Default::default();
+        let config = TrainConfig::for_test(
+            HopeConfig::tiny(),
+            r#"{"batch_size": 2, "learning_rate": 0.01, "lr_multipliers": {"token_embed": 0.0, "head": 2.0}}"#,
+        );
+        let model = HopeModel::<B>::new(config.model.clone(), &device);
+        let before = param_values(&model);
+        let mut trainer = HopeTrainer::new(model, config, &device);
+        trainer.train_step(generate_random_batch(2, 4, 8, &device));
+        let after = param_values(trainer.model());
+
+        let moved = |prefix: &str| {
+            before.iter().zip(&after)
+                .filter(|((path, _), _)| path.starts_with(prefix))
+                .any(|((_, old), (_, new))| old != new)
+        };
+        // Frozen by a 0 multiplier; the rest still trains
+        assert!(!moved("token_embed."));
+        assert!(moved("head."));
+        assert!(moved("pos_embed."));
+    }
+
+    #[test]
+    fn test_frozen_modules_skip_optimizer_and_weight_decay() {
+        let device = Default::default();
+        let config = TrainConfig::for_test(
+            HopeConfig::tiny(),
+            r#"{"batch_size": 2, "learning_rate": 0.01, "optimizer": "adamw", "weight_decay": 0.1,
+                "freeze": ["token_embed", "level_encoders"]}"#,
+        );
+        let model = HopeModel::<B>::new(config.model.clone(), &device);
+        let before = param_values(&model);
+        let mut trainer = HopeTrainer::new(model, config, &device);
+        trainer.train_step(generate_random_batch(2, 4, 8, &device));
+        let after = param_values(trainer.model());
+
+        let moved = |prefix: &str| {
+            before.iter().zip(&after)
+                .filter(|((path, _), _)| path.starts_with(prefix))
+                .any(|((_, old), (_, new))| old != new)
+        };
+        assert!(!moved("token_embed."));
+        assert!(!moved("level_encoders."));
+        assert!(moved("head."));
+    }
+
+    #[test]
+    fn test_callbacks_see_numbered_steps_and_can_stop() {
+        use super::super::callbacks::CallbackAction;
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        struct StopAfter {
+            last_step: usize,
+            seen: Rc<RefCell<Vec<(usize, usize)>>>,
+        }
+
+        impl TrainerCallback for StopAfter {
+            fn on_step_start(&mut self, step: usize) {
+                self.seen.borrow_mut().push((step, 0));
+            }
+
+            fn on_step_end(&mut self, step: &StepEnd) -> CallbackAction {
+                assert!(step.loss.is_finite());
+                self.seen.borrow_mut().push((step.step, 1));
+                if step.step >= self.last_step { CallbackAction::Stop } else { CallbackAction::Continue }
+            }
+        }
+
+        let device = Default::default();
+        let config = TrainConfig::for_test(HopeConfig::tiny(), r#"{"batch_size": 2, "learning_rate": 0.01}"#);
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let model = HopeModel::<B>::new(config.model.clone(), &device);
+        let mut trainer = HopeTrainer::new(model, config, &device)
+            .with_start_step(10)
+            .with_callback(Box::new(StopAfter { last_step: 12, seen: seen.clone() }));
+
+        let output = trainer.train_step(generate_random_batch(2, 4, 8, &device));
+        assert_eq!(output.step, 11);
+        assert!(!trainer.callbacks_mut().stop_requested());
+        trainer.train_step(generate_random_batch(2, 4, 8, &device));
+        assert!(trainer.callbacks_mut().stop_requested());
+        assert_eq!(*seen.borrow(), [(11, 0), (11, 1), (12, 0), (12, 1)]);
+        assert_eq!(trainer.step(), 12);
+    }
+
+    #[test]
+    fn test_activation_checkpointing_matches_plain_training() {
+        use burn::backend::autodiff::checkpoint::strategy::BalancedCheckpointing;
+        use burn::module::Module;
+        use burn::record::{BinBytesRecorder, FullPrecisionSettings, Recorder};
+
+        type C = Autodiff<NdArray<f32>, BalancedCheckpointing>;
+
+        let device = Default::default();
+        let config = TrainConfig::for_test(
+            HopeConfig {
+                num_levels: 2,
+                level_timescales: vec![1, 2],
+                dropout: 0.0,
+                ..HopeConfig::tiny()
+            },
+            r#"{"batch_size": 2, "learning_rate": 0.1}"#,
+        );
+        let model = HopeModel::<B>::new(config.model.clone(), &device);
+        let recorder = BinBytesRecorder::<FullPrecisionSettings>::default();
+        let bytes = recorder.record(model.clone().into_record(), ()).unwrap();
+        let checkpointed = HopeModel::<C>::new(config.model.clone(), &device)
+            .load_record(recorder.load(bytes, &device).unwrap());
+
+        fn batch<Bk: Backend>(device: &Bk::Device) -> BatchData<Bk> {
+            BatchData::new(
+                Tensor::<Bk, 1, Int>::from_ints([1, 2, 3, 4, 5, 6, 7, 0], device).reshape([2, 4]),
+                Tensor::<Bk, 1, Int>::from_ints([2, 3, 4, 5, 6, 7, 0, 1], device).reshape([2, 4]),
+            )
+        }
+
+        let mut plain = HopeTrainer::new(model, config.clone(), &device);
+        let mut recomputed = HopeTrainer::new(checkpointed, config, &device);
+        let plain_losses: Vec<f32> = (0..2)
+            .map(|_| plain.train_step(batch(&device)).loss.into_scalar())
+            .collect();
+        let recomputed_losses: Vec<f32> = (0..2)
+            .map(|_| recomputed.train_step(batch(&device)).loss.into_scalar())
+            .collect();
+
+        // Same first loss, and the same update leads to the same second loss
+        for (a, b) in plain_losses.iter().zip(&recomputed_losses) {
+            assert!((a - b).abs() < 1e-5, "{} != {}", a, b);
+        }
+        assert_ne!(plain_losses[0], plain_losses[1]);
+    }
+
+    #[test]
+    fn test_data_parallel_matches_single_device() {
+        let device = Default::default();
+        let config = TrainConfig::for_test(
+            HopeConfig {
+                num_levels: 2,
+                level_timescales: vec![1, 2],
+                dropout: 0.0,
+                ..HopeConfig::tiny()
+            },
+            r#"{"batch_size": 4, "learning_rate": 0.1}"#,
+        );
+        let model = HopeModel::<B>::new(config.model.clone(), &device);
+
+        let mut single = HopeTrainer::new(model.clone(), config.clone(), &device);
+        let mut parallel = HopeTrainer::new(model, config, &device)
+            .with_data_parallel(vec![device; 2]);
+        let losses = |trainer: &mut HopeTrainer<B>| -> Vec<f32> {
+            (0..2)
+                .map(|_| trainer.train_step(generate_random_batch(4, 4, 8, &device)).loss.into_scalar())
+                .collect()
+        };
+
+        // Averaged shard gradients equal the full-batch gradient, up to the
+        // order the floats are summed in
+        for (a, b) in losses(&mut single).iter().zip(&losses(&mut parallel)) {
+            assert!((a - b).abs() <= 1e-4 * a.abs().max(b.abs()), "{} != {}", a, b);
+        }
+    }
+
+    #[test]
+    fn test_padding_is_excluded_from_loss() {
+        let device = Default::default();
+        let config = TrainConfig::for_test(
+            HopeConfig {
+                num_levels: 2,
+                level_timescales: vec![1, 2],
+                dropout: 0.0,
+                ..HopeConfig::tiny()
+            },
+            r#"{"batch_size": 2, "document_loss_window": 1}"#,
+        );
+        let model = HopeModel::<B>::new(config.model.clone(), &device);
+        // Rows of 3 and 1 real tokens (shorter than seq_len), padded with `pad`
+        let batch = |pad: i64| BatchData {
+            lengths: vec![3, 1],
+            ..BatchData::new(
+                Tensor::<B, 1, Int>::from_ints([1, 2, 3, 4, pad, pad], &device).reshape([2, 3]),
+                Tensor::<B, 1, Int>::from_ints([2, 3, 4, 5, pad, pad], &device).reshape([2, 3]),
+            )
+        };
+
+        let output = |pad: i64| HopeTrainer::new(model.clone(), config.clone(), &device).train_step(batch(pad));
+        let (zeros, sevens) = (output(0), output(7));
+        let (a, b): (f32, f32) = (zeros.loss.into_scalar(), sevens.loss.into_scalar());
+        assert!((a - b).abs() < 1e-5, "{} != {}", a, b);
+        for (a, b) in zeros.sequence_losses.iter().zip(&sevens.sequence_losses) {
+            assert!((a - b).abs() < 1e-5, "{} != {}", a, b);
+        }
+    }
+
+    #[test]
+    fn test_stateful_carry_follows_streams() {
+        let device = Default::default();
+        let config = TrainConfig::for_test(
+            HopeConfig::tiny(),
+            r#"{"stateful": {"enabled": true, "reset_every": 2}}"#,
+        );
+        let model = HopeModel::<B>::new(config.model.clone(), &device);
+        let mut trainer = HopeTrainer::new(model, config, &device);
+        let batch = |offsets: Vec<usize>| BatchData {
+            offsets,
+            ..generate_random_batch(2, 4, 8, &device)
+        };
+
+        assert!(trainer.memory_state().is_none());
+        trainer.train_step(batch(vec![0, 100]));
+        assert_eq!(trainer.carry.as_ref().unwrap().batches, 1);
+        trainer.train_step(batch(vec![4, 104]));
+        assert_eq!(trainer.carry.as_ref().unwrap().batches, 2);
+        // One memory for both streams
+        assert_eq!(trainer.memory_state().unwrap().episodic.dims(), [1, 4, 16]);
+        // reset_every reached
+        trainer.train_step(batch(vec![8, 108]));
+        assert_eq!(trainer.carry.as_ref().unwrap().batches, 1);
+        // Not a continuation
+        trainer.train_step(batch(vec![0, 100]));
+        assert_eq!(trainer.carry.as_ref().unwrap().batches, 1);
+    }
+}
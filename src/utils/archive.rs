@@ -0,0 +1,104 @@
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Whether `path` is an archive whose members `for_each_archive_entry` can
+/// read (`.zip`, `.tar.zst`, `.tzst`)
+pub fn is_archive(path: &Path) -> bool {
+    let name = path.file_name()
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    name.ends_with(".zip") || name.ends_with(".tar.zst") || name.ends_with(".tzst")
+}
+
+/// Call `f` with the path (relative to the archive root) and contents of
+/// every regular file in the archive at `path`, in archive order.
+///
+/// Members are read one at a time straight from the archive stream, so large
+/// collections never have to be unpacked to disk.
+pub fn for_each_archive_entry(
+    path: &Path,
+    mut f: impl FnMut(&Path, Vec<u8>) -> Result<()>,
+) -> Result<()> {
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open archive: {:?}", path))?;
+
+    if path.to_string_lossy().to_lowercase().ends_with(".zip") {
+        let mut archive = zip::ZipArchive::new(file)
+            .with_context(|| format!("Invalid zip archive: {:?}", path))?;
+        for index in 0..archive.len() {
+            let mut entry = archive.by_index(index)?;
+            // Skips directories and names escaping the archive root
+            let Some(name) = entry.enclosed_name().map(Path::to_path_buf) else {
+                continue;
+            };
+            if entry.is_dir() {
+                continue;
+            }
+            let mut bytes = Vec::with_capacity(entry.size() as usize);
+            entry.read_to_end(&mut bytes)
+                .with_context(|| format!("Failed to read {:?} from {:?}", name, path))?;
+            f(&name, bytes)?;
+        }
+    } else {
+        let decoder = zstd::Decoder::new(file)
+            .with_context(|| format!("Invalid zstd stream: {:?}", path))?;
+        let mut archive = tar::Archive::new(decoder);
+        for entry in archive.entries()? {
+            let mut entry = entry.with_context(|| format!("Invalid tar archive: {:?}", path))?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let name: PathBuf = entry.path()?.into_owned();
+            let mut bytes = Vec::with_capacity(entry.size() as usize);
+            entry.read_to_end(&mut bytes)
+                .with_context(|| format!("Failed to read {:?} from {:?}", name, path))?;
+            f(&name, bytes)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_reads_zip_and_tar_zst_members() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let zip_path = dir.path().join("books.zip");
+        let mut writer = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        writer.add_directory("shelf/", Default::default()).unwrap();
+        writer.start_file("shelf/a.txt", Default::default()).unwrap();
+        writer.write_all(b"first book").unwrap();
+        writer.finish().unwrap();
+
+        let tar_path = dir.path().join("books.tar.zst");
+        let encoder = zstd::Encoder::new(File::create(&tar_path).unwrap(), 0).unwrap();
+        let mut builder = tar::Builder::new(encoder);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(11);
+        header.set_mode(0o644);
+        builder.append_data(&mut header, "b.txt", &b"second book"[..]).unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+
+        for (path, expected_name, expected) in [
+            (&zip_path, "shelf/a.txt", "first book"),
+            (&tar_path, "b.txt", "second book"),
+        ] {
+            assert!(is_archive(path));
+            let mut entries = Vec::new();
+            for_each_archive_entry(path, |name, bytes| {
+                entries.push((name.to_path_buf(), String::from_utf8(bytes)?));
+                Ok(())
+            })
+            .unwrap();
+            assert_eq!(entries, vec![(PathBuf::from(expected_name), expected.to_string())]);
+        }
+        assert!(!is_archive(Path::new("notes.docx")));
+    }
+}
@@ -0,0 +1,143 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::LazyLock;
+
+/// Minimum number of units (pages or documents) before repetition is meaningful
+const MIN_UNITS: usize = 3;
+
+/// Digit runs, replaced during normalization; compiled once since it runs per line
+static RE_DIGITS: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\d+").unwrap());
+
+/// A line detected as boilerplate, with the number of units it appeared in
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RepeatedLine {
+    pub line: String,
+    pub occurrences: usize,
+}
+
+/// Summary of a boilerplate removal pass
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BoilerplateReport {
+    pub units: usize,
+    pub removed_lines: usize,
+    pub repeated_lines: Vec<RepeatedLine>,
+}
+
+/// Normalize a line for repetition matching: lowercase, collapse whitespace,
+/// and replace digits so running headers like "Page 12" / "Page 13" match
+pub fn normalize_line(line: &str) -> String {
+    let collapsed = line.split_whitespace().collect::<Vec<_>>().join(" ");
+    RE_DIGITS.replace_all(&collapsed.to_lowercase(), "#").to_string()
+}
+
+/// Find normalized lines that appear in at least `threshold` (fraction) of the units
+pub fn find_repeated_lines(units: &[String], threshold: f32) -> HashMap<String, usize> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+
+    if units.len() < MIN_UNITS {
+        return counts;
+    }
+
+    for unit in units {
+        // Count each line at most once per unit
+        let lines: HashSet<String> = unit
+            .lines()
+            .map(normalize_line)
+            .filter(|l| !l.is_empty())
+            .collect();
+
+        for line in lines {
+            *counts.entry(line).or_insert(0) += 1;
+        }
+    }
+
+    let min_occurrences = ((units.len() as f32 * threshold).ceil() as usize).max(MIN_UNITS);
+    counts.retain(|_, count| *count >= min_occurrences);
+    counts
+}
+
+/// Remove lines repeating across units above the frequency threshold
+pub fn remove_boilerplate(units: &[String], threshold: f32) -> (Vec<String>, BoilerplateReport) {
+    let repeated = find_repeated_lines(units, threshold);
+    let mut removed_lines = 0;
+
+    let cleaned = units
+        .iter()
+        .map(|unit| {
+            unit.lines()
+                .filter(|line| {
+                    let is_boilerplate = repeated.contains_key(&normalize_line(line));
+                    if is_boilerplate {
+                        removed_lines += 1;
+                    }
+                    !is_boilerplate
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .collect();
+
+    let mut repeated_lines: Vec<RepeatedLine> = repeated
+        .into_iter()
+        .map(|(line, occurrences)| RepeatedLine { line, occurrences })
+        .collect();
+    repeated_lines.sort_by(|a, b| b.occurrences.cmp(&a.occurrences).then(a.line.cmp(&b.line)));
+
+    let report = BoilerplateReport {
+        units: units.len(),
+        removed_lines,
+        repeated_lines,
+    };
+
+    (cleaned, report)
+}
+
+/// Most frequent words in a text (stop-word candidates), highest count first
+pub fn top_words(text: &str, n: usize) -> Vec<(String, usize)> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+
+    for word in text.split(|c: char| !c.is_alphanumeric()) {
+        if !word.is_empty() {
+            *counts.entry(word.to_lowercase()).or_insert(0) += 1;
+        }
+    }
+
+    let mut words: Vec<(String, usize)> = counts.into_iter().collect();
+    words.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    words.truncate(n);
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remove_repeated_headers() {
+        let pages: Vec<String> = ["alpha", "beta", "gamma", "delta"]
+            .iter()
+            .enumerate()
+            .map(|(i, word)| format!("ACME Press - Page {}\nUnique content about {}.\nAll rights reserved.", i + 1, word))
+            .collect();
+
+        let (cleaned, report) = remove_boilerplate(&pages, 0.5);
+        assert_eq!(report.removed_lines, 8);
+        assert!(cleaned.iter().all(|p| !p.contains("ACME Press")));
+        assert!(cleaned[0].contains("Unique content"));
+    }
+
+    #[test]
+    fn test_too_few_units_untouched() {
+        let pages = vec!["Header\nA".to_string(), "Header\nB".to_string()];
+        let (cleaned, report) = remove_boilerplate(&pages, 0.5);
+        assert_eq!(report.removed_lines, 0);
+        assert_eq!(cleaned, pages);
+    }
+
+    #[test]
+    fn test_top_words() {
+        let words = top_words("the cat and the dog and the bird", 2);
+        assert_eq!(words, vec![("the".to_string(), 3), ("and".to_string(), 2)]);
+    }
+}
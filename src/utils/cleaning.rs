@@ -0,0 +1,220 @@
+use anyhow::Result;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// A single named text-cleaning stage
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CleaningStage {
+    /// Collapse runs of spaces/tabs and excess blank lines (keeps paragraph breaks)
+    Whitespace,
+    /// Drop lines consisting only of a number
+    PageNumbers,
+    /// Drop standalone "Page N" / "Chapter N" header/footer lines
+    Headers,
+    /// Re-join words hyphenated across line breaks
+    Dehyphenation,
+    /// Normalize line endings, ligatures, non-breaking and zero-width characters
+    Unicode,
+}
+
+impl CleaningStage {
+    pub const ALL: [CleaningStage; 5] = [
+        CleaningStage::Unicode,
+        CleaningStage::Dehyphenation,
+        CleaningStage::PageNumbers,
+        CleaningStage::Headers,
+        CleaningStage::Whitespace,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CleaningStage::Whitespace => "whitespace",
+            CleaningStage::PageNumbers => "page_numbers",
+            CleaningStage::Headers => "headers",
+            CleaningStage::Dehyphenation => "dehyphenation",
+            CleaningStage::Unicode => "unicode",
+        }
+    }
+}
+
+impl fmt::Display for CleaningStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for CleaningStage {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let normalized = s.trim().to_lowercase().replace('-', "_");
+        CleaningStage::ALL
+            .into_iter()
+            .find(|stage| stage.name() == normalized)
+            .ok_or_else(|| anyhow::anyhow!(
+                "Unknown cleaning stage: {} (expected one of: whitespace, page_numbers, headers, dehyphenation, unicode)",
+                s
+            ))
+    }
+}
+
+/// Character counts before and after one stage
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StageReport {
+    pub stage: CleaningStage,
+    pub chars_before: usize,
+    pub chars_after: usize,
+}
+
+impl StageReport {
+    pub fn removed(&self) -> usize {
+        self.chars_before.saturating_sub(self.chars_after)
+    }
+}
+
+/// Per-stage statistics for a pipeline run (or several runs merged)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CleaningReport {
+    pub stages: Vec<StageReport>,
+}
+
+impl CleaningReport {
+    /// Accumulate another report, matching stages by position
+    pub fn merge(&mut self, other: &CleaningReport) {
+        if self.stages.is_empty() {
+            self.stages = other.stages.clone();
+            return;
+        }
+
+        for (mine, theirs) in self.stages.iter_mut().zip(&other.stages) {
+            mine.chars_before += theirs.chars_before;
+            mine.chars_after += theirs.chars_after;
+        }
+    }
+}
+
+/// Ordered sequence of cleaning stages
+#[derive(Debug, Clone)]
+pub struct CleaningPipeline {
+    stages: Vec<CleaningStage>,
+    re_spaces: Regex,
+    re_newlines: Regex,
+    re_page_nums: Regex,
+    re_headers: Regex,
+    re_hyphen: Regex,
+}
+
+impl Default for CleaningPipeline {
+    fn default() -> Self {
+        Self::new(CleaningStage::ALL.to_vec())
+    }
+}
+
+impl CleaningPipeline {
+    pub fn new(stages: Vec<CleaningStage>) -> Self {
+        Self {
+            stages,
+            re_spaces: Regex::new(r"[ \t]+").unwrap(),
+            re_newlines: Regex::new(r"\n{3,}").unwrap(),
+            re_page_nums: Regex::new(r"(?m)^[ \t]*\d+[ \t]*$").unwrap(),
+            re_headers: Regex::new(r"(?m)^[ \t]*(Page \d+|Chapter \d+)[ \t]*$").unwrap(),
+            re_hyphen: Regex::new(r"(\p{L})-[ \t]*\n[ \t]*(\p{Ll})").unwrap(),
+        }
+    }
+
+    pub fn stages(&self) -> &[CleaningStage] {
+        &self.stages
+    }
+
+    /// Run all stages in order, recording character counts around each one
+    pub fn run(&self, text: &str) -> (String, CleaningReport) {
+        let mut text = text.to_string();
+        let mut report = CleaningReport::default();
+
+        for &stage in &self.stages {
+            let chars_before = text.chars().count();
+            text = self.apply(stage, &text);
+            report.stages.push(StageReport {
+                stage,
+                chars_before,
+                chars_after: text.chars().count(),
+            });
+        }
+
+        (text.trim().to_string(), report)
+    }
+
+    fn apply(&self, stage: CleaningStage, text: &str) -> String {
+        match stage {
+            CleaningStage::Whitespace => {
+                let text = self.re_spaces.replace_all(text, " ");
+                let text = text
+                    .lines()
+                    .map(str::trim)
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                self.re_newlines.replace_all(&text, "\n\n").to_string()
+            }
+            CleaningStage::PageNumbers => self.re_page_nums.replace_all(text, "").to_string(),
+            CleaningStage::Headers => self.re_headers.replace_all(text, "").to_string(),
+            CleaningStage::Dehyphenation => self.re_hyphen.replace_all(text, "$1$2").to_string(),
+            CleaningStage::Unicode => normalize_unicode(text),
+        }
+    }
+}
+
+fn normalize_unicode(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+
+    for c in text.replace("\r\n", "\n").chars() {
+        match c {
+            '\r' => result.push('\n'),
+            '\u{00A0}' | '\u{2007}' | '\u{202F}' => result.push(' '),
+            // Soft hyphen, zero-width space/joiners, BOM
+            '\u{00AD}' | '\u{200B}' | '\u{200C}' | '\u{200D}' | '\u{FEFF}' => {}
+            '\u{FB00}' => result.push_str("ff"),
+            '\u{FB01}' => result.push_str("fi"),
+            '\u{FB02}' => result.push_str("fl"),
+            '\u{FB03}' => result.push_str("ffi"),
+            '\u{FB04}' => result.push_str("ffl"),
+            _ => result.push(c),
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_pipeline() {
+        let text = "The of\u{FB01}ce was quiet.\n\n\n\n42\nIt was a won-\nderful day.";
+        let (cleaned, report) = CleaningPipeline::default().run(text);
+
+        assert_eq!(cleaned, "The office was quiet.\n\nIt was a wonderful day.");
+        assert_eq!(report.stages.len(), CleaningStage::ALL.len());
+        // Ligature expansion adds a character, dehyphenation drops "-\n"
+        assert_eq!(report.stages[0].chars_after, report.stages[0].chars_before + 1);
+        assert_eq!(report.stages[1].removed(), 2);
+    }
+
+    #[test]
+    fn test_stage_order_and_parsing() {
+        let stages: Vec<CleaningStage> = "whitespace, page-numbers"
+            .split(',')
+            .map(|s| s.parse().unwrap())
+            .collect();
+        assert_eq!(stages, vec![CleaningStage::Whitespace, CleaningStage::PageNumbers]);
+        assert!("bogus".parse::<CleaningStage>().is_err());
+
+        // Without a later whitespace pass the removed page number leaves its blank line behind
+        let (cleaned, report) = CleaningPipeline::new(stages).run("a\n\n 7 \n\nb");
+        assert_eq!(cleaned, "a\n\n\n\nb");
+        assert_eq!(report.stages[1].removed(), 1);
+    }
+}
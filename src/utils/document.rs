@@ -0,0 +1,208 @@
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+use tracing::info;
+
+use super::docx_parser::{extract_text_from_docx, parse_docx_bytes};
+use super::epub_parser::{extract_text_from_epub, parse_epub_bytes};
+use super::html_parser::{extract_text_from_html, parse_html};
+use super::pdf_parser::{extract_structured_content, extract_text_from_pdf_bytes, split_sections, structured_document};
+
+/// File extensions understood by `parse_document`
+pub const SUPPORTED_EXTENSIONS: &[&str] = &["pdf", "epub", "txt", "md", "html", "htm", "docx"];
+
+/// A titled section of a document (chapter, heading block, ...)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section {
+    pub title: String,
+    pub content: String,
+    /// The title is a heading line of the source text (kept in plain-text
+    /// output), not a placeholder such as the file name
+    pub heading: bool,
+}
+
+impl Section {
+    pub fn new(title: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            content: content.into(),
+            heading: false,
+        }
+    }
+
+    /// Mark whether the title is a heading of the source text
+    pub fn with_heading(mut self, heading: bool) -> Self {
+        self.heading = heading;
+        self
+    }
+}
+
+/// Format-independent parsed document
+#[derive(Debug, Clone)]
+pub struct Document {
+    pub title: String,
+    pub author: String,
+    pub language: Option<String>,
+    pub sections: Vec<Section>,
+}
+
+impl Document {
+    /// Concatenated section contents, separated by blank lines
+    pub fn text(&self) -> String {
+        self.sections
+            .iter()
+            .map(|s| s.content.as_str())
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+}
+
+/// Check whether a path has a format `parse_document` can handle
+pub fn is_supported_document(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| SUPPORTED_EXTENSIONS.contains(&ext.to_string_lossy().to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+/// Parse any supported document format into a `Document`
+pub fn parse_document(path: &Path) -> Result<Document> {
+    let ext = path.extension()
+        .and_then(|s| s.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+
+    match ext.as_str() {
+        "pdf" => extract_structured_content(path),
+        "epub" => extract_text_from_epub(path),
+        "html" | "htm" => extract_text_from_html(path),
+        "docx" => extract_text_from_docx(path),
+        "txt" | "md" => {
+            info!("Reading plain text: {:?}", path);
+            let text = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read text file: {:?}", path))?;
+
+            Ok(Document {
+                title: file_title(path),
+                author: "Unknown".to_string(),
+                language: None,
+                sections: split_sections(&text),
+            })
+        }
+        _ => anyhow::bail!("Unsupported file format: {}", ext),
+    }
+}
+
+/// Parse the contents of a document that isn't on disk (e.g. an archive
+/// entry); `path` only provides the format and fallback title
+pub fn parse_document_bytes(path: &Path, bytes: &[u8]) -> Result<Document> {
+    let ext = path.extension()
+        .and_then(|s| s.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+
+    match ext.as_str() {
+        "pdf" => structured_document(extract_text_from_pdf_bytes(bytes)?, file_title(path)),
+        "epub" => parse_epub_bytes(bytes),
+        "html" | "htm" => Ok(parse_html(&String::from_utf8_lossy(bytes), &file_title(path))),
+        "docx" => parse_docx_bytes(bytes, &file_title(path)),
+        "txt" | "md" => Ok(Document {
+            title: file_title(path),
+            author: "Unknown".to_string(),
+            language: None,
+            sections: split_sections(&String::from_utf8_lossy(bytes)),
+        }),
+        _ => anyhow::bail!("Unsupported file format: {}", ext),
+    }
+}
+
+/// Fallback document title derived from the file name
+pub fn file_title(path: &Path) -> String {
+    path.file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "Unknown".to_string())
+}
+
+/// Longest sanitized stem kept in an output file name (before the hash suffix)
+const MAX_OUTPUT_STEM_CHARS: usize = 64;
+
+/// Device names Windows reserves regardless of extension
+const RESERVED_WINDOWS_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Output file stem for the document at `path` (without extension).
+///
+/// The file stem is sanitized so it is valid on every platform (characters
+/// Windows rejects, control characters and reserved device names are
+/// replaced, non-UTF-8 bytes are decoded lossily, long names are truncated)
+/// and suffixed with a hash of `path` relative to `root`, so documents with
+/// the same name in different directories or with different extensions
+/// don't overwrite each other.
+pub fn output_stem(path: &Path, root: &Path) -> String {
+    let mut stem: String = file_title(path)
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .take(MAX_OUTPUT_STEM_CHARS)
+        .collect();
+
+    // Windows strips trailing dots and spaces
+    let trimmed = stem.trim_end_matches(['.', ' ']).len();
+    stem.truncate(trimmed);
+    if stem.is_empty() {
+        stem.push_str("document");
+    }
+    let base = stem.split('.').next().unwrap_or_default();
+    if RESERVED_WINDOWS_NAMES.iter().any(|name| name.eq_ignore_ascii_case(base)) {
+        stem.insert(0, '_');
+    }
+
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    let digest = Sha256::digest(relative.to_string_lossy().replace('\\', "/").as_bytes());
+    let suffix: String = digest[..4].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}-{}", stem, suffix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::Builder;
+
+    #[test]
+    fn test_parse_plain_text_document() {
+        let mut file = Builder::new().suffix(".txt").tempfile().unwrap();
+        writeln!(file, "CHAPTER 1\nIt was a dark night.\nCHAPTER 2\nMorning came.").unwrap();
+
+        let document = parse_document(file.path()).unwrap();
+        assert_eq!(document.sections.len(), 2);
+        assert_eq!(document.sections[1].title, "CHAPTER 2");
+        assert_eq!(document.text(), "It was a dark night.\n\nMorning came.");
+    }
+
+    #[test]
+    fn test_supported_extensions() {
+        assert!(is_supported_document(Path::new("book.EPUB")));
+        assert!(is_supported_document(Path::new("notes.docx")));
+        assert!(!is_supported_document(Path::new("image.png")));
+    }
+
+    #[test]
+    fn test_output_stem_is_sanitized_and_unique() {
+        let root = Path::new("books");
+        let a = output_stem(Path::new("books/a/Moby: Dick?.epub"), root);
+        let b = output_stem(Path::new("books/b/Moby: Dick?.epub"), root);
+
+        assert!(a.starts_with("Moby_ Dick_-"));
+        assert_ne!(a, b);
+        assert_eq!(a, output_stem(Path::new("books/a/Moby: Dick?.epub"), root));
+        assert!(output_stem(Path::new("books/con.txt"), root).starts_with("_con-"));
+        assert!(output_stem(Path::new("books/trailing. .pdf"), root).starts_with("trailing-"));
+    }
+}
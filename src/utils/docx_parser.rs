@@ -0,0 +1,133 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::fs::File;
+use std::io::{Cursor, Read, Seek};
+use std::path::Path;
+use tracing::info;
+
+use super::document::{Document, Section, file_title};
+
+/// Extract text from a DOCX (Office Open XML) file
+pub fn extract_text_from_docx(path: &Path) -> Result<Document> {
+    info!("Extracting text from DOCX: {:?}", path);
+
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open DOCX file: {:?}", path))?;
+    let archive = zip::ZipArchive::new(file)
+        .with_context(|| format!("Invalid DOCX archive: {:?}", path))?;
+
+    parse_docx(archive, &file_title(path))
+        .with_context(|| format!("Failed to parse DOCX file: {:?}", path))
+}
+
+/// Extract text from DOCX bytes (e.g. a file inside an archive)
+pub fn parse_docx_bytes(bytes: &[u8], fallback_title: &str) -> Result<Document> {
+    let archive = zip::ZipArchive::new(Cursor::new(bytes)).context("Invalid DOCX archive")?;
+    parse_docx(archive, fallback_title)
+}
+
+fn parse_docx<R: Read + Seek>(mut archive: zip::ZipArchive<R>, fallback_title: &str) -> Result<Document> {
+    let document_xml = read_entry(&mut archive, "word/document.xml")
+        .context("DOCX has no word/document.xml")?;
+    // Core properties are optional
+    let core_xml = read_entry(&mut archive, "docProps/core.xml").unwrap_or_default();
+
+    let mut document = parse_document_xml(&document_xml, fallback_title);
+
+    let re_title = Regex::new(r"(?s)<dc:title>(.*?)</dc:title>").unwrap();
+    let re_creator = Regex::new(r"(?s)<dc:creator>(.*?)</dc:creator>").unwrap();
+    let re_language = Regex::new(r"(?s)<dc:language>(.*?)</dc:language>").unwrap();
+
+    if let Some(caps) = re_title.captures(&core_xml) {
+        if !caps[1].trim().is_empty() {
+            document.title = unescape_xml(caps[1].trim());
+        }
+    }
+    if let Some(caps) = re_creator.captures(&core_xml) {
+        document.author = unescape_xml(caps[1].trim());
+    }
+    document.language = re_language.captures(&core_xml).map(|c| c[1].trim().to_string());
+
+    info!("Extracted {} sections from DOCX", document.sections.len());
+
+    Ok(document)
+}
+
+fn read_entry<R: Read + Seek>(archive: &mut zip::ZipArchive<R>, name: &str) -> Result<String> {
+    let mut entry = archive.by_name(name)?;
+    let mut xml = String::new();
+    entry.read_to_string(&mut xml)?;
+    Ok(xml)
+}
+
+/// Parse `word/document.xml`, starting a new section at every heading/title paragraph
+fn parse_document_xml(xml: &str, fallback_title: &str) -> Document {
+    let re_paragraph = Regex::new(r"(?s)<w:p[ >].*?</w:p>").unwrap();
+    let re_text = Regex::new(r"(?s)<w:t(?:\s[^>]*)?>(.*?)</w:t>").unwrap();
+    let re_heading_style = Regex::new(r#"<w:pStyle w:val="(?:Heading\d*|Title)""#).unwrap();
+
+    let mut sections = Vec::new();
+    let mut current_title = fallback_title.to_string();
+    let mut title_is_heading = false;
+    let mut current_content = String::new();
+
+    for paragraph in re_paragraph.find_iter(xml) {
+        let paragraph = paragraph.as_str();
+        let text: String = re_text
+            .captures_iter(paragraph)
+            .map(|c| unescape_xml(&c[1]))
+            .collect();
+        let text = text.trim();
+
+        if text.is_empty() {
+            continue;
+        }
+
+        if re_heading_style.is_match(paragraph) {
+            if !current_content.is_empty() || title_is_heading {
+                sections.push(Section::new(current_title.clone(), current_content.trim()).with_heading(title_is_heading));
+                current_content.clear();
+            }
+            current_title = text.to_string();
+            title_is_heading = true;
+        } else {
+            current_content.push_str(text);
+            current_content.push_str("\n\n");
+        }
+    }
+
+    if !current_content.is_empty() || title_is_heading {
+        sections.push(Section::new(current_title, current_content.trim()).with_heading(title_is_heading));
+    }
+
+    Document {
+        title: fallback_title.to_string(),
+        author: "Unknown".to_string(),
+        language: None,
+        sections,
+    }
+}
+
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_document_xml() {
+        let xml = r#"<w:body>
+            <w:p><w:pPr><w:pStyle w:val="Heading1"/></w:pPr><w:r><w:t>Intro</w:t></w:r></w:p>
+            <w:p><w:r><w:t xml:space="preserve">Fish &amp; </w:t></w:r><w:r><w:t>chips</w:t></w:r></w:p>
+        </w:body>"#;
+        let document = parse_document_xml(xml, "notes");
+
+        assert_eq!(document.sections, vec![Section::new("Intro", "Fish & chips").with_heading(true)]);
+    }
+}
@@ -0,0 +1,323 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+use crate::data::content_hash;
+
+/// Name of the manifest kept next to the downloaded files
+pub const DOWNLOAD_MANIFEST_FILE: &str = "manifest.json";
+
+/// Limits for `download_all`
+#[derive(Debug, Clone)]
+pub struct DownloadConfig {
+    /// Parallel downloads
+    pub concurrency: usize,
+    /// Retries after the first attempt, for transport errors, 429 and 5xx
+    pub max_retries: usize,
+    /// Delay before the first retry; doubles on every further retry
+    pub initial_backoff: Duration,
+    /// Overall request rate limit across all workers (None = unlimited)
+    pub requests_per_sec: Option<f64>,
+    pub timeout: Duration,
+}
+
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: 4,
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(500),
+            requests_per_sec: None,
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl DownloadConfig {
+    pub fn validate(&self) {
+        assert!(self.concurrency > 0, "concurrency must be > 0");
+        if let Some(rate) = self.requests_per_sec {
+            assert!(rate > 0.0, "requests_per_sec must be > 0");
+        }
+    }
+}
+
+/// A fetched URL; `etag`/`last_modified` make the next run a conditional request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub file: String,
+    pub sha256: String,
+    pub bytes: u64,
+    #[serde(default)]
+    pub content_type: Option<String>,
+    #[serde(default)]
+    pub etag: Option<String>,
+    #[serde(default)]
+    pub last_modified: Option<String>,
+    pub fetched_at: u64,
+}
+
+/// Record of every URL fetched into a download directory
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DownloadManifest {
+    pub entries: BTreeMap<String, ManifestEntry>,
+}
+
+impl DownloadManifest {
+    pub fn load(dir: &Path) -> Result<Self> {
+        let path = dir.join(DOWNLOAD_MANIFEST_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let json = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read download manifest: {:?}", path))?;
+        serde_json::from_str(&json).with_context(|| format!("Invalid download manifest: {:?}", path))
+    }
+
+    /// Write via a temporary file so an interrupted run never leaves a truncated manifest
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let path = dir.join(DOWNLOAD_MANIFEST_FILE);
+        let tmp = dir.join(format!("{}.tmp", DOWNLOAD_MANIFEST_FILE));
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write download manifest: {:?}", tmp))?;
+        fs::rename(&tmp, &path)
+            .with_context(|| format!("Failed to write download manifest: {:?}", path))
+    }
+}
+
+/// Result of one URL in `download_all`
+#[derive(Debug, Clone)]
+pub enum DownloadOutcome {
+    /// New or changed content was written to `path`
+    Downloaded(PathBuf),
+    /// The server reported no change since the manifest entry (or it was fetched earlier)
+    Unchanged(PathBuf),
+    Failed(String),
+}
+
+/// Download `urls` into `dir` with bounded concurrency, a global rate limit
+/// and retries with exponential backoff. Each finished download is recorded
+/// in `dir/manifest.json` immediately, so an interrupted run resumes where it
+/// stopped; URLs in the manifest are revalidated with their ETag /
+/// Last-Modified and only re-downloaded when changed.
+pub fn download_all(
+    urls: &[String],
+    dir: &Path,
+    config: &DownloadConfig,
+) -> Result<Vec<(String, DownloadOutcome)>> {
+    config.validate();
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create download directory: {:?}", dir))?;
+
+    let agent = ureq::AgentBuilder::new()
+        .timeout(config.timeout)
+        .user_agent(concat!("hope-model/", env!("CARGO_PKG_VERSION")))
+        .build();
+    let manifest = Mutex::new(DownloadManifest::load(dir)?);
+    let queue: Mutex<VecDeque<String>> = Mutex::new(urls.iter().cloned().collect());
+    let limiter = RateLimiter::new(config.requests_per_sec);
+    let results = Mutex::new(Vec::new());
+
+    thread::scope(|scope| {
+        for _ in 0..config.concurrency.min(urls.len().max(1)) {
+            scope.spawn(|| loop {
+                let Some(url) = queue.lock().unwrap().pop_front() else {
+                    break;
+                };
+                let previous = manifest.lock().unwrap().entries.get(&url).cloned();
+                let outcome = match fetch_with_retries(&agent, &url, previous.as_ref(), dir, config, &limiter) {
+                    Ok(Some(entry)) => {
+                        let path = dir.join(&entry.file);
+                        let mut manifest = manifest.lock().unwrap();
+                        manifest.entries.insert(url.clone(), entry);
+                        if let Err(e) = manifest.save(dir) {
+                            warn!("{}", e);
+                        }
+                        DownloadOutcome::Downloaded(path)
+                    }
+                    Ok(None) => DownloadOutcome::Unchanged(dir.join(&previous.unwrap().file)),
+                    Err(e) => {
+                        warn!("Failed to download {}: {}", url, e);
+                        DownloadOutcome::Failed(e.to_string())
+                    }
+                };
+                results.lock().unwrap().push((url, outcome));
+            });
+        }
+    });
+
+    // Report in input order
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(url, _)| urls.iter().position(|u| u == url));
+    Ok(results)
+}
+
+/// Fetch one URL; `Ok(None)` means the stored copy is still current
+fn fetch_with_retries(
+    agent: &ureq::Agent,
+    url: &str,
+    previous: Option<&ManifestEntry>,
+    dir: &Path,
+    config: &DownloadConfig,
+    limiter: &RateLimiter,
+) -> Result<Option<ManifestEntry>> {
+    // Only revalidate if the stored file is still there
+    let previous = previous.filter(|entry| dir.join(&entry.file).exists());
+    let mut backoff = config.initial_backoff;
+
+    for attempt in 0..=config.max_retries {
+        limiter.wait();
+        let mut request = agent.get(url);
+        if let Some(entry) = previous {
+            if let Some(ref etag) = entry.etag {
+                request = request.set("If-None-Match", etag);
+            }
+            if let Some(ref last_modified) = entry.last_modified {
+                request = request.set("If-Modified-Since", last_modified);
+            }
+        }
+
+        let retryable = match request.call() {
+            Ok(response) if response.status() == 304 && previous.is_some() => return Ok(None),
+            Ok(response) => return save_response(url, response, dir).map(Some),
+            Err(ureq::Error::Status(code, _)) if code == 429 || code >= 500 => format!("status code {}", code),
+            Err(ureq::Error::Status(code, _)) => anyhow::bail!("status code {}", code),
+            Err(ureq::Error::Transport(e)) => e.to_string(),
+        };
+
+        if attempt < config.max_retries {
+            warn!("{} ({}), retrying in {:?}", url, retryable, backoff);
+            thread::sleep(backoff);
+            backoff *= 2;
+        } else {
+            anyhow::bail!("{} after {} attempt(s)", retryable, attempt + 1);
+        }
+    }
+    unreachable!()
+}
+
+fn save_response(url: &str, response: ureq::Response, dir: &Path) -> Result<ManifestEntry> {
+    let etag = response.header("ETag").map(String::from);
+    let last_modified = response.header("Last-Modified").map(String::from);
+    let content_type = Some(response.content_type().to_string()).filter(|t| !t.is_empty());
+
+    let mut body = Vec::new();
+    response
+        .into_reader()
+        .read_to_end(&mut body)
+        .with_context(|| format!("Failed to read response body of {}", url))?;
+
+    let file = download_file_name(url);
+    let path = dir.join(&file);
+    fs::write(&path, &body).with_context(|| format!("Failed to write download: {:?}", path))?;
+    info!("Downloaded {} ({} bytes)", url, body.len());
+
+    Ok(ManifestEntry {
+        file,
+        sha256: content_hash(&body),
+        bytes: body.len() as u64,
+        content_type,
+        etag,
+        last_modified,
+        fetched_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+    })
+}
+
+/// Stable local name for a URL: hash plus the URL's extension, if any
+fn download_file_name(url: &str) -> String {
+    let hash = &content_hash(url.as_bytes())[..16];
+    let path = url.split(['?', '#']).next().unwrap_or("");
+    let extension = path
+        .rsplit('/')
+        .next()
+        .and_then(|name| name.rsplit_once('.'))
+        .map(|(_, ext)| ext.to_lowercase())
+        .filter(|ext| !ext.is_empty() && ext.len() <= 5 && ext.chars().all(|c| c.is_ascii_alphanumeric()));
+    match extension {
+        Some(ext) => format!("{}.{}", hash, ext),
+        None => hash.to_string(),
+    }
+}
+
+/// Spaces requests at least `1 / requests_per_sec` apart across threads
+struct RateLimiter {
+    interval: Option<Duration>,
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(requests_per_sec: Option<f64>) -> Self {
+        Self {
+            interval: requests_per_sec.map(|rate| Duration::from_secs_f64(1.0 / rate)),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    fn wait(&self) {
+        let Some(interval) = self.interval else {
+            return;
+        };
+        let slot = {
+            let mut next = self.next.lock().unwrap();
+            let slot = (*next).max(Instant::now());
+            *next = slot + interval;
+            slot
+        };
+        thread::sleep(slot.saturating_duration_since(Instant::now()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_download_file_name_keeps_extension() {
+        let name = download_file_name("https://example.com/books/a.PDF?download=1");
+        assert!(name.ends_with(".pdf"));
+        assert_eq!(name.len(), 16 + 4);
+        assert_eq!(download_file_name("https://example.com/post/").len(), 16);
+    }
+
+    #[test]
+    fn test_manifest_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        assert!(DownloadManifest::load(temp_dir.path()).unwrap().entries.is_empty());
+
+        let mut manifest = DownloadManifest::default();
+        manifest.entries.insert("https://example.com/a".to_string(), ManifestEntry {
+            file: "abc".to_string(),
+            sha256: "00".to_string(),
+            bytes: 3,
+            content_type: Some("text/html".to_string()),
+            etag: Some("\"v1\"".to_string()),
+            last_modified: None,
+            fetched_at: 1,
+        });
+        manifest.save(temp_dir.path()).unwrap();
+
+        let loaded = DownloadManifest::load(temp_dir.path()).unwrap();
+        assert_eq!(loaded.entries["https://example.com/a"].etag.as_deref(), Some("\"v1\""));
+    }
+
+    #[test]
+    fn test_rate_limiter_spaces_requests() {
+        let limiter = RateLimiter::new(Some(100.0));
+        let start = Instant::now();
+        for _ in 0..4 {
+            limiter.wait();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(30));
+    }
+}
@@ -1,26 +1,32 @@
 use anyhow::{Context, Result};
 use epub::doc::EpubDoc;
+use std::io::{Cursor, Read, Seek};
 use std::path::Path;
 use tracing::info;
 
-/// Structured content from EPUB
-#[derive(Debug, Clone)]
-pub struct EpubContent {
-    pub title: String,
-    pub author: String,
-    pub chapters: Vec<(String, String)>,  // (title, content)
-}
+use super::document::{Document, Section};
 
 /// Extract text from an EPUB file
-pub fn extract_text_from_epub(path: &Path) -> Result<EpubContent> {
+pub fn extract_text_from_epub(path: &Path) -> Result<Document> {
     info!("Extracting text from EPUB: {:?}", path);
     
-    let mut doc = EpubDoc::new(path)
+    let doc = EpubDoc::new(path)
         .with_context(|| format!("Failed to open EPUB file: {:?}", path))?;
     
+    Ok(parse_epub(doc))
+}
+
+/// Extract text from EPUB bytes (e.g. a file inside an archive)
+pub fn parse_epub_bytes(bytes: &[u8]) -> Result<Document> {
+    let doc = EpubDoc::from_reader(Cursor::new(bytes.to_vec())).context("Failed to open EPUB data")?;
+    Ok(parse_epub(doc))
+}
+
+fn parse_epub<R: Read + Seek>(mut doc: EpubDoc<R>) -> Document {
     // Get metadata
-    let title = doc.mdata("title").unwrap_or_else(|| "Unknown".to_string());
-    let author = doc.mdata("creator").unwrap_or_else(|| "Unknown".to_string());
+    let title = doc.mdata("title").map(|m| m.value.clone()).unwrap_or_else(|| "Unknown".to_string());
+    let author = doc.mdata("creator").map(|m| m.value.clone()).unwrap_or_else(|| "Unknown".to_string());
+    let language = doc.mdata("language").map(|m| m.value.clone());
     
     info!("EPUB: {} by {}", title, author);
     
@@ -31,7 +37,7 @@ pub fn extract_text_from_epub(path: &Path) -> Result<EpubContent> {
     let spine_len = doc.spine.len();
     
     for i in 0..spine_len {
-        doc.set_current_page(i);
+        doc.set_current_chapter(i);
         
         if let Some((content_bytes, _mime)) = doc.get_current_str() {
             // Parse HTML content
@@ -42,46 +48,50 @@ pub fn extract_text_from_epub(path: &Path) -> Result<EpubContent> {
                 let chapter_title = extract_chapter_title(&content)
                     .unwrap_or_else(|| format!("Chapter {}", i + 1));
                 
-                chapters.push((chapter_title, content));
+                chapters.push(Section::new(chapter_title, content));
             }
         }
     }
     
     info!("Extracted {} chapters from EPUB", chapters.len());
     
-    Ok(EpubContent {
+    Document {
         title,
         author,
-        chapters,
-    })
+        language,
+        sections: chapters,
+    }
 }
 
 /// Strip HTML tags from text (simple implementation)
-fn strip_html_tags(html: &str) -> String {
+pub(crate) fn strip_html_tags(html: &str) -> String {
     let mut result = String::new();
     let mut in_tag = false;
-    let mut in_script_or_style = false;
-    
-    let mut chars = html.chars().peekable();
+    // ASCII lowercasing keeps byte offsets, so positions carry over to `html`
+    let lower = html.to_ascii_lowercase();
+    let mut skip_to = 0;
     
-    while let Some(ch) = chars.next() {
+    for (i, ch) in html.char_indices() {
+        if i < skip_to {
+            continue;
+        }
         if ch == '<' {
             in_tag = true;
             
-            // Check if this is a script or style tag
-            let remaining: String = chars.clone().take(10).collect();
-            if remaining.to_lowercase().starts_with("script") || 
-               remaining.to_lowercase().starts_with("style") {
-                in_script_or_style = true;
+            // Skip a script or style element up to its matching closing tag
+            for name in ["script", "style"] {
+                let is_open = lower[i + 1..]
+                    .strip_prefix(name)
+                    .is_some_and(|rest| !rest.starts_with(|c: char| c.is_ascii_alphanumeric()));
+                if is_open {
+                    let close = format!("</{}", name);
+                    skip_to = lower[i + 1..].find(&close).map_or(html.len(), |end| i + 1 + end);
+                    break;
+                }
             }
         } else if ch == '>' {
             in_tag = false;
-            
-            // Check if this closes script or style
-            if in_script_or_style {
-                in_script_or_style = false;
-            }
-        } else if !in_tag && !in_script_or_style {
+        } else if !in_tag {
             result.push(ch);
         }
     }
@@ -111,7 +121,7 @@ mod tests {
     fn test_strip_html_tags() {
         let html = "<p>Hello <b>World</b>!</p>";
         let text = strip_html_tags(html);
-        assert_eq!(text, "Hello World !");
+        assert_eq!(text, "Hello World!");
     }
     
     #[test]
@@ -122,5 +132,11 @@ mod tests {
         assert!(text.contains("More text"));
         assert!(!text.contains("alert"));
     }
+    
+    #[test]
+    fn test_strip_style_with_markup_inside() {
+        let html = "<style>p > b { color: red }</style><STYLE media=\"print\">a{}</STYLE><p>Body</p>";
+        assert_eq!(strip_html_tags(html), "Body");
+    }
 }
 
@@ -0,0 +1,114 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::fs;
+use std::path::Path;
+use tracing::info;
+
+use super::document::{Document, Section, file_title};
+use super::epub_parser::strip_html_tags;
+
+/// Extract text from an HTML file
+pub fn extract_text_from_html(path: &Path) -> Result<Document> {
+    info!("Extracting text from HTML: {:?}", path);
+
+    let html = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read HTML file: {:?}", path))?;
+
+    Ok(parse_html(&html, &file_title(path)))
+}
+
+/// Parse an HTML string, splitting sections at <h1>/<h2> headings
+pub fn parse_html(html: &str, fallback_title: &str) -> Document {
+    let re_title = Regex::new(r"(?is)<title[^>]*>(.*?)</title>").unwrap();
+    let re_lang = Regex::new(r#"(?i)<html[^>]*\slang\s*=\s*["']([^"']+)["']"#).unwrap();
+    let re_author = Regex::new(r#"(?i)<meta[^>]*name\s*=\s*["']author["'][^>]*content\s*=\s*["']([^"']*)["']"#).unwrap();
+    let re_heading = Regex::new(r"(?is)<h[12][^>]*>(.*?)</h[12]>").unwrap();
+    let re_script = Regex::new(r"(?is)<(script|style|noscript)\b.*?</(script|style|noscript)>").unwrap();
+    let re_comment = Regex::new(r"(?s)<!--.*?-->").unwrap();
+    let re_head_end = Regex::new(r"(?i)</head\s*>").unwrap();
+
+    let title = re_title.captures(html)
+        .map(|c| strip_html_tags(&c[1]))
+        .filter(|t| !t.is_empty())
+        .unwrap_or_else(|| fallback_title.to_string());
+    let language = re_lang.captures(html).map(|c| c[1].to_string());
+    let author = re_author.captures(html)
+        .map(|c| c[1].to_string())
+        .unwrap_or_else(|| "Unknown".to_string());
+
+    // Drop the <head> so the page title doesn't leak into the body text
+    let body = match re_head_end.find(html) {
+        Some(head_end) => &html[head_end.end()..],
+        None => html,
+    };
+
+    let body = re_comment.replace_all(body, "");
+    let body = re_script.replace_all(&body, "");
+
+    let mut sections = Vec::new();
+    let mut current_title = title.clone();
+    let mut title_is_heading = false;
+    let mut last_end = 0;
+
+    for caps in re_heading.captures_iter(&body) {
+        let whole = caps.get(0).unwrap();
+        let content = strip_html_tags(&body[last_end..whole.start()]);
+        if !content.is_empty() || title_is_heading {
+            sections.push(Section::new(current_title.clone(), content).with_heading(title_is_heading));
+        }
+        current_title = strip_html_tags(&caps[1]);
+        title_is_heading = true;
+        last_end = whole.end();
+    }
+
+    let content = strip_html_tags(&body[last_end..]);
+    if !content.is_empty() || title_is_heading {
+        sections.push(Section::new(current_title, content).with_heading(title_is_heading));
+    }
+
+    Document {
+        title,
+        author,
+        language,
+        sections,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_html_sections() {
+        let html = r#"<html lang="en"><head><title>My Notes</title></head>
+            <body><p>Intro text</p><h2>Part One</h2><p>First <b>part</b>.</p></body></html>"#;
+        let document = parse_html(html, "fallback");
+
+        assert_eq!(document.title, "My Notes");
+        assert_eq!(document.language.as_deref(), Some("en"));
+        assert_eq!(document.sections.len(), 2);
+        assert_eq!(document.sections[1].title, "Part One");
+        assert_eq!(document.sections[1].content, "First part.");
+    }
+
+    #[test]
+    fn test_parse_html_drops_scripts_and_comments() {
+        let html = r#"<body><script>if (a > b) { track(); }</script><!-- <p>hidden</p> -->
+            <style>p > b { color: red }</style><p>Visible</p></body>"#;
+        let document = parse_html(html, "fallback");
+
+        assert_eq!(document.sections.len(), 1);
+        assert_eq!(document.sections[0].content, "Visible");
+    }
+
+    #[test]
+    fn test_parse_html_head_end_with_non_ascii_title() {
+        // 'İ' grows when lowercased, so offsets into a lowercased copy would be off
+        let html = "<HEAD><title>İİİİ</title></HEAD><p>Body text</p>";
+        let document = parse_html(html, "fallback");
+
+        assert_eq!(document.title, "İİİİ");
+        assert_eq!(document.sections.len(), 1);
+        assert_eq!(document.sections[0].content, "Body text");
+    }
+}
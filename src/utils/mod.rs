@@ -1,10 +1,26 @@
+pub mod archive;
+pub mod boilerplate;
+pub mod cleaning;
+pub mod document;
+pub mod download;
+pub mod docx_parser;
 pub mod epub_parser;
+pub mod html_parser;
 pub mod ocr;
 pub mod pdf_parser;
+pub mod pii;
 pub mod text_processor;
+pub mod web;
 
+pub use archive::{for_each_archive_entry, is_archive};
+pub use boilerplate::{BoilerplateReport, remove_boilerplate, top_words};
+pub use cleaning::{CleaningPipeline, CleaningReport, CleaningStage};
+pub use download::{DownloadConfig, DownloadManifest, DownloadOutcome, download_all};
+pub use document::{Document, Section, is_supported_document, parse_document, parse_document_bytes};
 pub use epub_parser::extract_text_from_epub;
 pub use ocr::{auto_ocr_if_needed, is_scanned_pdf, ocr_pdf_with_tesseract};
 pub use pdf_parser::extract_text_from_pdf;
-pub use text_processor::{clean_text, add_structure_markers};
+pub use pii::{PiiReport, PiiScrubber};
+pub use text_processor::{clean_text, add_structure_markers, document_to_text};
+pub use web::{FetchReport, fetch_articles};
 
@@ -1,8 +1,10 @@
 use anyhow::{Context, Result};
-use pdf_extract::extract_text;
+use pdf_extract::{extract_text, extract_text_from_mem};
 use std::path::Path;
 use tracing::{info, warn};
 
+use super::document::{Document, Section, file_title};
+
 /// Structured content from PDF
 #[derive(Debug, Clone)]
 pub struct PdfContent {
@@ -18,12 +20,22 @@ pub fn extract_text_from_pdf(path: &Path) -> Result<PdfContent> {
     let text = extract_text(path)
         .with_context(|| format!("Failed to extract text from PDF: {:?}", path))?;
     
-    let has_text = !text.trim().is_empty();
-    
-    if !has_text {
+    if text.trim().is_empty() {
         warn!("PDF appears to be scanned or has no extractable text: {:?}", path);
     }
     
+    Ok(pdf_content(text))
+}
+
+/// Extract text from PDF bytes (e.g. a file inside an archive)
+pub fn extract_text_from_pdf_bytes(bytes: &[u8]) -> Result<PdfContent> {
+    let text = extract_text_from_mem(bytes).context("Failed to extract text from PDF data")?;
+    Ok(pdf_content(text))
+}
+
+fn pdf_content(text: String) -> PdfContent {
+    let has_text = !text.trim().is_empty();
+    
     // Split by page breaks (heuristic - look for form feed characters or multiple newlines)
     let pages: Vec<String> = text
         .split("\x0C")  // Form feed character
@@ -32,21 +44,38 @@ pub fn extract_text_from_pdf(path: &Path) -> Result<PdfContent> {
     
     info!("Extracted {} pages from PDF", pages.len());
     
-    Ok(PdfContent {
+    PdfContent {
         text,
         pages,
         has_text,
-    })
+    }
 }
 
 /// Extract structured content with chapter/section detection
-pub fn extract_structured_content(path: &Path) -> Result<Vec<(String, String)>> {
-    let content = extract_text_from_pdf(path)?;
-    
+pub fn extract_structured_content(path: &Path) -> Result<Document> {
+    structured_document(extract_text_from_pdf(path)?, file_title(path))
+}
+
+/// Split extracted PDF content into a sectioned document
+pub(crate) fn structured_document(content: PdfContent, title: String) -> Result<Document> {
     if !content.has_text {
         anyhow::bail!("PDF has no extractable text. OCR may be required.");
     }
     
+    let sections = split_sections(&content.text);
+    
+    info!("Detected {} sections in PDF", sections.len());
+    
+    Ok(Document {
+        title,
+        author: "Unknown".to_string(),
+        language: None,
+        sections,
+    })
+}
+
+/// Split extracted text into (title, content) sections using heading heuristics
+pub fn split_sections(text: &str) -> Vec<Section> {
     // Simple heuristic: detect chapters by looking for lines that:
     // 1. Start with "Chapter" or numbers
     // 2. Are short (likely titles)
@@ -54,19 +83,21 @@ pub fn extract_structured_content(path: &Path) -> Result<Vec<(String, String)>>
     
     let mut sections = Vec::new();
     let mut current_title = String::from("Introduction");
+    let mut title_is_heading = false;
     let mut current_content = String::new();
     
-    for line in content.text.lines() {
+    for line in text.lines() {
         let trimmed = line.trim();
         
         // Check if this looks like a chapter/section heading
         if is_likely_heading(trimmed) {
-            // Save previous section
-            if !current_content.is_empty() {
-                sections.push((current_title.clone(), current_content.trim().to_string()));
+            // Save previous section (a heading without content too)
+            if !current_content.is_empty() || title_is_heading {
+                sections.push(Section::new(current_title.clone(), current_content.trim()).with_heading(title_is_heading));
                 current_content.clear();
             }
             current_title = trimmed.to_string();
+            title_is_heading = true;
         } else {
             // Add to current section content
             if !trimmed.is_empty() {
@@ -77,13 +108,11 @@ pub fn extract_structured_content(path: &Path) -> Result<Vec<(String, String)>>
     }
     
     // Add final section
-    if !current_content.is_empty() {
-        sections.push((current_title, current_content.trim().to_string()));
+    if !current_content.is_empty() || title_is_heading {
+        sections.push(Section::new(current_title, current_content.trim()).with_heading(title_is_heading));
     }
     
-    info!("Detected {} sections in PDF", sections.len());
-    
-    Ok(sections)
+    sections
 }
 
 /// Heuristic to detect if a line is likely a heading
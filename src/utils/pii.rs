@@ -0,0 +1,172 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// Redaction counts produced by a scrubbing pass
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PiiReport {
+    pub emails: usize,
+    pub phone_numbers: usize,
+    pub id_numbers: usize,
+    pub addresses: usize,
+}
+
+impl PiiReport {
+    /// Total number of redactions across all categories
+    pub fn total(&self) -> usize {
+        self.emails + self.phone_numbers + self.id_numbers + self.addresses
+    }
+
+    /// Accumulate counts from another report
+    pub fn merge(&mut self, other: &PiiReport) {
+        self.emails += other.emails;
+        self.phone_numbers += other.phone_numbers;
+        self.id_numbers += other.id_numbers;
+        self.addresses += other.addresses;
+    }
+}
+
+/// Regex + heuristic scrubber for personal information in document corpora
+///
+/// Matches are replaced with placeholder markers (`<EMAIL>`, `<PHONE>`, `<ID>`,
+/// `<ADDRESS>`) so the surrounding text stays usable for training.
+pub struct PiiScrubber {
+    email: Regex,
+    id_number: Regex,
+    card_number: Regex,
+    phone: Regex,
+    address: Regex,
+    cn_address: Regex,
+}
+
+impl Default for PiiScrubber {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PiiScrubber {
+    pub fn new() -> Self {
+        Self {
+            email: Regex::new(r"(?i)\b[A-Z0-9._%+-]+@[A-Z0-9.-]+\.[A-Z]{2,}\b").unwrap(),
+            // US SSN and 18-digit Chinese resident ID numbers
+            id_number: Regex::new(r"\b(?:\d{3}-\d{2}-\d{4}|\d{17}[\dXx])\b").unwrap(),
+            card_number: Regex::new(r"\b\d{4}[ -]?\d{4}[ -]?\d{4}[ -]?\d{1,7}\b").unwrap(),
+            phone: Regex::new(
+                r"(?:\+\d{1,3}[ .-]?)?(?:\(\d{2,4}\)[ .-]?|\b\d{3,4}[ .-])\d{3,4}[ .-]\d{4}\b|\b1[3-9]\d{9}\b",
+            )
+            .unwrap(),
+            address: Regex::new(
+                r"\b\d{1,5}\s+(?:[A-Z][a-z]+\s+){1,3}(?:Street|St|Avenue|Ave|Road|Rd|Boulevard|Blvd|Lane|Ln|Drive|Dr|Court|Ct|Way|Place|Pl)\b\.?",
+            )
+            .unwrap(),
+            cn_address: Regex::new(r"\p{Han}{2,}(?:省|市)\p{Han}{1,10}(?:区|县)\p{Han}{1,20}(?:路|街|道)\d+号").unwrap(),
+        }
+    }
+
+    /// Scrub a text, returning the redacted text and the per-category counts
+    pub fn scrub(&self, text: &str) -> (String, PiiReport) {
+        let mut report = PiiReport::default();
+
+        let (text, count) = replace_counted(&self.email, text, "<EMAIL>", |_| true);
+        report.emails = count;
+
+        let (text, count) = replace_counted(&self.id_number, &text, "<ID>", |_| true);
+        report.id_numbers = count;
+
+        // Card-like digit runs only count when they pass the Luhn checksum
+        let (text, count) = replace_counted(&self.card_number, &text, "<ID>", luhn_valid);
+        report.id_numbers += count;
+
+        // Require enough digits to avoid eating year ranges and short figures
+        let (text, count) = replace_counted(&self.phone, &text, "<PHONE>", |m| digit_count(m) >= 10);
+        report.phone_numbers = count;
+
+        let (text, count) = replace_counted(&self.address, &text, "<ADDRESS>", |_| true);
+        report.addresses = count;
+
+        let (text, count) = replace_counted(&self.cn_address, &text, "<ADDRESS>", |_| true);
+        report.addresses += count;
+
+        (text, report)
+    }
+}
+
+/// Replace matches accepted by `accept`, returning the new text and the number of replacements
+fn replace_counted<F>(re: &Regex, text: &str, replacement: &str, accept: F) -> (String, usize)
+where
+    F: Fn(&str) -> bool,
+{
+    let mut count = 0;
+    let replaced = re.replace_all(text, |caps: &regex::Captures| {
+        let matched = &caps[0];
+        if accept(matched) {
+            count += 1;
+            replacement.to_string()
+        } else {
+            matched.to_string()
+        }
+    });
+    (replaced.into_owned(), count)
+}
+
+fn digit_count(s: &str) -> usize {
+    s.chars().filter(|c| c.is_ascii_digit()).count()
+}
+
+/// Luhn checksum used by payment card numbers
+fn luhn_valid(s: &str) -> bool {
+    let digits: Vec<u32> = s.chars().filter_map(|c| c.to_digit(10)).collect();
+    if !(13..=19).contains(&digits.len()) {
+        return false;
+    }
+
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &d)| {
+            if i % 2 == 1 {
+                let doubled = d * 2;
+                if doubled > 9 { doubled - 9 } else { doubled }
+            } else {
+                d
+            }
+        })
+        .sum();
+
+    sum.is_multiple_of(10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scrub_email_and_phone() {
+        let scrubber = PiiScrubber::new();
+        let (text, report) = scrubber.scrub("Write to jane.doe@example.com or call (555) 123-4567.");
+        assert_eq!(text, "Write to <EMAIL> or call <PHONE>.");
+        assert_eq!(report.emails, 1);
+        assert_eq!(report.phone_numbers, 1);
+    }
+
+    #[test]
+    fn test_scrub_ids_and_addresses() {
+        let scrubber = PiiScrubber::new();
+        let (text, report) = scrubber.scrub("SSN 123-45-6789, card 4111 1111 1111 1111, at 42 Baker Street.");
+        assert!(!text.contains("6789"));
+        assert!(!text.contains("4111"));
+        assert!(text.contains("<ADDRESS>"));
+        assert_eq!(report.id_numbers, 2);
+        assert_eq!(report.addresses, 1);
+    }
+
+    #[test]
+    fn test_scrub_keeps_ordinary_numbers() {
+        let scrubber = PiiScrubber::new();
+        let input = "Between 1999-2001 the population grew by 1234 5678 people.";
+        let (text, report) = scrubber.scrub(input);
+        assert_eq!(text, input);
+        assert_eq!(report.total(), 0);
+    }
+}
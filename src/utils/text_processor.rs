@@ -1,38 +1,25 @@
 use regex::Regex;
 
-/// Clean text by removing extra whitespace and special characters
+use super::cleaning::{CleaningPipeline, CleaningReport};
+use super::document::{Document, Section};
+
+/// Clean text with the default `CleaningPipeline`
 pub fn clean_text(text: &str) -> String {
-    // Remove multiple spaces
-    let re_spaces = Regex::new(r"\s+").unwrap();
-    let text = re_spaces.replace_all(text, " ");
-    
-    // Remove multiple newlines (keep paragraph breaks)
-    let re_newlines = Regex::new(r"\n{3,}").unwrap();
-    let text = re_newlines.replace_all(&text, "\n\n");
-    
-    // Remove page numbers (heuristic: standalone numbers)
-    let re_page_nums = Regex::new(r"(?m)^\s*\d+\s*$").unwrap();
-    let text = re_page_nums.replace_all(&text, "");
-    
-    // Remove common headers/footers patterns
-    let re_headers = Regex::new(r"(?m)^(Page \d+|Chapter \d+)\s*$").unwrap();
-    let text = re_headers.replace_all(&text, "");
-    
-    text.trim().to_string()
+    CleaningPipeline::default().run(text).0
 }
 
 /// Add structure markers to text
-pub fn add_structure_markers(sections: Vec<(String, String)>) -> String {
+pub fn add_structure_markers(sections: &[Section]) -> String {
     let mut result = String::new();
     
-    for (title, content) in sections {
+    for section in sections {
         // Add chapter marker
         result.push_str("<CHAPTER>");
-        result.push_str(&title);
+        result.push_str(&section.title);
         result.push_str("</CHAPTER>\n");
         
         // Split content into paragraphs and add markers
-        for paragraph in content.split("\n\n") {
+        for paragraph in section.content.split("\n\n") {
             let cleaned = paragraph.trim();
             if !cleaned.is_empty() {
                 result.push_str("<PARAGRAPH>");
@@ -47,6 +34,40 @@ pub fn add_structure_markers(sections: Vec<(String, String)>) -> String {
     result
 }
 
+/// Render a parsed document as training text, cleaning each section with `pipeline`
+pub fn document_to_text(
+    document: &Document,
+    preserve_structure: bool,
+    pipeline: &CleaningPipeline,
+) -> (String, CleaningReport) {
+    let mut report = CleaningReport::default();
+    let sections: Vec<Section> = document.sections
+        .iter()
+        .map(|section| {
+            let (content, section_report) = pipeline.run(&section.content);
+            report.merge(&section_report);
+            Section::new(section.title.clone(), content).with_heading(section.heading)
+        })
+        .collect();
+    
+    let text = if preserve_structure {
+        add_structure_markers(&sections)
+    } else {
+        // Headings stay in the text; placeholder titles are not part of it
+        sections
+            .iter()
+            .flat_map(|section| {
+                let title = section.heading.then_some(section.title.trim());
+                title.into_iter().chain([section.content.as_str()])
+            })
+            .filter(|text| !text.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    };
+    
+    (text, report)
+}
+
 /// Extract plain text without structure markers
 pub fn remove_structure_markers(text: &str) -> String {
     let re = Regex::new(r"</?(?:CHAPTER|PARAGRAPH)>").unwrap();
@@ -56,6 +77,7 @@ pub fn remove_structure_markers(text: &str) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::utils::pdf_parser::split_sections;
     
     #[test]
     fn test_clean_text() {
@@ -67,14 +89,26 @@ mod tests {
     #[test]
     fn test_add_structure_markers() {
         let sections = vec![
-            ("Chapter 1".to_string(), "This is content.\n\nSecond paragraph.".to_string()),
+            Section::new("Chapter 1", "This is content.\n\nSecond paragraph."),
         ];
         
-        let marked = add_structure_markers(sections);
+        let marked = add_structure_markers(&sections);
         assert!(marked.contains("<CHAPTER>"));
         assert!(marked.contains("<PARAGRAPH>"));
     }
     
+    #[test]
+    fn test_document_to_text_keeps_headings() {
+        let document = Document {
+            title: "book".to_string(),
+            author: "Unknown".to_string(),
+            language: None,
+            sections: split_sections("Preface text.\nCHAPTER ONE\nTHE START\nIt began."),
+        };
+        let (text, _) = document_to_text(&document, false, &CleaningPipeline::default());
+        assert_eq!(text, "Preface text.\n\nCHAPTER ONE\n\nTHE START\n\nIt began.");
+    }
+    
     #[test]
     fn test_remove_structure_markers() {
         let text = "<CHAPTER>Title</CHAPTER><PARAGRAPH>Content</PARAGRAPH>";
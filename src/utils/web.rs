@@ -0,0 +1,250 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use super::document::{Document, Section};
+use super::download::{DownloadConfig, DownloadOutcome, download_all};
+use super::epub_parser::strip_html_tags;
+use crate::data::content_hash;
+
+/// Minimum characters for a paragraph to count as article text
+const MIN_PARAGRAPH_CHARS: usize = 40;
+
+/// Outcome of fetching a URL list
+#[derive(Debug, Default)]
+pub struct FetchReport {
+    pub saved: Vec<PathBuf>,
+    /// Articles unchanged since an earlier run
+    pub skipped: usize,
+    pub failed: Vec<(String, String)>,
+}
+
+/// Download the articles behind `urls` (article pages or RSS/Atom feeds)
+/// through the download manager (raw responses and their manifest live in
+/// `download_dir`) and save their readable text as `.txt` files in
+/// `out_dir`. Articles the server reports as unchanged are skipped.
+pub fn fetch_articles(
+    urls: &[String],
+    download_dir: &Path,
+    out_dir: &Path,
+    config: &DownloadConfig,
+) -> Result<FetchReport> {
+    fs::create_dir_all(out_dir)
+        .with_context(|| format!("Failed to create output directory: {:?}", out_dir))?;
+
+    let mut report = FetchReport::default();
+    let mut seen: HashSet<String> = urls.iter().cloned().collect();
+    let mut pending: Vec<String> = urls.to_vec();
+
+    // Feeds expand into another round of article downloads
+    while !pending.is_empty() {
+        let mut next = Vec::new();
+        for (url, outcome) in download_all(&pending, download_dir, config)? {
+            let (raw_path, changed) = match outcome {
+                DownloadOutcome::Downloaded(path) => (path, true),
+                DownloadOutcome::Unchanged(path) => (path, false),
+                DownloadOutcome::Failed(e) => {
+                    report.failed.push((url, e));
+                    continue;
+                }
+            };
+            let body = String::from_utf8_lossy(&fs::read(&raw_path)?).into_owned();
+
+            if is_feed(&body) {
+                let links = parse_feed_links(&body);
+                info!("Feed {} lists {} article(s)", url, links.len());
+                next.extend(links.into_iter().filter(|link| seen.insert(link.clone())));
+                continue;
+            }
+
+            let path = article_path(out_dir, &url);
+            if !changed && path.exists() {
+                report.skipped += 1;
+                continue;
+            }
+
+            let document = extract_readable(&body, &url);
+            if document.sections.is_empty() {
+                warn!("No readable text found at {}", url);
+                report.failed.push((url, "no readable text".to_string()));
+                continue;
+            }
+
+            let text = format!("{}\n\n{}", document.title, document.text());
+            fs::write(&path, text).with_context(|| format!("Failed to write article: {:?}", path))?;
+            info!("Saved {} -> {:?}", url, path);
+            report.saved.push(path);
+        }
+        pending = next;
+    }
+
+    Ok(report)
+}
+
+/// Stable file name for a URL, so re-runs skip articles already fetched
+fn article_path(out_dir: &Path, url: &str) -> PathBuf {
+    out_dir.join(format!("web_{}.txt", &content_hash(url.as_bytes())[..16]))
+}
+
+/// Whether a response body is an RSS or Atom feed rather than a page
+pub fn is_feed(body: &str) -> bool {
+    let head: String = body.chars().take(512).collect::<String>().to_lowercase();
+    head.contains("<rss") || head.contains("<feed") || head.contains("<rdf:rdf")
+}
+
+/// Article links of an RSS (`<item><link>`) or Atom (`<entry><link href>`) feed
+pub fn parse_feed_links(xml: &str) -> Vec<String> {
+    let re_item = Regex::new(r"(?is)<(item|entry)\b.*?</(item|entry)>").unwrap();
+    let re_rss_link = Regex::new(r"(?is)<link>\s*(?:<!\[CDATA\[)?\s*(.*?)\s*(?:\]\]>)?\s*</link>").unwrap();
+    let re_atom_link = Regex::new(r#"(?is)<link\b[^>]*\bhref\s*=\s*["']([^"']+)["'][^>]*>"#).unwrap();
+    let re_rel = Regex::new(r#"(?i)\brel\s*=\s*["']([^"']+)["']"#).unwrap();
+
+    let mut links = Vec::new();
+    for item in re_item.find_iter(xml) {
+        let item = item.as_str();
+        let link = re_rss_link.captures(item).map(|c| c[1].to_string()).or_else(|| {
+            // Atom: prefer rel="alternate" (the default when rel is missing)
+            re_atom_link
+                .captures_iter(item)
+                .find(|c| re_rel.captures(&c[0]).is_none_or(|rel| &rel[1] == "alternate"))
+                .map(|c| c[1].to_string())
+        });
+        if let Some(link) = link.map(|l| decode_entities(&l)).filter(|l| !l.is_empty()) {
+            if !links.contains(&link) {
+                links.push(link);
+            }
+        }
+    }
+    links
+}
+
+/// Readability-style extraction: drop navigation and other page chrome, pick
+/// the container with the most paragraph text and keep its paragraphs and
+/// headings
+pub fn extract_readable(html: &str, fallback_title: &str) -> Document {
+    let re_title = Regex::new(r"(?is)<title[^>]*>(.*?)</title>").unwrap();
+    let re_chrome = Regex::new(
+        r"(?is)<(script|style|noscript|nav|header|footer|aside|form|svg|iframe)\b.*?</(script|style|noscript|nav|header|footer|aside|form|svg|iframe)>",
+    )
+    .unwrap();
+    let re_comment = Regex::new(r"(?s)<!--.*?-->").unwrap();
+    let re_container = Regex::new(r"(?is)<(article|main)\b[^>]*>(.*?)</(article|main)>").unwrap();
+    let re_block = Regex::new(r"(?is)<(p|h[1-3]|li|blockquote)\b[^>]*>(.*?)</(p|h[1-3]|li|blockquote)>").unwrap();
+
+    let title = re_title
+        .captures(html)
+        .map(|c| decode_entities(&strip_html_tags(&c[1])))
+        .filter(|t| !t.is_empty())
+        .unwrap_or_else(|| fallback_title.to_string());
+
+    let cleaned = re_comment.replace_all(html, "");
+    let cleaned = re_chrome.replace_all(&cleaned, "");
+
+    let paragraph_chars = |fragment: &str| -> usize {
+        re_block
+            .captures_iter(fragment)
+            .filter(|c| c[1].eq_ignore_ascii_case("p"))
+            .map(|c| strip_html_tags(&c[2]).len())
+            .sum()
+    };
+    let body = re_container
+        .captures_iter(&cleaned)
+        .map(|c| c.get(2).unwrap().as_str())
+        .max_by_key(|fragment| paragraph_chars(fragment))
+        .filter(|fragment| paragraph_chars(fragment) > 0)
+        .unwrap_or(&cleaned);
+
+    let mut sections = Vec::new();
+    let mut current_title = title.clone();
+    let mut title_is_heading = false;
+    let mut paragraphs: Vec<String> = Vec::new();
+    for caps in re_block.captures_iter(body) {
+        let tag = caps[1].to_lowercase();
+        let text = decode_entities(&strip_html_tags(&caps[2]));
+        if text.is_empty() {
+            continue;
+        }
+        if tag.starts_with('h') {
+            if !paragraphs.is_empty() || title_is_heading {
+                sections.push(Section::new(current_title.clone(), paragraphs.join("\n\n")).with_heading(title_is_heading));
+                paragraphs.clear();
+            }
+            current_title = text;
+            title_is_heading = true;
+        } else if tag != "p" || text.chars().count() >= MIN_PARAGRAPH_CHARS {
+            paragraphs.push(text);
+        }
+    }
+    if !paragraphs.is_empty() || title_is_heading {
+        sections.push(Section::new(current_title, paragraphs.join("\n\n")).with_heading(title_is_heading));
+    }
+
+    Document {
+        title,
+        author: "Unknown".to_string(),
+        language: None,
+        sections,
+    }
+}
+
+fn decode_entities(text: &str) -> String {
+    let re_numeric = Regex::new(r"&#(x[0-9a-fA-F]+|[0-9]+);").unwrap();
+    let decoded = re_numeric.replace_all(text, |caps: &regex::Captures| {
+        let code = &caps[1];
+        let value = match code.strip_prefix('x') {
+            Some(hex) => u32::from_str_radix(hex, 16).ok(),
+            None => code.parse().ok(),
+        };
+        value.and_then(char::from_u32).map(String::from).unwrap_or_default()
+    });
+    decoded
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_readable_skips_page_chrome() {
+        let html = r#"<html><head><title>News &amp; Notes</title><script>var x = 1;</script></head>
+            <body><nav><p>Home | About | Contact us for more information today</p></nav>
+            <div class="sidebar"><p>Short</p></div>
+            <article><h1>Headline</h1>
+            <p>The first paragraph of the article has enough words to count as text.</p>
+            <p>A second paragraph that also carries the actual content of the story.</p></article>
+            <footer><p>Copyright notice that appears on every single page of the site</p></footer></body></html>"#;
+        let document = extract_readable(html, "fallback");
+
+        assert_eq!(document.title, "News & Notes");
+        assert_eq!(document.sections.len(), 1);
+        assert_eq!(document.sections[0].title, "Headline");
+        let text = document.text();
+        assert!(text.contains("first paragraph") && text.contains("second paragraph"));
+        assert!(!text.contains("Contact") && !text.contains("Copyright") && !text.contains("var x"));
+    }
+
+    #[test]
+    fn test_parse_feed_links() {
+        let rss = r#"<?xml version="1.0"?><rss><channel><link>https://example.com</link>
+            <item><title>A</title><link>https://example.com/a?x=1&amp;y=2</link></item>
+            <item><link><![CDATA[https://example.com/b]]></link></item></channel></rss>"#;
+        assert!(is_feed(rss));
+        assert_eq!(parse_feed_links(rss), vec!["https://example.com/a?x=1&y=2", "https://example.com/b"]);
+
+        let atom = r#"<feed xmlns="http://www.w3.org/2005/Atom"><entry>
+            <link rel="self" href="https://example.com/self"/>
+            <link href="https://example.com/c"/></entry></feed>"#;
+        assert!(is_feed(atom));
+        assert_eq!(parse_feed_links(atom), vec!["https://example.com/c"]);
+        assert!(!is_feed("<html><body>page</body></html>"));
+    }
+}